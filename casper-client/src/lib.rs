@@ -0,0 +1,1303 @@
+//! Async client for the Casper daemon
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), String> {
+//! let client = casper_client::Client::from_env();
+//! client.move_mouse(500, 300).await?;
+//! client.click_mouse("left").await?;
+//!
+//! let mut events = client.subscribe_events(&["recording_stopped"]).await?;
+//! while let Some(event) = events.next().await {
+//!     println!("{}", event?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+
+/// Socket of the default daemon instance: `$XDG_RUNTIME_DIR/casper.sock`,
+/// or `casper.sock` in the temporary directory, where the daemon puts it
+pub fn default_socket_path() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => std::env::temp_dir(),
+    };
+    dir.join("casper.sock")
+}
+
+/// Protocol version this client speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Window information as reported by the daemon
+#[derive(Debug, Clone, Deserialize)]
+pub struct WindowInfo {
+    pub id: String,
+    pub pid: u32,
+    pub desktop: i32,
+    pub class: String,
+    pub title: String,
+    pub machine: String,
+    /// Base64 PNG, from `list_windows_with_thumbnails` when the window
+    /// is uncovered on the shown workspace and could be captured
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+}
+
+/// The focused window as reported by the daemon
+#[derive(Debug, Clone, Deserialize)]
+pub struct ActiveWindow {
+    #[serde(flatten)]
+    pub window: WindowInfo,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A library sequence as listed by the daemon
+#[derive(Debug, Clone, Deserialize)]
+pub struct SequenceInfo {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub created_at: String,
+    pub steps: usize,
+    pub temporary: bool,
+    pub draft: bool,
+}
+
+/// Connection settings for talking to a daemon instance
+///
+/// The daemon answers one request per connection, so every call opens a
+/// fresh connection; a restarted daemon is picked up transparently.
+#[derive(Debug, Clone)]
+pub struct Client {
+    socket_path: PathBuf,
+    timeout: Duration,
+    connect_retries: u32,
+}
+
+impl Client {
+    pub fn new(socket_path: impl Into<PathBuf>) -> Self {
+        Client {
+            socket_path: socket_path.into(),
+            timeout: Duration::from_secs(30),
+            connect_retries: 3,
+        }
+    }
+
+    /// Connect to the socket named by CASPER_SOCKET, or the default socket
+    pub fn from_env() -> Self {
+        let socket_path = std::env::var("CASPER_SOCKET")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| default_socket_path());
+        Self::new(socket_path)
+    }
+
+    /// Maximum time to wait for a complete (non-streaming) response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times to retry connecting while the daemon is (re)starting
+    pub fn with_connect_retries(mut self, retries: u32) -> Self {
+        self.connect_retries = retries;
+        self
+    }
+
+    pub fn socket_path(&self) -> &PathBuf {
+        &self.socket_path
+    }
+
+    async fn connect(&self) -> Result<UnixStream, String> {
+        let mut attempt = 0;
+        loop {
+            match UnixStream::connect(&self.socket_path).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if attempt >= self.connect_retries => {
+                    return Err(format!(
+                        "Failed to connect to {}: {}",
+                        self.socket_path.display(),
+                        e
+                    ));
+                }
+                Err(_) => {
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_millis(200 * attempt as u64)).await;
+                }
+            }
+        }
+    }
+
+    async fn open(&self, request: &Value) -> Result<UnixStream, String> {
+        let mut stream = self.connect().await?;
+        let line = format!("{}\n", request);
+        stream
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+        Ok(stream)
+    }
+
+    /// Send a raw request and return the raw response, whatever its status
+    pub async fn send(&self, request: Value) -> Result<Value, String> {
+        let exchange = async {
+            let mut stream = self.open(&request).await?;
+            let mut response = Vec::new();
+            stream
+                .read_to_end(&mut response)
+                .await
+                .map_err(|e| format!("Failed to read response: {}", e))?;
+            parse_response(&response)
+        };
+
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| format!("Request timed out after {:?}", self.timeout))?
+    }
+
+    /// Send a request and turn an error status into `Err`
+    pub async fn request(&self, request: Value) -> Result<Value, String> {
+        let response = self.send(request).await?;
+        check_status(response)
+    }
+
+    /// Send a list request and follow its `next_offset` until the last page,
+    /// returning the items of `field` from every page
+    pub async fn request_all(&self, mut request: Value, field: &str) -> Result<Value, String> {
+        let mut items = Vec::new();
+        loop {
+            let mut response = self.request(request.clone()).await?;
+            let page = match response[field].as_array_mut() {
+                Some(page) => std::mem::take(page),
+                None => Vec::new(),
+            };
+            let empty = page.is_empty();
+            items.extend(page);
+            // An empty page would ask for the same offset again
+            match response["next_offset"].as_u64() {
+                Some(offset) if !empty => request["offset"] = json!(offset),
+                _ => return Ok(Value::Array(items)),
+            }
+        }
+    }
+
+    /// Send a request in streaming mode and read its chunks as they arrive
+    pub async fn request_stream(&self, mut request: Value) -> Result<MessageStream, String> {
+        request["stream"] = json!(true);
+        let stream = self.open(&request).await?;
+        Ok(MessageStream::new(stream))
+    }
+
+    /// Stream windows being created, closed, focused and retitled
+    pub async fn watch_window_events(&self) -> Result<MessageStream, String> {
+        self.subscribe_events(&[
+            "window_created",
+            "window_closed",
+            "window_focused",
+            "window_title_changed",
+        ])
+        .await
+    }
+
+    /// Subscribe to daemon events; an empty list subscribes to everything
+    pub async fn subscribe_events(&self, events: &[&str]) -> Result<MessageStream, String> {
+        self.watch(&json!({ "type": "subscribe", "events": events }))
+            .await
+            .map(|(_, messages)| messages)
+    }
+
+    /// Open a long-lived connection whose first line acknowledges (or rejects) it
+    async fn watch(&self, request: &Value) -> Result<(Value, MessageStream), String> {
+        let stream = self.open(request).await?;
+        let mut messages = MessageStream::new(stream);
+        match messages.next().await {
+            Some(Ok(ack)) => Ok((check_status(ack)?, messages)),
+            Some(Err(e)) => Err(e),
+            None => Err("Daemon closed the connection".to_string()),
+        }
+    }
+
+    // Status
+
+    pub async fn ping(&self) -> Result<Value, String> {
+        self.request(json!({ "type": "ping" })).await
+    }
+
+    /// Recording/playback state, active modes and favorite sequences
+    pub async fn status(&self) -> Result<Value, String> {
+        self.request(json!({ "type": "status" })).await
+    }
+
+    /// Sensors currently observing the user, e.g. `[{"sensor": "recording", ...}]`
+    pub async fn active_sensors(&self) -> Result<Vec<Value>, String> {
+        let response = self.request(json!({ "type": "active_sensors" })).await?;
+        serde_json::from_value(response["active_sensors"].clone())
+            .map_err(|e| format!("Invalid sensor list: {}", e))
+    }
+
+    /// Turn a daemon mode ("privacy" or "do_not_disturb") on or off
+    pub async fn set_mode(&self, mode: &str, enabled: bool) -> Result<(), String> {
+        self.request(json!({ "type": "set_mode", "mode": mode, "enabled": enabled }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn capabilities(&self) -> Result<Value, String> {
+        self.request(json!({ "type": "capabilities", "client_version": PROTOCOL_VERSION }))
+            .await
+    }
+
+    // Commands
+
+    pub async fn run_command(&self, command: &str) -> Result<String, String> {
+        let response = self
+            .request(json!({ "type": "run_command", "command": command }))
+            .await?;
+        Ok(response["output"].as_str().unwrap_or("").to_string())
+    }
+
+    // Mouse
+
+    pub async fn move_mouse(&self, x: i32, y: i32) -> Result<(), String> {
+        self.request(json!({ "type": "move_mouse", "x": x, "y": y }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Move the cursor by `dx`/`dy` from where it is
+    pub async fn move_mouse_relative(&self, dx: i32, dy: i32) -> Result<(), String> {
+        self.request(json!({ "type": "move_mouse", "x": dx, "y": dy, "relative": true }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn click_mouse(&self, button: &str) -> Result<(), String> {
+        self.request(json!({ "type": "click_mouse", "button": button }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Click twice, quickly enough to count as a double click
+    pub async fn double_click(&self, button: &str) -> Result<(), String> {
+        self.request(json!({ "type": "double_click", "button": button }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Move to `x`/`y` and click there, waiting `settle_ms` in between
+    pub async fn click_at(
+        &self,
+        x: i32,
+        y: i32,
+        button: &str,
+        settle_ms: u64,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "click_at",
+            "x": x,
+            "y": y,
+            "button": button,
+            "settle_ms": settle_ms,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Focus the window matching `window` and click `rel_x`/`rel_y` from its
+    /// top-left corner; returns the screen point clicked
+    pub async fn click_in_window(
+        &self,
+        window: &str,
+        rel_x: i32,
+        rel_y: i32,
+        button: &str,
+    ) -> Result<(i32, i32), String> {
+        let response = self
+            .request(json!({
+                "type": "click_in_window",
+                "window": window,
+                "rel_x": rel_x,
+                "rel_y": rel_y,
+                "button": button,
+            }))
+            .await?;
+        match (response["x"].as_i64(), response["y"].as_i64()) {
+            (Some(x), Some(y)) => Ok((x as i32, y as i32)),
+            _ => Err("Invalid click position".to_string()),
+        }
+    }
+
+    /// Press `button` at `from`, move to `to` over `duration_ms` and release
+    pub async fn drag(
+        &self,
+        from: (i32, i32),
+        to: (i32, i32),
+        button: &str,
+        duration_ms: u64,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "drag",
+            "from_x": from.0,
+            "from_y": from.1,
+            "to_x": to.0,
+            "to_y": to.1,
+            "button": button,
+            "duration_ms": duration_ms,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn scroll(&self, amount: i32, direction: &str) -> Result<(), String> {
+        self.request(json!({ "type": "scroll", "amount": amount, "direction": direction }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Scroll by pages with Page Up ("up") or Page Down ("down")
+    pub async fn scroll_pages(&self, pages: u32, direction: &str) -> Result<(), String> {
+        self.request(json!({ "type": "scroll_pages", "pages": pages, "direction": direction }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Swipe on the virtual touchscreen with `fingers` fingers
+    pub async fn swipe(
+        &self,
+        from: (i32, i32),
+        to: (i32, i32),
+        fingers: u32,
+        duration_ms: u64,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "swipe",
+            "from_x": from.0,
+            "from_y": from.1,
+            "to_x": to.0,
+            "to_y": to.1,
+            "fingers": fingers,
+            "duration_ms": duration_ms,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Pinch around `center`, the fingers going from `from_distance` to
+    /// `to_distance` apart; a growing distance zooms in
+    pub async fn pinch(
+        &self,
+        center: (i32, i32),
+        from_distance: i32,
+        to_distance: i32,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "pinch",
+            "x": center.0,
+            "y": center.1,
+            "from_distance": from_distance,
+            "to_distance": to_distance,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Tap the touchscreen with `fingers` fingers
+    pub async fn tap(&self, x: i32, y: i32, fingers: u32) -> Result<(), String> {
+        self.request(json!({"type": "tap", "x": x, "y": y, "fingers": fingers}))
+            .await
+            .map(|_| ())
+    }
+
+    /// Scroll until a reference image shows up and return its top-left corner
+    pub async fn scroll_to_image(
+        &self,
+        image: &str,
+        direction: &str,
+    ) -> Result<(i32, i32), String> {
+        let response = self
+            .request(json!({ "type": "scroll_to_image", "image": image, "direction": direction }))
+            .await?;
+        let x = response["x"].as_i64().ok_or("Missing x in response")? as i32;
+        let y = response["y"].as_i64().ok_or("Missing y in response")? as i32;
+        Ok((x, y))
+    }
+
+    /// Find a reference image on the screen by correlation and return the
+    /// center of the best place at least `confidence` alike, if any
+    pub async fn find_image_on_screen(
+        &self,
+        image: &str,
+        confidence: f64,
+    ) -> Result<Option<(i32, i32)>, String> {
+        let response = self
+            .request(
+                json!({ "type": "find_image_on_screen", "image": image, "confidence": confidence }),
+            )
+            .await?;
+        if !response["found"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+        let x = response["center_x"]
+            .as_i64()
+            .ok_or("Missing center_x in response")? as i32;
+        let y = response["center_y"]
+            .as_i64()
+            .ok_or("Missing center_y in response")? as i32;
+        Ok(Some((x, y)))
+    }
+
+    /// Wait until the screen changes; `options` may hold `region`,
+    /// `threshold`, `tolerance`, `stable_ms` and `timeout_ms`. Returns the
+    /// share of pixels left as they were and the boxes that changed.
+    pub async fn wait_for_screen_change(&self, mut options: Value) -> Result<Value, String> {
+        if options.is_null() {
+            options = json!({});
+        }
+        options["type"] = json!("wait_for_screen_change");
+        self.request(options).await
+    }
+
+    pub async fn get_mouse_position(&self) -> Result<(i32, i32), String> {
+        let response = self
+            .request(json!({ "type": "get_mouse_position" }))
+            .await?;
+        let x = response["x"].as_i64().ok_or("Missing x in response")? as i32;
+        let y = response["y"].as_i64().ok_or("Missing y in response")? as i32;
+        Ok((x, y))
+    }
+
+    /// Monitors with their resolution, offset and scale factor
+    pub async fn list_displays(&self) -> Result<Vec<Value>, String> {
+        let response = self.request(json!({ "type": "list_displays" })).await?;
+        Ok(response["displays"].as_array().cloned().unwrap_or_default())
+    }
+
+    /// Color of the screen pixel at `x`/`y` as (red, green, blue)
+    pub async fn get_pixel_color(&self, x: i32, y: i32) -> Result<(u8, u8, u8), String> {
+        let response = self
+            .request(json!({ "type": "get_pixel_color", "x": x, "y": y }))
+            .await?;
+        let channel = |name: &str| {
+            response[name]
+                .as_u64()
+                .map(|value| value as u8)
+                .ok_or(format!("Missing {} in response", name))
+        };
+        Ok((channel("r")?, channel("g")?, channel("b")?))
+    }
+
+    // Keyboard
+
+    pub async fn type_text(&self, text: &str) -> Result<(), String> {
+        self.request(json!({ "type": "type_text", "text": text }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Type text on a keyboard layout ("auto" for the detected one), pasting
+    /// the characters it would garble
+    pub async fn type_text_on_layout(&self, text: &str, layout: &str) -> Result<(), String> {
+        self.request(json!({ "type": "type_text", "text": text, "layout": layout }))
+            .await
+            .map(|_| ())
+    }
+
+    /// The keyboard layout the daemon detected, e.g. "de" or "us(intl)"
+    pub async fn keyboard_layout(&self) -> Result<String, String> {
+        let response = self.request(json!({ "type": "keyboard_layout" })).await?;
+        Ok(response["layout"].as_str().unwrap_or("").to_string())
+    }
+
+    /// Enter text by pasting it, for long texts or applications that drop
+    /// typed characters
+    pub async fn paste_text(&self, text: &str) -> Result<(), String> {
+        self.request(json!({ "type": "type_text", "text": text, "via_clipboard": true }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn press_key(&self, key: &str) -> Result<(), String> {
+        self.request(json!({ "type": "press_key", "key": key }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn key_down(&self, key: &str) -> Result<(), String> {
+        self.request(json!({ "type": "key_down", "key": key }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn key_up(&self, key: &str) -> Result<(), String> {
+        self.request(json!({ "type": "key_up", "key": key }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Press the focused application's shortcut for an action such as "new tab"
+    pub async fn send_app_action(&self, name: &str) -> Result<(), String> {
+        self.request(json!({ "type": "send_app_action", "name": name }))
+            .await
+            .map(|_| ())
+    }
+
+    // Windows
+
+    pub async fn list_windows(&self) -> Result<Vec<WindowInfo>, String> {
+        let windows = self
+            .request_all(json!({ "type": "list_windows" }), "windows")
+            .await?;
+        serde_json::from_value(windows).map_err(|e| format!("Invalid window list: {}", e))
+    }
+
+    /// The open windows with a thumbnail of each, at most `max_width`
+    /// pixels wide (160 by default)
+    pub async fn list_windows_with_thumbnails(
+        &self,
+        max_width: Option<u32>,
+    ) -> Result<Vec<WindowInfo>, String> {
+        let request = json!({
+            "type": "list_windows",
+            "thumbnails": true,
+            "thumbnail_width": max_width,
+        });
+        let windows = self.request_all(request, "windows").await?;
+        serde_json::from_value(windows).map_err(|e| format!("Invalid window list: {}", e))
+    }
+
+    /// The focused window and where it is on the screen
+    pub async fn active_window(&self) -> Result<ActiveWindow, String> {
+        let response = self.request(json!({ "type": "active_window" })).await?;
+        serde_json::from_value(response["window"].clone())
+            .map_err(|e| format!("Invalid window: {}", e))
+    }
+
+    pub async fn find_window(&self, pattern: &str) -> Result<Option<WindowInfo>, String> {
+        let response = self
+            .request(json!({ "type": "find_window", "pattern": pattern }))
+            .await?;
+        serde_json::from_value(response["window"].clone())
+            .map_err(|e| format!("Invalid window: {}", e))
+    }
+
+    /// Every window matching `pattern`, best match first
+    ///
+    /// `options` may hold `match` ("any", "class", "title" or "pid"),
+    /// `regex` and `case_sensitive`.
+    pub async fn find_windows(
+        &self,
+        pattern: &str,
+        mut options: Value,
+    ) -> Result<Vec<WindowInfo>, String> {
+        if options.is_null() {
+            options = json!({});
+        }
+        options["type"] = json!("find_window");
+        options["pattern"] = json!(pattern);
+        options["all"] = json!(true);
+        let response = self.request(options).await?;
+        serde_json::from_value(response["windows"].clone())
+            .map_err(|e| format!("Invalid windows: {}", e))
+    }
+
+    /// Close, minimize or move every window matching `pattern` and return
+    /// their ids
+    ///
+    /// `request` is "close_windows", "minimize_windows" or "move_windows";
+    /// `options` takes what `find_windows` does, plus `workspace` for moves.
+    pub async fn bulk_window_op(
+        &self,
+        request: &str,
+        pattern: &str,
+        mut options: Value,
+    ) -> Result<Vec<String>, String> {
+        if options.is_null() {
+            options = json!({});
+        }
+        options["type"] = json!(request);
+        options["pattern"] = json!(pattern);
+        let response = self.request(options).await?;
+        serde_json::from_value(response["windows"].clone())
+            .map_err(|e| format!("Invalid window ids: {}", e))
+    }
+
+    /// Wait until a window matching `pattern` is open
+    pub async fn wait_for_window(
+        &self,
+        pattern: &str,
+        timeout_ms: Option<u64>,
+    ) -> Result<WindowInfo, String> {
+        let response = self
+            .request(json!({
+                "type": "wait_for_window",
+                "pattern": pattern,
+                "timeout_ms": timeout_ms,
+            }))
+            .await?;
+        serde_json::from_value(response["window"].clone())
+            .map_err(|e| format!("Invalid window: {}", e))
+    }
+
+    pub async fn focus_window(&self, window: &str) -> Result<(), String> {
+        self.request(json!({ "type": "focus_window", "window": window }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Focus exactly this window, even when others share its title
+    pub async fn focus_window_by_id(&self, window_id: &str) -> Result<(), String> {
+        self.request(json!({ "type": "focus_window", "window_id": window_id }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Move and resize a window to part of its monitor, e.g. "left-half",
+    /// "top-right" or "center-third"
+    pub async fn snap_window(&self, window_id: &str, position: &str) -> Result<(), String> {
+        self.request(json!({
+            "type": "snap_window",
+            "window_id": window_id,
+            "position": position,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Put a window above all others without focusing it
+    pub async fn raise_window(&self, window_id: &str) -> Result<(), String> {
+        self.request(json!({ "type": "raise_window", "window_id": window_id }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Put a window below all others
+    pub async fn lower_window(&self, window_id: &str) -> Result<(), String> {
+        self.request(json!({ "type": "lower_window", "window_id": window_id }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Turn a window state such as "fullscreen", "always_on_top" or "sticky"
+    /// on or off
+    pub async fn set_window_state(
+        &self,
+        window_id: &str,
+        state: &str,
+        on: bool,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "set_window_state",
+            "window_id": window_id,
+            "state": state,
+            "on": on,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Save where every open window is as a named layout; returns how many
+    /// windows it holds
+    pub async fn save_layout(&self, name: &str) -> Result<u64, String> {
+        let response = self
+            .request(json!({ "type": "save_layout", "name": name }))
+            .await?;
+        Ok(response["windows"].as_u64().unwrap_or(0))
+    }
+
+    /// Put the open windows where a saved layout has them; the response
+    /// lists the `placed` window IDs and the `missing` application classes
+    pub async fn apply_layout(&self, name: &str) -> Result<Value, String> {
+        self.request(json!({ "type": "apply_layout", "name": name }))
+            .await
+    }
+
+    /// Workspaces with their `id`, `name` and whether they are `active`
+    pub async fn list_workspaces(&self) -> Result<Vec<Value>, String> {
+        let response = self.request(json!({ "type": "list_workspaces" })).await?;
+        Ok(response["workspaces"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    pub async fn switch_workspace(&self, workspace: i32) -> Result<(), String> {
+        self.request(json!({ "type": "switch_workspace", "workspace": workspace }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Move the first window matching `window` (a title or class pattern) to
+    /// a workspace without following it
+    pub async fn move_window_to_workspace(
+        &self,
+        window: &str,
+        workspace: i32,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "move_window_to_workspace",
+            "window": window,
+            "workspace": workspace,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn open_or_focus_application(&self, app: &str) -> Result<(), String> {
+        self.request(json!({ "type": "open_or_focus_application", "app": app }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Installed applications matching a query, best match first
+    ///
+    /// Each entry has `id`, `name`, `generic_name`, `icon` and `command`.
+    /// Processes with this name, program or PID, each with `pid`, `name`,
+    /// `cmdline`, `cpu_percent` and `memory_kb`
+    pub async fn get_process_info(&self, name: &str) -> Result<Vec<Value>, String> {
+        let response = self
+            .request(json!({ "type": "get_process_info", "name": name }))
+            .await?;
+        Ok(response["processes"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Signal every process with this name (TERM unless `signal` is given)
+    /// and return their PIDs
+    pub async fn kill_process(&self, name: &str, signal: Option<&str>) -> Result<Vec<u32>, String> {
+        let response = self
+            .request(json!({ "type": "kill_process", "name": name, "signal": signal }))
+            .await?;
+        serde_json::from_value(response["pids"].clone()).map_err(|e| format!("Invalid pids: {}", e))
+    }
+
+    /// Wait until no process with this name is left
+    pub async fn wait_for_process_exit(
+        &self,
+        name: &str,
+        timeout_ms: Option<u64>,
+    ) -> Result<(), String> {
+        self.request(json!({
+            "type": "wait_for_process_exit",
+            "name": name,
+            "timeout_ms": timeout_ms,
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    /// Every installed application with its desktop file `id`, `name`,
+    /// `icon` and `command`
+    pub async fn list_applications(&self) -> Result<Vec<Value>, String> {
+        let applications = self
+            .request_all(json!({ "type": "list_applications" }), "applications")
+            .await?;
+        Ok(applications.as_array().cloned().unwrap_or_default())
+    }
+
+    pub async fn search_applications(&self, query: &str) -> Result<Vec<Value>, String> {
+        let response = self
+            .request(json!({ "type": "search_applications", "query": query }))
+            .await?;
+        Ok(response["applications"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    // Screen
+
+    /// Receive frames of the screen every `interval_ms`
+    ///
+    /// `options` may hold `window` (a pattern), `region` and `max_frames`;
+    /// each message carries a base64 image in `data` and its `mime` type.
+    pub async fn stream_screen(
+        &self,
+        interval_ms: u64,
+        mut options: Value,
+    ) -> Result<MessageStream, String> {
+        if options.is_null() {
+            options = json!({});
+        }
+        options["type"] = json!("stream_screen");
+        options["interval_ms"] = json!(interval_ms);
+        self.watch(&options).await.map(|(_, messages)| messages)
+    }
+
+    /// Capture frames every `interval_ms` in the background and return the
+    /// stream ID; `options` may hold `window`, `region` and `max_frames` as
+    /// for `stream_screen`. The frames arrive as `screen_frame` events, see
+    /// `watch_screen_frames`.
+    pub async fn start_capture_stream(
+        &self,
+        interval_ms: u64,
+        mut options: Value,
+    ) -> Result<String, String> {
+        if options.is_null() {
+            options = json!({});
+        }
+        options["type"] = json!("start_capture_stream");
+        options["interval_ms"] = json!(interval_ms);
+        let response = self.request(options).await?;
+        Ok(response["stream_id"].as_str().unwrap_or("").to_string())
+    }
+
+    /// Stop a capture stream and return how many frames it published
+    pub async fn stop_capture_stream(&self, stream_id: &str) -> Result<u64, String> {
+        let response = self
+            .request(json!({ "type": "stop_capture_stream", "stream_id": stream_id }))
+            .await?;
+        Ok(response["frames"].as_u64().unwrap_or(0))
+    }
+
+    /// Receive the frames of every capture stream, and the end of each
+    pub async fn watch_screen_frames(&self) -> Result<MessageStream, String> {
+        self.subscribe_events(&["screen_frame", "screen_stream_ended"])
+            .await
+    }
+
+    /// Receive the cursor position whenever it moves, checked every
+    /// `interval_ms`; each message has `x`, `y` and, when the monitor is
+    /// known, `monitor`, `monitor_x` and `monitor_y`
+    pub async fn watch_mouse(&self, interval_ms: u64) -> Result<MessageStream, String> {
+        self.watch(&json!({ "type": "watch_mouse", "interval_ms": interval_ms }))
+            .await
+            .map(|(_, messages)| messages)
+    }
+
+    // Recording and playback
+
+    /// Start recording and return the session ID to pass to later recording calls
+    pub async fn start_recording(&self, name: &str, description: &str) -> Result<String, String> {
+        let response = self
+            .request(json!({
+                "type": "start_recording",
+                "name": name,
+                "description": description
+            }))
+            .await?;
+        Ok(response["session_id"].as_str().unwrap_or("").to_string())
+    }
+
+    /// PNG screenshot of the screen (`{"type":"capture_screen"}`), a region
+    /// (`capture_region` with `x`, `y`, `width`, `height`), a window
+    /// (`capture_window` with `window` or `window_id`) or a monitor
+    /// (`capture_monitor` with `monitor`), sent over the socket
+    /// as base64; `format`, `quality`, `scale` and `cursor` change how it is
+    /// taken and encoded
+    pub async fn capture(&self, mut request: Value) -> Result<String, String> {
+        request["path"] = Value::Null;
+        let response = self.request(request).await?;
+        response["data"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "No image data in the response".to_string())
+    }
+
+    /// Remove stored screenshots beyond `max_count` or older than
+    /// `max_age_hours` (the configured limits when not given); returns
+    /// `removed`, `kept` and `freed_bytes`
+    pub async fn cleanup_screenshots(
+        &self,
+        max_count: Option<u64>,
+        max_age_hours: Option<u64>,
+    ) -> Result<Value, String> {
+        self.request(json!({
+            "type": "cleanup_screenshots",
+            "max_count": max_count,
+            "max_age_hours": max_age_hours
+        }))
+        .await
+    }
+
+    /// Name the current mouse position in a recording and record a move to it
+    pub async fn capture_point(&self, name: &str, session_id: Option<&str>) -> Result<(), String> {
+        self.request(json!({ "type": "capture_point", "name": name, "session_id": session_id }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Stop a recording and return the name of the saved sequence
+    ///
+    /// The session ID may be omitted while only one recording is active.
+    pub async fn stop_recording(&self, session_id: Option<&str>) -> Result<String, String> {
+        let response = self
+            .request(json!({ "type": "stop_recording", "session_id": session_id }))
+            .await?;
+        Ok(response["sequence"].as_str().unwrap_or("").to_string())
+    }
+
+    /// The sequences in the library, only those tagged `tag` if given
+    pub async fn list_sequences(&self, tag: Option<&str>) -> Result<Vec<SequenceInfo>, String> {
+        let request = json!({ "type": "list_sequences", "tag": tag, "details": true });
+        let sequences = self.request_all(request, "sequences").await?;
+        serde_json::from_value(sequences).map_err(|e| format!("Invalid sequence list: {}", e))
+    }
+
+    /// A sequence from the library with all its steps, sensitive text hidden
+    pub async fn get_sequence(&self, name: &str) -> Result<Value, String> {
+        let response = self
+            .request(json!({ "type": "get_sequence", "name": name }))
+            .await?;
+        Ok(response["sequence"].clone())
+    }
+
+    /// Add tags to a sequence, returning all of its tags
+    pub async fn tag_sequence(&self, name: &str, tags: &[&str]) -> Result<Vec<String>, String> {
+        let response = self
+            .request(json!({ "type": "tag_sequence", "name": name, "tags": tags }))
+            .await?;
+        serde_json::from_value(response["tags"].clone()).map_err(|e| e.to_string())
+    }
+
+    /// Remove tags from a sequence, returning the tags it still has
+    pub async fn untag_sequence(&self, name: &str, tags: &[&str]) -> Result<Vec<String>, String> {
+        let response = self
+            .request(json!({ "type": "untag_sequence", "name": name, "tags": tags }))
+            .await?;
+        serde_json::from_value(response["tags"].clone()).map_err(|e| e.to_string())
+    }
+
+    /// Load a sequence from the library and start playing it
+    pub async fn play_sequence(&self, name: &str) -> Result<(), String> {
+        self.request(json!({ "type": "load_sequence", "name": name }))
+            .await?;
+        self.request(json!({ "type": "play_sequence" }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Like [`play_sequence`](Self::play_sequence), with the recorded delays divided by `speed`
+    pub async fn play_sequence_at(&self, name: &str, speed: f64) -> Result<(), String> {
+        self.request(json!({ "type": "load_sequence", "name": name }))
+            .await?;
+        self.request(json!({ "type": "play_sequence", "speed": speed }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Ask the daemon's AI provider to describe a sequence and its risky steps
+    pub async fn explain_sequence(&self, name: &str) -> Result<Value, String> {
+        self.request(json!({ "type": "explain_sequence", "name": name }))
+            .await
+    }
+
+    pub async fn pause_playback(&self) -> Result<(), String> {
+        self.request(json!({ "type": "pause_playback" }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn resume_playback(&self) -> Result<(), String> {
+        self.request(json!({ "type": "resume_playback" }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Stop the running playback; the step in progress still completes
+    pub async fn stop_playback(&self) -> Result<(), String> {
+        self.request(json!({ "type": "stop_playback" }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Stop the playback and everything queued, release blocked input and
+    /// held keys or buttons; returns what was stopped and released
+    pub async fn abort_all(&self) -> Result<Value, String> {
+        self.request(json!({ "type": "abort_all" })).await
+    }
+
+    /// Keep the real keyboard and mouse away from the desktop until
+    /// [`unblock_input`](Self::unblock_input) or `panic_key` (ctrl+alt+escape
+    /// if not given); returns the names of the blocked devices
+    pub async fn block_input(&self, panic_key: Option<&str>) -> Result<Vec<String>, String> {
+        let mut request = json!({ "type": "block_input" });
+        if let Some(panic_key) = panic_key {
+            request["panic_key"] = json!(panic_key);
+        }
+        let response = self.request(request).await?;
+        serde_json::from_value(response["devices"].clone()).map_err(|e| e.to_string())
+    }
+
+    pub async fn unblock_input(&self) -> Result<(), String> {
+        self.request(json!({ "type": "unblock_input" }))
+            .await
+            .map(|_| ())
+    }
+
+    // Pipelines
+
+    /// Run a saved pipeline with the given inputs and return its variables
+    pub async fn run_pipeline(&self, name: &str, vars: Value) -> Result<Value, String> {
+        let response = self
+            .request(json!({ "type": "run_pipeline", "name": name, "vars": vars }))
+            .await?;
+        Ok(response["vars"].clone())
+    }
+
+    // Key/value store
+
+    pub async fn store_set(&self, key: &str, value: Value) -> Result<(), String> {
+        self.request(json!({ "type": "store_set", "key": key, "value": value }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Value of a key, or `None` if it is not set
+    pub async fn store_get(&self, key: &str) -> Result<Option<Value>, String> {
+        let response = self
+            .request(json!({ "type": "store_get", "key": key }))
+            .await?;
+        Ok((response["exists"] == true).then(|| response["value"].clone()))
+    }
+
+    pub async fn store_delete(&self, key: &str) -> Result<(), String> {
+        self.request(json!({ "type": "store_delete", "key": key }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Watch keys (all keys if empty) for changes
+    ///
+    /// Returns the current values and a stream of `store_changed` events;
+    /// a deleted key is reported with a `null` value.
+    pub async fn store_watch(&self, keys: &[&str]) -> Result<(Value, MessageStream), String> {
+        let (ack, messages) = self
+            .watch(&json!({ "type": "store_watch", "keys": keys }))
+            .await?;
+        Ok((ack["values"].clone(), messages))
+    }
+
+    // Feedback
+
+    pub async fn show_notification(&self, summary: &str, body: &str) -> Result<(), String> {
+        self.request(json!({ "type": "show_notification", "summary": summary, "body": body }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn speak(&self, text: &str) -> Result<(), String> {
+        self.request(json!({ "type": "speak", "text": text }))
+            .await
+            .map(|_| ())
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// Convert a response with `"status": "error"` into `Err(message)`
+fn check_status(response: Value) -> Result<Value, String> {
+    if response["status"] == "error" {
+        Err(response["message"]
+            .as_str()
+            .unwrap_or("Unknown error")
+            .to_string())
+    } else {
+        Ok(response)
+    }
+}
+
+/// Newline-delimited JSON messages read from a streaming response or subscription
+pub struct MessageStream {
+    lines: Lines<BufReader<UnixStream>>,
+    finished: bool,
+}
+
+impl MessageStream {
+    fn new(stream: UnixStream) -> Self {
+        MessageStream {
+            lines: BufReader::new(stream).lines(),
+            finished: false,
+        }
+    }
+
+    /// Read the next message; `None` once the daemon sent `"done": true` or hung up
+    pub async fn next(&mut self) -> Option<Result<Value, String>> {
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            match self.lines.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => {
+                    let message = serde_json::from_str::<Value>(&line)
+                        .map_err(|e| format!("Invalid message from daemon: {}", e));
+                    if let Ok(ref value) = message {
+                        self.finished = value["done"] == true;
+                    }
+                    return Some(message);
+                }
+                Ok(None) => {
+                    self.finished = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(format!("Failed to read from daemon: {}", e)));
+                }
+            }
+        }
+    }
+
+    /// Collect the text of every chunk until the stream ends, returning the final message
+    pub async fn collect_chunks(mut self) -> Result<(String, Value), String> {
+        let mut text = String::new();
+        let mut last = Value::Null;
+        while let Some(message) = self.next().await {
+            let message = message?;
+            if let Some(chunk) = message["chunk"].as_str() {
+                text.push_str(chunk);
+            }
+            last = message;
+        }
+        Ok((text, check_status(last)?))
+    }
+}
+
+/// Parse a response, joining it first if the daemon sent it in `part` lines
+/// (when `responses.max_bytes` is configured)
+fn parse_response(response: &[u8]) -> Result<Value, String> {
+    let invalid = |e: serde_json::Error| format!("Invalid response from daemon: {}", e);
+    if let Ok(response) = serde_json::from_slice(response) {
+        return Ok(response);
+    }
+    let mut text = String::new();
+    for line in response
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+    {
+        let part: Value = serde_json::from_slice(line).map_err(invalid)?;
+        let data = part["data"]
+            .as_str()
+            .ok_or("Invalid response from daemon: expected one JSON object")?;
+        text.push_str(data);
+    }
+    serde_json::from_str(&text).map_err(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    /// Serve one connection with a canned reply and return the request it received
+    async fn serve_once(listener: UnixListener, reply: &'static str) -> Value {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = String::new();
+        let mut reader = tokio::io::BufReader::new(&mut socket);
+        tokio::io::AsyncBufReadExt::read_line(&mut reader, &mut request)
+            .await
+            .unwrap();
+        socket.write_all(reply.as_bytes()).await.unwrap();
+        serde_json::from_str(&request).unwrap()
+    }
+
+    fn test_socket(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "casper-client-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_typed_request_round_trip() {
+        let path = test_socket("position");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(serve_once(
+            listener,
+            r#"{"status":"success","x":12,"y":34}"#,
+        ));
+
+        let client = Client::new(&path);
+        assert_eq!(client.get_mouse_position().await.unwrap(), (12, 34));
+        assert_eq!(server.await.unwrap()["type"], "get_mouse_position");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_large_request_is_sent_whole() {
+        let path = test_socket("large");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(serve_once(listener, r#"{"status":"success"}"#));
+
+        let text = "x".repeat(10_000);
+        let client = Client::new(&path);
+        client.type_text(&text).await.unwrap();
+        assert_eq!(server.await.unwrap()["text"], text.as_str());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_error_status_becomes_err() {
+        let path = test_socket("error");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(serve_once(
+            listener,
+            r#"{"status":"error","message":"Unknown key: foo"}"#,
+        ));
+
+        let client = Client::new(&path);
+        assert_eq!(
+            client.press_key("foo").await.unwrap_err(),
+            "Unknown key: foo"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_stream_collects_chunks_until_done() {
+        let path = test_socket("stream");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(serve_once(
+            listener,
+            "{\"stream\":true,\"chunk\":\"a\"}\n{\"stream\":true,\"chunk\":\"b\"}\n{\"done\":true,\"status\":\"success\"}\n",
+        ));
+
+        let client = Client::new(&path);
+        let stream = client
+            .request_stream(json!({ "type": "run_command", "command": "ls" }))
+            .await
+            .unwrap();
+        let (text, last) = stream.collect_chunks().await.unwrap();
+        assert_eq!(text, "ab");
+        assert_eq!(last["done"], true);
+        assert_eq!(server.await.unwrap()["stream"], true);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_response_parts_are_joined() {
+        let parts = concat!(
+            r#"{"part":1,"parts":2,"data":"{\"status\":\"succ"}"#,
+            "\n",
+            r#"{"part":2,"parts":2,"data":"ess\",\"x\":1}"}"#,
+            "\n"
+        );
+        let response = parse_response(parts.as_bytes()).unwrap();
+        assert_eq!(response, json!({ "status": "success", "x": 1 }));
+        assert!(parse_response(b"{\"status\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure_is_reported() {
+        let client = Client::new(test_socket("missing")).with_connect_retries(0);
+        assert!(client.ping().await.is_err());
+    }
+}
@@ -1,43 +1,718 @@
+use crate::displays::DisplayRef;
+use crate::environment::EnvironmentSnapshot;
+use crate::journal::RecordingJournal;
+use crate::pipeline::Region;
+use crate::vault;
+use crate::window::SnapPosition;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 /// Represents a single action that can be performed
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Action {
-    MoveMouse { x: i32, y: i32 },
-    ClickMouse { button: String },
-    MouseDown { button: String },
-    MouseUp { button: String },
-    Scroll { amount: i32, direction: String },
-    TypeText { text: String },
-    PressKey { key: String },
-    KeyDown { key: String },
-    KeyUp { key: String },
-    RunCommand { command: String },
-    Wait { milliseconds: u64 },
-    LaunchApp { app_name: String },
-    FocusWindow { window_pattern: String },
-    ShowNotification { summary: String, body: String },
-    Speak { text: String },
+    MoveMouse {
+        x: i32,
+        y: i32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        anchor: Option<Anchor>,
+    },
+    /// Moves the pointer by an offset from where it is
+    MoveMouseRel {
+        dx: i32,
+        dy: i32,
+    },
+    /// Clicks where the pointer is, or first moves it to `x`/`y` when given,
+    /// waiting `settle_ms` between the move and the click; `count` 2 is a
+    /// double click
+    ClickMouse {
+        button: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        x: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        y: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        anchor: Option<Anchor>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        settle_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<u32>,
+    },
+    /// Presses `button` at the start, moves smoothly to the end over
+    /// `duration_ms` and releases it there
+    Drag {
+        from_x: i32,
+        from_y: i32,
+        to_x: i32,
+        to_y: i32,
+        button: String,
+        #[serde(default)]
+        duration_ms: u64,
+    },
+    MouseDown {
+        button: String,
+    },
+    MouseUp {
+        button: String,
+    },
+    /// With `step`, scrolls that many notches at a time with `delay_ms`
+    /// between them, for pages that animate or load while scrolling
+    Scroll {
+        amount: i32,
+        direction: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        step: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delay_ms: Option<u64>,
+    },
+    /// Presses Page Up or Page Down `pages` times
+    ScrollPages {
+        pages: u32,
+        direction: String,
+    },
+    /// Scrolls until a reference image is on the screen, looking again after
+    /// every few notches; fails after `max_scrolls` of them
+    ScrollToImage {
+        image: String,
+        direction: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tolerance: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_scrolls: Option<u32>,
+    },
+    /// `sensitive` text (a password) is hidden wherever steps are shown.
+    /// `delay_ms` types one character at a time for applications that drop
+    /// fast input; `via_clipboard` pastes the text instead of typing it.
+    /// With a keyboard `layout` ("auto" to detect it), the characters it
+    /// would garble are pasted on their own. Sensitive text is always typed,
+    /// so it never reaches clipboard managers
+    TypeText {
+        text: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        sensitive: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delay_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        via_clipboard: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        layout: Option<String>,
+    },
+    PressKey {
+        key: String,
+    },
+    KeyDown {
+        key: String,
+    },
+    KeyUp {
+        key: String,
+    },
+    /// Holds the modifiers of a combination such as "ctrl+shift+t" while the
+    /// key is pressed
+    PressCombo {
+        combo: String,
+    },
+    /// Holds keys (usually modifiers) while its actions run, and releases
+    /// them even if one of the actions fails
+    WithModifiers {
+        modifiers: Vec<String>,
+        actions: Vec<Action>,
+    },
+    RunCommand {
+        command: String,
+    },
+    Wait {
+        milliseconds: u64,
+    },
+    LaunchApp {
+        app_name: String,
+    },
+    FocusWindow {
+        window_pattern: String,
+    },
+    /// Shows a workspace (desktop): X11 desktops count from 0, Hyprland and
+    /// sway workspaces from 1
+    SwitchWorkspace {
+        workspace: i32,
+    },
+    /// Moves the first window matching the pattern to a workspace without
+    /// following it
+    MoveToWorkspace {
+        window_pattern: String,
+        workspace: i32,
+    },
+    /// Moves and resizes the first window matching the pattern to part of
+    /// the monitor it is on
+    SnapWindow {
+        window_pattern: String,
+        position: SnapPosition,
+    },
+    ShowNotification {
+        summary: String,
+        body: String,
+    },
+    Speak {
+        text: String,
+    },
+    AppAction {
+        name: String,
+    },
+    MoveToPoint {
+        point: String,
+    },
+    Repeat {
+        count: u32,
+        actions: Vec<Action>,
+    },
+    If(Conditional),
+    CallSequence {
+        name: String,
+    },
+    /// Runs its branches at the same time; playback goes on once all of them
+    /// are done, or stops at the first that fails
+    Parallel {
+        branches: Vec<Vec<Action>>,
+    },
+    WaitForWindow(WindowWait),
+    WaitForProcess(ProcessWait),
+    /// Waits until no process with the name is left, e.g. after `KillProcess`
+    WaitForProcessExit(ProcessWait),
+    WaitForPixelColor(PixelColorWait),
+    /// Waits until the screen differs from how it looked when the step began
+    WaitForScreenChange(ScreenChangeWait),
+    AssertScreen(ScreenAssertion),
+    /// Does nothing; names a place playback can resume from with `start_at`
+    Checkpoint {
+        name: String,
+    },
+    SetClipboard {
+        text: String,
+    },
+    /// Keeps the clipboard text as `${variable}` for later steps of the playback
+    GetClipboard {
+        variable: String,
+    },
+    /// Writes a key of the daemon's key/value store, announced as `store_changed`
+    StoreSet {
+        key: String,
+        value: String,
+    },
+    /// Keeps the value stored under the key as `${variable}` for later steps
+    StoreGet {
+        key: String,
+        variable: String,
+    },
+    /// Signals every process with the name (or PID); TERM unless `signal` says otherwise
+    KillProcess {
+        name: String,
+        signal: Option<String>,
+    },
+}
+
+/// What the coordinates of a mouse step are relative to
+///
+/// Steps without an anchor use absolute screen coordinates. The anchor is
+/// looked up when the step is played, so the step follows a window that
+/// moved or a button that is drawn somewhere else.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Anchor {
+    /// Top-left corner of the first window whose title or class contains
+    /// the pattern
+    Window { window: String },
+    /// Top-left corner of where a reference image is found on the screen.
+    /// `image` is a path, or a file name in the references directory.
+    Image {
+        image: String,
+        /// Largest difference per color channel still counted as a match
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tolerance: Option<u8>,
+        /// Search by correlation instead, taking the best place at least
+        /// this alike (0.0 to 1.0); `tolerance` is then unused
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        confidence: Option<f64>,
+    },
+    /// Top-left corner of a monitor, by its name ("DP-1") or its index in
+    /// `list_displays`, so a step keeps its place on that monitor whatever
+    /// the layout of the others
+    Monitor { monitor: DisplayRef },
+}
+
+/// Body of an `If` action: `{"condition": ..., "then": [...], "else": [...]}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conditional {
+    pub condition: Condition,
+    pub then: Vec<Action>,
+    #[serde(rename = "else", default)]
+    pub otherwise: Vec<Action>,
+}
+
+/// Body of a `WaitForWindow` action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowWait {
+    pub pattern: String,
+    pub timeout_ms: Option<u64>,
+}
+
+/// Body of a `WaitForProcess` action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessWait {
+    pub name: String,
+    pub timeout_ms: Option<u64>,
+}
+
+/// Body of a `WaitForPixelColor` action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PixelColorWait {
+    #[serde(flatten)]
+    pub pixel: PixelColor,
+    pub timeout_ms: Option<u64>,
+}
+
+/// Body of a `WaitForScreenChange` action
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScreenChangeWait {
+    /// Area watched; the whole screen when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// Share of pixels (0.0 to 1.0) that has to change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    /// Largest difference per color channel still counted as the same pixel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<u8>,
+    /// After the change, wait until the area has been still this long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stable_ms: Option<u64>,
+    pub timeout_ms: Option<u64>,
+}
+
+/// A screen pixel expected to have a color
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PixelColor {
+    pub x: i32,
+    pub y: i32,
+    /// "#rrggbb"
+    pub color: String,
+    /// Largest difference per color channel still counted as a match
+    pub tolerance: Option<u8>,
+}
+
+/// Body of an `AssertScreen` action
+///
+/// The captured area is compared with a reference PNG, and/or the AI is
+/// asked whether `expect` holds for it. Playback fails when it doesn't.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenAssertion {
+    /// Area checked; the whole screen when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// Reference image: a path, or a file name in the references directory.
+    /// A missing reference is recorded from the first capture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Largest difference per color channel still counted as the same pixel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<u8>,
+    /// Share of pixels (0.0 to 1.0) allowed to differ from the reference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_difference: Option<f64>,
+    /// Statement about the captured area the AI has to confirm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<String>,
+}
+
+/// How long a wait-for action waits when it has no `timeout_ms`
+pub const DEFAULT_WAIT_TIMEOUT_MS: u64 = 30_000;
+
+/// Something an `If` action checks when it is reached
+///
+/// `WindowExists` matches window titles and classes, `CommandSucceeds`
+/// holds when the command exits with status 0, `PixelColor` when the pixel
+/// is within `tolerance` of `color` in every channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Condition {
+    WindowExists { pattern: String },
+    ProcessRunning { name: String },
+    CommandSucceeds { command: String },
+    PixelColor(PixelColor),
+    Not { condition: Box<Condition> },
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::WindowExists { pattern } => write!(f, "window '{}'", pattern),
+            Condition::ProcessRunning { name } => write!(f, "process '{}'", name),
+            Condition::CommandSucceeds { command } => write!(f, "command `{}` to succeed", command),
+            Condition::PixelColor(pixel) => {
+                write!(f, "pixel ({}, {}) to be {}", pixel.x, pixel.y, pixel.color)
+            }
+            Condition::Not { condition } => write!(f, "not {}", condition),
+        }
+    }
+}
+
+/// JSON shapes of every action, used to describe the action model to AI providers
+pub const ACTION_SCHEMA: &str = r##"{"type":"MoveMouse","x":<int>,"y":<int>,"anchor"?:{"window":"<title or class pattern>"}|{"image":"<reference png>","tolerance"?:<0-255>,"confidence"?:<0.0-1.0>}|{"monitor":<name or index>}}
+{"type":"MoveMouseRel","dx":<int>,"dy":<int>}
+{"type":"ClickMouse","button":"left"|"right"|"middle","x"?:<int>,"y"?:<int>,"anchor"?:<as for MoveMouse>,"settle_ms"?:<int>,"count"?:<clicks, 2 for a double click>}
+{"type":"Drag","from_x":<int>,"from_y":<int>,"to_x":<int>,"to_y":<int>,"button":"left"|"right"|"middle","duration_ms"?:<int>}
+{"type":"MouseDown","button":"left"|"right"|"middle"}
+{"type":"MouseUp","button":"left"|"right"|"middle"}
+{"type":"Scroll","amount":<int>,"direction":"up"|"down"|"left"|"right","step"?:<notches at a time>,"delay_ms"?:<pause between steps>}
+{"type":"ScrollPages","pages":<int>,"direction":"up"|"down"}
+{"type":"ScrollToImage","image":<reference png>,"direction":"up"|"down"|"left"|"right","tolerance"?:<0-255>,"max_scrolls"?:<int>}
+{"type":"TypeText","text":<string>,"sensitive"?:<bool, true for passwords>,"delay_ms"?:<pause per character>,"via_clipboard"?:<bool, paste instead of typing>,"layout"?:<keyboard layout such as "de" or "auto", to paste what it would garble>}
+{"type":"PressKey","key":<key name, e.g. "enter", "tab", "f5">}
+{"type":"PressCombo","combo":<modifiers and key, e.g. "ctrl+shift+t">}
+{"type":"WithModifiers","modifiers":[<key name, e.g. "ctrl", "shift">, ...],"actions":[<action>, ...]}
+{"type":"KeyDown","key":<key name>}
+{"type":"KeyUp","key":<key name>}
+{"type":"RunCommand","command":<string>}
+{"type":"Wait","milliseconds":<int>}
+{"type":"LaunchApp","app_name":<string>}
+{"type":"FocusWindow","window_pattern":<string>}
+{"type":"SwitchWorkspace","workspace":<int, from 0 on X11 and 1 on Hyprland and Sway>}
+{"type":"MoveToWorkspace","window_pattern":<string>,"workspace":<int>}
+{"type":"SnapWindow","window_pattern":<string>,"position":"left-half"|"right-half"|"top-half"|"bottom-half"|"top-left"|"top-right"|"bottom-left"|"bottom-right"|"left-third"|"center-third"|"right-third"|"left-two-thirds"|"right-two-thirds"}
+{"type":"ShowNotification","summary":<string>,"body":<string>}
+{"type":"Speak","text":<string>}
+{"type":"AppAction","name":<shortcut of the focused app, e.g. "new tab", "find", "reload">}
+{"type":"MoveToPoint","point":<name of a coordinate in the sequence's "points">}
+{"type":"Repeat","count":<int>,"actions":[<action>, ...]}
+{"type":"If","condition":<condition>,"then":[<action>, ...],"else":[<action>, ...]}
+{"type":"CallSequence","name":<name of a saved sequence>}
+{"type":"Parallel","branches":[[<action>, ...], [<action>, ...]]}
+{"type":"Checkpoint","name":<name to resume playback from>}
+{"type":"SetClipboard","text":<string>}
+{"type":"GetClipboard","variable":<name; later steps use the text as ${name}>}
+{"type":"StoreSet","key":<store key>,"value":<string>}
+{"type":"StoreGet","key":<store key>,"variable":<name; later steps use the value as ${name}>}
+{"type":"KillProcess","name":<process name or pid>,"signal"?:"TERM"|"KILL"|"INT"|"HUP"|...}
+{"type":"WaitForWindow","pattern":<string>,"timeout_ms":<int, optional>}
+{"type":"WaitForProcess","name":<string>,"timeout_ms":<int, optional>}
+{"type":"WaitForProcessExit","name":<string>,"timeout_ms":<int, optional>}
+{"type":"WaitForPixelColor","x":<int>,"y":<int>,"color":"#rrggbb","tolerance":<int, optional>,"timeout_ms":<int, optional>}
+{"type":"WaitForScreenChange","region":{"x":<int>,"y":<int>,"width":<int>,"height":<int>} (optional),"threshold":<share of pixels, optional>,"stable_ms":<int, optional>,"timeout_ms":<int, optional>}
+{"type":"AssertScreen","region":{"x":<int>,"y":<int>,"width":<int>,"height":<int>} (optional),"reference":<reference image, optional>,"expect":<statement about the screen, optional>}
+where <condition> is one of
+{"type":"WindowExists","pattern":<string>}
+{"type":"ProcessRunning","name":<string>}
+{"type":"CommandSucceeds","command":<string>}
+{"type":"PixelColor","x":<int>,"y":<int>,"color":"#rrggbb","tolerance":<int, optional>}
+{"type":"Not","condition":<condition>}"##;
+
+/// Shown instead of the text of a sensitive `TypeText` step
+pub const REDACTED: &str = "••••••";
+
+/// Shell words that destroy or overwrite data
+const DESTRUCTIVE_COMMANDS: &[&str] = &[
+    "rm", "rmdir", "shred", "dd", "mkfs", "truncate", "unlink", "wipefs", "mv",
+];
+
+impl Action {
+    /// Why this action deserves a second look before playback, if it does
+    pub fn risk(&self) -> Option<String> {
+        match self {
+            Action::RunCommand { command } => {
+                if mentions_destructive_command(command) {
+                    Some(format!("Runs a command that can delete data: {}", command))
+                } else if command.contains("sudo") {
+                    Some(format!("Runs a command with root privileges: {}", command))
+                } else {
+                    Some(format!("Runs a shell command: {}", command))
+                }
+            }
+            Action::TypeText {
+                text, sensitive, ..
+            } if mentions_destructive_command(text) => Some(format!(
+                "Types text that looks like a deleting shell command: {}",
+                if *sensitive { REDACTED } else { text }
+            )),
+            Action::PressKey { key } if key.eq_ignore_ascii_case("delete") => {
+                Some("Presses Delete, which may remove the current selection".to_string())
+            }
+            Action::KillProcess { name, .. } => Some(format!("Kills every process named {}", name)),
+            Action::Repeat { actions, .. } | Action::WithModifiers { actions, .. } => {
+                actions.iter().find_map(Action::risk)
+            }
+            Action::Parallel { branches } => branches.iter().flatten().find_map(Action::risk),
+            Action::If(branch) => branch.condition.risk().or_else(|| {
+                branch
+                    .then
+                    .iter()
+                    .chain(&branch.otherwise)
+                    .find_map(Action::risk)
+            }),
+            _ => None,
+        }
+    }
+
+    /// The action as it may be shown in listings and logs, with the text of
+    /// sensitive `TypeText` steps (also inside `Repeat`, `If` and `Parallel`) hidden
+    pub fn redacted(&self) -> Action {
+        match self {
+            Action::TypeText {
+                sensitive: true,
+                delay_ms,
+                via_clipboard,
+                layout,
+                ..
+            } => Action::TypeText {
+                text: REDACTED.to_string(),
+                sensitive: true,
+                delay_ms: *delay_ms,
+                via_clipboard: *via_clipboard,
+                layout: layout.clone(),
+            },
+            Action::Repeat { count, actions } => Action::Repeat {
+                count: *count,
+                actions: actions.iter().map(Action::redacted).collect(),
+            },
+            Action::WithModifiers { modifiers, actions } => Action::WithModifiers {
+                modifiers: modifiers.clone(),
+                actions: actions.iter().map(Action::redacted).collect(),
+            },
+            Action::If(branch) => Action::If(Conditional {
+                condition: branch.condition.clone(),
+                then: branch.then.iter().map(Action::redacted).collect(),
+                otherwise: branch.otherwise.iter().map(Action::redacted).collect(),
+            }),
+            Action::Parallel { branches } => Action::Parallel {
+                branches: branches
+                    .iter()
+                    .map(|branch| branch.iter().map(Action::redacted).collect())
+                    .collect(),
+            },
+            other => other.clone(),
+        }
+    }
+
+    /// Refuse sensitive `TypeText` steps whose text is the `REDACTED`
+    /// placeholder, so saving a redacted copy can't replace the real text
+    pub fn check_not_redacted(&self) -> Result<(), String> {
+        let nested: Vec<&Action> = match self {
+            Action::TypeText {
+                text,
+                sensitive: true,
+                ..
+            } if text == REDACTED => {
+                return Err(
+                    "Sensitive text is redacted; fetch the sequence with \"reveal\": true or type the text again"
+                        .to_string(),
+                );
+            }
+            Action::Repeat { actions, .. } | Action::WithModifiers { actions, .. } => {
+                actions.iter().collect()
+            }
+            Action::If(branch) => branch.then.iter().chain(&branch.otherwise).collect(),
+            Action::Parallel { branches } => branches.iter().flatten().collect(),
+            _ => Vec::new(),
+        };
+        nested.into_iter().try_for_each(Action::check_not_redacted)
+    }
+
+    /// The condition a wait-for action waits for, and for how long at most
+    pub fn wait_condition(&self) -> Option<(Condition, Duration)> {
+        let (condition, timeout_ms) = match self {
+            Action::WaitForWindow(wait) => (
+                Condition::WindowExists {
+                    pattern: wait.pattern.clone(),
+                },
+                &wait.timeout_ms,
+            ),
+            Action::WaitForProcess(wait) => (
+                Condition::ProcessRunning {
+                    name: wait.name.clone(),
+                },
+                &wait.timeout_ms,
+            ),
+            Action::WaitForProcessExit(wait) => (
+                Condition::Not {
+                    condition: Box::new(Condition::ProcessRunning {
+                        name: wait.name.clone(),
+                    }),
+                },
+                &wait.timeout_ms,
+            ),
+            Action::WaitForPixelColor(wait) => {
+                (Condition::PixelColor(wait.pixel.clone()), &wait.timeout_ms)
+            }
+            _ => return None,
+        };
+        let timeout = Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_WAIT_TIMEOUT_MS));
+        Some((condition, timeout))
+    }
+}
+
+impl Condition {
+    fn risk(&self) -> Option<String> {
+        match self {
+            Condition::CommandSucceeds { command } => Some(format!(
+                "Runs a shell command to choose a branch: {}",
+                command
+            )),
+            Condition::Not { condition } => condition.risk(),
+            _ => None,
+        }
+    }
+}
+
+/// Whether any word of a shell snippet is a destructive command
+fn mentions_destructive_command(text: &str) -> bool {
+    text.split(|c: char| c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | '`'))
+        .any(|word| {
+            let program = word.rsplit('/').next().unwrap_or(word);
+            DESTRUCTIVE_COMMANDS.contains(&program) || program.starts_with("mkfs.")
+        })
 }
 
 /// A sequence of actions that can be recorded and replayed
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionSequence {
     pub name: String,
+    #[serde(default)]
     pub description: String,
     pub actions: Vec<ActionWithTimestamp>,
+    #[serde(default = "now_rfc3339")]
     pub created_at: String,
+    #[serde(default)]
     pub tags: Vec<String>,
+    /// Generated sequences start as drafts and must be approved before playback
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub draft: bool,
+    /// Named screen coordinates used by `MoveToPoint` steps
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub points: BTreeMap<String, Point>,
+    /// Where the sequence was recorded, if captured when saving
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<EnvironmentSnapshot>,
+    /// What to do when a step without its own policy fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<ErrorPolicy>,
+    /// What to do when this sequence is started while another one plays
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<Concurrency>,
+}
+
+/// What starting a sequence does while a playback is running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Concurrency {
+    /// Refuse with an error
+    #[default]
+    Reject,
+    /// Start once the running playback (and those queued before) ended
+    Queue,
+    /// Do nothing, successfully
+    SkipIfRunning,
+    /// Stop the running playback and start this one
+    CancelPrevious,
+}
+
+/// What playback does when a step fails, e.g. `"continue"` or
+/// `{"retry": {"attempts": 3, "backoff_ms": 500}}`
+///
+/// A step that still fails after its retries aborts the playback.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Stop the playback
+    #[default]
+    Abort,
+    /// Note the failure and go on with the next step
+    Continue,
+    /// Try again, waiting `backoff_ms` before the first retry and twice as
+    /// long before each further one
+    Retry {
+        attempts: u32,
+        #[serde(default = "default_backoff_ms")]
+        backoff_ms: u64,
+    },
+}
+
+fn now_rfc3339() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+/// A screen coordinate in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
 }
 
 /// Action with timing information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionWithTimestamp {
     pub action: Action,
+    #[serde(default)]
     pub delay_ms: u64, // Delay before this action (from previous action)
+    /// Optional stable step identifier, usable as a jump target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Human-readable label, usable as a jump target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Free-form note explaining the step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// What to do when the step fails; overrides the sequence's policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<ErrorPolicy>,
+    /// Screen when the step was recorded: a file in the sequence's
+    /// screenshots directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screenshot: Option<String>,
+}
+
+impl ActionWithTimestamp {
+    pub fn new(action: Action, delay_ms: u64) -> Self {
+        ActionWithTimestamp {
+            action,
+            delay_ms,
+            id: None,
+            label: None,
+            comment: None,
+            on_error: None,
+            screenshot: None,
+        }
+    }
+
+    /// Whether this step is addressed by the given label, step ID or checkpoint name
+    pub fn matches_target(&self, target: &str) -> bool {
+        self.label.as_deref() == Some(target)
+            || self.id.as_deref() == Some(target)
+            || matches!(&self.action, Action::Checkpoint { name } if name == target)
+    }
+}
+
+/// Reference to a step, either by position or by label/step ID
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepRef {
+    Index(usize),
+    Label(String),
+}
+
+impl StepRef {
+    /// Parse a step reference from a JSON number or string
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        if let Some(index) = value.as_u64() {
+            Some(StepRef::Index(index as usize))
+        } else {
+            value
+                .as_str()
+                .map(|label| StepRef::Label(label.to_string()))
+        }
+    }
 }
 
 impl ActionSequence {
@@ -46,13 +721,97 @@ impl ActionSequence {
             name,
             description,
             actions: Vec::new(),
-            created_at: chrono::Utc::now().to_rfc3339(),
+            created_at: now_rfc3339(),
             tags: Vec::new(),
+            draft: false,
+            points: BTreeMap::new(),
+            environment: None,
+            on_error: None,
+            concurrency: None,
+        }
+    }
+
+    /// The action to perform for a step, with named points replaced by coordinates
+    pub fn resolve_action(&self, action: &Action) -> Result<Action, String> {
+        match action {
+            Action::MoveToPoint { point } => self
+                .points
+                .get(point)
+                .map(|p| Action::MoveMouse {
+                    x: p.x,
+                    y: p.y,
+                    anchor: None,
+                })
+                .ok_or_else(|| format!("Sequence '{}' has no point '{}'", self.name, point)),
+            other => Ok(other.clone()),
         }
     }
 
     pub fn add_action(&mut self, action: Action, delay_ms: u64) {
-        self.actions.push(ActionWithTimestamp { action, delay_ms });
+        self.actions
+            .push(ActionWithTimestamp::new(action, delay_ms));
+    }
+
+    pub fn add_step(&mut self, step: ActionWithTimestamp) {
+        self.actions.push(step);
+    }
+
+    /// Find the index of the step with the given label or step ID
+    pub fn find_step(&self, target: &str) -> Option<usize> {
+        self.actions.iter().position(|a| a.matches_target(target))
+    }
+
+    /// Resolve a step reference to an index within this sequence
+    pub fn resolve_step(&self, step: &StepRef) -> Result<usize, String> {
+        match step {
+            StepRef::Index(index) if *index < self.actions.len() => Ok(*index),
+            StepRef::Index(index) => Err(format!(
+                "Step {} is out of range (sequence has {} steps)",
+                index,
+                self.actions.len()
+            )),
+            StepRef::Label(label) => self
+                .find_step(label)
+                .ok_or_else(|| format!("No step labeled '{}'", label)),
+        }
+    }
+
+    /// Name of the last checkpoint at or before step `index`, where playback
+    /// can be resumed from after that step failed
+    pub fn checkpoint_before(&self, index: usize) -> Option<&str> {
+        self.actions
+            .iter()
+            .take(index + 1)
+            .rev()
+            .find_map(|step| match &step.action {
+                Action::Checkpoint { name } => Some(name.as_str()),
+                _ => None,
+            })
+    }
+
+    /// The sequence with the text of its sensitive steps hidden
+    pub fn redacted(&self) -> ActionSequence {
+        let mut sequence = self.clone();
+        for step in &mut sequence.actions {
+            step.action = step.action.redacted();
+        }
+        sequence
+    }
+
+    /// Refuse a sequence holding redacted sensitive text, see `Action::check_not_redacted`
+    pub fn check_not_redacted(&self) -> Result<(), String> {
+        self.actions
+            .iter()
+            .try_for_each(|step| step.action.check_not_redacted())
+    }
+
+    /// Steps that should be reviewed before running an unfamiliar sequence
+    pub fn risky_steps(&self) -> Vec<(usize, String)> {
+        self.actions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, step)| step.action.risk().map(|reason| (index, reason)))
+            .collect()
     }
 
     pub fn add_tag(&mut self, tag: String) {
@@ -61,19 +820,73 @@ impl ActionSequence {
         }
     }
 
+    /// Remove a tag, returning whether the sequence had it
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let before = self.tags.len();
+        self.tags.retain(|t| t != tag);
+        self.tags.len() != before
+    }
+
+    /// Write the sequence in the format its extension names (see `SequenceFormat`)
     pub fn save_to_file(&self, path: &Path) -> Result<(), String> {
-        let json = serde_json::to_string_pretty(self)
-            .map_err(|e| format!("Failed to serialize: {}", e))?;
-        fs::write(path, json).map_err(|e| format!("Failed to write file: {}", e))?;
-        Ok(())
+        write_file(path, &vault::seal(&self.file_text(path)?)?)
+    }
+
+    /// The sequence as it is written to `path`
+    fn file_text(&self, path: &Path) -> Result<String, String> {
+        match SequenceFormat::from_path(path) {
+            SequenceFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            SequenceFormat::Yaml => serde_yaml::to_string(self).map_err(|e| e.to_string()),
+            SequenceFormat::Script => crate::script::render(self),
+        }
+        .map_err(|e| format!("Failed to serialize: {}", e))
     }
 
+    /// Read a sequence in the format its extension names (see `SequenceFormat`),
+    /// decrypting it if it was saved encrypted
     pub fn load_from_file(path: &Path) -> Result<Self, String> {
         let content =
             fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
-        let sequence: ActionSequence =
-            serde_json::from_str(&content).map_err(|e| format!("Failed to deserialize: {}", e))?;
-        Ok(sequence)
+        let content = vault::open(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match SequenceFormat::from_path(path) {
+            SequenceFormat::Json => serde_json::from_str(&content).map_err(|e| e.to_string()),
+            SequenceFormat::Yaml => serde_yaml::from_str(&content).map_err(|e| e.to_string()),
+            SequenceFormat::Script => crate::script::parse(&content),
+        }
+        .map_err(|e| format!("Failed to deserialize: {}", e))
+    }
+}
+
+/// Write a file through a temporary file, so a crash never leaves it truncated
+fn write_file(path: &Path, text: &str) -> Result<(), String> {
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("json");
+    let tmp = path.with_extension(format!("{}.tmp", extension));
+    fs::write(&tmp, text).map_err(|e| format!("Failed to write file: {}", e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Failed to write file: {}", e))?;
+    Ok(())
+}
+
+/// How a sequence file is written, chosen by its extension
+///
+/// `.yaml`/`.yml` is YAML with the same fields as JSON, `.casper` is the
+/// line-per-step format of `script`, anything else is JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceFormat {
+    Json,
+    Yaml,
+    Script,
+}
+
+impl SequenceFormat {
+    /// Extensions of sequence files, in the order they are looked up
+    pub const EXTENSIONS: &[&str] = &["json", "yaml", "yml", "casper"];
+
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("yaml" | "yml") => SequenceFormat::Yaml,
+            Some("casper") => SequenceFormat::Script,
+            _ => SequenceFormat::Json,
+        }
     }
 }
 
@@ -115,30 +928,73 @@ impl ActionRecorder {
     }
 
     pub fn record_action(&mut self, action: Action) -> Result<(), String> {
+        self.record_step(action, None, None, None)
+    }
+
+    /// Record an action together with an optional id, label and comment
+    pub fn record_step(
+        &mut self,
+        action: Action,
+        id: Option<String>,
+        label: Option<String>,
+        comment: Option<String>,
+    ) -> Result<(), String> {
+        self.record_step_at(action, id, label, comment, std::time::Instant::now())
+    }
+
+    /// Record an action that happened at `at` rather than now
+    pub fn record_step_at(
+        &mut self,
+        action: Action,
+        id: Option<String>,
+        label: Option<String>,
+        comment: Option<String>,
+        at: std::time::Instant,
+    ) -> Result<(), String> {
         if !self.is_recording {
             return Err("Not currently recording".to_string());
         }
 
-        let delay_ms = if let Some(last_time) = self.last_action_time {
-            let now = std::time::Instant::now();
-            let delay = now.duration_since(last_time);
-            self.last_action_time = Some(now);
-            delay.as_millis() as u64
-        } else {
-            0
+        let delay_ms = match self.last_action_time {
+            Some(last_time) => at.saturating_duration_since(last_time).as_millis() as u64,
+            None => 0,
         };
+        self.last_action_time = Some(self.last_action_time.map_or(at, |last| last.max(at)));
 
         if let Some(ref mut sequence) = self.current_sequence {
-            sequence.add_action(action, delay_ms);
+            let mut step = ActionWithTimestamp::new(action, delay_ms);
+            step.id = id;
+            step.label = label;
+            step.comment = comment;
+            sequence.add_step(step);
             Ok(())
         } else {
             Err("No active sequence".to_string())
         }
     }
 
+    /// Define (or move) a named point in the sequence being recorded
+    pub fn set_point(&mut self, name: &str, point: Point) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Point name cannot be empty".to_string());
+        }
+        match self.current_sequence.as_mut() {
+            Some(sequence) if self.is_recording => {
+                sequence.points.insert(name.to_string(), point);
+                Ok(())
+            }
+            _ => Err("Not currently recording".to_string()),
+        }
+    }
+
     pub fn is_recording(&self) -> bool {
         self.is_recording
     }
+
+    /// The sequence being recorded, if any
+    pub fn current_sequence(&self) -> Option<&ActionSequence> {
+        self.current_sequence.as_ref()
+    }
 }
 
 impl Default for ActionRecorder {
@@ -147,11 +1003,214 @@ impl Default for ActionRecorder {
     }
 }
 
+/// Independent recordings running at the same time, addressed by session ID
+///
+/// Requests may omit the session ID while exactly one recording is active.
+#[derive(Default)]
+pub struct RecordingSessions {
+    sessions: BTreeMap<String, ActionRecorder>,
+    next_id: u64,
+    /// Where in-progress recordings are journaled, if anywhere
+    journal_dir: Option<PathBuf>,
+    journals: BTreeMap<String, RecordingJournal>,
+}
+
+impl RecordingSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Journal every recorded step under `dir`, so recordings survive a crash
+    pub fn with_journal_dir(mut self, dir: PathBuf) -> Self {
+        self.journal_dir = Some(dir);
+        self
+    }
+
+    /// Start a new recording and return its session ID
+    pub fn start(&mut self, name: String, description: String) -> Result<String, String> {
+        let mut recorder = ActionRecorder::new();
+        recorder.start_recording(name, description)?;
+        self.next_id += 1;
+        let id = format!("rec-{}", self.next_id);
+
+        if let (Some(dir), Some(sequence)) = (&self.journal_dir, recorder.current_sequence()) {
+            // Recording still works without a journal, it just isn't crash-safe
+            match RecordingJournal::create(dir, &id, sequence) {
+                Ok(journal) => {
+                    self.journals.insert(id.clone(), journal);
+                }
+                Err(e) => eprintln!("Recording {} is not journaled: {}", id, e),
+            }
+        }
+
+        self.sessions.insert(id.clone(), recorder);
+        Ok(id)
+    }
+
+    /// Record a step into a session and journal it; returns the session ID
+    pub fn record_step(
+        &mut self,
+        session_id: Option<&str>,
+        action: Action,
+        id: Option<String>,
+        label: Option<String>,
+        comment: Option<String>,
+    ) -> Result<String, String> {
+        self.record_step_at(
+            session_id,
+            action,
+            id,
+            label,
+            comment,
+            std::time::Instant::now(),
+        )
+    }
+
+    /// Like [`record_step`](Self::record_step) for an action that happened at `at`
+    pub fn record_step_at(
+        &mut self,
+        session_id: Option<&str>,
+        action: Action,
+        step_id: Option<String>,
+        label: Option<String>,
+        comment: Option<String>,
+        at: std::time::Instant,
+    ) -> Result<String, String> {
+        let id = self.resolve(session_id)?;
+        let recorder = self.sessions.get_mut(&id).expect("resolved session exists");
+        recorder.record_step_at(action, step_id, label, comment, at)?;
+
+        let step = recorder
+            .current_sequence()
+            .and_then(|sequence| sequence.actions.last());
+        if let (Some(journal), Some(step)) = (self.journals.get_mut(&id), step)
+            && let Err(e) = journal.append(step)
+        {
+            eprintln!("{}", e);
+        }
+        Ok(id)
+    }
+
+    /// Note the screenshot taken for the last recorded step of a session
+    pub fn attach_screenshot(&mut self, session_id: &str, file: String) -> Result<(), String> {
+        let recorder = self
+            .sessions
+            .get_mut(session_id)
+            .ok_or_else(|| format!("No recording session '{}'", session_id))?;
+        match recorder
+            .current_sequence
+            .as_mut()
+            .and_then(|sequence| sequence.actions.last_mut())
+        {
+            Some(step) => {
+                step.screenshot = Some(file);
+                Ok(())
+            }
+            None => Err("Nothing recorded yet".to_string()),
+        }
+    }
+
+    /// Name a point in a session and record a move to it; returns the session ID
+    pub fn capture_point(
+        &mut self,
+        session_id: Option<&str>,
+        name: &str,
+        point: Point,
+        label: Option<String>,
+    ) -> Result<String, String> {
+        let id = self.resolve(session_id)?;
+        let recorder = self.sessions.get_mut(&id).expect("resolved session exists");
+        recorder.set_point(name, point)?;
+        if let Some(journal) = self.journals.get_mut(&id)
+            && let Err(e) = journal.append_point(name, point)
+        {
+            eprintln!("{}", e);
+        }
+        let action = Action::MoveToPoint {
+            point: name.to_string(),
+        };
+        self.record_step(Some(&id), action, None, label, None)
+    }
+
+    /// Resolve an optional session ID to an active session
+    pub fn resolve(&self, session_id: Option<&str>) -> Result<String, String> {
+        match session_id {
+            Some(id) if self.sessions.contains_key(id) => Ok(id.to_string()),
+            Some(id) => Err(format!("No recording session '{}'", id)),
+            None => match self.sessions.len() {
+                0 => Err("Not currently recording".to_string()),
+                1 => Ok(self.sessions.keys().next().unwrap().clone()),
+                n => Err(format!(
+                    "{} recordings are active; pass a session_id ({})",
+                    n,
+                    self.ids().join(", ")
+                )),
+            },
+        }
+    }
+
+    pub fn get_mut(&mut self, session_id: Option<&str>) -> Result<&mut ActionRecorder, String> {
+        let id = self.resolve(session_id)?;
+        Ok(self.sessions.get_mut(&id).expect("resolved session exists"))
+    }
+
+    /// Stop a recording, returning its session ID and the recorded sequence
+    pub fn stop(&mut self, session_id: Option<&str>) -> Result<(String, ActionSequence), String> {
+        let (id, sequence, journal) = self.stop_with_journal(session_id)?;
+        if let Some(journal) = journal
+            && let Err(e) = journal.remove()
+        {
+            eprintln!("{}", e);
+        }
+        Ok((id, sequence))
+    }
+
+    /// Like [`stop`](Self::stop), handing over the journal of the recording,
+    /// which should only be removed once the sequence is saved
+    pub fn stop_with_journal(
+        &mut self,
+        session_id: Option<&str>,
+    ) -> Result<(String, ActionSequence, Option<RecordingJournal>), String> {
+        let id = self.resolve(session_id)?;
+        let mut recorder = self.sessions.remove(&id).expect("resolved session exists");
+        let sequence = recorder.stop_recording()?;
+        let journal = self.journals.remove(&id);
+        Ok((id, sequence, journal))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        !self.sessions.is_empty()
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.sessions.keys().cloned().collect()
+    }
+
+    /// Active sessions with the sequence each is recording
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ActionSequence)> {
+        self.sessions
+            .iter()
+            .filter_map(|(id, recorder)| recorder.current_sequence().map(|s| (id, s)))
+    }
+}
+
+/// Where a player is in its playback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+    Idle,
+    Playing,
+    Paused,
+}
+
 /// Player for replaying action sequences
 pub struct ActionPlayer {
     current_sequence: Option<ActionSequence>,
     current_index: usize,
-    is_playing: bool,
+    end_index: Option<usize>, // Last step to play (inclusive)
+    state: PlaybackState,
+    /// Incremented by every start, so a playback task can tell its run was replaced
+    run_id: u64,
 }
 
 impl ActionPlayer {
@@ -159,50 +1218,143 @@ impl ActionPlayer {
         ActionPlayer {
             current_sequence: None,
             current_index: 0,
-            is_playing: false,
+            end_index: None,
+            state: PlaybackState::Idle,
+            run_id: 0,
         }
     }
 
     pub fn load_sequence(&mut self, sequence: ActionSequence) {
         self.current_sequence = Some(sequence);
         self.current_index = 0;
-        self.is_playing = false;
+        self.end_index = None;
+        self.state = PlaybackState::Idle;
+    }
+
+    pub fn sequence(&self) -> Option<&ActionSequence> {
+        self.current_sequence.as_ref()
     }
 
     pub fn start_playback(&mut self) -> Result<(), String> {
-        if self.current_sequence.is_none() {
-            return Err("No sequence loaded".to_string());
+        self.start_playback_at(0)
+    }
+
+    /// Start playback at the given step index
+    pub fn start_playback_at(&mut self, index: usize) -> Result<(), String> {
+        self.start_playback_range(index, None)
+    }
+
+    /// Start playback at the step with the given label or step ID
+    pub fn start_playback_from_label(&mut self, label: &str) -> Result<(), String> {
+        self.start_playback_between(Some(&StepRef::Label(label.to_string())), None)
+    }
+
+    /// Play only the steps from `start` up to and including `end`
+    pub fn start_playback_range(&mut self, start: usize, end: Option<usize>) -> Result<(), String> {
+        let sequence = self
+            .current_sequence
+            .as_ref()
+            .ok_or_else(|| "No sequence loaded".to_string())?;
+        if start > 0 && start >= sequence.actions.len() {
+            return Err(format!(
+                "Step {} is out of range (sequence has {} steps)",
+                start,
+                sequence.actions.len()
+            ));
         }
-        self.is_playing = true;
-        self.current_index = 0;
+        if let Some(end) = end
+            && end < start
+        {
+            return Err(format!("End step {} is before start step {}", end, start));
+        }
+        self.state = PlaybackState::Playing;
+        self.run_id += 1;
+        self.current_index = start;
+        self.end_index = end;
         Ok(())
     }
 
+    /// Play the steps between two optional step references (both inclusive)
+    pub fn start_playback_between(
+        &mut self,
+        start: Option<&StepRef>,
+        end: Option<&StepRef>,
+    ) -> Result<(), String> {
+        let sequence = self
+            .current_sequence
+            .as_ref()
+            .ok_or_else(|| "No sequence loaded".to_string())?;
+        let start = match start {
+            Some(step) => sequence.resolve_step(step)?,
+            None => 0,
+        };
+        let end = match end {
+            Some(step) => Some(sequence.resolve_step(step)?),
+            None => None,
+        };
+        self.start_playback_range(start, end)
+    }
+
     pub fn stop_playback(&mut self) {
-        self.is_playing = false;
+        self.state = PlaybackState::Idle;
         self.current_index = 0;
+        self.end_index = None;
+    }
+
+    /// Hold playback before the next step until it is resumed
+    pub fn pause_playback(&mut self) -> Result<(), String> {
+        match self.state {
+            PlaybackState::Playing => {
+                self.state = PlaybackState::Paused;
+                Ok(())
+            }
+            PlaybackState::Paused => Err("Playback is already paused".to_string()),
+            PlaybackState::Idle => Err("Nothing is playing".to_string()),
+        }
     }
 
+    pub fn resume_playback(&mut self) -> Result<(), String> {
+        match self.state {
+            PlaybackState::Paused => {
+                self.state = PlaybackState::Playing;
+                Ok(())
+            }
+            PlaybackState::Playing => Err("Playback is not paused".to_string()),
+            PlaybackState::Idle => Err("Nothing is playing".to_string()),
+        }
+    }
+
+    /// Next step to perform; `None` while paused and once playback is over
     pub fn next_action(&mut self) -> Option<&ActionWithTimestamp> {
-        if !self.is_playing {
+        if self.state != PlaybackState::Playing {
             return None;
         }
 
         if let Some(ref sequence) = self.current_sequence {
-            if self.current_index < sequence.actions.len() {
+            let past_end = self.end_index.is_some_and(|end| self.current_index > end);
+            if self.current_index < sequence.actions.len() && !past_end {
                 let action = &sequence.actions[self.current_index];
                 self.current_index += 1;
                 return Some(action);
             } else {
-                self.is_playing = false;
+                self.state = PlaybackState::Idle;
             }
         }
 
         None
     }
 
+    /// Whether a playback is in progress, including a paused one
     pub fn is_playing(&self) -> bool {
-        self.is_playing
+        self.state != PlaybackState::Idle
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        self.state
+    }
+
+    pub fn run_id(&self) -> u64 {
+        self.run_id
     }
 
     pub fn get_progress(&self) -> (usize, usize) {
@@ -220,10 +1372,40 @@ impl Default for ActionPlayer {
     }
 }
 
+/// How many earlier versions of a sequence are kept
+pub const MAX_VERSIONS: usize = 20;
+
+/// Longest a temporary sequence may be kept
+pub const MAX_TEMPORARY_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// An earlier state of a saved sequence, kept when it was overwritten
+#[derive(Debug, Clone, Serialize)]
+pub struct SequenceVersion {
+    /// Identifies the version for `restore_version`
+    pub version: String,
+    pub saved_at: String,
+    /// Number of steps, if the file can still be read
+    pub steps: Option<usize>,
+}
+
+/// A sequence as listings show it, without its steps
+#[derive(Debug, Clone, Serialize)]
+pub struct SequenceSummary {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub created_at: String,
+    pub steps: usize,
+    pub temporary: bool,
+    pub draft: bool,
+}
+
 /// Manager for storing and retrieving action sequences
 pub struct ActionLibrary {
     sequences: Vec<ActionSequence>,
     library_path: String,
+    /// Sequences kept only in memory, with the time they expire (if ever)
+    temporary: BTreeMap<String, Option<Instant>>,
 }
 
 impl ActionLibrary {
@@ -231,17 +1413,91 @@ impl ActionLibrary {
         ActionLibrary {
             sequences: Vec::new(),
             library_path,
+            temporary: BTreeMap::new(),
         }
     }
 
+    /// Add a sequence, replacing any existing sequence with the same name
     pub fn add_sequence(&mut self, sequence: ActionSequence) {
+        self.temporary.remove(&sequence.name);
+        self.sequences.retain(|s| s.name != sequence.name);
+        self.sequences.push(sequence);
+    }
+
+    /// Add a sequence that is never written to disk and is dropped after
+    /// `ttl`, or when the library goes away
+    ///
+    /// A temporary sequence may replace another temporary one, but never a
+    /// saved one. `ttl` may be at most `MAX_TEMPORARY_TTL`.
+    pub fn add_temporary(
+        &mut self,
+        sequence: ActionSequence,
+        ttl: Option<Duration>,
+    ) -> Result<(), String> {
+        if self.is_saved(&sequence.name) {
+            return Err(format!(
+                "A saved sequence is named '{}'; temporary sequences need their own name",
+                sequence.name
+            ));
+        }
+        let expires = match ttl {
+            Some(ttl) if ttl > MAX_TEMPORARY_TTL => {
+                return Err(format!(
+                    "A temporary sequence is kept for at most {} seconds",
+                    MAX_TEMPORARY_TTL.as_secs()
+                ));
+            }
+            Some(ttl) => Some(
+                Instant::now()
+                    .checked_add(ttl)
+                    .ok_or("The time to keep the sequence is too long")?,
+            ),
+            None => None,
+        };
+        self.sequences.retain(|s| s.name != sequence.name);
+        self.temporary.insert(sequence.name.clone(), expires);
         self.sequences.push(sequence);
+        Ok(())
+    }
+
+    pub fn is_temporary(&self, name: &str) -> bool {
+        self.temporary.contains_key(name)
+    }
+
+    /// Whether a sequence of this name is kept on disk
+    pub fn is_saved(&self, name: &str) -> bool {
+        !self.is_temporary(name)
+            && (self.get_sequence(name).is_some() || self.file_path(name).exists())
+    }
+
+    /// Names of the temporary sequences
+    pub fn temporary_sequences(&self) -> Vec<String> {
+        self.temporary.keys().cloned().collect()
+    }
+
+    /// Drop the temporary sequences whose time is up, returning their names
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<String> = self
+            .temporary
+            .iter()
+            .filter(|(_, expires)| expires.is_some_and(|expires| expires <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &expired {
+            self.temporary.remove(name);
+            self.sequences.retain(|s| &s.name != name);
+        }
+        expired
     }
 
     pub fn get_sequence(&self, name: &str) -> Option<&ActionSequence> {
         self.sequences.iter().find(|s| s.name == name)
     }
 
+    pub fn get_sequence_mut(&mut self, name: &str) -> Option<&mut ActionSequence> {
+        self.sequences.iter_mut().find(|s| s.name == name)
+    }
+
     pub fn list_sequences(&self) -> Vec<String> {
         self.sequences.iter().map(|s| s.name.clone()).collect()
     }
@@ -253,18 +1509,296 @@ impl ActionLibrary {
             .collect()
     }
 
+    /// What listings show of the sequences, only those tagged `tag` if given
+    pub fn summaries(&self, tag: Option<&str>) -> Vec<SequenceSummary> {
+        self.sequences
+            .iter()
+            .filter(|s| tag.is_none_or(|tag| s.tags.iter().any(|t| t == tag)))
+            .map(|s| SequenceSummary {
+                name: s.name.clone(),
+                description: s.description.clone(),
+                tags: s.tags.clone(),
+                created_at: s.created_at.clone(),
+                steps: s.actions.len(),
+                temporary: self.is_temporary(&s.name),
+                draft: s.draft,
+            })
+            .collect()
+    }
+
     pub fn save_all(&self) -> Result<(), String> {
+        for sequence in &self.sequences {
+            self.save_sequence(sequence)?;
+        }
+        Ok(())
+    }
+
+    fn save_sequence(&self, sequence: &ActionSequence) -> Result<(), String> {
+        if self.is_temporary(&sequence.name) {
+            return Ok(());
+        }
         let path = Path::new(&self.library_path);
         if !path.exists() {
             fs::create_dir_all(path).map_err(|e| format!("Failed to create directory: {}", e))?;
         }
+        let path = self.file_path(&sequence.name);
+        let text = sequence.file_text(&path)?;
+        if let Ok(previous) = fs::read_to_string(&path) {
+            let unchanged = vault::open(&previous).is_ok_and(|plain| plain == text);
+            // Also rewritten when encryption was turned on or off since
+            if unchanged && vault::is_sealed(&previous) == vault::is_unlocked() {
+                return Ok(());
+            }
+            if !unchanged {
+                self.keep_version(&sequence.name, &path, &previous)?;
+            }
+        }
+        write_file(&path, &vault::seal(&text)?)
+    }
 
-        for sequence in &self.sequences {
-            let file_name = format!("{}.json", sequence.name.replace(' ', "_"));
-            let file_path = path.join(file_name);
-            sequence.save_to_file(&file_path)?;
+    /// Directory holding the earlier versions of a sequence
+    fn history_dir(&self, name: &str) -> PathBuf {
+        Path::new(&self.library_path)
+            .join(".history")
+            .join(name.replace(' ', "_"))
+    }
+
+    /// Keep the contents of a sequence file before it is overwritten or
+    /// deleted, dropping the oldest versions beyond `MAX_VERSIONS`
+    fn keep_version(&self, name: &str, path: &Path, contents: &str) -> Result<(), String> {
+        let dir = self.history_dir(name);
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("json");
+        // A version from before encryption was turned on is encrypted as well
+        let contents = match vault::is_sealed(contents) {
+            true => contents.to_string(),
+            false => vault::seal(contents)?,
+        };
+        // Versions are named by the time they were replaced, in milliseconds
+        let mut millis = chrono::Utc::now().timestamp_millis();
+        while self.version_path(name, &millis.to_string()).is_some() {
+            millis += 1;
+        }
+        fs::write(dir.join(format!("{}.{}", millis, extension)), contents)
+            .map_err(|e| format!("Failed to keep the previous version: {}", e))?;
+
+        for (_, old) in self.version_files(name).into_iter().skip(MAX_VERSIONS) {
+            let _ = fs::remove_file(old);
         }
+        Ok(())
+    }
+
+    /// The files of a sequence's earlier versions, newest first
+    fn version_files(&self, name: &str) -> Vec<(i64, PathBuf)> {
+        let mut files: Vec<(i64, PathBuf)> = fs::read_dir(self.history_dir(name))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let millis = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((millis, path))
+            })
+            .collect();
+        files.sort_by_key(|(millis, _)| std::cmp::Reverse(*millis));
+        files
+    }
+
+    fn version_path(&self, name: &str, version: &str) -> Option<PathBuf> {
+        self.version_files(name)
+            .into_iter()
+            .find(|(millis, _)| millis.to_string() == version)
+            .map(|(_, path)| path)
+    }
+
+    /// Earlier versions of a sequence, newest first
+    pub fn list_versions(&self, name: &str) -> Vec<SequenceVersion> {
+        self.version_files(name)
+            .into_iter()
+            .map(|(millis, path)| SequenceVersion {
+                version: millis.to_string(),
+                saved_at: chrono::DateTime::from_timestamp_millis(millis)
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_default(),
+                steps: ActionSequence::load_from_file(&path)
+                    .ok()
+                    .map(|sequence| sequence.actions.len()),
+            })
+            .collect()
+    }
+
+    /// Bring back an earlier version of a sequence, which may have been
+    /// deleted since; the state it replaces becomes a version of its own
+    pub fn restore_version(
+        &mut self,
+        name: &str,
+        version: &str,
+    ) -> Result<&ActionSequence, String> {
+        if self.is_temporary(name) {
+            return Err(format!("'{}' is temporary and has no versions", name));
+        }
+        let path = self
+            .version_path(name, version)
+            .ok_or_else(|| format!("Sequence '{}' has no version {}", name, version))?;
+        let mut sequence = ActionSequence::load_from_file(&path)?;
+        // Versions from before a rename carry the old name
+        sequence.name = name.to_string();
+        self.save_sequence(&sequence)?;
+        self.add_sequence(sequence);
+        self.get_sequence(name)
+            .ok_or_else(|| format!("Sequence not found: {}", name))
+    }
+
+    /// The file of a sequence: its existing file in any format, or a new JSON file
+    fn file_path(&self, name: &str) -> PathBuf {
+        let stem = name.replace(' ', "_");
+        let dir = Path::new(&self.library_path);
+        SequenceFormat::EXTENSIONS
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", stem, extension)))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| dir.join(format!("{}.json", stem)))
+    }
+
+    /// Directory holding the screenshots recorded with a sequence's steps
+    pub fn screenshots_dir(&self, name: &str) -> PathBuf {
+        Path::new(&self.library_path).join(format!("{}.screenshots", name.replace(' ', "_")))
+    }
+
+    /// Make `dir` the screenshots directory of a sequence, replacing any
+    /// screenshots of an earlier recording
+    pub fn store_screenshots(&self, name: &str, dir: &Path) -> Result<(), String> {
+        let target = self.screenshots_dir(name);
+        if target.exists() {
+            fs::remove_dir_all(&target)
+                .map_err(|e| format!("Failed to remove old screenshots: {}", e))?;
+        }
+        fs::create_dir_all(&self.library_path)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        fs::rename(dir, &target).map_err(|e| format!("Failed to store screenshots: {}", e))
+    }
+
+    /// Change a sequence and save it; on any error neither the file nor the
+    /// library is changed
+    pub fn edit_sequence<T>(
+        &mut self,
+        name: &str,
+        edit: impl FnOnce(&mut ActionSequence) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut sequence = self
+            .get_sequence(name)
+            .cloned()
+            .ok_or_else(|| format!("Sequence not found: {}", name))?;
+        let result = edit(&mut sequence)?;
+        self.save_sequence(&sequence)?;
+        if let Some(existing) = self.get_sequence_mut(name) {
+            *existing = sequence;
+        }
+        Ok(result)
+    }
+
+    /// Replace the action of a step, keeping its delay, label and notes
+    pub fn update_action(
+        &mut self,
+        name: &str,
+        step: &StepRef,
+        action: Action,
+    ) -> Result<usize, String> {
+        self.edit_sequence(name, |sequence| {
+            let index = sequence.resolve_step(step)?;
+            sequence.actions[index].action = action;
+            Ok(index)
+        })
+    }
 
+    /// Insert a step before `index`, or append it without one
+    pub fn insert_action(
+        &mut self,
+        name: &str,
+        index: Option<usize>,
+        step: ActionWithTimestamp,
+    ) -> Result<usize, String> {
+        self.edit_sequence(name, |sequence| {
+            let len = sequence.actions.len();
+            let index = index.unwrap_or(len);
+            if index > len {
+                return Err(format!(
+                    "Step {} is out of range (sequence has {} steps)",
+                    index, len
+                ));
+            }
+            sequence.actions.insert(index, step);
+            Ok(index)
+        })
+    }
+
+    /// Remove a step, returning it
+    pub fn remove_action(
+        &mut self,
+        name: &str,
+        step: &StepRef,
+    ) -> Result<ActionWithTimestamp, String> {
+        self.edit_sequence(name, |sequence| {
+            let index = sequence.resolve_step(step)?;
+            Ok(sequence.actions.remove(index))
+        })
+    }
+
+    /// Move a step so it ends up at position `to`
+    pub fn move_action(&mut self, name: &str, step: &StepRef, to: usize) -> Result<usize, String> {
+        self.edit_sequence(name, |sequence| {
+            let from = sequence.resolve_step(step)?;
+            let to = sequence.resolve_step(&StepRef::Index(to))?;
+            let moved = sequence.actions.remove(from);
+            sequence.actions.insert(to, moved);
+            Ok(from)
+        })
+    }
+
+    /// Give a sequence a new name, moving its file
+    pub fn rename_sequence(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        if new_name.trim().is_empty() {
+            return Err("The new name cannot be empty".to_string());
+        }
+        if self.get_sequence(new_name).is_some() || self.file_path(new_name).exists() {
+            return Err(format!("A sequence named '{}' already exists", new_name));
+        }
+        let mut sequence = self
+            .get_sequence(name)
+            .cloned()
+            .ok_or_else(|| format!("Sequence not found: {}", name))?;
+        sequence.name = new_name.to_string();
+        if let Some(expires) = self.temporary.remove(name) {
+            self.temporary.insert(new_name.to_string(), expires);
+        } else {
+            // The new file, in the old file's format, is complete before the old one goes away
+            let old_path = self.file_path(name);
+            let extension = old_path
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("json");
+            let new_path = Path::new(&self.library_path).join(format!(
+                "{}.{}",
+                new_name.replace(' ', "_"),
+                extension
+            ));
+            sequence.save_to_file(&new_path)?;
+            if old_path.exists() {
+                fs::remove_file(old_path).map_err(|e| format!("Failed to delete file: {}", e))?;
+            }
+            let screenshots = self.screenshots_dir(name);
+            if screenshots.exists() {
+                fs::rename(&screenshots, self.screenshots_dir(new_name))
+                    .map_err(|e| format!("Failed to move screenshots: {}", e))?;
+            }
+            let history = self.history_dir(name);
+            if history.exists() && !self.history_dir(new_name).exists() {
+                fs::rename(&history, self.history_dir(new_name))
+                    .map_err(|e| format!("Failed to move versions: {}", e))?;
+            }
+        }
+        self.sequences.retain(|s| s.name != name);
+        self.sequences.push(sequence);
         Ok(())
     }
 
@@ -276,13 +1810,16 @@ impl ActionLibrary {
 
         let entries = fs::read_dir(path).map_err(|e| format!("Failed to read directory: {}", e))?;
 
-        self.sequences.clear();
+        let temporary = &self.temporary;
+        self.sequences.retain(|s| temporary.contains_key(&s.name));
 
         for entry in entries {
             let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
             let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+            let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+            if SequenceFormat::EXTENSIONS.contains(&extension) {
                 match ActionSequence::load_from_file(&path) {
+                    Ok(sequence) if self.is_temporary(&sequence.name) => {}
                     Ok(sequence) => self.sequences.push(sequence),
                     Err(e) => eprintln!("Failed to load sequence from {:?}: {}", path, e),
                 }
@@ -294,14 +1831,545 @@ impl ActionLibrary {
 
     pub fn delete_sequence(&mut self, name: &str) -> Result<(), String> {
         self.sequences.retain(|s| s.name != name);
+        if self.temporary.remove(name).is_some() {
+            return Ok(());
+        }
 
-        let file_name = format!("{}.json", name.replace(' ', "_"));
-        let file_path = Path::new(&self.library_path).join(file_name);
-
-        if file_path.exists() {
+        let file_path = self.file_path(name);
+        if let Ok(contents) = fs::read_to_string(&file_path) {
+            // Deleting is undone with restore_version
+            self.keep_version(name, &file_path, &contents)?;
             fs::remove_file(file_path).map_err(|e| format!("Failed to delete file: {}", e))?;
         }
+        let screenshots = self.screenshots_dir(name);
+        if screenshots.exists() {
+            fs::remove_dir_all(screenshots)
+                .map_err(|e| format!("Failed to delete screenshots: {}", e))?;
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labeled_sequence() -> ActionSequence {
+        let mut sequence = ActionSequence::new("test".to_string(), String::new());
+        sequence.add_action(Action::Wait { milliseconds: 10 }, 0);
+        let mut step = ActionWithTimestamp::new(
+            Action::PressKey {
+                key: "enter".into(),
+            },
+            5,
+        );
+        step.label = Some("submit".to_string());
+        step.id = Some("step-2".to_string());
+        sequence.add_step(step);
+        sequence
+    }
+
+    #[test]
+    fn test_risky_steps() {
+        let mut sequence = ActionSequence::new("test".to_string(), String::new());
+        sequence.add_action(Action::Wait { milliseconds: 10 }, 0);
+        sequence.add_action(
+            Action::RunCommand {
+                command: "cd /tmp && /bin/rm -rf build".into(),
+            },
+            0,
+        );
+        sequence.add_action(
+            Action::TypeText {
+                text: "hello romance".into(),
+                sensitive: false,
+                delay_ms: None,
+                via_clipboard: false,
+                layout: None,
+            },
+            0,
+        );
+
+        let risky = sequence.risky_steps();
+        assert_eq!(risky.len(), 1);
+        assert_eq!(risky[0].0, 1);
+        assert!(risky[0].1.contains("delete"));
+    }
+
+    #[test]
+    fn test_concurrent_recording_sessions() {
+        let mut sessions = RecordingSessions::new();
+        let first = sessions.start("a".into(), String::new()).unwrap();
+        sessions
+            .get_mut(None)
+            .unwrap()
+            .record_action(Action::Wait { milliseconds: 1 })
+            .unwrap();
+
+        let second = sessions.start("b".into(), String::new()).unwrap();
+        assert_ne!(first, second);
+        assert!(sessions.get_mut(None).is_err());
+        sessions
+            .get_mut(Some(&second))
+            .unwrap()
+            .record_action(Action::Wait { milliseconds: 2 })
+            .unwrap();
+
+        let (id, sequence) = sessions.stop(Some(&first)).unwrap();
+        assert_eq!(id, first);
+        assert_eq!(sequence.name, "a");
+        assert_eq!(sequence.actions.len(), 1);
+
+        // With one session left, the ID may be omitted again
+        let (id, _) = sessions.stop(None).unwrap();
+        assert_eq!(id, second);
+        assert!(!sessions.is_recording());
+    }
+
+    #[test]
+    fn test_captured_points_resolve_at_playback() {
+        let mut sessions = RecordingSessions::new();
+        sessions.start("a".into(), String::new()).unwrap();
+        sessions
+            .capture_point(None, "save_button", Point { x: 40, y: 20 }, None)
+            .unwrap();
+        let (_, mut sequence) = sessions.stop(None).unwrap();
+
+        let step = sequence.actions[0].action.clone();
+        assert!(matches!(&step, Action::MoveToPoint { point } if point == "save_button"));
+        assert!(matches!(
+            sequence.resolve_action(&step),
+            Ok(Action::MoveMouse {
+                x: 40,
+                y: 20,
+                anchor: None
+            })
+        ));
+
+        // Moving the point adapts every step that uses it
+        sequence
+            .points
+            .insert("save_button".into(), Point { x: 90, y: 20 });
+        assert!(matches!(
+            sequence.resolve_action(&step),
+            Ok(Action::MoveMouse { x: 90, .. })
+        ));
+        sequence.points.clear();
+        assert!(sequence.resolve_action(&step).is_err());
+    }
+
+    #[test]
+    fn test_mouse_anchors() {
+        let step: Action = serde_json::from_str(
+            r#"{"type":"ClickMouse","button":"left","x":30,"y":12,"anchor":{"window":"Firefox"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            &step,
+            Action::ClickMouse { x: Some(30), y: Some(12), anchor: Some(Anchor::Window { window }), .. }
+                if window == "Firefox"
+        ));
+
+        let step: Action =
+            serde_json::from_str(r#"{"type":"MoveMouse","x":4,"y":4,"anchor":{"image":"ok.png"}}"#)
+                .unwrap();
+        assert!(matches!(
+            &step,
+            Action::MoveMouse { anchor: Some(Anchor::Image { image, tolerance: None, .. }), .. }
+                if image == "ok.png"
+        ));
+
+        let step: Action =
+            serde_json::from_str(r#"{"type":"MoveMouse","x":4,"y":4,"anchor":{"monitor":1}}"#)
+                .unwrap();
+        assert!(matches!(
+            &step,
+            Action::MoveMouse {
+                anchor: Some(Anchor::Monitor {
+                    monitor: DisplayRef::Index(1)
+                }),
+                ..
+            }
+        ));
+
+        // Absolute steps keep their old shape
+        let step = Action::MoveMouse {
+            x: 1,
+            y: 2,
+            anchor: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&step).unwrap(),
+            r#"{"type":"MoveMouse","x":1,"y":2}"#
+        );
+    }
+
+    #[test]
+    fn test_find_step_by_label_or_id() {
+        let sequence = labeled_sequence();
+        assert_eq!(sequence.find_step("submit"), Some(1));
+        assert_eq!(sequence.find_step("step-2"), Some(1));
+        assert_eq!(sequence.find_step("missing"), None);
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let mut sequence = labeled_sequence();
+        sequence.add_action(
+            Action::Checkpoint {
+                name: "logged_in".into(),
+            },
+            0,
+        );
+        sequence.add_action(Action::Wait { milliseconds: 1 }, 0);
+        assert_eq!(sequence.checkpoint_before(1), None);
+        assert_eq!(sequence.checkpoint_before(3), Some("logged_in"));
+
+        let mut player = ActionPlayer::new();
+        player.load_sequence(sequence);
+        player.start_playback_from_label("logged_in").unwrap();
+        assert_eq!(player.get_progress(), (2, 4));
+    }
+
+    #[test]
+    fn test_start_playback_from_label() {
+        let mut player = ActionPlayer::new();
+        player.load_sequence(labeled_sequence());
+        player.start_playback_from_label("submit").unwrap();
+        assert_eq!(player.get_progress(), (1, 2));
+        assert!(player.start_playback_from_label("missing").is_err());
+    }
+
+    #[test]
+    fn test_pause_resume_stop() {
+        let mut player = ActionPlayer::new();
+        assert!(player.pause_playback().is_err());
+        player.load_sequence(labeled_sequence());
+        player.start_playback().unwrap();
+        assert!(player.next_action().is_some());
+
+        player.pause_playback().unwrap();
+        assert_eq!(player.state(), PlaybackState::Paused);
+        assert!(player.is_playing());
+        assert!(player.next_action().is_none());
+        assert!(player.pause_playback().is_err());
+
+        player.resume_playback().unwrap();
+        assert!(player.resume_playback().is_err());
+        assert!(player.next_action().is_some());
+
+        player.stop_playback();
+        assert_eq!(player.state(), PlaybackState::Idle);
+        assert!(player.resume_playback().is_err());
+    }
+
+    #[test]
+    fn test_partial_playback_range() {
+        let mut sequence = labeled_sequence();
+        sequence.add_action(Action::Wait { milliseconds: 1 }, 0);
+        let mut player = ActionPlayer::new();
+        player.load_sequence(sequence);
+
+        player
+            .start_playback_between(
+                Some(&StepRef::Index(0)),
+                Some(&StepRef::Label("submit".into())),
+            )
+            .unwrap();
+        assert!(player.next_action().is_some());
+        assert!(player.next_action().is_some());
+        assert!(player.next_action().is_none());
+        assert!(!player.is_playing());
+
+        assert!(player.start_playback_range(2, Some(1)).is_err());
+        assert_eq!(
+            StepRef::from_json(&serde_json::json!("submit")),
+            Some(StepRef::Label("submit".into()))
+        );
+        assert_eq!(
+            StepRef::from_json(&serde_json::json!(3)),
+            Some(StepRef::Index(3))
+        );
+    }
+
+    #[test]
+    fn test_step_notes_are_optional_in_json() {
+        let json = r#"{"action":{"type":"Wait","milliseconds":5},"delay_ms":0}"#;
+        let step: ActionWithTimestamp = serde_json::from_str(json).unwrap();
+        assert!(step.label.is_none());
+        assert!(!serde_json::to_string(&step).unwrap().contains("label"));
+    }
+
+    #[test]
+    fn test_edit_sequence_steps() {
+        let dir = std::env::temp_dir().join(format!("casper-library-{}", std::process::id()));
+        let mut library = ActionLibrary::new(dir.to_string_lossy().to_string());
+        library.add_sequence(labeled_sequence());
+
+        let wait = |milliseconds| Action::Wait { milliseconds };
+        library
+            .insert_action("test", Some(0), ActionWithTimestamp::new(wait(1), 0))
+            .unwrap();
+        assert_eq!(
+            library
+                .update_action("test", &StepRef::Label("submit".into()), wait(2))
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            library.move_action("test", &StepRef::Index(2), 0).unwrap(),
+            2
+        );
+        let removed = library.remove_action("test", &StepRef::Index(1)).unwrap();
+        assert!(matches!(removed.action, Action::Wait { milliseconds: 1 }));
+        assert!(library.remove_action("test", &StepRef::Index(5)).is_err());
+        assert!(
+            library
+                .insert_action("test", Some(9), ActionWithTimestamp::new(wait(3), 0))
+                .is_err()
+        );
+
+        library.rename_sequence("test", "renamed").unwrap();
+        assert!(library.get_sequence("test").is_none());
+        assert!(!dir.join("test.json").exists());
+        let saved = ActionSequence::load_from_file(&dir.join("renamed.json")).unwrap();
+        assert_eq!(saved.name, "renamed");
+        assert_eq!(saved.actions[0].label.as_deref(), Some("submit"));
+        assert_eq!(saved.actions.len(), 2);
+
+        library.add_sequence(ActionSequence::new("other".into(), String::new()));
+        assert!(library.rename_sequence("other", "renamed").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_yaml_sequences_keep_their_format() {
+        let dir = std::env::temp_dir().join(format!("casper-yaml-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("greet.yaml"),
+            "name: greet\nactions:\n  - action: {type: TypeText, text: hello}\n  - action: {type: PressKey, key: enter}\n    delay_ms: 200\n",
+        )
+        .unwrap();
+        let mut library = ActionLibrary::new(dir.to_string_lossy().to_string());
+        library.load_all().unwrap();
+        let greet = library.get_sequence("greet").unwrap();
+        assert_eq!(greet.actions.len(), 2);
+        assert_eq!(greet.actions[0].delay_ms, 0);
+
+        library
+            .update_action(
+                "greet",
+                &StepRef::Index(0),
+                Action::Wait { milliseconds: 1 },
+            )
+            .unwrap();
+        library.rename_sequence("greet", "hello").unwrap();
+        assert!(!dir.join("hello.json").exists());
+        let saved = ActionSequence::load_from_file(&dir.join("hello.yaml")).unwrap();
+        assert!(matches!(
+            saved.actions[0].action,
+            Action::Wait { milliseconds: 1 }
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_temporary_sequences_stay_in_memory() {
+        let dir = std::env::temp_dir().join(format!("casper-temporary-{}", std::process::id()));
+        let mut library = ActionLibrary::new(dir.to_string_lossy().to_string());
+        library.add_sequence(labeled_sequence());
+        library.save_all().unwrap();
+
+        // A saved name cannot be shadowed
+        assert!(library.add_temporary(labeled_sequence(), None).is_err());
+
+        let plan = ActionSequence::new("plan".into(), String::new());
+        library.add_temporary(plan, None).unwrap();
+        let once = ActionSequence::new("once".into(), String::new());
+        library
+            .add_temporary(once, Some(Duration::from_secs(60)))
+            .unwrap();
+        let forever = ActionSequence::new("forever".into(), String::new());
+        assert!(
+            library
+                .add_temporary(forever, Some(Duration::from_secs(u64::MAX)))
+                .is_err()
+        );
+        library.save_all().unwrap();
+        library
+            .insert_action(
+                "plan",
+                None,
+                ActionWithTimestamp::new(Action::Wait { milliseconds: 1 }, 0),
+            )
+            .unwrap();
+        assert!(!dir.join("plan.json").exists());
+        assert!(!dir.join("once.json").exists());
+        assert!(library.is_temporary("plan"));
+        assert_eq!(library.get_sequence("plan").unwrap().actions.len(), 1);
+
+        assert!(library.remove_expired(Instant::now()).is_empty());
+        let later = Instant::now() + Duration::from_secs(61);
+        assert_eq!(library.remove_expired(later), vec!["once".to_string()]);
+        assert!(library.get_sequence("once").is_none());
+
+        // Saving it normally makes it permanent
+        let plan = library.get_sequence("plan").unwrap().clone();
+        library.add_sequence(plan);
+        library.save_all().unwrap();
+        assert!(dir.join("plan.json").exists());
+        assert_eq!(library.temporary_sequences(), Vec::<String>::new());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_recorded_screenshots_follow_their_sequence() {
+        let dir = std::env::temp_dir().join(format!("casper-shots-{}", std::process::id()));
+        let scratch = dir.join("rec-1.screenshots");
+        fs::create_dir_all(&scratch).unwrap();
+        fs::write(scratch.join("1.png"), b"png").unwrap();
+
+        let mut sessions = RecordingSessions::new();
+        let id = sessions.start("login".into(), String::new()).unwrap();
+        assert!(sessions.attach_screenshot(&id, "0.png".into()).is_err());
+        sessions
+            .record_step(
+                Some(&id),
+                Action::Wait { milliseconds: 1 },
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        sessions.attach_screenshot(&id, "1.png".into()).unwrap();
+        let (_, sequence) = sessions.stop(Some(&id)).unwrap();
+        assert_eq!(sequence.actions[0].screenshot.as_deref(), Some("1.png"));
+
+        let library_dir = dir.join("actions");
+        let mut library = ActionLibrary::new(library_dir.to_string_lossy().to_string());
+        library.store_screenshots("login", &scratch).unwrap();
+        library.add_sequence(sequence);
+        library.save_all().unwrap();
+        assert!(!scratch.exists());
+        assert!(library_dir.join("login.screenshots/1.png").exists());
+
+        library.rename_sequence("login", "sign in").unwrap();
+        assert!(library_dir.join("sign_in.screenshots/1.png").exists());
+        library.delete_sequence("sign in").unwrap();
+        assert!(!library.screenshots_dir("sign in").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_overwritten_sequences_keep_versions() {
+        let dir = std::env::temp_dir().join(format!("casper-versions-{}", std::process::id()));
+        let mut library = ActionLibrary::new(dir.to_string_lossy().to_string());
+        let mut sequence = ActionSequence::new("login".into(), String::new());
+        sequence.add_action(Action::Wait { milliseconds: 1 }, 0);
+        library.add_sequence(sequence.clone());
+        library.save_all().unwrap();
+        library.save_all().unwrap();
+        assert!(library.list_versions("login").is_empty());
+
+        // A bad re-record replaces the working sequence
+        let rerecorded = ActionSequence::new("login".into(), String::new());
+        library.add_sequence(rerecorded);
+        library.save_all().unwrap();
+        let versions = library.list_versions("login");
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].steps, Some(1));
+
+        let restored = library
+            .restore_version("login", &versions[0].version)
+            .unwrap();
+        assert_eq!(restored.actions.len(), 1);
+        assert_eq!(library.list_versions("login").len(), 2);
+        assert!(library.restore_version("login", "1").is_err());
+
+        // Deleting keeps the last state as a version too
+        library.delete_sequence("login").unwrap();
+        let latest = library.list_versions("login")[0].version.clone();
+        library.restore_version("login", &latest).unwrap();
+        assert!(library.is_saved("login"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_tags_filter_summaries() {
+        let dir = std::env::temp_dir().join(format!("casper-tags-{}", std::process::id()));
+        let mut library = ActionLibrary::new(dir.to_string_lossy().to_string());
+        library.add_sequence(labeled_sequence());
+        library.add_sequence(ActionSequence::new("plain".into(), "No tags".into()));
+        library
+            .edit_sequence("test", |s| {
+                s.add_tag("work".into());
+                s.add_tag("work".into());
+                Ok(())
+            })
+            .unwrap();
+
+        let tagged = library.summaries(Some("work"));
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].tags, ["work"]);
+        assert_eq!(tagged[0].steps, 2);
+        assert_eq!(library.summaries(None).len(), 2);
+
+        let removed = library.edit_sequence("test", |s| Ok(s.remove_tag("work")));
+        assert_eq!(removed, Ok(true));
+        assert!(library.summaries(Some("work")).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sensitive_text_is_redacted() {
+        let secret: Action =
+            serde_json::from_str(r#"{"type":"TypeText","text":"hunter2","sensitive":true}"#)
+                .unwrap();
+        let mut sequence = ActionSequence::new("login".into(), String::new());
+        sequence.add_action(
+            Action::Repeat {
+                count: 2,
+                actions: vec![secret.clone()],
+            },
+            0,
+        );
+        sequence.add_action(secret, 0);
+
+        let shown = serde_json::to_string(&sequence.redacted()).unwrap();
+        assert!(!shown.contains("hunter2"));
+        assert_eq!(shown.matches(REDACTED).count(), 2);
+        // The sequence itself keeps the text to type
+        let saved = serde_json::to_string(&sequence).unwrap();
+        assert!(saved.contains("hunter2"));
+        // A redacted copy can't be saved over it
+        assert!(sequence.check_not_redacted().is_ok());
+        assert!(sequence.redacted().check_not_redacted().is_err());
+
+        let plain: Action = serde_json::from_str(r#"{"type":"TypeText","text":"hi"}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&plain.redacted()).unwrap(),
+            r#"{"type":"TypeText","text":"hi"}"#
+        );
+    }
+
+    #[test]
+    fn test_wait_for_actions() {
+        let action: Action = serde_json::from_str(
+            r##"{"type":"WaitForPixelColor","x":10,"y":20,"color":"#00ff00","tolerance":8}"##,
+        )
+        .unwrap();
+        let (condition, timeout) = action.wait_condition().unwrap();
+        assert_eq!(timeout, Duration::from_millis(DEFAULT_WAIT_TIMEOUT_MS));
+        assert_eq!(condition.to_string(), "pixel (10, 20) to be #00ff00");
+
+        let action: Action =
+            serde_json::from_str(r#"{"type":"WaitForWindow","pattern":"Slack","timeout_ms":500}"#)
+                .unwrap();
+        let (condition, timeout) = action.wait_condition().unwrap();
+        assert_eq!(timeout, Duration::from_millis(500));
+        assert_eq!(condition.to_string(), "window 'Slack'");
+        assert!(Action::Wait { milliseconds: 1 }.wait_condition().is_none());
+    }
+}
@@ -5,4 +5,4 @@ pub fn process_command(command: &str) -> Result<String, String> {
     } else {
         Err("AI under construction".to_string())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,655 @@
+// Without the "ai" feature the request plumbing is compiled but never reached
+#![cfg_attr(not(feature = "ai"), allow(dead_code))]
+
+use crate::ai_vision::{AIConfig, AIProvider, Completion, OnChunk, detect_image_mime_type};
+#[cfg(not(feature = "ai"))]
+use crate::features::missing;
+use base64::{Engine as _, engine::general_purpose};
+#[cfg(feature = "ai")]
+use reqwest::Client;
+use serde_json::{Value, json};
+
+/// Version of the Anthropic API the requests are written for
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires a limit on the answer's length; used without AI_MAX_TOKENS
+const ANTHROPIC_MAX_TOKENS: u32 = 1024;
+
+/// The HTTP side every provider shares
+struct Http {
+    #[cfg(feature = "ai")]
+    client: Client,
+}
+
+impl Http {
+    #[cfg(feature = "ai")]
+    fn new(config: &AIConfig) -> Self {
+        let timeout = std::time::Duration::from_secs(config.timeout_seconds.unwrap_or(30));
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_else(|_| Client::new());
+        Http { client }
+    }
+
+    #[cfg(not(feature = "ai"))]
+    fn new(_config: &AIConfig) -> Self {
+        Http {}
+    }
+
+    #[cfg(feature = "ai")]
+    async fn send(
+        &self,
+        url: &str,
+        headers: &[(&str, String)],
+        body: &Value,
+    ) -> Result<reqwest::Response, String> {
+        let mut request = self.client.post(url).json(body);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(format!("API error {}: {}", status, error_text));
+        }
+        Ok(response)
+    }
+
+    /// POST `body` as JSON and return the JSON answer
+    #[cfg(feature = "ai")]
+    async fn post(
+        &self,
+        url: String,
+        headers: Vec<(&str, String)>,
+        body: Value,
+    ) -> Result<Value, String> {
+        self.send(&url, &headers, &body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse response: {}", e))
+    }
+
+    #[cfg(not(feature = "ai"))]
+    async fn post(
+        &self,
+        _url: String,
+        _headers: Vec<(&str, String)>,
+        _body: Value,
+    ) -> Result<Value, String> {
+        Err(missing("ai"))
+    }
+
+    /// POST `body` for an answer sent line by line, passing the text `parse`
+    /// finds in each line to `on_chunk`
+    ///
+    /// Returns the full answer once the stream ends.
+    #[cfg(feature = "ai")]
+    async fn post_stream(
+        &self,
+        url: String,
+        headers: Vec<(&str, String)>,
+        body: Value,
+        parse: fn(&str) -> Option<String>,
+        on_chunk: OnChunk<'_>,
+    ) -> Result<String, String> {
+        let mut response = self.send(&url, &headers, &body).await?;
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut full_text = String::new();
+        let mut take = |line: &[u8]| {
+            if let Some(text) = parse(&String::from_utf8_lossy(line))
+                && !text.is_empty()
+            {
+                on_chunk(&text);
+                full_text.push_str(&text);
+            }
+        };
+        while let Some(bytes) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to read stream: {}", e))?
+        {
+            buffer.extend_from_slice(&bytes);
+            while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                take(&line);
+            }
+        }
+        take(&buffer);
+        Ok(full_text)
+    }
+
+    #[cfg(not(feature = "ai"))]
+    async fn post_stream(
+        &self,
+        _url: String,
+        _headers: Vec<(&str, String)>,
+        _body: Value,
+        _parse: fn(&str) -> Option<String>,
+        _on_chunk: OnChunk<'_>,
+    ) -> Result<String, String> {
+        Err(missing("ai"))
+    }
+}
+
+/// An image as the APIs take it, base64 encoded
+struct Image {
+    mime: &'static str,
+    data: String,
+}
+
+impl Image {
+    fn new(bytes: &[u8]) -> Self {
+        Image {
+            mime: detect_image_mime_type(bytes),
+            data: general_purpose::STANDARD.encode(bytes),
+        }
+    }
+}
+
+/// `value` without its null fields, which some APIs reject
+fn compact(mut value: Value) -> Value {
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|_, field| !field.is_null());
+    }
+    value
+}
+
+fn answer(text: Option<String>) -> Result<String, String> {
+    text.filter(|text| !text.is_empty())
+        .ok_or_else(|| "No response text from API".to_string())
+}
+
+/// The JSON of a server-sent event line (`data: {...}`)
+fn sse_data(line: &str) -> Option<Value> {
+    let data = line.trim().strip_prefix("data:")?.trim();
+    serde_json::from_str(data).ok()
+}
+
+fn bearer(token: &str) -> Vec<(&'static str, String)> {
+    if token.is_empty() {
+        return Vec::new();
+    }
+    vec![("Authorization", format!("Bearer {}", token))]
+}
+
+/// Google's Gemini API; the model is part of the request URL
+pub struct Gemini {
+    config: AIConfig,
+    http: Http,
+}
+
+impl Gemini {
+    pub fn new(config: AIConfig) -> Self {
+        let http = Http::new(&config);
+        Gemini { config, http }
+    }
+
+    fn body(&self, prompt: &str, image: Option<&[u8]>) -> Value {
+        let mut parts = vec![json!({ "text": prompt })];
+        if let Some(image) = image.map(Image::new) {
+            parts.push(json!({
+                "inline_data": { "mime_type": image.mime, "data": image.data }
+            }));
+        }
+        json!({
+            "contents": [{ "parts": parts }],
+            "generation_config": compact(json!({
+                "temperature": self.config.temperature,
+                "max_output_tokens": self.config.max_tokens
+            }))
+        })
+    }
+
+    async fn ask(&self, prompt: &str, image: Option<&[u8]>) -> Result<String, String> {
+        let url = format!("{}?key={}", self.config.request_url, self.config.token);
+        let response = self
+            .http
+            .post(url, Vec::new(), self.body(prompt, image))
+            .await?;
+        answer(gemini_text(&response))
+    }
+}
+
+impl AIProvider for Gemini {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn chat<'a>(&'a self, prompt: &'a str) -> Completion<'a> {
+        Box::pin(self.ask(prompt, None))
+    }
+
+    fn vision<'a>(&'a self, prompt: &'a str, image: &'a [u8]) -> Completion<'a> {
+        Box::pin(self.ask(prompt, Some(image)))
+    }
+
+    fn chat_stream<'a>(&'a self, prompt: &'a str, on_chunk: OnChunk<'a>) -> Completion<'a> {
+        Box::pin(async move {
+            let url = format!(
+                "{}?alt=sse&key={}",
+                stream_url(&self.config.request_url)?,
+                self.config.token
+            );
+            let body = self.body(prompt, None);
+            self.http
+                .post_stream(url, Vec::new(), body, gemini_chunk, on_chunk)
+                .await
+        })
+    }
+}
+
+/// Derive the streaming endpoint from a Gemini generateContent URL
+fn stream_url(request_url: &str) -> Result<String, String> {
+    if request_url.contains(":generateContent") {
+        Ok(request_url.replace(":generateContent", ":streamGenerateContent"))
+    } else if request_url.contains(":streamGenerateContent") {
+        Ok(request_url.to_string())
+    } else {
+        Err("Streaming requires a Gemini generateContent endpoint in AI_REQUEST_URL".to_string())
+    }
+}
+
+fn gemini_text(response: &Value) -> Option<String> {
+    let parts = response["candidates"][0]["content"]["parts"].as_array()?;
+    Some(
+        parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect(),
+    )
+}
+
+fn gemini_chunk(line: &str) -> Option<String> {
+    gemini_text(&sse_data(line)?)
+}
+
+/// OpenAI's chat completions API, which LM Studio, vLLM, llama.cpp and most
+/// other model servers also offer
+pub struct OpenAI {
+    config: AIConfig,
+    http: Http,
+}
+
+impl OpenAI {
+    pub fn new(config: AIConfig) -> Self {
+        let http = Http::new(&config);
+        OpenAI { config, http }
+    }
+
+    fn body(&self, prompt: &str, image: Option<&[u8]>, stream: bool) -> Value {
+        let content = match image.map(Image::new) {
+            Some(image) => json!([
+                { "type": "text", "text": prompt },
+                {
+                    "type": "image_url",
+                    "image_url": { "url": format!("data:{};base64,{}", image.mime, image.data) }
+                }
+            ]),
+            None => json!(prompt),
+        };
+        compact(json!({
+            "model": self.config.model,
+            "messages": [{ "role": "user", "content": content }],
+            "max_tokens": self.config.max_tokens,
+            "temperature": self.config.temperature,
+            "stream": stream
+        }))
+    }
+
+    async fn ask(&self, prompt: &str, image: Option<&[u8]>) -> Result<String, String> {
+        let response = self
+            .http
+            .post(
+                self.config.request_url.clone(),
+                bearer(&self.config.token),
+                self.body(prompt, image, false),
+            )
+            .await?;
+        answer(openai_text(&response["choices"][0]["message"]))
+    }
+}
+
+impl AIProvider for OpenAI {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn chat<'a>(&'a self, prompt: &'a str) -> Completion<'a> {
+        Box::pin(self.ask(prompt, None))
+    }
+
+    fn vision<'a>(&'a self, prompt: &'a str, image: &'a [u8]) -> Completion<'a> {
+        Box::pin(self.ask(prompt, Some(image)))
+    }
+
+    fn chat_stream<'a>(&'a self, prompt: &'a str, on_chunk: OnChunk<'a>) -> Completion<'a> {
+        Box::pin(self.http.post_stream(
+            self.config.request_url.clone(),
+            bearer(&self.config.token),
+            self.body(prompt, None, true),
+            openai_chunk,
+            on_chunk,
+        ))
+    }
+}
+
+/// Text of a message, or of the delta of a streamed one
+fn openai_text(message: &Value) -> Option<String> {
+    message["content"].as_str().map(str::to_string)
+}
+
+fn openai_chunk(line: &str) -> Option<String> {
+    // The stream ends with "data: [DONE]", which is not JSON
+    openai_text(&sse_data(line)?["choices"][0]["delta"])
+}
+
+/// Anthropic's Messages API
+pub struct Anthropic {
+    config: AIConfig,
+    http: Http,
+}
+
+impl Anthropic {
+    pub fn new(config: AIConfig) -> Self {
+        let http = Http::new(&config);
+        Anthropic { config, http }
+    }
+
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("x-api-key", self.config.token.clone()),
+            ("anthropic-version", ANTHROPIC_VERSION.to_string()),
+        ]
+    }
+
+    fn body(&self, prompt: &str, image: Option<&[u8]>, stream: bool) -> Value {
+        let mut content = Vec::new();
+        if let Some(image) = image.map(Image::new) {
+            content.push(json!({
+                "type": "image",
+                "source": { "type": "base64", "media_type": image.mime, "data": image.data }
+            }));
+        }
+        content.push(json!({ "type": "text", "text": prompt }));
+        compact(json!({
+            "model": self.config.model,
+            "max_tokens": self.config.max_tokens.unwrap_or(ANTHROPIC_MAX_TOKENS),
+            "messages": [{ "role": "user", "content": content }],
+            "temperature": self.config.temperature,
+            "stream": stream
+        }))
+    }
+
+    async fn ask(&self, prompt: &str, image: Option<&[u8]>) -> Result<String, String> {
+        let response = self
+            .http
+            .post(
+                self.config.request_url.clone(),
+                self.headers(),
+                self.body(prompt, image, false),
+            )
+            .await?;
+        answer(anthropic_text(&response))
+    }
+}
+
+impl AIProvider for Anthropic {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn chat<'a>(&'a self, prompt: &'a str) -> Completion<'a> {
+        Box::pin(self.ask(prompt, None))
+    }
+
+    fn vision<'a>(&'a self, prompt: &'a str, image: &'a [u8]) -> Completion<'a> {
+        Box::pin(self.ask(prompt, Some(image)))
+    }
+
+    fn chat_stream<'a>(&'a self, prompt: &'a str, on_chunk: OnChunk<'a>) -> Completion<'a> {
+        Box::pin(self.http.post_stream(
+            self.config.request_url.clone(),
+            self.headers(),
+            self.body(prompt, None, true),
+            anthropic_chunk,
+            on_chunk,
+        ))
+    }
+}
+
+fn anthropic_text(response: &Value) -> Option<String> {
+    let blocks = response["content"].as_array()?;
+    Some(
+        blocks
+            .iter()
+            .filter(|block| block["type"] == "text")
+            .filter_map(|block| block["text"].as_str())
+            .collect(),
+    )
+}
+
+fn anthropic_chunk(line: &str) -> Option<String> {
+    let event = sse_data(line)?;
+    if event["type"] != "content_block_delta" {
+        return None;
+    }
+    event["delta"]["text"].as_str().map(str::to_string)
+}
+
+/// A local Ollama server, through `/api/generate` or `/api/chat`
+pub struct Ollama {
+    config: AIConfig,
+    http: Http,
+}
+
+impl Ollama {
+    pub fn new(config: AIConfig) -> Self {
+        let http = Http::new(&config);
+        Ollama { config, http }
+    }
+
+    fn body(&self, prompt: &str, image: Option<&[u8]>, stream: bool) -> Value {
+        let images: Vec<String> = image
+            .map(|image| Image::new(image).data)
+            .into_iter()
+            .collect();
+        let options = compact(json!({
+            "temperature": self.config.temperature,
+            "num_predict": self.config.max_tokens
+        }));
+        if self
+            .config
+            .request_url
+            .trim_end_matches('/')
+            .ends_with("/api/chat")
+        {
+            json!({
+                "model": self.config.model,
+                "messages": [{ "role": "user", "content": prompt, "images": images }],
+                "stream": stream,
+                "options": options
+            })
+        } else {
+            json!({
+                "model": self.config.model,
+                "prompt": prompt,
+                "images": images,
+                "stream": stream,
+                "options": options
+            })
+        }
+    }
+
+    async fn ask(&self, prompt: &str, image: Option<&[u8]>) -> Result<String, String> {
+        let response = self
+            .http
+            .post(
+                self.config.request_url.clone(),
+                bearer(&self.config.token),
+                self.body(prompt, image, false),
+            )
+            .await?;
+        answer(ollama_text(&response))
+    }
+}
+
+impl AIProvider for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn chat<'a>(&'a self, prompt: &'a str) -> Completion<'a> {
+        Box::pin(self.ask(prompt, None))
+    }
+
+    fn vision<'a>(&'a self, prompt: &'a str, image: &'a [u8]) -> Completion<'a> {
+        Box::pin(self.ask(prompt, Some(image)))
+    }
+
+    fn chat_stream<'a>(&'a self, prompt: &'a str, on_chunk: OnChunk<'a>) -> Completion<'a> {
+        Box::pin(self.http.post_stream(
+            self.config.request_url.clone(),
+            bearer(&self.config.token),
+            self.body(prompt, None, true),
+            ollama_chunk,
+            on_chunk,
+        ))
+    }
+}
+
+/// Answer of `/api/generate` or `/api/chat`, whole or one streamed piece
+fn ollama_text(response: &Value) -> Option<String> {
+    response["response"]
+        .as_str()
+        .or(response["message"]["content"].as_str())
+        .map(str::to_string)
+}
+
+fn ollama_chunk(line: &str) -> Option<String> {
+    // One JSON object per line, without the "data:" of server-sent events
+    ollama_text(&serde_json::from_str(line.trim()).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_vision::AIProviderKind;
+
+    fn config(provider: AIProviderKind, request_url: &str) -> AIConfig {
+        AIConfig {
+            provider,
+            request_url: request_url.to_string(),
+            token: "secret".to_string(),
+            model: "model".to_string(),
+            max_tokens: None,
+            temperature: Some(0.5),
+            timeout_seconds: None,
+        }
+    }
+
+    #[test]
+    fn test_stream_url_and_sse_parsing() {
+        assert_eq!(
+            stream_url("https://x/models/gemini:generateContent").unwrap(),
+            "https://x/models/gemini:streamGenerateContent"
+        );
+        assert!(stream_url("https://api.openai.com/v1/chat/completions").is_err());
+
+        let line = r#"data: {"candidates":[{"content":{"parts":[{"text":"Hel"}]}}]}"#;
+        assert_eq!(gemini_chunk(line).as_deref(), Some("Hel"));
+        assert_eq!(gemini_chunk(""), None);
+
+        let line = r#"data: {"choices":[{"delta":{"content":"lo"}}]}"#;
+        assert_eq!(openai_chunk(line).as_deref(), Some("lo"));
+        assert_eq!(openai_chunk("data: [DONE]"), None);
+
+        let line = r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#;
+        assert_eq!(anthropic_chunk(line).as_deref(), Some("Hi"));
+        assert_eq!(anthropic_chunk(r#"data: {"type":"ping"}"#), None);
+
+        let line = r#"{"model":"llava","response":"Hey","done":false}"#;
+        assert_eq!(ollama_chunk(line).as_deref(), Some("Hey"));
+    }
+
+    #[test]
+    fn test_provider_requests() {
+        let png = [0x89, b'P', b'N', b'G'];
+
+        let gemini = Gemini::new(config(AIProviderKind::Gemini, "https://x:generateContent"));
+        let body = gemini.body("What is this?", Some(&png));
+        assert_eq!(body["contents"][0]["parts"][0]["text"], "What is this?");
+        assert_eq!(
+            body["contents"][0]["parts"][1]["inline_data"]["mime_type"],
+            "image/png"
+        );
+        assert_eq!(body["generation_config"], json!({ "temperature": 0.5 }));
+
+        let openai = OpenAI::new(config(
+            AIProviderKind::OpenAI,
+            "https://x/v1/chat/completions",
+        ));
+        let body = openai.body("Hi", None, false);
+        assert_eq!(body["messages"][0]["content"], "Hi");
+        assert!(body.get("max_tokens").is_none());
+        let body = openai.body("What is this?", Some(&png), true);
+        let url = body["messages"][0]["content"][1]["image_url"]["url"]
+            .as_str()
+            .unwrap();
+        assert!(url.starts_with("data:image/png;base64,"));
+        assert_eq!(body["stream"], true);
+
+        let anthropic = Anthropic::new(config(AIProviderKind::Anthropic, "https://x/v1/messages"));
+        let body = anthropic.body("What is this?", Some(&png), false);
+        assert_eq!(body["max_tokens"], ANTHROPIC_MAX_TOKENS);
+        assert_eq!(body["messages"][0]["content"][0]["type"], "image");
+        assert_eq!(body["messages"][0]["content"][1]["text"], "What is this?");
+        let response = json!({ "content": [{ "type": "text", "text": "A cat" }] });
+        assert_eq!(anthropic_text(&response).as_deref(), Some("A cat"));
+
+        let ollama = Ollama::new(config(
+            AIProviderKind::Ollama,
+            "http://x:11434/api/generate",
+        ));
+        let body = ollama.body("Hi", None, false);
+        assert_eq!(body["prompt"], "Hi");
+        assert_eq!(body["stream"], false);
+        let ollama = Ollama::new(config(AIProviderKind::Ollama, "http://x:11434/api/chat"));
+        let body = ollama.body("What is this?", Some(&png), false);
+        assert_eq!(body["messages"][0]["images"][0], "iVBORw==");
+
+        assert!(answer(Some(String::new())).is_err());
+
+        let kind = AIProviderKind::from_url;
+        assert_eq!(
+            kind("https://generativelanguage.googleapis.com/v1beta/models/m:generateContent"),
+            AIProviderKind::Gemini
+        );
+        assert_eq!(
+            kind("https://api.anthropic.com/v1/messages"),
+            AIProviderKind::Anthropic
+        );
+        assert_eq!(
+            kind("http://localhost:11434/api/generate"),
+            AIProviderKind::Ollama
+        );
+        assert_eq!(
+            kind("http://localhost:1234/v1/chat/completions"),
+            AIProviderKind::OpenAI
+        );
+        assert_eq!(
+            AIProviderKind::parse(" Claude ").unwrap(),
+            AIProviderKind::Anthropic
+        );
+        assert!(AIProviderKind::parse("skynet").is_err());
+    }
+}
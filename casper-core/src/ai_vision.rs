@@ -1,12 +1,92 @@
-use base64::{Engine as _, engine::general_purpose};
-use reqwest::Client;
+use crate::actions::{ACTION_SCHEMA, ActionSequence, ActionWithTimestamp};
+use crate::ai_providers::{Anthropic, Gemini, Ollama, OpenAI};
+use crate::features::missing;
+use crate::paths;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs;
+use std::future::Future;
+use std::pin::Pin;
+
+/// An answer being generated
+pub type Completion<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>>;
+
+/// Receives the text of a streamed answer as it arrives
+pub type OnChunk<'a> = &'a mut (dyn FnMut(&str) + Send);
+
+/// A way of asking a language model, speaking the API of one provider
+///
+/// Everything `AIVision` asks goes through `chat` and `vision`, so a new
+/// provider only has to build its requests and read its answers.
+pub trait AIProvider: Send + Sync {
+    /// Name used in `AI_PROVIDER`
+    fn name(&self) -> &'static str;
+    /// Answer a text-only prompt
+    fn chat<'a>(&'a self, prompt: &'a str) -> Completion<'a>;
+    /// Answer a prompt about an image (PNG, JPEG, GIF or WebP data)
+    fn vision<'a>(&'a self, prompt: &'a str, image: &'a [u8]) -> Completion<'a>;
+
+    /// Answer a text-only prompt, passing on the text as it arrives
+    ///
+    /// Providers that cannot stream pass on the whole answer at once.
+    fn chat_stream<'a>(&'a self, prompt: &'a str, on_chunk: OnChunk<'a>) -> Completion<'a> {
+        Box::pin(async move {
+            let answer = self.chat(prompt).await?;
+            on_chunk(&answer);
+            Ok(answer)
+        })
+    }
+}
+
+/// Which API the configured endpoint speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIProviderKind {
+    Gemini,
+    /// OpenAI's chat completions API, and the servers that copy it
+    OpenAI,
+    Anthropic,
+    Ollama,
+}
+
+impl AIProviderKind {
+    /// Parse an `AI_PROVIDER` value
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "gemini" | "google" => Ok(AIProviderKind::Gemini),
+            "openai" => Ok(AIProviderKind::OpenAI),
+            "anthropic" | "claude" => Ok(AIProviderKind::Anthropic),
+            "ollama" => Ok(AIProviderKind::Ollama),
+            other => Err(format!(
+                "Unknown AI provider '{}' (gemini, openai, anthropic or ollama)",
+                other
+            )),
+        }
+    }
+
+    /// Guess the provider from its endpoint, for settings without `AI_PROVIDER`
+    ///
+    /// Unknown servers are taken to be OpenAI-compatible, as most are.
+    pub fn from_url(request_url: &str) -> Self {
+        let url = request_url.to_lowercase();
+        if url.contains("generativelanguage.googleapis.com") || url.contains(":generatecontent") {
+            AIProviderKind::Gemini
+        } else if url.contains("anthropic.com") || url.ends_with("/v1/messages") {
+            AIProviderKind::Anthropic
+        } else if url.contains(":11434")
+            || url.contains("/api/generate")
+            || url.contains("/api/chat")
+        {
+            AIProviderKind::Ollama
+        } else {
+            AIProviderKind::OpenAI
+        }
+    }
+}
 
 /// Configuration for AI provider
 #[derive(Debug, Clone)]
 pub struct AIConfig {
+    pub provider: AIProviderKind,
     pub request_url: String,
     pub token: String,
     pub model: String,
@@ -18,14 +98,24 @@ pub struct AIConfig {
 impl AIConfig {
     /// Load configuration from environment variables
     pub fn from_env() -> Result<Self, String> {
-        // Load .env file if it exists
+        // Load .env file if it exists, then the settings saved by the setup wizard
         dotenv::dotenv().ok();
+        dotenv::from_path(paths::config_file(None)).ok();
 
         let request_url = env::var("AI_REQUEST_URL")
             .map_err(|_| "AI_REQUEST_URL not set in environment".to_string())?;
 
-        let token =
-            env::var("AI_TOKEN").map_err(|_| "AI_TOKEN not set in environment".to_string())?;
+        let provider = match env::var("AI_PROVIDER") {
+            Ok(name) => AIProviderKind::parse(&name)?,
+            Err(_) => AIProviderKind::from_url(&request_url),
+        };
+
+        // A local Ollama server needs no token
+        let token = match env::var("AI_TOKEN") {
+            Ok(token) => token,
+            Err(_) if provider == AIProviderKind::Ollama => String::new(),
+            Err(_) => return Err("AI_TOKEN not set in environment".to_string()),
+        };
 
         let model =
             env::var("AI_MODEL").map_err(|_| "AI_MODEL not set in environment".to_string())?;
@@ -39,6 +129,7 @@ impl AIConfig {
             .and_then(|v| v.parse().ok());
 
         Ok(AIConfig {
+            provider,
             request_url,
             token,
             model,
@@ -49,85 +140,46 @@ impl AIConfig {
     }
 }
 
-/// Request to Gemini API with vision
-#[derive(Debug, Serialize)]
-struct GeminiRequest {
-    contents: Vec<GeminiContent>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    generation_config: Option<GenerationConfig>,
-}
-
-#[derive(Debug, Serialize)]
-struct GeminiContent {
-    parts: Vec<GeminiPart>,
-}
-
-#[derive(Debug, Serialize)]
-#[serde(untagged)]
-enum GeminiPart {
-    Text { text: String },
-    Image { inline_data: InlineData },
-}
-
-#[derive(Debug, Serialize)]
-struct InlineData {
-    mime_type: String,
-    data: String,
-}
-
-#[derive(Debug, Serialize)]
-struct GenerationConfig {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    max_output_tokens: Option<u32>,
-}
-
-/// Response from Gemini API
-#[derive(Debug, Deserialize)]
-struct GeminiResponse {
-    candidates: Vec<GeminiCandidate>,
-}
-
-#[derive(Debug, Deserialize)]
-struct GeminiCandidate {
-    content: GeminiResponseContent,
-}
-
-#[derive(Debug, Deserialize)]
-struct GeminiResponseContent {
-    parts: Vec<GeminiResponsePart>,
-}
-
-#[derive(Debug, Deserialize)]
-struct GeminiResponsePart {
-    text: String,
+/// The provider `config` selects
+pub fn ai_provider(config: AIConfig) -> Box<dyn AIProvider> {
+    match config.provider {
+        AIProviderKind::Gemini => Box::new(Gemini::new(config)),
+        AIProviderKind::OpenAI => Box::new(OpenAI::new(config)),
+        AIProviderKind::Anthropic => Box::new(Anthropic::new(config)),
+        AIProviderKind::Ollama => Box::new(Ollama::new(config)),
+    }
 }
 
 /// AI Vision client for understanding screen content
 pub struct AIVision {
-    config: AIConfig,
-    client: Client,
+    provider: Box<dyn AIProvider>,
 }
 
 impl AIVision {
     /// Create a new AI vision client
     pub fn new(config: AIConfig) -> Self {
-        let timeout = std::time::Duration::from_secs(config.timeout_seconds.unwrap_or(30));
-        let client = Client::builder()
-            .timeout(timeout)
-            .build()
-            .unwrap_or_else(|_| Client::new());
+        Self::with_provider(ai_provider(config))
+    }
 
-        AIVision { config, client }
+    /// Create a client asking `provider`, which may be one of your own
+    pub fn with_provider(provider: Box<dyn AIProvider>) -> Self {
+        AIVision { provider }
     }
 
     /// Create from environment variables
     pub fn from_env() -> Result<Self, String> {
+        if !cfg!(feature = "ai") {
+            return Err(missing("ai"));
+        }
         let config = AIConfig::from_env()?;
         Ok(Self::new(config))
     }
 
+    /// Name of the provider asked
+    pub fn provider_name(&self) -> &'static str {
+        self.provider.name()
+    }
+
     /// Analyze a screenshot and answer a question about it
     pub async fn analyze_screenshot(
         &self,
@@ -143,67 +195,23 @@ impl AIVision {
 
     /// Analyze image data directly
     pub async fn analyze_image(&self, image_data: &[u8], prompt: &str) -> Result<String, String> {
-        // Encode image to base64
-        let base64_image = general_purpose::STANDARD.encode(image_data);
-
-        // Detect MIME type (simplified - assumes PNG for now)
-        let mime_type = detect_image_mime_type(image_data);
-
-        // Build request for Gemini
-        let request = GeminiRequest {
-            contents: vec![GeminiContent {
-                parts: vec![
-                    GeminiPart::Text {
-                        text: prompt.to_string(),
-                    },
-                    GeminiPart::Image {
-                        inline_data: InlineData {
-                            mime_type: mime_type.to_string(),
-                            data: base64_image,
-                        },
-                    },
-                ],
-            }],
-            generation_config: Some(GenerationConfig {
-                temperature: self.config.temperature,
-                max_output_tokens: self.config.max_tokens,
-            }),
-        };
-
-        // Make API request
-        let url = format!("{}?key={}", self.config.request_url, self.config.token);
-
-        let response = self
-            .client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send request: {}", e))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(format!("API error {}: {}", status, error_text));
-        }
-
-        let gemini_response: GeminiResponse = response
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+        self.provider.vision(prompt, image_data).await
+    }
 
-        // Extract text from response
-        let text = gemini_response
-            .candidates
-            .first()
-            .and_then(|c| c.content.parts.first())
-            .map(|p| p.text.clone())
-            .ok_or_else(|| "No response text from API".to_string())?;
+    /// Send a text-only prompt
+    pub async fn complete(&self, prompt: &str) -> Result<String, String> {
+        self.provider.chat(prompt).await
+    }
 
-        Ok(text)
+    /// Send a text-only prompt, reporting the answer incrementally as it arrives
+    ///
+    /// Returns the full answer once the stream ends.
+    pub async fn complete_stream<F: FnMut(&str) + Send>(
+        &self,
+        prompt: &str,
+        mut on_chunk: F,
+    ) -> Result<String, String> {
+        self.provider.chat_stream(prompt, &mut on_chunk).await
     }
 
     /// Find UI element coordinates by description
@@ -299,6 +307,84 @@ impl AIVision {
 
         Ok(steps)
     }
+
+    /// Draft a sequence of actions accomplishing a task described in plain language
+    ///
+    /// When a screenshot is given, the model also sees the current screen so it can
+    /// pick coordinates. The result is marked as a draft for review before playback.
+    pub async fn generate_sequence(
+        &self,
+        name: &str,
+        description: &str,
+        screenshot_path: Option<&str>,
+    ) -> Result<ActionSequence, String> {
+        let prompt = format!(
+            "You control a Linux desktop through these actions (one JSON object each):\n{}\n\n\
+             Task: {}\n\n\
+             Respond ONLY with a JSON array of steps in this exact format: \
+             [{{\"action\": <action object>, \"delay_ms\": <ms to wait before the step>, \
+             \"comment\": <short explanation>}}] \
+             Do not include any other text in your response.",
+            ACTION_SCHEMA, description
+        );
+
+        let response = match screenshot_path {
+            Some(path) => self.analyze_screenshot(path, &prompt).await?,
+            None => self.complete(&prompt).await?,
+        };
+
+        let steps = parse_generated_steps(&response)?;
+        let mut sequence = ActionSequence::new(name.to_string(), description.to_string());
+        sequence.actions = steps;
+        sequence.draft = true;
+        sequence.add_tag("generated".to_string());
+        Ok(sequence)
+    }
+
+    /// Summarize in plain language what a sequence will do when played
+    ///
+    /// Steps flagged by `ActionSequence::risky_steps` are passed along so the
+    /// summary calls them out explicitly.
+    pub async fn explain_sequence(&self, sequence: &ActionSequence) -> Result<String, String> {
+        let steps = serde_json::to_string_pretty(&sequence.redacted().actions)
+            .map_err(|e| format!("Failed to serialize sequence: {}", e))?;
+        let risky: Vec<String> = sequence
+            .risky_steps()
+            .iter()
+            .map(|(index, reason)| format!("- step {}: {}", index, reason))
+            .collect();
+        let risky = if risky.is_empty() {
+            "none detected".to_string()
+        } else {
+            risky.join("\n")
+        };
+
+        let prompt = format!(
+            "A desktop automation sequence named '{}' ({}) will replay these steps \
+             on a Linux desktop. Each step waits delay_ms and then performs its action.\n\
+             Action reference:\n{}\n\nSteps:\n{}\n\n\
+             Steps flagged as risky:\n{}\n\n\
+             Explain in a few short sentences of plain language what running this \
+             sequence will do, for someone deciding whether to trust it. Then list \
+             every risky step (shell commands, deleting files or text, anything \
+             irreversible) with its step number and why it is risky.",
+            sequence.name, sequence.description, ACTION_SCHEMA, steps, risky
+        );
+
+        self.complete(&prompt).await
+    }
+}
+
+/// Parse the step list produced for `generate_sequence`
+fn parse_generated_steps(response: &str) -> Result<Vec<ActionWithTimestamp>, String> {
+    let json_str = extract_json_array_from_text(response)
+        .ok_or_else(|| format!("AI response is not a JSON array: {}", response))?;
+    let steps: Vec<ActionWithTimestamp> = serde_json::from_str(&json_str)
+        .map_err(|e| format!("Failed to parse generated steps: {}", e))?;
+    if steps.is_empty() {
+        return Err("AI returned an empty sequence".to_string());
+    }
+    Ok(steps)
 }
 
 /// Position of a UI element
@@ -318,7 +404,7 @@ pub struct ElementPosition {
 }
 
 /// Detect MIME type from image data
-fn detect_image_mime_type(data: &[u8]) -> &'static str {
+pub(crate) fn detect_image_mime_type(data: &[u8]) -> &'static str {
     if data.len() < 4 {
         return "image/png"; // default
     }
@@ -333,6 +419,18 @@ fn detect_image_mime_type(data: &[u8]) -> &'static str {
     }
 }
 
+/// Extract a JSON array from text that might contain extra content
+fn extract_json_array_from_text(text: &str) -> Option<String> {
+    let start = text.find('[')?;
+    let end = text.rfind(']')?;
+
+    if end > start {
+        Some(text[start..=end].to_string())
+    } else {
+        None
+    }
+}
+
 /// Extract JSON object from text that might contain extra content
 fn extract_json_from_text(text: &str) -> Option<String> {
     // Find the first { and last }
@@ -369,4 +467,15 @@ mod tests {
         assert!(json.is_some());
         assert_eq!(json.unwrap(), r#"{"found": true, "x": 100}"#);
     }
+
+    #[test]
+    fn test_parse_generated_steps() {
+        let text = "Here you go:\n[{\"action\": {\"type\": \"PressKey\", \"key\": \"enter\"}, \"delay_ms\": 100, \"comment\": \"Confirm\"}]";
+        let steps = parse_generated_steps(text).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].delay_ms, 100);
+        assert_eq!(steps[0].comment.as_deref(), Some("Confirm"));
+        assert!(parse_generated_steps("[]").is_err());
+        assert!(parse_generated_steps("no json here").is_err());
+    }
 }
@@ -0,0 +1,238 @@
+use crate::actions::ScreenAssertion;
+use crate::ai_vision::AIVision;
+use crate::capture::{capture_screen_temp, colors_match, decode_png, encode_png};
+use crate::paths;
+use crate::pipeline::capture_region_temp;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Check an `AssertScreen` step against the screen as it is now
+pub fn assert_screen(assertion: &ScreenAssertion) -> Result<(), String> {
+    check_assertion(assertion)?;
+    let capture = match &assertion.region {
+        Some(region) => capture_region_temp(region)?,
+        None => capture_screen_temp()?,
+    };
+    let result = check_capture(assertion, &capture);
+    let _ = fs::remove_file(&capture);
+    result
+}
+
+/// Whether an `AssertScreen` step has anything to check, a reference image
+/// or an `expect` statement
+pub fn check_assertion(assertion: &ScreenAssertion) -> Result<(), String> {
+    if assertion.reference.is_none() && assertion.expect.is_none() {
+        return Err("AssertScreen needs a reference image or an expect statement".to_string());
+    }
+    Ok(())
+}
+
+fn check_capture(assertion: &ScreenAssertion, capture: &str) -> Result<(), String> {
+    if let Some(reference) = &assertion.reference {
+        let actual = fs::read(capture).map_err(|e| format!("Failed to read capture: {}", e))?;
+        compare_with_reference(
+            &actual,
+            &reference_path(reference),
+            assertion.tolerance.unwrap_or(0),
+            assertion.max_difference.unwrap_or(0.0),
+        )?;
+    }
+    if let Some(statement) = &assertion.expect {
+        ask_ai(capture, statement)?;
+    }
+    Ok(())
+}
+
+/// Where the reference image of an `AssertScreen` step is kept
+pub fn reference_path(reference: &str) -> PathBuf {
+    let path = Path::new(reference);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        paths::references_dir().join(path)
+    }
+}
+
+/// Compare a capture with its reference image, recording the reference if
+/// there is none yet
+///
+/// On a mismatch, an image of the differing pixels is written to the errors
+/// directory and its path is part of the error.
+fn compare_with_reference(
+    actual: &[u8],
+    reference: &Path,
+    tolerance: u8,
+    max_difference: f64,
+) -> Result<(), String> {
+    if !reference.exists() {
+        if let Some(parent) = reference.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        return fs::write(reference, actual)
+            .map_err(|e| format!("Failed to write {}: {}", reference.display(), e));
+    }
+    let expected = fs::read(reference)
+        .map_err(|e| format!("Failed to read {}: {}", reference.display(), e))?;
+    let diff = diff_images(actual, &expected, tolerance)
+        .map_err(|e| format!("{} ({})", e, reference.display()))?;
+    let share = diff.differing as f64 / diff.total.max(1) as f64;
+    if share <= max_difference {
+        return Ok(());
+    }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let stem = reference
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let dir = paths::errors_dir(None);
+    let diff_path = dir.join(format!("{}-{}-diff.png", timestamp, stem));
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+    fs::write(&diff_path, &diff.image)
+        .map_err(|e| format!("Failed to write {}: {}", diff_path.display(), e))?;
+    Err(format!(
+        "Screen differs from {} in {:.2}% of pixels; diff: {}",
+        reference.display(),
+        share * 100.0,
+        diff_path.display()
+    ))
+}
+
+/// Pixels of a capture that differ from the reference
+struct ImageDiff {
+    differing: usize,
+    total: usize,
+    /// PNG of the reference, faded, with the differing pixels in red
+    image: Vec<u8>,
+}
+
+fn diff_images(actual: &[u8], expected: &[u8], tolerance: u8) -> Result<ImageDiff, String> {
+    let (width, height, actual) = decode_png(actual)?;
+    let (expected_width, expected_height, expected) = decode_png(expected)?;
+    if (width, height) != (expected_width, expected_height) {
+        return Err(format!(
+            "Screen area is {}x{} but the reference is {}x{}",
+            width, height, expected_width, expected_height
+        ));
+    }
+
+    let mut differing = 0;
+    let mut pixels = Vec::with_capacity(expected.len());
+    for (a, e) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        if colors_match((a[0], a[1], a[2]), (e[0], e[1], e[2]), tolerance) {
+            pixels.extend_from_slice(&[170 + e[0] / 3, 170 + e[1] / 3, 170 + e[2] / 3, 255]);
+        } else {
+            differing += 1;
+            pixels.extend_from_slice(&[255, 0, 0, 255]);
+        }
+    }
+    Ok(ImageDiff {
+        differing,
+        total: expected.len() / 4,
+        image: encode_png(width, height, &pixels)?,
+    })
+}
+
+/// Ask the AI whether a statement holds for a screenshot
+fn ask_ai(image: &str, statement: &str) -> Result<(), String> {
+    let vision = AIVision::from_env()?;
+    let prompt = format!(
+        "Does this statement hold for the screenshot? \"{}\"\n\
+         Answer YES or NO on the first line, then give a short reason.",
+        statement
+    );
+    // A thread of its own, so this works from async code as well as from
+    // the blocking tasks steps normally run on
+    let answer = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| format!("Failed to start runtime: {}", e))?
+                    .block_on(vision.analyze_screenshot(image, &prompt))
+            })
+            .join()
+            .unwrap_or_else(|_| Err("AI request panicked".to_string()))
+    })?;
+    verdict(statement, &answer)
+}
+
+/// Read a YES/NO answer, failing with the AI's reason on anything but yes
+fn verdict(statement: &str, answer: &str) -> Result<(), String> {
+    let answer = answer.trim();
+    let (first, reason) = answer.split_once('\n').unwrap_or((answer, ""));
+    let word = first
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    if word.starts_with("yes") {
+        return Ok(());
+    }
+    let reason = match reason.trim() {
+        "" => first.trim(),
+        reason => reason,
+    };
+    Err(format!(
+        "Screen assertion failed: {} ({})",
+        statement, reason
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "capture")]
+    fn image(pixels: &[[u8; 4]]) -> Vec<u8> {
+        encode_png(pixels.len() as u32, 1, pixels.concat().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_assertion_needs_something_to_check() {
+        let mut assertion: ScreenAssertion = serde_json::from_str("{}").unwrap();
+        assert!(check_assertion(&assertion).is_err());
+        assert!(assert_screen(&assertion).is_err());
+        assertion.expect = Some("a dialog is open".to_string());
+        assert!(check_assertion(&assertion).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_diff_images() {
+        let reference = image(&[[0, 0, 0, 255], [255, 255, 255, 255], [9, 9, 9, 255]]);
+        let capture = image(&[[4, 0, 0, 255], [255, 255, 255, 255], [9, 90, 9, 255]]);
+        let diff = diff_images(&capture, &reference, 4).unwrap();
+        assert_eq!((diff.differing, diff.total), (1, 3));
+        let (_, _, pixels) = decode_png(&diff.image).unwrap();
+        assert_eq!(&pixels[8..], &[255, 0, 0, 255]);
+        assert_eq!(diff_images(&capture, &reference, 100).unwrap().differing, 0);
+
+        let smaller = image(&[[0, 0, 0, 255]]);
+        assert!(diff_images(&smaller, &reference, 0).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_reference_is_recorded_once() {
+        let dir = std::env::temp_dir().join(format!("casper_refs_{}", std::process::id()));
+        let reference = dir.join("button.png");
+        let first = image(&[[1, 2, 3, 255]]);
+        compare_with_reference(&first, &reference, 0, 0.0).unwrap();
+        assert_eq!(fs::read(&reference).unwrap(), first);
+        compare_with_reference(&first, &reference, 0, 0.0).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_verdict() {
+        assert!(verdict("a dialog is open", "Yes.\nThere is a dialog").is_ok());
+        assert!(verdict("a dialog is open", "**YES** it is").is_ok());
+        assert_eq!(
+            verdict("a dialog is open", "NO\nThe desktop is empty"),
+            Err("Screen assertion failed: a dialog is open (The desktop is empty)".to_string())
+        );
+    }
+}
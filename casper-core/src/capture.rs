@@ -1,26 +1,145 @@
+use crate::commands::CommandDeadline;
+use crate::displays::{Display, DisplayRef, find_display};
+use crate::features::missing;
+use crate::paths;
+use crate::portal;
+use crate::screenshots::screenshot_path;
+use crate::window::{active_window_id, window_geometry};
+use serde::{Deserialize, Serialize};
 use std::process::Command;
 
+/// JPEG quality used when the options do not give one
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
 /// Screen capture utility for Wayland and X11
 pub struct ScreenCapture {
     backend: CaptureBackend,
+    options: CaptureOptions,
+}
+
+/// Image formats captures can be encoded in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    /// Lossless WebP, usually well under the size of the PNG
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+
+    /// The format a file name asks for by its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        match extension.to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// How captures are taken and encoded
+///
+/// `cursor` applies to every capture; `format`, `quality` and `scale` to
+/// `capture` and `capture_image`, the other methods always write PNG at
+/// full size.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaptureOptions {
+    #[serde(default)]
+    pub format: ImageFormat,
+    /// JPEG quality from 1 to 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// Factor the width and height are scaled down by, e.g. 0.5 for a
+    /// quarter of the pixels, which keeps images sent to the AI small
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+    /// Draw the mouse pointer into the image
+    #[serde(default)]
+    pub cursor: bool,
+}
+
+impl CaptureOptions {
+    /// Whether captures come out as the backend takes them: full-size PNG
+    fn is_plain(&self) -> bool {
+        self.format == ImageFormat::Png && self.scale.is_none_or(|scale| scale >= 1.0)
+    }
 }
 
 #[derive(Debug, Clone)]
 enum CaptureBackend {
+    X11,    // X11 (in-process, straight from the X server)
     Grim,   // Wayland (grim + slurp)
     Scrot,  // X11
     Import, // X11 (ImageMagick)
+    Portal, // xdg-desktop-portal Screenshot, see `portal::portal_only`
 }
 
 impl ScreenCapture {
     /// Create a new screen capture instance, auto-detecting the backend
     pub fn new() -> Result<Self, String> {
+        if !cfg!(feature = "capture") {
+            return Err(missing("capture"));
+        }
         let backend = Self::detect_backend()?;
-        Ok(ScreenCapture { backend })
+        Ok(ScreenCapture {
+            backend,
+            options: CaptureOptions::default(),
+        })
+    }
+
+    pub fn with_options(mut self, options: CaptureOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Arguments that make the backend's tool draw the pointer, if the
+    /// options ask for it
+    fn cursor_args(&self) -> Result<&'static [&'static str], String> {
+        if !self.options.cursor {
+            return Ok(&[]);
+        }
+        match self.backend {
+            // The pointer is drawn in-process
+            CaptureBackend::X11 => Ok(&[]),
+            CaptureBackend::Grim => Ok(&["-c"]),
+            CaptureBackend::Scrot => Ok(&["-p"]),
+            CaptureBackend::Import => Err("ImageMagick cannot include the cursor".to_string()),
+            CaptureBackend::Portal => Err("The portal cannot include the cursor".to_string()),
+        }
     }
 
     /// Detect which capture backend to use
     fn detect_backend() -> Result<CaptureBackend, String> {
+        if portal::portal_only() {
+            return Ok(CaptureBackend::Portal);
+        }
+
+        // X11 sessions need no tool at all
+        if cfg!(feature = "x11")
+            && std::env::var("DISPLAY").is_ok()
+            && std::env::var("WAYLAND_DISPLAY").is_err()
+        {
+            return Ok(CaptureBackend::X11);
+        }
+
+        Self::detect_tool_backend()
+    }
+
+    /// The screenshot tool to use, for what cannot be done in-process
+    fn detect_tool_backend() -> Result<CaptureBackend, String> {
         // Check if we're on Wayland
         if std::env::var("WAYLAND_DISPLAY").is_ok() {
             // Try grim for Wayland
@@ -46,11 +165,17 @@ impl ScreenCapture {
 
     /// Capture the entire screen
     pub fn capture_screen(&self, output_path: &str) -> Result<(), String> {
+        let cursor = self.cursor_args()?;
         match self.backend {
+            CaptureBackend::X11 => {
+                write_x11_capture(&FrameSource::Screen, self.options.cursor, output_path)
+            }
+            CaptureBackend::Portal => portal::screenshot(output_path, false),
             CaptureBackend::Grim => {
                 let output = Command::new("grim")
+                    .args(cursor)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute grim: {}", e))?;
 
                 if output.status.success() {
@@ -64,8 +189,9 @@ impl ScreenCapture {
             }
             CaptureBackend::Scrot => {
                 let output = Command::new("scrot")
+                    .args(cursor)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute scrot: {}", e))?;
 
                 if output.status.success() {
@@ -82,7 +208,7 @@ impl ScreenCapture {
                     .arg("-window")
                     .arg("root")
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute import: {}", e))?;
 
                 if output.status.success() {
@@ -106,14 +232,35 @@ impl ScreenCapture {
         height: i32,
         output_path: &str,
     ) -> Result<(), String> {
+        let cursor = self.cursor_args()?;
         match self.backend {
+            CaptureBackend::X11 => write_x11_capture(
+                &FrameSource::Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                self.options.cursor,
+                output_path,
+            ),
+            CaptureBackend::Portal => {
+                // The portal only captures whole screens; the region is cut out afterwards
+                portal::screenshot(output_path, false)?;
+                let screen = std::fs::read(output_path)
+                    .map_err(|e| format!("Failed to read capture: {}", e))?;
+                let region = crop_png(&screen, x, y, width, height)?;
+                std::fs::write(output_path, region)
+                    .map_err(|e| format!("Failed to write capture: {}", e))
+            }
             CaptureBackend::Grim => {
                 let geometry = format!("{},{} {}x{}", x, y, width, height);
                 let output = Command::new("grim")
+                    .args(cursor)
                     .arg("-g")
                     .arg(geometry)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute grim: {}", e))?;
 
                 if output.status.success() {
@@ -128,10 +275,11 @@ impl ScreenCapture {
             CaptureBackend::Scrot => {
                 let geometry = format!("{}x{}+{}+{}", width, height, x, y);
                 let output = Command::new("scrot")
+                    .args(cursor)
                     .arg("-a")
                     .arg(geometry)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute scrot: {}", e))?;
 
                 if output.status.success() {
@@ -151,7 +299,7 @@ impl ScreenCapture {
                     .arg("-crop")
                     .arg(geometry)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute import: {}", e))?;
 
                 if output.status.success() {
@@ -168,18 +316,29 @@ impl ScreenCapture {
 
     /// Capture a specific window by its ID
     pub fn capture_window(&self, window_id: &str, output_path: &str) -> Result<(), String> {
+        let cursor = self.cursor_args()?;
         match self.backend {
+            CaptureBackend::X11 => write_x11_capture(
+                &FrameSource::Window(window_id.to_string()),
+                self.options.cursor,
+                output_path,
+            ),
+            CaptureBackend::Portal => Err(
+                "Window capture is not available through portals. Use capture_region instead."
+                    .to_string(),
+            ),
             CaptureBackend::Grim => {
                 // For grim, we need to get window geometry first using swaymsg or similar
                 Err("Window capture with grim requires window geometry. Use capture_region instead.".to_string())
             }
             CaptureBackend::Scrot => {
                 let output = Command::new("scrot")
+                    .args(cursor)
                     .arg("-u")
                     .arg("-i")
                     .arg(window_id)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute scrot: {}", e))?;
 
                 if output.status.success() {
@@ -196,7 +355,7 @@ impl ScreenCapture {
                     .arg("-window")
                     .arg(window_id)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute import: {}", e))?;
 
                 if output.status.success() {
@@ -213,8 +372,10 @@ impl ScreenCapture {
 
     /// Capture the active window
     pub fn capture_active_window(&self, output_path: &str) -> Result<(), String> {
+        let cursor = self.cursor_args()?;
         match self.backend {
-            CaptureBackend::Grim => {
+            CaptureBackend::X11 => self.capture_window(&active_window_id()?, output_path),
+            CaptureBackend::Grim | CaptureBackend::Portal => {
                 // For Wayland/grim, we need a different approach
                 // This is a simplified version that captures the full screen
                 // In a real implementation, you'd use compositor-specific commands
@@ -222,9 +383,10 @@ impl ScreenCapture {
             }
             CaptureBackend::Scrot => {
                 let output = Command::new("scrot")
+                    .args(cursor)
                     .arg("-u")
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute scrot: {}", e))?;
 
                 if output.status.success() {
@@ -240,7 +402,7 @@ impl ScreenCapture {
                 // Get active window ID
                 let xdotool_output = Command::new("xdotool")
                     .arg("getactivewindow")
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to get active window: {}", e))?;
 
                 if !xdotool_output.status.success() {
@@ -256,14 +418,156 @@ impl ScreenCapture {
         }
     }
 
-    /// Capture to a temporary file and return the path
-    pub fn capture_to_temp(&self) -> Result<String, String> {
-        let temp_dir = std::env::temp_dir();
+    /// Capture the screen, a region or a window to a file, encoded as the
+    /// options say; windows the backend cannot capture on their own (grim,
+    /// the portal) are cut out of the screen where they are
+    pub fn capture(&self, source: &FrameSource, output_path: &str) -> Result<(), String> {
+        if !self.options.is_plain() {
+            let image = self.capture_image(source)?;
+            return std::fs::write(output_path, image.data)
+                .map_err(|e| format!("Failed to write {}: {}", output_path, e));
+        }
+        match (&self.backend, source) {
+            (_, FrameSource::Screen) => self.capture_screen(output_path),
+            (
+                _,
+                FrameSource::Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+            ) => self.capture_region(*x, *y, *width, *height, output_path),
+            (CaptureBackend::Grim | CaptureBackend::Portal, FrameSource::Window(id)) => {
+                let (x, y, width, height) = window_geometry(id)?;
+                self.capture_region(x, y, width, height, output_path)
+            }
+            (_, FrameSource::Window(id)) => self.capture_window(id, output_path),
+        }
+    }
+
+    /// Capture one monitor, by its name ("DP-1") or its index in
+    /// `list_displays`, encoded as the options say
+    pub fn capture_monitor(&self, monitor: &DisplayRef, output_path: &str) -> Result<(), String> {
+        self.capture(&monitor_source(&find_display(monitor)?), output_path)
+    }
+
+    /// Capture the screen, a region or a window in memory, encoded as the
+    /// options say
+    pub fn capture_image(&self, source: &FrameSource) -> Result<Frame, String> {
+        let (width, height, rgba) = match self.backend {
+            CaptureBackend::X11 => x11_capture(source, self.options.cursor)?,
+            _ => {
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos();
+                let path = paths::scratch_file(&format!("casper_capture_{}.png", timestamp));
+                let path_str = path.to_str().ok_or("Invalid temp path")?;
+                let plain = ScreenCapture {
+                    backend: self.backend.clone(),
+                    options: CaptureOptions {
+                        cursor: self.options.cursor,
+                        ..CaptureOptions::default()
+                    },
+                };
+                let data = plain.capture(source, path_str).and_then(|()| {
+                    std::fs::read(&path).map_err(|e| format!("Failed to read capture: {}", e))
+                });
+                let _ = std::fs::remove_file(&path);
+                if self.options.is_plain() {
+                    return Ok(Frame {
+                        mime: "image/png",
+                        data: data?,
+                    });
+                }
+                decode_png(&data?)?
+            }
+        };
+        encode_image(width, height, &rgba, &self.options)
+    }
+
+    /// Capture one frame of a screen stream, as JPEG where the backend can
+    /// encode it (grim, scrot, import) and PNG otherwise
+    pub fn capture_frame(&self, source: &FrameSource) -> Result<Frame, String> {
+        if let CaptureBackend::X11 = self.backend {
+            let (width, height, rgba) = x11_capture(source, self.options.cursor)?;
+            return Ok(Frame {
+                mime: "image/png",
+                data: encode_png(width, height, &rgba)?,
+            });
+        }
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
-            .as_millis();
-        let temp_path = temp_dir.join(format!("casper_screenshot_{}.png", timestamp));
+            .as_nanos();
+        let extension = match self.backend {
+            CaptureBackend::Portal => "png",
+            _ => "jpg",
+        };
+        let path = paths::scratch_file(&format!("casper_frame_{}.{}", timestamp, extension));
+        let path_str = path.to_str().ok_or("Invalid temp path")?;
+
+        let result = match (&self.backend, source) {
+            // grim writes PNG unless told otherwise; scrot and import go by the extension
+            (CaptureBackend::Grim, FrameSource::Window(_)) => Err(
+                "Window capture with grim requires window geometry. Use a region instead."
+                    .to_string(),
+            ),
+            (CaptureBackend::Grim, _) => {
+                let mut command = Command::new("grim");
+                command.args(self.cursor_args()?).args(["-t", "jpeg"]);
+                if let FrameSource::Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                } = source
+                {
+                    command.args(["-g", &format!("{},{} {}x{}", x, y, width, height)]);
+                }
+                let output = command
+                    .arg(path_str)
+                    .output_bounded()
+                    .map_err(|e| format!("Failed to execute grim: {}", e))?;
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "grim failed: {}",
+                        String::from_utf8_lossy(&output.stderr)
+                    ))
+                }
+            }
+            (_, FrameSource::Screen) => self.capture_screen(path_str),
+            (
+                _,
+                FrameSource::Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+            ) => self.capture_region(*x, *y, *width, *height, path_str),
+            (_, FrameSource::Window(id)) => self.capture_window(id, path_str),
+        };
+        let data = result.and_then(|()| {
+            std::fs::read(&path).map_err(|e| format!("Failed to read capture: {}", e))
+        });
+        let _ = std::fs::remove_file(&path);
+        let data = data?;
+        let mime = if data.starts_with(&[0xff, 0xd8]) {
+            "image/jpeg"
+        } else {
+            "image/png"
+        };
+        Ok(Frame { mime, data })
+    }
+
+    /// Capture to a new file in the screenshot store and return the path;
+    /// callers remove it when done, and the store's limits catch the rest
+    pub fn capture_to_temp(&self) -> Result<String, String> {
+        let temp_path = screenshot_path("screenshot")?;
         let temp_path_str = temp_path.to_str().ok_or("Invalid temp path")?;
 
         self.capture_screen(temp_path_str)?;
@@ -274,10 +578,18 @@ impl ScreenCapture {
     /// Interactive region selection (for Wayland with slurp)
     pub fn select_region(&self, output_path: &str) -> Result<(), String> {
         match self.backend {
+            // Picking the area takes a tool that draws the selection
+            CaptureBackend::X11 => ScreenCapture {
+                backend: Self::detect_tool_backend()?,
+                options: self.options.clone(),
+            }
+            .select_region(output_path),
+            // The portal's interactive dialog lets the user pick the area
+            CaptureBackend::Portal => portal::screenshot(output_path, true),
             CaptureBackend::Grim => {
                 // Use slurp to select region, then grim to capture
                 let slurp_output = Command::new("slurp")
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute slurp: {}", e))?;
 
                 if !slurp_output.status.success() {
@@ -292,7 +604,7 @@ impl ScreenCapture {
                     .arg("-g")
                     .arg(geometry)
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute grim: {}", e))?;
 
                 if output.status.success() {
@@ -308,7 +620,7 @@ impl ScreenCapture {
                 let output = Command::new("scrot")
                     .arg("-s")
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute scrot: {}", e))?;
 
                 if output.status.success() {
@@ -324,7 +636,7 @@ impl ScreenCapture {
                 // Interactive selection is default for import without -window
                 let output = Command::new("import")
                     .arg(output_path)
-                    .output()
+                    .output_bounded()
                     .map_err(|e| format!("Failed to execute import: {}", e))?;
 
                 if output.status.success() {
@@ -340,6 +652,50 @@ impl ScreenCapture {
     }
 }
 
+/// What the frames of a screen stream show
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameSource {
+    Screen,
+    Region {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    /// A window by its ID
+    Window(String),
+}
+
+/// The part of the desktop a monitor shows
+///
+/// Regions are in desktop coordinates, so a scaled monitor still comes out
+/// at its full resolution with grim.
+pub fn monitor_source(display: &Display) -> FrameSource {
+    let (width, height) = display.logical_size();
+    FrameSource::Region {
+        x: display.x,
+        y: display.y,
+        width,
+        height,
+    }
+}
+
+/// An encoded image of the screen
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// "image/jpeg" or "image/png"
+    pub mime: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// The image as base64, for JSON messages
+    pub fn to_base64(&self) -> String {
+        use base64::Engine as _;
+        base64::engine::general_purpose::STANDARD.encode(&self.data)
+    }
+}
+
 impl Default for ScreenCapture {
     fn default() -> Self {
         Self::new().expect("Failed to initialize screen capture")
@@ -352,6 +708,12 @@ pub fn capture_screen(output_path: &str) -> Result<(), String> {
     capture.capture_screen(output_path)
 }
 
+/// Convenience function to capture one monitor
+pub fn capture_monitor(monitor: &DisplayRef, output_path: &str) -> Result<(), String> {
+    let capture = ScreenCapture::new()?;
+    capture.capture_monitor(monitor, output_path)
+}
+
 /// Convenience function to capture region
 pub fn capture_region(
     x: i32,
@@ -364,16 +726,609 @@ pub fn capture_region(
     capture.capture_region(x, y, width, height, output_path)
 }
 
+/// Capture the screen, a region or a window as a PNG image in memory
+pub fn capture_png(source: &FrameSource) -> Result<Frame, String> {
+    ScreenCapture::new()?.capture_image(source)
+}
+
 /// Convenience function to capture to temp file
 pub fn capture_screen_temp() -> Result<String, String> {
     let capture = ScreenCapture::new()?;
     capture.capture_to_temp()
 }
 
+/// Capture one monitor to a new file in the screenshot store and return the path
+pub fn capture_monitor_temp(monitor: &DisplayRef) -> Result<String, String> {
+    let path = screenshot_path("monitor")?;
+    let path = path.to_str().ok_or("Invalid temp path")?.to_string();
+    capture_monitor(monitor, &path)?;
+    Ok(path)
+}
+
+/// Color of the screen pixel at (x, y) as (red, green, blue)
+pub fn pixel_color(x: i32, y: i32) -> Result<(u8, u8, u8), String> {
+    // On X11 the pixel is read straight from the server, without a screenshot
+    if std::env::var("DISPLAY").is_ok()
+        && std::env::var("WAYLAND_DISPLAY").is_err()
+        && !portal::portal_only()
+        && let Ok(color) = x11_pixel_color(x, y)
+    {
+        return Ok(color);
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = paths::scratch_file(&format!("casper_pixel_{}.png", timestamp));
+    let path_str = path.to_str().ok_or("Invalid temp path")?;
+    capture_region(x, y, 1, 1, path_str)?;
+    let data = std::fs::read(&path).map_err(|e| format!("Failed to read capture: {}", e));
+    let _ = std::fs::remove_file(&path);
+    first_pixel(&data?)
+}
+
+fn x11_pixel_color(x: i32, y: i32) -> Result<(u8, u8, u8), String> {
+    let region = FrameSource::Region {
+        x,
+        y,
+        width: 1,
+        height: 1,
+    };
+    match x11_capture(&region, false)?.2.get(..3) {
+        Some(&[r, g, b]) => Ok((r, g, b)),
+        _ => Err("Failed to read the pixel".to_string()),
+    }
+}
+
+/// Capture straight from the X server as `(width, height, rgba)`; windows
+/// are read from their own contents, the rest from the root window. With
+/// `cursor`, the pointer image from XFixes is drawn on top.
+#[cfg(feature = "x11")]
+fn x11_capture(source: &FrameSource, cursor: bool) -> Result<(u32, u32, Vec<u8>), String> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder};
+
+    let (connection, screen) =
+        x11rb::connect(None).map_err(|e| format!("Failed to connect to X11: {}", e))?;
+    let setup = connection.setup();
+    let root = &setup.roots[screen];
+    let (drawable, x, y, width, height) = match source {
+        FrameSource::Screen => (
+            root.root,
+            0,
+            0,
+            root.width_in_pixels as i32,
+            root.height_in_pixels as i32,
+        ),
+        FrameSource::Region {
+            x,
+            y,
+            width,
+            height,
+        } => (root.root, *x, *y, *width, *height),
+        FrameSource::Window(id) => {
+            let window = match id.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => id.parse(),
+            }
+            .map_err(|_| format!("Invalid X11 window id {}", id))?;
+            let geometry = connection
+                .get_geometry(window)
+                .map_err(|e| e.to_string())?
+                .reply()
+                .map_err(|e| format!("No window with id {}: {}", id, e))?;
+            (window, 0, 0, geometry.width as i32, geometry.height as i32)
+        }
+    };
+    let (x, y) = (
+        i16::try_from(x).map_err(|_| format!("x {} is off the screen", x))?,
+        i16::try_from(y).map_err(|_| format!("y {} is off the screen", y))?,
+    );
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+        _ => return Err(format!("Invalid capture size {}x{}", width, height)),
+    };
+    let image = connection
+        .get_image(ImageFormat::Z_PIXMAP, drawable, x, y, width, height, !0)
+        .map_err(|e| e.to_string())?
+        .reply()
+        .map_err(|e| format!("Failed to read the screen: {}", e))?;
+    let bits_per_pixel = setup
+        .pixmap_formats
+        .iter()
+        .find(|format| format.depth == image.depth)
+        .map(|format| format.bits_per_pixel);
+    if !matches!(image.depth, 24 | 32) || bits_per_pixel != Some(32) {
+        return Err(format!("Unsupported X11 pixel depth {}", image.depth));
+    }
+    let mut rgba = x11_to_rgba(&image.data, setup.image_byte_order == ImageOrder::LSB_FIRST);
+    if cursor {
+        use x11rb::protocol::xfixes::ConnectionExt as _;
+
+        connection
+            .xfixes_query_version(4, 0)
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| format!("XFixes is not available: {}", e))?;
+        let pointer = connection
+            .xfixes_get_cursor_image()
+            .map_err(|e| e.to_string())?
+            .reply()
+            .map_err(|e| format!("Failed to read the cursor: {}", e))?;
+        // Where the capture starts, in root window coordinates
+        let origin = match drawable == root.root {
+            true => (x, y),
+            false => {
+                let translated = connection
+                    .translate_coordinates(drawable, root.root, 0, 0)
+                    .map_err(|e| e.to_string())?
+                    .reply()
+                    .map_err(|e| format!("Failed to place the window: {}", e))?;
+                (translated.dst_x, translated.dst_y)
+            }
+        };
+        draw_cursor(
+            &mut rgba,
+            width as i32,
+            &pointer.cursor_image,
+            pointer.width as i32,
+            (
+                (pointer.x - pointer.xhot as i16 - origin.0) as i32,
+                (pointer.y - pointer.yhot as i16 - origin.1) as i32,
+            ),
+        );
+    }
+    Ok((width as u32, height as u32, rgba))
+}
+
+#[cfg(not(feature = "x11"))]
+fn x11_capture(_source: &FrameSource, _cursor: bool) -> Result<(u32, u32, Vec<u8>), String> {
+    Err(missing("x11"))
+}
+
+/// Blend a cursor image of premultiplied ARGB pixels onto RGBA pixels
+/// `width` wide, with its top-left corner at `at`
+#[cfg(feature = "x11")]
+fn draw_cursor(rgba: &mut [u8], width: i32, cursor: &[u32], cursor_width: i32, at: (i32, i32)) {
+    let height = rgba.len() as i32 / 4 / width.max(1);
+    for (i, argb) in cursor.iter().enumerate() {
+        let (x, y) = (
+            at.0 + i as i32 % cursor_width,
+            at.1 + i as i32 / cursor_width,
+        );
+        if !(0..width).contains(&x) || !(0..height).contains(&y) {
+            continue;
+        }
+        let alpha = argb >> 24;
+        let pixel = &mut rgba[((y * width + x) * 4) as usize..][..3];
+        for (channel, shift) in pixel.iter_mut().zip([16, 8, 0]) {
+            let over = (argb >> shift) & 0xff;
+            *channel = (over + *channel as u32 * (255 - alpha) / 255).min(255) as u8;
+        }
+    }
+}
+
+#[cfg(feature = "x11")]
+/// Opaque RGBA pixels from 32-bit X11 pixels, which keep blue first on LSB
+/// servers
+fn x11_to_rgba(data: &[u8], lsb_first: bool) -> Vec<u8> {
+    data.chunks_exact(4)
+        .flat_map(|pixel| match (pixel, lsb_first) {
+            (&[b, g, r, _], true) => [r, g, b, 255],
+            (&[_, r, g, b], _) => [r, g, b, 255],
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Capture straight from the X server into a PNG file
+fn write_x11_capture(source: &FrameSource, cursor: bool, output_path: &str) -> Result<(), String> {
+    let (width, height, rgba) = x11_capture(source, cursor)?;
+    std::fs::write(output_path, encode_png(width, height, &rgba)?)
+        .map_err(|e| format!("Failed to write {}: {}", output_path, e))
+}
+
+/// First pixel of a PNG image
+fn first_pixel(png_data: &[u8]) -> Result<(u8, u8, u8), String> {
+    let (_, _, rgba) = decode_png(png_data)?;
+    match rgba.get(..3) {
+        Some(&[r, g, b]) => Ok((r, g, b)),
+        _ => Err("Empty PNG image".to_string()),
+    }
+}
+
+/// Cut a rectangle out of a PNG image, as an 8-bit RGBA PNG
+fn crop_png(png_data: &[u8], x: i32, y: i32, width: i32, height: i32) -> Result<Vec<u8>, String> {
+    let (image_width, image_height, rgba) = decode_png(png_data)?;
+    let (image_width, image_height) = (image_width as i32, image_height as i32);
+    if x < 0
+        || y < 0
+        || width <= 0
+        || height <= 0
+        || x + width > image_width
+        || y + height > image_height
+    {
+        return Err(format!(
+            "Region {}x{}+{}+{} is outside the {}x{} screen",
+            width, height, x, y, image_width, image_height
+        ));
+    }
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for row in y..y + height {
+        let start = ((row * image_width + x) * 4) as usize;
+        pixels.extend_from_slice(&rgba[start..start + (width * 4) as usize]);
+    }
+    encode_png(width as u32, height as u32, &pixels)
+}
+
+/// Capture the whole screen, scaled down to at most `max_width` pixels wide
+pub fn capture_screen_small(output_path: &str, max_width: u32) -> Result<(), String> {
+    let full = capture_screen_temp()?;
+    let data = std::fs::read(&full).map_err(|e| format!("Failed to read capture: {}", e));
+    let _ = std::fs::remove_file(&full);
+    let small = shrink_png(&data?, max_width)?;
+    std::fs::write(output_path, small)
+        .map_err(|e| format!("Failed to write {}: {}", output_path, e))
+}
+
+/// A PNG of a window scaled down to at most `max_width` pixels wide
+///
+/// This is the part of the screen the window covers, so windows above it
+/// show in the thumbnail and minimized windows are not captured.
+pub fn window_thumbnail(window_id: &str, max_width: u32) -> Result<Frame, String> {
+    let (x, y, width, height) = window_geometry(window_id)?;
+    if width <= 0 || height <= 0 {
+        return Err(format!("Window {} has no size", window_id));
+    }
+    let region = FrameSource::Region {
+        x,
+        y,
+        width,
+        height,
+    };
+    Ok(Frame {
+        mime: "image/png",
+        data: shrink_png(&capture_png(&region)?.data, max_width)?,
+    })
+}
+
+/// Scale a PNG image down by a whole factor until it is at most `max_width`
+/// pixels wide, averaging the pixels merged into one
+pub(crate) fn shrink_png(png_data: &[u8], max_width: u32) -> Result<Vec<u8>, String> {
+    let (width, height, rgba) = decode_png(png_data)?;
+    let factor = width.div_ceil(max_width.max(1)).max(1);
+    if factor == 1 {
+        return encode_png(width, height, &rgba);
+    }
+    let (small_width, small_height) = ((width / factor).max(1), (height / factor).max(1));
+    let mut pixels = Vec::with_capacity((small_width * small_height * 4) as usize);
+    for row in 0..small_height {
+        for column in 0..small_width {
+            let (mut sum, mut count) = ([0u32; 4], 0);
+            for y in row * factor..((row + 1) * factor).min(height) {
+                for x in column * factor..((column + 1) * factor).min(width) {
+                    let start = ((y * width + x) * 4) as usize;
+                    for (total, value) in sum.iter_mut().zip(&rgba[start..start + 4]) {
+                        *total += *value as u32;
+                    }
+                    count += 1;
+                }
+            }
+            pixels.extend(sum.map(|total| (total / count) as u8));
+        }
+    }
+    encode_png(small_width, small_height, &pixels)
+}
+
+/// Scale RGBA pixels down by `scale` (at most 1), each pixel of the result
+/// being the average of the ones it covers
+fn scale_rgba(width: u32, height: u32, rgba: &[u8], scale: f64) -> (u32, u32, Vec<u8>) {
+    if !(scale > 0.0 && scale < 1.0) {
+        return (width, height, rgba.to_vec());
+    }
+    let small_width = ((width as f64 * scale).round() as u32).max(1);
+    let small_height = ((height as f64 * scale).round() as u32).max(1);
+    let mut pixels = Vec::with_capacity((small_width * small_height * 4) as usize);
+    let span = |index: u32, small: u32, full: u32| {
+        let start = index as u64 * full as u64 / small as u64;
+        let end = ((index as u64 + 1) * full as u64).div_ceil(small as u64);
+        start as u32..end.max(start + 1) as u32
+    };
+    for row in 0..small_height {
+        let rows = span(row, small_height, height);
+        for column in 0..small_width {
+            let columns = span(column, small_width, width);
+            let (mut sum, mut count) = ([0u32; 4], 0);
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let start = ((y * width + x) * 4) as usize;
+                    for (total, value) in sum.iter_mut().zip(&rgba[start..start + 4]) {
+                        *total += *value as u32;
+                    }
+                    count += 1;
+                }
+            }
+            pixels.extend(sum.map(|total| (total / count) as u8));
+        }
+    }
+    (small_width, small_height, pixels)
+}
+
+/// Scale and encode RGBA pixels as `options` say
+pub(crate) fn encode_image(
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    options: &CaptureOptions,
+) -> Result<Frame, String> {
+    let (width, height, rgba) = scale_rgba(width, height, rgba, options.scale.unwrap_or(1.0));
+    let data = match options.format {
+        ImageFormat::Png => encode_png(width, height, &rgba)?,
+        ImageFormat::Jpeg => encode_jpeg(
+            width,
+            height,
+            &rgba,
+            options
+                .quality
+                .unwrap_or(DEFAULT_JPEG_QUALITY)
+                .clamp(1, 100),
+        )?,
+        ImageFormat::Webp => encode_webp(width, height, &rgba)?,
+    };
+    Ok(Frame {
+        mime: options.format.mime(),
+        data,
+    })
+}
+
+/// Decode a PNG image into its width, height and 8-bit RGBA pixels
+#[cfg(feature = "capture")]
+pub(crate) fn decode_png(png_data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut decoder = png::Decoder::new(png_data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("Invalid PNG: {}", e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| format!("Invalid PNG: {}", e))?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        other => return Err(format!("Unsupported PNG color type {:?}", other)),
+    };
+
+    let mut pixels = Vec::with_capacity((info.width * info.height * 4) as usize);
+    for row in 0..info.height as usize {
+        let line = &buffer[row * info.line_size..][..info.width as usize * channels];
+        for pixel in line.chunks_exact(channels) {
+            pixels.extend_from_slice(&match pixel {
+                [gray] => [*gray, *gray, *gray, 255],
+                [gray, alpha] => [*gray, *gray, *gray, *alpha],
+                [r, g, b] => [*r, *g, *b, 255],
+                [r, g, b, a] => [*r, *g, *b, *a],
+                _ => unreachable!(),
+            });
+        }
+    }
+    Ok((info.width, info.height, pixels))
+}
+
+#[cfg(not(feature = "capture"))]
+pub(crate) fn decode_png(_png_data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    Err(missing("capture"))
+}
+
+/// Encode 8-bit RGBA pixels as a PNG image
+#[cfg(feature = "capture")]
+pub(crate) fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .write_image_data(rgba)
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .finish()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "capture"))]
+pub(crate) fn encode_png(_width: u32, _height: u32, _rgba: &[u8]) -> Result<Vec<u8>, String> {
+    Err(missing("capture"))
+}
+
+/// Encode 8-bit RGBA pixels as a JPEG image, dropping the alpha channel
+#[cfg(feature = "capture")]
+fn encode_jpeg(width: u32, height: u32, rgba: &[u8], quality: u8) -> Result<Vec<u8>, String> {
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(format!("{}x{} is too large for JPEG", width, height)),
+    };
+    let mut out = Vec::new();
+    jpeg_encoder::Encoder::new(&mut out, quality)
+        .encode(rgba, width, height, jpeg_encoder::ColorType::Rgba)
+        .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "capture"))]
+fn encode_jpeg(_width: u32, _height: u32, _rgba: &[u8], _quality: u8) -> Result<Vec<u8>, String> {
+    Err(missing("capture"))
+}
+
+/// Encode 8-bit RGBA pixels as a lossless WebP image
+#[cfg(feature = "capture")]
+fn encode_webp(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    image_webp::WebPEncoder::new(&mut out)
+        .encode(rgba, width, height, image_webp::ColorType::Rgba8)
+        .map_err(|e| format!("Failed to encode WebP: {}", e))?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "capture"))]
+fn encode_webp(_width: u32, _height: u32, _rgba: &[u8]) -> Result<Vec<u8>, String> {
+    Err(missing("capture"))
+}
+
+/// Parse "#rrggbb" (the "#" is optional) into (red, green, blue)
+pub fn parse_color(color: &str) -> Result<(u8, u8, u8), String> {
+    let hex = color.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(|| format!("Invalid color '{}' (expected #rrggbb)", color))
+    };
+    if hex.len() != 6 {
+        return Err(format!("Invalid color '{}' (expected #rrggbb)", color));
+    }
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Whether every channel of two colors differs by at most `tolerance`
+pub fn colors_match(a: (u8, u8, u8), b: (u8, u8, u8), tolerance: u8) -> bool {
+    a.0.abs_diff(b.0) <= tolerance
+        && a.1.abs_diff(b.1) <= tolerance
+        && a.2.abs_diff(b.2) <= tolerance
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_colors() {
+        assert_eq!(parse_color("#FF8000"), Ok((255, 128, 0)));
+        assert_eq!(parse_color("00ff00"), Ok((0, 255, 0)));
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("#gg0000").is_err());
+        assert!(colors_match((250, 128, 0), (255, 125, 3), 5));
+        assert!(!colors_match((250, 128, 0), (255, 125, 3), 4));
+    }
+
+    #[test]
+    #[cfg(feature = "x11")]
+    fn test_x11_pixels() {
+        let pixels = [10, 20, 30, 0, 0, 40, 50, 60];
+        assert_eq!(
+            x11_to_rgba(&pixels, true),
+            vec![30, 20, 10, 255, 50, 40, 0, 255]
+        );
+        assert_eq!(
+            x11_to_rgba(&pixels, false),
+            vec![20, 30, 0, 255, 40, 50, 60, 255]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_shrink_png() {
+        let mut pixels = Vec::new();
+        for i in 0..4u8 {
+            pixels.extend_from_slice(&[i * 10, 0, 0, 255]);
+        }
+        let image = encode_png(4, 1, &pixels).unwrap();
+        let (width, height, small) = decode_png(&shrink_png(&image, 2).unwrap()).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(small, vec![5, 0, 0, 255, 25, 0, 0, 255]);
+        let (width, _, _) = decode_png(&shrink_png(&image, 10).unwrap()).unwrap();
+        assert_eq!(width, 4);
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_crop_png() {
+        let mut image = Vec::new();
+        let mut encoder = png::Encoder::new(&mut image, 3, 2);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        let mut pixels = vec![0u8; 3 * 2 * 3];
+        pixels[(3 + 2) * 3..(3 + 2) * 3 + 3].copy_from_slice(&[10, 20, 30]);
+        writer.write_image_data(&pixels).unwrap();
+        writer.finish().unwrap();
+
+        let region = crop_png(&image, 2, 1, 1, 1).unwrap();
+        assert_eq!(first_pixel(&region), Ok((10, 20, 30)));
+        assert!(crop_png(&image, 2, 1, 2, 1).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_capture_options() {
+        let options: CaptureOptions =
+            serde_json::from_str(r#"{"format":"jpg","quality":70,"scale":0.5}"#).unwrap();
+        assert_eq!(options.format, ImageFormat::Jpeg);
+        assert!(!options.is_plain() && !options.cursor);
+        assert_eq!(
+            ImageFormat::from_path("/tmp/shot.WEBP"),
+            Some(ImageFormat::Webp)
+        );
+
+        // 4x2 pixels become 2x1, each the average of a 2x2 block
+        let mut pixels = Vec::new();
+        for i in 0..8u8 {
+            pixels.extend_from_slice(&[i * 10, 100, 0, 255]);
+        }
+        let (width, height, small) = scale_rgba(4, 2, &pixels, 0.5);
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(small, vec![25, 100, 0, 255, 45, 100, 0, 255]);
+
+        let jpeg = encode_image(4, 2, &pixels, &options).unwrap();
+        assert_eq!(
+            (jpeg.mime, &jpeg.data[..2]),
+            ("image/jpeg", &[0xff, 0xd8][..])
+        );
+        let webp = CaptureOptions {
+            format: ImageFormat::Webp,
+            ..CaptureOptions::default()
+        };
+        let webp = encode_image(4, 2, &pixels, &webp).unwrap();
+        assert_eq!(&webp.data[8..12], b"WEBP");
+    }
+
+    #[test]
+    #[cfg(feature = "x11")]
+    fn test_draw_cursor() {
+        // A 2x2 cursor, half-transparent white in its corner, over black
+        let mut rgba = [0, 0, 0, 255].repeat(9);
+        draw_cursor(&mut rgba, 3, &[0xff00ff00, 0x80808080, 0, 0], 2, (1, 1));
+        assert_eq!(&rgba[16..20], &[0, 255, 0, 255]);
+        assert_eq!(&rgba[20..24], &[128, 128, 128, 255]);
+        assert_eq!(&rgba[..16], &[0, 0, 0, 255].repeat(4)[..]);
+    }
+
+    #[test]
+    fn test_monitor_source() {
+        let display = Display {
+            name: "DP-2".to_string(),
+            x: 2560,
+            y: 0,
+            width: 3840,
+            height: 2160,
+            scale: 1.5,
+            primary: false,
+        };
+        assert_eq!(
+            monitor_source(&display),
+            FrameSource::Region {
+                x: 2560,
+                y: 0,
+                width: 2560,
+                height: 1440
+            }
+        );
+    }
+
     #[test]
     fn test_backend_detection() {
         // This test will pass if at least one backend is available
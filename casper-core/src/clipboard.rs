@@ -0,0 +1,111 @@
+use crate::commands::CommandDeadline;
+use crate::screen::press_key_combo;
+use crate::setup::find_in_path;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Programs reading and writing the clipboard, by display server
+struct ClipboardTool {
+    name: &'static str,
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+const WAYLAND_TOOLS: &[ClipboardTool] = &[ClipboardTool {
+    name: "wl-copy",
+    copy: &["wl-copy"],
+    paste: &["wl-paste", "--no-newline"],
+}];
+
+const X11_TOOLS: &[ClipboardTool] = &[
+    ClipboardTool {
+        name: "xclip",
+        copy: &["xclip", "-selection", "clipboard"],
+        paste: &["xclip", "-selection", "clipboard", "-o"],
+    },
+    ClipboardTool {
+        name: "xsel",
+        copy: &["xsel", "--clipboard", "--input"],
+        paste: &["xsel", "--clipboard", "--output"],
+    },
+];
+
+/// The first clipboard tool installed for the current session
+fn tool() -> Result<&'static ClipboardTool, String> {
+    let tools = if std::env::var("WAYLAND_DISPLAY").is_ok() {
+        WAYLAND_TOOLS
+    } else {
+        X11_TOOLS
+    };
+    tools
+        .iter()
+        .find(|tool| find_in_path(tool.copy[0]).is_some() && find_in_path(tool.paste[0]).is_some())
+        .ok_or_else(|| {
+            let names: Vec<_> = tools.iter().map(|tool| tool.name).collect();
+            format!("No clipboard tool found (install {})", names.join(" or "))
+        })
+}
+
+/// Whether the clipboard can be used, or why not
+pub fn check_clipboard() -> Result<(), String> {
+    tool().map(|_| ())
+}
+
+/// Text on the clipboard
+pub fn get_clipboard() -> Result<String, String> {
+    let tool = tool()?;
+    let output = Command::new(tool.paste[0])
+        .args(&tool.paste[1..])
+        .output_bounded()
+        .map_err(|e| format!("Failed to run {}: {}", tool.paste[0], e))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        // An empty clipboard is not an error
+        if error.contains("Nothing is copied") || error.contains("not available") {
+            return Ok(String::new());
+        }
+        return Err(format!("{} failed: {}", tool.paste[0], error));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Put text on the clipboard
+///
+/// The tool stays in the background serving the clipboard until something
+/// else is copied, so its output is not waited for.
+pub fn set_clipboard(text: &str) -> Result<(), String> {
+    let tool = tool()?;
+    let mut child = Command::new(tool.copy[0])
+        .args(&tool.copy[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", tool.copy[0], e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|e| format!("Failed to write to {}: {}", tool.copy[0], e))?;
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("{} could not set the clipboard", tool.copy[0]));
+    }
+    Ok(())
+}
+
+/// Enter text by pasting it with Ctrl+V, which is much faster than typing
+/// long texts and keeps every character
+///
+/// What was on the clipboard before is put back once the application had
+/// time to read the pasted text; when it could not be read (an empty
+/// clipboard, say) the clipboard is emptied, so the text never stays there.
+pub fn paste_text(text: &str) -> Result<(), String> {
+    let previous = get_clipboard().unwrap_or_default();
+    set_clipboard(text)?;
+    let pasted = press_key_combo("ctrl+v");
+    std::thread::sleep(Duration::from_millis(200));
+    let restored = set_clipboard(&previous);
+    pasted.and(restored)
+}
@@ -1,4 +1,134 @@
-use std::process::Command;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    /// Point in time by which external tools started for the current request must finish
+    static DEADLINE: Instant;
+}
+
+/// Run a future with a deadline for every external tool it starts
+///
+/// Tools still running at the deadline are killed, so a hung helper program
+/// cannot keep a request (and the thread serving it) busy forever.
+pub async fn with_deadline<F: Future>(deadline: Instant, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+/// Blocking counterpart of `with_deadline`, for work on a blocking thread
+pub fn with_deadline_blocking<R>(deadline: Instant, f: impl FnOnce() -> R) -> R {
+    DEADLINE.sync_scope(deadline, f)
+}
+
+/// `tokio::task::spawn_blocking` that carries the current deadline over to
+/// the blocking thread, where the task-local one cannot reach
+pub fn spawn_blocking_bounded<R, F>(f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let deadline = current_deadline();
+    tokio::task::spawn_blocking(move || match deadline {
+        Some(deadline) => with_deadline_blocking(deadline, f),
+        None => f(),
+    })
+}
+
+fn current_deadline() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// `Command::output` that respects the current request deadline
+pub trait CommandDeadline {
+    /// Run to completion and collect output, killing the process at the deadline
+    fn output_bounded(&mut self) -> std::io::Result<Output>;
+    /// `output_bounded` with `input` written to the process's stdin, for
+    /// text that must not show up in its arguments
+    fn output_bounded_with_input(&mut self, input: &[u8]) -> std::io::Result<Output>;
+}
+
+impl CommandDeadline for Command {
+    fn output_bounded(&mut self) -> std::io::Result<Output> {
+        run_bounded(self, None)
+    }
+
+    fn output_bounded_with_input(&mut self, input: &[u8]) -> std::io::Result<Output> {
+        run_bounded(self, Some(input))
+    }
+}
+
+fn run_bounded(command: &mut Command, input: Option<&[u8]>) -> std::io::Result<Output> {
+    let deadline = current_deadline();
+    if deadline.is_none() && input.is_none() {
+        return command.output();
+    }
+
+    let stdin = match input {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    };
+    let mut child = command
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(input) = input
+        && let Some(mut stdin) = child.stdin.take()
+    {
+        // Written from a thread so a process that does not read cannot block us
+        let input = input.to_vec();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            kill(&mut child);
+            return Err(timed_out(command));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let collect = |reader: Option<std::thread::JoinHandle<Vec<u8>>>| {
+        reader.and_then(|r| r.join().ok()).unwrap_or_default()
+    };
+    Ok(Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    })
+}
+
+fn read_to_end<R: Read + Send + 'static>(mut reader: R) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn timed_out(command: &Command) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!(
+            "{} did not finish before the request deadline and was killed",
+            command.get_program().to_string_lossy()
+        ),
+    )
+}
 
 pub fn run_command(cmd: &str) -> Result<String, String> {
     let parts: Vec<&str> = cmd.split_whitespace().collect();
@@ -7,11 +137,129 @@ pub fn run_command(cmd: &str) -> Result<String, String> {
     }
     let output = Command::new(parts[0])
         .args(&parts[1..])
-        .output()
+        .output_bounded()
         .map_err(|e| e.to_string())?;
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     } else {
         Err(String::from_utf8_lossy(&output.stderr).to_string())
     }
-}
\ No newline at end of file
+}
+
+/// Which output stream a line came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Run a command, reporting each output line as it is produced
+///
+/// Returns the exit code once the command finishes. The command is killed if
+/// it is still running at the current request deadline.
+pub fn run_command_streaming<F: FnMut(OutputStream, &str)>(
+    cmd: &str,
+    mut on_line: F,
+) -> Result<i32, String> {
+    let parts: Vec<&str> = cmd.split_whitespace().collect();
+    if parts.is_empty() {
+        return Err("Empty command".to_string());
+    }
+    let mut command = Command::new(parts[0]);
+    let mut child = command
+        .args(&parts[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(forward_lines(stdout, OutputStream::Stdout, tx.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(forward_lines(stderr, OutputStream::Stderr, tx.clone()));
+    }
+    drop(tx);
+
+    let deadline = current_deadline();
+    loop {
+        let received = match deadline {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok((stream, line)) => on_line(stream, &line),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                kill(&mut child);
+                return Err(timed_out(&command).to_string());
+            }
+        }
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    Ok(status.code().unwrap_or(-1))
+}
+
+fn forward_lines<R: std::io::Read + Send + 'static>(
+    reader: R,
+    stream: OutputStream,
+    tx: mpsc::Sender<(OutputStream, String)>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if tx.send((stream, line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_bounded_kills_at_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let started = Instant::now();
+        let result =
+            with_deadline_blocking(deadline, || Command::new("sleep").arg("5").output_bounded());
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_spawn_blocking_keeps_deadline() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let result = runtime.block_on(with_deadline(deadline, async {
+            spawn_blocking_bounded(|| Command::new("sleep").arg("5").output_bounded()).await
+        }));
+        let error = result.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_output_bounded_collects_output() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let output = with_deadline_blocking(deadline, || run_command("echo hello")).unwrap();
+        assert_eq!(output, "hello\n");
+    }
+
+    #[test]
+    fn test_output_bounded_with_input() {
+        let output = Command::new("cat")
+            .output_bounded_with_input(b"secret")
+            .unwrap();
+        assert_eq!(output.stdout, b"secret");
+    }
+}
@@ -0,0 +1,322 @@
+use crate::paths;
+use crate::vault::KeySource;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::Duration;
+
+/// Daemon settings read from `config.json` in the Casper directory
+///
+/// Every field has a default, so the file only needs the settings being changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    pub timeouts: TimeoutConfig,
+    pub usage: UsageConfig,
+    pub metrics: MetricsConfig,
+    /// Global hotkey to the sequence it plays, e.g. `{"ctrl+alt+1": "morning"}`
+    pub hotkeys: BTreeMap<String, String>,
+    /// Global hotkey pausing and resuming automations
+    pub pause_hotkey: Option<String>,
+    /// Global hotkey aborting every automation; `DEFAULT_ABORT_HOTKEY`
+    /// unless set, and none when set to ""
+    pub abort_hotkey: Option<String>,
+    /// How input is sent: "enigo", "ydotool", "wtype" or "auto" (the default)
+    pub input_backend: Option<String>,
+    pub error_capture: ErrorCaptureConfig,
+    pub screenshots: ScreenshotConfig,
+    pub responses: ResponseConfig,
+    pub requests: RequestConfig,
+    pub commands: CommandConfig,
+    pub encryption: EncryptionConfig,
+}
+
+/// Abort hotkey grabbed when the config does not name one
+pub const DEFAULT_ABORT_HOTKEY: &str = "ctrl+alt+shift+escape";
+
+impl DaemonConfig {
+    /// The abort hotkey in effect, if it was not turned off
+    pub fn abort_hotkey(&self) -> Option<&str> {
+        match self.abort_hotkey.as_deref() {
+            None => Some(DEFAULT_ABORT_HOTKEY),
+            Some("") => None,
+            Some(hotkey) => Some(hotkey),
+        }
+    }
+
+    /// Load the config of a profile, falling back to defaults when there is no file
+    pub fn load(profile: Option<&str>) -> Result<Self, String> {
+        let path = paths::daemon_config_file(profile);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Write the config of a profile, e.g. after a hotkey was bound
+    pub fn save(&self, profile: Option<&str>) -> Result<(), String> {
+        let path = paths::daemon_config_file(profile);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize config: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Encryption of the sequence library and recording journals
+///
+/// ```json
+/// { "encryption": { "enabled": true, "key_source": "passphrase" } }
+/// ```
+///
+/// The key comes from the desktop keyring (`"keyring"`, the default) or is
+/// derived from `CASPER_PASSPHRASE` (`"passphrase"`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    pub key_source: KeySource,
+}
+
+/// Opt-in local usage statistics (feature counts only, never sent anywhere)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageConfig {
+    pub enabled: bool,
+}
+
+/// Prometheus metrics endpoint
+///
+/// ```json
+/// { "metrics": { "listen": "127.0.0.1:9464" } }
+/// ```
+///
+/// Without `listen` the metrics are only available through the `metrics` request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// TCP address serving `GET /metrics`
+    pub listen: Option<String>,
+}
+
+/// Limits keeping responses within what simple clients can read at once
+///
+/// ```json
+/// { "responses": { "max_items": 50, "max_bytes": 4096 } }
+/// ```
+///
+/// Requests can override both with `limit` and `max_bytes`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseConfig {
+    /// Page size of list responses when the request has no `limit`
+    pub max_items: Option<usize>,
+    /// Larger responses are sent as a series of `part` lines of this size
+    pub max_bytes: Option<usize>,
+}
+
+/// Limit on the size of a single request
+///
+/// ```json
+/// { "requests": { "max_bytes": 1048576 } }
+/// ```
+///
+/// Larger requests are answered with an error; `DEFAULT_MAX_REQUEST_BYTES`
+/// applies when this isn't set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestConfig {
+    pub max_bytes: Option<usize>,
+}
+
+/// Request size limit when the config does not set one
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 8 * 1024 * 1024;
+
+impl RequestConfig {
+    /// The request size limit in effect
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes.unwrap_or(DEFAULT_MAX_REQUEST_BYTES)
+    }
+}
+
+/// Programs that `RunCommand` steps are expected to run
+///
+/// ```json
+/// { "commands": { "allowlist": ["notify-send", "firefox"] } }
+/// ```
+///
+/// Dry runs report a step whose program (the first word of its command) is
+/// not listed; without an allowlist any installed program passes. Playback
+/// itself does not consult it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandConfig {
+    pub allowlist: Option<Vec<String>>,
+}
+
+/// Screenshots attached to failed commands and playbacks
+///
+/// ```json
+/// { "error_capture": { "screenshot": true, "describe": true } }
+/// ```
+///
+/// The screenshots are kept in the `errors` state directory; with `describe`
+/// the AI also sums up the screen in one line.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErrorCaptureConfig {
+    pub screenshot: bool,
+    pub describe: bool,
+}
+
+/// How many screenshots the screenshot store keeps, and for how long
+///
+/// ```json
+/// { "screenshots": { "max_count": 50, "max_age_hours": 24 } }
+/// ```
+///
+/// Screenshots taken for the AI, image searches and assertions go to the
+/// store and are cleaned up as new ones are taken. A value of 0 lifts that
+/// limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenshotConfig {
+    pub max_count: usize,
+    pub max_age_hours: u64,
+}
+
+impl ScreenshotConfig {
+    pub const DEFAULT: ScreenshotConfig = ScreenshotConfig {
+        max_count: 50,
+        max_age_hours: 24,
+    };
+
+    /// Age after which screenshots are removed, if there is a limit
+    pub fn max_age(&self) -> Option<Duration> {
+        (self.max_age_hours > 0)
+            .then(|| Duration::from_secs(self.max_age_hours.saturating_mul(3600)))
+    }
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// How long each request type may run before it is aborted
+///
+/// ```json
+/// { "timeouts": { "default_seconds": 30, "requests": { "run_command": 600 } } }
+/// ```
+///
+/// A value of 0 disables the timeout for that request type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    pub default_seconds: u64,
+    /// Per-request-type overrides, in seconds
+    pub requests: HashMap<String, u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            default_seconds: 30,
+            requests: HashMap::new(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Timeout for a request type, or `None` if it may run indefinitely
+    pub fn for_request(&self, request_type: &str) -> Option<Duration> {
+        let seconds = self
+            .requests
+            .get(request_type)
+            .copied()
+            .or_else(|| builtin_timeout(request_type))
+            .unwrap_or(self.default_seconds);
+        (seconds > 0).then(|| Duration::from_secs(seconds))
+    }
+}
+
+/// Defaults for request types that legitimately take longer than usual
+fn builtin_timeout(request_type: &str) -> Option<u64> {
+    match request_type {
+        "run_command" => Some(120),
+        "speak" => Some(300),
+        "ask_ai" | "generate_sequence" | "explain_sequence" => Some(120),
+        "connect_to_service" => Some(60),
+        // A step may take as long as its action does; stop_step_playback interrupts it
+        "step_playback" => Some(0),
+        // Waits carry their own `timeout_ms` and give up after it
+        "wait_for_process_exit" | "wait_for_window" | "wait_for_screen_change" => Some(0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_lookup_order() {
+        let config: DaemonConfig = serde_json::from_str(
+            r#"{ "timeouts": { "default_seconds": 10, "requests": { "speak": 5, "ping": 0 } } }"#,
+        )
+        .unwrap();
+        let timeouts = &config.timeouts;
+        assert_eq!(timeouts.for_request("speak"), Some(Duration::from_secs(5)));
+        assert_eq!(
+            timeouts.for_request("ask_ai"),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            timeouts.for_request("move_mouse"),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(timeouts.for_request("ping"), None);
+        assert_eq!(timeouts.for_request("step_playback"), None);
+        assert_eq!(timeouts.for_request("wait_for_window"), None);
+    }
+
+    #[test]
+    fn test_screenshot_max_age() {
+        let max_age = |max_age_hours| {
+            ScreenshotConfig {
+                max_count: 0,
+                max_age_hours,
+            }
+            .max_age()
+        };
+        assert_eq!(max_age(0), None);
+        assert_eq!(max_age(2), Some(Duration::from_secs(7200)));
+        assert_eq!(max_age(u64::MAX), Some(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config: DaemonConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.timeouts.default_seconds, 30);
+        assert!(!config.error_capture.screenshot);
+        assert_eq!(config.abort_hotkey(), Some(DEFAULT_ABORT_HOTKEY));
+
+        let config: DaemonConfig =
+            serde_json::from_str(r#"{ "error_capture": { "screenshot": true } }"#).unwrap();
+        assert!(config.error_capture.screenshot);
+        assert!(!config.error_capture.describe);
+
+        let config: DaemonConfig = serde_json::from_str(r#"{ "abort_hotkey": "" }"#).unwrap();
+        assert_eq!(config.abort_hotkey(), None);
+
+        let config: DaemonConfig =
+            serde_json::from_str(r#"{ "input_backend": "ydotool" }"#).unwrap();
+        assert_eq!(config.input_backend.as_deref(), Some("ydotool"));
+    }
+}
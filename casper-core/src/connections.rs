@@ -1,5 +1,12 @@
+#[cfg(feature = "http")]
 use reqwest::Client;
 
+#[cfg(not(feature = "http"))]
+pub async fn connect_to_service(_service: &str, _action: &str) -> Result<String, String> {
+    Err(crate::features::missing("http"))
+}
+
+#[cfg(feature = "http")]
 pub async fn connect_to_service(service: &str, _action: &str) -> Result<String, String> {
     //  Example HTTP request
     let client = Client::new();
@@ -11,7 +18,7 @@ pub async fn connect_to_service(service: &str, _action: &str) -> Result<String,
                 .await
                 .map_err(|e| e.to_string())?;
             response.text().await.map_err(|e| e.to_string())
-        },
+        }
         _ => Err(format!("Unsupported service: {}", service)),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,268 @@
+use crate::actions::{Action, ActionPlayer, ActionSequence, ActionWithTimestamp, StepRef};
+use crate::executor::{
+    PlaybackOptions, SequenceLookup, SharedPlayer, StepFailure, perform_single_step,
+};
+use crate::pipeline::Variables;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Identifies step sessions, so a step is never finished in a later session
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+/// A sequence played one step at a time, on request
+///
+/// The client looks at the next step, optionally replaces its action or
+/// skips it, and asks for it to be performed; the result of every step is
+/// kept so the session can be inspected at any point.
+pub struct StepSession {
+    id: u64,
+    sequence: ActionSequence,
+    /// Index of the step performed next
+    next: usize,
+    /// Playing while a step runs; stopping it interrupts the step
+    player: SharedPlayer,
+    options: PlaybackOptions,
+    results: Vec<StepResult>,
+    running: bool,
+    /// Set by `GetClipboard` steps, kept from one step to the next
+    variables: Arc<Mutex<Variables>>,
+}
+
+/// What happened to a step of a step session
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    pub index: usize,
+    pub label: Option<String>,
+    /// The action that was performed (sensitive text hidden)
+    pub action: Action,
+    /// The action was replaced for this run
+    pub overridden: bool,
+    pub skipped: bool,
+    pub error: Option<String>,
+    /// Failures a `continue` error policy went past
+    pub failures: Vec<StepFailure>,
+    pub duration_ms: u64,
+}
+
+/// A step taken out of a session to be performed without holding it
+pub struct PendingStep {
+    session: u64,
+    player: SharedPlayer,
+    step: ActionWithTimestamp,
+    index: usize,
+    scope: ActionSequence,
+    options: PlaybackOptions,
+    overridden: bool,
+    variables: Arc<Mutex<Variables>>,
+}
+
+impl StepSession {
+    pub fn new(sequence: ActionSequence, options: PlaybackOptions) -> Result<Self, String> {
+        if sequence.actions.is_empty() {
+            return Err(format!("Sequence '{}' has no steps", sequence.name));
+        }
+        Ok(StepSession {
+            id: NEXT_SESSION.fetch_add(1, Ordering::Relaxed),
+            sequence,
+            next: 0,
+            player: Arc::new(Mutex::new(ActionPlayer::new())),
+            options,
+            results: Vec::new(),
+            running: false,
+            variables: Arc::new(Mutex::new(Variables::new())),
+        })
+    }
+
+    pub fn sequence(&self) -> &ActionSequence {
+        &self.sequence
+    }
+
+    /// The step performed next, unless every step has been done
+    pub fn next_step(&self) -> Option<(usize, &ActionWithTimestamp)> {
+        self.sequence
+            .actions
+            .get(self.next)
+            .map(|step| (self.next, step))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.sequence.actions.len()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn results(&self) -> &[StepResult] {
+        &self.results
+    }
+
+    /// Make `step` (an index or label) the next one, going back or ahead
+    pub fn jump(&mut self, step: &StepRef) -> Result<usize, String> {
+        if self.running {
+            return Err("A step is running".to_string());
+        }
+        self.next = self.sequence.resolve_step(step)?;
+        Ok(self.next)
+    }
+
+    /// Skip the next step without performing it
+    pub fn skip(&mut self) -> Result<&StepResult, String> {
+        if self.running {
+            return Err("A step is running".to_string());
+        }
+        let (index, step) = self.next_step().ok_or("Every step has been played")?;
+        let result = StepResult {
+            index,
+            label: step.label.clone(),
+            action: step.action.redacted(),
+            overridden: false,
+            skipped: true,
+            error: None,
+            failures: Vec::new(),
+            duration_ms: 0,
+        };
+        self.next += 1;
+        self.results.push(result);
+        Ok(self.results.last().unwrap())
+    }
+
+    /// Take the next step out to be performed, with `action` in place of its
+    /// own if given
+    ///
+    /// Hand the result of `PendingStep::perform` back with `finish`.
+    pub fn begin(&mut self, action: Option<Action>) -> Result<PendingStep, String> {
+        if self.running {
+            return Err("A step is running".to_string());
+        }
+        let (index, step) = self.next_step().ok_or("Every step has been played")?;
+        let mut step = step.clone();
+        let overridden = action.is_some();
+        if let Some(action) = action {
+            step.action = action;
+        }
+        let mut player = ActionPlayer::new();
+        player.load_sequence(self.sequence.clone());
+        player.start_playback()?;
+        *self.player.lock().unwrap() = player;
+        self.running = true;
+        Ok(PendingStep {
+            session: self.id,
+            player: Arc::clone(&self.player),
+            step,
+            index,
+            scope: self.sequence.clone(),
+            options: self.options,
+            overridden,
+            variables: Arc::clone(&self.variables),
+        })
+    }
+
+    /// Record a step performed in session `session`; a step that failed
+    /// stays next so it can be retried, changed or skipped
+    ///
+    /// A step of a session that was stopped and replaced in the meantime is
+    /// refused.
+    pub fn finish(&mut self, session: u64, result: StepResult) -> Result<&StepResult, String> {
+        if session != self.id {
+            return Err("Step playback was stopped".to_string());
+        }
+        self.running = false;
+        self.player.lock().unwrap().stop_playback();
+        if result.error.is_none() {
+            self.next = result.index + 1;
+        }
+        self.results.push(result);
+        Ok(self.results.last().unwrap())
+    }
+
+    /// Interrupt the step that is running, if any
+    pub fn stop(&self) {
+        self.player.lock().unwrap().stop_playback();
+    }
+}
+
+impl PendingStep {
+    /// The session the step was taken from, to pass to `StepSession::finish`
+    pub fn session(&self) -> u64 {
+        self.session
+    }
+
+    /// Perform the step; blocking input runs on tokio's blocking threads
+    pub async fn perform(self, lookup: SequenceLookup) -> StepResult {
+        let started = Instant::now();
+        let outcome = perform_single_step(
+            self.player,
+            &self.step,
+            self.index,
+            &self.scope,
+            self.options,
+            lookup,
+            self.variables,
+        )
+        .await;
+        let (error, failures) = match outcome {
+            Ok(failures) => (None, failures),
+            Err(error) => (Some(error), Vec::new()),
+        };
+        StepResult {
+            index: self.index,
+            label: self.step.label,
+            action: self.step.action.redacted(),
+            overridden: self.overridden,
+            skipped: false,
+            error,
+            failures,
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_through_sequence() {
+        let mut sequence = ActionSequence::new("debug".into(), String::new());
+        for milliseconds in [1, 2, 3] {
+            sequence.add_step(ActionWithTimestamp::new(Action::Wait { milliseconds }, 0));
+        }
+        let mut session = StepSession::new(sequence, PlaybackOptions::default()).unwrap();
+        let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence {}", name)));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let pending = session.begin(None).unwrap();
+        assert!(session.begin(None).is_err());
+        let id = pending.session();
+        let result = runtime.block_on(pending.perform(Arc::clone(&lookup)));
+        assert!(session.finish(id, result).unwrap().error.is_none());
+        assert_eq!(session.next_step().unwrap().0, 1);
+
+        assert!(session.skip().unwrap().skipped);
+        let pending = session
+            .begin(Some(Action::CallSequence {
+                name: "missing".into(),
+            }))
+            .unwrap();
+        let result = runtime.block_on(pending.perform(Arc::clone(&lookup)));
+        let result = session.finish(id, result).unwrap();
+        assert!(result.overridden && result.error.is_some());
+        // A failed step stays next
+        assert_eq!(session.next_step().unwrap().0, 2);
+
+        session.jump(&StepRef::Index(0)).unwrap();
+        assert_eq!(session.results().len(), 3);
+        assert!(!session.is_finished());
+
+        // A step of a stopped session is not applied to the next one
+        let pending = session.begin(None).unwrap();
+        let stale = runtime.block_on(pending.perform(Arc::clone(&lookup)));
+        let sequence = session.sequence().clone();
+        let mut session = StepSession::new(sequence, PlaybackOptions::default()).unwrap();
+        assert!(session.finish(id, stale).is_err());
+        assert!(session.results().is_empty());
+    }
+}
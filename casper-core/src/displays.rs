@@ -0,0 +1,242 @@
+use crate::commands::CommandDeadline;
+use crate::screen::display_size;
+use crate::setup::find_in_path;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::Command;
+
+/// A monitor and where it sits in the desktop
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Display {
+    /// Output name, e.g. "DP-1"
+    pub name: String,
+    /// Position of the top-left corner in desktop coordinates
+    pub x: i32,
+    pub y: i32,
+    /// Resolution in physical pixels
+    pub width: i32,
+    pub height: i32,
+    /// Scale factor; desktop coordinates cover `width / scale` pixels
+    pub scale: f64,
+    pub primary: bool,
+}
+
+impl Display {
+    /// Size in desktop coordinates, which scaled monitors cover less of
+    pub fn logical_size(&self) -> (i32, i32) {
+        let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+        (
+            (self.width as f64 / scale).round() as i32,
+            (self.height as f64 / scale).round() as i32,
+        )
+    }
+}
+
+/// The monitor showing the desktop point (x, y)
+pub fn display_at(displays: &[Display], x: i32, y: i32) -> Option<&Display> {
+    displays.iter().find(|display| {
+        let (width, height) = display.logical_size();
+        (display.x..display.x + width).contains(&x) && (display.y..display.y + height).contains(&y)
+    })
+}
+
+/// A monitor picked by its place in `list_displays` or by its name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DisplayRef {
+    Index(usize),
+    Name(String),
+}
+
+/// The connected monitor `display` refers to
+pub fn find_display(display: &DisplayRef) -> Result<Display, String> {
+    let displays = list_displays()?;
+    match display {
+        DisplayRef::Index(index) => displays.get(*index).cloned().ok_or_else(|| {
+            format!(
+                "There is no monitor {} ({} connected)",
+                index,
+                displays.len()
+            )
+        }),
+        DisplayRef::Name(name) => displays
+            .into_iter()
+            .find(|display| display.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("No monitor named '{}'", name)),
+    }
+}
+
+/// Every active monitor, from the compositor on Wayland and xrandr on X11
+///
+/// Without any of those tools the main display is reported on its own.
+pub fn list_displays() -> Result<Vec<Display>, String> {
+    let displays = if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+        parse_hyprctl_monitors(&run("hyprctl", &["monitors", "-j"])?)?
+    } else if std::env::var("SWAYSOCK").is_ok() {
+        parse_sway_outputs(&run("swaymsg", &["-t", "get_outputs", "-r"])?)?
+    } else if std::env::var("WAYLAND_DISPLAY").is_ok() && find_in_path("wlr-randr").is_some() {
+        parse_wlr_randr(&run("wlr-randr", &["--json"])?)?
+    } else if std::env::var("DISPLAY").is_ok() && find_in_path("xrandr").is_some() {
+        parse_xrandr_monitors(&run("xrandr", &["--listmonitors"])?)
+    } else {
+        Vec::new()
+    };
+    if !displays.is_empty() {
+        return Ok(displays);
+    }
+    let (width, height) = display_size()?;
+    Ok(vec![Display {
+        name: "default".to_string(),
+        x: 0,
+        y: 0,
+        width,
+        height,
+        scale: 1.0,
+        primary: true,
+    }])
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output_bounded()
+        .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn number(value: &Value) -> i32 {
+    value.as_i64().unwrap_or(0) as i32
+}
+
+fn parse_json(output: &str, program: &str) -> Result<Vec<Value>, String> {
+    serde_json::from_str(output).map_err(|e| format!("Failed to parse {} output: {}", program, e))
+}
+
+/// `hyprctl monitors -j`: physical size, logical position
+fn parse_hyprctl_monitors(output: &str) -> Result<Vec<Display>, String> {
+    let monitors = parse_json(output, "hyprctl")?;
+    Ok(monitors
+        .iter()
+        .filter(|monitor| !monitor["disabled"].as_bool().unwrap_or(false))
+        .enumerate()
+        .map(|(index, monitor)| Display {
+            name: monitor["name"].as_str().unwrap_or_default().to_string(),
+            x: number(&monitor["x"]),
+            y: number(&monitor["y"]),
+            width: number(&monitor["width"]),
+            height: number(&monitor["height"]),
+            scale: monitor["scale"].as_f64().unwrap_or(1.0),
+            // Hyprland has no primary monitor; the first one stands in
+            primary: index == 0,
+        })
+        .collect())
+}
+
+/// `swaymsg -t get_outputs -r`: the mode has the physical size
+fn parse_sway_outputs(output: &str) -> Result<Vec<Display>, String> {
+    let outputs = parse_json(output, "swaymsg")?;
+    Ok(outputs
+        .iter()
+        .filter(|output| output["active"].as_bool().unwrap_or(false))
+        .map(|output| Display {
+            name: output["name"].as_str().unwrap_or_default().to_string(),
+            x: number(&output["rect"]["x"]),
+            y: number(&output["rect"]["y"]),
+            width: number(&output["current_mode"]["width"]),
+            height: number(&output["current_mode"]["height"]),
+            scale: output["scale"].as_f64().unwrap_or(1.0),
+            primary: output["primary"].as_bool().unwrap_or(false),
+        })
+        .collect())
+}
+
+/// `wlr-randr --json`: the size comes from the current mode
+fn parse_wlr_randr(output: &str) -> Result<Vec<Display>, String> {
+    let heads = parse_json(output, "wlr-randr")?;
+    Ok(heads
+        .iter()
+        .filter(|head| head["enabled"].as_bool().unwrap_or(false))
+        .filter_map(|head| {
+            let modes = head["modes"].as_array()?;
+            let mode = modes
+                .iter()
+                .find(|mode| mode["current"].as_bool().unwrap_or(false))?;
+            Some(Display {
+                name: head["name"].as_str().unwrap_or_default().to_string(),
+                x: number(&head["position"]["x"]),
+                y: number(&head["position"]["y"]),
+                width: number(&mode["width"]),
+                height: number(&mode["height"]),
+                scale: head["scale"].as_f64().unwrap_or(1.0),
+                primary: false,
+            })
+        })
+        .collect())
+}
+
+/// `xrandr --listmonitors` lines such as
+/// ` 0: +*DP-1 2560/597x1440/336+0+0  DP-1`; X11 does not scale outputs
+fn parse_xrandr_monitors(output: &str) -> Vec<Display> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let flags = fields.next()?;
+            let geometry = fields.next()?;
+            let name = fields
+                .next()
+                .unwrap_or(flags.trim_start_matches(['+', '*']));
+            // "2560/597x1440/336+0+0": sizes carry their millimetres
+            let (width, rest) = geometry.split_once('x')?;
+            let mut parts = rest.split('+');
+            let height = parts.next()?;
+            let size = |part: &str| part.split('/').next()?.parse().ok();
+            Some(Display {
+                name: name.to_string(),
+                x: parts.next()?.parse().ok()?,
+                y: parts.next()?.parse().ok()?,
+                width: size(width)?,
+                height: size(height)?,
+                scale: 1.0,
+                primary: flags.contains('*'),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_monitor_lists() {
+        let xrandr = "Monitors: 2\n \
+                      0: +*DP-1 2560/597x1440/336+0+0  DP-1\n \
+                      1: +HDMI-1 1920/527x1080/296+2560+180  HDMI-1\n";
+        let displays = parse_xrandr_monitors(xrandr);
+        assert_eq!(displays.len(), 2);
+        assert!(displays[0].primary);
+        assert_eq!(
+            (displays[1].x, displays[1].y, displays[1].width),
+            (2560, 180, 1920)
+        );
+
+        let hyprctl = r#"[{"name":"eDP-1","x":0,"y":0,"width":2880,"height":1800,"scale":2.0,"disabled":false}]"#;
+        let displays = parse_hyprctl_monitors(hyprctl).unwrap();
+        assert_eq!(displays[0].scale, 2.0);
+
+        let sway = r#"[{"name":"DP-2","active":true,"primary":false,"rect":{"x":1280,"y":0,"width":1280,"height":720},
+            "current_mode":{"width":2560,"height":1440},"scale":2.0},{"name":"HDMI-A-1","active":false}]"#;
+        let displays = parse_sway_outputs(sway).unwrap();
+        assert_eq!(displays.len(), 1);
+        assert_eq!((displays[0].x, displays[0].width), (1280, 2560));
+    }
+}
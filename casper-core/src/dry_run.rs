@@ -0,0 +1,578 @@
+use crate::actions::{Action, ActionSequence, Anchor, Condition, ScreenAssertion, StepRef};
+use crate::ai_vision::AIVision;
+use crate::assertions::{check_assertion, reference_path};
+use crate::capture::parse_color;
+use crate::clipboard::check_clipboard;
+use crate::displays::find_display;
+use crate::executor::{PlaybackOptions, SequenceLookup};
+use crate::launcher::resolve_application;
+use crate::paths;
+use crate::screen::{check_click_count, display_size, parse_button, parse_key, parse_key_combo};
+use crate::setup::find_in_path;
+use crate::shortcuts::ShortcutTable;
+use crate::window::find_window_by_pattern;
+use serde::Serialize;
+use std::path::Path;
+
+/// How deeply a dry run follows `CallSequence` steps, as playback does
+const MAX_CALL_DEPTH: usize = 8;
+
+/// What playing a sequence would do, without doing any of it
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRun {
+    pub steps: Vec<PlannedStep>,
+    /// Whether no step has a problem
+    pub valid: bool,
+    /// Time spent in delays and `Wait` steps, at the requested speed
+    pub duration_ms: u64,
+}
+
+/// A step of a dry run
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedStep {
+    pub index: usize,
+    pub label: Option<String>,
+    /// Time waited before the step, at the requested speed
+    pub delay_ms: u64,
+    pub action: Action,
+    /// Why the step would fail
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+    /// What may go wrong or deserves a look, e.g. a window that isn't open yet
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Walk the steps between `start` and `end` (both inclusive) and check each
+/// one: keys and buttons parse, programs and applications exist, windows are
+/// open, points and called sequences are found, and commands are on the
+/// `allowlist` if there is one
+///
+/// Nothing is performed: conditions are not evaluated and commands not run,
+/// so both branches of an `If` are checked.
+pub fn dry_run(
+    sequence: &ActionSequence,
+    start: Option<&StepRef>,
+    end: Option<&StepRef>,
+    options: PlaybackOptions,
+    lookup: &SequenceLookup,
+    allowlist: Option<&[String]>,
+) -> Result<DryRun, String> {
+    let start = match start {
+        Some(step) => sequence.resolve_step(step)?,
+        None => 0,
+    };
+    let end = match end {
+        Some(step) => sequence.resolve_step(step)?,
+        None => sequence.actions.len().saturating_sub(1),
+    };
+    if end < start {
+        return Err(format!("End step {} is before start step {}", end, start));
+    }
+
+    let checker = Checker {
+        screen: display_size().ok(),
+        shortcuts: ShortcutTable::load(&paths::shortcuts_file()),
+        lookup,
+        allowlist,
+    };
+    let mut duration_ms = 0;
+    let mut steps = Vec::new();
+    for (index, step) in sequence.actions.iter().enumerate() {
+        if index < start || index > end {
+            continue;
+        }
+        let delay_ms = options.delay(step.delay_ms).as_millis() as u64;
+        duration_ms += delay_ms;
+        if let Action::Wait { milliseconds } = step.action {
+            duration_ms += milliseconds;
+        }
+        let mut report = Report::default();
+        checker.check_step(sequence, &step.action, 0, &mut report);
+        steps.push(PlannedStep {
+            index,
+            label: step.label.clone(),
+            delay_ms,
+            action: step.action.redacted(),
+            problems: report.problems,
+            warnings: report.warnings,
+        });
+    }
+
+    Ok(DryRun {
+        valid: steps.iter().all(|step| step.problems.is_empty()),
+        steps,
+        duration_ms,
+    })
+}
+
+#[derive(Default)]
+struct Report {
+    problems: Vec<String>,
+    warnings: Vec<String>,
+}
+
+struct Checker<'a> {
+    screen: Option<(i32, i32)>,
+    shortcuts: Result<ShortcutTable, String>,
+    lookup: &'a SequenceLookup,
+    allowlist: Option<&'a [String]>,
+}
+
+impl Checker<'_> {
+    /// Check a step of a sequence, flagging it if it is risky
+    fn check_step(
+        &self,
+        sequence: &ActionSequence,
+        action: &Action,
+        depth: usize,
+        report: &mut Report,
+    ) {
+        if let Some(risk) = action.risk() {
+            report.warnings.push(risk);
+        }
+        self.check(sequence, action, depth, report);
+    }
+
+    fn check(&self, sequence: &ActionSequence, action: &Action, depth: usize, report: &mut Report) {
+        let problem = |result: Result<(), String>, report: &mut Report| {
+            if let Err(e) = result {
+                report.problems.push(e);
+            }
+        };
+
+        match action {
+            Action::MoveMouse { x, y, anchor } => self.check_point(*x, *y, anchor, report),
+            Action::ClickMouse {
+                button,
+                x,
+                y,
+                anchor,
+                count,
+                ..
+            } => {
+                problem(parse_button(button).map(|_| ()), report);
+                if let Some(count) = count {
+                    problem(check_click_count(*count), report);
+                }
+                match (x, y) {
+                    (Some(x), Some(y)) => self.check_point(*x, *y, anchor, report),
+                    (None, None) if anchor.is_none() => {}
+                    _ => report
+                        .problems
+                        .push("ClickMouse needs both x and y to be positioned".to_string()),
+                }
+            }
+            Action::Drag {
+                from_x,
+                from_y,
+                to_x,
+                to_y,
+                button,
+                ..
+            } => {
+                problem(parse_button(button).map(|_| ()), report);
+                self.check_point(*from_x, *from_y, &None, report);
+                self.check_point(*to_x, *to_y, &None, report);
+            }
+            Action::MouseDown { button } | Action::MouseUp { button } => {
+                problem(parse_button(button).map(|_| ()), report)
+            }
+            Action::Scroll { direction, .. } => check_scroll_direction(direction, report),
+            Action::ScrollPages { direction, .. } => {
+                if !matches!(direction.as_str(), "up" | "down") {
+                    report
+                        .problems
+                        .push(format!("Pages only scroll up or down, not {}", direction));
+                }
+            }
+            Action::ScrollToImage {
+                image, direction, ..
+            } => {
+                check_scroll_direction(direction, report);
+                let path = reference_path(image);
+                if !path.exists() {
+                    report
+                        .problems
+                        .push(format!("No reference image at {}", path.display()));
+                }
+            }
+            Action::PressKey { key } | Action::KeyDown { key } | Action::KeyUp { key } => {
+                problem(parse_key(key).map(|_| ()), report)
+            }
+            Action::PressCombo { combo } => problem(
+                parse_key_combo(combo).and_then(|(_, key)| parse_key(&key).map(|_| ())),
+                report,
+            ),
+            Action::RunCommand { command } => {
+                problem(check_program(command, self.allowlist), report)
+            }
+            Action::LaunchApp { app_name } => {
+                let found = if app_name.contains('/') {
+                    Path::new(app_name).exists()
+                } else {
+                    resolve_application(app_name).is_some() || find_in_path(app_name).is_some()
+                };
+                if !found {
+                    report
+                        .problems
+                        .push(format!("No application or program named '{}'", app_name));
+                }
+            }
+            Action::FocusWindow { window_pattern }
+            | Action::MoveToWorkspace { window_pattern, .. }
+            | Action::SnapWindow { window_pattern, .. } => {
+                self.check_window(window_pattern, report)
+            }
+            Action::AppAction { name } => match &self.shortcuts {
+                Ok(table) if table.knows(name) => {}
+                Ok(_) => report
+                    .problems
+                    .push(format!("No application has a shortcut for '{}'", name)),
+                Err(e) => report.problems.push(e.clone()),
+            },
+            Action::MoveToPoint { .. } => {
+                problem(sequence.resolve_action(action).map(|_| ()), report)
+            }
+            Action::Repeat { actions, .. } => {
+                for action in actions {
+                    self.check(sequence, action, depth + 1, report);
+                }
+            }
+            Action::WithModifiers { modifiers, actions } => {
+                for key in modifiers {
+                    problem(parse_key(key).map(|_| ()), report);
+                }
+                for action in actions {
+                    self.check(sequence, action, depth + 1, report);
+                }
+            }
+            Action::Parallel { branches } => {
+                for action in branches.iter().flatten() {
+                    self.check(sequence, action, depth + 1, report);
+                }
+            }
+            Action::If(branch) => {
+                check_condition(&branch.condition, self.allowlist, report);
+                for action in branch.then.iter().chain(&branch.otherwise) {
+                    self.check(sequence, action, depth + 1, report);
+                }
+            }
+            Action::CallSequence { name } => self.check_call(name, depth, report),
+            Action::WaitForPixelColor(wait) => {
+                problem(parse_color(&wait.pixel.color).map(|_| ()), report)
+            }
+            Action::WaitForScreenChange(wait) => {
+                if let Some(share) = wait.threshold
+                    && !(0.0..1.0).contains(&share)
+                {
+                    report
+                        .problems
+                        .push(format!("threshold {} is not between 0 and 1", share));
+                }
+            }
+            Action::AssertScreen(assertion) => self.check_assertion(assertion, report),
+            Action::SetClipboard { .. }
+            | Action::GetClipboard { .. }
+            | Action::TypeText {
+                via_clipboard: true,
+                ..
+            } => problem(check_clipboard(), report),
+            Action::MoveMouseRel { .. }
+            | Action::TypeText { .. }
+            | Action::Wait { .. }
+            | Action::ShowNotification { .. }
+            | Action::Speak { .. }
+            | Action::WaitForWindow(_)
+            | Action::WaitForProcess(_)
+            | Action::WaitForProcessExit(_)
+            | Action::KillProcess { .. }
+            | Action::Checkpoint { .. }
+            | Action::StoreSet { .. }
+            | Action::StoreGet { .. }
+            | Action::SwitchWorkspace { .. } => {}
+        }
+    }
+
+    fn check_point(&self, x: i32, y: i32, anchor: &Option<Anchor>, report: &mut Report) {
+        match anchor {
+            None => {
+                if let Some((width, height)) = self.screen
+                    && (!(0..width).contains(&x) || !(0..height).contains(&y))
+                {
+                    report.warnings.push(format!(
+                        "({}, {}) is outside the {}x{} screen",
+                        x, y, width, height
+                    ));
+                }
+            }
+            Some(Anchor::Window { window }) => self.check_window(window, report),
+            Some(Anchor::Monitor { monitor }) => match find_display(monitor) {
+                Ok(display) => {
+                    let (width, height) = display.logical_size();
+                    if !(0..width).contains(&x) || !(0..height).contains(&y) {
+                        report.warnings.push(format!(
+                            "({}, {}) is outside the {}x{} monitor {}",
+                            x, y, width, height, display.name
+                        ));
+                    }
+                }
+                Err(e) => report.problems.push(e),
+            },
+            Some(Anchor::Image { image, .. }) => {
+                let path = reference_path(image);
+                if !path.exists() {
+                    report
+                        .problems
+                        .push(format!("No anchor image at {}", path.display()));
+                }
+            }
+        }
+    }
+
+    fn check_window(&self, pattern: &str, report: &mut Report) {
+        match find_window_by_pattern(pattern) {
+            Ok(Some(_)) => {}
+            Ok(None) => report
+                .warnings
+                .push(format!("No window matches '{}' right now", pattern)),
+            Err(e) => report
+                .warnings
+                .push(format!("Could not list windows: {}", e)),
+        }
+    }
+
+    fn check_assertion(&self, assertion: &ScreenAssertion, report: &mut Report) {
+        if let Err(e) = check_assertion(assertion) {
+            report.problems.push(e);
+        }
+        if let Some(reference) = &assertion.reference {
+            let path = reference_path(reference);
+            if !path.exists() {
+                report.warnings.push(format!(
+                    "No reference image at {} yet; the first playback records it",
+                    path.display()
+                ));
+            }
+        }
+        if assertion.expect.is_some()
+            && let Err(e) = AIVision::from_env()
+        {
+            report.problems.push(e);
+        }
+        if let Some(share) = assertion.max_difference
+            && !(0.0..=1.0).contains(&share)
+        {
+            report
+                .problems
+                .push(format!("max_difference {} is not between 0 and 1", share));
+        }
+        if let (Some(region), Some((width, height))) = (&assertion.region, self.screen)
+            && (region.x < 0
+                || region.y < 0
+                || region.width <= 0
+                || region.height <= 0
+                || region.x + region.width > width
+                || region.y + region.height > height)
+        {
+            report.warnings.push(format!(
+                "Region {}x{}+{}+{} is outside the {}x{} screen",
+                region.width, region.height, region.x, region.y, width, height
+            ));
+        }
+    }
+
+    /// Check every step of a called sequence, naming the step in each finding
+    fn check_call(&self, name: &str, depth: usize, report: &mut Report) {
+        if depth >= MAX_CALL_DEPTH {
+            report.problems.push(format!(
+                "Sequence calls nest more than {} deep",
+                MAX_CALL_DEPTH
+            ));
+            return;
+        }
+        let called = match (self.lookup)(name) {
+            Ok(called) => called,
+            Err(e) => return report.problems.push(e),
+        };
+        for (index, step) in called.actions.iter().enumerate() {
+            let mut inner = Report::default();
+            self.check_step(&called, &step.action, depth + 1, &mut inner);
+            let context = |finding: String| format!("In '{}' step {}: {}", name, index, finding);
+            report
+                .problems
+                .extend(inner.problems.into_iter().map(context));
+            report
+                .warnings
+                .extend(inner.warnings.into_iter().map(context));
+        }
+    }
+}
+
+fn check_scroll_direction(direction: &str, report: &mut Report) {
+    if !matches!(direction, "up" | "down" | "left" | "right") {
+        report
+            .problems
+            .push(format!("Unknown scroll direction: {}", direction));
+    }
+}
+
+/// Conditions are only checked for well-formedness, never evaluated
+fn check_condition(condition: &Condition, allowlist: Option<&[String]>, report: &mut Report) {
+    match condition {
+        Condition::PixelColor(pixel) => {
+            if let Err(e) = parse_color(&pixel.color) {
+                report.problems.push(e);
+            }
+        }
+        Condition::CommandSucceeds { command } => {
+            if let Err(e) = check_program(command, allowlist) {
+                report.warnings.push(e);
+            }
+        }
+        Condition::Not { condition } => check_condition(condition, allowlist, report),
+        Condition::WindowExists { .. } | Condition::ProcessRunning { .. } => {}
+    }
+}
+
+/// Whether the program of a command line can be found, and is on the
+/// allowlist if there is one
+fn check_program(command: &str, allowlist: Option<&[String]>) -> Result<(), String> {
+    let Some(program) = command.split_whitespace().next() else {
+        return Err("Empty command".to_string());
+    };
+    if let Some(allowlist) = allowlist {
+        let name = Path::new(program)
+            .file_name()
+            .and_then(|name| name.to_str());
+        if !allowlist
+            .iter()
+            .any(|allowed| allowed == program || Some(allowed.as_str()) == name)
+        {
+            return Err(format!(
+                "Program is not on the command allowlist: {}",
+                program
+            ));
+        }
+    }
+    let found = if program.contains('/') {
+        Path::new(program).exists()
+    } else {
+        find_in_path(program).is_some()
+    };
+    if found {
+        Ok(())
+    } else {
+        Err(format!("Program not found: {}", program))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Conditional;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_dry_run_finds_problems() {
+        let mut helper = ActionSequence::new("helper".into(), String::new());
+        helper.add_action(
+            Action::PressKey {
+                key: "nokey".into(),
+            },
+            0,
+        );
+        let lookup: SequenceLookup = Arc::new(move |name| match name {
+            "helper" => Ok(helper.clone()),
+            _ => Err(format!("Sequence not found: {}", name)),
+        });
+
+        let mut sequence = ActionSequence::new("test".into(), String::new());
+        sequence.add_action(Action::PressKey { key: "tab".into() }, 100);
+        sequence.add_action(
+            Action::ClickMouse {
+                button: "thumb".into(),
+                x: None,
+                y: None,
+                anchor: None,
+                settle_ms: None,
+                count: None,
+            },
+            0,
+        );
+        sequence.add_action(Action::Wait { milliseconds: 50 }, 100);
+        sequence.add_action(
+            Action::RunCommand {
+                command: "casper-no-such-program --flag".into(),
+            },
+            0,
+        );
+        sequence.add_action(
+            Action::If(Conditional {
+                condition: Condition::Not {
+                    condition: Box::new(Condition::ProcessRunning { name: "x".into() }),
+                },
+                then: vec![Action::CallSequence {
+                    name: "helper".into(),
+                }],
+                otherwise: vec![Action::MoveToPoint {
+                    point: "missing".into(),
+                }],
+            }),
+            0,
+        );
+
+        let options = PlaybackOptions::new(2.0, false).unwrap();
+        let plan = dry_run(&sequence, None, None, options, &lookup, None).unwrap();
+        assert!(!plan.valid);
+        assert_eq!(plan.duration_ms, 150);
+        assert_eq!(plan.steps[0].delay_ms, 50);
+        assert!(plan.steps[0].problems.is_empty());
+        assert_eq!(plan.steps[1].problems, vec!["Unknown button: thumb"]);
+        assert!(plan.steps[2].problems.is_empty());
+        assert_eq!(
+            plan.steps[3].problems,
+            vec!["Program not found: casper-no-such-program"]
+        );
+        assert_eq!(plan.steps[4].problems.len(), 2);
+        assert_eq!(
+            plan.steps[4].problems[0],
+            "In 'helper' step 0: Unknown key: nokey"
+        );
+
+        let start = StepRef::Index(2);
+        let plan = dry_run(
+            &sequence,
+            Some(&start),
+            Some(&start),
+            options,
+            &lookup,
+            None,
+        )
+        .unwrap();
+        assert!(plan.valid);
+        assert_eq!(plan.steps.len(), 1);
+        assert!(
+            dry_run(
+                &sequence,
+                Some(&start),
+                Some(&StepRef::Index(0)),
+                options,
+                &lookup,
+                None
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_dry_run_checks_command_allowlist() {
+        assert!(check_program("sh -c true", None).is_ok());
+        let allowlist = vec!["sh".to_string()];
+        assert!(check_program("/bin/sh -c true", Some(&allowlist)).is_ok());
+        assert_eq!(
+            check_program("ls -l", Some(&allowlist)).unwrap_err(),
+            "Program is not on the command allowlist: ls"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::commands::CommandDeadline;
+use crate::screen::display_size;
+use crate::window::list_windows;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Where a sequence was recorded, so playback can warn about differences
+///
+/// Every field is best effort: whatever could not be determined is left
+/// out and never reported as a difference.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentSnapshot {
+    /// Main display size in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen: Option<(i32, i32)>,
+    /// "x11" or "wayland"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_type: Option<String>,
+    /// Desktop or compositor, e.g. "GNOME" or "Hyprland"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desktop: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyboard_layout: Option<String>,
+    /// Window classes that were open, with the version of their package if known
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub apps: BTreeMap<String, Option<String>>,
+}
+
+impl EnvironmentSnapshot {
+    /// Describe the current environment
+    pub fn capture() -> Self {
+        let mut apps = BTreeMap::new();
+        for window in list_windows().unwrap_or_default() {
+            if window.class.is_empty() || apps.contains_key(&window.class) {
+                continue;
+            }
+            let version = (window.pid > 0)
+                .then(|| std::fs::read_link(format!("/proc/{}/exe", window.pid)).ok())
+                .flatten()
+                .and_then(|exe| package_version(&exe));
+            apps.insert(window.class, version);
+        }
+
+        EnvironmentSnapshot {
+            screen: display_size().ok(),
+            session_type: non_empty_var("XDG_SESSION_TYPE"),
+            desktop: if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+                Some("Hyprland".to_string())
+            } else {
+                non_empty_var("XDG_CURRENT_DESKTOP")
+            },
+            keyboard_layout: keyboard_layout(),
+            apps,
+        }
+    }
+
+    /// Material differences between this (recorded) environment and `current`
+    pub fn differences(&self, current: &EnvironmentSnapshot) -> Vec<String> {
+        let mut differences = Vec::new();
+        if let (Some((w, h)), Some((cw, ch))) = (self.screen, current.screen)
+            && (w, h) != (cw, ch)
+        {
+            differences.push(format!(
+                "Recorded on a {}x{} screen, the screen is now {}x{}",
+                w, h, cw, ch
+            ));
+        }
+        let fields = [
+            ("session", &self.session_type, &current.session_type),
+            ("desktop", &self.desktop, &current.desktop),
+            (
+                "keyboard layout",
+                &self.keyboard_layout,
+                &current.keyboard_layout,
+            ),
+        ];
+        for (what, recorded, now) in fields {
+            if let (Some(recorded), Some(now)) = (recorded, now)
+                && !recorded.eq_ignore_ascii_case(now)
+            {
+                differences.push(format!("Recorded with {} {}, now {}", what, recorded, now));
+            }
+        }
+        for (app, recorded) in &self.apps {
+            if let (Some(recorded), Some(Some(now))) = (recorded, current.apps.get(app))
+                && recorded != now
+            {
+                differences.push(format!("Recorded with {} {}, now {}", app, recorded, now));
+            }
+        }
+        differences
+    }
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Active keyboard layout, e.g. "us" or "de"
+fn keyboard_layout() -> Option<String> {
+    if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+        let output = Command::new("hyprctl")
+            .args(["devices", "-j"])
+            .output_bounded()
+            .ok()?;
+        let devices: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+        let keyboards = devices["keyboards"].as_array()?;
+        let keyboard = keyboards
+            .iter()
+            .find(|k| k["main"].as_bool() == Some(true))
+            .or_else(|| keyboards.first())?;
+        return keyboard["active_keymap"].as_str().map(str::to_string);
+    }
+    if let Ok(output) = Command::new("setxkbmap").arg("-query").output_bounded()
+        && output.status.success()
+    {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if let Some(layout) = stdout.lines().find_map(|line| line.strip_prefix("layout:")) {
+            return Some(layout.trim().to_string());
+        }
+    }
+    non_empty_var("XKB_DEFAULT_LAYOUT")
+}
+
+/// Version of the installed package owning an executable
+fn package_version(exe: &Path) -> Option<String> {
+    let exe = exe.to_str()?;
+    let query = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output_bounded()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    // pacman: "/usr/bin/firefox is owned by firefox 121.0-1"
+    if let Some(owner) = query("pacman", &["-Qo", exe]) {
+        return owner.split_whitespace().last().map(str::to_string);
+    }
+    // dpkg: "firefox: /usr/lib/firefox/firefox"
+    if let Some(owner) = query("dpkg-query", &["-S", exe]) {
+        let package = owner.split(':').next()?.trim().to_string();
+        return query("dpkg-query", &["-W", "-f=${Version}", &package]);
+    }
+    query(
+        "rpm",
+        &["-qf", "--queryformat", "%{VERSION}-%{RELEASE}", exe],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_differences() {
+        let recorded = EnvironmentSnapshot {
+            screen: Some((1920, 1080)),
+            session_type: Some("x11".into()),
+            desktop: None,
+            keyboard_layout: Some("us".into()),
+            apps: BTreeMap::from([
+                ("firefox".to_string(), Some("120.0".to_string())),
+                ("kitty".to_string(), None),
+            ]),
+        };
+        let mut current = recorded.clone();
+        assert!(recorded.differences(&current).is_empty());
+
+        current.screen = Some((2560, 1440));
+        current.desktop = Some("GNOME".into());
+        current.keyboard_layout = None;
+        current
+            .apps
+            .insert("firefox".to_string(), Some("121.0".to_string()));
+        let differences = recorded.differences(&current);
+        assert_eq!(differences.len(), 2);
+        assert!(differences[0].contains("2560x1440"));
+        assert!(differences[1].contains("firefox 120.0, now 121.0"));
+    }
+}
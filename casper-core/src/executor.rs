@@ -0,0 +1,1235 @@
+use crate::actions::{
+    Action, ActionPlayer, ActionSequence, ActionWithTimestamp, Anchor, Condition, ErrorPolicy,
+    PlaybackState,
+};
+use crate::assertions::{assert_screen, reference_path};
+use crate::capture::{colors_match, parse_color, pixel_color};
+use crate::clipboard::{get_clipboard, paste_text, set_clipboard};
+use crate::commands::{run_command, spawn_blocking_bounded};
+use crate::image_diff::wait_for_screen_change;
+use crate::keyboard_layout::type_text_on_layout;
+use crate::locate::{DEFAULT_MAX_SCROLLS, resolve_point, scroll_to_image};
+use crate::notifications::show_notification;
+use crate::pipeline::{Variables, substitute_bound};
+use crate::processes::kill_processes;
+use crate::screen::{
+    click_at, click_times, drag, key_down, key_up, mouse_down, mouse_up, move_mouse,
+    move_mouse_relative, press_key, press_key_combo, scroll, scroll_pages, scroll_smoothly,
+    with_modifiers,
+};
+use crate::sensors::watch_screen;
+use crate::shortcuts::send_app_action;
+use crate::store::{set_shared_text, shared_text};
+use crate::tts::speak_blocking;
+use crate::window::{
+    find_window_by_pattern, focus_window, is_process_running, launch_application,
+    move_window_to_workspace, snap_window, switch_workspace,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+/// A player shared between request handlers and the playback task
+pub type SharedPlayer = Arc<Mutex<ActionPlayer>>;
+
+/// How often a waiting playback checks for pause, resume and stop
+const CONTROL_TICK: Duration = Duration::from_millis(50);
+
+/// How often a wait-for action checks its condition
+const WAIT_POLL: Duration = Duration::from_millis(250);
+
+/// How deeply `CallSequence` steps may nest; also ends sequences calling themselves
+const MAX_CALL_DEPTH: usize = 8;
+
+tokio::task_local! {
+    /// Cancellation of the `Parallel` branch being performed
+    static BRANCH: Arc<Cancellation>;
+}
+
+/// Set when a sibling of a `Parallel` branch, or of a branch it is nested
+/// in, has failed
+struct Cancellation {
+    cancelled: AtomicBool,
+    parent: Option<Arc<Cancellation>>,
+}
+
+impl Cancellation {
+    fn is_set(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.parent.as_ref().is_some_and(|p| p.is_set())
+    }
+}
+
+fn branch_cancelled() -> bool {
+    BRANCH.try_with(|branch| branch.is_set()).unwrap_or(false)
+}
+
+/// Finds a saved sequence by name, for `CallSequence` steps
+pub type SequenceLookup = Arc<dyn Fn(&str) -> Result<ActionSequence, String> + Send + Sync>;
+
+/// Slowest playback speed; slower ones would stretch a recorded delay past
+/// what a `Duration` holds
+pub const MIN_SPEED: f64 = 0.01;
+
+/// How fast a sequence is replayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackOptions {
+    /// Multiplier for the recorded delays between steps (2.0 is twice as fast)
+    pub speed: f64,
+    /// Skip the recorded delays entirely
+    pub no_delays: bool,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        PlaybackOptions {
+            speed: 1.0,
+            no_delays: false,
+        }
+    }
+}
+
+impl PlaybackOptions {
+    /// Options for replaying at `speed`, at least `MIN_SPEED`
+    pub fn new(speed: f64, no_delays: bool) -> Result<Self, String> {
+        if !(speed > 0.0 && speed.is_finite()) {
+            return Err(format!("speed must be a positive number, got {}", speed));
+        }
+        Ok(PlaybackOptions {
+            speed: speed.max(MIN_SPEED),
+            no_delays,
+        })
+    }
+
+    /// Time to wait before a step recorded `delay_ms` after the previous one
+    ///
+    /// Explicit `Wait` steps are not affected; they usually give an
+    /// application time to react rather than reflect how fast the user was.
+    pub fn delay(&self, delay_ms: u64) -> Duration {
+        if self.no_delays {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(delay_ms).div_f64(self.speed.max(MIN_SPEED))
+        }
+    }
+}
+
+/// Progress of a running playback, reported after every step
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlaybackProgress {
+    /// A step finished; `index` is zero-based
+    Step {
+        index: usize,
+        total: usize,
+        label: Option<String>,
+        /// Time the step took, without its recorded delay
+        duration_ms: u64,
+    },
+    /// Every step ran; `failures` lists the steps skipped by a `continue` policy
+    Finished {
+        steps: usize,
+        failures: Vec<StepFailure>,
+    },
+    /// Playback was stopped before the last step
+    Stopped {
+        steps: usize,
+        failures: Vec<StepFailure>,
+    },
+    /// A step failed and playback was stopped
+    Failed {
+        index: usize,
+        error: String,
+        failures: Vec<StepFailure>,
+    },
+}
+
+/// A failed step that playback went past
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepFailure {
+    /// The sequence the step belongs to, which differs for called sequences
+    pub sequence: String,
+    pub index: usize,
+    pub label: Option<String>,
+    pub error: String,
+}
+
+/// Perform a single action
+///
+/// Blocking: input, window and command actions wait for their tool, and
+/// speech waits until it has been spoken so later steps follow it.
+/// Actions looking at the screen are refused in privacy mode.
+pub fn execute_action(action: &Action) -> Result<(), String> {
+    if reads_screen(action) {
+        return watch_screen("playback", || perform_action(action));
+    }
+    perform_action(action)
+}
+
+/// Whether an action captures the screen (nested actions are checked as
+/// they run)
+fn reads_screen(action: &Action) -> bool {
+    match action {
+        Action::MoveMouse { anchor, .. } | Action::ClickMouse { anchor, .. } => {
+            matches!(anchor, Some(Anchor::Image { .. }))
+        }
+        Action::ScrollToImage { .. } | Action::WaitForScreenChange(_) | Action::AssertScreen(_) => {
+            true
+        }
+        _ => false,
+    }
+}
+
+fn perform_action(action: &Action) -> Result<(), String> {
+    match action {
+        Action::MoveMouse { x, y, anchor } => {
+            let (x, y) = resolve_point(*x, *y, anchor.as_ref())?;
+            move_mouse(x, y)
+        }
+        Action::MoveMouseRel { dx, dy } => move_mouse_relative(*dx, *dy),
+        Action::ClickMouse {
+            button,
+            x,
+            y,
+            anchor,
+            settle_ms,
+            count,
+        } => match (x, y) {
+            (Some(x), Some(y)) => {
+                let (x, y) = resolve_point(*x, *y, anchor.as_ref())?;
+                click_at(x, y, button, count.unwrap_or(1), settle_ms.unwrap_or(0))
+            }
+            (None, None) if anchor.is_none() => click_times(button, count.unwrap_or(1)),
+            _ => Err("ClickMouse needs both x and y to be positioned".to_string()),
+        },
+        Action::Drag {
+            from_x,
+            from_y,
+            to_x,
+            to_y,
+            button,
+            duration_ms,
+        } => drag(*from_x, *from_y, *to_x, *to_y, button, *duration_ms),
+        Action::MouseDown { button } => mouse_down(button),
+        Action::MouseUp { button } => mouse_up(button),
+        Action::Scroll {
+            amount,
+            direction,
+            step: Some(step),
+            delay_ms,
+        } => scroll_smoothly(*amount, direction, *step, *delay_ms),
+        Action::Scroll {
+            amount, direction, ..
+        } => scroll(*amount, direction),
+        Action::ScrollPages { pages, direction } => scroll_pages(*pages, direction),
+        Action::ScrollToImage {
+            image,
+            direction,
+            tolerance,
+            max_scrolls,
+        } => scroll_to_image(
+            &reference_path(image),
+            tolerance.unwrap_or(0),
+            direction,
+            max_scrolls.unwrap_or(DEFAULT_MAX_SCROLLS),
+        )
+        .map(|_| ()),
+        Action::TypeText {
+            text,
+            via_clipboard: true,
+            sensitive: false,
+            ..
+        } => paste_text(text),
+        Action::TypeText {
+            text,
+            delay_ms,
+            layout,
+            sensitive,
+            ..
+        } => type_text_on_layout(text, layout.as_deref(), *delay_ms, *sensitive),
+        Action::PressKey { key } => press_key(key),
+        Action::PressCombo { combo } => press_key_combo(combo),
+        Action::WithModifiers { modifiers, actions } => {
+            with_modifiers(modifiers, || actions.iter().try_for_each(execute_action))
+        }
+        Action::KeyDown { key } => key_down(key),
+        Action::KeyUp { key } => key_up(key),
+        Action::RunCommand { command } => run_command(command).map(|_| ()),
+        Action::Wait { milliseconds } => {
+            std::thread::sleep(Duration::from_millis(*milliseconds));
+            Ok(())
+        }
+        Action::LaunchApp { app_name } => launch_application(app_name),
+        Action::FocusWindow { window_pattern } => focus_window(window_pattern),
+        Action::SwitchWorkspace { workspace } => switch_workspace(*workspace),
+        Action::MoveToWorkspace {
+            window_pattern,
+            workspace,
+        } => match find_window_by_pattern(window_pattern)? {
+            Some(window) => move_window_to_workspace(&window.id, *workspace),
+            None => Err(format!("No window matches '{}'", window_pattern)),
+        },
+        Action::SnapWindow {
+            window_pattern,
+            position,
+        } => match find_window_by_pattern(window_pattern)? {
+            Some(window) => snap_window(&window.id, *position, None).map(|_| ()),
+            None => Err(format!("No window matches '{}'", window_pattern)),
+        },
+        Action::ShowNotification { summary, body } => show_notification(summary, body),
+        Action::Speak { text } => speak_blocking(text),
+        Action::AppAction { name } => send_app_action(name).map(|_| ()),
+        Action::MoveToPoint { point } => Err(format!(
+            "Point '{}' can only be resolved while playing its sequence",
+            point
+        )),
+        Action::Repeat { .. }
+        | Action::If(_)
+        | Action::CallSequence { .. }
+        | Action::Parallel { .. } => {
+            Err("Control flow actions only run as part of sequence playback".to_string())
+        }
+        Action::WaitForWindow(_)
+        | Action::WaitForProcess(_)
+        | Action::WaitForProcessExit(_)
+        | Action::WaitForPixelColor(_) => wait_until(action),
+        Action::WaitForScreenChange(wait) => wait_for_screen_change(wait).map(|_| ()),
+        Action::KillProcess { name, signal } => kill_processes(name, signal.as_deref()).map(|_| ()),
+        Action::AssertScreen(assertion) => assert_screen(assertion),
+        Action::Checkpoint { .. } => Ok(()),
+        Action::SetClipboard { text } => set_clipboard(text),
+        Action::GetClipboard { .. } => {
+            Err("GetClipboard only keeps the text while playing a sequence".to_string())
+        }
+        Action::StoreSet { key, value } => set_shared_text(key, value),
+        Action::StoreGet { .. } => {
+            Err("StoreGet only keeps the value while playing a sequence".to_string())
+        }
+    }
+}
+
+/// Block until the condition of a wait-for action holds or it times out
+fn wait_until(action: &Action) -> Result<(), String> {
+    let Some((condition, timeout)) = action.wait_condition() else {
+        return Ok(());
+    };
+    let started = std::time::Instant::now();
+    while !check_condition(&condition)? {
+        if started.elapsed() >= timeout {
+            return Err(wait_timed_out(&condition, timeout));
+        }
+        std::thread::sleep(WAIT_POLL);
+    }
+    Ok(())
+}
+
+/// Whether a condition of an `If` action holds right now
+pub fn check_condition(condition: &Condition) -> Result<bool, String> {
+    match condition {
+        Condition::WindowExists { pattern } => {
+            find_window_by_pattern(pattern).map(|window| window.is_some())
+        }
+        Condition::ProcessRunning { name } => is_process_running(name),
+        Condition::CommandSucceeds { command } => Ok(run_command(command).is_ok()),
+        Condition::PixelColor(pixel) => {
+            let expected = parse_color(&pixel.color)?;
+            let actual = watch_screen("playback", || pixel_color(pixel.x, pixel.y))?;
+            Ok(colors_match(actual, expected, pixel.tolerance.unwrap_or(0)))
+        }
+        Condition::Not { condition } => check_condition(condition).map(|holds| !holds),
+    }
+}
+
+fn wait_timed_out(condition: &Condition, timeout: Duration) -> String {
+    format!(
+        "Timed out after {} ms waiting for {}",
+        timeout.as_millis(),
+        condition
+    )
+}
+
+/// Check a condition without blocking the playback task
+async fn check_condition_async(condition: &Condition) -> Result<bool, String> {
+    let condition = condition.clone();
+    spawn_blocking_bounded(move || check_condition(&condition))
+        .await
+        .unwrap_or_else(|e| Err(format!("Condition panicked: {}", e)))
+}
+
+/// Play the steps the player hands out, in a background task
+///
+/// Each step waits its recorded `delay_ms` first, scaled by `options`.
+/// Pausing the player holds the task before the next step (delays and
+/// `Wait` steps are suspended too), stopping it ends the task once the
+/// current step is done. A failing step stops the player. `Repeat`, `If`
+/// and `CallSequence` steps run their actions in turn, each of which can be
+/// paused and stopped the same way; called sequences come from `lookup`.
+/// `on_progress` is called after every top-level step and once at the end.
+pub fn spawn_playback<F>(
+    player: SharedPlayer,
+    options: PlaybackOptions,
+    lookup: SequenceLookup,
+    on_progress: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: Fn(PlaybackProgress) + Send + 'static,
+{
+    spawn_playback_with(player, options, execute_action, lookup, on_progress)
+}
+
+fn spawn_playback_with<E, F>(
+    player: SharedPlayer,
+    options: PlaybackOptions,
+    execute: E,
+    lookup: SequenceLookup,
+    on_progress: F,
+) -> tokio::task::JoinHandle<()>
+where
+    E: Fn(&Action) -> Result<(), String> + Clone + Send + Sync + 'static,
+    F: Fn(PlaybackProgress) + Send + 'static,
+{
+    tokio::spawn(async move {
+        let (run, scope) = {
+            let player = player.lock().unwrap();
+            let scope = player
+                .sequence()
+                .cloned()
+                .unwrap_or_else(|| ActionSequence::new(String::new(), String::new()));
+            (player.run_id(), scope)
+        };
+        let playback = Playback {
+            player,
+            run,
+            options,
+            execute,
+            lookup,
+            failures: Mutex::new(Vec::new()),
+            variables: Arc::new(Mutex::new(Variables::new())),
+        };
+        let player = &playback.player;
+        let failures = || playback.failures.lock().unwrap().clone();
+        let mut played = 0;
+        let outcome = loop {
+            if !wait_while_playing(player, run, Duration::ZERO).await {
+                break PlaybackProgress::Stopped {
+                    steps: played,
+                    failures: failures(),
+                };
+            }
+            let next = {
+                let mut player = player.lock().unwrap();
+                if player.run_id() != run || player.state() != PlaybackState::Playing {
+                    // Paused or stopped since the check above
+                    continue;
+                }
+                let step = player.next_action().cloned();
+                let (current, total) = player.get_progress();
+                step.map(|step| (step, current.saturating_sub(1), total))
+            };
+            let Some((step, index, total)) = next else {
+                break PlaybackProgress::Finished {
+                    steps: played,
+                    failures: failures(),
+                };
+            };
+
+            let delay = options.delay(step.delay_ms);
+            let mut result = playback.wait(delay).await;
+            let started = std::time::Instant::now();
+            if result.is_ok() {
+                result = playback.perform_step(&step, index, &scope, 0).await;
+            }
+            match result {
+                Ok(()) => {}
+                Err(Interrupted::Stopped) => {
+                    break PlaybackProgress::Stopped {
+                        steps: played,
+                        failures: failures(),
+                    };
+                }
+                Err(Interrupted::Failed(error)) => {
+                    let mut player = player.lock().unwrap();
+                    if player.run_id() == run {
+                        player.stop_playback();
+                    }
+                    break PlaybackProgress::Failed {
+                        index,
+                        error,
+                        failures: failures(),
+                    };
+                }
+            }
+
+            played += 1;
+            on_progress(PlaybackProgress::Step {
+                index,
+                total,
+                label: step.label,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        };
+        on_progress(outcome);
+    })
+}
+
+/// Perform one step of `scope` on its own, as the step debugger does
+///
+/// `player` must be playing; stopping it interrupts waits within the step.
+/// The step's error policy applies, and the failures a `continue` policy
+/// went past are returned. The step's recorded delay is not waited.
+/// `variables` carries `GetClipboard` results from one step to the next.
+pub async fn perform_single_step(
+    player: SharedPlayer,
+    step: &ActionWithTimestamp,
+    index: usize,
+    scope: &ActionSequence,
+    options: PlaybackOptions,
+    lookup: SequenceLookup,
+    variables: Arc<Mutex<Variables>>,
+) -> Result<Vec<StepFailure>, String> {
+    let run = player.lock().unwrap().run_id();
+    let playback = Playback {
+        player,
+        run,
+        options,
+        execute: execute_action,
+        lookup,
+        failures: Mutex::new(Vec::new()),
+        variables,
+    };
+    match playback.perform_step(step, index, scope, 0).await {
+        Ok(()) => Ok(playback.failures.into_inner().unwrap()),
+        Err(Interrupted::Stopped) => Err("Stopped".to_string()),
+        Err(Interrupted::Failed(error)) => Err(error),
+    }
+}
+
+/// `text` as a single word for `sh`, in single quotes
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Why an action did not complete
+enum Interrupted {
+    Stopped,
+    Failed(String),
+}
+
+/// An action being performed
+type Performing<'a> = Pin<Box<dyn Future<Output = Result<(), Interrupted>> + Send + 'a>>;
+
+/// One playback run, shared by the actions it performs
+struct Playback<E> {
+    player: SharedPlayer,
+    run: u64,
+    options: PlaybackOptions,
+    execute: E,
+    lookup: SequenceLookup,
+    /// Steps skipped by a `continue` policy so far
+    failures: Mutex<Vec<StepFailure>>,
+    /// Set by `GetClipboard`, used as `${name}` by later steps
+    variables: Arc<Mutex<Variables>>,
+}
+
+impl<E> Playback<E>
+where
+    E: Fn(&Action) -> Result<(), String> + Clone + Send + Sync + 'static,
+{
+    /// Wait while playing; a cancelled `Parallel` branch stops here too
+    async fn wait(&self, duration: Duration) -> Result<(), Interrupted> {
+        let mut remaining = duration;
+        loop {
+            let slice = remaining.min(CONTROL_TICK);
+            if branch_cancelled() || !wait_while_playing(&self.player, self.run, slice).await {
+                return Err(Interrupted::Stopped);
+            }
+            remaining -= slice;
+            if remaining.is_zero() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Poll a wait-for condition; time spent paused does not count toward `timeout`
+    async fn wait_for(&self, condition: &Condition, timeout: Duration) -> Result<(), Interrupted> {
+        let mut waited = Duration::ZERO;
+        while !check_condition_async(condition)
+            .await
+            .map_err(Interrupted::Failed)?
+        {
+            if waited >= timeout {
+                return Err(Interrupted::Failed(wait_timed_out(condition, timeout)));
+            }
+            self.wait(WAIT_POLL).await?;
+            waited += WAIT_POLL;
+        }
+        Ok(())
+    }
+
+    /// Perform a step of `scope`, applying its error policy
+    async fn perform_step(
+        &self,
+        step: &ActionWithTimestamp,
+        index: usize,
+        scope: &ActionSequence,
+        depth: usize,
+    ) -> Result<(), Interrupted> {
+        let policy = step
+            .on_error
+            .as_ref()
+            .or(scope.on_error.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        let mut retries = 0;
+        loop {
+            let error = match self.perform(&step.action, scope, depth).await {
+                Err(Interrupted::Failed(error)) => error,
+                result => return result,
+            };
+            match policy {
+                ErrorPolicy::Retry {
+                    attempts,
+                    backoff_ms,
+                } if retries < attempts => {
+                    let backoff = Duration::from_millis(backoff_ms)
+                        .saturating_mul(2u32.saturating_pow(retries));
+                    self.wait(backoff).await?;
+                    retries += 1;
+                }
+                ErrorPolicy::Continue => {
+                    self.failures.lock().unwrap().push(StepFailure {
+                        sequence: scope.name.clone(),
+                        index,
+                        label: step.label.clone(),
+                        error,
+                    });
+                    return Ok(());
+                }
+                _ => return Err(Interrupted::Failed(error)),
+            }
+        }
+    }
+
+    /// The action with `${name}` replaced by the variables set so far
+    ///
+    /// In a `RunCommand` each value is one shell word, quoted, so whatever
+    /// was on the clipboard cannot run commands of its own.
+    fn fill_variables(&self, action: Action) -> Result<Action, String> {
+        let variables = self.variables.lock().unwrap();
+        if variables.is_empty() {
+            return Ok(action);
+        }
+        let in_shell = matches!(action, Action::RunCommand { .. });
+        // Values are escaped for JSON, so the text can be substituted in place
+        let escaped: Variables = variables
+            .iter()
+            .map(|(name, value)| {
+                let value = match in_shell {
+                    true => shell_quote(value),
+                    false => value.clone(),
+                };
+                let json = Value::String(value).to_string();
+                (name.clone(), json[1..json.len() - 1].to_string())
+            })
+            .collect();
+        let json = serde_json::to_string(&action).map_err(|e| e.to_string())?;
+        serde_json::from_str(&substitute_bound(&json, &escaped)).map_err(|e| e.to_string())
+    }
+
+    /// Perform an action; `scope` is the sequence whose points it refers to
+    fn perform<'a>(
+        &'a self,
+        action: &'a Action,
+        scope: &'a ActionSequence,
+        depth: usize,
+    ) -> Performing<'a> {
+        Box::pin(async move {
+            let action = scope.resolve_action(action).map_err(Interrupted::Failed)?;
+            if let Some((condition, timeout)) = action.wait_condition() {
+                return self.wait_for(&condition, timeout).await;
+            }
+            match action {
+                // Waits are done here so they can be paused and stopped
+                Action::Wait { milliseconds } => {
+                    self.wait(Duration::from_millis(milliseconds)).await
+                }
+                Action::Repeat { count, actions } => {
+                    for _ in 0..count {
+                        for action in &actions {
+                            self.wait(Duration::ZERO).await?;
+                            self.perform(action, scope, depth).await?;
+                        }
+                    }
+                    Ok(())
+                }
+                Action::WithModifiers { modifiers, actions } => {
+                    let mut held = HeldKeys {
+                        keys: Vec::new(),
+                        execute: self.execute.clone(),
+                    };
+                    let mut result = Ok(());
+                    for key in modifiers {
+                        result = self
+                            .perform(&Action::KeyDown { key: key.clone() }, scope, depth)
+                            .await;
+                        if result.is_err() {
+                            break;
+                        }
+                        held.keys.push(key);
+                    }
+                    for action in &actions {
+                        if result.is_err() {
+                            break;
+                        }
+                        result = match self.wait(Duration::ZERO).await {
+                            Ok(()) => self.perform(action, scope, depth).await,
+                            Err(e) => Err(e),
+                        };
+                    }
+                    // Released also when a step failed or playback was stopped
+                    while let Some(key) = held.keys.pop() {
+                        let _ = self.perform(&Action::KeyUp { key }, scope, depth).await;
+                    }
+                    result
+                }
+                Action::If(branch) => {
+                    let holds = check_condition_async(&branch.condition)
+                        .await
+                        .map_err(Interrupted::Failed)?;
+                    let actions = if holds {
+                        &branch.then
+                    } else {
+                        &branch.otherwise
+                    };
+                    for action in actions {
+                        self.wait(Duration::ZERO).await?;
+                        self.perform(action, scope, depth).await?;
+                    }
+                    Ok(())
+                }
+                Action::Parallel { branches } => {
+                    let branches = branches.iter().map(|actions| {
+                        Box::pin(async move {
+                            for action in actions {
+                                self.wait(Duration::ZERO).await?;
+                                self.perform(action, scope, depth).await?;
+                            }
+                            Ok(())
+                        }) as Performing<'_>
+                    });
+                    join_branches(branches.collect()).await
+                }
+                Action::CallSequence { name } => {
+                    if depth >= MAX_CALL_DEPTH {
+                        return Err(Interrupted::Failed(format!(
+                            "Sequence calls nest deeper than {} levels at '{}'",
+                            MAX_CALL_DEPTH, name
+                        )));
+                    }
+                    let sequence = (self.lookup)(&name).map_err(Interrupted::Failed)?;
+                    for (index, step) in sequence.actions.iter().enumerate() {
+                        self.wait(self.options.delay(step.delay_ms)).await?;
+                        self.perform_step(step, index, &sequence, depth + 1).await?;
+                    }
+                    Ok(())
+                }
+                Action::GetClipboard { variable } => {
+                    let text = spawn_blocking_bounded(get_clipboard)
+                        .await
+                        .unwrap_or_else(|e| Err(format!("Step panicked: {}", e)))
+                        .map_err(Interrupted::Failed)?;
+                    self.variables.lock().unwrap().insert(variable, text);
+                    Ok(())
+                }
+                Action::StoreGet { key, variable } => {
+                    let value = shared_text(&key).map_err(Interrupted::Failed)?;
+                    self.variables.lock().unwrap().insert(variable, value);
+                    Ok(())
+                }
+                action => {
+                    let action = self.fill_variables(action).map_err(Interrupted::Failed)?;
+                    let execute = self.execute.clone();
+                    spawn_blocking_bounded(move || execute(&action))
+                        .await
+                        .unwrap_or_else(|e| Err(format!("Step panicked: {}", e)))
+                        .map_err(Interrupted::Failed)
+                }
+            }
+        })
+    }
+}
+
+/// Modifiers a `WithModifiers` step holds down
+///
+/// The step releases them itself; should it be dropped halfway, whatever is
+/// still held is released here.
+struct HeldKeys<E: Fn(&Action) -> Result<(), String>> {
+    keys: Vec<String>,
+    execute: E,
+}
+
+impl<E: Fn(&Action) -> Result<(), String>> Drop for HeldKeys<E> {
+    fn drop(&mut self) {
+        while let Some(key) = self.keys.pop() {
+            let _ = (self.execute)(&Action::KeyUp { key });
+        }
+    }
+}
+
+/// Run the branches of a `Parallel` step together until all are done
+///
+/// When one fails, the others stop at their next wait, and are waited for
+/// so that no action they started is left running; the first failure is
+/// returned.
+async fn join_branches(branches: Vec<Performing<'_>>) -> Result<(), Interrupted> {
+    let cancellation = Arc::new(Cancellation {
+        cancelled: AtomicBool::new(false),
+        parent: BRANCH.try_with(Arc::clone).ok(),
+    });
+    let mut branches: Vec<Performing<'_>> = branches
+        .into_iter()
+        .map(|branch| Box::pin(BRANCH.scope(Arc::clone(&cancellation), branch)) as Performing<'_>)
+        .collect();
+    let mut failure = None;
+    std::future::poll_fn(|cx| {
+        let mut index = 0;
+        while index < branches.len() {
+            match branches[index].as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    drop(branches.swap_remove(index));
+                    if let Err(e) = result
+                        && failure.is_none()
+                    {
+                        cancellation.cancelled.store(true, Ordering::SeqCst);
+                        failure = Some(e);
+                    }
+                }
+                Poll::Pending => index += 1,
+            }
+        }
+        if branches.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    failure.map_or(Ok(()), Err)
+}
+
+/// Let `duration` of playing time pass, not counting time spent paused
+///
+/// Returns false as soon as the player stops or starts another run.
+async fn wait_while_playing(player: &SharedPlayer, run: u64, duration: Duration) -> bool {
+    let mut remaining = duration;
+    loop {
+        let state = {
+            let player = player.lock().unwrap();
+            if player.run_id() != run {
+                return false;
+            }
+            player.state()
+        };
+        match state {
+            PlaybackState::Idle => return false,
+            PlaybackState::Paused => tokio::time::sleep(CONTROL_TICK).await,
+            PlaybackState::Playing if remaining.is_zero() => return true,
+            PlaybackState::Playing => {
+                let slice = remaining.min(CONTROL_TICK);
+                tokio::time::sleep(slice).await;
+                remaining -= slice;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::{Conditional, ProcessWait};
+
+    fn player_with(actions: Vec<Action>) -> SharedPlayer {
+        let mut sequence = ActionSequence::new("test".into(), String::new());
+        for action in actions {
+            sequence.add_step(ActionWithTimestamp::new(action, 1));
+        }
+        let mut player = ActionPlayer::new();
+        player.load_sequence(sequence);
+        player.start_playback().unwrap();
+        Arc::new(Mutex::new(player))
+    }
+
+    fn collect_progress(
+        player: SharedPlayer,
+        execute: fn(&Action) -> Result<(), String>,
+    ) -> Vec<PlaybackProgress> {
+        let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence {}", name)));
+        collect_progress_with(player, execute, lookup)
+    }
+
+    fn collect_progress_with<E>(
+        player: SharedPlayer,
+        execute: E,
+        lookup: SequenceLookup,
+    ) -> Vec<PlaybackProgress>
+    where
+        E: Fn(&Action) -> Result<(), String> + Clone + Send + Sync + 'static,
+    {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&progress);
+        let _guard = runtime.enter();
+        let task = spawn_playback_with(
+            player,
+            PlaybackOptions::default(),
+            execute,
+            lookup,
+            move |p| sink.lock().unwrap().push(p),
+        );
+        runtime.block_on(task).unwrap();
+        Arc::try_unwrap(progress).unwrap().into_inner().unwrap()
+    }
+
+    fn press(key: &str) -> Action {
+        Action::PressKey { key: key.into() }
+    }
+
+    #[test]
+    fn test_plays_every_step() {
+        let player = player_with(vec![
+            Action::Wait { milliseconds: 1 },
+            Action::Wait { milliseconds: 1 },
+        ]);
+        let progress = collect_progress(Arc::clone(&player), |_| Ok(()));
+
+        assert_eq!(progress.len(), 3);
+        assert!(matches!(
+            progress[1],
+            PlaybackProgress::Step {
+                index: 1,
+                total: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            progress[2],
+            PlaybackProgress::Finished { steps: 2, .. }
+        ));
+        assert!(!player.lock().unwrap().is_playing());
+    }
+
+    #[test]
+    fn test_failed_step_stops_playback() {
+        let player = player_with(vec![
+            Action::PressKey { key: "a".into() },
+            Action::Wait { milliseconds: 1 },
+        ]);
+        let progress = collect_progress(Arc::clone(&player), |action| match action {
+            Action::PressKey { .. } => Err("no input device".into()),
+            _ => Ok(()),
+        });
+
+        assert_eq!(progress.len(), 1);
+        assert!(matches!(
+            progress[0],
+            PlaybackProgress::Failed { index: 0, .. }
+        ));
+        assert!(!player.lock().unwrap().is_playing());
+    }
+
+    #[test]
+    fn test_stop_interrupts_wait() {
+        let player = player_with(vec![
+            Action::Wait { milliseconds: 1 },
+            Action::Wait {
+                milliseconds: 60_000,
+            },
+        ]);
+        let stopper = Arc::clone(&player);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            stopper.lock().unwrap().pause_playback().unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            stopper.lock().unwrap().stop_playback();
+        });
+
+        let started = std::time::Instant::now();
+        let progress = collect_progress(player, |_| Ok(()));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Stopped { steps: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parallel_branches_run_together() {
+        let wait = |milliseconds| Action::Wait { milliseconds };
+        let player = player_with(vec![Action::Parallel {
+            branches: vec![vec![wait(300)], vec![wait(300), press("a")]],
+        }]);
+        let started = std::time::Instant::now();
+        let progress = collect_progress(player, |_| Ok(()));
+        assert!(started.elapsed() < Duration::from_millis(550));
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Finished { steps: 1, .. })
+        ));
+
+        let player = player_with(vec![Action::Parallel {
+            branches: vec![vec![wait(60_000)], vec![press("a")]],
+        }]);
+        let progress = collect_progress(player, |_| Err("no input device".into()));
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Failed { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parallel_waits_for_failed_siblings() {
+        let player = player_with(vec![Action::Parallel {
+            branches: vec![vec![press("a")], vec![press("b"), press("c")]],
+        }]);
+        let performed = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&performed);
+        let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence {}", name)));
+        let execute = move |action: &Action| match action {
+            Action::PressKey { key } if key == "a" => Err("no input device".into()),
+            Action::PressKey { key } => {
+                std::thread::sleep(Duration::from_millis(200));
+                log.lock().unwrap().push(key.clone());
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        let progress = collect_progress_with(player, execute, lookup);
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Failed { index: 0, .. })
+        ));
+        // "b" was under way and finished; "c" never started
+        assert_eq!(*performed.lock().unwrap(), ["b"]);
+    }
+
+    #[test]
+    fn test_modifiers_released_after_failure() {
+        let player = player_with(vec![Action::WithModifiers {
+            modifiers: vec!["ctrl".into(), "shift".into()],
+            actions: vec![press("a"), press("b")],
+        }]);
+        let performed = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&performed);
+        let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence {}", name)));
+        let execute = move |action: &Action| {
+            log.lock().unwrap().push(action.clone());
+            match action {
+                Action::PressKey { .. } => Err("no input device".into()),
+                _ => Ok(()),
+            }
+        };
+        let progress = collect_progress_with(player, execute, lookup);
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Failed { index: 0, .. })
+        ));
+        let keys: Vec<String> = performed
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|action| match action {
+                Action::KeyDown { key } => format!("+{}", key),
+                Action::KeyUp { key } => format!("-{}", key),
+                Action::PressKey { key } => key.clone(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(keys, ["+ctrl", "+shift", "a", "-shift", "-ctrl"]);
+    }
+
+    #[test]
+    fn test_variables_fill_later_steps() {
+        let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence {}", name)));
+        let variables = Variables::from([("copied".to_string(), "say \"hi\"".to_string())]);
+        let playback = Playback {
+            player: player_with(Vec::new()),
+            run: 0,
+            options: PlaybackOptions::default(),
+            execute: |_: &Action| Ok(()),
+            lookup,
+            failures: Mutex::new(Vec::new()),
+            variables: Arc::new(Mutex::new(variables)),
+        };
+        let filled = playback
+            .fill_variables(Action::RunCommand {
+                command: "echo ${copied} > ${HOME}/out".into(),
+            })
+            .unwrap();
+        assert!(matches!(
+            filled,
+            Action::RunCommand { command } if command == "echo 'say \"hi\"' > ${HOME}/out"
+        ));
+
+        playback
+            .variables
+            .lock()
+            .unwrap()
+            .insert("copied".to_string(), "x'; rm -rf ~; '".to_string());
+        let filled = playback
+            .fill_variables(Action::RunCommand {
+                command: "echo ${copied}".into(),
+            })
+            .unwrap();
+        assert!(matches!(
+            filled,
+            Action::RunCommand { command } if command == r#"echo 'x'\''; rm -rf ~; '\'''"#
+        ));
+        let filled = playback
+            .fill_variables(
+                serde_json::from_str(r#"{"type":"TypeText","text":"${copied}"}"#).unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(filled, Action::TypeText { text, .. } if text == "x'; rm -rf ~; '"));
+    }
+
+    #[test]
+    fn test_delay_scaling() {
+        assert_eq!(
+            PlaybackOptions::new(2.0, false).unwrap().delay(1000),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            PlaybackOptions::new(0.5, true).unwrap().delay(1000),
+            Duration::ZERO
+        );
+        assert!(PlaybackOptions::new(0.0, false).is_err());
+        assert!(PlaybackOptions::new(f64::NAN, false).is_err());
+        assert_eq!(
+            PlaybackOptions::new(f64::MIN_POSITIVE, false)
+                .unwrap()
+                .delay(u64::MAX),
+            Duration::from_millis(u64::MAX).div_f64(MIN_SPEED)
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let player = player_with(vec![
+            Action::Repeat {
+                count: 2,
+                actions: vec![press("a")],
+            },
+            Action::If(Conditional {
+                condition: Condition::CommandSucceeds {
+                    command: "true".into(),
+                },
+                then: vec![Action::CallSequence {
+                    name: "inner".into(),
+                }],
+                otherwise: vec![press("x")],
+            }),
+            Action::CallSequence {
+                name: "looping".into(),
+            },
+        ]);
+        let lookup: SequenceLookup = Arc::new(|name| {
+            let mut sequence = ActionSequence::new(name.into(), String::new());
+            match name {
+                "inner" => sequence.add_action(press("b"), 0),
+                "looping" => sequence.add_action(
+                    Action::CallSequence {
+                        name: "looping".into(),
+                    },
+                    0,
+                ),
+                _ => return Err(format!("No sequence {}", name)),
+            }
+            Ok(sequence)
+        });
+        let pressed = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&pressed);
+        let execute = move |action: &Action| {
+            if let Action::PressKey { key } = action {
+                sink.lock().unwrap().push(key.clone());
+            }
+            Ok(())
+        };
+
+        let progress = collect_progress_with(player, execute, lookup);
+        assert_eq!(*pressed.lock().unwrap(), vec!["a", "a", "b"]);
+        // A sequence calling itself fails instead of recursing forever
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Failed { index: 2, error, .. }) if error.contains("nest")
+        ));
+    }
+
+    #[test]
+    fn test_wait_for_times_out() {
+        let player = player_with(vec![
+            Action::WaitForProcess(ProcessWait {
+                name: "casper-no-such-process".into(),
+                timeout_ms: Some(300),
+            }),
+            press("a"),
+        ]);
+        let progress = collect_progress(player, |_| Ok(()));
+        assert!(matches!(
+            progress.last(),
+            Some(PlaybackProgress::Failed { index: 0, error, .. })
+                if error.contains("Timed out after 300 ms waiting for process")
+        ));
+    }
+
+    #[test]
+    fn test_error_policies() {
+        let mut sequence = ActionSequence::new("test".into(), String::new());
+        sequence.on_error = Some(ErrorPolicy::Continue);
+        sequence.add_action(press("flaky"), 0);
+        sequence.add_action(press("broken"), 0);
+        sequence.add_action(press("a"), 0);
+        sequence.actions[0].on_error = Some(ErrorPolicy::Retry {
+            attempts: 2,
+            backoff_ms: 1,
+        });
+        let mut player = ActionPlayer::new();
+        player.load_sequence(sequence);
+        player.start_playback().unwrap();
+
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&attempts);
+        let execute = move |action: &Action| {
+            let Action::PressKey { key } = action else {
+                return Ok(());
+            };
+            let mut attempts = sink.lock().unwrap();
+            attempts.push(key.clone());
+            let flaky_tries = attempts.iter().filter(|k| *k == "flaky").count();
+            match key.as_str() {
+                "flaky" if flaky_tries < 3 => Err("not yet".to_string()),
+                "broken" => Err("broken".to_string()),
+                _ => Ok(()),
+            }
+        };
+        let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence {}", name)));
+
+        let progress = collect_progress_with(Arc::new(Mutex::new(player)), execute, lookup);
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec!["flaky", "flaky", "flaky", "broken", "a"]
+        );
+        let Some(PlaybackProgress::Finished { steps, failures }) = progress.last() else {
+            panic!("playback did not finish: {:?}", progress);
+        };
+        assert_eq!(*steps, 3);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(
+            (failures[0].index, failures[0].error.as_str()),
+            (1, "broken")
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::ai_vision::AIConfig;
+use crate::capture::ScreenCapture;
+use crate::portal;
+use crate::setup::find_in_path;
+use serde::Serialize;
+
+/// Optional parts of casper, each a cargo feature of casper-core, and
+/// whether this build has it
+pub const FEATURES: &[(&str, bool)] = &[
+    ("ai", cfg!(feature = "ai")),
+    ("http", cfg!(feature = "http")),
+    ("voice", cfg!(feature = "voice")),
+    ("tts", cfg!(feature = "tts")),
+    ("capture", cfg!(feature = "capture")),
+    ("wayland", cfg!(feature = "wayland")),
+    ("x11", cfg!(feature = "x11")),
+    ("encryption", cfg!(feature = "encryption")),
+];
+
+/// Whether an optional part of casper was compiled into this build
+pub fn compiled(name: &str) -> bool {
+    FEATURES.iter().any(|&(feature, on)| feature == name && on)
+}
+
+/// Error for something that needs a feature this build was made without
+pub fn missing(name: &str) -> String {
+    format!(
+        "This build of casper has no {} support (rebuild with the '{}' feature)",
+        name, name
+    )
+}
+
+/// An optional part of casper and whether it can be used right now
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureStatus {
+    pub name: &'static str,
+    pub compiled: bool,
+    pub available: bool,
+    /// Why it is not available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// What every optional part needs at runtime, checked now
+///
+/// A feature is available when it was compiled in and what it relies on is
+/// there: an AI endpoint, a speech or screenshot program, a display.
+pub fn feature_status() -> Vec<FeatureStatus> {
+    FEATURES
+        .iter()
+        .map(|&(name, compiled)| {
+            let check = if compiled {
+                runtime_check(name)
+            } else {
+                Err(missing(name))
+            };
+            FeatureStatus {
+                name,
+                compiled,
+                available: check.is_ok(),
+                reason: check.err(),
+            }
+        })
+        .collect()
+}
+
+fn runtime_check(name: &str) -> Result<(), String> {
+    match name {
+        "ai" => AIConfig::from_env().map(|_| ()),
+        "voice" => Err("Voice recognition is not implemented yet".to_string()),
+        "tts" => find_in_path("espeak-ng")
+            .map(|_| ())
+            .ok_or_else(|| "espeak-ng is not installed".to_string()),
+        "capture" => ScreenCapture::new().map(|_| ()),
+        "wayland" => {
+            if std::env::var("WAYLAND_DISPLAY").is_ok() || portal::portal_only() {
+                Ok(())
+            } else {
+                Err("Not in a Wayland session".to_string())
+            }
+        }
+        "x11" => {
+            if std::env::var("DISPLAY").is_ok() {
+                Ok(())
+            } else {
+                Err("No X11 display".to_string())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_features_are_reported() {
+        for status in feature_status() {
+            assert_eq!(status.compiled, compiled(status.name));
+            assert!(status.available || status.reason.is_some());
+            if !status.compiled {
+                assert_eq!(status.reason, Some(missing(status.name)));
+            }
+        }
+        assert!(!compiled("teleport"));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::screen::display_size;
+use evdev::uinput::VirtualDevice;
+use evdev::{
+    AbsInfo, AbsoluteAxisCode, AttributeSet, EventType, InputEvent, KeyCode, PropType,
+    UinputAbsSetup,
+};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Pause between two positions of the fingers while they move
+const FRAME: Duration = Duration::from_millis(16);
+
+/// How long a new touchscreen takes to be picked up by the desktop
+const DEVICE_SETTLE: Duration = Duration::from_millis(500);
+
+/// Distance between the fingers of a multi-finger swipe or tap
+pub const FINGER_SPACING: i32 = 60;
+
+/// Most fingers the touchscreen tracks at once
+const MAX_FINGERS: usize = 5;
+
+/// The virtual touchscreen, created on first use and kept for the next gestures
+static TOUCHSCREEN: Mutex<Option<Touchscreen>> = Mutex::new(None);
+
+/// Tracking IDs tell the desktop a new touch from one that moved
+static TRACKING_ID: AtomicI32 = AtomicI32::new(1);
+
+/// Where one finger touches down and where it lifts off
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stroke {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+}
+
+impl Stroke {
+    /// Position after `step` of `steps`
+    fn at(&self, step: u64, steps: u64) -> (i32, i32) {
+        // Wide enough for any distance times any step count
+        let along = |from: i32, to: i32| {
+            let (from, to) = (from as i128, to as i128);
+            (from + (to - from) * step as i128 / steps as i128) as i32
+        };
+        (along(self.from.0, self.to.0), along(self.from.1, self.to.1))
+    }
+
+    /// Both ends lie within `width` x `height`
+    fn fits(&self, width: i32, height: i32) -> bool {
+        [self.from, self.to]
+            .iter()
+            .all(|&(x, y)| (0..width).contains(&x) && (0..height).contains(&y))
+    }
+}
+
+/// `fingers` side by side moving from `from` to `to`, the first one on the
+/// given points
+pub fn swipe_strokes(from: (i32, i32), to: (i32, i32), fingers: u32) -> Vec<Stroke> {
+    (0..fingers as i32)
+        .map(|finger| Stroke {
+            from: (from.0.saturating_add(finger * FINGER_SPACING), from.1),
+            to: (to.0.saturating_add(finger * FINGER_SPACING), to.1),
+        })
+        .collect()
+}
+
+/// Two fingers on a horizontal line through `center`, `from_distance` apart
+/// at the start and `to_distance` at the end: spreading them zooms in
+pub fn pinch_strokes(center: (i32, i32), from_distance: i32, to_distance: i32) -> Vec<Stroke> {
+    [-1, 1]
+        .into_iter()
+        .map(|side| Stroke {
+            from: (center.0.saturating_add(from_distance / 2 * side), center.1),
+            to: (center.0.saturating_add(to_distance / 2 * side), center.1),
+        })
+        .collect()
+}
+
+/// Touch the screen with a finger for every stroke, move them all along
+/// their strokes over `duration_ms` and lift them
+///
+/// Coordinates are on the main monitor, and every stroke has to stay on it.
+/// The touches come from a virtual
+/// touchscreen created through /dev/uinput, which the user needs write
+/// access to (usually a udev rule giving it to the `input` group).
+pub fn perform(strokes: &[Stroke], duration_ms: u64) -> Result<(), String> {
+    if strokes.is_empty() || strokes.len() > MAX_FINGERS {
+        return Err(format!("A gesture takes 1 to {} fingers", MAX_FINGERS));
+    }
+    // The touchscreen is left in a usable state even by a gesture that panicked
+    let mut touchscreen = TOUCHSCREEN.lock().unwrap_or_else(PoisonError::into_inner);
+    if touchscreen.is_none() {
+        *touchscreen = Some(Touchscreen::create()?);
+        std::thread::sleep(DEVICE_SETTLE);
+    }
+    let touchscreen = touchscreen.as_mut().unwrap();
+    let (width, height) = touchscreen.size;
+    if !strokes.iter().all(|stroke| stroke.fits(width, height)) {
+        return Err(format!(
+            "Touches have to be on the main monitor, within {}x{}",
+            width, height
+        ));
+    }
+
+    let steps = (duration_ms / FRAME.as_millis() as u64).max(1);
+    let pause = Duration::from_millis(duration_ms / steps);
+    let ids: Vec<i32> = strokes
+        .iter()
+        .map(|_| TRACKING_ID.fetch_add(1, Ordering::Relaxed) & 0xffff)
+        .collect();
+    let mut result = touchscreen.emit(&touch_events(strokes, &ids, 0, steps));
+    for step in 1..=steps {
+        if result.is_err() {
+            break;
+        }
+        std::thread::sleep(pause);
+        result = touchscreen.emit(&touch_events(strokes, &[], step, steps));
+    }
+    // The fingers are lifted whatever happened, or the desktop keeps them down
+    let lifted = touchscreen.emit(&lift_events(strokes.len()));
+    result.and(lifted)
+}
+
+/// Tap with `fingers` fingers at `x`/`y`
+pub fn tap(x: i32, y: i32, fingers: u32) -> Result<(), String> {
+    perform(&swipe_strokes((x, y), (x, y), fingers), 50)
+}
+
+/// Events placing the fingers `step` of `steps` along their strokes; with
+/// tracking `ids` they are new touches
+fn touch_events(strokes: &[Stroke], ids: &[i32], step: u64, steps: u64) -> Vec<InputEvent> {
+    let mut events = Vec::new();
+    for (slot, stroke) in strokes.iter().enumerate() {
+        let (x, y) = stroke.at(step, steps);
+        events.push(abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32));
+        if let Some(id) = ids.get(slot) {
+            events.push(abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, *id));
+        }
+        events.push(abs(AbsoluteAxisCode::ABS_MT_POSITION_X, x));
+        events.push(abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, y));
+    }
+    if !ids.is_empty() {
+        events.push(key(KeyCode::BTN_TOUCH, 1));
+        events.push(key(finger_tool(strokes.len()), 1));
+    }
+    // Single-touch axes follow the first finger for older clients
+    let (x, y) = strokes[0].at(step, steps);
+    events.push(abs(AbsoluteAxisCode::ABS_X, x));
+    events.push(abs(AbsoluteAxisCode::ABS_Y, y));
+    events
+}
+
+fn lift_events(fingers: usize) -> Vec<InputEvent> {
+    let mut events = Vec::new();
+    for slot in 0..fingers {
+        events.push(abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32));
+        events.push(abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1));
+    }
+    events.push(key(KeyCode::BTN_TOUCH, 0));
+    events.push(key(finger_tool(fingers), 0));
+    events
+}
+
+fn abs(code: AbsoluteAxisCode, value: i32) -> InputEvent {
+    InputEvent::new(EventType::ABSOLUTE.0, code.0, value)
+}
+
+fn key(code: KeyCode, value: i32) -> InputEvent {
+    InputEvent::new(EventType::KEY.0, code.code(), value)
+}
+
+/// The key telling how many fingers touch the screen
+fn finger_tool(fingers: usize) -> KeyCode {
+    match fingers {
+        1 => KeyCode::BTN_TOOL_FINGER,
+        2 => KeyCode::BTN_TOOL_DOUBLETAP,
+        3 => KeyCode::BTN_TOOL_TRIPLETAP,
+        _ => KeyCode::BTN_TOOL_QUADTAP,
+    }
+}
+
+struct Touchscreen {
+    device: VirtualDevice,
+    /// Width and height of the monitor it covers
+    size: (i32, i32),
+}
+
+impl Touchscreen {
+    /// A multi-touch screen as large as the main monitor
+    fn create() -> Result<Self, String> {
+        let (width, height) = display_size()?;
+        let failed = |e: std::io::Error| {
+            format!(
+                "Failed to create a virtual touchscreen: {} (is /dev/uinput writable?)",
+                e
+            )
+        };
+        let axis = |code, maximum| UinputAbsSetup::new(code, AbsInfo::new(0, 0, maximum, 0, 0, 0));
+        let mut keys = AttributeSet::<KeyCode>::new();
+        for key in [
+            KeyCode::BTN_TOUCH,
+            KeyCode::BTN_TOOL_FINGER,
+            KeyCode::BTN_TOOL_DOUBLETAP,
+            KeyCode::BTN_TOOL_TRIPLETAP,
+            KeyCode::BTN_TOOL_QUADTAP,
+        ] {
+            keys.insert(key);
+        }
+        let mut properties = AttributeSet::<PropType>::new();
+        properties.insert(PropType::DIRECT);
+        let device = VirtualDevice::builder()
+            .map_err(failed)?
+            .name("casper touchscreen")
+            .with_properties(&properties)
+            .map_err(failed)?
+            .with_keys(&keys)
+            .map_err(failed)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_X, width - 1))
+            .map_err(failed)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_Y, height - 1))
+            .map_err(failed)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_SLOT, MAX_FINGERS as i32 - 1))
+            .map_err(failed)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_TRACKING_ID, 0xffff))
+            .map_err(failed)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_X, width - 1))
+            .map_err(failed)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_Y, height - 1))
+            .map_err(failed)?
+            .build()
+            .map_err(failed)?;
+        Ok(Touchscreen {
+            device,
+            size: (width, height),
+        })
+    }
+
+    fn emit(&mut self, events: &[InputEvent]) -> Result<(), String> {
+        self.device
+            .emit(events)
+            .map_err(|e| format!("Failed to send touch events: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gesture_strokes() {
+        let strokes = swipe_strokes((100, 500), (100, 100), 2);
+        assert_eq!(strokes[1].from, (100 + FINGER_SPACING, 500));
+        assert_eq!(strokes[0].at(1, 2), (100, 300));
+
+        // Spreading two fingers apart around the center
+        let strokes = pinch_strokes((400, 300), 100, 300);
+        assert_eq!((strokes[0].from, strokes[1].from), ((350, 300), (450, 300)));
+        assert_eq!((strokes[0].to, strokes[1].to), ((250, 300), (550, 300)));
+
+        let events = touch_events(&strokes, &[7, 8], 0, 4);
+        assert!(
+            events
+                .iter()
+                .any(|e| e.code() == KeyCode::BTN_TOOL_DOUBLETAP.code())
+        );
+        assert_eq!(lift_events(2).len(), 6);
+
+        // Far apart ends neither overflow nor fit on a screen
+        let stroke = Stroke {
+            from: (i32::MIN, 0),
+            to: (i32::MAX, 0),
+        };
+        assert_eq!(stroke.at(1, 2), (-1, 0));
+        assert_eq!(stroke.at(u64::MAX, u64::MAX), (i32::MAX, 0));
+        assert!(!stroke.fits(1920, 1080));
+        assert!(strokes[0].fits(1920, 1080));
+        assert_eq!(swipe_strokes((i32::MAX, 0), (0, 0), 3)[2].from.0, i32::MAX);
+    }
+}
@@ -0,0 +1,394 @@
+use crate::screen::parse_key_combo;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+#[cfg(feature = "wayland")]
+use {
+    crate::portal::{
+        PORTAL_DESTINATION, PORTAL_PATH, portal_request, request_token, session_handle,
+    },
+    futures_util::StreamExt,
+    std::collections::HashMap,
+    zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+};
+#[cfg(feature = "x11")]
+use {
+    std::time::Duration,
+    x11rb::connection::Connection,
+    x11rb::protocol::Event,
+    x11rb::protocol::xproto::{ConnectionExt, GrabMode, Keycode, ModMask},
+};
+
+/// How often the X11 listener checks for key presses and for being stopped
+#[cfg(feature = "x11")]
+const X11_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[cfg(feature = "wayland")]
+const SHORTCUTS_INTERFACE: &str = "org.freedesktop.portal.GlobalShortcuts";
+
+/// Named keys: the names accepted by `press_key`, the X11 keysym and the XKB name
+pub(crate) const NAMED_KEYS: &[(&[&str], u32, &str)] = &[
+    (&["return", "enter"], 0xff0d, "Return"),
+    (&["escape", "esc"], 0xff1b, "Escape"),
+    (&["backspace"], 0xff08, "BackSpace"),
+    (&["tab"], 0xff09, "Tab"),
+    (&["space"], 0x20, "space"),
+    (&["delete", "del"], 0xffff, "Delete"),
+    (&["insert"], 0xff63, "Insert"),
+    (&["home"], 0xff50, "Home"),
+    (&["end"], 0xff57, "End"),
+    (&["pageup"], 0xff55, "Page_Up"),
+    (&["pagedown"], 0xff56, "Page_Down"),
+    (&["left", "leftarrow"], 0xff51, "Left"),
+    (&["up", "uparrow"], 0xff52, "Up"),
+    (&["right", "rightarrow"], 0xff53, "Right"),
+    (&["down", "downarrow"], 0xff54, "Down"),
+    (&["print", "printscreen"], 0xff61, "Print"),
+    (&["menu"], 0xff67, "Menu"),
+    (&["pause"], 0xff13, "Pause"),
+    (&["capslock"], 0xffe5, "Caps_Lock"),
+    (&["numlock"], 0xff7f, "Num_Lock"),
+    (&["scrolllock"], 0xff14, "Scroll_Lock"),
+    (&["kp0"], 0xffb0, "KP_0"),
+    (&["kp1"], 0xffb1, "KP_1"),
+    (&["kp2"], 0xffb2, "KP_2"),
+    (&["kp3"], 0xffb3, "KP_3"),
+    (&["kp4"], 0xffb4, "KP_4"),
+    (&["kp5"], 0xffb5, "KP_5"),
+    (&["kp6"], 0xffb6, "KP_6"),
+    (&["kp7"], 0xffb7, "KP_7"),
+    (&["kp8"], 0xffb8, "KP_8"),
+    (&["kp9"], 0xffb9, "KP_9"),
+    (&["kpenter"], 0xff8d, "KP_Enter"),
+    (&["kpadd", "kpplus"], 0xffab, "KP_Add"),
+    (&["kpsubtract", "kpminus"], 0xffad, "KP_Subtract"),
+    (&["kpmultiply"], 0xffaa, "KP_Multiply"),
+    (&["kpdivide"], 0xffaf, "KP_Divide"),
+    (&["kpdecimal"], 0xffae, "KP_Decimal"),
+    (&["volumeup"], 0x1008ff13, "XF86AudioRaiseVolume"),
+    (&["volumedown"], 0x1008ff11, "XF86AudioLowerVolume"),
+    (&["volumemute", "mute"], 0x1008ff12, "XF86AudioMute"),
+    (&["micmute"], 0x1008ffb2, "XF86AudioMicMute"),
+    (&["playpause", "mediaplay"], 0x1008ff14, "XF86AudioPlay"),
+    (&["mediastop"], 0x1008ff15, "XF86AudioStop"),
+    (&["mediaprev", "prevtrack"], 0x1008ff16, "XF86AudioPrev"),
+    (&["medianext", "nexttrack"], 0x1008ff17, "XF86AudioNext"),
+];
+
+/// Modifiers in the order they are written, with their X11 mask (Control,
+/// Mod1, Shift, Mod4) and portal name
+const MODIFIERS: &[(&str, &str, u16, &str)] = &[
+    ("control", "ctrl", 1 << 2, "CTRL"),
+    ("alt", "alt", 1 << 3, "ALT"),
+    ("shift", "shift", 1, "SHIFT"),
+    ("meta", "super", 1 << 6, "LOGO"),
+];
+
+/// A global key combination such as "ctrl+alt+1"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotkey {
+    /// Modifier key names as used by `key_down`, in `MODIFIERS` order
+    modifiers: Vec<&'static str>,
+    key: String,
+    keysym: u32,
+    key_name: String,
+}
+
+impl Hotkey {
+    /// Parse a chord such as "Ctrl+Alt+1" or "super+F5"
+    ///
+    /// At least one modifier is required so plain typing never triggers a hotkey.
+    pub fn parse(chord: &str) -> Result<Self, String> {
+        let (modifiers, key) = parse_key_combo(chord)?;
+        if modifiers.is_empty() {
+            return Err(format!("Hotkey '{}' needs at least one modifier", chord));
+        }
+        let (keysym, key_name) = keysym(&key)
+            .ok_or_else(|| format!("Unsupported key '{}' in hotkey '{}'", key, chord))?;
+        let modifiers = MODIFIERS
+            .iter()
+            .map(|(name, ..)| *name)
+            .filter(|name| modifiers.contains(name))
+            .collect();
+        Ok(Hotkey {
+            modifiers,
+            key,
+            keysym,
+            key_name,
+        })
+    }
+
+    /// X11 modifier mask of the hotkey
+    #[cfg(feature = "x11")]
+    fn mod_mask(&self) -> ModMask {
+        MODIFIERS
+            .iter()
+            .filter(|(name, ..)| self.modifiers.contains(name))
+            .fold(ModMask::from(0u16), |mask, (_, _, bit, _)| {
+                mask | ModMask::from(*bit)
+            })
+    }
+
+    /// Trigger in the shortcuts specification format, e.g. "CTRL+ALT+1"
+    #[cfg_attr(not(feature = "wayland"), allow(dead_code))]
+    fn portal_trigger(&self) -> String {
+        let mut parts: Vec<&str> = MODIFIERS
+            .iter()
+            .filter(|(name, ..)| self.modifiers.contains(name))
+            .map(|(_, _, _, portal)| *portal)
+            .collect();
+        parts.push(&self.key_name);
+        parts.join("+")
+    }
+}
+
+/// The canonical form, e.g. "ctrl+alt+shift+super+f5"
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, written, ..) in MODIFIERS {
+            if self.modifiers.contains(name) {
+                write!(f, "{}+", written)?;
+            }
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+/// X11 keysym and XKB name of a key, for letters, digits, F-keys and named keys
+pub(crate) fn keysym(key: &str) -> Option<(u32, String)> {
+    if let Some((_, keysym, name)) = NAMED_KEYS.iter().find(|(names, ..)| names.contains(&key)) {
+        return Some((*keysym, name.to_string()));
+    }
+    if let Some(number) = key.strip_prefix('f').and_then(|n| n.parse::<u32>().ok())
+        && (1..=24).contains(&number)
+    {
+        return Some((0xffbe + number - 1, format!("F{}", number)));
+    }
+    match key.chars().collect::<Vec<_>>().as_slice() {
+        [c] if c.is_ascii_lowercase() || c.is_ascii_digit() => Some((*c as u32, c.to_string())),
+        _ => None,
+    }
+}
+
+/// Called with the canonical hotkey (see `Hotkey`'s `Display`) on every press
+pub type PressHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Global hotkeys grabbed for the daemon, released when dropped
+///
+/// On X11 the keys are grabbed on the root window; on Wayland they are
+/// requested from the GlobalShortcuts desktop portal, which may ask the
+/// user to confirm them.
+pub enum HotkeyListener {
+    X11 {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    },
+    Portal(tokio::task::JoinHandle<()>),
+}
+
+impl HotkeyListener {
+    pub fn start(hotkeys: Vec<Hotkey>, on_press: PressHandler) -> Result<Self, String> {
+        if std::env::var("WAYLAND_DISPLAY").is_ok() || crate::portal::portal_only() {
+            let runtime = tokio::runtime::Handle::try_current()
+                .map_err(|_| "The portal hotkey listener needs a tokio runtime".to_string())?;
+            let task = runtime.spawn(async move {
+                if let Err(e) = portal_listen(hotkeys, on_press).await {
+                    eprintln!("⚠️  Global hotkeys unavailable: {}", e);
+                }
+            });
+            return Ok(HotkeyListener::Portal(task));
+        }
+        if std::env::var("DISPLAY").is_ok() {
+            return x11_listen(hotkeys, on_press);
+        }
+        Err("Global hotkeys need an X11 or Wayland session".to_string())
+    }
+}
+
+impl Drop for HotkeyListener {
+    fn drop(&mut self) {
+        match self {
+            HotkeyListener::X11 { stop, thread } => {
+                stop.store(true, Ordering::SeqCst);
+                // Wait for the grabs to be released so new ones for the same keys succeed
+                if let Some(thread) = thread.take() {
+                    let _ = thread.join();
+                }
+            }
+            HotkeyListener::Portal(task) => task.abort(),
+        }
+    }
+}
+
+/// Grab every hotkey on the X11 root window and poll for presses
+#[cfg(feature = "x11")]
+fn x11_listen(hotkeys: Vec<Hotkey>, on_press: PressHandler) -> Result<HotkeyListener, String> {
+    let (connection, screen) =
+        x11rb::connect(None).map_err(|e| format!("Failed to connect to X11: {}", e))?;
+    let root = connection.setup().roots[screen].root;
+    let min_keycode = connection.setup().min_keycode;
+    let max_keycode = connection.setup().max_keycode;
+    let mapping = connection
+        .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)
+        .map_err(|e| e.to_string())?
+        .reply()
+        .map_err(|e| format!("Failed to read the keyboard mapping: {}", e))?;
+    let per_keycode = usize::from(mapping.keysyms_per_keycode.max(1));
+
+    let mut grabbed: Vec<(Keycode, ModMask, String)> = Vec::new();
+    for hotkey in &hotkeys {
+        let keycode = mapping
+            .keysyms
+            .chunks(per_keycode)
+            .position(|keysyms| keysyms.contains(&hotkey.keysym))
+            .map(|index| min_keycode + index as u8)
+            .ok_or_else(|| format!("No key on this keyboard produces '{}'", hotkey.key))?;
+        let modifiers = hotkey.mod_mask();
+        // Caps Lock and Num Lock change the modifier state, so grab with them too
+        for extra in [
+            ModMask::from(0u16),
+            ModMask::LOCK,
+            ModMask::M2,
+            ModMask::LOCK | ModMask::M2,
+        ] {
+            connection
+                .grab_key(
+                    false,
+                    root,
+                    modifiers | extra,
+                    keycode,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )
+                .map_err(|e| e.to_string())?
+                .check()
+                .map_err(|e| format!("Hotkey {} is taken by another program: {}", hotkey, e))?;
+        }
+        grabbed.push((keycode, modifiers, hotkey.to_string()));
+    }
+    connection.flush().map_err(|e| e.to_string())?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    let thread = std::thread::spawn(move || {
+        let relevant = u16::from(ModMask::CONTROL | ModMask::M1 | ModMask::SHIFT | ModMask::M4);
+        while !stopped.load(Ordering::SeqCst) {
+            match connection.poll_for_event() {
+                Ok(Some(Event::KeyPress(event))) => {
+                    let state = u16::from(event.state) & relevant;
+                    if let Some((_, _, hotkey)) = grabbed.iter().find(|(keycode, modifiers, _)| {
+                        *keycode == event.detail && u16::from(*modifiers) == state
+                    }) {
+                        on_press(hotkey);
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => std::thread::sleep(X11_POLL_INTERVAL),
+                Err(e) => {
+                    eprintln!("⚠️  X11 hotkey listener stopped: {}", e);
+                    return;
+                }
+            }
+        }
+        // The grabs are released when the connection closes
+    });
+    Ok(HotkeyListener::X11 {
+        stop,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(not(feature = "x11"))]
+fn x11_listen(_hotkeys: Vec<Hotkey>, _on_press: PressHandler) -> Result<HotkeyListener, String> {
+    Err(crate::features::missing("x11"))
+}
+
+/// Bind the hotkeys through the GlobalShortcuts portal and wait for activations
+#[cfg(feature = "wayland")]
+async fn portal_listen(hotkeys: Vec<Hotkey>, on_press: PressHandler) -> Result<(), String> {
+    let connection = zbus::Connection::session()
+        .await
+        .map_err(|e| format!("Failed to connect to the session bus: {}", e))?;
+    let portal = zbus::Proxy::new(
+        &connection,
+        PORTAL_DESTINATION,
+        PORTAL_PATH,
+        SHORTCUTS_INTERFACE,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let mut activations = portal
+        .receive_signal("Activated")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let token = request_token();
+    let options = HashMap::from([
+        ("handle_token", Value::from(token.as_str())),
+        ("session_handle_token", Value::from(token.as_str())),
+    ]);
+    let results =
+        portal_request(&connection, &portal, "CreateSession", &token, &(options,)).await?;
+    let session = session_handle(&results)?;
+    let session = ObjectPath::try_from(session.as_str()).map_err(|e| e.to_string())?;
+
+    let shortcuts: Vec<(String, HashMap<&str, Value>)> = hotkeys
+        .iter()
+        .map(|hotkey| {
+            let options = HashMap::from([
+                ("description", Value::from(format!("Casper: {}", hotkey))),
+                ("preferred_trigger", Value::from(hotkey.portal_trigger())),
+            ]);
+            (hotkey.to_string(), options)
+        })
+        .collect();
+    let token = request_token();
+    let options = HashMap::from([("handle_token", Value::from(token.as_str()))]);
+    let body = (&session, shortcuts, "", options);
+    portal_request(&connection, &portal, "BindShortcuts", &token, &body).await?;
+
+    while let Some(message) = activations.next().await {
+        type Activation = (OwnedObjectPath, String, u64, HashMap<String, OwnedValue>);
+        let Ok((activated, id, _, _)) = message.body().deserialize::<Activation>() else {
+            continue;
+        };
+        if activated.as_str() == session.as_str() {
+            on_press(&id);
+        }
+    }
+    Err("The portal session ended".to_string())
+}
+
+#[cfg(not(feature = "wayland"))]
+async fn portal_listen(_hotkeys: Vec<Hotkey>, _on_press: PressHandler) -> Result<(), String> {
+    Err(crate::features::missing("wayland"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hotkey() {
+        let hotkey = Hotkey::parse("Alt+Ctrl+1").unwrap();
+        assert_eq!(hotkey.to_string(), "ctrl+alt+1");
+        assert_eq!(hotkey.portal_trigger(), "CTRL+ALT+1");
+        assert_eq!(hotkey.keysym, '1' as u32);
+        assert_eq!(hotkey, Hotkey::parse("control+alt+1").unwrap());
+
+        let hotkey = Hotkey::parse("super+shift+F5").unwrap();
+        assert_eq!(hotkey.to_string(), "shift+super+f5");
+        assert_eq!(hotkey.portal_trigger(), "SHIFT+LOGO+F5");
+        assert_eq!(hotkey.keysym, 0xffc2);
+        assert_eq!(
+            Hotkey::parse("meta+enter").unwrap().portal_trigger(),
+            "LOGO+Return"
+        );
+
+        assert!(Hotkey::parse("a").is_err());
+        assert!(Hotkey::parse("ctrl+/").is_err());
+        assert!(Hotkey::parse("ctrl+f25").is_err());
+        assert!(Hotkey::parse("hyper+a").is_err());
+    }
+}
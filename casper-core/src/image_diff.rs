@@ -0,0 +1,180 @@
+use crate::actions::{DEFAULT_WAIT_TIMEOUT_MS, ScreenChangeWait};
+use crate::capture::{FrameSource, capture_png, colors_match, decode_png};
+use crate::pipeline::Region;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Side of the square cells changed pixels are grouped into
+const DIFF_CELL: usize = 16;
+
+/// How often `wait_for_screen_change` looks at the screen
+const CHANGE_POLL: Duration = Duration::from_millis(200);
+
+/// Share of pixels that has to change before the screen counts as changed,
+/// when a wait does not say
+pub const DEFAULT_CHANGE_THRESHOLD: f64 = 0.001;
+
+/// How alike two images are
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageComparison {
+    /// Share of pixels that are the same, 1.0 for identical images
+    pub similarity: f64,
+    /// Boxes around the areas that changed, top to bottom
+    pub changed: Vec<Region>,
+}
+
+/// Compare two PNG images of the same size pixel by pixel
+///
+/// Pixels within `tolerance` in each color channel count as the same.
+/// Changed pixels are grouped in cells of 16×16 and touching cells are
+/// merged, so text that changed comes back as one box and not one per
+/// letter.
+pub fn compare_images(a: &[u8], b: &[u8], tolerance: u8) -> Result<ImageComparison, String> {
+    let (width, height, a) = decode_png(a)?;
+    let (b_width, b_height, b) = decode_png(b)?;
+    if (width, height) != (b_width, b_height) {
+        return Err(format!(
+            "Images differ in size: {}x{} and {}x{}",
+            width, height, b_width, b_height
+        ));
+    }
+    let (width, height) = (width as usize, height as usize);
+    let columns = width.div_ceil(DIFF_CELL);
+    let rows = height.div_ceil(DIFF_CELL);
+    let mut cells = vec![false; columns * rows];
+    let mut differing = 0;
+    for (i, (a, b)) in a.chunks_exact(4).zip(b.chunks_exact(4)).enumerate() {
+        if !colors_match((a[0], a[1], a[2]), (b[0], b[1], b[2]), tolerance) {
+            differing += 1;
+            let (x, y) = (i % width, i / width);
+            cells[(y / DIFF_CELL) * columns + x / DIFF_CELL] = true;
+        }
+    }
+
+    let mut changed = Vec::new();
+    let mut seen = vec![false; cells.len()];
+    for start in 0..cells.len() {
+        if !cells[start] || seen[start] {
+            continue;
+        }
+        // Flood the cells touching this one, diagonals included
+        let (mut left, mut top, mut right, mut bottom) = (columns, rows, 0, 0);
+        let mut pending = vec![start];
+        seen[start] = true;
+        while let Some(cell) = pending.pop() {
+            let (column, row) = (cell % columns, cell / columns);
+            (left, top) = (left.min(column), top.min(row));
+            (right, bottom) = (right.max(column), bottom.max(row));
+            for next_row in row.saturating_sub(1)..=(row + 1).min(rows - 1) {
+                for next_column in column.saturating_sub(1)..=(column + 1).min(columns - 1) {
+                    let next = next_row * columns + next_column;
+                    if cells[next] && !seen[next] {
+                        seen[next] = true;
+                        pending.push(next);
+                    }
+                }
+            }
+        }
+        let (x, y) = (left * DIFF_CELL, top * DIFF_CELL);
+        changed.push(Region {
+            x: x as i32,
+            y: y as i32,
+            width: (((right + 1) * DIFF_CELL).min(width) - x) as i32,
+            height: (((bottom + 1) * DIFF_CELL).min(height) - y) as i32,
+        });
+    }
+
+    let total = (width * height).max(1);
+    Ok(ImageComparison {
+        similarity: 1.0 - differing as f64 / total as f64,
+        changed,
+    })
+}
+
+/// Wait until the screen (or the wait's region) differs from how it looked
+/// when the wait began, and return how it changed
+///
+/// With `stable_ms`, the wait goes on until the screen has then stopped
+/// changing for that long, which is how a page that finished loading looks.
+/// Changed regions are relative to the region's top-left corner.
+pub fn wait_for_screen_change(wait: &ScreenChangeWait) -> Result<ImageComparison, String> {
+    let source = match wait.region {
+        Some(region) => FrameSource::Region {
+            x: region.x,
+            y: region.y,
+            width: region.width,
+            height: region.height,
+        },
+        None => FrameSource::Screen,
+    };
+    let timeout = Duration::from_millis(wait.timeout_ms.unwrap_or(DEFAULT_WAIT_TIMEOUT_MS));
+    let threshold = wait.threshold.unwrap_or(DEFAULT_CHANGE_THRESHOLD);
+    let tolerance = wait.tolerance.unwrap_or(0);
+    let started = Instant::now();
+    let before = capture_png(&source)?.data;
+
+    let mut latest = loop {
+        std::thread::sleep(CHANGE_POLL);
+        let now = capture_png(&source)?.data;
+        if 1.0 - compare_images(&before, &now, tolerance)?.similarity > threshold {
+            break now;
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "Timed out after {} ms waiting for the screen to change",
+                timeout.as_millis()
+            ));
+        }
+    };
+    if let Some(stable_ms) = wait.stable_ms {
+        let mut still_since = Instant::now();
+        while still_since.elapsed() < Duration::from_millis(stable_ms) {
+            if started.elapsed() >= timeout {
+                return Err(format!(
+                    "Timed out after {} ms waiting for the screen to settle",
+                    timeout.as_millis()
+                ));
+            }
+            std::thread::sleep(CHANGE_POLL);
+            let now = capture_png(&source)?.data;
+            if 1.0 - compare_images(&latest, &now, tolerance)?.similarity > threshold {
+                still_since = Instant::now();
+            }
+            latest = now;
+        }
+    }
+    compare_images(&before, &latest, tolerance)
+}
+
+#[cfg(all(test, feature = "capture"))]
+mod tests {
+    use super::*;
+    use crate::capture::encode_png;
+
+    #[test]
+    fn test_compare_images() {
+        let before = vec![[30, 30, 30, 255]; 64 * 40];
+        let mut after = before.clone();
+        // A word of two letters in the top-left corner, and a spinner
+        // bottom right
+        after[5 * 64 + 3] = [255, 255, 255, 255];
+        after[5 * 64 + 20] = [255, 255, 255, 255];
+        after[38 * 64 + 60] = [0, 200, 0, 255];
+        after[39 * 64 + 63] = [32, 32, 32, 255];
+        let before = encode_png(64, 40, &before.concat()).unwrap();
+        let after = encode_png(64, 40, &after.concat()).unwrap();
+
+        let comparison = compare_images(&before, &after, 4).unwrap();
+        assert_eq!(comparison.similarity, 1.0 - 3.0 / 2560.0);
+        let boxes: Vec<_> = comparison
+            .changed
+            .iter()
+            .map(|r| (r.x, r.y, r.width, r.height))
+            .collect();
+        assert_eq!(boxes, [(0, 0, 32, 16), (48, 32, 16, 8)]);
+
+        assert_eq!(compare_images(&before, &before, 0).unwrap().similarity, 1.0);
+        let smaller = encode_png(1, 1, &[0, 0, 0, 255]).unwrap();
+        assert!(compare_images(&before, &smaller, 0).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::commands::CommandDeadline;
+use crate::input_recorder::key_name;
+use crate::screen::{Enigo, InputBackend};
+use evdev::KeyCode;
+use std::process::Command;
+
+/// Input through ydotool, which writes to /dev/uinput and so works on every
+/// Wayland compositor; `ydotoold` has to be running
+///
+/// ydotool cannot tell where the pointer is, so the position is still read
+/// through enigo.
+pub struct Ydotool;
+
+impl InputBackend for Ydotool {
+    fn name(&self) -> &'static str {
+        "ydotool"
+    }
+
+    fn move_mouse(&self, x: i32, y: i32) -> Result<(), String> {
+        let (x, y) = (x.to_string(), y.to_string());
+        run("ydotool", &["mousemove", "--absolute", "-x", &x, "-y", &y])
+    }
+
+    fn move_mouse_relative(&self, dx: i32, dy: i32) -> Result<(), String> {
+        let (dx, dy) = (dx.to_string(), dy.to_string());
+        run("ydotool", &["mousemove", "-x", &dx, "-y", &dy])
+    }
+
+    fn button(&self, button: &str, presses: &[bool]) -> Result<(), String> {
+        run("ydotool", &["click", &click_code(button, presses)?])
+    }
+
+    fn scroll(&self, amount: i32, direction: &str) -> Result<(), String> {
+        let (x, y) = match direction {
+            "up" => (0, amount),
+            "down" => (0, -amount),
+            "left" => (-amount, 0),
+            "right" => (amount, 0),
+            _ => return Err(format!("Unknown scroll direction: {}", direction)),
+        };
+        let (x, y) = (x.to_string(), y.to_string());
+        run("ydotool", &["mousemove", "--wheel", "-x", &x, "-y", &y])
+    }
+
+    fn type_text(&self, text: &str) -> Result<(), String> {
+        run_with_input("ydotool", &["type", "--file", "-"], text)
+    }
+
+    fn key(&self, key: &str, presses: &[bool]) -> Result<(), String> {
+        let Some(code) = evdev_code(key) else {
+            // Characters off the keyboard, like "ç", can still be typed
+            if key.chars().count() == 1 && presses == [true, false] {
+                return self.type_text(key);
+            }
+            return Err(format!("Unknown key: {}", key));
+        };
+        let events: Vec<String> = presses
+            .iter()
+            .map(|&pressed| format!("{}:{}", code, pressed as u8))
+            .collect();
+        let mut args = vec!["key"];
+        args.extend(events.iter().map(String::as_str));
+        run("ydotool", &args)
+    }
+
+    fn location(&self) -> Result<(i32, i32), String> {
+        Enigo.location()
+    }
+}
+
+/// Keyboard input through wtype, which uses the virtual keyboard protocol of
+/// wlroots compositors such as Sway and Hyprland
+///
+/// wtype has no pointer, so mouse input still goes through enigo.
+pub struct Wtype;
+
+impl InputBackend for Wtype {
+    fn name(&self) -> &'static str {
+        "wtype"
+    }
+
+    fn move_mouse(&self, x: i32, y: i32) -> Result<(), String> {
+        Enigo.move_mouse(x, y)
+    }
+
+    fn move_mouse_relative(&self, dx: i32, dy: i32) -> Result<(), String> {
+        Enigo.move_mouse_relative(dx, dy)
+    }
+
+    fn button(&self, button: &str, presses: &[bool]) -> Result<(), String> {
+        Enigo.button(button, presses)
+    }
+
+    fn scroll(&self, amount: i32, direction: &str) -> Result<(), String> {
+        Enigo.scroll(amount, direction)
+    }
+
+    fn type_text(&self, text: &str) -> Result<(), String> {
+        run_with_input("wtype", &["-"], text)
+    }
+
+    fn key(&self, key: &str, presses: &[bool]) -> Result<(), String> {
+        let args = wtype_args(key, presses)?;
+        run(
+            "wtype",
+            &args.iter().map(String::as_str).collect::<Vec<_>>(),
+        )
+    }
+
+    fn location(&self) -> Result<(i32, i32), String> {
+        Enigo.location()
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let output = Command::new(program).args(args).output_bounded();
+    check(program, output)
+}
+
+/// Like `run`, with `input` on stdin, so typed text (which may be a
+/// password) is not visible in the process list
+fn run_with_input(program: &str, args: &[&str], input: &str) -> Result<(), String> {
+    let output = Command::new(program)
+        .args(args)
+        .output_bounded_with_input(input.as_bytes());
+    check(program, output)
+}
+
+fn check(program: &str, output: std::io::Result<std::process::Output>) -> Result<(), String> {
+    let output = output.map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// The evdev code of a key name `press_key` takes
+fn evdev_code(key: &str) -> Option<u16> {
+    let key = key.to_lowercase();
+    let name = match key.as_str() {
+        "return" => "enter",
+        "esc" => "escape",
+        "del" => "delete",
+        "ctrl" => "control",
+        "super" | "windows" | "command" => "meta",
+        "leftarrow" => "left",
+        "rightarrow" => "right",
+        "uparrow" => "up",
+        "downarrow" => "down",
+        name => name,
+    };
+    // The left-hand modifiers come first
+    (0..KeyCode::BTN_0.code()).find(|&code| key_name(KeyCode::new(code)) == Some(name))
+}
+
+/// `ydotool click` argument: the button, 0x40 to press and 0x80 to release
+fn click_code(button: &str, presses: &[bool]) -> Result<String, String> {
+    let button = match button {
+        "left" => 0x00,
+        "right" => 0x01,
+        "middle" => 0x02,
+        _ => return Err(format!("Unknown button: {}", button)),
+    };
+    let action = match presses {
+        [true] => 0x40,
+        [false] => 0x80,
+        _ => 0xC0,
+    };
+    Ok(format!("0x{:02X}", action | button))
+}
+
+/// wtype arguments pressing and/or releasing a key; modifiers are held with
+/// -M/-m, other keys go by their keysym name
+fn wtype_args(key: &str, presses: &[bool]) -> Result<Vec<String>, String> {
+    let lower = key.to_lowercase();
+    let modifier = match lower.as_str() {
+        "shift" => Some("shift"),
+        "control" | "ctrl" => Some("ctrl"),
+        "alt" => Some("alt"),
+        "meta" | "super" | "windows" | "command" => Some("logo"),
+        _ => None,
+    };
+    let (name, press, release, tap) = match modifier {
+        Some(modifier) => (modifier.to_string(), "-M", "-m", None),
+        None => {
+            let name = match lower.as_str() {
+                "return" | "enter" => "Return".to_string(),
+                "escape" | "esc" => "Escape".to_string(),
+                "backspace" => "BackSpace".to_string(),
+                "tab" => "Tab".to_string(),
+                "space" => "space".to_string(),
+                "delete" | "del" => "Delete".to_string(),
+                "home" => "Home".to_string(),
+                "end" => "End".to_string(),
+                "pageup" => "Prior".to_string(),
+                "pagedown" => "Next".to_string(),
+                "left" | "leftarrow" => "Left".to_string(),
+                "right" | "rightarrow" => "Right".to_string(),
+                "up" | "uparrow" => "Up".to_string(),
+                "down" | "downarrow" => "Down".to_string(),
+                f if f.len() > 1
+                    && f.starts_with('f')
+                    && f[1..].parse::<u8>().is_ok_and(|n| (1..=24).contains(&n)) =>
+                {
+                    f.to_uppercase()
+                }
+                _ if key.chars().count() == 1 => key.to_string(),
+                _ => return Err(format!("Unknown key: {}", key)),
+            };
+            (name, "-P", "-p", Some("-k"))
+        }
+    };
+    let flags: Vec<&str> = match (presses, tap) {
+        ([true, false], Some(tap)) => vec![tap],
+        _ => presses
+            .iter()
+            .map(|&pressed| if pressed { press } else { release })
+            .collect(),
+    };
+    Ok(flags
+        .into_iter()
+        .flat_map(|flag| [flag.to_string(), name.clone()])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_arguments() {
+        assert_eq!(evdev_code("Return"), Some(KeyCode::KEY_ENTER.code()));
+        assert_eq!(evdev_code("ctrl"), Some(KeyCode::KEY_LEFTCTRL.code()));
+        assert_eq!(evdev_code("ç"), None);
+
+        assert_eq!(click_code("left", &[true, false]).unwrap(), "0xC0");
+        assert_eq!(click_code("right", &[false]).unwrap(), "0x81");
+
+        assert_eq!(
+            wtype_args("enter", &[true, false]).unwrap(),
+            ["-k", "Return"]
+        );
+        assert_eq!(
+            wtype_args("ctrl", &[true, false]).unwrap(),
+            ["-M", "ctrl", "-m", "ctrl"]
+        );
+        assert_eq!(wtype_args("F12", &[true]).unwrap(), ["-P", "F12"]);
+        assert!(wtype_args("hyper", &[true]).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::input_recorder::{RawInput, key_name, open_devices, translate};
+use crate::screen::parse_key_combo;
+use evdev::KeyCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the grabbed devices are read for the panic key
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Key combination releasing the devices when none is given
+pub const DEFAULT_PANIC_KEY: &str = "ctrl+alt+escape";
+
+/// Longest the devices may be blocked in one go
+pub const MAX_BLOCK_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Why a block ended without being stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockEnd {
+    /// The panic key was pressed on a grabbed keyboard
+    PanicKey,
+    /// The block lasted as long as it was allowed to
+    Timeout,
+}
+
+impl BlockEnd {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockEnd::PanicKey => "panic_key",
+            BlockEnd::Timeout => "timeout",
+        }
+    }
+}
+
+/// A key combination watched for on the real keyboard
+#[derive(Debug, Clone)]
+pub struct PanicChord {
+    modifiers: Vec<&'static str>,
+    key: String,
+    held: Vec<&'static str>,
+}
+
+impl PanicChord {
+    /// Parse a combination such as "ctrl+alt+escape"
+    pub fn parse(combo: &str) -> Result<Self, String> {
+        let (modifiers, key) = parse_key_combo(combo)?;
+        // Only keys the devices can be read as can complete the chord; every
+        // keyboard key comes before the buttons
+        let readable = (0..KeyCode::BTN_0.code())
+            .filter_map(|code| key_name(KeyCode::new(code)))
+            .any(|name| name == key);
+        if !readable {
+            return Err(format!("'{}' cannot be used as the panic key", key));
+        }
+        Ok(PanicChord {
+            modifiers,
+            key,
+            held: Vec::new(),
+        })
+    }
+
+    /// Follow a key event; true when it completes the chord
+    fn feed(&mut self, input: &RawInput) -> bool {
+        let RawInput::Key { name, pressed } = *input else {
+            return false;
+        };
+        if self.modifiers.contains(&name) && name != self.key {
+            self.held.retain(|held| *held != name);
+            if pressed {
+                self.held.push(name);
+            }
+            return false;
+        }
+        pressed && name == self.key && self.modifiers.iter().all(|m| self.held.contains(m))
+    }
+}
+
+/// Keeps the real keyboard and mouse away from the desktop
+///
+/// Every keyboard and mouse in /dev/input is grabbed, so their events reach
+/// casper alone while its own input goes on as before; a playback cannot be
+/// disturbed by bumping the mouse. Virtual devices (those without a
+/// physical path, like the ones of ydotool) are left alone. The devices are
+/// released when the panic key is pressed, when the timeout passes, or when
+/// the block is stopped or dropped.
+pub struct InputBlock {
+    devices: Vec<String>,
+    stop: Arc<AtomicBool>,
+    active: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InputBlock {
+    /// Grab the devices; `on_end` is called from a background thread if the
+    /// panic key or the timeout releases them
+    ///
+    /// `timeout` must be between a millisecond and `MAX_BLOCK_TIMEOUT`.
+    pub fn start<F>(mut panic: PanicChord, timeout: Duration, on_end: F) -> Result<Self, String>
+    where
+        F: FnOnce(BlockEnd) + Send + 'static,
+    {
+        let deadline = check_timeout(timeout)?;
+        let mut devices: Vec<_> = open_devices()
+            .into_iter()
+            .filter(|(_, device)| device.physical_path().is_some())
+            .filter_map(|(name, mut device)| match device.grab() {
+                Ok(()) => Some((name, device)),
+                Err(e) => {
+                    eprintln!("⚠️  Could not grab {}: {}", name, e);
+                    None
+                }
+            })
+            .collect();
+        if devices.is_empty() {
+            return Err(
+                "No keyboard or mouse in /dev/input could be grabbed; is the user in the 'input' group?"
+                    .to_string(),
+            );
+        }
+        let names = devices.iter().map(|(name, _)| name.clone()).collect();
+        let stop = Arc::new(AtomicBool::new(false));
+        let active = Arc::new(AtomicBool::new(true));
+        let (stopped, still_active) = (Arc::clone(&stop), Arc::clone(&active));
+
+        let thread = std::thread::spawn(move || {
+            let mut end = None;
+            while end.is_none() && !stopped.load(Ordering::Relaxed) {
+                let mut inputs = Vec::new();
+                devices.retain_mut(|(name, device)| match device.fetch_events() {
+                    Ok(events) => {
+                        inputs.extend(events.filter_map(translate));
+                        true
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => true,
+                    Err(e) => {
+                        eprintln!("Stopped blocking {}: {}", name, e);
+                        false
+                    }
+                });
+                if inputs.iter().any(|input| panic.feed(input)) {
+                    end = Some(BlockEnd::PanicKey);
+                } else if Instant::now() >= deadline {
+                    end = Some(BlockEnd::Timeout);
+                } else {
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+            for (_, device) in &mut devices {
+                let _ = device.ungrab();
+            }
+            still_active.store(false, Ordering::Relaxed);
+            if let Some(end) = end {
+                on_end(end);
+            }
+        });
+
+        Ok(InputBlock {
+            devices: names,
+            stop,
+            active,
+            thread: Some(thread),
+        })
+    }
+
+    /// Names of the grabbed devices
+    pub fn devices(&self) -> &[String] {
+        &self.devices
+    }
+
+    /// The devices are still grabbed
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Release the devices
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for InputBlock {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// When a block of `timeout` started now ends
+fn check_timeout(timeout: Duration) -> Result<Instant, String> {
+    if timeout.is_zero() || timeout > MAX_BLOCK_TIMEOUT {
+        return Err(format!(
+            "Input can be blocked for 1 to {} milliseconds",
+            MAX_BLOCK_TIMEOUT.as_millis()
+        ));
+    }
+    Instant::now()
+        .checked_add(timeout)
+        .ok_or_else(|| "The block timeout is too long".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &'static str, pressed: bool) -> RawInput {
+        RawInput::Key { name, pressed }
+    }
+
+    #[test]
+    fn test_panic_chord() {
+        let mut chord = PanicChord::parse(DEFAULT_PANIC_KEY).unwrap();
+        assert!(!chord.feed(&key("escape", true)));
+        assert!(!chord.feed(&key("control", true)));
+        assert!(!chord.feed(&key("alt", true)));
+        assert!(chord.feed(&key("escape", true)));
+        // Releasing a modifier breaks the chord
+        assert!(!chord.feed(&key("alt", false)));
+        assert!(!chord.feed(&key("escape", true)));
+
+        assert!(PanicChord::parse("f12").unwrap().feed(&key("f12", true)));
+        assert!(PanicChord::parse("ctrl+volumeup").is_err());
+        assert!(PanicChord::parse("hyper+escape").is_err());
+    }
+
+    #[test]
+    fn test_block_timeout() {
+        assert!(check_timeout(Duration::from_secs(60)).is_ok());
+        assert!(check_timeout(Duration::ZERO).is_err());
+        assert!(check_timeout(Duration::from_millis(u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,745 @@
+use crate::actions::Action;
+use crate::screen::get_mouse_position;
+use evdev::{Device, EventSummary, KeyCode, RelativeAxisCode};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the input devices are read
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Keys recorded as held modifiers rather than as keystrokes
+const MODIFIERS: &[&str] = &["shift", "control", "alt", "meta"];
+
+/// A keyboard or mouse event, already named the way actions name keys and buttons
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawInput {
+    Key {
+        name: &'static str,
+        pressed: bool,
+    },
+    Button {
+        button: &'static str,
+        pressed: bool,
+        /// Cursor position when the event was read, if it could be queried
+        position: Option<(i32, i32)>,
+    },
+    Wheel {
+        direction: &'static str,
+        amount: i32,
+        position: Option<(i32, i32)>,
+    },
+}
+
+#[derive(Debug)]
+struct HeldModifier {
+    name: &'static str,
+    since: Instant,
+    /// A KeyDown was recorded for it
+    emitted: bool,
+    /// It changed another key (shift for text) or was recorded
+    used: bool,
+}
+
+/// A press whose action depends on what happens next
+#[derive(Debug)]
+enum Pending {
+    Key {
+        name: &'static str,
+        at: Instant,
+    },
+    Button {
+        button: &'static str,
+        at: Instant,
+        position: Option<(i32, i32)>,
+    },
+    Scroll {
+        direction: &'static str,
+        amount: i32,
+        at: Instant,
+    },
+}
+
+/// Turns raw input events into the actions a user would have written
+///
+/// Printable keys become `TypeText`, a press and release with nothing in
+/// between becomes `PressKey` or `ClickMouse`, modifiers are only recorded
+/// (as `KeyDown`/`KeyUp`) around keys and clicks they change, and wheel
+/// notches in one direction are merged into one `Scroll`. Each action comes
+/// with the time of the first event it was made from. Cursor motion is only
+/// recorded where it matters: before clicks, scrolls and at the end of drags.
+#[derive(Debug, Default)]
+pub struct InputConverter {
+    modifiers: Vec<HeldModifier>,
+    pending: Option<Pending>,
+    keys_down: Vec<&'static str>,
+    buttons_down: Vec<&'static str>,
+    text: Option<(String, Instant)>,
+    position: Option<(i32, i32)>,
+    out: Vec<(Action, Instant)>,
+}
+
+impl InputConverter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one event; returns the actions it completed
+    pub fn feed(&mut self, input: RawInput, at: Instant) -> Vec<(Action, Instant)> {
+        match input {
+            RawInput::Key { name, pressed } if MODIFIERS.contains(&name) => {
+                self.modifier(name, pressed, at)
+            }
+            RawInput::Key {
+                name,
+                pressed: true,
+            } => self.key_press(name, at),
+            RawInput::Key {
+                name,
+                pressed: false,
+            } => self.key_release(name, at),
+            RawInput::Button {
+                button,
+                pressed: true,
+                position,
+            } => {
+                self.flush_text();
+                self.flush_pending();
+                self.emit_modifiers(at);
+                self.move_to(position, at);
+                self.pending = Some(Pending::Button {
+                    button,
+                    at,
+                    position,
+                });
+            }
+            RawInput::Button {
+                button,
+                pressed: false,
+                position,
+            } => self.button_release(button, position, at),
+            RawInput::Wheel {
+                direction,
+                amount,
+                position,
+            } => {
+                self.flush_text();
+                if let Some(Pending::Scroll {
+                    direction: pending,
+                    amount: total,
+                    ..
+                }) = &mut self.pending
+                    && *pending == direction
+                {
+                    *total += amount;
+                } else {
+                    self.flush_pending();
+                    self.emit_modifiers(at);
+                    self.move_to(position, at);
+                    self.pending = Some(Pending::Scroll {
+                        direction,
+                        amount,
+                        at,
+                    });
+                }
+            }
+        }
+        std::mem::take(&mut self.out)
+    }
+
+    /// Flush everything still pending and release whatever is held down
+    pub fn finish(&mut self, at: Instant) -> Vec<(Action, Instant)> {
+        self.flush_text();
+        self.flush_pending();
+        for key in std::mem::take(&mut self.keys_down) {
+            self.emit(Action::KeyUp { key: key.into() }, at);
+        }
+        for button in std::mem::take(&mut self.buttons_down) {
+            self.emit(
+                Action::MouseUp {
+                    button: button.into(),
+                },
+                at,
+            );
+        }
+        for modifier in std::mem::take(&mut self.modifiers) {
+            if modifier.emitted {
+                self.emit(
+                    Action::KeyUp {
+                        key: modifier.name.into(),
+                    },
+                    at,
+                );
+            }
+        }
+        std::mem::take(&mut self.out)
+    }
+
+    fn modifier(&mut self, name: &'static str, pressed: bool, at: Instant) {
+        if pressed {
+            if !self.modifiers.iter().any(|m| m.name == name) {
+                self.flush_pending();
+                self.modifiers.push(HeldModifier {
+                    name,
+                    since: at,
+                    emitted: false,
+                    used: false,
+                });
+            }
+            return;
+        }
+        let Some(index) = self.modifiers.iter().position(|m| m.name == name) else {
+            return;
+        };
+        let modifier = self.modifiers.remove(index);
+        if modifier.emitted {
+            self.flush_pending();
+            self.emit(Action::KeyUp { key: name.into() }, at);
+        } else if !modifier.used {
+            // Tapped on its own, e.g. super to open a launcher
+            self.flush_text();
+            self.flush_pending();
+            self.emit(Action::PressKey { key: name.into() }, modifier.since);
+        }
+    }
+
+    fn key_press(&mut self, name: &'static str, at: Instant) {
+        let typing = (name.chars().count() == 1 || name == "space")
+            && self.modifiers.iter().all(|m| m.name == "shift");
+        if typing {
+            self.flush_pending();
+            let shift = !self.modifiers.is_empty();
+            for modifier in &mut self.modifiers {
+                modifier.used = true;
+            }
+            let c = if name == "space" {
+                ' '
+            } else {
+                typed_char(name, shift)
+            };
+            self.text
+                .get_or_insert_with(|| (String::new(), at))
+                .0
+                .push(c);
+            return;
+        }
+        self.flush_text();
+        self.flush_pending();
+        self.emit_modifiers(at);
+        self.pending = Some(Pending::Key { name, at });
+    }
+
+    fn key_release(&mut self, name: &'static str, at: Instant) {
+        match self.pending.take() {
+            Some(Pending::Key {
+                name: pending,
+                at: pressed,
+            }) if pending == name => {
+                self.emit(Action::PressKey { key: name.into() }, pressed);
+            }
+            other => {
+                self.pending = other;
+                // Releases of typed keys were already accounted for by the text
+                if let Some(index) = self.keys_down.iter().position(|k| *k == name) {
+                    self.keys_down.remove(index);
+                    self.flush_pending();
+                    self.emit(Action::KeyUp { key: name.into() }, at);
+                }
+            }
+        }
+    }
+
+    fn button_release(&mut self, button: &'static str, position: Option<(i32, i32)>, at: Instant) {
+        match self.pending.take() {
+            Some(Pending::Button {
+                button: pending,
+                at: pressed,
+                position: start,
+            }) if pending == button => {
+                if position.is_none() || position == start {
+                    self.emit(
+                        Action::ClickMouse {
+                            button: button.into(),
+                            x: None,
+                            y: None,
+                            anchor: None,
+                            settle_ms: None,
+                            count: None,
+                        },
+                        pressed,
+                    );
+                    return;
+                }
+                // A drag: press where it started, release where it ended
+                self.emit(
+                    Action::MouseDown {
+                        button: button.into(),
+                    },
+                    pressed,
+                );
+            }
+            other => {
+                self.pending = other;
+                let Some(index) = self.buttons_down.iter().position(|b| *b == button) else {
+                    return;
+                };
+                self.buttons_down.remove(index);
+                self.flush_pending();
+            }
+        }
+        self.move_to(position, at);
+        self.emit(
+            Action::MouseUp {
+                button: button.into(),
+            },
+            at,
+        );
+    }
+
+    fn emit_modifiers(&mut self, at: Instant) {
+        let mut downs = Vec::new();
+        for modifier in self.modifiers.iter_mut().filter(|m| !m.emitted) {
+            modifier.emitted = true;
+            modifier.used = true;
+            downs.push(modifier.name);
+        }
+        for name in downs {
+            self.emit(Action::KeyDown { key: name.into() }, at);
+        }
+    }
+
+    fn move_to(&mut self, position: Option<(i32, i32)>, at: Instant) {
+        if let Some((x, y)) = position
+            && self.position != position
+        {
+            self.position = position;
+            self.emit(Action::MoveMouse { x, y, anchor: None }, at);
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if let Some((text, at)) = self.text.take() {
+            self.emit(
+                Action::TypeText {
+                    text,
+                    sensitive: false,
+                    delay_ms: None,
+                    via_clipboard: false,
+                    layout: None,
+                },
+                at,
+            );
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        match self.pending.take() {
+            Some(Pending::Key { name, at }) => {
+                self.keys_down.push(name);
+                self.emit(Action::KeyDown { key: name.into() }, at);
+            }
+            Some(Pending::Button { button, at, .. }) => {
+                self.buttons_down.push(button);
+                self.emit(
+                    Action::MouseDown {
+                        button: button.into(),
+                    },
+                    at,
+                );
+            }
+            Some(Pending::Scroll {
+                direction,
+                amount,
+                at,
+            }) => self.emit(
+                Action::Scroll {
+                    amount,
+                    direction: direction.into(),
+                    step: None,
+                    delay_ms: None,
+                },
+                at,
+            ),
+            None => {}
+        }
+    }
+
+    fn emit(&mut self, action: Action, at: Instant) {
+        self.out.push((action, at));
+    }
+}
+
+/// Character a printable key types on a US layout
+fn typed_char(name: &str, shift: bool) -> char {
+    let c = name.chars().next().unwrap_or(' ');
+    if !shift {
+        return c;
+    }
+    match c {
+        'a'..='z' => c.to_ascii_uppercase(),
+        '1' => '!',
+        '2' => '@',
+        '3' => '#',
+        '4' => '$',
+        '5' => '%',
+        '6' => '^',
+        '7' => '&',
+        '8' => '*',
+        '9' => '(',
+        '0' => ')',
+        '-' => '_',
+        '=' => '+',
+        '[' => '{',
+        ']' => '}',
+        ';' => ':',
+        '\'' => '"',
+        '`' => '~',
+        '\\' => '|',
+        ',' => '<',
+        '.' => '>',
+        '/' => '?',
+        other => other,
+    }
+}
+
+/// Name of a keyboard key as used by `PressKey`, `KeyDown` and `KeyUp`
+pub(crate) fn key_name(code: KeyCode) -> Option<&'static str> {
+    let name = match code {
+        KeyCode::KEY_A => "a",
+        KeyCode::KEY_B => "b",
+        KeyCode::KEY_C => "c",
+        KeyCode::KEY_D => "d",
+        KeyCode::KEY_E => "e",
+        KeyCode::KEY_F => "f",
+        KeyCode::KEY_G => "g",
+        KeyCode::KEY_H => "h",
+        KeyCode::KEY_I => "i",
+        KeyCode::KEY_J => "j",
+        KeyCode::KEY_K => "k",
+        KeyCode::KEY_L => "l",
+        KeyCode::KEY_M => "m",
+        KeyCode::KEY_N => "n",
+        KeyCode::KEY_O => "o",
+        KeyCode::KEY_P => "p",
+        KeyCode::KEY_Q => "q",
+        KeyCode::KEY_R => "r",
+        KeyCode::KEY_S => "s",
+        KeyCode::KEY_T => "t",
+        KeyCode::KEY_U => "u",
+        KeyCode::KEY_V => "v",
+        KeyCode::KEY_W => "w",
+        KeyCode::KEY_X => "x",
+        KeyCode::KEY_Y => "y",
+        KeyCode::KEY_Z => "z",
+        KeyCode::KEY_1 => "1",
+        KeyCode::KEY_2 => "2",
+        KeyCode::KEY_3 => "3",
+        KeyCode::KEY_4 => "4",
+        KeyCode::KEY_5 => "5",
+        KeyCode::KEY_6 => "6",
+        KeyCode::KEY_7 => "7",
+        KeyCode::KEY_8 => "8",
+        KeyCode::KEY_9 => "9",
+        KeyCode::KEY_0 => "0",
+        KeyCode::KEY_MINUS => "-",
+        KeyCode::KEY_EQUAL => "=",
+        KeyCode::KEY_LEFTBRACE => "[",
+        KeyCode::KEY_RIGHTBRACE => "]",
+        KeyCode::KEY_SEMICOLON => ";",
+        KeyCode::KEY_APOSTROPHE => "'",
+        KeyCode::KEY_GRAVE => "`",
+        KeyCode::KEY_BACKSLASH => "\\",
+        KeyCode::KEY_COMMA => ",",
+        KeyCode::KEY_DOT => ".",
+        KeyCode::KEY_SLASH => "/",
+        KeyCode::KEY_SPACE => "space",
+        KeyCode::KEY_ENTER | KeyCode::KEY_KPENTER => "enter",
+        KeyCode::KEY_ESC => "escape",
+        KeyCode::KEY_BACKSPACE => "backspace",
+        KeyCode::KEY_TAB => "tab",
+        KeyCode::KEY_DELETE => "delete",
+        KeyCode::KEY_HOME => "home",
+        KeyCode::KEY_END => "end",
+        KeyCode::KEY_PAGEUP => "pageup",
+        KeyCode::KEY_PAGEDOWN => "pagedown",
+        KeyCode::KEY_LEFT => "left",
+        KeyCode::KEY_RIGHT => "right",
+        KeyCode::KEY_UP => "up",
+        KeyCode::KEY_DOWN => "down",
+        KeyCode::KEY_LEFTSHIFT | KeyCode::KEY_RIGHTSHIFT => "shift",
+        KeyCode::KEY_LEFTCTRL | KeyCode::KEY_RIGHTCTRL => "control",
+        KeyCode::KEY_LEFTALT | KeyCode::KEY_RIGHTALT => "alt",
+        KeyCode::KEY_LEFTMETA | KeyCode::KEY_RIGHTMETA => "meta",
+        KeyCode::KEY_F1 => "f1",
+        KeyCode::KEY_F2 => "f2",
+        KeyCode::KEY_F3 => "f3",
+        KeyCode::KEY_F4 => "f4",
+        KeyCode::KEY_F5 => "f5",
+        KeyCode::KEY_F6 => "f6",
+        KeyCode::KEY_F7 => "f7",
+        KeyCode::KEY_F8 => "f8",
+        KeyCode::KEY_F9 => "f9",
+        KeyCode::KEY_F10 => "f10",
+        KeyCode::KEY_F11 => "f11",
+        KeyCode::KEY_F12 => "f12",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn button_name(code: KeyCode) -> Option<&'static str> {
+    match code {
+        KeyCode::BTN_LEFT => Some("left"),
+        KeyCode::BTN_RIGHT => Some("right"),
+        KeyCode::BTN_MIDDLE => Some("middle"),
+        _ => None,
+    }
+}
+
+/// Name an evdev event; the cursor position is filled in by the caller
+pub(crate) fn translate(event: evdev::InputEvent) -> Option<RawInput> {
+    match event.destructure() {
+        // Value 2 is autorepeat, which playback does not need
+        EventSummary::Key(_, code, value @ (0 | 1)) => {
+            let pressed = value == 1;
+            if let Some(button) = button_name(code) {
+                Some(RawInput::Button {
+                    button,
+                    pressed,
+                    position: None,
+                })
+            } else {
+                key_name(code).map(|name| RawInput::Key { name, pressed })
+            }
+        }
+        EventSummary::RelativeAxis(_, RelativeAxisCode::REL_WHEEL, value) if value != 0 => {
+            Some(RawInput::Wheel {
+                direction: if value > 0 { "up" } else { "down" },
+                amount: value.abs(),
+                position: None,
+            })
+        }
+        EventSummary::RelativeAxis(_, RelativeAxisCode::REL_HWHEEL, value) if value != 0 => {
+            Some(RawInput::Wheel {
+                direction: if value > 0 { "right" } else { "left" },
+                amount: value.abs(),
+                position: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Keyboards and mice in /dev/input that can be read, with their names
+pub(crate) fn open_devices() -> Vec<(String, Device)> {
+    evdev::enumerate()
+        .filter(|(_, device)| {
+            let keys = device.supported_keys();
+            let keyboard = keys.is_some_and(|k| k.contains(KeyCode::KEY_ENTER));
+            let mouse = keys.is_some_and(|k| k.contains(KeyCode::BTN_LEFT))
+                || device
+                    .supported_relative_axes()
+                    .is_some_and(|a| a.contains(RelativeAxisCode::REL_WHEEL));
+            keyboard || mouse
+        })
+        .filter(|(_, device)| device.set_nonblocking(true).is_ok())
+        .map(|(path, device)| {
+            let name = device
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| path.display().to_string());
+            (name, device)
+        })
+        .collect()
+}
+
+/// Records what the user does with the real keyboard and mouse
+///
+/// Reads every keyboard and mouse in /dev/input, which requires read
+/// access to the devices (usually membership of the `input` group), and
+/// works the same on X11 and Wayland. Cursor positions come from the input
+/// backend and are left out where it cannot report them. Recording ends
+/// when the recorder is stopped or dropped.
+pub struct InputRecorder {
+    devices: Vec<String>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InputRecorder {
+    /// Start reading input; `on_action` is called from a background thread
+    /// with each action and the time it happened
+    pub fn start<F>(mut on_action: F) -> Result<Self, String>
+    where
+        F: FnMut(Action, Instant) + Send + 'static,
+    {
+        let mut devices = open_devices();
+        if devices.is_empty() {
+            return Err(
+                "No keyboard or mouse in /dev/input could be opened; is the user in the 'input' group?"
+                    .to_string(),
+            );
+        }
+        let names = devices.iter().map(|(name, _)| name.clone()).collect();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+
+        let thread = std::thread::spawn(move || {
+            let mut converter = InputConverter::new();
+            while !stopped.load(Ordering::Relaxed) {
+                let mut inputs = Vec::new();
+                devices.retain_mut(|(name, device)| match device.fetch_events() {
+                    Ok(events) => {
+                        inputs.extend(events.filter_map(translate));
+                        true
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => true,
+                    Err(e) => {
+                        eprintln!("Stopped reading {}: {}", name, e);
+                        false
+                    }
+                });
+                let at = Instant::now();
+                for mut input in inputs {
+                    if let RawInput::Button { position, .. } | RawInput::Wheel { position, .. } =
+                        &mut input
+                    {
+                        *position = get_mouse_position().ok();
+                    }
+                    for (action, at) in converter.feed(input, at) {
+                        on_action(action, at);
+                    }
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            for (action, at) in converter.finish(Instant::now()) {
+                on_action(action, at);
+            }
+        });
+
+        Ok(InputRecorder {
+            devices: names,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Names of the devices being read
+    pub fn devices(&self) -> &[String] {
+        &self.devices
+    }
+
+    /// Stop recording once every action seen so far has been reported
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for InputRecorder {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(inputs: Vec<RawInput>) -> Vec<Action> {
+        let mut converter = InputConverter::new();
+        let start = Instant::now();
+        let mut actions = Vec::new();
+        for (i, input) in inputs.into_iter().enumerate() {
+            let at = start + Duration::from_millis(i as u64 * 10);
+            actions.extend(converter.feed(input, at));
+        }
+        actions.extend(converter.finish(start + Duration::from_secs(1)));
+        actions.into_iter().map(|(action, _)| action).collect()
+    }
+
+    fn key(name: &'static str, pressed: bool) -> RawInput {
+        RawInput::Key { name, pressed }
+    }
+
+    fn button(pressed: bool, x: i32) -> RawInput {
+        RawInput::Button {
+            button: "left",
+            pressed,
+            position: Some((x, 10)),
+        }
+    }
+
+    fn names(actions: &[Action]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|a| serde_json::to_value(a).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_typing_and_shortcuts() {
+        let actions = convert(vec![
+            key("shift", true),
+            key("h", true),
+            key("h", false),
+            key("shift", false),
+            key("i", true),
+            key("i", false),
+            key("control", true),
+            key("s", true),
+            key("s", false),
+            key("control", false),
+            key("enter", true),
+            key("enter", false),
+        ]);
+        assert_eq!(
+            names(&actions),
+            vec![
+                r#"{"text":"Hi","type":"TypeText"}"#,
+                r#"{"key":"control","type":"KeyDown"}"#,
+                r#"{"key":"s","type":"PressKey"}"#,
+                r#"{"key":"control","type":"KeyUp"}"#,
+                r#"{"key":"enter","type":"PressKey"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clicks_drags_and_scrolls() {
+        let actions = convert(vec![
+            button(true, 5),
+            button(false, 5),
+            button(true, 5),
+            button(false, 50),
+            RawInput::Wheel {
+                direction: "down",
+                amount: 1,
+                position: Some((50, 10)),
+            },
+            RawInput::Wheel {
+                direction: "down",
+                amount: 2,
+                position: Some((50, 10)),
+            },
+        ]);
+        assert_eq!(
+            names(&actions),
+            vec![
+                r#"{"type":"MoveMouse","x":5,"y":10}"#,
+                r#"{"button":"left","type":"ClickMouse"}"#,
+                r#"{"button":"left","type":"MouseDown"}"#,
+                r#"{"type":"MoveMouse","x":50,"y":10}"#,
+                r#"{"button":"left","type":"MouseUp"}"#,
+                r#"{"amount":3,"direction":"down","type":"Scroll"}"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::actions::{ActionSequence, ActionWithTimestamp, Point};
+use crate::vault;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const JOURNAL_EXTENSION: &str = "journal";
+
+/// Append-only log of an in-progress recording
+///
+/// The first line holds the sequence header (name, description, tags) and
+/// every further line one recorded step or captured point, flushed to disk
+/// as it is recorded. Lines are encrypted while the library is.
+/// A recording that never reaches `stop_recording` leaves its journal behind
+/// so it can be recovered on the next start.
+pub struct RecordingJournal {
+    path: PathBuf,
+    file: File,
+}
+
+impl RecordingJournal {
+    /// Start a journal for a new recording
+    pub fn create(dir: &Path, session_id: &str, sequence: &ActionSequence) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+        // The PID keeps journals of a previous (crashed) daemon apart from ours
+        let path = dir.join(format!(
+            "recording-{}-{}.{}",
+            std::process::id(),
+            session_id,
+            JOURNAL_EXTENSION
+        ));
+        // Never appended to a journal left behind, which would mix two recordings
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open journal {}: {}", path.display(), e))?;
+
+        let mut journal = RecordingJournal { path, file };
+        journal.write_line(sequence)?;
+        Ok(journal)
+    }
+
+    /// Persist one recorded step
+    pub fn append(&mut self, step: &ActionWithTimestamp) -> Result<(), String> {
+        self.write_line(step)
+    }
+
+    /// Persist a named point captured during the recording
+    pub fn append_point(&mut self, name: &str, point: Point) -> Result<(), String> {
+        self.write_line(&PointEntry {
+            point: name.to_string(),
+            x: point.x,
+            y: point.y,
+        })
+    }
+
+    fn write_line<T: serde::Serialize>(&mut self, value: &T) -> Result<(), String> {
+        let line = serde_json::to_string(value)
+            .map_err(|e| format!("Failed to serialize journal entry: {}", e))?;
+        let mut line = vault::seal(&line)?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| format!("Failed to write journal {}: {}", self.path.display(), e))
+    }
+
+    /// Delete the journal once the recording has been saved
+    pub fn remove(self) -> Result<(), String> {
+        fs::remove_file(&self.path)
+            .map_err(|e| format!("Failed to remove journal {}: {}", self.path.display(), e))
+    }
+
+    /// Keep the journal of a recording that could not be saved, to be
+    /// recovered like one a crash left behind
+    pub fn keep(self) -> Option<RecoveredRecording> {
+        let sequence = read_journal(&self.path)?;
+        let id = self.path.file_stem()?.to_string_lossy().to_string();
+        Some(RecoveredRecording {
+            id,
+            path: self.path,
+            sequence,
+        })
+    }
+}
+
+/// Journal line of a captured point, `{"point": "save_button", "x": 10, "y": 20}`
+#[derive(Serialize, Deserialize)]
+struct PointEntry {
+    point: String,
+    x: i32,
+    y: i32,
+}
+
+/// A recording left behind by a daemon that stopped before saving it
+#[derive(Debug, Clone)]
+pub struct RecoveredRecording {
+    /// Identifier used to recover or discard this recording
+    pub id: String,
+    pub path: PathBuf,
+    pub sequence: ActionSequence,
+}
+
+/// Find journals of interrupted recordings in `dir`
+///
+/// A partially written last line (e.g. from a power loss) is ignored.
+pub fn find_interrupted(dir: &Path) -> Vec<RecoveredRecording> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut recovered: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == JOURNAL_EXTENSION))
+        .filter_map(|path| {
+            let sequence = read_journal(&path)?;
+            let id = path.file_stem()?.to_string_lossy().to_string();
+            Some(RecoveredRecording { id, path, sequence })
+        })
+        .collect();
+    recovered.sort_by(|a, b| a.sequence.created_at.cmp(&b.sequence.created_at));
+    recovered
+}
+
+fn read_journal(path: &Path) -> Option<ActionSequence> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content
+        .lines()
+        .map(|line| vault::open(line).unwrap_or_default());
+    let mut sequence: ActionSequence = serde_json::from_str(&lines.next()?).ok()?;
+    for line in lines {
+        if let Ok(step) = serde_json::from_str::<ActionWithTimestamp>(&line) {
+            sequence.actions.push(step);
+        } else if let Ok(entry) = serde_json::from_str::<PointEntry>(&line) {
+            let point = Point {
+                x: entry.x,
+                y: entry.y,
+            };
+            sequence.points.insert(entry.point, point);
+        } else {
+            break;
+        }
+    }
+    Some(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Action;
+
+    #[test]
+    fn test_interrupted_recording_is_recovered() {
+        let dir = std::env::temp_dir().join(format!("casper-journal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let sequence = ActionSequence::new("long task".into(), "desc".into());
+        let mut journal = RecordingJournal::create(&dir, "rec-1", &sequence).unwrap();
+        journal
+            .append(&ActionWithTimestamp::new(
+                Action::TypeText {
+                    text: "hello".into(),
+                    sensitive: false,
+                    delay_ms: None,
+                    via_clipboard: false,
+                    layout: None,
+                },
+                10,
+            ))
+            .unwrap();
+        journal
+            .append_point("save_button", Point { x: 40, y: 20 })
+            .unwrap();
+        // Simulate a crash in the middle of writing the next step
+        let path = journal.path.clone();
+        drop(journal);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"action\":{\"type\":\"Wa")
+            .unwrap();
+
+        let recovered = find_interrupted(&dir);
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].sequence.name, "long task");
+        assert_eq!(recovered[0].sequence.actions.len(), 1);
+        assert_eq!(
+            recovered[0].sequence.points["save_button"],
+            Point { x: 40, y: 20 }
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stopped_recording_leaves_no_journal() {
+        use crate::actions::RecordingSessions;
+
+        let dir = std::env::temp_dir().join(format!("casper-sessions-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut sessions = RecordingSessions::new().with_journal_dir(dir.clone());
+        let id = sessions.start("demo".into(), String::new()).unwrap();
+        sessions
+            .record_step(None, Action::Wait { milliseconds: 5 }, None, None, None)
+            .unwrap();
+        assert_eq!(find_interrupted(&dir)[0].sequence.actions.len(), 1);
+
+        let (stopped, sequence) = sessions.stop(Some(&id)).unwrap();
+        assert_eq!(stopped, id);
+        assert_eq!(sequence.actions.len(), 1);
+        assert!(find_interrupted(&dir).is_empty());
+
+        // One whose sequence was not saved keeps its journal
+        let id = sessions.start("unsaved".into(), String::new()).unwrap();
+        let (_, _, journal) = sessions.stop_with_journal(Some(&id)).unwrap();
+        let kept = journal.unwrap().keep().unwrap();
+        assert_eq!(kept.sequence.name, "unsaved");
+        assert_eq!(find_interrupted(&dir).len(), 1);
+        // ...and a new journal never reuses its file
+        let sequence = ActionSequence::new("other".into(), String::new());
+        assert!(RecordingJournal::create(&dir, &id, &sequence).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
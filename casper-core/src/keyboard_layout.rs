@@ -0,0 +1,194 @@
+use crate::clipboard::{check_clipboard, paste_text};
+use crate::commands::CommandDeadline;
+use crate::screen::{type_text, type_text_slowly};
+use crate::setup::find_in_path;
+use std::process::Command;
+
+/// Characters typed through AltGr or dead keys on a layout, which input
+/// synthesis gets wrong: AltGr is not pressed and dead keys wait for the
+/// next character
+const TRICKY_CHARACTERS: &[(&str, &str)] = &[
+    ("us", ""),
+    ("us(intl)", "'\"`~^"),
+    ("gb", ""),
+    ("de", "@€{}[]\\|~²³µ^`"),
+    ("at", "@€{}[]\\|~²³µ^`"),
+    ("ch", "@#€{}[]\\|~^`´¨"),
+    ("fr", "@#€{}[]\\|~^`¤"),
+    ("be", "@#€{}[]\\|~^`´"),
+    ("es", "@#€{}[]\\|~¬^`´¨"),
+    ("it", "@#€{}[]~`"),
+    ("pt", "@€£§{}[]~^`´¨"),
+    ("br", "~^`´¨¹²³£¢¬§ªº°"),
+    ("se", "@£$€{}[]\\|~^`´¨"),
+    ("no", "@£$€{}[]|~^`´¨"),
+    ("dk", "@£$€{}[]\\|~^`´¨"),
+    ("fi", "@£$€{}[]\\|~^`´¨"),
+    ("pl", "€"),
+];
+
+/// The keyboard layout of the session, e.g. "de" or "us(intl)"
+///
+/// X11 is asked through `setxkbmap -query`; Wayland compositors do not share
+/// theirs, so `XKB_DEFAULT_LAYOUT` and then the system layout from
+/// `localectl` are used.
+pub fn detect_layout() -> Result<String, String> {
+    if std::env::var("DISPLAY").is_ok() && find_in_path("setxkbmap").is_some() {
+        let output = Command::new("setxkbmap")
+            .arg("-query")
+            .output_bounded()
+            .map_err(|e| format!("Failed to execute setxkbmap: {}", e))?;
+        if let Some(layout) = parse_setxkbmap(&String::from_utf8_lossy(&output.stdout)) {
+            return Ok(layout);
+        }
+    }
+    if let Ok(layout) = std::env::var("XKB_DEFAULT_LAYOUT") {
+        let variant = std::env::var("XKB_DEFAULT_VARIANT").unwrap_or_default();
+        return Ok(layout_name(&layout, &variant));
+    }
+    if find_in_path("localectl").is_some() {
+        let output = Command::new("localectl")
+            .arg("status")
+            .output_bounded()
+            .map_err(|e| format!("Failed to execute localectl: {}", e))?;
+        if let Some(layout) = parse_localectl(&String::from_utf8_lossy(&output.stdout)) {
+            return Ok(layout);
+        }
+    }
+    Err("Could not detect the keyboard layout; pass a layout such as \"de\"".to_string())
+}
+
+/// "layout:     de,us" and "variant:    intl" lines; the first layout is
+/// the one in use
+fn parse_setxkbmap(output: &str) -> Option<String> {
+    let field = |name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(|value| value.trim().to_string())
+    };
+    let layout = field("layout")?;
+    Some(layout_name(&layout, &field("variant").unwrap_or_default()))
+}
+
+/// "X11 Layout: de" and "X11 Variant: nodeadkeys" lines
+fn parse_localectl(output: &str) -> Option<String> {
+    let field = |name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(name)?.strip_prefix(':'))
+            .map(|value| value.trim().to_string())
+    };
+    let layout = field("X11 Layout")?;
+    Some(layout_name(
+        &layout,
+        &field("X11 Variant").unwrap_or_default(),
+    ))
+}
+
+/// The first of comma-separated layouts and variants, as "layout(variant)"
+fn layout_name(layout: &str, variant: &str) -> String {
+    let layout = layout.split(',').next().unwrap_or_default().trim();
+    match variant.split(',').next().unwrap_or_default().trim() {
+        "" => layout.to_string(),
+        variant => format!("{}({})", layout, variant),
+    }
+}
+
+/// Whether `c` cannot be trusted to come out right when typed on `layout`
+///
+/// Variants without a row of their own share the one of their layout. For
+/// layouts that are not known, anything but letters, digits and plain
+/// whitespace or punctuation is pasted.
+pub fn is_tricky(layout: &str, c: char) -> bool {
+    let base = layout.split('(').next().unwrap_or(layout);
+    let known = TRICKY_CHARACTERS
+        .iter()
+        .find(|(name, _)| *name == layout)
+        .or_else(|| TRICKY_CHARACTERS.iter().find(|(name, _)| *name == base));
+    match known {
+        Some((_, tricky)) => tricky.contains(c),
+        None => !(c.is_ascii_alphanumeric() || " \n\t.,-".contains(c)),
+    }
+}
+
+/// `text` cut into runs to type and runs to paste, in order
+pub fn plan_typing<'a>(layout: &str, text: &'a str) -> Vec<(bool, &'a str)> {
+    let mut runs: Vec<(bool, &str)> = Vec::new();
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        let paste = is_tricky(layout, c);
+        if let Some((last, run)) = runs.last_mut()
+            && *last == paste
+        {
+            *run = &text[start..index + c.len_utf8()];
+            continue;
+        }
+        start = index;
+        runs.push((paste, &text[index..index + c.len_utf8()]));
+    }
+    runs
+}
+
+/// Type `text`, and with a `layout` ("auto" for the detected one) paste
+/// the characters that layout would garble
+///
+/// Pasting is opt-in: it goes through the clipboard, which clipboard
+/// managers keep a history of and terminals do not paste with ctrl+v.
+/// `sensitive` text is therefore always typed, and without a clipboard tool
+/// everything is.
+pub fn type_text_on_layout(
+    text: &str,
+    layout: Option<&str>,
+    delay_ms: Option<u64>,
+    sensitive: bool,
+) -> Result<(), String> {
+    let type_run = |run: &str| match delay_ms {
+        Some(delay_ms) => type_text_slowly(run, delay_ms),
+        None => type_text(run),
+    };
+    let layout = match layout {
+        _ if sensitive => return type_run(text),
+        None => return type_run(text),
+        Some("auto") => detect_layout().unwrap_or_else(|_| "us".to_string()),
+        Some(layout) => layout.to_string(),
+    };
+    if check_clipboard().is_err() {
+        return type_run(text);
+    }
+    for (paste, run) in plan_typing(&layout, text) {
+        if paste {
+            paste_text(run)?;
+        } else {
+            type_run(run)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_layouts() {
+        let query =
+            "rules:      evdev\nmodel:      pc105\nlayout:     de,us\nvariant:    nodeadkeys,\n";
+        assert_eq!(parse_setxkbmap(query).unwrap(), "de(nodeadkeys)");
+        let status =
+            "   System Locale: LANG=pt_BR.UTF-8\n       X11 Layout: br\n        X11 Model: abnt2\n";
+        assert_eq!(parse_localectl(status).unwrap(), "br");
+
+        assert!(!is_tricky("us", '@'));
+        assert!(is_tricky("de", '@'));
+        assert!(is_tricky("de(nodeadkeys)", '{'));
+        assert!(is_tricky("us(intl)", '\''));
+        assert!(is_tricky("ru", '/'));
+
+        assert_eq!(
+            plan_typing("de", "mail@host.de"),
+            vec![(false, "mail"), (true, "@"), (false, "host.de")]
+        );
+        assert_eq!(plan_typing("us", "a{b}"), vec![(false, "a{b}")]);
+    }
+}
@@ -0,0 +1,470 @@
+use crate::setup::find_in_path;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// An application described by a freedesktop `.desktop` file
+#[derive(Debug, Clone, Serialize)]
+pub struct DesktopEntry {
+    /// Desktop file ID, e.g. "org.mozilla.firefox"
+    pub id: String,
+    pub name: String,
+    pub generic_name: Option<String>,
+    pub exec: String,
+    pub icon: Option<String>,
+    pub keywords: Vec<String>,
+    pub terminal: bool,
+    pub path: PathBuf,
+}
+
+impl DesktopEntry {
+    /// Parse the `[Desktop Entry]` group of a desktop file
+    ///
+    /// Returns `None` for entries that are not launchable applications
+    /// (hidden, `NoDisplay`, links, directories, or without `Exec`).
+    pub fn parse(id: &str, path: &Path, content: &str) -> Option<Self> {
+        let mut in_entry = false;
+        let mut name = None;
+        let mut generic_name = None;
+        let mut exec = None;
+        let mut icon = None;
+        let mut keywords = Vec::new();
+        let mut terminal = false;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                in_entry = line == "[Desktop Entry]";
+                continue;
+            }
+            if !in_entry || line.starts_with('#') {
+                continue;
+            }
+            // Localized keys ("Name[de]") are skipped in favor of the default
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "Type" if value != "Application" => return None,
+                "Hidden" | "NoDisplay" if value == "true" => return None,
+                "Name" => name = Some(value),
+                "GenericName" => generic_name = Some(value),
+                "Exec" => exec = Some(value),
+                "Icon" => icon = Some(value),
+                "Terminal" => terminal = value == "true",
+                "Keywords" => {
+                    keywords = value
+                        .split(';')
+                        .filter(|k| !k.is_empty())
+                        .map(|k| k.to_string())
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+
+        Some(DesktopEntry {
+            id: id.to_string(),
+            name: name?,
+            generic_name,
+            exec: exec?,
+            icon,
+            keywords,
+            terminal,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// The `Exec` line as argv, with field codes expanded for a launch without files
+    pub fn command_line(&self) -> Vec<String> {
+        self.command_line_with(&[])
+    }
+
+    /// The `Exec` line as argv opening `files` (paths or URLs); entries taking
+    /// a single file get the first one
+    pub fn command_line_with(&self, files: &[String]) -> Vec<String> {
+        let mut args = Vec::new();
+        for word in split_exec(&self.exec) {
+            match word.as_str() {
+                "%f" | "%u" => args.extend(files.first().cloned()),
+                "%F" | "%U" => args.extend(files.iter().cloned()),
+                // Deprecated codes, dropped as the spec asks
+                "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => {}
+                "%i" => {
+                    if let Some(icon) = &self.icon {
+                        args.push("--icon".to_string());
+                        args.push(icon.clone());
+                    }
+                }
+                _ => args.push(
+                    word.replace("%c", &self.name)
+                        .replace("%k", &self.path.to_string_lossy())
+                        .replace("%%", "%"),
+                ),
+            }
+        }
+        args
+    }
+
+    /// Start the application, in a terminal if the entry asks for one
+    pub fn launch(&self) -> Result<(), String> {
+        self.launch_with(&[])
+    }
+
+    /// Start the application opening `files` (paths or URLs)
+    ///
+    /// `gio launch` or `gtk-launch` start it the way the desktop does, with
+    /// startup notification and D-Bus activation; without them the `Exec`
+    /// line is run directly.
+    pub fn launch_with(&self, files: &[String]) -> Result<(), String> {
+        let launchers = [
+            (
+                "gio",
+                vec![
+                    "launch".to_string(),
+                    self.path.to_string_lossy().to_string(),
+                ],
+            ),
+            ("gtk-launch", vec![self.id.clone()]),
+        ];
+        for (launcher, mut args) in launchers {
+            if find_in_path(launcher).is_none() {
+                continue;
+            }
+            args.extend(files.iter().cloned());
+            // Both return once the application is started; old gio has no
+            // `launch`. The application keeps the output streams, so they
+            // are not read.
+            if Command::new(launcher)
+                .args(&args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+            {
+                return Ok(());
+            }
+        }
+
+        let mut argv = self.command_line_with(files);
+        if argv.is_empty() {
+            return Err(format!("{} has an empty Exec line", self.id));
+        }
+        if self.terminal {
+            let terminal =
+                std::env::var("TERMINAL").unwrap_or_else(|_| "x-terminal-emulator".to_string());
+            argv.splice(0..0, [terminal, "-e".to_string()]);
+        }
+        Command::new(&argv[0])
+            .args(&argv[1..])
+            .spawn()
+            .map_err(|e| format!("Failed to launch {}: {}", self.name, e))?;
+        Ok(())
+    }
+
+    /// Program the entry runs, without wrappers like `env` or `flatpak run`
+    fn program(&self) -> String {
+        let argv = self.command_line();
+        let mut words = argv.iter().map(|w| w.as_str());
+        let mut program = words.next().unwrap_or("");
+        loop {
+            match program.rsplit('/').next().unwrap_or(program) {
+                "env" => program = words.find(|w| !w.contains('=')).unwrap_or(""),
+                "flatpak" | "snap" => {
+                    // flatpak run [options] <app-id>, snap run <name>
+                    program = words
+                        .find(|w| *w != "run" && !w.starts_with('-'))
+                        .unwrap_or("");
+                    break;
+                }
+                _ => break,
+            }
+        }
+        program.rsplit('/').next().unwrap_or(program).to_string()
+    }
+
+    /// How well the entry matches a query; lower is better, `None` is no match
+    fn match_rank(&self, query: &str) -> Option<u8> {
+        let query = query.to_lowercase();
+        let name = self.name.to_lowercase();
+        let generic = self.generic_name.as_deref().unwrap_or("").to_lowercase();
+        let id = self.id.to_lowercase();
+        let program = self.program().to_lowercase();
+
+        if name == query
+            || generic == query
+            || program == query
+            || id == query
+            || id.rsplit('.').next() == Some(&query)
+        {
+            Some(0)
+        } else if name.starts_with(&query) {
+            Some(1)
+        } else if name.contains(&query) || generic.contains(&query) || id.contains(&query) {
+            Some(2)
+        } else if self
+            .keywords
+            .iter()
+            .any(|k| k.to_lowercase().contains(&query))
+        {
+            Some(3)
+        } else {
+            None
+        }
+    }
+}
+
+/// Split an `Exec` value into words, honoring the spec's double-quote rules
+fn split_exec(exec: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+/// Directories holding desktop files, most specific first
+pub fn application_dirs() -> Vec<PathBuf> {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+    let data_home =
+        std::env::var("XDG_DATA_HOME").unwrap_or_else(|_| format!("{}/.local/share", home));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    let mut dirs = vec![PathBuf::from(&data_home)];
+    dirs.extend(data_dirs.split(':').map(PathBuf::from));
+    // Flatpak and snap exports, in case the session did not add them to XDG_DATA_DIRS
+    dirs.push(PathBuf::from(&data_home).join("flatpak/exports/share"));
+    dirs.push(PathBuf::from("/var/lib/flatpak/exports/share"));
+    dirs.push(PathBuf::from("/var/lib/snapd/desktop"));
+
+    let mut seen = HashSet::new();
+    dirs.into_iter()
+        .map(|dir| dir.join("applications"))
+        .filter(|dir| seen.insert(dir.clone()))
+        .collect()
+}
+
+/// Every launchable application in the given directories
+///
+/// An entry in an earlier directory hides one with the same ID in a later one.
+pub fn load_entries(dirs: &[PathBuf]) -> Vec<DesktopEntry> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for dir in dirs {
+        collect_entries(dir, dir, &mut seen, &mut entries);
+    }
+    entries
+}
+
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    seen: &mut HashSet<String>,
+    entries: &mut Vec<DesktopEntry>,
+) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in read_dir.filter_map(|e| e.ok().map(|e| e.path())) {
+        if path.is_dir() {
+            collect_entries(root, &path, seen, entries);
+            continue;
+        }
+        if path.extension().is_none_or(|ext| ext != "desktop") {
+            continue;
+        }
+        // Subdirectories become part of the ID: kde/foo.desktop is "kde-foo"
+        let Ok(relative) = path
+            .with_extension("")
+            .strip_prefix(root)
+            .map(Path::to_path_buf)
+        else {
+            continue;
+        };
+        let id = relative.to_string_lossy().replace('/', "-");
+        // The first entry with an ID wins, even if it hides the application
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        if let Ok(content) = fs::read_to_string(&path)
+            && let Some(entry) = DesktopEntry::parse(&id, &path, &content)
+        {
+            entries.push(entry);
+        }
+    }
+}
+
+/// Applications matching a query, best match first
+pub fn search_applications(query: &str) -> Vec<DesktopEntry> {
+    search_entries(load_entries(&application_dirs()), query)
+}
+
+fn search_entries(entries: Vec<DesktopEntry>, query: &str) -> Vec<DesktopEntry> {
+    let query = query.trim();
+    if query.is_empty() {
+        let mut entries = entries;
+        entries.sort_by_key(|e| e.name.to_lowercase());
+        return entries;
+    }
+    let mut ranked: Vec<(u8, DesktopEntry)> = entries
+        .into_iter()
+        .filter_map(|entry| entry.match_rank(query).map(|rank| (rank, entry)))
+        .collect();
+    ranked.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank
+            .cmp(b_rank)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    ranked.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// The application a name most likely refers to
+///
+/// A desktop file ID such as "firefox.desktop" or the path of a desktop file
+/// names exactly that entry. Otherwise only confident matches count (the
+/// name, generic name, program or ID, or the start of the name), so an
+/// unrelated app is never launched.
+pub fn resolve_application(name: &str) -> Option<DesktopEntry> {
+    if let Some(id) = name.strip_suffix(".desktop") {
+        let path = Path::new(name);
+        if path.is_absolute() {
+            let content = fs::read_to_string(path).ok()?;
+            let id = id.rsplit('/').next().unwrap_or(id);
+            return DesktopEntry::parse(id, path, &content);
+        }
+        return load_entries(&application_dirs())
+            .into_iter()
+            .find(|entry| entry.id == id);
+    }
+    let entries = load_entries(&application_dirs());
+    search_entries(entries, name)
+        .into_iter()
+        .next()
+        .filter(|entry| entry.match_rank(name).is_some_and(|rank| rank <= 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIREFOX: &str = "[Desktop Entry]
+Type=Application
+Name=Firefox
+Name[de]=Firefox Webbrowser
+GenericName=Web Browser
+Exec=/usr/lib/firefox/firefox %u
+Icon=firefox
+Keywords=Internet;WWW;
+
+[Desktop Action new-window]
+Name=New Window
+Exec=/usr/lib/firefox/firefox --new-window %u
+";
+
+    fn entry(id: &str, content: &str) -> DesktopEntry {
+        DesktopEntry::parse(id, Path::new("/tmp/test.desktop"), content).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_exec() {
+        let firefox = entry("firefox", FIREFOX);
+        assert_eq!(firefox.name, "Firefox");
+        assert_eq!(firefox.generic_name.as_deref(), Some("Web Browser"));
+        assert_eq!(firefox.command_line(), vec!["/usr/lib/firefox/firefox"]);
+        let pages = [
+            "https://a.example".to_string(),
+            "https://b.example".to_string(),
+        ];
+        assert_eq!(
+            firefox.command_line_with(&pages),
+            vec!["/usr/lib/firefox/firefox", "https://a.example"]
+        );
+        assert_eq!(firefox.program(), "firefox");
+
+        let flatpak = entry(
+            "org.gnome.Calculator",
+            "[Desktop Entry]\nName=Calculator\nExec=/usr/bin/flatpak run --branch=stable org.gnome.Calculator %U\nIcon=calc\n",
+        );
+        assert_eq!(flatpak.program(), "org.gnome.Calculator");
+        assert_eq!(flatpak.command_line_with(&pages).len(), 6);
+
+        assert_eq!(
+            split_exec(r#"sh -c "echo \"hi there\"" %i"#),
+            vec!["sh", "-c", "echo \"hi there\"", "%i"]
+        );
+        assert!(
+            DesktopEntry::parse(
+                "x",
+                Path::new("x"),
+                "[Desktop Entry]\nName=X\nExec=x\nNoDisplay=true\n"
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let entries = vec![
+            entry("firefox", FIREFOX),
+            entry(
+                "fire-manager",
+                "[Desktop Entry]\nName=Campfire Manager\nExec=campfire\n",
+            ),
+            entry(
+                "org.kde.konsole",
+                "[Desktop Entry]\nName=Konsole\nGenericName=Terminal\nExec=konsole\nKeywords=shell;\n",
+            ),
+        ];
+        let results = search_entries(entries.clone(), "fire");
+        assert_eq!(results[0].id, "firefox");
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            search_entries(entries.clone(), "terminal")[0].id,
+            "org.kde.konsole"
+        );
+        assert_eq!(
+            search_entries(entries.clone(), "konsole")[0].id,
+            "org.kde.konsole"
+        );
+        assert_eq!(
+            search_entries(entries.clone(), "shell")[0].id,
+            "org.kde.konsole"
+        );
+        assert_eq!(entries[2].match_rank("shell"), Some(3));
+    }
+}
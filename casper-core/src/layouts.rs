@@ -0,0 +1,232 @@
+use crate::window::{
+    WindowInfo, list_windows, move_resize_window, move_window_to_workspace, window_geometry,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a window was when its layout was saved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowPlacement {
+    pub class: String,
+    /// Used to tell apart windows of the same application
+    pub title: String,
+    pub workspace: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A named arrangement of windows, such as "coding" or "meeting"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layout {
+    pub name: String,
+    pub saved_at: String,
+    pub windows: Vec<WindowPlacement>,
+}
+
+/// What applying a layout did
+#[derive(Debug, Default, Serialize)]
+pub struct LayoutReport {
+    /// IDs of the windows that were placed
+    pub placed: Vec<String>,
+    /// Classes of saved windows that are not open
+    pub missing: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl Layout {
+    /// The open windows as they are placed now
+    pub fn snapshot(name: &str) -> Result<Self, String> {
+        let mut windows = Vec::new();
+        for window in list_windows()? {
+            // Windows closing while the list is read are left out
+            let Ok((x, y, width, height)) = window_geometry(&window.id) else {
+                continue;
+            };
+            windows.push(WindowPlacement {
+                class: window.class,
+                title: window.title,
+                workspace: window.desktop,
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+        Ok(Layout {
+            name: name.to_string(),
+            saved_at: chrono::Utc::now().to_rfc3339(),
+            windows,
+        })
+    }
+
+    /// Move the open windows back to where the layout has them
+    ///
+    /// Applications that are not running are reported, not launched.
+    pub fn apply(&self) -> Result<LayoutReport, String> {
+        let windows = list_windows()?;
+        let mut report = LayoutReport::default();
+        for (placement, window) in self.windows.iter().zip(assign(&self.windows, &windows)) {
+            let Some(window) = window else {
+                report.missing.push(placement.class.clone());
+                continue;
+            };
+            // Sticky windows (workspace -1 on X11) stay on every workspace
+            let moved = if placement.workspace >= 0 && placement.workspace != window.desktop {
+                move_window_to_workspace(&window.id, placement.workspace)
+            } else {
+                Ok(())
+            };
+            let result = moved.and_then(|_| {
+                move_resize_window(
+                    &window.id,
+                    placement.x,
+                    placement.y,
+                    placement.width,
+                    placement.height,
+                )
+            });
+            match result {
+                Ok(_) => report.placed.push(window.id.clone()),
+                Err(e) => report.errors.push(format!("{}: {}", window.id, e)),
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// The open window each placement goes to: one of the same class, the one
+/// with the same title if there is one, and no window twice
+fn assign<'a>(
+    placements: &[WindowPlacement],
+    windows: &'a [WindowInfo],
+) -> Vec<Option<&'a WindowInfo>> {
+    let mut taken = vec![false; windows.len()];
+    let mut assigned = vec![None; placements.len()];
+    // Exact titles are claimed first, so a looser match cannot take them
+    for exact in [true, false] {
+        for (slot, placement) in assigned.iter_mut().zip(placements) {
+            if slot.is_some() {
+                continue;
+            }
+            let found = windows.iter().enumerate().position(|(index, window)| {
+                !taken[index]
+                    && window.class == placement.class
+                    && (!exact || window.title == placement.title)
+            });
+            if let Some(index) = found {
+                taken[index] = true;
+                *slot = Some(&windows[index]);
+            }
+        }
+    }
+    assigned
+}
+
+/// The saved layouts
+#[derive(Debug)]
+pub struct Layouts {
+    path: PathBuf,
+    layouts: Vec<Layout>,
+}
+
+impl Layouts {
+    /// No layouts, saved to `path` once one is added
+    pub fn new(path: &Path) -> Self {
+        Layouts {
+            path: path.to_path_buf(),
+            layouts: Vec::new(),
+        }
+    }
+
+    /// Load the layouts at `path`, starting with none if there is no file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut layouts = Self::new(path);
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            layouts.layouts = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid layouts {}: {}", path.display(), e))?;
+        }
+        Ok(layouts)
+    }
+
+    pub fn layouts(&self) -> &[Layout] {
+        &self.layouts
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Layout> {
+        self.layouts.iter().find(|layout| layout.name == name)
+    }
+
+    /// Add a layout, replacing any layout with the same name
+    pub fn add(&mut self, layout: Layout) -> Result<(), String> {
+        if layout.name.is_empty() {
+            return Err("Layout needs a name".to_string());
+        }
+        self.layouts.retain(|l| l.name != layout.name);
+        self.layouts.push(layout);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        let before = self.layouts.len();
+        self.layouts.retain(|l| l.name != name);
+        if self.layouts.len() == before {
+            return Err(format!("No layout '{}'", name));
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.layouts)
+            .map_err(|e| format!("Failed to serialize layouts: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write layouts: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_assignment() {
+        let window = |id: &str, class: &str, title: &str| WindowInfo {
+            id: id.into(),
+            pid: 0,
+            desktop: 0,
+            class: class.into(),
+            title: title.into(),
+            machine: "localhost".into(),
+        };
+        let placement = |class: &str, title: &str| WindowPlacement {
+            class: class.into(),
+            title: title.into(),
+            workspace: 0,
+            x: 0,
+            y: 0,
+            width: 800,
+            height: 600,
+        };
+        let placements = [
+            placement("kitty", "~/notes"),
+            placement("kitty", "~/src"),
+            placement("slack", "Slack"),
+        ];
+        let windows = [
+            window("0x1", "kitty", "~/src"),
+            window("0x2", "kitty", "vim"),
+            window("0x3", "firefox", "Docs"),
+        ];
+        let ids: Vec<_> = assign(&placements, &windows)
+            .into_iter()
+            .map(|window| window.map(|w| w.id.as_str()))
+            .collect();
+        assert_eq!(ids, [Some("0x2"), Some("0x1"), None]);
+    }
+}
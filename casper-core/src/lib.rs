@@ -1,12 +1,51 @@
 pub mod actions;
 pub mod ai;
+pub mod ai_providers;
 pub mod ai_vision;
+pub mod assertions;
 pub mod capture;
+pub mod clipboard;
 pub mod commands;
+pub mod config;
 pub mod connections;
+pub mod debugger;
+pub mod displays;
+pub mod dry_run;
+pub mod environment;
+pub mod executor;
+pub mod features;
+pub mod gestures;
+pub mod hotkeys;
+pub mod image_diff;
+pub mod input_backends;
+pub mod input_block;
+pub mod input_recorder;
+pub mod journal;
+pub mod keyboard_layout;
+pub mod launcher;
+pub mod layouts;
+pub mod locate;
 pub mod mcp;
+pub mod metrics;
 pub mod notifications;
+pub mod packs;
+pub mod paths;
+pub mod pipeline;
+pub mod portal;
+pub mod processes;
+pub mod runs;
 pub mod screen;
+pub mod screenshots;
+pub mod script;
+pub mod sensors;
+pub mod setup;
+pub mod shortcuts;
+pub mod store;
+pub mod trigger;
 pub mod tts;
+pub mod usage;
+pub mod vault;
 pub mod voice;
 pub mod window;
+pub mod window_backends;
+pub mod window_rules;
@@ -0,0 +1,515 @@
+use crate::actions::Anchor;
+use crate::assertions::reference_path;
+use crate::capture::{capture_screen_temp, colors_match, decode_png};
+use crate::displays::find_display;
+use crate::screen::{click_at, scroll};
+use crate::window::{find_window_by_pattern, focus_window_by_id, window_geometry};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Wheel notches scrolled between two looks for the image
+const SCROLL_SEARCH_STEP: i32 = 3;
+
+/// How long the page may settle after scrolling before it is looked at
+const SCROLL_SEARCH_SETTLE: Duration = Duration::from_millis(250);
+
+/// Scrolls `scroll_to_image` gives up after when none is given
+pub const DEFAULT_MAX_SCROLLS: u32 = 20;
+
+/// How long a window may take to come to the front after being focused
+const FOCUS_SETTLE: Duration = Duration::from_millis(100);
+
+/// Correlation `locate_image_on_screen` counts as a match when none is given
+pub const DEFAULT_CONFIDENCE: f64 = 0.9;
+
+/// Smallest side, in pixels, a template is shrunk to for the first search
+const MATCH_MIN_SIDE: usize = 8;
+
+/// Most a screen and template are shrunk for the first search
+const MATCH_MAX_SHRINK: usize = 4;
+
+/// Best places of the shrunk search looked at again at full size
+const MATCH_CANDIDATES: usize = 32;
+
+/// Screen coordinates of a point given relative to an anchor
+pub fn resolve_point(x: i32, y: i32, anchor: Option<&Anchor>) -> Result<(i32, i32), String> {
+    let (origin_x, origin_y) = match anchor {
+        None => (0, 0),
+        Some(Anchor::Window { window }) => {
+            let info = find_window_by_pattern(window)?
+                .ok_or_else(|| format!("No window matches '{}'", window))?;
+            let (wx, wy, _, _) = window_geometry(&info.id)?;
+            (wx, wy)
+        }
+        Some(Anchor::Image {
+            image,
+            tolerance,
+            confidence,
+        }) => {
+            let path = reference_path(image);
+            let found = match confidence {
+                Some(confidence) => {
+                    locate_image_on_screen(&path, *confidence)?.map(|found| (found.x, found.y))
+                }
+                None => find_image_on_screen(&path, tolerance.unwrap_or(0))?,
+            };
+            found.ok_or_else(|| format!("{} is not on the screen", path.display()))?
+        }
+        Some(Anchor::Monitor { monitor }) => {
+            let display = find_display(monitor)?;
+            (display.x, display.y)
+        }
+    };
+    Ok((origin_x + x, origin_y + y))
+}
+
+/// Screen coordinates of a point `rel_x`/`rel_y` into a window at
+/// `(x, y, width, height)`; negative offsets count from the right and
+/// bottom edges
+fn window_point(
+    (x, y, width, height): (i32, i32, i32, i32),
+    rel_x: i32,
+    rel_y: i32,
+) -> Result<(i32, i32), String> {
+    let rel_x = if rel_x < 0 { width + rel_x } else { rel_x };
+    let rel_y = if rel_y < 0 { height + rel_y } else { rel_y };
+    // A window that shrank must not pass the click on to whatever is beside it
+    if !(0..width).contains(&rel_x) || !(0..height).contains(&rel_y) {
+        return Err(format!(
+            "({}, {}) is outside the {}x{} window",
+            rel_x, rel_y, width, height
+        ));
+    }
+    Ok((x + rel_x, y + rel_y))
+}
+
+/// Focus a window and click `count` times at `rel_x`/`rel_y` from its
+/// top-left corner, returning where the click went on the screen
+pub fn click_in_window(
+    window_id: &str,
+    rel_x: i32,
+    rel_y: i32,
+    button: &str,
+    count: u32,
+) -> Result<(i32, i32), String> {
+    let (x, y) = window_point(window_geometry(window_id)?, rel_x, rel_y)?;
+    focus_window_by_id(window_id)?;
+    std::thread::sleep(FOCUS_SETTLE);
+    click_at(x, y, button, count, 0)?;
+    Ok((x, y))
+}
+
+/// Top-left corner of the first place the image is shown on the screen
+pub fn find_image_on_screen(image: &Path, tolerance: u8) -> Result<Option<(i32, i32)>, String> {
+    let template =
+        fs::read(image).map_err(|e| format!("Failed to read {}: {}", image.display(), e))?;
+    let capture = capture_screen_temp()?;
+    let screen = fs::read(&capture).map_err(|e| format!("Failed to read capture: {}", e));
+    let _ = fs::remove_file(&capture);
+    find_image(&screen?, &template, tolerance)
+}
+
+/// Where the image looks most like it is shown on the screen, if it is at
+/// least `confidence` alike (see `match_image`)
+pub fn locate_image_on_screen(image: &Path, confidence: f64) -> Result<Option<ImageMatch>, String> {
+    let template =
+        fs::read(image).map_err(|e| format!("Failed to read {}: {}", image.display(), e))?;
+    let capture = capture_screen_temp()?;
+    let screen = fs::read(&capture).map_err(|e| format!("Failed to read capture: {}", e));
+    let _ = fs::remove_file(&capture);
+    match_image(&screen?, &template, confidence)
+}
+
+/// Scroll in `direction` until the image is on the screen and return where
+/// it is; fails once it was not found after `max_scrolls` scrolls
+pub fn scroll_to_image(
+    image: &Path,
+    tolerance: u8,
+    direction: &str,
+    max_scrolls: u32,
+) -> Result<(i32, i32), String> {
+    for scrolled in 0..=max_scrolls {
+        if scrolled > 0 {
+            scroll(SCROLL_SEARCH_STEP, direction)?;
+            std::thread::sleep(SCROLL_SEARCH_SETTLE);
+        }
+        if let Some(position) = find_image_on_screen(image, tolerance)? {
+            return Ok(position);
+        }
+    }
+    Err(format!(
+        "{} did not come into view after scrolling {} {} times",
+        image.display(),
+        direction,
+        max_scrolls
+    ))
+}
+
+/// Top-left corner of the first place `template` appears in `screen`,
+/// scanning row by row
+///
+/// Both are PNG data. Every pixel has to be within `tolerance` in each color
+/// channel; fully transparent template pixels match anything.
+pub fn find_image(
+    screen: &[u8],
+    template: &[u8],
+    tolerance: u8,
+) -> Result<Option<(i32, i32)>, String> {
+    let (width, height, screen) = decode_png(screen)?;
+    let (template_width, template_height, template) = decode_png(template)?;
+    if template_width == 0 || template_height == 0 {
+        return Err("Image is empty".to_string());
+    }
+    if template_width > width || template_height > height {
+        return Ok(None);
+    }
+
+    let (width, template_width) = (width as usize, template_width as usize);
+    // Pixels to compare, as (offset in the screen, color)
+    let pixels: Vec<(usize, (u8, u8, u8))> = template
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(_, p)| p[3] != 0)
+        .map(|(i, p)| {
+            let offset = (i / template_width) * width + i % template_width;
+            (offset, (p[0], p[1], p[2]))
+        })
+        .collect();
+
+    for y in 0..=(height - template_height) as usize {
+        for x in 0..=width - template_width {
+            let origin = y * width + x;
+            let found = pixels.iter().all(|(offset, color)| {
+                let p = &screen[(origin + offset) * 4..][..3];
+                colors_match((p[0], p[1], p[2]), *color, tolerance)
+            });
+            if found {
+                return Ok(Some((x as i32, y as i32)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Where a template was found by `match_image`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageMatch {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Normalized cross-correlation, 1.0 for an exact match
+    pub confidence: f64,
+}
+
+impl ImageMatch {
+    pub fn center(&self) -> (i32, i32) {
+        (
+            self.x + self.width as i32 / 2,
+            self.y + self.height as i32 / 2,
+        )
+    }
+}
+
+/// Luminance of an image, one value per pixel
+struct Gray {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+}
+
+impl Gray {
+    fn from_rgba(width: u32, height: u32, rgba: &[u8]) -> Self {
+        let pixels = rgba
+            .chunks_exact(4)
+            .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+            .collect();
+        Gray {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        }
+    }
+
+    /// The averages of `factor`×`factor` blocks; a partial last row or
+    /// column of blocks is dropped
+    fn shrink(&self, factor: usize) -> Self {
+        let (width, height) = (self.width / factor, self.height / factor);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let sum: f32 = (0..factor)
+                    .flat_map(|dy| {
+                        let row = (y * factor + dy) * self.width + x * factor;
+                        &self.pixels[row..row + factor]
+                    })
+                    .sum();
+                pixels.push(sum / (factor * factor) as f32);
+            }
+        }
+        Gray {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Sums of the values and their squares over any window of an image, from
+/// summed-area tables
+struct WindowSums {
+    stride: usize,
+    values: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl WindowSums {
+    fn new(image: &Gray) -> Self {
+        let stride = image.width + 1;
+        let mut values = vec![0.0; stride * (image.height + 1)];
+        let mut squares = values.clone();
+        for y in 0..image.height {
+            let (mut row, mut row_squares) = (0.0, 0.0);
+            for x in 0..image.width {
+                let v = image.pixels[y * image.width + x] as f64;
+                row += v;
+                row_squares += v * v;
+                let at = (y + 1) * stride + x + 1;
+                values[at] = values[at - stride] + row;
+                squares[at] = squares[at - stride] + row_squares;
+            }
+        }
+        WindowSums {
+            stride,
+            values,
+            squares,
+        }
+    }
+
+    fn get(&self, x: usize, y: usize, width: usize, height: usize) -> (f64, f64) {
+        let sum = |table: &[f64]| {
+            let (top, bottom) = (y * self.stride, (y + height) * self.stride);
+            table[bottom + x + width] - table[bottom + x] - table[top + x + width] + table[top + x]
+        };
+        (sum(&self.values), sum(&self.squares))
+    }
+}
+
+/// A template with its mean taken out, ready to be correlated
+struct Template {
+    width: usize,
+    height: usize,
+    deviations: Vec<f32>,
+    norm: f64,
+}
+
+impl Template {
+    /// `None` for a template of a single flat color, which matches any
+    /// flat area equally well
+    fn new(image: &Gray) -> Option<Self> {
+        let mean = image.pixels.iter().sum::<f32>() / image.pixels.len() as f32;
+        let deviations: Vec<f32> = image.pixels.iter().map(|v| v - mean).collect();
+        let norm = deviations
+            .iter()
+            .map(|d| (*d as f64).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        (norm > 1e-3).then_some(Template {
+            width: image.width,
+            height: image.height,
+            deviations,
+            norm,
+        })
+    }
+
+    /// Normalized cross-correlation with the area of `image` at `x`/`y`
+    fn correlation(&self, image: &Gray, sums: &WindowSums, x: usize, y: usize) -> f64 {
+        let (sum, squares) = sums.get(x, y, self.width, self.height);
+        let variance = squares - sum * sum / (self.width * self.height) as f64;
+        if variance <= 1e-6 {
+            return 0.0;
+        }
+        let mut cross = 0.0f64;
+        for row in 0..self.height {
+            let start = (y + row) * image.width + x;
+            let pixels = &image.pixels[start..start + self.width];
+            let deviations = &self.deviations[row * self.width..][..self.width];
+            cross += pixels
+                .iter()
+                .zip(deviations)
+                .map(|(p, d)| p * d)
+                .sum::<f32>() as f64;
+        }
+        cross / (self.norm * variance.sqrt())
+    }
+}
+
+/// The place `template` correlates best with in `screen`, if its normalized
+/// cross-correlation is at least `confidence` (0.0 to 1.0)
+///
+/// Both are PNG data, compared in gray, so a template still matches when the
+/// screen is a bit brighter, darker or blurred. Transparency is ignored, so
+/// templates should be cropped closely. Large templates are searched for on
+/// shrunk copies first and only the best places are compared at full size.
+pub fn match_image(
+    screen: &[u8],
+    template: &[u8],
+    confidence: f64,
+) -> Result<Option<ImageMatch>, String> {
+    let (width, height, screen) = decode_png(screen)?;
+    let (template_width, template_height, template) = decode_png(template)?;
+    if template_width == 0 || template_height == 0 {
+        return Err("Image is empty".to_string());
+    }
+    if template_width > width || template_height > height {
+        return Ok(None);
+    }
+    let screen = Gray::from_rgba(width, height, &screen);
+    let template = Gray::from_rgba(template_width, template_height, &template);
+    let fine =
+        Template::new(&template).ok_or("Image is a single flat color, which could be anywhere")?;
+
+    let factor = (template.width.min(template.height) / MATCH_MIN_SIDE).clamp(1, MATCH_MAX_SHRINK);
+    let coarse = match factor {
+        1 => None,
+        _ => Template::new(&template.shrink(factor)).map(|t| (screen.shrink(factor), t)),
+    };
+    let sums = WindowSums::new(&screen);
+    let mut best: Option<(f64, usize, usize)> = None;
+    let mut consider = |x: usize, y: usize| {
+        let score = fine.correlation(&screen, &sums, x, y);
+        if best.is_none_or(|(top, _, _)| score > top) {
+            best = Some((score, x, y));
+        }
+    };
+    match coarse {
+        Some((small, template)) => {
+            let small_sums = WindowSums::new(&small);
+            let mut candidates = Vec::new();
+            for y in 0..=small.height - template.height {
+                for x in 0..=small.width - template.width {
+                    candidates.push((template.correlation(&small, &small_sums, x, y), x, y));
+                }
+            }
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+            // The image can sit anywhere within a block, so the blocks
+            // around each candidate are searched pixel by pixel
+            for (_, x, y) in candidates.into_iter().take(MATCH_CANDIDATES) {
+                let xs = (x * factor).saturating_sub(factor)
+                    ..=((x + 1) * factor).min(screen.width - fine.width);
+                for y in (y * factor).saturating_sub(factor)
+                    ..=((y + 1) * factor).min(screen.height - fine.height)
+                {
+                    for x in xs.clone() {
+                        consider(x, y);
+                    }
+                }
+            }
+        }
+        None => {
+            for y in 0..=screen.height - fine.height {
+                for x in 0..=screen.width - fine.width {
+                    consider(x, y);
+                }
+            }
+        }
+    }
+    Ok(best
+        .filter(|(score, _, _)| *score >= confidence)
+        .map(|(score, x, y)| ImageMatch {
+            x: x as i32,
+            y: y as i32,
+            width: template_width,
+            height: template_height,
+            confidence: (score.min(1.0) * 1000.0).round() / 1000.0,
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_point() {
+        let window = (100, 50, 800, 600);
+        assert_eq!(window_point(window, 10, 20).unwrap(), (110, 70));
+        assert_eq!(window_point(window, -10, -1).unwrap(), (890, 649));
+        assert!(window_point(window, 800, 0).is_err());
+        assert!(window_point(window, 0, -601).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_find_image() {
+        use crate::capture::encode_png;
+
+        // A 6x4 dark screen with a 2x2 pattern at (3, 1)
+        let mut pixels = [[10, 10, 10, 255]; 24];
+        pixels[9] = [200, 0, 0, 255];
+        pixels[10] = [0, 200, 0, 255];
+        pixels[15] = [0, 0, 200, 255];
+        pixels[16] = [200, 200, 200, 255];
+        let screen = encode_png(6, 4, &pixels.concat()).unwrap();
+
+        let pattern = [
+            [198, 2, 0, 255],
+            [0, 200, 0, 255],
+            [0, 0, 200, 255],
+            [0, 0, 0, 0],
+        ];
+        let template = encode_png(2, 2, &pattern.concat()).unwrap();
+        assert_eq!(find_image(&screen, &template, 4).unwrap(), Some((3, 1)));
+        assert_eq!(find_image(&screen, &template, 0).unwrap(), None);
+
+        let too_big = encode_png(7, 1, &[0; 28]).unwrap();
+        assert_eq!(find_image(&screen, &too_big, 255).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "capture")]
+    fn test_match_image() {
+        use crate::capture::encode_png;
+
+        // A noisy 64x48 screen, and a 20x16 piece of it at (37, 21)
+        let mut seed = 7u32;
+        let screen: Vec<[u8; 4]> = (0..64 * 48)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let v = (seed >> 16) as u8;
+                [v, v / 2, 255 - v, 255]
+            })
+            .collect();
+        let piece: Vec<[u8; 4]> = (21..37)
+            .flat_map(|y| screen[y * 64 + 37..y * 64 + 57].to_vec())
+            .collect();
+        let screen_png = encode_png(64, 48, &screen.concat()).unwrap();
+
+        let template = encode_png(20, 16, &piece.concat()).unwrap();
+        let found = match_image(&screen_png, &template, 0.95).unwrap().unwrap();
+        assert_eq!((found.x, found.y, found.confidence), (37, 21, 1.0));
+        assert_eq!(found.center(), (47, 29));
+
+        // Dimmer, it still correlates fully
+        let dimmed: Vec<u8> = piece
+            .iter()
+            .flat_map(|p| [p[0] / 2 + 20, p[1] / 2 + 20, p[2] / 2 + 20, 255])
+            .collect();
+        let template = encode_png(20, 16, &dimmed).unwrap();
+        let found = match_image(&screen_png, &template, 0.95).unwrap().unwrap();
+        assert_eq!((found.x, found.y), (37, 21));
+
+        let mirrored: Vec<[u8; 4]> = piece.iter().rev().copied().collect();
+        let template = encode_png(20, 16, &mirrored.concat()).unwrap();
+        assert_eq!(match_image(&screen_png, &template, 0.9).unwrap(), None);
+
+        let flat = encode_png(4, 4, &[90; 64]).unwrap();
+        assert!(match_image(&screen_png, &flat, 0.9).is_err());
+    }
+
+    #[test]
+    fn test_unanchored_points_are_absolute() {
+        assert_eq!(resolve_point(12, -3, None).unwrap(), (12, -3));
+    }
+}
@@ -1,4 +1,4 @@
 pub fn process_mcp(data: &str) -> Result<String, String> {
     // Process MCP protocol data
     Err(format!("MCP under development: received {}", data))
-}
\ No newline at end of file
+}
@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Upper bounds (seconds) of the request latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+/// In-memory daemon metrics, reset when the daemon restarts
+///
+/// Rendered in the Prometheus text format by [`Metrics::render_prometheus`]
+/// or as JSON by [`Metrics::snapshot`].
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    requests: BTreeMap<String, RequestMetrics>,
+    playback_runs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RequestMetrics {
+    pub count: u64,
+    pub errors: u64,
+    /// Total time spent handling this request type, in seconds
+    pub latency_sum: f64,
+    pub latency_max: f64,
+    /// Cumulative counts per entry of the latency buckets
+    #[serde(skip)]
+    buckets: Vec<u64>,
+}
+
+/// JSON view of the metrics, returned by the `metrics` request
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub uptime_seconds: u64,
+    pub playback_runs: u64,
+    pub requests: BTreeMap<String, RequestMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            requests: BTreeMap::new(),
+            playback_runs: 0,
+        }
+    }
+
+    /// Count one handled request and how long it took
+    pub fn record_request(&mut self, request_type: &str, success: bool, elapsed: Duration) {
+        let entry = self
+            .requests
+            .entry(request_type.to_string())
+            .or_insert_with(|| RequestMetrics {
+                buckets: vec![0; LATENCY_BUCKETS.len()],
+                ..Default::default()
+            });
+        let seconds = elapsed.as_secs_f64();
+        entry.count += 1;
+        if !success {
+            entry.errors += 1;
+        }
+        entry.latency_sum += seconds;
+        entry.latency_max = entry.latency_max.max(seconds);
+        for (bucket, bound) in entry.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+    }
+
+    /// Count a started sequence playback
+    pub fn record_playback(&mut self) {
+        self.playback_runs += 1;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            uptime_seconds: self.started.elapsed().as_secs(),
+            playback_runs: self.playback_runs,
+            requests: self.requests.clone(),
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP casper_uptime_seconds Time since the daemon started.\n");
+        out.push_str("# TYPE casper_uptime_seconds gauge\n");
+        let _ = writeln!(
+            out,
+            "casper_uptime_seconds {}",
+            self.started.elapsed().as_secs()
+        );
+
+        out.push_str("# HELP casper_playback_runs_total Sequence playbacks started.\n");
+        out.push_str("# TYPE casper_playback_runs_total counter\n");
+        let _ = writeln!(out, "casper_playback_runs_total {}", self.playback_runs);
+
+        out.push_str("# HELP casper_requests_total Requests handled, by request type.\n");
+        out.push_str("# TYPE casper_requests_total counter\n");
+        for (name, request) in &self.requests {
+            let _ = writeln!(
+                out,
+                "casper_requests_total{{type=\"{}\"}} {}",
+                escape_label(name),
+                request.count
+            );
+        }
+
+        out.push_str(
+            "# HELP casper_request_errors_total Requests answered with an error, by request type.\n",
+        );
+        out.push_str("# TYPE casper_request_errors_total counter\n");
+        for (name, request) in &self.requests {
+            let _ = writeln!(
+                out,
+                "casper_request_errors_total{{type=\"{}\"}} {}",
+                escape_label(name),
+                request.errors
+            );
+        }
+
+        out.push_str(
+            "# HELP casper_request_duration_seconds Time spent handling requests, by request type.\n",
+        );
+        out.push_str("# TYPE casper_request_duration_seconds histogram\n");
+        for (name, request) in &self.requests {
+            let label = escape_label(name);
+            for (count, bound) in request.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "casper_request_duration_seconds_bucket{{type=\"{}\",le=\"{}\"}} {}",
+                    label, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "casper_request_duration_seconds_bucket{{type=\"{}\",le=\"+Inf\"}} {}",
+                label, request.count
+            );
+            let _ = writeln!(
+                out,
+                "casper_request_duration_seconds_sum{{type=\"{}\"}} {}",
+                label, request.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "casper_request_duration_seconds_count{{type=\"{}\"}} {}",
+                label, request.count
+            );
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_counts_and_histogram() {
+        let mut metrics = Metrics::new();
+        metrics.record_request("ping", true, Duration::from_millis(1));
+        metrics.record_request("ping", false, Duration::from_secs(3));
+        metrics.record_playback();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.playback_runs, 1);
+        assert_eq!(snapshot.requests["ping"].count, 2);
+        assert_eq!(snapshot.requests["ping"].errors, 1);
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("casper_requests_total{type=\"ping\"} 2\n"));
+        assert!(text.contains("casper_request_errors_total{type=\"ping\"} 1\n"));
+        assert!(
+            text.contains("casper_request_duration_seconds_bucket{type=\"ping\",le=\"0.005\"} 1\n")
+        );
+        assert!(
+            text.contains("casper_request_duration_seconds_bucket{type=\"ping\",le=\"5\"} 2\n")
+        );
+        assert!(
+            text.contains("casper_request_duration_seconds_bucket{type=\"ping\",le=\"+Inf\"} 2\n")
+        );
+        assert!(text.contains("casper_playback_runs_total 1\n"));
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
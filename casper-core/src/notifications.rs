@@ -1,10 +1,165 @@
-use notify_rust::Notification;
+use notify_rust::{Notification, Timeout};
+use serde::Deserialize;
+
+/// A button on a notification
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationAction {
+    pub id: String,
+    pub label: String,
+}
+
+/// Extras for a notification
+///
+/// Each platform uses the ones its notification service understands and
+/// ignores the rest, so the same request works everywhere. Buttons on
+/// Windows toasts and macOS categories are not passed on by notify-rust yet.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NotificationOptions {
+    /// Buttons shown on the notification (Linux)
+    pub actions: Vec<NotificationAction>,
+    /// Category, e.g. "email.arrived" (Linux)
+    pub category: Option<String>,
+    /// "low", "normal" or "critical" (Linux)
+    pub urgency: Option<String>,
+    /// How long the notification stays up
+    pub timeout_ms: Option<u32>,
+    /// Name of a sound to play
+    pub sound: Option<String>,
+    /// Second line under the summary (macOS, Windows)
+    pub subtitle: Option<String>,
+    /// Application user model id the toast is shown for (Windows)
+    pub app_id: Option<String>,
+    /// Wait until the notification is closed and report the chosen action
+    pub wait: bool,
+}
 
 pub fn show_notification(summary: &str, body: &str) -> Result<(), String> {
-    Notification::new()
-        .summary(summary)
-        .body(body)
-        .show()
-        .map_err(|e| e.to_string())?;
+    show_notification_with(summary, body, &NotificationOptions::default())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Show a notification with platform-specific extras
+///
+/// With `wait`, blocks until the notification is closed and returns the id
+/// of the action that was clicked, if any.
+pub fn show_notification_with(
+    summary: &str,
+    body: &str,
+    options: &NotificationOptions,
+) -> Result<Option<String>, String> {
+    let mut notification = Notification::new();
+    notification.summary(summary).body(body);
+    if let Some(subtitle) = &options.subtitle {
+        notification.subtitle(subtitle);
+    }
+    if let Some(sound) = &options.sound {
+        notification.sound_name(sound);
+    }
+    if let Some(ms) = options.timeout_ms {
+        notification.timeout(Timeout::Milliseconds(ms));
+    }
+    for action in &options.actions {
+        notification.action(&action.id, &action.label);
+    }
+    platform::show(&mut notification, options)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::NotificationOptions;
+    use notify_rust::{Hint, Notification, Urgency};
+
+    pub fn show(
+        notification: &mut Notification,
+        options: &NotificationOptions,
+    ) -> Result<Option<String>, String> {
+        if let Some(category) = &options.category {
+            notification.hint(Hint::Category(category.clone()));
+        }
+        if let Some(urgency) = &options.urgency {
+            notification.urgency(parse_urgency(urgency)?);
+        }
+        let handle = notification.show().map_err(|e| e.to_string())?;
+        if !options.wait {
+            return Ok(None);
+        }
+        let mut chosen = None;
+        handle.wait_for_action(|action| {
+            // "__closed" is what notify-rust reports for a dismissed notification
+            if action != "__closed" {
+                chosen = Some(action.to_string());
+            }
+        });
+        Ok(chosen)
+    }
+
+    pub(super) fn parse_urgency(urgency: &str) -> Result<Urgency, String> {
+        match urgency.to_lowercase().as_str() {
+            "low" => Ok(Urgency::Low),
+            "normal" => Ok(Urgency::Normal),
+            "critical" => Ok(Urgency::Critical),
+            _ => Err(format!(
+                "Unknown urgency '{}' (use low, normal or critical)",
+                urgency
+            )),
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::NotificationOptions;
+    use notify_rust::Notification;
+
+    pub fn show(
+        notification: &mut Notification,
+        options: &NotificationOptions,
+    ) -> Result<Option<String>, String> {
+        if let Some(app_id) = &options.app_id {
+            notification.app_id(app_id);
+        }
+        // Toasts can't be waited on; clicks go to the app registered for
+        // the app id
+        notification.show().map_err(|e| e.to_string())?;
+        Ok(None)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::NotificationOptions;
+    use notify_rust::Notification;
+
+    pub fn show(
+        notification: &mut Notification,
+        _options: &NotificationOptions,
+    ) -> Result<Option<String>, String> {
+        notification.show().map_err(|e| e.to_string())?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_options() {
+        let options: NotificationOptions = serde_json::from_value(serde_json::json!({
+            "summary": "Build finished",
+            "actions": [{ "id": "open", "label": "Open log" }],
+            "urgency": "critical",
+            "wait": true
+        }))
+        .unwrap();
+        assert_eq!(options.actions[0].id, "open");
+        assert!(options.wait && options.category.is_none());
+
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            assert!(platform::parse_urgency("Critical").is_ok());
+            assert!(platform::parse_urgency("urgent").is_err());
+        }
+    }
+}
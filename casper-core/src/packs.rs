@@ -0,0 +1,103 @@
+use crate::actions::{ActionLibrary, ActionSequence};
+use crate::pipeline::{Pipeline, Pipelines};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Example automations shipped with casper, one pack file each
+const BUILTIN_PACKS: &[&str] = &[
+    include_str!("../packs/morning_workspace.json"),
+    include_str!("../packs/meeting_mode.json"),
+    include_str!("../packs/screen_text_to_clipboard.json"),
+    include_str!("../packs/click_by_description.json"),
+];
+
+/// Sequences and pipelines that are installed together
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pack {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub sequences: Vec<ActionSequence>,
+    #[serde(default)]
+    pub pipelines: Vec<Pipeline>,
+}
+
+impl Pack {
+    /// Load a pack file (JSON)
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid pack {}: {}", path.display(), e))
+    }
+
+    /// Check that the pack has something to install and that its pipelines
+    /// are valid
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("Pack needs a name".to_string());
+        }
+        if self.sequences.is_empty() && self.pipelines.is_empty() {
+            return Err(format!("Pack '{}' is empty", self.name));
+        }
+        if let Some(sequence) = self.sequences.iter().find(|s| s.actions.is_empty()) {
+            return Err(format!("Sequence '{}' has no steps", sequence.name));
+        }
+        for pipeline in &self.pipelines {
+            pipeline.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Add the pack's sequences and pipelines, replacing any with the same
+    /// names (replaced sequences keep their earlier versions)
+    pub fn install(
+        &self,
+        library: &mut ActionLibrary,
+        pipelines: &mut Pipelines,
+    ) -> Result<(), String> {
+        self.validate()?;
+        for pipeline in &self.pipelines {
+            pipelines.add(pipeline.clone())?;
+        }
+        for sequence in &self.sequences {
+            library.add_sequence(sequence.clone());
+        }
+        library.save_all()
+    }
+
+    /// Whether every sequence and pipeline of the pack is there
+    pub fn is_installed(&self, library: &ActionLibrary, pipelines: &Pipelines) -> bool {
+        self.sequences
+            .iter()
+            .all(|s| library.get_sequence(&s.name).is_some())
+            && self
+                .pipelines
+                .iter()
+                .all(|p| pipelines.get(&p.name).is_some())
+    }
+}
+
+/// The example packs shipped with casper
+pub fn builtin_packs() -> Vec<Pack> {
+    BUILTIN_PACKS
+        .iter()
+        .map(|text| {
+            serde_json::from_str(text).unwrap_or_else(|e| panic!("Invalid built-in pack: {}", e))
+        })
+        .collect()
+}
+
+/// A built-in pack by name, or a pack file by path
+pub fn find_pack(name: &str) -> Result<Pack, String> {
+    if let Some(pack) = builtin_packs().into_iter().find(|p| p.name == name) {
+        return Ok(pack);
+    }
+    let path = Path::new(name);
+    if path.is_file() {
+        return Pack::load(path);
+    }
+    Err(format!("No pack '{}'", name))
+}
@@ -0,0 +1,348 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Check that a profile name is safe to use in file and socket names
+pub fn validate_profile_name(profile: &str) -> Result<(), String> {
+    if profile.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+
+    if !profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid profile name '{}': use letters, digits, '-' or '_'",
+            profile
+        ));
+    }
+
+    Ok(())
+}
+
+/// Config, data or state, the three XDG base directories Casper keeps files in
+#[derive(Debug, Clone, Copy)]
+enum Base {
+    Config,
+    Data,
+    State,
+}
+
+/// Files and directories of the old `~/.casper` layout, by where they belong now
+const LEGACY_LAYOUT: &[(&str, Base)] = &[
+    ("config.env", Base::Config),
+    ("config.json", Base::Config),
+    ("shortcuts.json", Base::Config),
+    ("window_rules.json", Base::Config),
+    ("pipelines.json", Base::Config),
+    ("actions", Base::Data),
+    ("store.json", Base::Data),
+    ("usage.json", Base::Data),
+    ("tmp", Base::State),
+];
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string()))
+}
+
+/// An XDG base directory variable, or `$HOME/<fallback>` when it is unset or relative
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    match std::env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => home_dir().join(fallback),
+    }
+}
+
+fn base_dir(base: Base) -> PathBuf {
+    let dir = match base {
+        Base::Config => xdg_dir("XDG_CONFIG_HOME", ".config"),
+        Base::Data => xdg_dir("XDG_DATA_HOME", ".local/share"),
+        Base::State => xdg_dir("XDG_STATE_HOME", ".local/state"),
+    };
+    dir.join("casper")
+}
+
+fn with_profile(dir: PathBuf, profile: Option<&str>) -> PathBuf {
+    match profile {
+        Some(name) => dir.join("profiles").join(name),
+        None => dir,
+    }
+}
+
+/// Settings of a profile (~/.config/casper, or ~/.config/casper/profiles/<name>)
+pub fn config_dir(profile: Option<&str>) -> PathBuf {
+    with_profile(base_dir(Base::Config), profile)
+}
+
+/// Sequences and other saved data of a profile (~/.local/share/casper)
+pub fn data_dir(profile: Option<&str>) -> PathBuf {
+    with_profile(base_dir(Base::Data), profile)
+}
+
+/// Data worth keeping across restarts but not worth backing up (~/.local/state/casper)
+pub fn state_dir(profile: Option<&str>) -> PathBuf {
+    with_profile(base_dir(Base::State), profile)
+}
+
+/// Per-login directory for sockets and scratch files: $XDG_RUNTIME_DIR, or
+/// the system temporary directory when there is none
+pub fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => std::env::temp_dir(),
+    }
+}
+
+/// Where Casper kept everything before it followed the XDG base directories
+pub fn legacy_dir(profile: Option<&str>) -> PathBuf {
+    with_profile(home_dir().join(".casper"), profile)
+}
+
+/// Directory holding the action library of a profile
+pub fn actions_dir(profile: Option<&str>) -> PathBuf {
+    data_dir(profile).join("actions")
+}
+
+/// Settings file written by the setup wizard (dotenv format)
+pub fn config_file(profile: Option<&str>) -> PathBuf {
+    config_dir(profile).join("config.env")
+}
+
+/// Daemon settings file (JSON)
+pub fn daemon_config_file(profile: Option<&str>) -> PathBuf {
+    config_dir(profile).join("config.json")
+}
+
+/// Locally kept usage counts (only written when usage statistics are enabled)
+pub fn usage_file(profile: Option<&str>) -> PathBuf {
+    data_dir(profile).join("usage.json")
+}
+
+/// Salt of a library key derived from a passphrase
+pub fn salt_file(profile: Option<&str>) -> PathBuf {
+    data_dir(profile).join("library.salt")
+}
+
+/// Window placement rules (JSON)
+pub fn window_rules_file(profile: Option<&str>) -> PathBuf {
+    config_dir(profile).join("window_rules.json")
+}
+
+/// Saved window layouts (JSON)
+pub fn layouts_file(profile: Option<&str>) -> PathBuf {
+    config_dir(profile).join("layouts.json")
+}
+
+/// Saved pipelines (JSON)
+pub fn pipelines_file(profile: Option<&str>) -> PathBuf {
+    config_dir(profile).join("pipelines.json")
+}
+
+/// User additions to the application shortcut table (JSON), shared by all profiles
+pub fn shortcuts_file() -> PathBuf {
+    config_dir(None).join("shortcuts.json")
+}
+
+/// Reference images of `AssertScreen` steps, shared by all profiles
+pub fn references_dir() -> PathBuf {
+    data_dir(None).join("references")
+}
+
+/// Key/value store shared by clients (JSON)
+pub fn store_file(profile: Option<&str>) -> PathBuf {
+    data_dir(profile).join("store.json")
+}
+
+/// Scratch data that must survive a crash, such as journals of in-progress recordings
+pub fn tmp_dir(profile: Option<&str>) -> PathBuf {
+    state_dir(profile).join("tmp")
+}
+
+/// Run logs of the sequences, one file per sequence
+pub fn runs_dir(profile: Option<&str>) -> PathBuf {
+    state_dir(profile).join("runs")
+}
+
+/// Screenshots of the screen as failed commands and playbacks left it
+pub fn errors_dir(profile: Option<&str>) -> PathBuf {
+    state_dir(profile).join("errors")
+}
+
+/// Screenshots taken to be looked at once, such as those for the AI; kept
+/// within the limits of the `screenshots` config
+pub fn screenshots_dir(profile: Option<&str>) -> PathBuf {
+    state_dir(profile).join("screenshots")
+}
+
+/// A short-lived file such as a screenshot taken for the AI, in the runtime directory
+pub fn scratch_file(name: &str) -> PathBuf {
+    let dir = runtime_dir().join("casper");
+    // A missing directory shows up as an error from whoever writes the file
+    let _ = fs::create_dir_all(&dir);
+    dir.join(name)
+}
+
+/// Default socket path for a profile ($XDG_RUNTIME_DIR/casper.sock or casper-<name>.sock)
+pub fn socket_path(profile: Option<&str>) -> PathBuf {
+    match profile {
+        Some(name) => runtime_dir().join(format!("casper-{}.sock", name)),
+        None => runtime_dir().join("casper.sock"),
+    }
+}
+
+/// What moving ~/.casper to the XDG directories did
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// A "from → to" line for each moved entry
+    pub moved: Vec<String>,
+    /// Old entries left in place because the new location already has them
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Move the files of every profile from ~/.casper to the XDG directories
+///
+/// Entries that already exist at the new location stay where they are and
+/// are reported as skipped.
+pub fn migrate_legacy_dirs() -> Result<Migration, String> {
+    migrate(&legacy_dir(None), &|base, profile| {
+        with_profile(base_dir(base), profile)
+    })
+}
+
+fn migrate(
+    legacy: &Path,
+    target: &dyn Fn(Base, Option<&str>) -> PathBuf,
+) -> Result<Migration, String> {
+    let mut migration = Migration::default();
+    if !legacy.is_dir() {
+        return Ok(migration);
+    }
+    let profiles: Vec<String> = fs::read_dir(legacy.join("profiles"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| validate_profile_name(name).is_ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let all = std::iter::once(None).chain(profiles.iter().map(|name| Some(name.as_str())));
+    for profile in all {
+        let from_dir = with_profile(legacy.to_path_buf(), profile);
+        for (name, base) in LEGACY_LAYOUT {
+            let from = from_dir.join(name);
+            let to = target(*base, profile).join(name);
+            if !from.exists() {
+                continue;
+            }
+            if to.exists() {
+                migration.skipped.push(from);
+                continue;
+            }
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            move_path(&from, &to)?;
+            migration
+                .moved
+                .push(format!("{} → {}", from.display(), to.display()));
+        }
+    }
+
+    // Only succeeds for directories that are now empty
+    for name in &profiles {
+        let _ = fs::remove_dir(with_profile(legacy.to_path_buf(), Some(name)));
+    }
+    let _ = fs::remove_dir(legacy.join("profiles"));
+    let _ = fs::remove_dir(legacy);
+    Ok(migration)
+}
+
+/// Rename, or copy and delete when the target is on another filesystem
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_recursive(from, to)?;
+    let removed = if from.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    };
+    removed.map_err(|e| format!("Failed to remove {}: {}", from.display(), e))
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> Result<(), String> {
+    let failed = |e: std::io::Error| format!("Failed to copy {}: {}", from.display(), e);
+    if from.is_dir() {
+        fs::create_dir_all(to).map_err(failed)?;
+        for entry in fs::read_dir(from).map_err(failed)? {
+            let entry = entry.map_err(failed)?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ()).map_err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_name_validation() {
+        assert!(validate_profile_name("work").is_ok());
+        assert!(validate_profile_name("home_2-b").is_ok());
+        assert!(validate_profile_name("").is_err());
+        assert!(validate_profile_name("../etc").is_err());
+        assert!(validate_profile_name("a b").is_err());
+    }
+
+    #[test]
+    fn test_profile_paths_are_isolated() {
+        assert_ne!(config_dir(None), config_dir(Some("work")));
+        assert!(config_dir(Some("work")).ends_with("casper/profiles/work"));
+        assert!(data_dir(Some("work")).ends_with("casper/profiles/work"));
+        assert_eq!(
+            socket_path(Some("work")),
+            runtime_dir().join("casper-work.sock")
+        );
+        assert_ne!(socket_path(None), socket_path(Some("work")));
+    }
+
+    #[test]
+    fn test_migrate_legacy_layout() {
+        let root = std::env::temp_dir().join(format!("casper-migrate-{}", std::process::id()));
+        let legacy = root.join("legacy");
+        fs::create_dir_all(legacy.join("actions")).unwrap();
+        fs::create_dir_all(legacy.join("profiles/work")).unwrap();
+        fs::write(legacy.join("actions/a.json"), "{}").unwrap();
+        fs::write(legacy.join("config.json"), "{}").unwrap();
+        fs::write(legacy.join("profiles/work/store.json"), "{}").unwrap();
+        fs::write(legacy.join("notes.txt"), "kept").unwrap();
+
+        let target = |base: Base, profile: Option<&str>| {
+            with_profile(root.join(format!("{:?}", base).to_lowercase()), profile)
+        };
+        // An existing file at the new location wins
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::write(root.join("config/config.json"), "new").unwrap();
+
+        let migration = migrate(&legacy, &target).unwrap();
+        assert_eq!(migration.moved.len(), 2);
+        assert_eq!(migration.skipped, vec![legacy.join("config.json")]);
+        assert!(root.join("data/actions/a.json").exists());
+        assert!(root.join("data/profiles/work/store.json").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("config/config.json")).unwrap(),
+            "new"
+        );
+        // Unknown files keep the old directory around
+        assert!(legacy.join("notes.txt").exists());
+        assert!(!legacy.join("profiles").exists());
+        assert!(migrate(&legacy, &target).unwrap().moved.is_empty());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::actions::Action;
+use crate::ai_vision::AIVision;
+use crate::capture::{capture_region, capture_screen_temp};
+use crate::commands::CommandDeadline;
+use crate::executor::execute_action;
+use crate::paths;
+use regex::Regex;
+#[cfg(feature = "http")]
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Values bound by pipeline steps, by variable name
+pub type Variables = BTreeMap<String, String>;
+
+/// A named flow in which steps bind their output to variables that later
+/// steps use as `${name}`, e.g. reading a 2FA code and typing it:
+///
+/// ```json
+/// { "name": "type_2fa_code", "steps": [
+///   { "type": "capture", "as": "shot" },
+///   { "type": "ocr", "image": "${shot}", "as": "text" },
+///   { "type": "extract", "input": "${text}", "pattern": "\\b(\\d{6})\\b", "as": "code" },
+///   { "type": "action", "action": { "type": "TypeText", "text": "${code}" } } ] }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pipeline {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Variables that must be given when the pipeline is run
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    pub steps: Vec<PipelineStep>,
+}
+
+/// One step of a pipeline; every step except `action` binds its result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PipelineStep {
+    /// Screenshot of the screen or a region; binds the image path
+    Capture {
+        #[serde(default)]
+        region: Option<Region>,
+        #[serde(rename = "as")]
+        bind: String,
+    },
+    /// Text recognized in an image with tesseract
+    Ocr {
+        image: String,
+        #[serde(rename = "as")]
+        bind: String,
+    },
+    /// Answer of the AI to a prompt, about an image if one is given
+    Ai {
+        prompt: String,
+        #[serde(default)]
+        image: Option<String>,
+        #[serde(rename = "as")]
+        bind: String,
+    },
+    /// Body of an HTTP response
+    Http {
+        url: String,
+        #[serde(default = "default_method")]
+        method: String,
+        #[serde(default)]
+        body: Option<String>,
+        #[serde(rename = "as")]
+        bind: String,
+    },
+    /// First capture group (or the whole match) of a regular expression
+    Extract {
+        input: String,
+        pattern: String,
+        #[serde(rename = "as")]
+        bind: String,
+    },
+    /// A sequence action, with variables substituted in its fields
+    Action { action: Action },
+}
+
+/// A screen area in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+impl PipelineStep {
+    /// The variable the step binds, if any
+    pub fn binds(&self) -> Option<&str> {
+        match self {
+            PipelineStep::Capture { bind, .. }
+            | PipelineStep::Ocr { bind, .. }
+            | PipelineStep::Ai { bind, .. }
+            | PipelineStep::Http { bind, .. }
+            | PipelineStep::Extract { bind, .. } => Some(bind),
+            PipelineStep::Action { .. } => None,
+        }
+    }
+
+    /// Variables the step uses
+    fn references(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Ok(value) = serde_json::to_value(self) {
+            collect_references(&value, &mut names);
+        }
+        names
+    }
+}
+
+impl Pipeline {
+    /// Check that every variable is bound (or an input) before it is used
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("Pipeline needs a name".to_string());
+        }
+        if self.steps.is_empty() {
+            return Err(format!("Pipeline '{}' has no steps", self.name));
+        }
+        let mut bound: Vec<&str> = self.inputs.iter().map(String::as_str).collect();
+        for (index, step) in self.steps.iter().enumerate() {
+            for name in step.references() {
+                if !bound.contains(&name.as_str()) {
+                    return Err(format!(
+                        "Step {} uses ${{{}}} before it is bound",
+                        index + 1,
+                        name
+                    ));
+                }
+            }
+            if let PipelineStep::Extract { pattern, .. } = step {
+                Regex::new(pattern).map_err(|e| format!("Step {}: {}", index + 1, e))?;
+            }
+            if let Some(name) = step.binds() {
+                if name.is_empty() {
+                    return Err(format!("Step {} binds an empty variable name", index + 1));
+                }
+                bound.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the steps in order and return every bound variable
+    ///
+    /// `allow_capture` is false while screen capture is disabled, making
+    /// `capture` steps fail. Screenshots taken by the pipeline are deleted
+    /// once it ends.
+    pub async fn run(&self, inputs: Variables, allow_capture: bool) -> Result<Variables, String> {
+        self.validate()?;
+        if let Some(missing) = self.inputs.iter().find(|name| !inputs.contains_key(*name)) {
+            return Err(format!("Missing input '{}'", missing));
+        }
+        let mut variables = inputs;
+        let mut screenshots = Vec::new();
+        let mut result = Ok(());
+        for (index, step) in self.steps.iter().enumerate() {
+            match run_step(step, &variables, allow_capture, &mut screenshots).await {
+                Ok(Some(value)) => {
+                    if let Some(name) = step.binds() {
+                        variables.insert(name.to_string(), value);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    result = Err(format!("Step {}: {}", index + 1, e));
+                    break;
+                }
+            }
+        }
+        for screenshot in screenshots {
+            let _ = fs::remove_file(screenshot);
+        }
+        result.map(|_| variables)
+    }
+}
+
+async fn run_step(
+    step: &PipelineStep,
+    variables: &Variables,
+    allow_capture: bool,
+    screenshots: &mut Vec<String>,
+) -> Result<Option<String>, String> {
+    let value = match step {
+        PipelineStep::Capture { region, .. } => {
+            if !allow_capture {
+                return Err("Screen capture is disabled while privacy mode is on".to_string());
+            }
+            let path = match region {
+                Some(region) => capture_region_temp(region)?,
+                None => capture_screen_temp()?,
+            };
+            screenshots.push(path.clone());
+            path
+        }
+        PipelineStep::Ocr { image, .. } => ocr(&substitute(image, variables)?)?,
+        PipelineStep::Ai { prompt, image, .. } => {
+            let vision = AIVision::from_env()?;
+            let prompt = substitute(prompt, variables)?;
+            match image {
+                Some(image) => {
+                    vision
+                        .analyze_screenshot(&substitute(image, variables)?, &prompt)
+                        .await?
+                }
+                None => vision.complete(&prompt).await?,
+            }
+        }
+        #[cfg(feature = "http")]
+        PipelineStep::Http {
+            url, method, body, ..
+        } => {
+            let method = Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid HTTP method '{}'", method))?;
+            let mut request = Client::new().request(method, substitute(url, variables)?);
+            if let Some(body) = body {
+                request = request.body(substitute(body, variables)?);
+            }
+            let response = request
+                .send()
+                .await
+                .map_err(|e| format!("HTTP request failed: {}", e))?;
+            let status = response.status();
+            let text = response
+                .text()
+                .await
+                .map_err(|e| format!("Failed to read HTTP response: {}", e))?;
+            if !status.is_success() {
+                return Err(format!("HTTP request failed with {}", status));
+            }
+            text
+        }
+        #[cfg(not(feature = "http"))]
+        PipelineStep::Http { .. } => return Err(crate::features::missing("http")),
+        PipelineStep::Extract { input, pattern, .. } => {
+            let input = substitute(input, variables)?;
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+            let captures = regex
+                .captures(&input)
+                .ok_or_else(|| format!("No match for /{}/", pattern))?;
+            captures
+                .get(1)
+                .or_else(|| captures.get(0))
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default()
+        }
+        PipelineStep::Action { action } => {
+            let mut value = serde_json::to_value(action).map_err(|e| e.to_string())?;
+            substitute_value(&mut value, variables)?;
+            let action: Action = serde_json::from_value(value).map_err(|e| e.to_string())?;
+            execute_action(&action)?;
+            return Ok(None);
+        }
+    };
+    Ok(Some(value))
+}
+
+/// Capture a screen region to a temporary file and return the path
+pub(crate) fn capture_region_temp(region: &Region) -> Result<String, String> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let path = paths::scratch_file(&format!("casper_region_{}.png", timestamp));
+    let path = path.to_str().ok_or("Invalid temp path")?.to_string();
+    capture_region(region.x, region.y, region.width, region.height, &path)?;
+    Ok(path)
+}
+
+/// Text in an image, recognized by the tesseract command
+fn ocr(image: &str) -> Result<String, String> {
+    let output = Command::new("tesseract")
+        .args([image, "stdout"])
+        .output_bounded()
+        .map_err(|e| format!("Failed to run tesseract (is it installed?): {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "tesseract failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Replace every `${name}` in `text` with the variable's value
+pub fn substitute(text: &str, variables: &Variables) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unclosed variable in '{}'", text))?;
+        let name = &after[..end];
+        let value = variables
+            .get(name)
+            .ok_or_else(|| format!("Unknown variable '{}'", name))?;
+        result.push_str(value);
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Replace the `${name}` of every bound variable in `text`, leaving anything
+/// else (such as `${HOME}` in a shell command) as it is
+pub fn substitute_bound(text: &str, variables: &Variables) -> String {
+    variables
+        .iter()
+        .fold(text.to_string(), |text, (name, value)| {
+            text.replace(&format!("${{{}}}", name), value)
+        })
+}
+
+fn substitute_value(value: &mut Value, variables: &Variables) -> Result<(), String> {
+    match value {
+        Value::String(text) => *text = substitute(text, variables)?,
+        Value::Array(items) => {
+            for item in items {
+                substitute_value(item, variables)?;
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                substitute_value(field, variables)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn collect_references(value: &Value, names: &mut Vec<String>) {
+    match value {
+        Value::String(text) => {
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find("${") {
+                let after = &rest[start + 2..];
+                let Some(end) = after.find('}') else { break };
+                names.push(after[..end].to_string());
+                rest = &after[end + 1..];
+            }
+        }
+        Value::Array(items) => items
+            .iter()
+            .for_each(|item| collect_references(item, names)),
+        Value::Object(fields) => fields
+            .values()
+            .for_each(|field| collect_references(field, names)),
+        _ => {}
+    }
+}
+
+/// The saved pipelines
+#[derive(Debug)]
+pub struct Pipelines {
+    path: PathBuf,
+    pipelines: Vec<Pipeline>,
+}
+
+impl Pipelines {
+    /// No pipelines, saved to `path` once one is added
+    pub fn new(path: &Path) -> Self {
+        Pipelines {
+            path: path.to_path_buf(),
+            pipelines: Vec::new(),
+        }
+    }
+
+    /// Load the pipelines at `path`, starting with none if there is no file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut pipelines = Self::new(path);
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            pipelines.pipelines = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid pipelines {}: {}", path.display(), e))?;
+        }
+        Ok(pipelines)
+    }
+
+    pub fn pipelines(&self) -> &[Pipeline] {
+        &self.pipelines
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Pipeline> {
+        self.pipelines.iter().find(|p| p.name == name)
+    }
+
+    /// Add a pipeline, replacing any pipeline with the same name
+    pub fn add(&mut self, pipeline: Pipeline) -> Result<(), String> {
+        pipeline.validate()?;
+        self.pipelines.retain(|p| p.name != pipeline.name);
+        self.pipelines.push(pipeline);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        let before = self.pipelines.len();
+        self.pipelines.retain(|p| p.name != name);
+        if self.pipelines.len() == before {
+            return Err(format!("No pipeline '{}'", name));
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.pipelines)
+            .map_err(|e| format!("Failed to serialize pipelines: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write pipelines: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(steps: Value) -> Pipeline {
+        serde_json::from_value(serde_json::json!({ "name": "test", "steps": steps })).unwrap()
+    }
+
+    #[test]
+    fn test_substitute() {
+        let variables = Variables::from([("code".to_string(), "123456".to_string())]);
+        assert_eq!(
+            substitute("code: ${code}!", &variables).unwrap(),
+            "code: 123456!"
+        );
+        assert_eq!(substitute("$5 {x}", &variables).unwrap(), "$5 {x}");
+        assert!(substitute("${missing}", &variables).is_err());
+        assert!(substitute("${code", &variables).is_err());
+        assert_eq!(
+            substitute_bound("${code} in ${HOME}", &variables),
+            "123456 in ${HOME}"
+        );
+    }
+
+    #[test]
+    fn test_validate_bindings() {
+        let valid = pipeline(serde_json::json!([
+            { "type": "capture", "as": "shot" },
+            { "type": "ocr", "image": "${shot}", "as": "text" },
+            { "type": "extract", "input": "${text}", "pattern": "(\\d{6})", "as": "code" },
+            { "type": "action", "action": { "type": "TypeText", "text": "${code}" } }
+        ]));
+        assert!(valid.validate().is_ok());
+
+        let unbound = pipeline(serde_json::json!([
+            { "type": "action", "action": { "type": "TypeText", "text": "${code}" } },
+            { "type": "capture", "as": "code" }
+        ]));
+        assert!(unbound.validate().unwrap_err().contains("${code}"));
+
+        let mut with_input = unbound.clone();
+        with_input.inputs.push("code".to_string());
+        assert!(with_input.validate().is_ok());
+    }
+
+    #[test]
+    fn test_run_extract() {
+        let mut pipeline = pipeline(serde_json::json!([
+            { "type": "extract", "input": "${text}", "pattern": "code (\\d+)", "as": "code" }
+        ]));
+        pipeline.inputs.push("text".to_string());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let inputs = Variables::from([("text".to_string(), "Your code 482913.".to_string())]);
+        let variables = runtime.block_on(pipeline.run(inputs, false)).unwrap();
+        assert_eq!(variables["code"], "482913");
+        assert!(
+            runtime
+                .block_on(pipeline.run(Variables::new(), false))
+                .is_err()
+        );
+    }
+}
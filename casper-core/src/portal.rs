@@ -0,0 +1,642 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc;
+#[cfg(feature = "wayland")]
+use {
+    crate::paths,
+    futures_util::StreamExt,
+    std::collections::HashMap,
+    std::sync::OnceLock,
+    std::sync::atomic::AtomicU32,
+    zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+};
+
+#[cfg(feature = "wayland")]
+pub(crate) const PORTAL_DESTINATION: &str = "org.freedesktop.portal.Desktop";
+#[cfg(feature = "wayland")]
+pub(crate) const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+#[cfg(feature = "wayland")]
+const REMOTE_DESKTOP_INTERFACE: &str = "org.freedesktop.portal.RemoteDesktop";
+#[cfg(feature = "wayland")]
+const SCREEN_CAST_INTERFACE: &str = "org.freedesktop.portal.ScreenCast";
+#[cfg(feature = "wayland")]
+const SCREENSHOT_INTERFACE: &str = "org.freedesktop.portal.Screenshot";
+
+/// RemoteDesktop device types: keyboard | pointer
+#[cfg(feature = "wayland")]
+const KEYBOARD_AND_POINTER: u32 = 1 | 2;
+/// ScreenCast source type: monitor
+#[cfg(feature = "wayland")]
+const MONITOR_SOURCE: u32 = 1;
+/// Keep the permission until it is revoked, so the dialog is shown once
+#[cfg(feature = "wayland")]
+const PERSIST_UNTIL_REVOKED: u32 = 2;
+
+/// Whether portal-only mode was set: 0 = not set (detect), 1 = on, 2 = off
+static MODE: AtomicU8 = AtomicU8::new(0);
+
+/// Whether capture and input go through xdg-desktop-portal only
+///
+/// Portal-only mode needs no grim, scrot, ydotool or X11 access, so it works
+/// inside a Flatpak sandbox and on locked-down Wayland sessions. It is on
+/// inside a Flatpak or with `CASPER_PORTALS=1`, unless set otherwise.
+pub fn portal_only() -> bool {
+    match MODE.load(Ordering::Relaxed) {
+        1 => true,
+        2 => false,
+        _ => in_flatpak() || std::env::var("CASPER_PORTALS").is_ok_and(|v| v == "1"),
+    }
+}
+
+/// Turn portal-only mode on or off for the whole process
+pub fn set_portal_only(enabled: bool) {
+    MODE.store(if enabled { 1 } else { 2 }, Ordering::Relaxed);
+}
+
+/// Whether this process runs inside a Flatpak sandbox
+pub fn in_flatpak() -> bool {
+    std::env::var_os("FLATPAK_ID").is_some() || std::path::Path::new("/.flatpak-info").exists()
+}
+
+/// A synthetic input event sent through the RemoteDesktop portal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    /// Move the pointer to absolute screen coordinates
+    MoveTo { x: i32, y: i32 },
+    /// Move the pointer by an offset from where it is
+    MoveBy { dx: i32, dy: i32 },
+    /// Press or release a mouse button, by its evdev code (see `button_code`)
+    Button { code: i32, pressed: bool },
+    /// Press or release a key, by its X11 keysym (see `keysym`)
+    Key { keysym: i32, pressed: bool },
+    /// Scroll by whole steps; positive is down or right
+    Scroll { horizontal: bool, steps: i32 },
+}
+
+/// Send input through the RemoteDesktop portal
+///
+/// The first event starts a session, which may show a permission dialog;
+/// the permission is remembered so later sessions start silently.
+pub fn send_input(events: &[InputEvent]) -> Result<(), String> {
+    let events = events.to_vec();
+    call(|reply| Job::Input(events, reply))
+}
+
+/// Take a screenshot through the Screenshot portal and save it as `output_path`
+///
+/// With `interactive` the portal lets the user pick what to capture.
+pub fn screenshot(output_path: &str, interactive: bool) -> Result<(), String> {
+    let uri = call(|reply| Job::Screenshot(interactive, reply))?;
+    let source = file_uri_path(&uri).ok_or_else(|| format!("Unexpected screenshot URI {}", uri))?;
+    std::fs::copy(&source, output_path).map_err(|e| format!("Failed to save screenshot: {}", e))?;
+    // The portal saves into the user's pictures; the copy is all that was asked for
+    let _ = std::fs::remove_file(source);
+    Ok(())
+}
+
+/// Size of the screen shared with the RemoteDesktop session, covering every monitor
+pub fn display_size() -> Result<(i32, i32), String> {
+    call(Job::ScreenSize)
+}
+
+/// evdev code of a mouse button name
+pub fn button_code(button: &str) -> Result<i32, String> {
+    match button {
+        "left" => Ok(0x110),
+        "right" => Ok(0x111),
+        "middle" => Ok(0x112),
+        _ => Err(format!("Unknown button: {}", button)),
+    }
+}
+
+/// X11 keysym of a key name as accepted by `press_key`, e.g. "enter", "ctrl" or "é"
+pub fn keysym(key: &str) -> Result<i32, String> {
+    let lower = key.to_lowercase();
+    let modifier = match lower.as_str() {
+        "shift" => Some(0xffe1_i32),
+        "control" | "ctrl" => Some(0xffe3),
+        "alt" => Some(0xffe9),
+        "meta" | "super" | "windows" | "command" => Some(0xffeb),
+        _ => None,
+    };
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(char_keysym(c));
+    }
+    modifier
+        .or_else(|| crate::hotkeys::keysym(&lower).map(|(sym, _)| sym as i32))
+        .ok_or_else(|| format!("Unknown key: {}", key))
+}
+
+/// X11 keysym typing a character
+pub fn char_keysym(c: char) -> i32 {
+    match c {
+        '\n' => 0xff0d,
+        '\t' => 0xff09,
+        // Latin-1 keysyms are the code points, everything else is offset
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as i32,
+        _ => 0x0100_0000 + c as i32,
+    }
+}
+
+/// A request to the portal worker and where its answer goes
+#[cfg_attr(not(feature = "wayland"), allow(dead_code))]
+enum Job {
+    Input(Vec<InputEvent>, mpsc::Sender<Result<(), String>>),
+    Screenshot(bool, mpsc::Sender<Result<String, String>>),
+    ScreenSize(mpsc::Sender<Result<(i32, i32), String>>),
+}
+
+/// Hand a job to the portal worker and wait for the answer
+///
+/// The worker owns the D-Bus connection and the RemoteDesktop session on a
+/// thread of its own, so this works from blocking code and async tasks alike.
+#[cfg(feature = "wayland")]
+fn call<T>(job: impl FnOnce(mpsc::Sender<Result<T, String>>) -> Job) -> Result<T, String> {
+    static WORKER: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
+    let worker = WORKER.get_or_init(|| {
+        let (jobs, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_worker(receiver));
+        jobs
+    });
+    let (reply, answer) = mpsc::channel();
+    worker
+        .send(job(reply))
+        .map_err(|_| "The portal worker has stopped".to_string())?;
+    answer
+        .recv()
+        .map_err(|_| "The portal worker has stopped".to_string())?
+}
+
+#[cfg(not(feature = "wayland"))]
+fn call<T>(_job: impl FnOnce(mpsc::Sender<Result<T, String>>) -> Job) -> Result<T, String> {
+    Err(crate::features::missing("wayland"))
+}
+
+#[cfg(feature = "wayland")]
+fn run_worker(jobs: mpsc::Receiver<Job>) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the portal worker: {}", e);
+            return;
+        }
+    };
+    let mut worker = Worker::default();
+    while let Ok(job) = jobs.recv() {
+        runtime.block_on(async {
+            match job {
+                Job::Input(events, reply) => {
+                    let _ = reply.send(worker.input(&events).await);
+                }
+                Job::Screenshot(interactive, reply) => {
+                    let _ = reply.send(worker.screenshot(interactive).await);
+                }
+                Job::ScreenSize(reply) => {
+                    let result = worker.session().await.map(|session| session.screen_size());
+                    let _ = reply.send(result);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(feature = "wayland")]
+#[derive(Default)]
+struct Worker {
+    connection: Option<zbus::Connection>,
+    session: Option<RemoteSession>,
+}
+
+/// A started RemoteDesktop session with the monitors it shares
+#[cfg(feature = "wayland")]
+struct RemoteSession {
+    handle: OwnedObjectPath,
+    streams: Vec<Stream>,
+}
+
+/// A shared monitor: its PipeWire node, position and size in the desktop
+#[cfg(feature = "wayland")]
+struct Stream {
+    node: u32,
+    position: (i32, i32),
+    size: (i32, i32),
+}
+
+#[cfg(feature = "wayland")]
+impl RemoteSession {
+    /// The stream containing a desktop point and the point within it
+    fn locate(&self, x: i32, y: i32) -> Option<(u32, f64, f64)> {
+        let contains = |s: &&Stream| {
+            (s.position.0..s.position.0 + s.size.0).contains(&x)
+                && (s.position.1..s.position.1 + s.size.1).contains(&y)
+        };
+        let stream = self
+            .streams
+            .iter()
+            .find(contains)
+            .or(self.streams.first())?;
+        Some((
+            stream.node,
+            (x - stream.position.0) as f64,
+            (y - stream.position.1) as f64,
+        ))
+    }
+
+    fn screen_size(&self) -> (i32, i32) {
+        self.streams.iter().fold((0, 0), |(w, h), s| {
+            (
+                w.max(s.position.0 + s.size.0),
+                h.max(s.position.1 + s.size.1),
+            )
+        })
+    }
+}
+
+#[cfg(feature = "wayland")]
+impl Worker {
+    async fn connection(&mut self) -> Result<zbus::Connection, String> {
+        if let Some(connection) = &self.connection {
+            return Ok(connection.clone());
+        }
+        let connection = zbus::Connection::session()
+            .await
+            .map_err(|e| format!("Failed to connect to the session bus: {}", e))?;
+        self.connection = Some(connection.clone());
+        Ok(connection)
+    }
+
+    async fn proxy(&mut self, interface: &'static str) -> Result<zbus::Proxy<'static>, String> {
+        let connection = self.connection().await?;
+        zbus::Proxy::new(&connection, PORTAL_DESTINATION, PORTAL_PATH, interface)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn screenshot(&mut self, interactive: bool) -> Result<String, String> {
+        let connection = self.connection().await?;
+        let portal = self.proxy(SCREENSHOT_INTERFACE).await?;
+        let token = request_token();
+        let options = HashMap::from([
+            ("handle_token", Value::from(token.as_str())),
+            ("interactive", Value::from(interactive)),
+        ]);
+        let results =
+            portal_request(&connection, &portal, "Screenshot", &token, &("", options)).await?;
+        results
+            .get("uri")
+            .and_then(|uri| String::try_from(uri.clone()).ok())
+            .ok_or_else(|| "The portal did not return a screenshot".to_string())
+    }
+
+    async fn input(&mut self, events: &[InputEvent]) -> Result<(), String> {
+        match self.try_input(events).await {
+            Ok(()) => Ok(()),
+            // The session may have been closed by the user or the compositor
+            Err(_) if self.session.take().is_some() => self.try_input(events).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn try_input(&mut self, events: &[InputEvent]) -> Result<(), String> {
+        self.session().await?;
+        let portal = self.proxy(REMOTE_DESKTOP_INTERFACE).await?;
+        let session = self.session.as_ref().ok_or("No RemoteDesktop session")?;
+        let handle = session.handle.as_ref();
+        let options: HashMap<&str, Value> = HashMap::new();
+        for event in events {
+            let result = match *event {
+                InputEvent::MoveTo { x, y } => {
+                    let (stream, x, y) = session
+                        .locate(x, y)
+                        .ok_or("The RemoteDesktop session shares no screen")?;
+                    portal
+                        .call_method(
+                            "NotifyPointerMotionAbsolute",
+                            &(&handle, &options, stream, x, y),
+                        )
+                        .await
+                }
+                InputEvent::MoveBy { dx, dy } => {
+                    portal
+                        .call_method(
+                            "NotifyPointerMotion",
+                            &(&handle, &options, dx as f64, dy as f64),
+                        )
+                        .await
+                }
+                InputEvent::Button { code, pressed } => {
+                    portal
+                        .call_method(
+                            "NotifyPointerButton",
+                            &(&handle, &options, code, pressed as u32),
+                        )
+                        .await
+                }
+                InputEvent::Key { keysym, pressed } => {
+                    portal
+                        .call_method(
+                            "NotifyKeyboardKeysym",
+                            &(&handle, &options, keysym, pressed as u32),
+                        )
+                        .await
+                }
+                InputEvent::Scroll { horizontal, steps } => {
+                    portal
+                        .call_method(
+                            "NotifyPointerAxisDiscrete",
+                            &(&handle, &options, horizontal as u32, steps),
+                        )
+                        .await
+                }
+            };
+            result.map_err(|e| format!("RemoteDesktop input failed: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// The RemoteDesktop session, started on first use
+    async fn session(&mut self) -> Result<&RemoteSession, String> {
+        if self.session.is_none() {
+            let session = self.start_session().await?;
+            self.session = Some(session);
+        }
+        self.session
+            .as_ref()
+            .ok_or_else(|| "No RemoteDesktop session".to_string())
+    }
+
+    async fn start_session(&mut self) -> Result<RemoteSession, String> {
+        let connection = self.connection().await?;
+        let remote = self.proxy(REMOTE_DESKTOP_INTERFACE).await?;
+        let screen_cast = self.proxy(SCREEN_CAST_INTERFACE).await?;
+
+        let token = request_token();
+        let options = HashMap::from([
+            ("handle_token", Value::from(token.as_str())),
+            ("session_handle_token", Value::from(token.as_str())),
+        ]);
+        let results =
+            portal_request(&connection, &remote, "CreateSession", &token, &(options,)).await?;
+        let handle = session_handle(&results)?;
+        let session = ObjectPath::try_from(handle.as_str()).map_err(|e| e.to_string())?;
+
+        let token = request_token();
+        let mut options = HashMap::from([
+            ("handle_token", Value::from(token.as_str())),
+            ("types", Value::from(KEYBOARD_AND_POINTER)),
+            ("persist_mode", Value::from(PERSIST_UNTIL_REVOKED)),
+        ]);
+        let restore_token = std::fs::read_to_string(restore_token_file()).ok();
+        if let Some(restore) = &restore_token {
+            options.insert("restore_token", Value::from(restore.trim()));
+        }
+        portal_request(
+            &connection,
+            &remote,
+            "SelectDevices",
+            &token,
+            &(&session, options),
+        )
+        .await?;
+
+        // Absolute pointer motion is relative to a shared monitor
+        let token = request_token();
+        let options = HashMap::from([
+            ("handle_token", Value::from(token.as_str())),
+            ("types", Value::from(MONITOR_SOURCE)),
+            ("multiple", Value::from(true)),
+        ]);
+        portal_request(
+            &connection,
+            &screen_cast,
+            "SelectSources",
+            &token,
+            &(&session, options),
+        )
+        .await?;
+
+        let token = request_token();
+        let options = HashMap::from([("handle_token", Value::from(token.as_str()))]);
+        let results = portal_request(
+            &connection,
+            &remote,
+            "Start",
+            &token,
+            &(&session, "", options),
+        )
+        .await?;
+        if let Some(token) = results
+            .get("restore_token")
+            .and_then(|token| String::try_from(token.clone()).ok())
+        {
+            let path = restore_token_file();
+            if let Some(parent) = path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let _ = std::fs::write(path, token);
+        }
+
+        Ok(RemoteSession {
+            handle: session.into(),
+            streams: results
+                .get("streams")
+                .map(parse_streams)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Streams from a Start response: `a(ua{sv})` with "position" and "size"
+#[cfg(feature = "wayland")]
+fn parse_streams(value: &OwnedValue) -> Vec<Stream> {
+    let Value::Array(streams) = &**value else {
+        return Vec::new();
+    };
+    streams
+        .iter()
+        .filter_map(|stream| {
+            let Value::Structure(stream) = stream else {
+                return None;
+            };
+            let [Value::U32(node), Value::Dict(properties)] = stream.fields() else {
+                return None;
+            };
+            // Decoded by hand: older zvariant releases cannot read tuples
+            // straight out of a dictionary
+            let pair = |key: &str| -> Option<(i32, i32)> {
+                let (_, value) = properties
+                    .iter()
+                    .find(|(name, _)| matches!(name, Value::Str(name) if name.as_str() == key))?;
+                let value = match value {
+                    Value::Value(inner) => &**inner,
+                    value => value,
+                };
+                match value {
+                    Value::Structure(pair) => match pair.fields() {
+                        [Value::I32(a), Value::I32(b)] => Some((*a, *b)),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            };
+            Some(Stream {
+                node: *node,
+                position: pair("position").unwrap_or((0, 0)),
+                size: pair("size")?,
+            })
+        })
+        .collect()
+}
+
+/// Where the permission to control input is remembered between sessions
+#[cfg(feature = "wayland")]
+fn restore_token_file() -> std::path::PathBuf {
+    paths::state_dir(None).join("portal_restore_token")
+}
+
+/// The session handle of a CreateSession response
+#[cfg(feature = "wayland")]
+pub(crate) fn session_handle(results: &HashMap<String, OwnedValue>) -> Result<String, String> {
+    results
+        .get("session_handle")
+        .and_then(|handle| {
+            String::try_from(handle.clone()).ok().or_else(|| {
+                OwnedObjectPath::try_from(handle.clone())
+                    .ok()
+                    .map(|p| p.to_string())
+            })
+        })
+        .ok_or_else(|| "The portal did not return a session".to_string())
+}
+
+/// Call a portal method and wait for the response on its Request object
+#[cfg(feature = "wayland")]
+pub(crate) async fn portal_request<B>(
+    connection: &zbus::Connection,
+    portal: &zbus::Proxy<'_>,
+    method: &str,
+    token: &str,
+    body: &B,
+) -> Result<HashMap<String, OwnedValue>, String>
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    // Subscribe before calling, so a quick response is not missed
+    let sender = connection
+        .unique_name()
+        .map(|name| name.trim_start_matches(':').replace('.', "_"))
+        .unwrap_or_default();
+    let path = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+    let request = zbus::Proxy::new(
+        connection,
+        PORTAL_DESTINATION,
+        path,
+        "org.freedesktop.portal.Request",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let mut responses = request
+        .receive_signal("Response")
+        .await
+        .map_err(|e| e.to_string())?;
+    portal
+        .call_method(method, body)
+        .await
+        .map_err(|e| format!("{} failed: {}", method, e))?;
+
+    let message = responses
+        .next()
+        .await
+        .ok_or_else(|| format!("No response to {}", method))?;
+    let (code, results): (u32, HashMap<String, OwnedValue>) = message
+        .body()
+        .deserialize()
+        .map_err(|e| format!("Invalid response to {}: {}", method, e))?;
+    match code {
+        0 => Ok(results),
+        1 => Err(format!("{} was cancelled", method)),
+        _ => Err(format!("{} failed", method)),
+    }
+}
+
+/// A handle token unique within this process
+#[cfg(feature = "wayland")]
+pub(crate) fn request_token() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    format!(
+        "casper_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Local path of a "file://" URI, with percent escapes decoded
+fn file_uri_path(uri: &str) -> Option<String> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%'
+            && let Some(byte) = encoded
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            bytes.push(byte);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keysyms_and_uris() {
+        assert_eq!(keysym("Enter"), Ok(0xff0d));
+        assert_eq!(keysym("ctrl"), Ok(0xffe3));
+        assert_eq!(keysym("f5"), Ok(0xffc2));
+        assert_eq!(keysym("A"), Ok('A' as i32));
+        assert_eq!(keysym("é"), Ok(0xe9));
+        assert_eq!(keysym("€"), Ok(0x0100_20ac));
+        assert!(keysym("nokey").is_err());
+        assert_eq!(button_code("right"), Ok(0x111));
+
+        assert_eq!(
+            file_uri_path("file:///home/me/Pictures/Screenshot%20from%202026.png").as_deref(),
+            Some("/home/me/Pictures/Screenshot from 2026.png")
+        );
+        assert_eq!(file_uri_path("https://example.com"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "wayland")]
+    fn test_locate_point_on_stream() {
+        let session = RemoteSession {
+            handle: OwnedObjectPath::try_from("/session/1").unwrap(),
+            streams: vec![
+                Stream {
+                    node: 40,
+                    position: (0, 0),
+                    size: (1920, 1080),
+                },
+                Stream {
+                    node: 41,
+                    position: (1920, 0),
+                    size: (2560, 1440),
+                },
+            ],
+        };
+        assert_eq!(session.locate(100, 50), Some((40, 100.0, 50.0)));
+        assert_eq!(session.locate(2000, 10), Some((41, 80.0, 10.0)));
+        assert_eq!(session.screen_size(), (4480, 1440));
+    }
+}
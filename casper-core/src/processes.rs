@@ -0,0 +1,200 @@
+use crate::commands::CommandDeadline;
+use serde::Serialize;
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Linux counts CPU time in these ticks (USER_HZ) on every architecture
+const CLOCK_TICKS: f64 = 100.0;
+
+/// How often `wait_for_process_exit` looks for the process
+const EXIT_POLL: Duration = Duration::from_millis(100);
+
+/// Signals `kill_processes` sends, by name
+const SIGNALS: &[&str] = &[
+    "HUP", "INT", "QUIT", "KILL", "USR1", "USR2", "TERM", "CONT", "STOP",
+];
+
+/// A running process
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Name the kernel knows it by, at most 15 characters
+    pub name: String,
+    pub cmdline: Vec<String>,
+    /// Average CPU use since the process started, as `ps` shows it
+    pub cpu_percent: f64,
+    /// Resident memory
+    pub memory_kb: u64,
+}
+
+/// Whether a process is the one `pattern` names: its PID, its name or the
+/// file name of the program it runs, exactly as `pgrep -x` matches
+fn matches(pattern: &str, pid: u32, name: &str, cmdline: &[String]) -> bool {
+    let program = cmdline
+        .first()
+        .map(|argv0| argv0.rsplit('/').next().unwrap_or(argv0));
+    pattern == pid.to_string() || pattern == name || program == Some(pattern)
+}
+
+/// Name, CPU ticks used and start time (in ticks after boot) from `/proc/<pid>/stat`
+fn parse_stat(stat: &str) -> Option<(String, u64, u64)> {
+    // The name is in parentheses and may itself hold spaces and parentheses
+    let name = &stat[stat.find('(')? + 1..stat.rfind(')')?];
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |index: usize| fields.get(index)?.parse::<u64>().ok();
+    // utime and stime are fields 14 and 15 of the line, starttime field 22
+    Some((name.to_string(), field(11)? + field(12)?, field(19)?))
+}
+
+fn read_process(pid: u32, uptime: f64) -> Option<ProcessInfo> {
+    let dir = format!("/proc/{}", pid);
+    let (name, ticks, started) = parse_stat(&fs::read_to_string(format!("{}/stat", dir)).ok()?)?;
+    let cmdline = fs::read(format!("{}/cmdline", dir))
+        .map(|raw| {
+            raw.split(|&b| b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let memory_kb = fs::read_to_string(format!("{}/status", dir))
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+            line.split_whitespace().nth(1)?.parse().ok()
+        })
+        .unwrap_or(0);
+    let running_for = uptime - started as f64 / CLOCK_TICKS;
+    let cpu_percent = if running_for > 0.0 {
+        (ticks as f64 / CLOCK_TICKS / running_for * 1000.0).round() / 10.0
+    } else {
+        0.0
+    };
+    Some(ProcessInfo {
+        pid,
+        name,
+        cmdline,
+        cpu_percent,
+        memory_kb,
+    })
+}
+
+/// Processes matching `pattern` (a name, program or PID), other than casper itself
+pub fn get_process_info(pattern: &str) -> Result<Vec<ProcessInfo>, String> {
+    let uptime: f64 = fs::read_to_string("/proc/uptime")
+        .map_err(|e| format!("Failed to read /proc: {}", e))?
+        .split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(0.0);
+    let own_pid = std::process::id();
+    let mut processes: Vec<ProcessInfo> = fs::read_dir("/proc")
+        .map_err(|e| format!("Failed to read /proc: {}", e))?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| pid != own_pid)
+        .filter_map(|pid| read_process(pid, uptime))
+        .filter(|process| matches(pattern, process.pid, &process.name, &process.cmdline))
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    Ok(processes)
+}
+
+/// A signal name for `kill -s`: "TERM", "SIGKILL" and "9" all work
+fn signal_name(signal: &str) -> Result<String, String> {
+    let name = signal.trim().to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    if SIGNALS.contains(&name) || name.parse::<u8>().is_ok_and(|n| (1..=64).contains(&n)) {
+        return Ok(name.to_string());
+    }
+    Err(format!(
+        "Unknown signal '{}' (one of {}, or a number)",
+        signal,
+        SIGNALS.join(", ")
+    ))
+}
+
+/// Send `signal` (TERM when not given) to every process matching `pattern`
+/// and return their PIDs
+pub fn kill_processes(pattern: &str, signal: Option<&str>) -> Result<Vec<u32>, String> {
+    let signal = signal_name(signal.unwrap_or("TERM"))?;
+    let processes = get_process_info(pattern)?;
+    if processes.is_empty() {
+        return Err(format!("No process matches '{}'", pattern));
+    }
+    let mut signalled = Vec::new();
+    let mut errors = Vec::new();
+    for process in processes {
+        let output = Command::new("kill")
+            .args(["-s", &signal, &process.pid.to_string()])
+            .output_bounded()
+            .map_err(|e| format!("Failed to execute kill: {}", e))?;
+        if output.status.success() {
+            signalled.push(process.pid);
+        } else {
+            errors.push(format!(
+                "{} ({}): {}",
+                process.name,
+                process.pid,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+    if signalled.is_empty() {
+        return Err(format!("Failed to signal {}", errors.join("; ")));
+    }
+    Ok(signalled)
+}
+
+/// Wait until no process matches `pattern`
+pub fn wait_for_process_exit(pattern: &str, timeout_ms: u64) -> Result<(), String> {
+    let deadline = Instant::now()
+        .checked_add(Duration::from_millis(timeout_ms))
+        .ok_or("timeout_ms is too long")?;
+    loop {
+        let remaining = get_process_info(pattern)?;
+        if remaining.is_empty() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            let pids: Vec<String> = remaining.iter().map(|p| p.pid.to_string()).collect();
+            return Err(format!(
+                "'{}' still running after {} ms (PID {})",
+                pattern,
+                timeout_ms,
+                pids.join(", ")
+            ));
+        }
+        std::thread::sleep(EXIT_POLL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_parsing() {
+        let stat = "4242 (Web Content (1)) S 1 4242 4242 0 -1 4194560 9 0 0 0 250 50 0 0 20 0 7 0 1000 123 45";
+        assert_eq!(
+            parse_stat(stat),
+            Some(("Web Content (1)".to_string(), 300, 1000))
+        );
+
+        let cmdline = vec![
+            "/usr/lib/firefox/firefox".to_string(),
+            "-new-tab".to_string(),
+        ];
+        assert!(matches("firefox", 10, "GeckoMain", &cmdline));
+        assert!(matches("GeckoMain", 10, "GeckoMain", &cmdline));
+        assert!(matches("10", 10, "GeckoMain", &cmdline));
+        assert!(!matches("fire", 10, "GeckoMain", &cmdline));
+
+        assert_eq!(signal_name("sigkill").unwrap(), "KILL");
+        assert_eq!(signal_name("9").unwrap(), "9");
+        assert!(signal_name("BOOM").is_err());
+
+        let own = get_process_info(&std::process::id().to_string()).unwrap();
+        assert!(own.is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::executor::{PlaybackProgress, StepFailure};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Runs kept per sequence; older ones are dropped
+const MAX_RUNS: usize = 200;
+
+/// How a playback ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Finished,
+    Stopped,
+    Failed,
+}
+
+/// Time one step of a run took
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepTiming {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// One playback of a sequence, as kept in its run log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub sequence: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub outcome: RunOutcome,
+    pub duration_ms: u64,
+    /// The step that failed the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_step: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub steps: Vec<StepTiming>,
+    /// Failures a `continue` error policy went past
+    #[serde(default)]
+    pub failures: Vec<StepFailure>,
+}
+
+impl RunRecord {
+    /// Finished with no step failing, not even one a policy went past
+    pub fn succeeded(&self) -> bool {
+        self.outcome == RunOutcome::Finished && self.failures.is_empty()
+    }
+}
+
+/// Builds the record of a playback from its progress reports
+pub struct RunTracker {
+    sequence: String,
+    started_at: String,
+    started: Instant,
+    steps: Vec<StepTiming>,
+}
+
+impl RunTracker {
+    pub fn start(sequence: &str) -> Self {
+        RunTracker {
+            sequence: sequence.to_string(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            started: Instant::now(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Note a progress report; returns the record once the playback ended
+    pub fn observe(&mut self, progress: &PlaybackProgress) -> Option<RunRecord> {
+        let (outcome, failed_step, error, failures) = match progress {
+            PlaybackProgress::Step {
+                index,
+                label,
+                duration_ms,
+                ..
+            } => {
+                self.steps.push(StepTiming {
+                    index: *index,
+                    label: label.clone(),
+                    duration_ms: *duration_ms,
+                });
+                return None;
+            }
+            PlaybackProgress::Finished { failures, .. } => {
+                (RunOutcome::Finished, None, None, failures)
+            }
+            PlaybackProgress::Stopped { failures, .. } => {
+                (RunOutcome::Stopped, None, None, failures)
+            }
+            PlaybackProgress::Failed {
+                index,
+                error,
+                failures,
+            } => (
+                RunOutcome::Failed,
+                Some(*index),
+                Some(error.clone()),
+                failures,
+            ),
+        };
+        Some(RunRecord {
+            sequence: self.sequence.clone(),
+            started_at: self.started_at.clone(),
+            ended_at: chrono::Utc::now().to_rfc3339(),
+            outcome,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            failed_step,
+            error,
+            steps: std::mem::take(&mut self.steps),
+            failures: failures.clone(),
+        })
+    }
+}
+
+/// Counts over the runs of a sequence
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunSummary {
+    pub runs: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub stopped: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<String>,
+}
+
+impl RunSummary {
+    pub fn of(runs: &[RunRecord]) -> Self {
+        let mut summary = RunSummary {
+            runs: runs.len(),
+            ..Default::default()
+        };
+        for run in runs {
+            if run.succeeded() {
+                summary.succeeded += 1;
+                summary.last_success = max_time(summary.last_success, &run.started_at);
+            } else if run.outcome == RunOutcome::Stopped {
+                summary.stopped += 1;
+            } else {
+                summary.failed += 1;
+                summary.last_failure = max_time(summary.last_failure, &run.started_at);
+            }
+        }
+        summary
+    }
+}
+
+fn max_time(current: Option<String>, time: &str) -> Option<String> {
+    match current {
+        Some(current) if current.as_str() >= time => Some(current),
+        _ => Some(time.to_string()),
+    }
+}
+
+/// Run logs of every sequence, one JSON line per run in `<dir>/<name>.jsonl`
+pub struct RunHistory {
+    dir: PathBuf,
+}
+
+impl RunHistory {
+    pub fn new(dir: PathBuf) -> Self {
+        RunHistory { dir }
+    }
+
+    fn log_file(&self, sequence: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", sequence))
+    }
+
+    /// Append a run to its sequence's log, dropping the oldest beyond `MAX_RUNS`
+    pub fn record(&self, run: &RunRecord) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+        let path = self.log_file(&run.sequence);
+        let line = serde_json::to_string(run).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write run log: {}", e))?;
+        drop(file);
+
+        let runs = read_log(&path);
+        if runs.len() > MAX_RUNS {
+            let kept: Vec<String> = runs[runs.len() - MAX_RUNS..]
+                .iter()
+                .filter_map(|run| serde_json::to_string(run).ok())
+                .collect();
+            fs::write(&path, kept.join("\n") + "\n")
+                .map_err(|e| format!("Failed to write run log: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Runs of a sequence, or of every sequence, newest first
+    pub fn runs(&self, sequence: Option<&str>) -> Vec<RunRecord> {
+        let mut runs = match sequence {
+            Some(sequence) => read_log(&self.log_file(sequence)),
+            None => fs::read_dir(&self.dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+                .flat_map(|path| read_log(&path))
+                .collect(),
+        };
+        runs.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        runs
+    }
+}
+
+/// Runs in a log, oldest first; damaged lines are skipped
+fn read_log(path: &Path) -> Vec<RunRecord> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_history() {
+        let dir = std::env::temp_dir().join(format!("casper-runs-{}", std::process::id()));
+        let history = RunHistory::new(dir.clone());
+
+        let mut tracker = RunTracker::start("nightly");
+        let step = PlaybackProgress::Step {
+            index: 0,
+            total: 2,
+            label: Some("open".into()),
+            duration_ms: 12,
+        };
+        assert!(tracker.observe(&step).is_none());
+        let run = tracker
+            .observe(&PlaybackProgress::Failed {
+                index: 1,
+                error: "Window not found".into(),
+                failures: Vec::new(),
+            })
+            .unwrap();
+        assert_eq!(run.steps[0].duration_ms, 12);
+        history.record(&run).unwrap();
+
+        let mut tracker = RunTracker::start("nightly");
+        let finished = PlaybackProgress::Finished {
+            steps: 0,
+            failures: Vec::new(),
+        };
+        history
+            .record(&tracker.observe(&finished).unwrap())
+            .unwrap();
+
+        let runs = history.runs(Some("nightly"));
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].failed_step, Some(1));
+        let summary = RunSummary::of(&runs);
+        assert_eq!((summary.succeeded, summary.failed), (1, 1));
+        assert_eq!(history.runs(None).len(), 2);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,163 +1,779 @@
-use enigo::{Button, Coordinate, Direction, Enigo, Key, Keyboard, Mouse, Settings};
+use crate::hotkeys::NAMED_KEYS;
+use crate::input_backends::{Wtype, Ydotool};
+use crate::portal::{self, InputEvent};
+use crate::setup::find_in_path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub(crate) use enigo_input::{parse_button, parse_key};
 
 pub fn move_mouse(x: i32, y: i32) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-    enigo
-        .move_mouse(x, y, Coordinate::Abs)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+    if portal::portal_only() {
+        return portal::send_input(&[InputEvent::MoveTo { x, y }]);
+    }
+    input_backend().move_mouse(x, y)
+}
+
+/// Move the pointer by `dx`/`dy` from where it is, for games and canvases
+/// that read pointer motion rather than positions
+pub fn move_mouse_relative(dx: i32, dy: i32) -> Result<(), String> {
+    if portal::portal_only() {
+        return portal::send_input(&[InputEvent::MoveBy { dx, dy }]);
+    }
+    input_backend().move_mouse_relative(dx, dy)
 }
 
+/// Pause between the clicks of a double or triple click, well within the
+/// double-click time of every desktop
+const CLICK_INTERVAL: Duration = Duration::from_millis(60);
+
 pub fn click_mouse(button: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-
-    let btn = match button {
-        "left" => Button::Left,
-        "right" => Button::Right,
-        "middle" => Button::Middle,
-        _ => return Err(format!("Unknown button: {}", button)),
-    };
+    click_times(button, 1)
+}
 
-    enigo
-        .button(btn, enigo::Direction::Click)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+/// Click `count` times where the pointer is: 2 for a double click, 3 for a
+/// triple click
+pub fn click_times(button: &str, count: u32) -> Result<(), String> {
+    check_click_count(count)?;
+    if portal::portal_only() {
+        for click in 0..count {
+            if click > 0 {
+                std::thread::sleep(CLICK_INTERVAL);
+            }
+            portal_button(button, &[true, false])?;
+        }
+        return Ok(());
+    }
+    input_backend().clicks(None, button, count, Duration::ZERO)
 }
 
-pub fn mouse_down(button: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-
-    let btn = match button {
-        "left" => Button::Left,
-        "right" => Button::Right,
-        "middle" => Button::Middle,
-        _ => return Err(format!("Unknown button: {}", button)),
-    };
+/// Move to `x`/`y` and click there `count` times as one input operation, so
+/// nothing can move the pointer in between; `settle_ms` gives the target
+/// time to react to the hover before the first click
+pub fn click_at(x: i32, y: i32, button: &str, count: u32, settle_ms: u64) -> Result<(), String> {
+    check_click_count(count)?;
+    let settle = Duration::from_millis(settle_ms);
+    if portal::portal_only() {
+        if settle.is_zero() && count == 1 {
+            let code = portal::button_code(button)?;
+            return portal::send_input(&[
+                InputEvent::MoveTo { x, y },
+                InputEvent::Button {
+                    code,
+                    pressed: true,
+                },
+                InputEvent::Button {
+                    code,
+                    pressed: false,
+                },
+            ]);
+        }
+        portal::send_input(&[InputEvent::MoveTo { x, y }])?;
+        std::thread::sleep(settle);
+        return click_times(button, count);
+    }
+    input_backend().clicks(Some((x, y)), button, count, settle)
+}
+
+/// Time between the pointer moves of a drag, about one frame
+const DRAG_STEP: Duration = Duration::from_millis(16);
 
-    enigo
-        .button(btn, Direction::Press)
-        .map_err(|e| e.to_string())?;
+/// Press `button` at the start, move the pointer smoothly to the end over
+/// `duration_ms` and release it there
+///
+/// The button is released even when a move fails, so a broken drag never
+/// leaves it held down.
+pub fn drag(
+    from_x: i32,
+    from_y: i32,
+    to_x: i32,
+    to_y: i32,
+    button: &str,
+    duration_ms: u64,
+) -> Result<(), String> {
+    move_mouse(from_x, from_y)?;
+    mouse_down(button)?;
+    // At least one move in between, or applications may not see a drag
+    let steps = (duration_ms / DRAG_STEP.as_millis() as u64).max(2) as i64;
+    let pause = Duration::from_millis(duration_ms / steps as u64);
+    let mut result = Ok(());
+    for step in 1..=steps {
+        std::thread::sleep(pause);
+        // Widened first, as the distance between two i32 may not fit one
+        let x = from_x as i64 + (to_x as i64 - from_x as i64) * step / steps;
+        let y = from_y as i64 + (to_y as i64 - from_y as i64) * step / steps;
+        if let Err(e) = move_mouse(x as i32, y as i32) {
+            result = Err(e);
+            break;
+        }
+    }
+    let released = mouse_up(button);
+    result.and(released)
+}
+
+/// Most clicks a single click request or step may repeat
+pub const MAX_CLICK_COUNT: u32 = 10;
+
+/// Check the `count` of a click, between 1 and `MAX_CLICK_COUNT`
+pub fn check_click_count(count: u32) -> Result<(), String> {
+    if count == 0 {
+        return Err("A click needs a count of at least 1".to_string());
+    }
+    if count > MAX_CLICK_COUNT {
+        return Err(format!(
+            "A click can repeat at most {} times, got {}",
+            MAX_CLICK_COUNT, count
+        ));
+    }
     Ok(())
 }
 
-pub fn mouse_up(button: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-
-    let btn = match button {
-        "left" => Button::Left,
-        "right" => Button::Right,
-        "middle" => Button::Middle,
-        _ => return Err(format!("Unknown button: {}", button)),
+/// Keys and buttons pressed with `key_down`/`mouse_down` and not released
+/// yet, so an abort can let go of them
+static HELD: Mutex<Vec<Held>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq)]
+enum Held {
+    Key(String),
+    Button(String),
+}
+
+fn track(held: Held, pressed: bool) {
+    let mut all = HELD.lock().unwrap();
+    all.retain(|other| *other != held);
+    if pressed {
+        all.push(held);
+    }
+}
+
+/// Release every key and mouse button still held down, the last pressed
+/// first; returns the keys and the buttons that were released
+pub fn release_held() -> (Vec<String>, Vec<String>) {
+    let held = std::mem::take(&mut *HELD.lock().unwrap());
+    let (mut keys, mut buttons) = (Vec::new(), Vec::new());
+    for held in held.into_iter().rev() {
+        match held {
+            Held::Key(key) => {
+                let _ = key_up(&key);
+                keys.push(key);
+            }
+            Held::Button(button) => {
+                let _ = mouse_up(&button);
+                buttons.push(button);
+            }
+        }
+    }
+    (keys, buttons)
+}
+
+pub fn mouse_down(button: &str) -> Result<(), String> {
+    let result = if portal::portal_only() {
+        portal_button(button, &[true])
+    } else {
+        input_backend().button(button, &[true])
     };
+    if result.is_ok() {
+        track(Held::Button(button.to_lowercase()), true);
+    }
+    result
+}
 
-    enigo
-        .button(btn, Direction::Release)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+pub fn mouse_up(button: &str) -> Result<(), String> {
+    track(Held::Button(button.to_lowercase()), false);
+    if portal::portal_only() {
+        return portal_button(button, &[false]);
+    }
+    input_backend().button(button, &[false])
 }
 
 pub fn scroll(amount: i32, direction: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
+    if portal::portal_only() {
+        let (horizontal, steps) = match direction {
+            "up" => (false, -amount),
+            "down" => (false, amount),
+            "left" => (true, -amount),
+            "right" => (true, amount),
+            _ => return Err(format!("Unknown scroll direction: {}", direction)),
+        };
+        return portal::send_input(&[InputEvent::Scroll { horizontal, steps }]);
+    }
+    input_backend().scroll(amount, direction)
+}
 
-    match direction {
-        "up" | "down" => {
-            let scroll_amount = if direction == "down" { -amount } else { amount };
-            enigo
-                .scroll(scroll_amount, enigo::Axis::Vertical)
-                .map_err(|e| e.to_string())?;
-        }
-        "left" | "right" => {
-            let scroll_amount = if direction == "left" { -amount } else { amount };
-            enigo
-                .scroll(scroll_amount, enigo::Axis::Horizontal)
-                .map_err(|e| e.to_string())?;
+/// Pause between the steps of a smooth scroll unless another is given
+const SMOOTH_SCROLL_DELAY: Duration = Duration::from_millis(30);
+
+/// Scroll `amount` notches `step` at a time with `delay_ms` after each step,
+/// which animated and lazily loading pages follow better than one jump
+pub fn scroll_smoothly(
+    amount: i32,
+    direction: &str,
+    step: u32,
+    delay_ms: Option<u64>,
+) -> Result<(), String> {
+    let step = step.max(1) as i32;
+    let delay = delay_ms.map_or(SMOOTH_SCROLL_DELAY, Duration::from_millis);
+    let mut remaining = amount;
+    while remaining > 0 {
+        scroll(remaining.min(step), direction)?;
+        remaining -= step;
+        if remaining > 0 {
+            std::thread::sleep(delay);
         }
-        _ => return Err(format!("Unknown scroll direction: {}", direction)),
     }
+    Ok(())
+}
 
+/// Scroll by whole pages with the Page Up and Page Down keys, in the
+/// focused window
+pub fn scroll_pages(pages: u32, direction: &str) -> Result<(), String> {
+    let key = match direction {
+        "up" => "pageup",
+        "down" => "pagedown",
+        _ => return Err(format!("Pages only scroll up or down, not {}", direction)),
+    };
+    for _ in 0..pages {
+        press_key(key)?;
+    }
     Ok(())
 }
 
 pub fn type_text(text: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-    enigo.fast_text(text).map_err(|e| e.to_string())?;
+    if portal::portal_only() {
+        let events: Vec<InputEvent> = text
+            .chars()
+            .flat_map(|c| {
+                let keysym = portal::char_keysym(c);
+                [true, false].map(|pressed| InputEvent::Key { keysym, pressed })
+            })
+            .collect();
+        return portal::send_input(&events);
+    }
+    input_backend().type_text(text)
+}
+
+/// Type one character at a time with `delay_ms` after each, like a person;
+/// some Electron apps and terminals drop characters typed all at once
+pub fn type_text_slowly(text: &str, delay_ms: u64) -> Result<(), String> {
+    let mut buffer = [0; 4];
+    for c in text.chars() {
+        type_text(c.encode_utf8(&mut buffer))?;
+        std::thread::sleep(Duration::from_millis(delay_ms));
+    }
     Ok(())
 }
 
 pub fn press_key(key: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-
-    let k = parse_key(key)?;
-    enigo.key(k, Direction::Click).map_err(|e| e.to_string())?;
-    Ok(())
+    if portal::portal_only() {
+        return portal_key(key, &[true, false]);
+    }
+    input_backend().key(key, &[true, false])
 }
 
 pub fn key_down(key: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-
-    let k = parse_key(key)?;
-    enigo.key(k, Direction::Press).map_err(|e| e.to_string())?;
-    Ok(())
+    let result = if portal::portal_only() {
+        portal_key(key, &[true])
+    } else {
+        input_backend().key(key, &[true])
+    };
+    if result.is_ok() {
+        track(Held::Key(key.to_lowercase()), true);
+    }
+    result
 }
 
 pub fn key_up(key: &str) -> Result<(), String> {
-    let settings = Settings::default();
-    let mut enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
+    track(Held::Key(key.to_lowercase()), false);
+    if portal::portal_only() {
+        return portal_key(key, &[false]);
+    }
+    input_backend().key(key, &[false])
+}
 
-    let k = parse_key(key)?;
-    enigo
-        .key(k, Direction::Release)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+/// Split "ctrl+shift+t" into modifier key names and the key
+pub fn parse_key_combo(combo: &str) -> Result<(Vec<&'static str>, String), String> {
+    let parts: Vec<&str> = combo.split('+').map(str::trim).collect();
+    // "ctrl++" ends in an empty part for the plus key itself
+    let (key, modifiers) = match parts.as_slice() {
+        [rest @ .., "", ""] => ("+".to_string(), rest),
+        [rest @ .., key] if !key.is_empty() => (key.to_lowercase(), rest),
+        _ => return Err(format!("Invalid key combination '{}'", combo)),
+    };
+    let modifiers = modifiers
+        .iter()
+        .map(|m| match m.to_lowercase().as_str() {
+            "ctrl" | "control" => Ok("control"),
+            "shift" => Ok("shift"),
+            "alt" => Ok("alt"),
+            "super" | "meta" | "win" => Ok("meta"),
+            other => Err(format!("Unknown modifier '{}' in '{}'", other, combo)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((modifiers, key))
+}
+
+/// Every key name `press_key` understands, besides single characters
+pub fn key_names() -> Vec<String> {
+    let modifiers = [
+        "shift", "control", "ctrl", "alt", "meta", "super", "windows", "command",
+    ];
+    let named = NAMED_KEYS.iter().flat_map(|(names, ..)| names.iter());
+    modifiers
+        .iter()
+        .chain(named)
+        .map(|name| name.to_string())
+        .chain((1..=24).map(|number| format!("f{}", number)))
+        .collect()
+}
+
+/// Press a combination such as "ctrl+shift+t": the modifiers go down in
+/// order, the key is tapped, and the modifiers are released in reverse
+/// order, even when a step in between failed
+pub fn press_key_combo(combo: &str) -> Result<(), String> {
+    let (modifiers, key) = parse_key_combo(combo)?;
+    with_modifiers(&modifiers, || press_key(&key))
 }
 
-fn parse_key(key: &str) -> Result<Key, String> {
-    match key.to_lowercase().as_str() {
-        "return" | "enter" => Ok(Key::Return),
-        "escape" | "esc" => Ok(Key::Escape),
-        "backspace" => Ok(Key::Backspace),
-        "tab" => Ok(Key::Tab),
-        "space" => Ok(Key::Space),
-        "delete" | "del" => Ok(Key::Delete),
-        "home" => Ok(Key::Home),
-        "end" => Ok(Key::End),
-        "pageup" => Ok(Key::PageUp),
-        "pagedown" => Ok(Key::PageDown),
-        "left" | "leftarrow" => Ok(Key::LeftArrow),
-        "right" | "rightarrow" => Ok(Key::RightArrow),
-        "up" | "uparrow" => Ok(Key::UpArrow),
-        "down" | "downarrow" => Ok(Key::DownArrow),
-        "shift" => Ok(Key::Shift),
-        "control" | "ctrl" => Ok(Key::Control),
-        "alt" => Ok(Key::Alt),
-        "meta" | "super" | "windows" | "command" => Ok(Key::Meta),
-        "f1" => Ok(Key::F1),
-        "f2" => Ok(Key::F2),
-        "f3" => Ok(Key::F3),
-        "f4" => Ok(Key::F4),
-        "f5" => Ok(Key::F5),
-        "f6" => Ok(Key::F6),
-        "f7" => Ok(Key::F7),
-        "f8" => Ok(Key::F8),
-        "f9" => Ok(Key::F9),
-        "f10" => Ok(Key::F10),
-        "f11" => Ok(Key::F11),
-        "f12" => Ok(Key::F12),
-        _ => Err(format!("Unknown key: {}", key)),
+/// Run `inner` with `modifiers` held down, e.g. for a shift-click
+///
+/// The keys are released in reverse order whatever happens, also when
+/// pressing one of them or `inner` fails.
+pub fn with_modifiers<S, T>(
+    modifiers: &[S],
+    inner: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String>
+where
+    S: AsRef<str>,
+{
+    let mut held = Vec::new();
+    let mut result = Ok(());
+    for modifier in modifiers {
+        match key_down(modifier.as_ref()) {
+            Ok(()) => held.push(modifier.as_ref()),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    let result = result.and_then(|_| inner());
+    for modifier in held.iter().rev() {
+        let _ = key_up(modifier);
     }
+    result
+}
+
+/// Press and/or release a mouse button through the RemoteDesktop portal
+fn portal_button(button: &str, presses: &[bool]) -> Result<(), String> {
+    let code = portal::button_code(button)?;
+    let events: Vec<InputEvent> = presses
+        .iter()
+        .map(|&pressed| InputEvent::Button { code, pressed })
+        .collect();
+    portal::send_input(&events)
+}
+
+/// Press and/or release a key through the RemoteDesktop portal
+fn portal_key(key: &str, presses: &[bool]) -> Result<(), String> {
+    let keysym = portal::keysym(key)?;
+    let events: Vec<InputEvent> = presses
+        .iter()
+        .map(|&pressed| InputEvent::Key { keysym, pressed })
+        .collect();
+    portal::send_input(&events)
 }
 
 pub fn get_mouse_position() -> Result<(i32, i32), String> {
-    let settings = Settings::default();
-    let enigo = Enigo::new(&settings).map_err(|e| e.to_string())?;
-    let (x, y) = enigo.location().map_err(|e| e.to_string())?;
-    Ok((x, y))
+    if portal::portal_only() {
+        return Err("The pointer position is not available through portals".to_string());
+    }
+    input_backend().location()
+}
+
+/// Size of the main display in pixels
+pub fn display_size() -> Result<(i32, i32), String> {
+    if portal::portal_only() {
+        return portal::display_size();
+    }
+    enigo_input::main_display()
+}
+
+/// A way of sending mouse and keyboard input outside portal-only mode
+///
+/// Key and button names are the ones `press_key` and `click_mouse` take;
+/// `presses` is `[true]` for a press, `[false]` for a release and
+/// `[true, false]` for both.
+pub trait InputBackend: Send + Sync {
+    /// Name used in the config and in `status`
+    fn name(&self) -> &'static str;
+    fn move_mouse(&self, x: i32, y: i32) -> Result<(), String>;
+    fn move_mouse_relative(&self, dx: i32, dy: i32) -> Result<(), String>;
+    fn button(&self, button: &str, presses: &[bool]) -> Result<(), String>;
+    fn scroll(&self, amount: i32, direction: &str) -> Result<(), String>;
+    fn type_text(&self, text: &str) -> Result<(), String>;
+    fn key(&self, key: &str, presses: &[bool]) -> Result<(), String>;
+    fn location(&self) -> Result<(i32, i32), String>;
+
+    /// Click `count` times, first moving to `position` when given
+    fn clicks(
+        &self,
+        position: Option<(i32, i32)>,
+        button: &str,
+        count: u32,
+        settle: Duration,
+    ) -> Result<(), String> {
+        if let Some((x, y)) = position {
+            self.move_mouse(x, y)?;
+            std::thread::sleep(settle);
+        }
+        for click in 0..count {
+            if click > 0 {
+                std::thread::sleep(CLICK_INTERVAL);
+            }
+            self.button(button, &[true, false])?;
+        }
+        Ok(())
+    }
+}
+
+/// Input through enigo, which talks to the X server (and XWayland)
+pub struct Enigo;
+
+impl InputBackend for Enigo {
+    fn name(&self) -> &'static str {
+        "enigo"
+    }
+
+    fn move_mouse(&self, x: i32, y: i32) -> Result<(), String> {
+        enigo_input::move_mouse(x, y)
+    }
+
+    fn move_mouse_relative(&self, dx: i32, dy: i32) -> Result<(), String> {
+        enigo_input::move_mouse_relative(dx, dy)
+    }
+
+    fn button(&self, button: &str, presses: &[bool]) -> Result<(), String> {
+        enigo_input::button(button, presses)
+    }
+
+    fn scroll(&self, amount: i32, direction: &str) -> Result<(), String> {
+        enigo_input::scroll(amount, direction)
+    }
+
+    fn type_text(&self, text: &str) -> Result<(), String> {
+        enigo_input::type_text(text)
+    }
+
+    fn key(&self, key: &str, presses: &[bool]) -> Result<(), String> {
+        enigo_input::key(key, presses)
+    }
+
+    fn location(&self) -> Result<(i32, i32), String> {
+        enigo_input::location()
+    }
+
+    fn clicks(
+        &self,
+        position: Option<(i32, i32)>,
+        button: &str,
+        count: u32,
+        settle: Duration,
+    ) -> Result<(), String> {
+        enigo_input::clicks(position, button, count, settle)
+    }
+}
+
+/// Names `set_input_backend` takes besides "auto"
+pub const INPUT_BACKENDS: &[&str] = &["enigo", "ydotool", "wtype"];
+
+/// The backend chosen with `set_input_backend`, or the detected one once
+/// input was sent
+static INPUT_BACKEND: RwLock<Option<Arc<dyn InputBackend>>> = RwLock::new(None);
+
+/// Use the backend named `name` from now on; "auto" detects it again
+pub fn set_input_backend(name: &str) -> Result<(), String> {
+    let backend: Option<Arc<dyn InputBackend>> = match name {
+        "auto" => None,
+        "enigo" => Some(Arc::new(Enigo)),
+        "ydotool" => Some(Arc::new(Ydotool)),
+        "wtype" => Some(Arc::new(Wtype)),
+        _ => {
+            return Err(format!(
+                "Unknown input backend '{}' (one of {} or auto)",
+                name,
+                INPUT_BACKENDS.join(", ")
+            ));
+        }
+    };
+    *INPUT_BACKEND.write().unwrap() = backend;
+    Ok(())
+}
+
+/// The backend input goes through
+///
+/// Unless one was chosen, X11 sessions use enigo. Wayland sessions use
+/// ydotool when it is installed, then wtype (keyboard only), and enigo
+/// through XWayland otherwise.
+pub fn input_backend() -> Arc<dyn InputBackend> {
+    if let Some(backend) = INPUT_BACKEND.read().unwrap().as_ref() {
+        return Arc::clone(backend);
+    }
+    let wayland = std::env::var("WAYLAND_DISPLAY").is_ok();
+    let detected: Arc<dyn InputBackend> = if wayland && find_in_path("ydotool").is_some() {
+        Arc::new(Ydotool)
+    } else if wayland && find_in_path("wtype").is_some() {
+        Arc::new(Wtype)
+    } else {
+        Arc::new(Enigo)
+    };
+    *INPUT_BACKEND.write().unwrap() = Some(Arc::clone(&detected));
+    detected
+}
+
+/// Input through enigo, which talks to the X server
+#[cfg(feature = "x11")]
+mod enigo_input {
+    use enigo::{Axis, Button, Coordinate, Direction, Enigo, Key, Keyboard, Mouse, Settings};
+    use std::time::Duration;
+
+    fn enigo() -> Result<Enigo, String> {
+        Enigo::new(&Settings::default()).map_err(|e| e.to_string())
+    }
+
+    /// The enigo direction of a press and/or release
+    fn direction(presses: &[bool]) -> Direction {
+        match presses {
+            [true] => Direction::Press,
+            [false] => Direction::Release,
+            _ => Direction::Click,
+        }
+    }
+
+    pub fn move_mouse(x: i32, y: i32) -> Result<(), String> {
+        enigo()?
+            .move_mouse(x, y, Coordinate::Abs)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn move_mouse_relative(dx: i32, dy: i32) -> Result<(), String> {
+        enigo()?
+            .move_mouse(dx, dy, Coordinate::Rel)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn button(button: &str, presses: &[bool]) -> Result<(), String> {
+        let button = parse_button(button)?;
+        enigo()?
+            .button(button, direction(presses))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Click `count` times, first moving to `position` when given
+    pub fn clicks(
+        position: Option<(i32, i32)>,
+        button: &str,
+        count: u32,
+        settle: Duration,
+    ) -> Result<(), String> {
+        let button = parse_button(button)?;
+        let mut enigo = enigo()?;
+        if let Some((x, y)) = position {
+            enigo
+                .move_mouse(x, y, Coordinate::Abs)
+                .map_err(|e| e.to_string())?;
+            if !settle.is_zero() {
+                std::thread::sleep(settle);
+            }
+        }
+        for click in 0..count {
+            if click > 0 {
+                std::thread::sleep(super::CLICK_INTERVAL);
+            }
+            enigo
+                .button(button, Direction::Click)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn scroll(amount: i32, direction: &str) -> Result<(), String> {
+        let (amount, axis) = match direction {
+            "up" => (amount, Axis::Vertical),
+            "down" => (-amount, Axis::Vertical),
+            "left" => (-amount, Axis::Horizontal),
+            "right" => (amount, Axis::Horizontal),
+            _ => return Err(format!("Unknown scroll direction: {}", direction)),
+        };
+        enigo()?.scroll(amount, axis).map_err(|e| e.to_string())
+    }
+
+    pub fn type_text(text: &str) -> Result<(), String> {
+        enigo()?.fast_text(text).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn key(key: &str, presses: &[bool]) -> Result<(), String> {
+        let key = parse_key(key)?;
+        enigo()?
+            .key(key, direction(presses))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn location() -> Result<(i32, i32), String> {
+        enigo()?.location().map_err(|e| e.to_string())
+    }
+
+    pub fn main_display() -> Result<(i32, i32), String> {
+        enigo()?.main_display().map_err(|e| e.to_string())
+    }
+
+    pub(crate) fn parse_button(button: &str) -> Result<Button, String> {
+        match button {
+            "left" => Ok(Button::Left),
+            "right" => Ok(Button::Right),
+            "middle" => Ok(Button::Middle),
+            _ => Err(format!("Unknown button: {}", button)),
+        }
+    }
+
+    pub(crate) fn parse_key(key: &str) -> Result<Key, String> {
+        match key.to_lowercase().as_str() {
+            "return" | "enter" => Ok(Key::Return),
+            "escape" | "esc" => Ok(Key::Escape),
+            "backspace" => Ok(Key::Backspace),
+            "tab" => Ok(Key::Tab),
+            "space" => Ok(Key::Space),
+            "delete" | "del" => Ok(Key::Delete),
+            "home" => Ok(Key::Home),
+            "end" => Ok(Key::End),
+            "pageup" => Ok(Key::PageUp),
+            "pagedown" => Ok(Key::PageDown),
+            "left" | "leftarrow" => Ok(Key::LeftArrow),
+            "right" | "rightarrow" => Ok(Key::RightArrow),
+            "up" | "uparrow" => Ok(Key::UpArrow),
+            "down" | "downarrow" => Ok(Key::DownArrow),
+            "shift" => Ok(Key::Shift),
+            "control" | "ctrl" => Ok(Key::Control),
+            "alt" => Ok(Key::Alt),
+            "meta" | "super" | "windows" | "command" => Ok(Key::Meta),
+            "f1" => Ok(Key::F1),
+            "f2" => Ok(Key::F2),
+            "f3" => Ok(Key::F3),
+            "f4" => Ok(Key::F4),
+            "f5" => Ok(Key::F5),
+            "f6" => Ok(Key::F6),
+            "f7" => Ok(Key::F7),
+            "f8" => Ok(Key::F8),
+            "f9" => Ok(Key::F9),
+            "f10" => Ok(Key::F10),
+            "f11" => Ok(Key::F11),
+            "f12" => Ok(Key::F12),
+            // Any other single character, e.g. "a", "/" or "ç"
+            _ if key.chars().count() == 1 => Ok(Key::Unicode(key.chars().next().unwrap())),
+            // Keypad, media and F13-F24 keys go by their X11 keysym
+            lower => crate::hotkeys::keysym(lower)
+                .map(|(keysym, _)| Key::Other(keysym))
+                .ok_or_else(|| format!("Unknown key: {}", key)),
+        }
+    }
+}
+
+/// Without enigo only the portal can send input; names are still checked
+#[cfg(not(feature = "x11"))]
+mod enigo_input {
+    use crate::features::missing;
+    use crate::portal;
+    use std::time::Duration;
+
+    pub fn move_mouse(_x: i32, _y: i32) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn move_mouse_relative(_dx: i32, _dy: i32) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn button(_button: &str, _presses: &[bool]) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn clicks(
+        _position: Option<(i32, i32)>,
+        _button: &str,
+        _count: u32,
+        _settle: Duration,
+    ) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn scroll(_amount: i32, _direction: &str) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn type_text(_text: &str) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn key(_key: &str, _presses: &[bool]) -> Result<(), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn location() -> Result<(i32, i32), String> {
+        Err(missing("x11"))
+    }
+
+    pub fn main_display() -> Result<(i32, i32), String> {
+        Err(missing("x11"))
+    }
+
+    pub(crate) fn parse_button(button: &str) -> Result<i32, String> {
+        portal::button_code(button)
+    }
+
+    pub(crate) fn parse_key(key: &str) -> Result<i32, String> {
+        portal::keysym(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_combo() {
+        assert_eq!(
+            parse_key_combo("Ctrl+Shift+T").unwrap(),
+            (vec!["control", "shift"], "t".to_string())
+        );
+        assert_eq!(
+            parse_key_combo("ctrl++").unwrap(),
+            (vec!["control"], "+".to_string())
+        );
+        assert_eq!(parse_key_combo("f5").unwrap(), (vec![], "f5".to_string()));
+        assert!(parse_key_combo("hyper+x").is_err());
+        assert!(parse_key_combo("ctrl+").is_err());
+    }
+
+    #[test]
+    fn test_click_count() {
+        assert!(check_click_count(0).is_err());
+        assert!(check_click_count(3).is_ok());
+        assert!(check_click_count(MAX_CLICK_COUNT + 1).is_err());
+        assert!(click_times("left", u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_scroll_pages_direction() {
+        assert!(scroll_pages(1, "left").is_err());
+        assert!(scroll_pages(0, "down").is_ok());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        for key in ["a", "7", "ç", "Enter", "kp5", "volumeup", "f20"] {
+            assert!(parse_key(key).is_ok(), "{}", key);
+        }
+        assert!(parse_key("hyper").is_err());
+        assert!(key_names().iter().all(|name| parse_key(name).is_ok()));
+    }
 }
@@ -0,0 +1,165 @@
+use crate::config::ScreenshotConfig;
+use crate::paths;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Retention of the screenshot store, set from the daemon config
+static RETENTION: Mutex<ScreenshotConfig> = Mutex::new(ScreenshotConfig::DEFAULT);
+
+/// Directory of the screenshot store, that of the daemon's profile
+static STORE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// What a cleanup removed
+#[derive(Debug, Default, Serialize)]
+pub struct CleanupReport {
+    pub removed: usize,
+    pub kept: usize,
+    pub freed_bytes: u64,
+}
+
+/// Keep the screenshots taken from now on in the store of `profile`,
+/// within `config`
+pub fn set_store(profile: Option<&str>, config: ScreenshotConfig) {
+    *STORE.lock().unwrap() = Some(paths::screenshots_dir(profile));
+    *RETENTION.lock().unwrap() = config;
+}
+
+fn store_dir() -> PathBuf {
+    STORE
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| paths::screenshots_dir(None))
+}
+
+/// A new file in the screenshot store, named
+/// `casper_<kind>_<timestamp>.png`
+///
+/// The store is cleaned up first, so the files screenshots were taken into
+/// and never deleted do not pile up.
+pub fn screenshot_path(kind: &str) -> Result<PathBuf, String> {
+    let dir = store_dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+    let retention = RETENTION.lock().unwrap().clone();
+    let _ = cleanup_dir(&dir, retention.max_count, retention.max_age());
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    Ok(dir.join(format!("casper_{}_{}.png", kind, timestamp)))
+}
+
+/// Remove screenshots beyond the newest `max_count` and those older than
+/// `max_age_hours`, which default to the configured ones; like there, 0
+/// lifts a limit
+pub fn cleanup_screenshots(
+    max_count: Option<usize>,
+    max_age_hours: Option<u64>,
+) -> Result<CleanupReport, String> {
+    let configured = RETENTION.lock().unwrap().clone();
+    let retention = ScreenshotConfig {
+        max_count: max_count.unwrap_or(configured.max_count),
+        max_age_hours: max_age_hours.unwrap_or(configured.max_age_hours),
+    };
+    let dir = store_dir();
+    if !dir.exists() {
+        return Ok(CleanupReport::default());
+    }
+    cleanup_dir(&dir, retention.max_count, retention.max_age())
+}
+
+fn cleanup_dir(
+    dir: &Path,
+    max_count: usize,
+    max_age: Option<Duration>,
+) -> Result<CleanupReport, String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            metadata.is_file().then_some(())?;
+            Some((entry.path(), metadata.modified().ok()?, metadata.len()))
+        })
+        .collect();
+    let (expired, kept) = expired(entries, SystemTime::now(), max_count, max_age);
+    let mut report = CleanupReport {
+        kept,
+        ..CleanupReport::default()
+    };
+    for (path, size) in expired {
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                report.removed += 1;
+                report.freed_bytes += size;
+            }
+            Err(_) => report.kept += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// The files (path, modified, size) to remove, with their sizes, and how
+/// many stay
+fn expired(
+    mut entries: Vec<(PathBuf, SystemTime, u64)>,
+    now: SystemTime,
+    max_count: usize,
+    max_age: Option<Duration>,
+) -> (Vec<(PathBuf, u64)>, usize) {
+    // Newest first, so whatever is past the count is the oldest
+    entries.sort_by_key(|&(_, modified, _)| std::cmp::Reverse(modified));
+    let total = entries.len();
+    let expired: Vec<(PathBuf, u64)> = entries
+        .into_iter()
+        .enumerate()
+        .filter(|(index, (_, modified, _))| {
+            let too_many = max_count > 0 && *index >= max_count;
+            let too_old = max_age.is_some_and(|max_age| {
+                now.duration_since(*modified).is_ok_and(|age| age > max_age)
+            });
+            too_many || too_old
+        })
+        .map(|(_, (path, _, size))| (path, size))
+        .collect();
+    let kept = total - expired.len();
+    (expired, kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screenshot_retention() {
+        let now = SystemTime::now();
+        let hours = |h: u64| now - Duration::from_secs(h * 3600);
+        let entries = vec![
+            (PathBuf::from("old.png"), hours(30), 10),
+            (PathBuf::from("new.png"), hours(0), 20),
+            (PathBuf::from("older.png"), hours(2), 30),
+            (PathBuf::from("mid.png"), hours(1), 40),
+        ];
+        let names = |(expired, kept): (Vec<(PathBuf, u64)>, usize)| {
+            let names: Vec<String> = expired
+                .iter()
+                .map(|(path, _)| path.display().to_string())
+                .collect();
+            (names, kept)
+        };
+
+        let day = Some(Duration::from_secs(24 * 3600));
+        assert_eq!(
+            names(expired(entries.clone(), now, 0, day)),
+            (vec!["old.png".to_string()], 3)
+        );
+        assert_eq!(
+            names(expired(entries.clone(), now, 2, None)),
+            (vec!["older.png".to_string(), "old.png".to_string()], 2)
+        );
+        assert_eq!(names(expired(entries, now, 0, None)), (vec![], 4));
+    }
+}
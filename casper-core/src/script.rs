@@ -0,0 +1,515 @@
+use crate::actions::{Action, ActionSequence, ActionWithTimestamp, ProcessWait, WindowWait};
+use serde_json::{Map, Value};
+
+/// Parse a sequence written in the compact script format
+///
+/// ```text
+/// @name open_github
+/// @description Open GitHub in a new tab
+/// launch "firefox"
+/// after 2s key ctrl+l
+/// type "github.com"
+/// key enter
+///
+/// retry:
+/// # Comment lines right above a step become its comment
+/// after 500ms click left
+/// json {"type": "Repeat", "count": 2, "actions": [{"type": "PressKey", "key": "tab"}]}
+/// ```
+///
+/// `@field value` lines set sequence fields (the value is JSON, or a bare
+/// string), `label:` labels the next step, `after <delay>` sets the delay
+/// before a step, and `json` / `step` take any action / whole step as JSON.
+pub fn parse(text: &str) -> Result<ActionSequence, String> {
+    let mut fields = Map::new();
+    let mut steps = Vec::new();
+    let mut label = None;
+    let mut comment: Vec<&str> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let at_line = |e: String| format!("Line {}: {}", number + 1, e);
+        if line.is_empty() {
+            comment.clear();
+        } else if let Some(text) = line.strip_prefix('#') {
+            comment.push(text.strip_prefix(' ').unwrap_or(text));
+        } else if let Some(field) = line.strip_prefix('@') {
+            let (key, value) = field.split_once(' ').unwrap_or((field, ""));
+            let value = value.trim();
+            let value =
+                serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+            fields.insert(key.to_string(), value);
+        } else if let Some(name) = line.strip_suffix(':')
+            && !name.is_empty()
+            && !name.contains(char::is_whitespace)
+        {
+            label = Some(name.to_string());
+        } else {
+            let mut step = parse_step(line).map_err(at_line)?;
+            if step.label.is_none() {
+                step.label = label.take();
+            }
+            if step.comment.is_none() && !comment.is_empty() {
+                step.comment = Some(comment.join("\n"));
+            }
+            comment.clear();
+            steps.push(step);
+        }
+    }
+
+    fields.insert(
+        "actions".to_string(),
+        serde_json::to_value(steps).map_err(|e| e.to_string())?,
+    );
+    serde_json::from_value(Value::Object(fields)).map_err(|e| e.to_string())
+}
+
+/// Write a sequence in the compact script format
+pub fn render(sequence: &ActionSequence) -> Result<String, String> {
+    let mut fields = serde_json::to_value(sequence).map_err(|e| e.to_string())?;
+    let fields = fields
+        .as_object_mut()
+        .ok_or("A sequence serializes to an object")?;
+    fields.remove("actions");
+
+    let mut out = String::new();
+    for (key, value) in fields.iter() {
+        out.push_str(&format!("@{} {}\n", key, render_field(value)));
+    }
+    for step in &sequence.actions {
+        out.push('\n');
+        if let Some(label) = &step.label
+            && !label.is_empty()
+            && !label.contains(char::is_whitespace)
+        {
+            out.push_str(&format!("{}:\n", label));
+        }
+        if let Some(comment) = &step.comment {
+            for line in comment.lines() {
+                out.push_str(&format!("# {}\n", line));
+            }
+        }
+        out.push_str(&render_step(step)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// A field value: bare when it reads back as the same string, JSON otherwise
+fn render_field(value: &Value) -> String {
+    match value {
+        Value::String(text)
+            if text.trim() == text
+                && !text.is_empty()
+                && serde_json::from_str::<Value>(text).is_err() =>
+        {
+            text.clone()
+        }
+        other => other.to_string(),
+    }
+}
+
+fn parse_step(line: &str) -> Result<ActionWithTimestamp, String> {
+    let (mut command, mut rest) = split_word(line);
+    let mut delay_ms = 0;
+    if command == "after" {
+        let (delay, after) = split_word(rest);
+        delay_ms = parse_duration(delay)?;
+        (command, rest) = split_word(after);
+    }
+
+    match command {
+        "step" => {
+            let mut step: ActionWithTimestamp =
+                serde_json::from_str(rest).map_err(|e| format!("Invalid step: {}", e))?;
+            if delay_ms > 0 {
+                step.delay_ms = delay_ms;
+            }
+            return Ok(step);
+        }
+        "json" => {
+            let action =
+                serde_json::from_str(rest).map_err(|e| format!("Invalid action: {}", e))?;
+            return Ok(ActionWithTimestamp::new(action, delay_ms));
+        }
+        _ => {}
+    }
+
+    let args = tokenize(rest)?;
+    let arg = |index: usize| {
+        args.get(index)
+            .cloned()
+            .ok_or_else(|| format!("'{}' needs {} argument(s)", command, index + 1))
+    };
+    let number = |index: usize| {
+        arg(index)?
+            .parse::<i32>()
+            .map_err(|_| format!("'{}' expects a number", command))
+    };
+    let button = || args.first().cloned().unwrap_or_else(|| "left".to_string());
+    let timeout = |index: usize| args.get(index).map(|t| parse_duration(t)).transpose();
+
+    let action = match command {
+        "move" => Action::MoveMouse {
+            x: number(0)?,
+            y: number(1)?,
+            anchor: None,
+        },
+        "move_to" => Action::MoveToPoint { point: arg(0)? },
+        "move_by" => Action::MoveMouseRel {
+            dx: number(0)?,
+            dy: number(1)?,
+        },
+        "click" | "double_click" => Action::ClickMouse {
+            button: button(),
+            x: None,
+            y: None,
+            anchor: None,
+            settle_ms: None,
+            count: match (command, args.get(1)) {
+                ("double_click", _) => Some(2),
+                (_, Some(_)) => Some(number(1)? as u32),
+                _ => None,
+            },
+        },
+        "mouse_down" => Action::MouseDown { button: button() },
+        "mouse_up" => Action::MouseUp { button: button() },
+        "scroll" => Action::Scroll {
+            direction: arg(0)?,
+            amount: number(1)?,
+            step: None,
+            delay_ms: None,
+        },
+        "type" => Action::TypeText {
+            text: arg(0)?,
+            sensitive: false,
+            delay_ms: None,
+            via_clipboard: false,
+            layout: None,
+        },
+        // "key ctrl+l" holds the modifiers; "key +" is the plus key itself
+        "key" => match arg(0)? {
+            combo if combo.len() > 1 && combo.contains('+') => Action::PressCombo { combo },
+            key => Action::PressKey { key },
+        },
+        "key_down" => Action::KeyDown { key: arg(0)? },
+        "key_up" => Action::KeyUp { key: arg(0)? },
+        "run" => Action::RunCommand { command: arg(0)? },
+        "wait" => Action::Wait {
+            milliseconds: parse_duration(&arg(0)?)?,
+        },
+        "launch" => Action::LaunchApp { app_name: arg(0)? },
+        "focus" => Action::FocusWindow {
+            window_pattern: arg(0)?,
+        },
+        "notify" => Action::ShowNotification {
+            summary: arg(0)?,
+            body: args.get(1).cloned().unwrap_or_default(),
+        },
+        "say" => Action::Speak { text: arg(0)? },
+        "app_action" => Action::AppAction { name: arg(0)? },
+        "call" => Action::CallSequence { name: arg(0)? },
+        "checkpoint" => Action::Checkpoint { name: arg(0)? },
+        "set_clipboard" => Action::SetClipboard { text: arg(0)? },
+        "get_clipboard" => Action::GetClipboard { variable: arg(0)? },
+        "store_set" => Action::StoreSet {
+            key: arg(0)?,
+            value: arg(1)?,
+        },
+        "store_get" => Action::StoreGet {
+            key: arg(0)?,
+            variable: arg(1)?,
+        },
+        "wait_for_window" => Action::WaitForWindow(WindowWait {
+            pattern: arg(0)?,
+            timeout_ms: timeout(1)?,
+        }),
+        "wait_for_process" => Action::WaitForProcess(ProcessWait {
+            name: arg(0)?,
+            timeout_ms: timeout(1)?,
+        }),
+        "wait_for_exit" => Action::WaitForProcessExit(ProcessWait {
+            name: arg(0)?,
+            timeout_ms: timeout(1)?,
+        }),
+        "kill" => Action::KillProcess {
+            name: arg(0)?,
+            signal: args.get(1).cloned(),
+        },
+        other => return Err(format!("Unknown command '{}'", other)),
+    };
+    Ok(ActionWithTimestamp::new(action, delay_ms))
+}
+
+fn render_step(step: &ActionWithTimestamp) -> Result<String, String> {
+    let mut line = String::new();
+    if step.delay_ms > 0 {
+        line.push_str(&format!("after {}ms ", step.delay_ms));
+    }
+    // Step IDs, error policies and labels that don't fit on a line of their own
+    let label_fits = step
+        .label
+        .as_ref()
+        .is_none_or(|l| !l.is_empty() && !l.contains(char::is_whitespace));
+    if step.id.is_some() || step.on_error.is_some() || !label_fits {
+        let mut step = step.clone();
+        step.delay_ms = 0;
+        step.comment = None;
+        if label_fits {
+            step.label = None;
+        }
+        let json = serde_json::to_string(&step).map_err(|e| e.to_string())?;
+        line.push_str(&format!("step {}", json));
+        return Ok(line);
+    }
+
+    let quote = |text: &str| Value::String(text.to_string()).to_string();
+    let with_timeout = |command: &str, text: &str, timeout_ms: &Option<u64>| match timeout_ms {
+        Some(timeout) => format!("{} {} {}ms", command, quote(text), timeout),
+        None => format!("{} {}", command, quote(text)),
+    };
+    let rendered = match &step.action {
+        Action::MoveMouse { x, y, anchor: None } => format!("move {} {}", x, y),
+        Action::MoveToPoint { point } => format!("move_to {}", quote(point)),
+        Action::MoveMouseRel { dx, dy } => format!("move_by {} {}", dx, dy),
+        Action::ClickMouse {
+            button,
+            x: None,
+            y: None,
+            anchor: None,
+            settle_ms: None,
+            count,
+        } => match count {
+            None => format!("click {}", quote(button)),
+            Some(2) => format!("double_click {}", quote(button)),
+            Some(count) => format!("click {} {}", quote(button), count),
+        },
+        Action::MouseDown { button } => format!("mouse_down {}", quote(button)),
+        Action::MouseUp { button } => format!("mouse_up {}", quote(button)),
+        Action::Scroll {
+            amount,
+            direction,
+            step: None,
+            delay_ms: None,
+        } => format!("scroll {} {}", quote(direction), amount),
+        Action::TypeText {
+            text,
+            sensitive: false,
+            delay_ms: None,
+            via_clipboard: false,
+            layout: None,
+        } => format!("type {}", quote(text)),
+        Action::PressKey { key } => format!("key {}", quote(key)),
+        Action::PressCombo { combo } => format!("key {}", quote(combo)),
+        Action::KeyDown { key } => format!("key_down {}", quote(key)),
+        Action::KeyUp { key } => format!("key_up {}", quote(key)),
+        Action::RunCommand { command } => format!("run {}", quote(command)),
+        Action::Wait { milliseconds } => format!("wait {}ms", milliseconds),
+        Action::LaunchApp { app_name } => format!("launch {}", quote(app_name)),
+        Action::FocusWindow { window_pattern } => format!("focus {}", quote(window_pattern)),
+        Action::ShowNotification { summary, body } => {
+            format!("notify {} {}", quote(summary), quote(body))
+        }
+        Action::Speak { text } => format!("say {}", quote(text)),
+        Action::AppAction { name } => format!("app_action {}", quote(name)),
+        Action::CallSequence { name } => format!("call {}", quote(name)),
+        Action::Checkpoint { name } => format!("checkpoint {}", quote(name)),
+        Action::SetClipboard { text } => format!("set_clipboard {}", quote(text)),
+        Action::GetClipboard { variable } => format!("get_clipboard {}", quote(variable)),
+        Action::StoreSet { key, value } => format!("store_set {} {}", quote(key), quote(value)),
+        Action::StoreGet { key, variable } => {
+            format!("store_get {} {}", quote(key), quote(variable))
+        }
+        Action::WaitForWindow(wait) => {
+            with_timeout("wait_for_window", &wait.pattern, &wait.timeout_ms)
+        }
+        Action::WaitForProcess(wait) => {
+            with_timeout("wait_for_process", &wait.name, &wait.timeout_ms)
+        }
+        Action::WaitForProcessExit(wait) => {
+            with_timeout("wait_for_exit", &wait.name, &wait.timeout_ms)
+        }
+        Action::KillProcess { name, signal } => match signal {
+            Some(signal) => format!("kill {} {}", quote(name), quote(signal)),
+            None => format!("kill {}", quote(name)),
+        },
+        other => format!(
+            "json {}",
+            serde_json::to_string(other).map_err(|e| e.to_string())?
+        ),
+    };
+    line.push_str(&rendered);
+    Ok(line)
+}
+
+/// "500", "500ms", "1.5s" or "2m" as milliseconds
+fn parse_duration(text: &str) -> Result<u64, String> {
+    let (number, scale) = if let Some(ms) = text.strip_suffix("ms") {
+        (ms, 1.0)
+    } else if let Some(s) = text.strip_suffix('s') {
+        (s, 1000.0)
+    } else if let Some(m) = text.strip_suffix('m') {
+        (m, 60_000.0)
+    } else {
+        (text, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0.0 => Ok((value * scale).round() as u64),
+        _ => Err(format!("Invalid duration '{}' (e.g. 500ms or 2s)", text)),
+    }
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Split arguments on whitespace; double-quoted arguments use JSON escapes
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if rest.starts_with('"') {
+            let mut escaped = false;
+            let end = rest[1..]
+                .char_indices()
+                .find(|&(_, c)| {
+                    let closes = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map(|(i, _)| i + 2)
+                .ok_or("Unterminated string")?;
+            let arg: String =
+                serde_json::from_str(&rest[..end]).map_err(|e| format!("Invalid string: {}", e))?;
+            args.push(arg);
+            rest = rest[end..].trim_start();
+        } else {
+            let (word, after) = split_word(rest);
+            args.push(word.to_string());
+            rest = after;
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let sequence = parse(
+            "@name open_github\n\
+             @tags [\"web\"]\n\
+             launch firefox\n\
+             after 1.5s key ctrl+l\n\
+             \n\
+             go:\n\
+             # Types the address\n\
+             type \"github.com \\\"home\\\"\"\n\
+             json {\"type\": \"WaitForProcess\", \"name\": \"firefox\", \"timeout_ms\": null}\n",
+        )
+        .unwrap();
+        assert_eq!(sequence.name, "open_github");
+        assert_eq!(sequence.tags, vec!["web"]);
+        assert_eq!(sequence.actions.len(), 4);
+        assert_eq!(sequence.actions[1].delay_ms, 1500);
+        assert!(matches!(
+            &sequence.actions[1].action,
+            Action::PressCombo { combo } if combo == "ctrl+l"
+        ));
+        assert_eq!(sequence.actions[2].label.as_deref(), Some("go"));
+        assert_eq!(
+            sequence.actions[2].comment.as_deref(),
+            Some("Types the address")
+        );
+        assert!(matches!(
+            &sequence.actions[2].action,
+            Action::TypeText { text, .. } if text == "github.com \"home\""
+        ));
+
+        let error = parse("@name x\nmove 1\n").unwrap_err();
+        assert!(error.starts_with("Line 2:"), "{}", error);
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let mut sequence = ActionSequence::new("round trip".to_string(), "Two words".to_string());
+        sequence.add_action(
+            Action::MoveMouse {
+                x: 10,
+                y: -20,
+                anchor: None,
+            },
+            0,
+        );
+        sequence.add_action(Action::MoveMouseRel { dx: -5, dy: 12 }, 0);
+        sequence.add_action(
+            Action::ShowNotification {
+                summary: "Done".to_string(),
+                body: "line\nbreak".to_string(),
+            },
+            250,
+        );
+        for count in [Some(2), Some(3)] {
+            sequence.add_action(
+                Action::ClickMouse {
+                    button: "left".to_string(),
+                    x: None,
+                    y: None,
+                    anchor: None,
+                    settle_ms: None,
+                    count,
+                },
+                0,
+            );
+        }
+        sequence.add_action(
+            Action::KillProcess {
+                name: "stuck app".to_string(),
+                signal: Some("KILL".to_string()),
+            },
+            0,
+        );
+        sequence.add_action(
+            Action::Repeat {
+                count: 2,
+                actions: vec![Action::PressKey {
+                    key: "tab".to_string(),
+                }],
+            },
+            0,
+        );
+        sequence.add_action(
+            Action::StoreSet {
+                key: "meeting_mode".to_string(),
+                value: "on".to_string(),
+            },
+            0,
+        );
+        sequence.add_action(
+            Action::StoreGet {
+                key: "meeting_mode".to_string(),
+                variable: "mode".to_string(),
+            },
+            0,
+        );
+        let mut step = ActionWithTimestamp::new(Action::Wait { milliseconds: 5 }, 0);
+        step.id = Some("pause".to_string());
+        step.label = Some("two words".to_string());
+        sequence.add_step(step);
+
+        let text = render(&sequence).unwrap();
+        let parsed = parse(&text).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&sequence).unwrap(),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Told when a sensor turns on or off outside the daemon's own handlers
+pub type SensorObserver = Box<dyn Fn(Sensor, &str, bool) + Send + Sync>;
+
+/// Whether playback may read the screen; privacy mode turns it off
+static SCREEN_ALLOWED: AtomicBool = AtomicBool::new(true);
+
+static OBSERVER: Mutex<Option<SensorObserver>> = Mutex::new(None);
+
+/// Allow or refuse reading the screen through `watch_screen`
+pub fn set_screen_allowed(allowed: bool) {
+    SCREEN_ALLOWED.store(allowed, Ordering::Relaxed);
+}
+
+/// Tell `observer` about the sensors `watch_screen` turns on and off
+pub fn set_observer(observer: SensorObserver) {
+    *OBSERVER.lock().unwrap() = Some(observer);
+}
+
+/// Run `read`, which looks at the screen for `detail`, with
+/// `ScreenWatching` on; refused while the screen may not be read
+pub fn watch_screen<R>(
+    detail: &str,
+    read: impl FnOnce() -> Result<R, String>,
+) -> Result<R, String> {
+    if !SCREEN_ALLOWED.load(Ordering::Relaxed) {
+        return Err("Screen capture is disabled while privacy mode is on".to_string());
+    }
+    let notify = |active: bool| {
+        if let Some(observer) = OBSERVER.lock().unwrap().as_ref() {
+            observer(Sensor::ScreenWatching, detail, active);
+        }
+    };
+    notify(true);
+    let result = read();
+    notify(false);
+    result
+}
+
+/// Ways Casper can observe the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sensor {
+    /// Recording input into a sequence
+    Recording,
+    /// Microphone capture
+    Listening,
+    /// Periodic screenshots
+    Timelapse,
+    /// Continuous screen capture or watching for screen changes
+    ScreenWatching,
+}
+
+/// A sensor that is currently on
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveSensor {
+    pub sensor: Sensor,
+    /// What the sensor is working for (sequence name, stream ID, ...)
+    pub detail: String,
+    /// RFC 3339 time the sensor was turned on
+    pub since: String,
+}
+
+/// Bookkeeping of active sensors, so they can always be shown to the user
+#[derive(Debug, Default)]
+pub struct SensorRegistry {
+    active: Vec<ActiveSensor>,
+}
+
+impl SensorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark a sensor as on; returns false if it was already on for this detail
+    pub fn start(&mut self, sensor: Sensor, detail: &str) -> bool {
+        if self.find(sensor, detail).is_some() {
+            return false;
+        }
+        self.active.push(ActiveSensor {
+            sensor,
+            detail: detail.to_string(),
+            since: chrono::Utc::now().to_rfc3339(),
+        });
+        true
+    }
+
+    /// Mark a sensor as off; returns false if it was not on for this detail
+    pub fn stop(&mut self, sensor: Sensor, detail: &str) -> bool {
+        match self.find(sensor, detail) {
+            Some(index) => {
+                self.active.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn active(&self) -> &[ActiveSensor] {
+        &self.active
+    }
+
+    pub fn is_active(&self, sensor: Sensor) -> bool {
+        self.active.iter().any(|a| a.sensor == sensor)
+    }
+
+    fn find(&self, sensor: Sensor, detail: &str) -> Option<usize> {
+        self.active
+            .iter()
+            .position(|a| a.sensor == sensor && a.detail == detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_and_stop() {
+        let mut sensors = SensorRegistry::new();
+        assert!(sensors.start(Sensor::Recording, "demo"));
+        assert!(!sensors.start(Sensor::Recording, "demo"));
+        assert!(sensors.is_active(Sensor::Recording));
+        assert!(!sensors.is_active(Sensor::Listening));
+
+        assert!(!sensors.stop(Sensor::Recording, "other"));
+        assert!(sensors.stop(Sensor::Recording, "demo"));
+        assert!(sensors.active().is_empty());
+    }
+
+    #[test]
+    fn test_watch_screen_in_privacy_mode() {
+        set_screen_allowed(false);
+        let mut read = false;
+        let result = watch_screen("test", || {
+            read = true;
+            Ok(())
+        });
+        set_screen_allowed(true);
+        assert!(result.is_err() && !read);
+        assert!(watch_screen("test", || Ok(1)).is_ok());
+    }
+
+    #[test]
+    fn test_sensor_names() {
+        assert_eq!(
+            serde_json::to_value(Sensor::ScreenWatching).unwrap(),
+            "screen_watching"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::paths;
+use crate::screen::{get_mouse_position, move_mouse};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Display server the session runs on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionType {
+    Wayland,
+    X11,
+    Unknown,
+}
+
+/// Detected desktop environment
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub session: SessionType,
+    pub desktop: Option<String>,
+}
+
+impl Environment {
+    /// Detect the session type and desktop from the usual environment variables
+    pub fn detect() -> Self {
+        let session = match std::env::var("XDG_SESSION_TYPE").as_deref() {
+            Ok("wayland") => SessionType::Wayland,
+            Ok("x11") => SessionType::X11,
+            _ if std::env::var("WAYLAND_DISPLAY").is_ok() => SessionType::Wayland,
+            _ if std::env::var("DISPLAY").is_ok() => SessionType::X11,
+            _ => SessionType::Unknown,
+        };
+        let desktop = std::env::var("XDG_CURRENT_DESKTOP")
+            .ok()
+            .filter(|d| !d.is_empty());
+
+        Environment { session, desktop }
+    }
+
+    pub fn is_hyprland(&self) -> bool {
+        std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok()
+            || self
+                .desktop
+                .as_deref()
+                .is_some_and(|d| d.eq_ignore_ascii_case("hyprland"))
+    }
+}
+
+/// An external program Casper shells out to
+#[derive(Debug, Clone)]
+pub struct ToolCheck {
+    pub name: &'static str,
+    pub purpose: &'static str,
+    /// Arch Linux package providing the tool
+    pub package: &'static str,
+    pub required: bool,
+    pub found: bool,
+}
+
+impl ToolCheck {
+    pub fn install_hint(&self) -> String {
+        format!("sudo pacman -S {}", self.package)
+    }
+}
+
+/// Check which external tools are installed, given the environment
+///
+/// Tools that only matter on another session type are left out; a tool is
+/// `required` when the feature it backs has no alternative in this session.
+pub fn check_tools(env: &Environment) -> Vec<ToolCheck> {
+    let wayland = env.session == SessionType::Wayland;
+    let x11 = env.session == SessionType::X11;
+
+    let mut tools = vec![
+        (
+            "wmctrl",
+            "window listing and focus",
+            "wmctrl",
+            !env.is_hyprland(),
+        ),
+        ("xdotool", "window search and activation", "xdotool", x11),
+        ("espeak-ng", "text-to-speech", "espeak-ng", false),
+        ("pgrep", "process detection", "procps-ng", true),
+    ];
+    if wayland {
+        tools.push(("grim", "screenshots", "grim", true));
+        tools.push(("slurp", "region selection", "slurp", false));
+    } else {
+        tools.push(("scrot", "screenshots", "scrot", false));
+        tools.push(("import", "screenshots (fallback)", "imagemagick", false));
+    }
+    if env.is_hyprland() {
+        tools.push(("hyprctl", "Hyprland window control", "hyprland", true));
+    }
+
+    tools
+        .into_iter()
+        .map(|(name, purpose, package, required)| ToolCheck {
+            name,
+            purpose,
+            package,
+            required,
+            found: find_in_path(name).is_some(),
+        })
+        .collect()
+}
+
+/// Locate an executable on PATH
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| {
+            fs::metadata(candidate)
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+/// Create the config and data directories used by a profile
+pub fn create_directories(profile: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let dirs = vec![paths::config_dir(profile), paths::actions_dir(profile)];
+    for dir in &dirs {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    Ok(dirs)
+}
+
+/// Set keys in a dotenv-style file, keeping unrelated lines and comments
+///
+/// The file holds secrets, so it is created readable by the owner only.
+pub fn write_env_file(path: &Path, values: &[(&str, &str)]) -> Result<(), String> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let content = merge_env(&existing, values);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to restrict {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Replace or append `KEY=value` lines in dotenv content
+fn merge_env(existing: &str, values: &[(&str, &str)]) -> String {
+    let mut remaining: Vec<_> = values.to_vec();
+    let mut lines: Vec<String> = existing
+        .lines()
+        .map(|line| {
+            let key = line.split('=').next().unwrap_or("").trim();
+            match remaining.iter().position(|(k, _)| *k == key) {
+                Some(i) if !line.trim_start().starts_with('#') => {
+                    let (k, v) = remaining.remove(i);
+                    format!("{}={}", k, v)
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect();
+    lines.extend(remaining.iter().map(|(k, v)| format!("{}={}", k, v)));
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// Verify that synthetic input reaches the desktop
+///
+/// Nudges the pointer, reads its position back and restores it.
+pub fn verify_input_injection() -> Result<(), String> {
+    let (x, y) = get_mouse_position()?;
+    let target = (x + 25, y + 25);
+    move_mouse(target.0, target.1)?;
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let moved = get_mouse_position()?;
+    let _ = move_mouse(x, y);
+
+    if moved == target {
+        Ok(())
+    } else {
+        Err(format!(
+            "Pointer ended at {:?} instead of {:?}; the compositor may be blocking virtual input",
+            moved, target
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_env_replaces_and_appends() {
+        let existing = "# AI settings\nAI_TOKEN=old\nOTHER=1\n";
+        let merged = merge_env(existing, &[("AI_TOKEN", "new"), ("AI_MODEL", "m")]);
+        assert_eq!(merged, "# AI settings\nAI_TOKEN=new\nOTHER=1\nAI_MODEL=m\n");
+    }
+
+    #[test]
+    fn test_merge_env_ignores_commented_keys() {
+        let merged = merge_env("#AI_TOKEN=example", &[("AI_TOKEN", "x")]);
+        assert_eq!(merged, "#AI_TOKEN=example\nAI_TOKEN=x\n");
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        assert!(find_in_path("sh").is_some());
+        assert!(find_in_path("definitely-not-a-casper-tool").is_none());
+    }
+}
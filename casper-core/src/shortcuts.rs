@@ -0,0 +1,286 @@
+use crate::paths;
+use crate::screen::{parse_key_combo, press_key_combo};
+use crate::window::active_window_class;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// An application, its window class patterns and its (action, chord) pairs
+type BuiltinApp = (
+    &'static str,
+    &'static [&'static str],
+    &'static [(&'static str, &'static str)],
+);
+
+/// Built-in shortcuts
+///
+/// An application without class patterns applies to every window and is
+/// consulted last.
+const BUILTIN: &[BuiltinApp] = &[
+    (
+        "Firefox",
+        &["firefox", "librewolf"],
+        &[
+            ("new tab", "ctrl+t"),
+            ("close tab", "ctrl+w"),
+            ("reopen closed tab", "ctrl+shift+t"),
+            ("next tab", "ctrl+tab"),
+            ("previous tab", "ctrl+shift+tab"),
+            ("new window", "ctrl+n"),
+            ("private window", "ctrl+shift+p"),
+            ("address bar", "ctrl+l"),
+            ("reload", "ctrl+r"),
+            ("back", "alt+left"),
+            ("forward", "alt+right"),
+            ("zoom in", "ctrl+="),
+            ("zoom out", "ctrl+-"),
+            ("reset zoom", "ctrl+0"),
+            ("bookmark page", "ctrl+d"),
+            ("history", "ctrl+h"),
+            ("downloads", "ctrl+shift+y"),
+        ],
+    ),
+    (
+        "Chromium",
+        &["chrom", "brave", "vivaldi", "edge"],
+        &[
+            ("new tab", "ctrl+t"),
+            ("close tab", "ctrl+w"),
+            ("reopen closed tab", "ctrl+shift+t"),
+            ("next tab", "ctrl+tab"),
+            ("previous tab", "ctrl+shift+tab"),
+            ("new window", "ctrl+n"),
+            ("private window", "ctrl+shift+n"),
+            ("address bar", "ctrl+l"),
+            ("reload", "ctrl+r"),
+            ("back", "alt+left"),
+            ("forward", "alt+right"),
+            ("zoom in", "ctrl+="),
+            ("zoom out", "ctrl+-"),
+            ("reset zoom", "ctrl+0"),
+            ("bookmark page", "ctrl+d"),
+            ("history", "ctrl+h"),
+            ("downloads", "ctrl+j"),
+        ],
+    ),
+    (
+        "Terminal",
+        &[
+            "kitty",
+            "alacritty",
+            "terminal",
+            "konsole",
+            "foot",
+            "wezterm",
+            "tilix",
+            "terminator",
+            "ghostty",
+        ],
+        &[
+            ("new tab", "ctrl+shift+t"),
+            ("close tab", "ctrl+shift+w"),
+            ("new window", "ctrl+shift+n"),
+            ("copy", "ctrl+shift+c"),
+            ("paste", "ctrl+shift+v"),
+            ("find", "ctrl+shift+f"),
+            ("zoom in", "ctrl+shift+="),
+            ("zoom out", "ctrl+-"),
+        ],
+    ),
+    (
+        "VS Code",
+        &["code", "vscodium"],
+        &[
+            ("command palette", "ctrl+shift+p"),
+            ("quick open", "ctrl+p"),
+            ("new file", "ctrl+n"),
+            ("close tab", "ctrl+w"),
+            ("reopen closed tab", "ctrl+shift+t"),
+            ("next tab", "ctrl+pagedown"),
+            ("previous tab", "ctrl+pageup"),
+            ("toggle terminal", "ctrl+`"),
+            ("toggle sidebar", "ctrl+b"),
+            ("find in files", "ctrl+shift+f"),
+            ("go to line", "ctrl+g"),
+            ("comment line", "ctrl+/"),
+        ],
+    ),
+    (
+        "Any application",
+        &[],
+        &[
+            ("copy", "ctrl+c"),
+            ("cut", "ctrl+x"),
+            ("paste", "ctrl+v"),
+            ("undo", "ctrl+z"),
+            ("redo", "ctrl+shift+z"),
+            ("select all", "ctrl+a"),
+            ("save", "ctrl+s"),
+            ("save as", "ctrl+shift+s"),
+            ("open", "ctrl+o"),
+            ("print", "ctrl+p"),
+            ("find", "ctrl+f"),
+            ("new window", "ctrl+n"),
+            ("close window", "alt+f4"),
+        ],
+    ),
+];
+
+/// Shortcuts of one application, e.g. `{"app": "Kitty", "classes": ["kitty"],
+/// "actions": {"new tab": "ctrl+shift+t"}}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppShortcuts {
+    pub app: String,
+    /// Case-insensitive substrings of the window class; none means every window
+    #[serde(default)]
+    pub classes: Vec<String>,
+    /// Action name to key chord
+    pub actions: BTreeMap<String, String>,
+}
+
+impl AppShortcuts {
+    fn matches(&self, class: &str) -> bool {
+        let class = class.to_lowercase();
+        self.classes.is_empty()
+            || self
+                .classes
+                .iter()
+                .any(|pattern| class.contains(&pattern.to_lowercase()))
+    }
+}
+
+/// A shortcut found for a window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Shortcut {
+    pub app: String,
+    pub action: String,
+    pub chord: String,
+}
+
+/// Which key chord performs a named action in which application
+///
+/// User entries come first so they override the built-in ones; within each,
+/// application-specific entries win over the ones for every window.
+#[derive(Debug, Clone)]
+pub struct ShortcutTable {
+    apps: Vec<AppShortcuts>,
+}
+
+impl ShortcutTable {
+    /// Only the built-in shortcuts
+    pub fn builtin() -> Self {
+        let apps = BUILTIN
+            .iter()
+            .map(|(app, classes, actions)| AppShortcuts {
+                app: app.to_string(),
+                classes: classes.iter().map(|c| c.to_string()).collect(),
+                actions: actions
+                    .iter()
+                    .map(|(name, chord)| (name.to_string(), chord.to_string()))
+                    .collect(),
+            })
+            .collect();
+        ShortcutTable { apps }
+    }
+
+    /// Built-in shortcuts extended by the user's entries at `path`, if any
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut table = Self::builtin();
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let user: Vec<AppShortcuts> = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid shortcuts {}: {}", path.display(), e))?;
+            for app in &user {
+                for (name, chord) in &app.actions {
+                    parse_key_combo(chord).map_err(|e| format!("{} '{}': {}", app.app, name, e))?;
+                }
+            }
+            table.apps.splice(0..0, user);
+        }
+        Ok(table)
+    }
+
+    /// Every action available in a window of this class, by action name
+    pub fn for_class(&self, class: &str) -> BTreeMap<String, Shortcut> {
+        let mut sheet = BTreeMap::new();
+        for app in self.ordered().filter(|app| app.matches(class)) {
+            for (name, chord) in &app.actions {
+                sheet.entry(normalize(name)).or_insert_with(|| Shortcut {
+                    app: app.app.clone(),
+                    action: normalize(name),
+                    chord: chord.clone(),
+                });
+            }
+        }
+        sheet
+    }
+
+    /// The chord for an action in a window of this class
+    pub fn lookup(&self, class: &str, action: &str) -> Option<Shortcut> {
+        self.for_class(class).remove(&normalize(action))
+    }
+
+    /// Whether any application has a shortcut for this action
+    pub fn knows(&self, action: &str) -> bool {
+        let action = normalize(action);
+        self.apps
+            .iter()
+            .any(|app| app.actions.keys().any(|name| normalize(name) == action))
+    }
+
+    /// User then built-in entries, specific applications before generic ones
+    fn ordered(&self) -> impl Iterator<Item = &AppShortcuts> {
+        let specific = self.apps.iter().filter(|app| !app.classes.is_empty());
+        let generic = self.apps.iter().filter(|app| app.classes.is_empty());
+        specific.chain(generic)
+    }
+}
+
+/// "New_Tab", "new-tab" and " new  tab " all name the same action
+fn normalize(action: &str) -> String {
+    action
+        .to_lowercase()
+        .replace(['_', '-'], " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Perform a named action, e.g. "new tab", in the focused application
+pub fn send_app_action(action: &str) -> Result<Shortcut, String> {
+    let table = ShortcutTable::load(&paths::shortcuts_file())?;
+    let class = active_window_class()?;
+    let shortcut = table
+        .lookup(&class, action)
+        .ok_or_else(|| format!("No shortcut for '{}' in {}", action, class))?;
+    press_key_combo(&shortcut.chord)?;
+    Ok(shortcut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_by_class() {
+        let table = ShortcutTable::builtin();
+        let chord = |class: &str, action: &str| table.lookup(class, action).map(|s| s.chord);
+
+        assert_eq!(
+            chord("firefox.Firefox", "new_tab"),
+            Some("ctrl+t".to_string())
+        );
+        assert_eq!(
+            chord("kitty.kitty", "New Tab"),
+            Some("ctrl+shift+t".to_string())
+        );
+        assert_eq!(
+            chord("kitty.kitty", "copy"),
+            Some("ctrl+shift+c".to_string())
+        );
+        assert_eq!(chord("gedit", "copy"), Some("ctrl+c".to_string()));
+        assert_eq!(chord("gedit", "new tab"), None);
+    }
+}
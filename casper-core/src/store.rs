@@ -0,0 +1,173 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The daemon's store as `StoreGet` and `StoreSet` steps reach it
+pub trait SharedStore: Send + Sync {
+    fn get(&self, key: &str) -> Option<Value>;
+    fn set(&self, key: &str, value: Value) -> Result<(), String>;
+}
+
+static SHARED: Mutex<Option<Box<dyn SharedStore>>> = Mutex::new(None);
+
+/// Let playback steps read and write `store`
+pub fn set_shared(store: Box<dyn SharedStore>) {
+    *SHARED.lock().unwrap() = Some(store);
+}
+
+fn with_shared<R>(use_store: impl FnOnce(&dyn SharedStore) -> R) -> Result<R, String> {
+    match SHARED.lock().unwrap().as_deref() {
+        Some(store) => Ok(use_store(store)),
+        None => Err("The store is only available in the daemon".to_string()),
+    }
+}
+
+/// The value stored under `key`, as text: strings as they are, other
+/// values as JSON
+pub fn shared_text(key: &str) -> Result<String, String> {
+    match with_shared(|store| store.get(key))? {
+        Some(Value::String(text)) => Ok(text),
+        Some(value) => Ok(value.to_string()),
+        None => Err(format!("Nothing is stored under '{}'", key)),
+    }
+}
+
+/// Store `text` under `key`
+pub fn set_shared_text(key: &str, text: &str) -> Result<(), String> {
+    with_shared(|store| store.set(key, Value::String(text.to_string())))?
+}
+
+/// Persistent key/value store shared by clients and sequences
+///
+/// Values are arbitrary JSON and the whole store is rewritten on every
+/// change, so it is meant for small coordination flags ("meeting_mode=on"),
+/// not bulk data.
+#[derive(Debug)]
+pub struct KvStore {
+    path: PathBuf,
+    values: BTreeMap<String, Value>,
+}
+
+impl KvStore {
+    /// An empty store persisted at `path`
+    pub fn new(path: &Path) -> Self {
+        KvStore {
+            path: path.to_path_buf(),
+            values: BTreeMap::new(),
+        }
+    }
+
+    /// Load the store at `path`, starting empty if there is none
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut store = Self::new(path);
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            store.values = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid store {}: {}", path.display(), e))?;
+        }
+        Ok(store)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn all(&self) -> &BTreeMap<String, Value> {
+        &self.values
+    }
+
+    /// Set a key and persist the store; returns the previous value
+    pub fn set(&mut self, key: &str, value: Value) -> Result<Option<Value>, String> {
+        validate_key(key)?;
+        let previous = self.values.insert(key.to_string(), value);
+        self.save()?;
+        Ok(previous)
+    }
+
+    /// Remove a key and persist the store; returns the removed value
+    pub fn delete(&mut self, key: &str) -> Result<Option<Value>, String> {
+        let previous = self.values.remove(key);
+        if previous.is_some() {
+            self.save()?;
+        }
+        Ok(previous)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.values)
+            .map_err(|e| format!("Failed to serialize store: {}", e))?;
+        // Write to a temporary file first so a crash never leaves a truncated store
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json).map_err(|e| format!("Failed to write store: {}", e))?;
+        fs::rename(&tmp, &self.path).map_err(|e| format!("Failed to write store: {}", e))
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("Store key cannot be empty".to_string());
+    }
+    if key.len() > 256 {
+        return Err("Store key is longer than 256 bytes".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_values_persist() {
+        let path = std::env::temp_dir().join(format!("casper-store-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = KvStore::load(&path).unwrap();
+        assert_eq!(store.set("meeting_mode", json!("on")).unwrap(), None);
+        assert_eq!(
+            store.set("meeting_mode", json!("off")).unwrap(),
+            Some(json!("on"))
+        );
+        store.set("volume", json!(40)).unwrap();
+        assert!(store.set("", json!(1)).is_err());
+
+        let mut reloaded = KvStore::load(&path).unwrap();
+        assert_eq!(reloaded.get("meeting_mode"), Some(&json!("off")));
+        assert_eq!(reloaded.delete("volume").unwrap(), Some(json!(40)));
+        assert_eq!(reloaded.all().len(), 1);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    struct MemoryStore(Mutex<BTreeMap<String, Value>>);
+
+    impl SharedStore for MemoryStore {
+        fn get(&self, key: &str) -> Option<Value> {
+            self.0.lock().unwrap().get(key).cloned()
+        }
+
+        fn set(&self, key: &str, value: Value) -> Result<(), String> {
+            self.0.lock().unwrap().insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_shared_store_as_text() {
+        assert!(shared_text("meeting_mode").is_err());
+        let values = BTreeMap::from([("volume".to_string(), json!(40))]);
+        set_shared(Box::new(MemoryStore(Mutex::new(values))));
+
+        set_shared_text("meeting_mode", "on").unwrap();
+        assert_eq!(shared_text("meeting_mode").unwrap(), "on");
+        assert_eq!(shared_text("volume").unwrap(), "40");
+        assert!(shared_text("missing").is_err());
+    }
+}
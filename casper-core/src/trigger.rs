@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Limits on how often an event-driven rule fires, e.g.
+/// `{"debounce_ms": 500, "cooldown_ms": 10000}`
+///
+/// - `debounce_ms`: wait until events stop for this long, then fire once
+///   for the last one
+/// - `throttle_ms`: fire at most once per period, dropping the events in between
+/// - `cooldown_ms`: ignore events for this long after a firing finished
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggerLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debounce_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_ms: Option<u64>,
+}
+
+impl TriggerLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == TriggerLimits::default()
+    }
+}
+
+/// Applies `TriggerLimits` to the events of one rule
+///
+/// Events carry a payload (the window, file or message that triggered the
+/// rule); a debounced event keeps the latest payload until it is due.
+#[derive(Debug)]
+pub struct TriggerGate<T> {
+    limits: TriggerLimits,
+    last_fired: Option<Instant>,
+    last_finished: Option<Instant>,
+    pending: Option<(Instant, T)>,
+}
+
+impl<T> TriggerGate<T> {
+    pub fn new(limits: TriggerLimits) -> Self {
+        TriggerGate {
+            limits,
+            last_fired: None,
+            last_finished: None,
+            pending: None,
+        }
+    }
+
+    pub fn limits(&self) -> TriggerLimits {
+        self.limits
+    }
+
+    /// Offer an event; returns the payload if the rule should fire now
+    pub fn offer(&mut self, now: Instant, payload: T) -> Option<T> {
+        match self.limits.debounce_ms {
+            Some(debounce) => {
+                self.pending = Some((now + Duration::from_millis(debounce), payload));
+                None
+            }
+            None if self.allows(now) => {
+                self.last_fired = Some(now);
+                Some(payload)
+            }
+            None => None,
+        }
+    }
+
+    /// The debounced payload, once events have been quiet long enough
+    pub fn due(&mut self, now: Instant) -> Option<T> {
+        match &self.pending {
+            Some((deadline, _)) if *deadline <= now => {}
+            _ => return None,
+        }
+        let (_, payload) = self.pending.take()?;
+        if !self.allows(now) {
+            return None;
+        }
+        self.last_fired = Some(now);
+        Some(payload)
+    }
+
+    /// Record that a firing finished, starting the cooldown
+    pub fn finished(&mut self, now: Instant) {
+        self.last_finished = Some(now);
+    }
+
+    fn allows(&self, now: Instant) -> bool {
+        let elapsed = |since: Option<Instant>, limit: Option<u64>| match (since, limit) {
+            (Some(since), Some(limit)) => {
+                now.saturating_duration_since(since) >= Duration::from_millis(limit)
+            }
+            _ => true,
+        };
+        elapsed(self.last_fired, self.limits.throttle_ms)
+            && elapsed(self.last_finished, self.limits.cooldown_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_throttle_and_cooldown() {
+        let start = Instant::now();
+        let mut gate = TriggerGate::new(TriggerLimits {
+            throttle_ms: Some(100),
+            ..Default::default()
+        });
+        assert_eq!(gate.offer(start, 1), Some(1));
+        assert_eq!(gate.offer(at(start, 50), 2), None);
+        assert_eq!(gate.offer(at(start, 100), 3), Some(3));
+
+        let mut gate = TriggerGate::new(TriggerLimits {
+            cooldown_ms: Some(100),
+            ..Default::default()
+        });
+        assert_eq!(gate.offer(start, 1), Some(1));
+        gate.finished(at(start, 500));
+        assert_eq!(gate.offer(at(start, 550), 2), None);
+        assert_eq!(gate.offer(at(start, 600), 3), Some(3));
+    }
+
+    #[test]
+    fn test_debounce_fires_once_for_a_burst() {
+        let start = Instant::now();
+        let mut gate = TriggerGate::new(TriggerLimits {
+            debounce_ms: Some(100),
+            ..Default::default()
+        });
+        for (ms, payload) in [(0, 1), (30, 2), (60, 3)] {
+            assert_eq!(gate.offer(at(start, ms), payload), None);
+        }
+        assert_eq!(gate.due(at(start, 150)), None);
+        assert_eq!(gate.due(at(start, 160)), Some(3));
+        assert_eq!(gate.due(at(start, 500)), None);
+    }
+}
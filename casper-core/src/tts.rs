@@ -1,9 +1,51 @@
+use crate::commands::CommandDeadline;
+use crate::features::missing;
 use std::process::Command;
 
 pub fn speak(text: &str) -> Result<(), String> {
+    if !cfg!(feature = "tts") {
+        return Err(missing("tts"));
+    }
     Command::new("espeak-ng")
         .arg(text)
         .spawn()
         .map_err(|e| e.to_string())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Speak text and wait until it has been spoken
+pub fn speak_blocking(text: &str) -> Result<(), String> {
+    if !cfg!(feature = "tts") {
+        return Err(missing("tts"));
+    }
+    let output = Command::new("espeak-ng")
+        .arg(text)
+        .output_bounded()
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("espeak-ng exited with {}", output.status))
+    }
+}
+
+/// Split text into sentences so long speech can report progress
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        current.push(c);
+        if matches!(c, '.' | '!' | '?' | '\n') {
+            let sentence = current.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+            current.clear();
+        }
+    }
+    let rest = current.trim();
+    if !rest.is_empty() {
+        sentences.push(rest.to_string());
+    }
+    sentences
+}
@@ -0,0 +1,179 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many requests are counted between writes to disk
+const SAVE_EVERY: u64 = 10;
+
+/// Local, opt-in counts of which features are used
+///
+/// Only request type names and success/failure are counted; no request
+/// content, arguments or timestamps of individual requests are kept, and
+/// nothing is ever sent anywhere.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageStats {
+    /// RFC 3339 time counting started (or was last reset)
+    pub since: String,
+    pub requests: BTreeMap<String, u64>,
+    pub errors: BTreeMap<String, u64>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    unsaved: u64,
+}
+
+impl UsageStats {
+    /// Load the counts stored at `path`, starting fresh if there are none
+    pub fn load(path: &Path) -> Self {
+        let mut stats = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<UsageStats>(&content).ok())
+            .unwrap_or_else(Self::empty);
+        stats.path = path.to_path_buf();
+        stats
+    }
+
+    fn empty() -> Self {
+        UsageStats {
+            since: chrono::Utc::now().to_rfc3339(),
+            requests: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            path: PathBuf::new(),
+            unsaved: 0,
+        }
+    }
+
+    /// Count one request of a known type
+    pub fn record(&mut self, feature: &str, success: bool) {
+        *self.requests.entry(feature.to_string()).or_insert(0) += 1;
+        if !success {
+            *self.errors.entry(feature.to_string()).or_insert(0) += 1;
+        }
+
+        self.unsaved += 1;
+        if self.unsaved >= SAVE_EVERY {
+            let _ = self.save();
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize usage: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write usage: {}", e))?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// Forget all counts
+    pub fn reset(&mut self) -> Result<(), String> {
+        let path = std::mem::take(&mut self.path);
+        *self = Self::empty();
+        self.path = path;
+        self.save()
+    }
+
+    /// Build a report of the counts
+    ///
+    /// With `epsilon`, every count gets Laplace noise calibrated for
+    /// epsilon-differential privacy (one request changes one count by one),
+    /// so the report can be shared without revealing exact usage.
+    pub fn report(&self, epsilon: Option<f64>) -> Result<UsageReport, String> {
+        let noise = match epsilon {
+            Some(e) if !(e > 0.0 && e.is_finite()) => {
+                return Err(format!("epsilon must be a positive number, got {}", e));
+            }
+            Some(e) => Some(1.0 / e),
+            None => None,
+        };
+        let mut rng = rand::thread_rng();
+        let mut perturb = |counts: &BTreeMap<String, u64>| -> BTreeMap<String, u64> {
+            counts
+                .iter()
+                .map(|(feature, count)| {
+                    let count = match noise {
+                        Some(scale) => {
+                            (*count as f64 + laplace(&mut rng, scale)).round().max(0.0) as u64
+                        }
+                        None => *count,
+                    };
+                    (feature.clone(), count)
+                })
+                .collect()
+        };
+
+        Ok(UsageReport {
+            since: self.since.clone(),
+            generated_at: chrono::Utc::now().to_rfc3339(),
+            epsilon,
+            requests: perturb(&self.requests),
+            errors: perturb(&self.errors),
+        })
+    }
+}
+
+/// Exportable summary of feature usage
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub since: String,
+    pub generated_at: String,
+    /// Privacy budget used for the noise, if any
+    pub epsilon: Option<f64>,
+    pub requests: BTreeMap<String, u64>,
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl UsageReport {
+    pub fn export(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize report: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Sample Laplace(0, scale) noise
+fn laplace<R: Rng>(rng: &mut R, scale: f64) -> f64 {
+    let u: f64 = rng.gen_range(-0.5..0.5);
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> UsageStats {
+        let mut stats = UsageStats::empty();
+        stats.path = std::env::temp_dir().join(format!("casper-usage-{}.json", std::process::id()));
+        stats
+    }
+
+    #[test]
+    fn test_exact_report() {
+        let mut stats = stats();
+        stats.record("move_mouse", true);
+        stats.record("move_mouse", false);
+        stats.record("ping", true);
+
+        let report = stats.report(None).unwrap();
+        assert_eq!(report.requests["move_mouse"], 2);
+        assert_eq!(report.requests["ping"], 1);
+        assert_eq!(report.errors["move_mouse"], 1);
+        assert!(!report.errors.contains_key("ping"));
+    }
+
+    #[test]
+    fn test_noisy_report_keeps_features() {
+        let mut stats = stats();
+        for _ in 0..5 {
+            stats.record("type_text", true);
+        }
+        let report = stats.report(Some(0.5)).unwrap();
+        assert_eq!(report.epsilon, Some(0.5));
+        assert!(report.requests.contains_key("type_text"));
+        assert!(stats.report(Some(0.0)).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use cipher::{keyring_key, open_with, passphrase_key, salt, seal_with};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Start of a file (or journal line) encrypted with the library key
+const PREFIX: &str = "casper-encrypted:v1:";
+
+/// Text sealed into the key check file, to tell a wrong key at startup
+const KEY_CHECK: &str = "casper library key";
+
+/// Key encrypting the library, once it is unlocked
+static KEY: RwLock<Option<[u8; 32]>> = RwLock::new(None);
+
+/// Where the key encrypting the library comes from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// A random key kept in the desktop keyring (through `secret-tool`)
+    #[default]
+    Keyring,
+    /// A key derived from the `CASPER_PASSPHRASE` environment variable
+    Passphrase,
+}
+
+/// Fetch or create the library key and use it for every file written from
+/// now on
+///
+/// `salt_file` keeps the salt of a passphrase-derived key; it is created on
+/// first use, and a check of the key is kept next to it. A key that does not
+/// open the check (or, before there is one, a file in `library_dir`) is
+/// refused, and a new keyring key is only created for a library with nothing
+/// encrypted in it yet.
+pub fn unlock(source: KeySource, salt_file: &Path, library_dir: &Path) -> Result<(), String> {
+    let key = library_key(source, salt_file, library_dir)?;
+    *KEY.write().unwrap() = Some(key);
+    Ok(())
+}
+
+fn library_key(
+    source: KeySource,
+    salt_file: &Path,
+    library_dir: &Path,
+) -> Result<[u8; 32], String> {
+    let check_file = salt_file.with_extension("check");
+    let check = std::fs::read_to_string(&check_file).ok();
+    let sealed_file = match check {
+        Some(_) => None,
+        None => find_sealed(library_dir),
+    };
+    let key = match source {
+        KeySource::Keyring => keyring_key(check.is_none() && sealed_file.is_none())?,
+        KeySource::Passphrase => {
+            let passphrase = std::env::var("CASPER_PASSPHRASE")
+                .map_err(|_| "CASPER_PASSPHRASE is not set".to_string())?;
+            if passphrase.is_empty() {
+                return Err("CASPER_PASSPHRASE is empty".to_string());
+            }
+            passphrase_key(&passphrase, &salt(salt_file)?)?
+        }
+    };
+
+    let wrong_key = || {
+        format!(
+            "The library key does not open the encrypted library{}",
+            match source {
+                KeySource::Passphrase => " (is CASPER_PASSPHRASE right?)",
+                KeySource::Keyring => "",
+            }
+        )
+    };
+    match (&check, &sealed_file) {
+        (Some(check), _) => {
+            if open_with(&key, check).ok().as_deref() != Some(KEY_CHECK) {
+                return Err(wrong_key());
+            }
+        }
+        (None, sealed_file) => {
+            if let Some(text) = sealed_file
+                && open_with(&key, text).is_err()
+            {
+                return Err(wrong_key());
+            }
+            std::fs::write(&check_file, seal_with(&key, KEY_CHECK)?)
+                .map_err(|e| format!("Failed to write {}: {}", check_file.display(), e))?;
+        }
+    }
+    Ok(key)
+}
+
+/// The contents of some encrypted file under `dir`, if there is one
+fn find_sealed(dir: &Path) -> Option<String> {
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        let found = if path.is_dir() {
+            find_sealed(&path)
+        } else {
+            std::fs::read_to_string(&path)
+                .ok()
+                .filter(|text| is_sealed(text))
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// Whether files are being encrypted
+pub fn is_unlocked() -> bool {
+    KEY.read().unwrap().is_some()
+}
+
+/// Whether text was written by `seal`
+pub fn is_sealed(text: &str) -> bool {
+    text.starts_with(PREFIX)
+}
+
+/// Encrypt text with the library key, or return it as is while the library
+/// is not encrypted
+pub fn seal(text: &str) -> Result<String, String> {
+    match *KEY.read().unwrap() {
+        Some(key) => seal_with(&key, text),
+        None => Ok(text.to_string()),
+    }
+}
+
+/// Decrypt text written by `seal`; plain text is returned as is
+pub fn open(text: &str) -> Result<String, String> {
+    if !is_sealed(text) {
+        return Ok(text.to_string());
+    }
+    match *KEY.read().unwrap() {
+        Some(key) => open_with(&key, text),
+        None => Err("The file is encrypted and the library key is not unlocked".to_string()),
+    }
+}
+
+/// Encryption with ring
+#[cfg(feature = "encryption")]
+mod cipher {
+    use super::PREFIX;
+    use base64::{Engine as _, engine::general_purpose};
+    use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+    use ring::pbkdf2::{PBKDF2_HMAC_SHA256, derive};
+    use ring::rand::{SecureRandom, SystemRandom};
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    fn random_bytes<const N: usize>() -> Result<[u8; N], String> {
+        let mut bytes = [0; N];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| "No random numbers available".to_string())?;
+        Ok(bytes)
+    }
+
+    fn aead_key(key: &[u8; 32]) -> Result<LessSafeKey, String> {
+        let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| "Invalid key".to_string())?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    /// AES-256-GCM with a random nonce, as `PREFIX` + base64(nonce + ciphertext)
+    pub fn seal_with(key: &[u8; 32], text: &str) -> Result<String, String> {
+        let nonce = random_bytes::<NONCE_LEN>()?;
+        let mut data = text.as_bytes().to_vec();
+        aead_key(key)?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| "Encryption failed".to_string())?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&data);
+        Ok(format!(
+            "{}{}",
+            PREFIX,
+            general_purpose::STANDARD.encode(sealed)
+        ))
+    }
+
+    pub fn open_with(key: &[u8; 32], text: &str) -> Result<String, String> {
+        let data = general_purpose::STANDARD
+            .decode(text[PREFIX.len()..].trim())
+            .map_err(|_| "The encrypted file is damaged".to_string())?;
+        if data.len() < NONCE_LEN {
+            return Err("The encrypted file is damaged".to_string());
+        }
+        let (nonce, data) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "Invalid nonce")?;
+        let mut data = data.to_vec();
+        let plain = aead_key(key)?
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| "Cannot decrypt: wrong key or damaged file".to_string())?;
+        String::from_utf8(plain.to_vec()).map_err(|e| e.to_string())
+    }
+
+    pub fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+        const ITERATIONS: std::num::NonZeroU32 = std::num::NonZeroU32::new(600_000).unwrap();
+
+        let mut key = [0; 32];
+        derive(
+            PBKDF2_HMAC_SHA256,
+            ITERATIONS,
+            salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        Ok(key)
+    }
+
+    /// The salt in `path`, creating it the first time
+    pub fn salt(path: &Path) -> Result<Vec<u8>, String> {
+        if let Ok(salt) = std::fs::read(path) {
+            return Ok(salt);
+        }
+        let salt = random_bytes::<16>()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        std::fs::write(path, salt)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(salt.to_vec())
+    }
+
+    /// The library key in the keyring, stored there when it is not found
+    /// and `create` allows it
+    ///
+    /// Only a lookup that finds nothing counts as "not found": a locked
+    /// keyring or a D-Bus error must not lead to a new key replacing the one
+    /// the library was encrypted with.
+    pub fn keyring_key(create: bool) -> Result<[u8; 32], String> {
+        const ATTRIBUTES: [&str; 4] = ["service", "casper", "key", "library"];
+        let lookup = Command::new("secret-tool")
+            .arg("lookup")
+            .args(ATTRIBUTES)
+            .output()
+            .map_err(|e| format!("Failed to run secret-tool (is libsecret installed?): {}", e))?;
+        let stored = String::from_utf8_lossy(&lookup.stdout).trim().to_string();
+        let error = String::from_utf8_lossy(&lookup.stderr).trim().to_string();
+        if lookup.status.success() && !stored.is_empty() {
+            let key = general_purpose::STANDARD
+                .decode(&stored)
+                .map_err(|_| "The library key in the keyring is damaged".to_string())?;
+            return key
+                .try_into()
+                .map_err(|_| "The library key in the keyring is damaged".to_string());
+        }
+        // secret-tool exits with 1 and prints nothing when there is no such secret
+        if lookup.status.code() != Some(1) || !error.is_empty() {
+            return Err(format!(
+                "Failed to read the library key from the keyring: {}",
+                if error.is_empty() {
+                    "no key returned"
+                } else {
+                    &error
+                }
+            ));
+        }
+        if !create {
+            return Err(
+                "The library key is missing from the keyring, but the library is encrypted \
+                 with it; restore the keyring instead of starting with a new key"
+                    .to_string(),
+            );
+        }
+
+        let key = random_bytes::<32>()?;
+        let mut store = Command::new("secret-tool")
+            .args(["store", "--label=Casper library key"])
+            .args(ATTRIBUTES)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run secret-tool: {}", e))?;
+        if let Some(mut stdin) = store.stdin.take() {
+            stdin
+                .write_all(general_purpose::STANDARD.encode(key).as_bytes())
+                .map_err(|e| format!("Failed to store the library key: {}", e))?;
+        }
+        let status = store.wait().map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err("secret-tool could not store the library key".to_string());
+        }
+        Ok(key)
+    }
+}
+
+/// Without ring nothing can be encrypted; plain files still work
+#[cfg(not(feature = "encryption"))]
+mod cipher {
+    use crate::features::missing;
+    use std::path::Path;
+
+    pub fn seal_with(_key: &[u8; 32], _text: &str) -> Result<String, String> {
+        Err(missing("encryption"))
+    }
+
+    pub fn open_with(_key: &[u8; 32], _text: &str) -> Result<String, String> {
+        Err(missing("encryption"))
+    }
+
+    pub fn passphrase_key(_passphrase: &str, _salt: &[u8]) -> Result<[u8; 32], String> {
+        Err(missing("encryption"))
+    }
+
+    pub fn salt(_path: &Path) -> Result<Vec<u8>, String> {
+        Err(missing("encryption"))
+    }
+
+    pub fn keyring_key(_create: bool) -> Result<[u8; 32], String> {
+        Err(missing("encryption"))
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::cipher::{open_with, passphrase_key, seal_with};
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = passphrase_key("correct horse", b"0123456789abcdef").unwrap();
+        let sealed = seal_with(&key, "{\"text\":\"hunter2\"}").unwrap();
+        assert!(is_sealed(&sealed) && !sealed.contains("hunter2"));
+        assert_ne!(sealed, seal_with(&key, "{\"text\":\"hunter2\"}").unwrap());
+        assert_eq!(open_with(&key, &sealed).unwrap(), "{\"text\":\"hunter2\"}");
+
+        let other = passphrase_key("wrong horse", b"0123456789abcdef").unwrap();
+        assert!(open_with(&other, &sealed).is_err());
+        assert_eq!(open("plain").unwrap(), "plain");
+    }
+
+    #[test]
+    fn test_key_check() {
+        let dir = std::env::temp_dir().join(format!("casper-vault-{}", std::process::id()));
+        let library = dir.join("actions");
+        std::fs::create_dir_all(&library).unwrap();
+        let salt_file = dir.join("library.salt");
+        let key = passphrase_key("correct horse", &salt(&salt_file).unwrap()).unwrap();
+        std::fs::write(library.join("a.json"), seal_with(&key, "{}").unwrap()).unwrap();
+
+        // SAFETY: no other test reads CASPER_PASSPHRASE
+        unsafe { std::env::set_var("CASPER_PASSPHRASE", "wrong horse") };
+        let refused = library_key(KeySource::Passphrase, &salt_file, &library);
+        assert!(refused.unwrap_err().contains("CASPER_PASSPHRASE"));
+        assert!(!salt_file.with_extension("check").exists());
+
+        unsafe { std::env::set_var("CASPER_PASSPHRASE", "correct horse") };
+        library_key(KeySource::Passphrase, &salt_file, &library).unwrap();
+        assert!(salt_file.with_extension("check").exists());
+        unsafe { std::env::set_var("CASPER_PASSPHRASE", "wrong horse") };
+        assert!(library_key(KeySource::Passphrase, &salt_file, &library).is_err());
+
+        unsafe { std::env::remove_var("CASPER_PASSPHRASE") };
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
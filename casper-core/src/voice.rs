@@ -1,4 +1,7 @@
 pub fn recognize_voice() -> Result<String, String> {
+    if !cfg!(feature = "voice") {
+        return Err(crate::features::missing("voice"));
+    }
     // Will use vosk-rust later, later...
     Err("Voice under contruction".to_string())
-}
\ No newline at end of file
+}
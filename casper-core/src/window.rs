@@ -1,26 +1,100 @@
+use crate::commands::CommandDeadline;
+use crate::displays::{DisplayRef, display_at, find_display, list_displays};
+use crate::launcher::resolve_application;
+use crate::window_backends::{Hyprland, KWin, Sway, Wmctrl};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
 use std::process::Command;
+use std::time::{Duration, Instant};
 
-/// Detect which window manager/compositor is running
-fn detect_environment() -> WindowEnvironment {
-    // Check for Hyprland
-    if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
-        return WindowEnvironment::Hyprland;
+/// How often `wait_for_window` looks for the window
+const WINDOW_POLL: Duration = Duration::from_millis(100);
+
+/// How long `open_or_focus_application` waits for a launched application's window
+pub const LAUNCH_TIMEOUT_MS: u64 = 10_000;
+
+/// A way of listing and managing windows
+///
+/// Window IDs are whatever the backend uses (X11 window IDs, Hyprland client
+/// addresses, sway container IDs, KWin UUIDs), as `list_windows` reports them.
+pub trait WindowBackend: Send + Sync {
+    /// Name reported in `status`
+    fn name(&self) -> &'static str;
+    fn list_windows(&self) -> Result<Vec<WindowInfo>, String>;
+    fn focus(&self, window_id: &str) -> Result<(), String>;
+    fn active_window_id(&self) -> Result<String, String>;
+    /// Position and size: `(x, y, width, height)`
+    fn geometry(&self, window_id: &str) -> Result<(i32, i32, i32, i32), String>;
+    fn move_resize(
+        &self,
+        window_id: &str,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String>;
+    fn maximize(&self, window_id: &str) -> Result<(), String>;
+    fn minimize(&self, window_id: &str) -> Result<(), String>;
+    fn close(&self, window_id: &str) -> Result<(), String>;
+    fn move_to_workspace(&self, window_id: &str, workspace: i32) -> Result<(), String>;
+    fn list_workspaces(&self) -> Result<Vec<Workspace>, String>;
+    fn switch_workspace(&self, workspace: i32) -> Result<(), String>;
+
+    /// Put a window above all others without focusing it
+    fn raise(&self, window_id: &str) -> Result<(), String> {
+        let _ = window_id;
+        Err(format!("Windows cannot be raised through {}", self.name()))
     }
 
-    // Check for Wayland (generic)
-    if std::env::var("WAYLAND_DISPLAY").is_ok() {
-        return WindowEnvironment::Wayland;
+    /// Put a window below all others
+    fn lower(&self, window_id: &str) -> Result<(), String> {
+        let _ = window_id;
+        Err(format!("Windows cannot be lowered through {}", self.name()))
     }
 
-    // Default to X11
-    WindowEnvironment::X11
+    /// Turn a state of a window on or off
+    fn set_state(&self, window_id: &str, state: WindowState, on: bool) -> Result<(), String> {
+        let _ = (window_id, on);
+        Err(format!(
+            "Windows cannot be made {} through {}",
+            state.as_str(),
+            self.name()
+        ))
+    }
+
+    /// Focus the first window whose title or class contains `pattern`
+    fn focus_matching(&self, pattern: &str) -> Result<(), String> {
+        let pattern_lower = pattern.to_lowercase();
+        let window = self
+            .list_windows()?
+            .into_iter()
+            .find(|w| {
+                w.class.to_lowercase().contains(&pattern_lower)
+                    || w.title.to_lowercase().contains(&pattern_lower)
+            })
+            .ok_or_else(|| format!("Failed to focus window: no window matches '{}'", pattern))?;
+        self.focus(&window.id)
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum WindowEnvironment {
-    Hyprland,
-    Wayland,
-    X11,
+/// The backend for the running compositor or window manager
+///
+/// Hyprland and sway are asked through their own IPC and KWin on Plasma
+/// Wayland through scripts; everything else (X11 and other Wayland
+/// compositors, through XWayland) goes through wmctrl.
+pub fn window_backend() -> Box<dyn WindowBackend> {
+    if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+        return Box::new(Hyprland);
+    }
+    if std::env::var("SWAYSOCK").is_ok() {
+        return Box::new(Sway);
+    }
+    if std::env::var("WAYLAND_DISPLAY").is_ok()
+        && std::env::var("XDG_CURRENT_DESKTOP").is_ok_and(|desktop| desktop.contains("KDE"))
+    {
+        return Box::new(KWin);
+    }
+    Box::new(Wmctrl)
 }
 
 /// Check if a process is running by name
@@ -28,7 +102,7 @@ pub fn is_process_running(process_name: &str) -> Result<bool, String> {
     let output = Command::new("pgrep")
         .arg("-x")
         .arg(process_name)
-        .output()
+        .output_bounded()
         .map_err(|e| format!("Failed to execute pgrep: {}", e))?;
 
     Ok(output.status.success())
@@ -39,7 +113,7 @@ pub fn find_processes(pattern: &str) -> Result<Vec<String>, String> {
     let output = Command::new("pgrep")
         .arg("-f")
         .arg(pattern)
-        .output()
+        .output_bounded()
         .map_err(|e| format!("Failed to execute pgrep: {}", e))?;
 
     if output.status.success() {
@@ -56,8 +130,25 @@ pub fn find_processes(pattern: &str) -> Result<Vec<String>, String> {
 }
 
 /// Launch an application
+///
+/// Names are looked up in the installed desktop entries first ("Firefox",
+/// "Web Browser", "org.gnome.Calculator", "firefox.desktop"), so they work whatever the binary
+/// is called or however it is packaged; anything else is run as a program.
 pub fn launch_application(app_name: &str) -> Result<(), String> {
+    launch_application_with(app_name, &[])
+}
+
+/// Launch an application opening `files` (paths or URLs), or run a program
+/// with them as arguments
+pub fn launch_application_with(app_name: &str, files: &[String]) -> Result<(), String> {
+    if (!app_name.contains('/') || app_name.ends_with(".desktop"))
+        && let Some(entry) = resolve_application(app_name)
+    {
+        return entry.launch_with(files);
+    }
+
     Command::new(app_name)
+        .args(files)
         .spawn()
         .map_err(|e| format!("Failed to launch {}: {}", app_name, e))?;
     Ok(())
@@ -65,95 +156,51 @@ pub fn launch_application(app_name: &str) -> Result<(), String> {
 
 /// Focus a window by application name
 pub fn focus_window(app_name: &str) -> Result<(), String> {
-    match detect_environment() {
-        WindowEnvironment::Hyprland => {
-            // Use hyprctl to focus window
-            let output = Command::new("hyprctl")
-                .args(&["dispatch", "focuswindow", &format!("title:{}", app_name)])
-                .output()
-                .map_err(|e| format!("Failed to execute hyprctl: {}", e))?;
-
-            if output.status.success() {
-                Ok(())
-            } else {
-                Err(format!(
-                    "Failed to focus window: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ))
-            }
-        }
-        WindowEnvironment::Wayland | WindowEnvironment::X11 => {
-            // Use wmctrl for X11/generic Wayland
-            let output = Command::new("wmctrl")
-                .arg("-a")
-                .arg(app_name)
-                .output()
-                .map_err(|e| format!("Failed to execute wmctrl: {}", e))?;
-
-            if output.status.success() {
-                Ok(())
-            } else {
-                Err(format!(
-                    "Failed to focus window: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ))
-            }
-        }
-    }
+    window_backend().focus_matching(app_name)
 }
 
 /// Get list of all windows with their properties
 pub fn list_windows() -> Result<Vec<WindowInfo>, String> {
-    match detect_environment() {
-        WindowEnvironment::Hyprland => {
-            // Use hyprctl to list windows
-            let output = Command::new("hyprctl")
-                .args(&["clients", "-j"])
-                .output()
-                .map_err(|e| format!("Failed to execute hyprctl: {}", e))?;
-
-            if !output.status.success() {
-                return Err(format!(
-                    "hyprctl failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ));
-            }
-
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            parse_hyprctl_clients(&stdout)
-        }
-        WindowEnvironment::Wayland | WindowEnvironment::X11 => {
-            let output = Command::new("wmctrl")
-                .arg("-l")
-                .arg("-p")
-                .arg("-x")
-                .output()
-                .map_err(|e| format!("Failed to execute wmctrl: {}", e))?;
-
-            if !output.status.success() {
-                return Err(format!(
-                    "wmctrl failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ));
-            }
+    window_backend().list_windows()
+}
 
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let mut windows = Vec::new();
+/// The focused window, with where it is on the screen
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveWindow {
+    #[serde(flatten)]
+    pub window: WindowInfo,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
 
-            for line in stdout.lines() {
-                if let Some(window_info) = parse_wmctrl_line(line) {
-                    windows.push(window_info);
-                }
-            }
+/// The focused window according to GNOME Shell, with everything
+/// `ActiveWindow` holds
+const GNOME_ACTIVE_WINDOW: &str = "(w => { if (!w) return null; const r = w.get_frame_rect(); \
+    return { id: String(w.get_id()), pid: w.get_pid(), \
+    desktop: w.is_on_all_workspaces() ? -1 : w.get_workspace().index(), \
+    class: w.get_wm_class() || '', title: w.get_title() || '', \
+    x: r.x, y: r.y, width: r.width, height: r.height }; })(global.display.focus_window)";
 
-            Ok(windows)
-        }
+/// Get active window information, from the window backend or else gdbus
+/// (GNOME on Wayland) or xdotool
+pub fn get_active_window() -> Result<ActiveWindow, String> {
+    if let Ok(id) = active_window_id()
+        && let Ok(windows) = list_windows()
+        && let Some(window) = windows.into_iter().find(|w| w.id == id)
+        && let Ok((x, y, width, height)) = window_geometry(&id)
+    {
+        return Ok(ActiveWindow {
+            window,
+            x,
+            y,
+            width,
+            height,
+        });
     }
-}
 
-/// Get active window information (using xdotool or gdbus for Wayland)
-pub fn get_active_window() -> Result<WindowInfo, String> {
-    // Try gdbus first for Wayland/Gnome
+    // Try gdbus for Wayland/Gnome
     if let Ok(window) = get_active_window_gdbus() {
         return Ok(window);
     }
@@ -162,7 +209,9 @@ pub fn get_active_window() -> Result<WindowInfo, String> {
     get_active_window_xdotool()
 }
 
-fn get_active_window_gdbus() -> Result<WindowInfo, String> {
+/// The focused window through GNOME Shell's Eval, which GNOME 41 and later
+/// only answer in unsafe mode
+fn get_active_window_gdbus() -> Result<ActiveWindow, String> {
     let output = Command::new("gdbus")
         .args(&[
             "call",
@@ -173,116 +222,111 @@ fn get_active_window_gdbus() -> Result<WindowInfo, String> {
             "/org/gnome/Shell",
             "--method",
             "org.gnome.Shell.Eval",
-            "global.display.focus_window.get_wm_class()",
+            GNOME_ACTIVE_WINDOW,
         ])
-        .output()
+        .output_bounded()
         .map_err(|e| format!("Failed to execute gdbus: {}", e))?;
 
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        // Parse the output to extract window class
-        // Format is usually: (true, '"ClassName"')
-        if let Some(class) = extract_window_class(&stdout) {
-            return Ok(WindowInfo {
-                id: String::from("0"),
-                pid: 0,
-                desktop: 0,
-                class: class.clone(),
-                title: class,
-                machine: String::from("localhost"),
-            });
-        }
+    if output.status.success()
+        && let Some(window) = parse_gnome_window(&String::from_utf8_lossy(&output.stdout))
+    {
+        return Ok(window);
     }
 
     Err("Failed to get active window via gdbus".to_string())
 }
 
-fn get_active_window_xdotool() -> Result<WindowInfo, String> {
-    let output = Command::new("xdotool")
-        .args(&["getactivewindow", "getwindowname"])
-        .output()
-        .map_err(|e| format!("Failed to execute xdotool: {}", e))?;
-
-    if output.status.success() {
-        let title = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        Ok(WindowInfo {
-            id: String::from("0"),
-            pid: 0,
-            desktop: 0,
-            class: String::new(),
-            title,
-            machine: String::from("localhost"),
-        })
-    } else {
-        Err("Failed to get active window via xdotool".to_string())
+/// The window in an Eval reply such as `(true, '{"id":"12",...}')`, whose
+/// JSON is a GVariant string with `\` and `'` escaped
+fn parse_gnome_window(gdbus_output: &str) -> Option<ActiveWindow> {
+    let quoted = &gdbus_output[gdbus_output.find('\'')? + 1..gdbus_output.rfind('\'')?];
+    let mut json = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            json.push(chars.next()?);
+        } else {
+            json.push(c);
+        }
     }
+    let value: serde_json::Value = serde_json::from_str(&json).ok()?;
+    let number = |name: &str| value[name].as_i64().map(|n| n as i32);
+    Some(ActiveWindow {
+        window: WindowInfo {
+            id: value["id"].as_str()?.to_string(),
+            pid: value["pid"].as_u64().unwrap_or(0) as u32,
+            desktop: number("desktop").unwrap_or(0),
+            class: value["class"].as_str().unwrap_or_default().to_string(),
+            title: value["title"].as_str().unwrap_or_default().to_string(),
+            machine: String::from("localhost"),
+        },
+        x: number("x")?,
+        y: number("y")?,
+        width: number("width")?,
+        height: number("height")?,
+    })
 }
 
-fn extract_window_class(gdbus_output: &str) -> Option<String> {
-    // Extract class from gdbus output: (true, '"ClassName"')
-    if let Some(start) = gdbus_output.find('"') {
-        if let Some(end) = gdbus_output[start + 1..].find('"') {
-            return Some(gdbus_output[start + 1..start + 1 + end].to_string());
+/// The focused window through xdotool alone, for X11 without wmctrl
+fn get_active_window_xdotool() -> Result<ActiveWindow, String> {
+    let xdotool = |args: &[&str]| {
+        let output = Command::new("xdotool")
+            .arg("getactivewindow")
+            .args(args)
+            .output_bounded()
+            .map_err(|e| format!("Failed to execute xdotool: {}", e))?;
+        if !output.status.success() {
+            return Err("Failed to get active window via xdotool".to_string());
         }
-    }
-    None
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let id: u64 = xdotool(&[])?
+        .parse()
+        .map_err(|_| "No active window".to_string())?;
+    // X=10, Y=20, WIDTH=800, HEIGHT=600 lines
+    let geometry = xdotool(&["getwindowgeometry", "--shell"])?;
+    let field = |name: &str| {
+        geometry
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('=')?.parse().ok())
+            .ok_or_else(|| format!("xdotool did not report the window {}", name))
+    };
+    Ok(ActiveWindow {
+        window: WindowInfo {
+            // wmctrl lists IDs as zero-padded hex
+            id: format!("0x{:08x}", id),
+            pid: xdotool(&["getwindowpid"])
+                .ok()
+                .and_then(|pid| pid.parse().ok())
+                .unwrap_or(0),
+            desktop: xdotool(&["get_desktop_for_window"])
+                .ok()
+                .and_then(|desktop| desktop.parse().ok())
+                .unwrap_or(0),
+            class: xdotool(&["getwindowclassname"]).unwrap_or_default(),
+            title: xdotool(&["getwindowname"])?,
+            machine: String::from("localhost"),
+        },
+        x: field("X")?,
+        y: field("Y")?,
+        width: field("WIDTH")?,
+        height: field("HEIGHT")?,
+    })
 }
 
 /// Maximize a window
 pub fn maximize_window(window_id: &str) -> Result<(), String> {
-    let output = Command::new("wmctrl")
-        .args(&[
-            "-i",
-            "-r",
-            window_id,
-            "-b",
-            "add,maximized_vert,maximized_horz",
-        ])
-        .output()
-        .map_err(|e| format!("Failed to execute wmctrl: {}", e))?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(format!(
-            "Failed to maximize window: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ))
-    }
+    window_backend().maximize(window_id)
 }
 
 /// Minimize a window
 pub fn minimize_window(window_id: &str) -> Result<(), String> {
-    let output = Command::new("wmctrl")
-        .args(&["-i", "-r", window_id, "-b", "add,hidden"])
-        .output()
-        .map_err(|e| format!("Failed to execute wmctrl: {}", e))?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(format!(
-            "Failed to minimize window: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ))
-    }
+    window_backend().minimize(window_id)
 }
 
 /// Close a window
 pub fn close_window(window_id: &str) -> Result<(), String> {
-    let output = Command::new("wmctrl")
-        .args(&["-i", "-c", window_id])
-        .output()
-        .map_err(|e| format!("Failed to execute wmctrl: {}", e))?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(format!(
-            "Failed to close window: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ))
-    }
+    window_backend().close(window_id)
 }
 
 /// Move and resize a window
@@ -293,112 +337,356 @@ pub fn move_resize_window(
     width: i32,
     height: i32,
 ) -> Result<(), String> {
-    let geometry = format!("0,{},{},{},{}", x, y, width, height);
-    let output = Command::new("wmctrl")
-        .args(&["-i", "-r", window_id, "-e", &geometry])
-        .output()
-        .map_err(|e| format!("Failed to execute wmctrl: {}", e))?;
+    window_backend().move_resize(window_id, x, y, width, height)
+}
 
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(format!(
-            "Failed to move/resize window: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ))
+/// Position and size of a window: `(x, y, width, height)`
+pub fn window_geometry(window_id: &str) -> Result<(i32, i32, i32, i32), String> {
+    window_backend().geometry(window_id)
+}
+
+/// IDs of the windows in `windows` that nothing covers, so a capture of
+/// their part of the screen shows them and only them
+///
+/// These are the windows on the shown workspace (or on all workspaces) that
+/// have a size and overlap no other window there. Backends do not report the
+/// stacking order, so the one exception is the focused window, which is above
+/// the others.
+pub fn uncovered_windows(windows: &[WindowInfo]) -> Result<Vec<String>, String> {
+    let backend = window_backend();
+    let shown = backend
+        .list_workspaces()?
+        .into_iter()
+        .find(|workspace| workspace.active)
+        .map(|workspace| workspace.id);
+    let placed: Vec<_> = windows
+        .iter()
+        .filter(|w| w.desktop == -1 || Some(w.desktop) == shown)
+        .filter_map(|w| Some((w.id.clone(), backend.geometry(&w.id).ok()?)))
+        .filter(|(_, (_, _, width, height))| *width > 0 && *height > 0)
+        .collect();
+    let active = backend.active_window_id().ok();
+    Ok(uncovered(&placed, active.as_deref()))
+}
+
+/// Position and size of a window: `(x, y, width, height)`
+type Geometry = (i32, i32, i32, i32);
+
+/// The windows (ID, geometry) that overlap none of the others, and `active`
+/// if it is among them
+fn uncovered(windows: &[(String, Geometry)], active: Option<&str>) -> Vec<String> {
+    let overlap = |a: Geometry, b: Geometry| {
+        let (a_x, a_y, a_width, a_height) = a;
+        let (b_x, b_y, b_width, b_height) = b;
+        let (a_right, a_bottom) = (a_x as i64 + a_width as i64, a_y as i64 + a_height as i64);
+        let (b_right, b_bottom) = (b_x as i64 + b_width as i64, b_y as i64 + b_height as i64);
+        (a_x as i64) < b_right
+            && (b_x as i64) < a_right
+            && (a_y as i64) < b_bottom
+            && (b_y as i64) < a_bottom
+    };
+    windows
+        .iter()
+        .enumerate()
+        .filter(|(index, (id, geometry))| {
+            Some(id.as_str()) == active
+                || windows
+                    .iter()
+                    .enumerate()
+                    .all(|(other, (_, other_geometry))| {
+                        other == *index || !overlap(*geometry, *other_geometry)
+                    })
+        })
+        .map(|(_, (id, _))| id.clone())
+        .collect()
+}
+
+/// ID of the focused window, in the format `list_windows` uses
+pub fn active_window_id() -> Result<String, String> {
+    window_backend().active_window_id()
+}
+
+/// Window class of the focused window
+pub fn active_window_class() -> Result<String, String> {
+    if let Ok(id) = active_window_id()
+        && let Some(window) = list_windows()?.into_iter().find(|w| w.id == id)
+    {
+        return Ok(window.class);
     }
+    // GNOME on Wayland only reports the class through its shell
+    get_active_window_gdbus()
+        .map(|w| w.window.class)
+        .map_err(|_| "Could not determine the focused window".to_string())
 }
 
-/// Window information structure
-#[derive(Debug, Clone)]
-pub struct WindowInfo {
-    pub id: String,
-    pub pid: u32,
-    pub desktop: i32,
-    pub class: String,
-    pub title: String,
-    pub machine: String,
+/// Focus a window by its ID
+pub fn focus_window_by_id(window_id: &str) -> Result<(), String> {
+    window_backend().focus(window_id)
 }
 
-fn parse_wmctrl_line(line: &str) -> Option<WindowInfo> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.len() < 5 {
-        return None;
+/// A part of a monitor a window can be snapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SnapPosition {
+    LeftHalf,
+    RightHalf,
+    TopHalf,
+    BottomHalf,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    LeftThird,
+    CenterThird,
+    RightThird,
+    LeftTwoThirds,
+    RightTwoThirds,
+}
+
+impl SnapPosition {
+    pub const ALL: &[SnapPosition] = &[
+        SnapPosition::LeftHalf,
+        SnapPosition::RightHalf,
+        SnapPosition::TopHalf,
+        SnapPosition::BottomHalf,
+        SnapPosition::TopLeft,
+        SnapPosition::TopRight,
+        SnapPosition::BottomLeft,
+        SnapPosition::BottomRight,
+        SnapPosition::LeftThird,
+        SnapPosition::CenterThird,
+        SnapPosition::RightThird,
+        SnapPosition::LeftTwoThirds,
+        SnapPosition::RightTwoThirds,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapPosition::LeftHalf => "left-half",
+            SnapPosition::RightHalf => "right-half",
+            SnapPosition::TopHalf => "top-half",
+            SnapPosition::BottomHalf => "bottom-half",
+            SnapPosition::TopLeft => "top-left",
+            SnapPosition::TopRight => "top-right",
+            SnapPosition::BottomLeft => "bottom-left",
+            SnapPosition::BottomRight => "bottom-right",
+            SnapPosition::LeftThird => "left-third",
+            SnapPosition::CenterThird => "center-third",
+            SnapPosition::RightThird => "right-third",
+            SnapPosition::LeftTwoThirds => "left-two-thirds",
+            SnapPosition::RightTwoThirds => "right-two-thirds",
+        }
     }
 
-    let id = parts[0].to_string();
-    let desktop = parts[1].parse::<i32>().unwrap_or(-1);
-    let pid = parts[2].parse::<u32>().unwrap_or(0);
-    let class = parts[3].to_string();
-    let machine = parts[4].to_string();
+    /// Parse a name such as "left-half"; "left_half" works too
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let name = name.to_lowercase().replace('_', "-");
+        SnapPosition::ALL
+            .iter()
+            .find(|position| position.as_str() == name)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = SnapPosition::ALL.iter().map(|p| p.as_str()).collect();
+                format!(
+                    "Unknown snap position '{}' (one of {})",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
 
-    // The title is the rest of the line after the first 5 parts
-    let title = if parts.len() > 5 {
-        parts[5..].join(" ")
-    } else {
-        String::new()
+    /// The area `(x, y, width, height)` it covers of a monitor at `(x, y)`
+    /// with the given size
+    pub fn area(&self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        // Left, top, right and bottom edges in sixths, which fit halves and thirds
+        let (left, top, right, bottom) = match self {
+            SnapPosition::LeftHalf => (0, 0, 3, 6),
+            SnapPosition::RightHalf => (3, 0, 6, 6),
+            SnapPosition::TopHalf => (0, 0, 6, 3),
+            SnapPosition::BottomHalf => (0, 3, 6, 6),
+            SnapPosition::TopLeft => (0, 0, 3, 3),
+            SnapPosition::TopRight => (3, 0, 6, 3),
+            SnapPosition::BottomLeft => (0, 3, 3, 6),
+            SnapPosition::BottomRight => (3, 3, 6, 6),
+            SnapPosition::LeftThird => (0, 0, 2, 6),
+            SnapPosition::CenterThird => (2, 0, 4, 6),
+            SnapPosition::RightThird => (4, 0, 6, 6),
+            SnapPosition::LeftTwoThirds => (0, 0, 4, 6),
+            SnapPosition::RightTwoThirds => (2, 0, 6, 6),
+        };
+        let column = |sixths: i32| width * sixths / 6;
+        let row = |sixths: i32| height * sixths / 6;
+        (
+            x + column(left),
+            y + row(top),
+            column(right) - column(left),
+            row(bottom) - row(top),
+        )
+    }
+}
+
+/// Move and resize a window to cover part of a monitor, and return the area
+/// `(x, y, width, height)`
+///
+/// Without a `monitor` the one showing the window's centre is used.
+pub fn snap_window(
+    window_id: &str,
+    position: SnapPosition,
+    monitor: Option<&DisplayRef>,
+) -> Result<(i32, i32, i32, i32), String> {
+    let display = match monitor {
+        Some(monitor) => find_display(monitor)?,
+        None => {
+            let (x, y, width, height) = window_geometry(window_id)?;
+            let displays = list_displays()?;
+            display_at(&displays, x + width / 2, y + height / 2)
+                .or_else(|| displays.iter().find(|display| display.primary))
+                .or(displays.first())
+                .cloned()
+                .ok_or_else(|| "No monitor found".to_string())?
+        }
     };
+    let (width, height) = display.logical_size();
+    let (x, y, width, height) = position.area(display.x, display.y, width, height);
+    move_resize_window(window_id, x, y, width, height)?;
+    Ok((x, y, width, height))
+}
 
-    Some(WindowInfo {
-        id,
-        pid,
-        desktop,
-        class,
-        title,
-        machine,
-    })
+/// Put a window above all others without focusing it
+pub fn raise_window(window_id: &str) -> Result<(), String> {
+    window_backend().raise(window_id)
 }
 
-/// Parse Hyprland clients JSON output
-fn parse_hyprctl_clients(json_str: &str) -> Result<Vec<WindowInfo>, String> {
-    // Simple JSON parsing for Hyprland clients
-    // Format: [{"address":"0x...","class":"Firefox","title":"...","pid":1234,...}]
-    let mut windows = Vec::new();
-
-    // Very basic JSON parsing - in production, use serde_json
-    if let Some(start) = json_str.find('[') {
-        if let Some(end) = json_str.rfind(']') {
-            let content = &json_str[start + 1..end];
-
-            // Split by "},{"
-            for entry in content.split("},{") {
-                let entry = entry.trim_matches(|c| c == '{' || c == '}');
-
-                let mut id = String::new();
-                let mut class = String::new();
-                let mut title = String::new();
-                let mut pid = 0u32;
-
-                for field in entry.split(',') {
-                    if let Some(colon_pos) = field.find(':') {
-                        let key = field[..colon_pos].trim().trim_matches('"');
-                        let value = field[colon_pos + 1..].trim().trim_matches('"');
-
-                        match key {
-                            "address" => id = value.to_string(),
-                            "class" => class = value.to_string(),
-                            "title" => title = value.to_string(),
-                            "pid" => pid = value.parse().unwrap_or(0),
-                            _ => {}
-                        }
-                    }
-                }
-
-                if !id.is_empty() {
-                    windows.push(WindowInfo {
-                        id,
-                        pid,
-                        desktop: 0,
-                        class,
-                        title,
-                        machine: String::from("localhost"),
-                    });
-                }
-            }
+/// Put a window below all others
+pub fn lower_window(window_id: &str) -> Result<(), String> {
+    window_backend().lower(window_id)
+}
+
+/// Move a window to a workspace (desktop), without following it
+pub fn move_window_to_workspace(window_id: &str, workspace: i32) -> Result<(), String> {
+    window_backend().move_to_workspace(window_id, workspace)
+}
+
+/// A state of a window besides maximized and minimized, as in the
+/// `_NET_WM_STATE` property of X11
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowState {
+    Fullscreen,
+    /// Kept above other windows
+    AlwaysOnTop,
+    /// Kept below other windows
+    AlwaysBelow,
+    /// Rolled up into its title bar
+    Shaded,
+    /// Shown on every workspace
+    Sticky,
+    SkipTaskbar,
+    SkipPager,
+    DemandsAttention,
+}
+
+impl WindowState {
+    pub const ALL: &[WindowState] = &[
+        WindowState::Fullscreen,
+        WindowState::AlwaysOnTop,
+        WindowState::AlwaysBelow,
+        WindowState::Shaded,
+        WindowState::Sticky,
+        WindowState::SkipTaskbar,
+        WindowState::SkipPager,
+        WindowState::DemandsAttention,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WindowState::Fullscreen => "fullscreen",
+            WindowState::AlwaysOnTop => "always_on_top",
+            WindowState::AlwaysBelow => "always_below",
+            WindowState::Shaded => "shaded",
+            WindowState::Sticky => "sticky",
+            WindowState::SkipTaskbar => "skip_taskbar",
+            WindowState::SkipPager => "skip_pager",
+            WindowState::DemandsAttention => "demands_attention",
+        }
+    }
+
+    /// Parse a name such as "always_on_top"; the X11 names ("above",
+    /// "below") work too
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let name = name.to_lowercase().replace('-', "_");
+        let alias = match name.as_str() {
+            "above" => "always_on_top",
+            "below" => "always_below",
+            "shade" => "shaded",
+            other => other,
+        };
+        WindowState::ALL
+            .iter()
+            .find(|state| state.as_str() == alias)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = WindowState::ALL.iter().map(|s| s.as_str()).collect();
+                format!(
+                    "Unknown window state '{}' (one of {})",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// The `_NET_WM_STATE` property wmctrl changes
+    pub fn wm_property(&self) -> &'static str {
+        match self {
+            WindowState::Fullscreen => "fullscreen",
+            WindowState::AlwaysOnTop => "above",
+            WindowState::AlwaysBelow => "below",
+            WindowState::Shaded => "shaded",
+            WindowState::Sticky => "sticky",
+            WindowState::SkipTaskbar => "skip_taskbar",
+            WindowState::SkipPager => "skip_pager",
+            WindowState::DemandsAttention => "demands_attention",
         }
     }
+}
+
+/// Turn a state such as fullscreen or always-on-top on or off for a window
+///
+/// X11 window managers support every state; Hyprland and Sway only
+/// fullscreen and sticky.
+pub fn set_window_state(window_id: &str, state: WindowState, on: bool) -> Result<(), String> {
+    window_backend().set_state(window_id, state, on)
+}
+
+/// Show a workspace (desktop)
+pub fn switch_workspace(workspace: i32) -> Result<(), String> {
+    window_backend().switch_workspace(workspace)
+}
+
+/// The workspaces (desktops) of the session
+///
+/// Numbers are the ones the desktop uses: X11 desktops count from 0,
+/// Hyprland and sway workspaces from 1.
+pub fn list_workspaces() -> Result<Vec<Workspace>, String> {
+    window_backend().list_workspaces()
+}
+
+/// A workspace (desktop) and whether it is the one shown
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Workspace {
+    pub id: i32,
+    pub name: String,
+    pub active: bool,
+}
 
-    Ok(windows)
+/// Window information structure
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowInfo {
+    pub id: String,
+    pub pid: u32,
+    pub desktop: i32,
+    pub class: String,
+    pub title: String,
+    pub machine: String,
 }
 
 /// Check if an application window is visible/open
@@ -421,32 +709,367 @@ pub fn find_window_by_pattern(pattern: &str) -> Result<Option<WindowInfo>, Strin
     }))
 }
 
+/// Which property of a window a `WindowQuery` looks at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WindowField {
+    /// Class or title
+    #[default]
+    Any,
+    Class,
+    Title,
+    /// The process ID, which has to match exactly
+    Pid,
+}
+
+impl WindowField {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "any" => Ok(WindowField::Any),
+            "class" => Ok(WindowField::Class),
+            "title" => Ok(WindowField::Title),
+            "pid" => Ok(WindowField::Pid),
+            _ => Err(format!(
+                "Unknown window field '{}' (one of any, class, title, pid)",
+                name
+            )),
+        }
+    }
+}
+
+/// A search for windows: a substring (or regular expression) matched against
+/// the class, the title or the process ID
+#[derive(Debug, Clone, Default)]
+pub struct WindowQuery {
+    pub pattern: String,
+    pub field: WindowField,
+    pub regex: bool,
+    pub case_sensitive: bool,
+}
+
+impl WindowQuery {
+    /// A case-insensitive substring search of class and title, as
+    /// `find_window_by_pattern` does
+    pub fn new(pattern: &str) -> Self {
+        WindowQuery {
+            pattern: pattern.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The matching windows, best first: whole matches before prefixes before
+    /// matches elsewhere, class before title, then in list order
+    pub fn rank(&self, windows: Vec<WindowInfo>) -> Result<Vec<WindowInfo>, String> {
+        let source = if self.regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+            .map_err(|e| format!("Invalid window pattern: {}", e))?;
+        let mut ranked: Vec<(u8, WindowInfo)> = windows
+            .into_iter()
+            .filter_map(|window| Some((self.score(&regex, &window)?, window)))
+            .collect();
+        ranked.sort_by_key(|(score, _)| *score);
+        Ok(ranked.into_iter().map(|(_, window)| window).collect())
+    }
+
+    /// Lower is better; `None` when the window does not match
+    fn score(&self, regex: &Regex, window: &WindowInfo) -> Option<u8> {
+        let score = |text: &str| {
+            let found = regex.find(text)?;
+            Some(match (found.start(), found.end()) {
+                (0, end) if end == text.len() => 0,
+                (0, _) => 2,
+                _ => 4,
+            })
+        };
+        match self.field {
+            WindowField::Any => {
+                let class = score(&window.class);
+                let title = score(&window.title).map(|s| s + 1);
+                class.into_iter().chain(title).min()
+            }
+            WindowField::Class => score(&window.class),
+            WindowField::Title => score(&window.title),
+            WindowField::Pid => score(&window.pid.to_string()).filter(|&s| s == 0),
+        }
+    }
+}
+
+/// Every window matching `query`, best match first
+pub fn find_windows(query: &WindowQuery) -> Result<Vec<WindowInfo>, String> {
+    query.rank(list_windows()?)
+}
+
+/// Something done to every window matching a query at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulkWindowOp {
+    Close,
+    Minimize,
+    MoveToWorkspace(i32),
+}
+
+/// What a bulk window operation did
+#[derive(Debug, Default, Serialize)]
+pub struct BulkReport {
+    /// IDs of the windows it was done to
+    pub windows: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Close, minimize or move every window matching `query`
+///
+/// No matching window is not an error, so cleanup steps can run whether or
+/// not the application is open; an empty pattern is refused rather than
+/// matching everything.
+pub fn bulk_window_op(query: &WindowQuery, op: BulkWindowOp) -> Result<BulkReport, String> {
+    if query.pattern.is_empty() {
+        return Err("Give a pattern for the windows".to_string());
+    }
+    let mut report = BulkReport::default();
+    for window in find_windows(query)? {
+        let result = match op {
+            BulkWindowOp::Close => close_window(&window.id),
+            BulkWindowOp::Minimize => minimize_window(&window.id),
+            BulkWindowOp::MoveToWorkspace(workspace) => {
+                move_window_to_workspace(&window.id, workspace)
+            }
+        };
+        match result {
+            Ok(_) => report.windows.push(window.id),
+            Err(e) => report
+                .errors
+                .push(format!("{} ({}): {}", window.title, window.id, e)),
+        }
+    }
+    if report.windows.is_empty() && !report.errors.is_empty() {
+        return Err(report.errors.join("; "));
+    }
+    Ok(report)
+}
+
+/// Wait until a window whose class or title contains `pattern` is open
+pub fn wait_for_window(pattern: &str, timeout_ms: u64) -> Result<WindowInfo, String> {
+    let deadline = Instant::now()
+        .checked_add(Duration::from_millis(timeout_ms))
+        .ok_or("timeout_ms is too long")?;
+    loop {
+        if let Some(window) = find_window_by_pattern(pattern)? {
+            return Ok(window);
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "No window matching '{}' appeared within {} ms",
+                pattern, timeout_ms
+            ));
+        }
+        std::thread::sleep(WINDOW_POLL);
+    }
+}
+
 /// Open or focus an application
+///
+/// Returns a warning when the application was launched but no window of it
+/// showed up to be focused.
 pub fn open_or_focus_application(
     app_name: &str,
     launch_command: Option<&str>,
-) -> Result<(), String> {
+) -> Result<Option<String>, String> {
+    let cmd = launch_command.unwrap_or(app_name);
+    let mut names = application_names(app_name);
+    if cmd != app_name {
+        names.extend(application_names(cmd));
+    }
+    let find = || -> Result<Option<WindowInfo>, String> {
+        Ok(list_windows()?.into_iter().find(|w| {
+            let (class, title) = (w.class.to_lowercase(), w.title.to_lowercase());
+            names
+                .iter()
+                .any(|name| class.contains(name) || title.contains(name))
+        }))
+    };
+
     // First, check if the application is already running and visible
-    if let Ok(Some(window)) = find_window_by_pattern(app_name) {
+    // The ID, since several windows may share the title
+    if let Ok(Some(window)) = find() {
         // Application is already open, just focus it
-        focus_window(&window.title)?;
-        return Ok(());
+        focus_window_by_id(&window.id)?;
+        return Ok(None);
     }
 
-    // Check if process is running but no window is visible
-    if is_process_running(app_name)? {
-        // Process exists, try to focus by name
-        if focus_window(app_name).is_ok() {
-            return Ok(());
+    // Application is not running, launch it
+    launch_application(cmd)?;
+
+    // Return once its window is up and focused, so the next action can use it
+    let deadline = Instant::now() + Duration::from_millis(LAUNCH_TIMEOUT_MS);
+    loop {
+        if let Some(window) = find()? {
+            focus_window_by_id(&window.id)?;
+            return Ok(None);
         }
+        if Instant::now() >= deadline {
+            return Ok(Some(format!(
+                "{} was launched, but no window of it appeared within {} ms",
+                app_name, LAUNCH_TIMEOUT_MS
+            )));
+        }
+        std::thread::sleep(WINDOW_POLL);
     }
+}
 
-    // Application is not running, launch it
-    let cmd = launch_command.unwrap_or(app_name);
-    launch_application(cmd)?;
+/// What the windows of an application may have in their class or title,
+/// lowercase: the name it was given and, for an installed application, its
+/// desktop file ID (whole and its last part) and name
+///
+/// So "Firefox Web Browser" or "org.mozilla.firefox.desktop" find a window
+/// of class "firefox".
+fn application_names(app_name: &str) -> Vec<String> {
+    let mut names = vec![app_name.trim_end_matches(".desktop").to_string()];
+    if let Some(entry) = resolve_application(app_name) {
+        let last = entry.id.rsplit('.').next().unwrap_or_default().to_string();
+        names.extend([last, entry.id, entry.name]);
+    }
+    let mut names: Vec<String> = names
+        .into_iter()
+        .map(|name| name.to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.dedup();
+    names
+}
 
-    // Wait a bit for the application to start
-    std::thread::sleep(std::time::Duration::from_millis(500));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    #[test]
+    fn test_uncovered_windows() {
+        let window = |id: &str, x, y| (id.to_string(), (x, y, 100, 100));
+        let windows = vec![
+            window("editor", 0, 0),
+            window("terminal", 50, 50),
+            window("browser", 500, 0),
+        ];
+        assert_eq!(uncovered(&windows, None), vec!["browser"]);
+        assert_eq!(
+            uncovered(&windows, Some("terminal")),
+            vec!["terminal", "browser"]
+        );
+        // Touching edges do not overlap
+        let side_by_side = vec![window("left", 0, 0), window("right", 100, 0)];
+        assert_eq!(uncovered(&side_by_side, None), vec!["left", "right"]);
+    }
+
+    #[test]
+    fn test_parse_gnome_window() {
+        let reply = r#"(true, '{"id":"2147","pid":4242,"desktop":1,"class":"org.gnome.Nautilus","title":"Bob\'s \\"files\\"","x":10,"y":20,"width":800,"height":600}')"#;
+        let active = parse_gnome_window(reply).unwrap();
+        assert_eq!(active.window.id, "2147");
+        assert_eq!(active.window.pid, 4242);
+        assert_eq!(active.window.title, "Bob's \"files\"");
+        assert_eq!(
+            (active.x, active.y, active.width, active.height),
+            (10, 20, 800, 600)
+        );
+        assert!(parse_gnome_window("(true, 'null')").is_none());
+        assert!(parse_gnome_window("(false, 'ReferenceError')").is_none());
+    }
+
+    #[test]
+    fn test_window_state_names() {
+        for state in WindowState::ALL {
+            assert_eq!(WindowState::parse(state.as_str()).unwrap(), *state);
+        }
+        assert_eq!(
+            WindowState::parse("above").unwrap(),
+            WindowState::AlwaysOnTop
+        );
+        assert_eq!(WindowState::AlwaysOnTop.wm_property(), "above");
+        assert!(WindowState::parse("maximized").is_err());
+    }
+
+    #[test]
+    fn test_snap_positions() {
+        for position in SnapPosition::ALL {
+            assert_eq!(SnapPosition::parse(position.as_str()).unwrap(), *position);
+        }
+        assert_eq!(
+            SnapPosition::parse("top_right").unwrap(),
+            SnapPosition::TopRight
+        );
+        assert!(SnapPosition::parse("middle").is_err());
+
+        // A second monitor to the right of a 1920x1080 one
+        assert_eq!(
+            SnapPosition::RightHalf.area(1920, 0, 1920, 1080),
+            (2880, 0, 960, 1080)
+        );
+        assert_eq!(
+            SnapPosition::BottomLeft.area(0, 0, 1920, 1080),
+            (0, 540, 960, 540)
+        );
+        assert_eq!(
+            SnapPosition::CenterThird.area(0, 0, 1000, 600),
+            (333, 0, 333, 600)
+        );
+        assert_eq!(
+            SnapPosition::RightThird.area(0, 0, 1000, 600),
+            (666, 0, 334, 600)
+        );
+    }
+
+    #[test]
+    fn test_window_query_ranking() {
+        let window = |id: &str, pid: u32, class: &str, title: &str| WindowInfo {
+            id: id.into(),
+            pid,
+            desktop: 0,
+            class: class.into(),
+            title: title.into(),
+            machine: "localhost".into(),
+        };
+        let windows = vec![
+            window("0x1", 10, "Navigator.firefox", "Terminal tricks - Firefox"),
+            window("0x2", 20, "gnome-terminal", "~/src"),
+            window("0x3", 30, "terminal", "bash"),
+        ];
+        let ids = |query: &WindowQuery| -> Vec<String> {
+            let ranked = query.rank(windows.clone()).unwrap();
+            ranked.into_iter().map(|w| w.id).collect()
+        };
+
+        assert_eq!(ids(&WindowQuery::new("terminal")), ["0x3", "0x1", "0x2"]);
+        let titles = WindowQuery {
+            field: WindowField::Title,
+            ..WindowQuery::new("terminal")
+        };
+        assert_eq!(ids(&titles), ["0x1"]);
+        let case_sensitive = WindowQuery {
+            case_sensitive: true,
+            ..WindowQuery::new("Terminal")
+        };
+        assert_eq!(ids(&case_sensitive), ["0x1"]);
+        let regex = WindowQuery {
+            regex: true,
+            field: WindowField::Class,
+            ..WindowQuery::new("^(gnome-)?terminal$")
+        };
+        assert_eq!(ids(&regex), ["0x2", "0x3"]);
+        let pid = WindowQuery {
+            field: WindowField::Pid,
+            ..WindowQuery::new("2")
+        };
+        assert!(ids(&pid).is_empty());
+        assert!(
+            WindowQuery {
+                regex: true,
+                ..WindowQuery::new("(")
+            }
+            .rank(windows.clone())
+            .is_err()
+        );
+    }
 }
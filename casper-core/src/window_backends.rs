@@ -0,0 +1,1052 @@
+use crate::commands::CommandDeadline;
+use crate::paths;
+use crate::setup::find_in_path;
+use crate::window::{WindowBackend, WindowInfo, WindowState, Workspace};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::sync::Mutex;
+#[cfg(feature = "wayland")]
+use std::sync::OnceLock;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Run a window tool and return what it printed; `what` names the
+/// operation in errors
+fn run(program: &str, args: &[&str], what: &str) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output_bounded()
+        .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to {}: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// X11 window managers through wmctrl (and xdotool for the focused window);
+/// on Wayland this only sees XWayland windows
+pub struct Wmctrl;
+
+impl WindowBackend for Wmctrl {
+    fn name(&self) -> &'static str {
+        "wmctrl"
+    }
+
+    fn list_windows(&self) -> Result<Vec<WindowInfo>, String> {
+        let output = run("wmctrl", &["-l", "-p", "-x"], "list windows")?;
+        Ok(output.lines().filter_map(parse_wmctrl_line).collect())
+    }
+
+    fn focus_matching(&self, pattern: &str) -> Result<(), String> {
+        run("wmctrl", &["-a", pattern], "focus window").map(|_| ())
+    }
+
+    fn focus(&self, window_id: &str) -> Result<(), String> {
+        run("wmctrl", &["-i", "-a", window_id], "focus window").map(|_| ())
+    }
+
+    fn active_window_id(&self) -> Result<String, String> {
+        let output = run("xdotool", &["getactivewindow"], "find the active window")?;
+        let id: u64 = output
+            .trim()
+            .parse()
+            .map_err(|_| "No active window".to_string())?;
+        // wmctrl lists IDs as zero-padded hex
+        Ok(format!("0x{:08x}", id))
+    }
+
+    fn geometry(&self, window_id: &str) -> Result<(i32, i32, i32, i32), String> {
+        run("wmctrl", &["-l", "-G"], "list windows")?
+            .lines()
+            .find_map(|line| parse_wmctrl_geometry(line, window_id))
+            .ok_or_else(|| format!("No window with id {}", window_id))
+    }
+
+    fn move_resize(
+        &self,
+        window_id: &str,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String> {
+        let geometry = format!("0,{},{},{},{}", x, y, width, height);
+        run(
+            "wmctrl",
+            &["-i", "-r", window_id, "-e", &geometry],
+            "move/resize window",
+        )
+        .map(|_| ())
+    }
+
+    fn maximize(&self, window_id: &str) -> Result<(), String> {
+        run(
+            "wmctrl",
+            &[
+                "-i",
+                "-r",
+                window_id,
+                "-b",
+                "add,maximized_vert,maximized_horz",
+            ],
+            "maximize window",
+        )
+        .map(|_| ())
+    }
+
+    fn minimize(&self, window_id: &str) -> Result<(), String> {
+        run(
+            "wmctrl",
+            &["-i", "-r", window_id, "-b", "add,hidden"],
+            "minimize window",
+        )
+        .map(|_| ())
+    }
+
+    fn close(&self, window_id: &str) -> Result<(), String> {
+        run("wmctrl", &["-i", "-c", window_id], "close window").map(|_| ())
+    }
+
+    fn move_to_workspace(&self, window_id: &str, workspace: i32) -> Result<(), String> {
+        let what = format!("move window to workspace {}", workspace);
+        let workspace = workspace.to_string();
+        run("wmctrl", &["-i", "-r", window_id, "-t", &workspace], &what).map(|_| ())
+    }
+
+    fn list_workspaces(&self) -> Result<Vec<Workspace>, String> {
+        let output = run("wmctrl", &["-d"], "list workspaces")?;
+        Ok(output.lines().filter_map(parse_wmctrl_desktop).collect())
+    }
+
+    fn switch_workspace(&self, workspace: i32) -> Result<(), String> {
+        let what = format!("switch to workspace {}", workspace);
+        run("wmctrl", &["-s", &workspace.to_string()], &what).map(|_| ())
+    }
+
+    fn raise(&self, window_id: &str) -> Result<(), String> {
+        restack_x11_window(window_id, true)
+    }
+
+    fn lower(&self, window_id: &str) -> Result<(), String> {
+        restack_x11_window(window_id, false)
+    }
+
+    fn set_state(&self, window_id: &str, state: WindowState, on: bool) -> Result<(), String> {
+        let change = format!(
+            "{},{}",
+            if on { "add" } else { "remove" },
+            state.wm_property()
+        );
+        let what = format!("set {} on the window", state.as_str());
+        run("wmctrl", &["-i", "-r", window_id, "-b", &change], &what).map(|_| ())
+    }
+}
+
+/// Move an X11 window to the top or bottom of the stack; wmctrl can only
+/// raise a window by activating it
+#[cfg(feature = "x11")]
+fn restack_x11_window(window_id: &str, above: bool) -> Result<(), String> {
+    use x11rb::protocol::xproto::{ConfigureWindowAux, ConnectionExt, StackMode};
+
+    let window = u32::from_str_radix(window_id.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("Invalid X11 window id {}", window_id))?;
+    let (connection, _) =
+        x11rb::connect(None).map_err(|e| format!("Failed to connect to X11: {}", e))?;
+    let stack_mode = if above {
+        StackMode::ABOVE
+    } else {
+        StackMode::BELOW
+    };
+    connection
+        .configure_window(window, &ConfigureWindowAux::new().stack_mode(stack_mode))
+        .map_err(|e| e.to_string())?
+        .check()
+        .map_err(|e| format!("Failed to restack window {}: {}", window_id, e))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "x11"))]
+fn restack_x11_window(_window_id: &str, _above: bool) -> Result<(), String> {
+    Err(crate::features::missing("x11"))
+}
+
+/// Hyprland through hyprctl; window IDs are client addresses
+pub struct Hyprland;
+
+/// The `address:` selector of a window, for IDs that are client addresses
+/// (`0x` and hex digits), so no ID can add to a dispatcher's arguments
+fn hyprland_address(window_id: &str) -> Result<String, String> {
+    match window_id.strip_prefix("0x") {
+        Some(digits) if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(format!("address:{}", window_id))
+        }
+        _ => Err(format!("Invalid Hyprland window id {}", window_id)),
+    }
+}
+
+impl Hyprland {
+    fn dispatch(&self, args: &[&str], what: &str) -> Result<(), String> {
+        let mut all = vec!["dispatch"];
+        all.extend_from_slice(args);
+        run("hyprctl", &all, what).map(|_| ())
+    }
+
+    fn clients(&self) -> Result<Vec<Value>, String> {
+        let output = run("hyprctl", &["clients", "-j"], "list windows")?;
+        serde_json::from_str(&output).map_err(|e| format!("Failed to parse hyprctl output: {}", e))
+    }
+}
+
+impl WindowBackend for Hyprland {
+    fn name(&self) -> &'static str {
+        "hyprland"
+    }
+
+    fn list_windows(&self) -> Result<Vec<WindowInfo>, String> {
+        Ok(self.clients()?.iter().filter_map(hyprland_window).collect())
+    }
+
+    fn focus_matching(&self, pattern: &str) -> Result<(), String> {
+        let title = format!("title:{}", pattern);
+        self.dispatch(&["focuswindow", &title], "focus window")
+    }
+
+    fn focus(&self, window_id: &str) -> Result<(), String> {
+        let address = hyprland_address(window_id)?;
+        self.dispatch(&["focuswindow", &address], "focus window")
+    }
+
+    fn active_window_id(&self) -> Result<String, String> {
+        let output = run("hyprctl", &["activewindow", "-j"], "find the active window")?;
+        let client: Value = serde_json::from_str(&output).unwrap_or_default();
+        hyprland_window(&client)
+            .map(|window| window.id)
+            .ok_or_else(|| "No active window".to_string())
+    }
+
+    fn geometry(&self, window_id: &str) -> Result<(i32, i32, i32, i32), String> {
+        self.clients()?
+            .iter()
+            .find(|client| client["address"].as_str() == Some(window_id))
+            .and_then(|client| {
+                let at = |key: &str, i: usize| client[key][i].as_i64().map(|v| v as i32);
+                Some((at("at", 0)?, at("at", 1)?, at("size", 0)?, at("size", 1)?))
+            })
+            .ok_or_else(|| format!("No window with id {}", window_id))
+    }
+
+    fn move_resize(
+        &self,
+        window_id: &str,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String> {
+        // Tiled windows keep the place the layout gives them
+        let address = hyprland_address(window_id)?;
+        self.dispatch(&["setfloating", &address], "move/resize window")?;
+        let position = format!("exact {} {},{}", x, y, address);
+        self.dispatch(&["movewindowpixel", &position], "move/resize window")?;
+        let size = format!("exact {} {},{}", width, height, address);
+        self.dispatch(&["resizewindowpixel", &size], "move/resize window")
+    }
+
+    fn maximize(&self, window_id: &str) -> Result<(), String> {
+        // Fullscreen mode 1 keeps the bar and gaps, Hyprland's maximize
+        self.focus(window_id)?;
+        self.dispatch(&["fullscreen", "1"], "maximize window")
+    }
+
+    fn raise(&self, window_id: &str) -> Result<(), String> {
+        let target = format!("top,{}", hyprland_address(window_id)?);
+        self.dispatch(&["alterzorder", &target], "raise window")
+    }
+
+    fn lower(&self, window_id: &str) -> Result<(), String> {
+        let target = format!("bottom,{}", hyprland_address(window_id)?);
+        self.dispatch(&["alterzorder", &target], "lower window")
+    }
+
+    fn minimize(&self, window_id: &str) -> Result<(), String> {
+        // Hyprland has no minimizing; a special workspace hides the window
+        let target = format!("special:minimized,{}", hyprland_address(window_id)?);
+        self.dispatch(&["movetoworkspacesilent", &target], "minimize window")
+    }
+
+    fn close(&self, window_id: &str) -> Result<(), String> {
+        let address = hyprland_address(window_id)?;
+        self.dispatch(&["closewindow", &address], "close window")
+    }
+
+    fn move_to_workspace(&self, window_id: &str, workspace: i32) -> Result<(), String> {
+        let target = format!("{},{}", workspace, hyprland_address(window_id)?);
+        self.dispatch(
+            &["movetoworkspacesilent", &target],
+            &format!("move window to workspace {}", workspace),
+        )
+    }
+
+    fn list_workspaces(&self) -> Result<Vec<Workspace>, String> {
+        let output = run("hyprctl", &["workspaces", "-j"], "list workspaces")?;
+        let workspaces: Vec<Value> = serde_json::from_str(&output)
+            .map_err(|e| format!("Failed to parse hyprctl output: {}", e))?;
+        let output = run("hyprctl", &["activeworkspace", "-j"], "list workspaces")?;
+        let active: Value = serde_json::from_str(&output).unwrap_or_default();
+        let mut workspaces: Vec<Workspace> = workspaces
+            .iter()
+            .filter_map(|workspace| {
+                let id = workspace["id"].as_i64()? as i32;
+                Some(Workspace {
+                    id,
+                    name: workspace["name"].as_str().unwrap_or_default().to_string(),
+                    active: active["id"].as_i64() == Some(id as i64),
+                })
+            })
+            // Special workspaces have negative IDs and cannot be switched to
+            .filter(|workspace| workspace.id > 0)
+            .collect();
+        workspaces.sort_by_key(|workspace| workspace.id);
+        Ok(workspaces)
+    }
+
+    fn switch_workspace(&self, workspace: i32) -> Result<(), String> {
+        self.dispatch(
+            &["workspace", &workspace.to_string()],
+            &format!("switch to workspace {}", workspace),
+        )
+    }
+
+    /// Hyprland only toggles these, so the current state is looked up first
+    fn set_state(&self, window_id: &str, state: WindowState, on: bool) -> Result<(), String> {
+        let address = hyprland_address(window_id)?;
+        let client = self
+            .clients()?
+            .into_iter()
+            .find(|client| client["address"].as_str() == Some(window_id))
+            .ok_or_else(|| format!("No window with id {}", window_id))?;
+        let what = format!("set {} on the window", state.as_str());
+        match state {
+            WindowState::Fullscreen => {
+                // Older versions report a bool, newer ones a mode number
+                let current = client["fullscreen"].as_bool().unwrap_or(false)
+                    || client["fullscreen"].as_i64().is_some_and(|mode| mode > 0);
+                if current != on {
+                    self.focus(window_id)?;
+                    self.dispatch(&["fullscreen", "0"], &what)?;
+                }
+                Ok(())
+            }
+            WindowState::Sticky => {
+                // Only floating windows can be pinned to every workspace
+                if on && !client["floating"].as_bool().unwrap_or(false) {
+                    self.dispatch(&["setfloating", &address], &what)?;
+                }
+                if client["pinned"].as_bool().unwrap_or(false) != on {
+                    self.dispatch(&["pin", &address], &what)?;
+                }
+                Ok(())
+            }
+            _ => Err(format!(
+                "Windows cannot be made {} on Hyprland",
+                state.as_str()
+            )),
+        }
+    }
+}
+
+/// Sway through swaymsg; window IDs are container IDs
+pub struct Sway;
+
+/// A window of the sway tree with what `WindowInfo` leaves out
+struct SwayWindow {
+    info: WindowInfo,
+    rect: (i32, i32, i32, i32),
+    focused: bool,
+}
+
+impl Sway {
+    fn windows(&self) -> Result<Vec<SwayWindow>, String> {
+        let output = run("swaymsg", &["-t", "get_tree", "-r"], "list windows")?;
+        let tree: Value = serde_json::from_str(&output)
+            .map_err(|e| format!("Failed to parse swaymsg output: {}", e))?;
+        let mut windows = Vec::new();
+        collect_sway_windows(&tree, 0, &mut windows);
+        Ok(windows)
+    }
+
+    /// Run sway commands on one container
+    ///
+    /// Container IDs are decimal numbers; anything else could close the
+    /// criteria and run commands of its own.
+    fn command(&self, window_id: &str, command: &str, what: &str) -> Result<(), String> {
+        if window_id.is_empty() || !window_id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("Invalid sway window id {}", window_id));
+        }
+        let command = format!("[con_id={}] {}", window_id, command);
+        run("swaymsg", &[&command], what).map(|_| ())
+    }
+}
+
+impl WindowBackend for Sway {
+    fn name(&self) -> &'static str {
+        "sway"
+    }
+
+    fn list_windows(&self) -> Result<Vec<WindowInfo>, String> {
+        Ok(self.windows()?.into_iter().map(|w| w.info).collect())
+    }
+
+    fn focus(&self, window_id: &str) -> Result<(), String> {
+        self.command(window_id, "focus", "focus window")
+    }
+
+    fn active_window_id(&self) -> Result<String, String> {
+        self.windows()?
+            .into_iter()
+            .find(|window| window.focused)
+            .map(|window| window.info.id)
+            .ok_or_else(|| "No active window".to_string())
+    }
+
+    fn geometry(&self, window_id: &str) -> Result<(i32, i32, i32, i32), String> {
+        self.windows()?
+            .into_iter()
+            .find(|window| window.info.id == window_id)
+            .map(|window| window.rect)
+            .ok_or_else(|| format!("No window with id {}", window_id))
+    }
+
+    fn move_resize(
+        &self,
+        window_id: &str,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String> {
+        let command = format!(
+            "floating enable, move absolute position {} {}, resize set {} {}",
+            x, y, width, height
+        );
+        self.command(window_id, &command, "move/resize window")
+    }
+
+    fn maximize(&self, window_id: &str) -> Result<(), String> {
+        // Sway has no maximizing; fullscreen is the nearest
+        self.command(window_id, "fullscreen enable", "maximize window")
+    }
+
+    fn minimize(&self, window_id: &str) -> Result<(), String> {
+        self.command(window_id, "move scratchpad", "minimize window")
+    }
+
+    fn close(&self, window_id: &str) -> Result<(), String> {
+        self.command(window_id, "kill", "close window")
+    }
+
+    fn move_to_workspace(&self, window_id: &str, workspace: i32) -> Result<(), String> {
+        self.command(
+            window_id,
+            &format!("move container to workspace number {}", workspace),
+            &format!("move window to workspace {}", workspace),
+        )
+    }
+
+    fn list_workspaces(&self) -> Result<Vec<Workspace>, String> {
+        let output = run(
+            "swaymsg",
+            &["-t", "get_workspaces", "-r"],
+            "list workspaces",
+        )?;
+        let workspaces: Vec<Value> = serde_json::from_str(&output)
+            .map_err(|e| format!("Failed to parse swaymsg output: {}", e))?;
+        Ok(workspaces.iter().filter_map(sway_workspace).collect())
+    }
+
+    fn set_state(&self, window_id: &str, state: WindowState, on: bool) -> Result<(), String> {
+        let what = format!("set {} on the window", state.as_str());
+        let toggle = if on { "enable" } else { "disable" };
+        match state {
+            WindowState::Fullscreen => {
+                self.command(window_id, &format!("fullscreen {}", toggle), &what)
+            }
+            // Only floating windows can be sticky
+            WindowState::Sticky if on => {
+                self.command(window_id, "floating enable, sticky enable", &what)
+            }
+            WindowState::Sticky => self.command(window_id, "sticky disable", &what),
+            _ => Err(format!("Windows cannot be made {} on Sway", state.as_str())),
+        }
+    }
+
+    fn switch_workspace(&self, workspace: i32) -> Result<(), String> {
+        let command = format!("workspace number {}", workspace);
+        run(
+            "swaymsg",
+            &[&command],
+            &format!("switch to workspace {}", workspace),
+        )
+        .map(|_| ())
+    }
+}
+
+/// KDE Plasma 6 on Wayland through KWin scripts, loaded and run with qdbus;
+/// window IDs are KWin's internal window UUIDs
+///
+/// Scripts hand their results back with `callDBus` to an object the daemon
+/// serves on the session bus.
+pub struct KWin;
+
+/// How long a KWin script may take to send its result
+const KWIN_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where KWin scripts send their results, on our own session bus connection
+const KWIN_REPLY_PATH: &str = "/org/casper/KWinReply";
+const KWIN_REPLY_INTERFACE: &str = "org.casper.KWinReply";
+
+/// Scripts waiting for their result, by plugin name
+static KWIN_WAITING: Mutex<BTreeMap<String, mpsc::Sender<String>>> = Mutex::new(BTreeMap::new());
+
+/// The object KWin scripts call with their result
+#[cfg(feature = "wayland")]
+struct KWinReplies;
+
+#[cfg(feature = "wayland")]
+#[zbus::interface(name = "org.casper.KWinReply")]
+impl KWinReplies {
+    fn reply(&self, plugin: String, result: String) {
+        if let Some(waiting) = KWIN_WAITING.lock().unwrap().remove(&plugin) {
+            let _ = waiting.send(result);
+        }
+    }
+}
+
+/// Unique bus name of the connection serving `KWinReplies`, which runs on a
+/// thread of its own for as long as the process does
+#[cfg(feature = "wayland")]
+fn kwin_reply_service() -> Result<String, String> {
+    static SERVICE: OnceLock<Result<String, String>> = OnceLock::new();
+    SERVICE
+        .get_or_init(|| {
+            let (ready, service) = mpsc::channel();
+            std::thread::spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = ready.send(Err(format!("Failed to start the KWin replies: {}", e)));
+                        return;
+                    }
+                };
+                runtime.block_on(async {
+                    let connection = match zbus::Connection::session().await {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            let _ = ready
+                                .send(Err(format!("Failed to connect to the session bus: {}", e)));
+                            return;
+                        }
+                    };
+                    if let Err(e) = connection
+                        .object_server()
+                        .at(KWIN_REPLY_PATH, KWinReplies)
+                        .await
+                    {
+                        let _ = ready.send(Err(format!("Failed to serve KWin replies: {}", e)));
+                        return;
+                    }
+                    let name = connection.unique_name().map(|name| name.to_string());
+                    let _ = ready.send(name.ok_or("No name on the session bus".to_string()));
+                    // The connection answers calls while this thread waits
+                    std::future::pending::<()>().await;
+                });
+            });
+            service
+                .recv()
+                .unwrap_or_else(|_| Err("The KWin replies stopped".to_string()))
+        })
+        .clone()
+}
+
+#[cfg(not(feature = "wayland"))]
+fn kwin_reply_service() -> Result<String, String> {
+    Err(crate::features::missing("wayland"))
+}
+
+/// Functions every KWin script can use: `find(id)` throws for unknown
+/// windows, `info(w)` is the JSON `kwin_window` reads
+const KWIN_PRELUDE: &str = "\
+function find(id) {
+    const w = workspace.windowList().find(w => String(w.internalId) === id);
+    if (!w) throw 'No window with id ' + id;
+    return w;
+}
+function desktop(n) {
+    const d = workspace.desktops[n];
+    if (!d) throw 'No workspace ' + n;
+    return d;
+}
+function info(w) {
+    const g = w.frameGeometry;
+    return { id: String(w.internalId), pid: w.pid, class: w.resourceClass, title: w.caption,
+        desktop: w.onAllDesktops ? -1 : workspace.desktops.indexOf(w.desktops[0]),
+        x: g.x, y: g.y, width: g.width, height: g.height, active: w === workspace.activeWindow };
+}
+";
+
+impl KWin {
+    /// Run `body` (JavaScript ending in `return` of the result, if any) as
+    /// a KWin script and return its result
+    fn script(&self, body: &str, what: &str) -> Result<Value, String> {
+        let qdbus = ["qdbus6", "qdbus-qt6", "qdbus"]
+            .into_iter()
+            .find(|program| find_in_path(program).is_some())
+            .ok_or("KWin is driven through qdbus, which is not installed")?;
+        let service = kwin_reply_service()?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let plugin = format!("casper-{}-{}", std::process::id(), started.as_nanos());
+        let path = paths::scratch_file(&format!("{}.js", plugin));
+        let quote = |text: &str| serde_json::to_string(text).unwrap_or_default();
+        let source = format!(
+            "{}\nlet result;\ntry {{ result = {{ ok: (() => {{ {} }})() }}; }} \
+             catch (e) {{ result = {{ error: String(e) }}; }}\n\
+             callDBus({}, {}, {}, 'Reply', {}, JSON.stringify(result));\n",
+            KWIN_PRELUDE,
+            body,
+            quote(&service),
+            quote(KWIN_REPLY_PATH),
+            quote(KWIN_REPLY_INTERFACE),
+            quote(&plugin)
+        );
+        std::fs::write(&path, source).map_err(|e| format!("Failed to write KWin script: {}", e))?;
+        let (waiting, reply) = mpsc::channel();
+        KWIN_WAITING.lock().unwrap().insert(plugin.clone(), waiting);
+        let loaded = run(
+            qdbus,
+            &[
+                "org.kde.KWin",
+                "/Scripting",
+                "org.kde.kwin.Scripting.loadScript",
+                &path.to_string_lossy(),
+                &plugin,
+            ],
+            what,
+        );
+        let result = loaded.and_then(|id| {
+            let object = format!("/Scripting/Script{}", id.trim());
+            run(
+                qdbus,
+                &["org.kde.KWin", &object, "org.kde.kwin.Script.run"],
+                what,
+            )?;
+            let reply = reply
+                .recv_timeout(KWIN_REPLY_TIMEOUT)
+                .map_err(|_| format!("Failed to {}: KWin did not answer in time", what))?;
+            kwin_result(&reply).map_err(|e| format!("Failed to {}: {}", what, e))
+        });
+        KWIN_WAITING.lock().unwrap().remove(&plugin);
+        let _ = run(
+            qdbus,
+            &[
+                "org.kde.KWin",
+                "/Scripting",
+                "org.kde.kwin.Scripting.unloadScript",
+                &plugin,
+            ],
+            what,
+        );
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    /// Run `body` on the window `window_id` as `w`
+    fn on_window(&self, window_id: &str, body: &str, what: &str) -> Result<(), String> {
+        let id = serde_json::to_string(window_id).unwrap_or_default();
+        self.script(&format!("const w = find({}); {}", id, body), what)
+            .map(|_| ())
+    }
+
+    fn windows(&self) -> Result<Vec<KWinWindow>, String> {
+        let list = self.script(
+            "return workspace.windowList().filter(w => w.normalWindow).map(info);",
+            "list windows",
+        )?;
+        Ok(list
+            .as_array()
+            .map(|windows| windows.iter().filter_map(kwin_window).collect())
+            .unwrap_or_default())
+    }
+}
+
+/// A window as a KWin script reports it
+struct KWinWindow {
+    info: WindowInfo,
+    rect: (i32, i32, i32, i32),
+    active: bool,
+}
+
+impl WindowBackend for KWin {
+    fn name(&self) -> &'static str {
+        "kwin"
+    }
+
+    fn list_windows(&self) -> Result<Vec<WindowInfo>, String> {
+        Ok(self.windows()?.into_iter().map(|w| w.info).collect())
+    }
+
+    fn focus(&self, window_id: &str) -> Result<(), String> {
+        self.on_window(window_id, "workspace.activeWindow = w;", "focus window")
+    }
+
+    fn active_window_id(&self) -> Result<String, String> {
+        self.windows()?
+            .into_iter()
+            .find(|window| window.active)
+            .map(|window| window.info.id)
+            .ok_or_else(|| "No active window".to_string())
+    }
+
+    fn geometry(&self, window_id: &str) -> Result<(i32, i32, i32, i32), String> {
+        self.windows()?
+            .into_iter()
+            .find(|window| window.info.id == window_id)
+            .map(|window| window.rect)
+            .ok_or_else(|| format!("No window with id {}", window_id))
+    }
+
+    fn move_resize(
+        &self,
+        window_id: &str,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String> {
+        let body = format!(
+            "w.setMaximize(false, false); w.frameGeometry = {{ x: {}, y: {}, width: {}, height: {} }};",
+            x, y, width, height
+        );
+        self.on_window(window_id, &body, "move/resize window")
+    }
+
+    fn maximize(&self, window_id: &str) -> Result<(), String> {
+        self.on_window(window_id, "w.setMaximize(true, true);", "maximize window")
+    }
+
+    fn minimize(&self, window_id: &str) -> Result<(), String> {
+        self.on_window(window_id, "w.minimized = true;", "minimize window")
+    }
+
+    fn close(&self, window_id: &str) -> Result<(), String> {
+        self.on_window(window_id, "w.closeWindow();", "close window")
+    }
+
+    fn raise(&self, window_id: &str) -> Result<(), String> {
+        self.on_window(window_id, "workspace.raiseWindow(w);", "raise window")
+    }
+
+    fn move_to_workspace(&self, window_id: &str, workspace: i32) -> Result<(), String> {
+        self.on_window(
+            window_id,
+            &format!("w.desktops = [desktop({})];", workspace),
+            &format!("move window to workspace {}", workspace),
+        )
+    }
+
+    fn list_workspaces(&self) -> Result<Vec<Workspace>, String> {
+        let desktops = self.script(
+            "return workspace.desktops.map(d => ({ name: d.name, active: d === workspace.currentDesktop }));",
+            "list workspaces",
+        )?;
+        Ok(desktops
+            .as_array()
+            .map(|desktops| {
+                desktops
+                    .iter()
+                    .enumerate()
+                    .map(|(index, desktop)| Workspace {
+                        id: index as i32,
+                        name: desktop["name"].as_str().unwrap_or_default().to_string(),
+                        active: desktop["active"].as_bool().unwrap_or(false),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn set_state(&self, window_id: &str, state: WindowState, on: bool) -> Result<(), String> {
+        let property = match state {
+            WindowState::Fullscreen => "fullScreen",
+            WindowState::AlwaysOnTop => "keepAbove",
+            WindowState::AlwaysBelow => "keepBelow",
+            WindowState::Shaded => "shade",
+            WindowState::Sticky => "onAllDesktops",
+            WindowState::SkipTaskbar => "skipTaskbar",
+            WindowState::SkipPager => "skipPager",
+            WindowState::DemandsAttention => "demandsAttention",
+        };
+        self.on_window(
+            window_id,
+            &format!("w.{} = {};", property, on),
+            &format!("set {} on the window", state.as_str()),
+        )
+    }
+
+    fn switch_workspace(&self, workspace: i32) -> Result<(), String> {
+        self.script(
+            &format!("workspace.currentDesktop = desktop({});", workspace),
+            &format!("switch to workspace {}", workspace),
+        )
+        .map(|_| ())
+    }
+}
+
+/// The result a KWin script sent: what the script returned, or the error
+/// it threw
+fn kwin_result(reply: &str) -> Result<Value, String> {
+    let result: Value =
+        serde_json::from_str(reply).map_err(|e| format!("Invalid KWin script output: {}", e))?;
+    match result["error"].as_str() {
+        Some(error) => Err(error.to_string()),
+        None => Ok(result["ok"].clone()),
+    }
+}
+
+/// A window of a KWin script's `info()`
+fn kwin_window(window: &Value) -> Option<KWinWindow> {
+    let number = |key: &str| window[key].as_f64().map(|v| v.round() as i32);
+    Some(KWinWindow {
+        info: WindowInfo {
+            id: window["id"].as_str()?.to_string(),
+            pid: window["pid"].as_u64().unwrap_or(0) as u32,
+            desktop: window["desktop"].as_i64().unwrap_or(0) as i32,
+            class: window["class"].as_str().unwrap_or_default().to_string(),
+            title: window["title"].as_str().unwrap_or_default().to_string(),
+            machine: String::from("localhost"),
+        },
+        rect: (
+            number("x")?,
+            number("y")?,
+            number("width")?,
+            number("height")?,
+        ),
+        active: window["active"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Windows below a sway tree node; containers with a process are windows,
+/// the others only split the space
+fn collect_sway_windows(node: &Value, workspace: i32, windows: &mut Vec<SwayWindow>) {
+    let workspace = match node["type"].as_str() {
+        Some("workspace") => node["num"].as_i64().unwrap_or(-1) as i32,
+        _ => workspace,
+    };
+    if let Some(pid) = node["pid"].as_u64() {
+        let number = |key: &str| node["rect"][key].as_i64().unwrap_or(0) as i32;
+        let class = node["app_id"]
+            .as_str()
+            .or_else(|| node["window_properties"]["class"].as_str())
+            .unwrap_or_default();
+        windows.push(SwayWindow {
+            info: WindowInfo {
+                id: node["id"].as_i64().unwrap_or(0).to_string(),
+                pid: pid as u32,
+                desktop: workspace,
+                class: class.to_string(),
+                title: node["name"].as_str().unwrap_or_default().to_string(),
+                machine: String::from("localhost"),
+            },
+            rect: (number("x"), number("y"), number("width"), number("height")),
+            focused: node["focused"].as_bool().unwrap_or(false),
+        });
+    }
+    for child in ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+    {
+        collect_sway_windows(child, workspace, windows);
+    }
+}
+
+/// A workspace of `swaymsg -t get_workspaces`
+fn sway_workspace(workspace: &Value) -> Option<Workspace> {
+    Some(Workspace {
+        id: workspace["num"].as_i64()? as i32,
+        name: workspace["name"].as_str().unwrap_or_default().to_string(),
+        active: workspace["focused"].as_bool().unwrap_or(false),
+    })
+}
+
+/// A `wmctrl -d` line such as
+/// `0  * DG: 1920x1080  VP: 0,0  WA: 0,0 1920x1050  Work`
+fn parse_wmctrl_desktop(line: &str) -> Option<Workspace> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() < 2 {
+        return None;
+    }
+    Some(Workspace {
+        id: parts[0].parse().ok()?,
+        name: parts
+            .get(9..)
+            .map(|name| name.join(" "))
+            .unwrap_or_default(),
+        active: parts[1] == "*",
+    })
+}
+
+/// A client of `hyprctl clients -j`
+fn hyprland_window(client: &Value) -> Option<WindowInfo> {
+    Some(WindowInfo {
+        id: client["address"].as_str()?.to_string(),
+        pid: client["pid"].as_u64().unwrap_or(0) as u32,
+        desktop: client["workspace"]["id"].as_i64().unwrap_or(0) as i32,
+        class: client["class"].as_str().unwrap_or_default().to_string(),
+        title: client["title"].as_str().unwrap_or_default().to_string(),
+        machine: String::from("localhost"),
+    })
+}
+
+fn parse_wmctrl_line(line: &str) -> Option<WindowInfo> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() < 5 {
+        return None;
+    }
+
+    let id = parts[0].to_string();
+    let desktop = parts[1].parse::<i32>().unwrap_or(-1);
+    let pid = parts[2].parse::<u32>().unwrap_or(0);
+    let class = parts[3].to_string();
+    let machine = parts[4].to_string();
+
+    // The title is the rest of the line after the first 5 parts
+    let title = if parts.len() > 5 {
+        parts[5..].join(" ")
+    } else {
+        String::new()
+    };
+
+    Some(WindowInfo {
+        id,
+        pid,
+        desktop,
+        class,
+        title,
+        machine,
+    })
+}
+
+/// Geometry from a `wmctrl -l -G` line: id, desktop, x, y, width, height, ...
+fn parse_wmctrl_geometry(line: &str, window_id: &str) -> Option<(i32, i32, i32, i32)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() < 6 || parts[0] != window_id {
+        return None;
+    }
+    let number = |i: usize| parts[i].parse::<i32>().ok();
+    Some((number(2)?, number(3)?, number(4)?, number(5)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_window_lists() {
+        let tree = r#"{"type":"root","nodes":[{"type":"output","nodes":[
+            {"type":"workspace","num":2,"nodes":[{"type":"con","nodes":[
+                {"type":"con","id":12,"pid":4021,"app_id":"foot","name":"~","focused":true,
+                 "rect":{"x":0,"y":30,"width":960,"height":1050}}]}],
+             "floating_nodes":[{"type":"floating_con","id":15,"pid":4100,"app_id":null,
+                 "window_properties":{"class":"Gimp"},"name":"GIMP","focused":false,
+                 "rect":{"x":200,"y":100,"width":800,"height":600}}]}]}]}"#;
+        let mut windows = Vec::new();
+        collect_sway_windows(&serde_json::from_str(tree).unwrap(), 0, &mut windows);
+        assert_eq!(windows.len(), 2);
+        assert_eq!(
+            (windows[0].info.id.as_str(), windows[0].info.desktop),
+            ("12", 2)
+        );
+        assert!(windows[0].focused);
+        assert_eq!(windows[1].info.class, "Gimp");
+        assert_eq!(windows[1].rect, (200, 100, 800, 600));
+
+        let line = "0x03e00003  1 2288   firefox.Firefox  laptop Mozilla Firefox";
+        let window = parse_wmctrl_line(line).unwrap();
+        assert_eq!(
+            (window.desktop, window.title.as_str()),
+            (1, "Mozilla Firefox")
+        );
+
+        let clients =
+            r#"[{"address":"0x5a1","pid":77,"class":"kitty","title":"vim","workspace":{"id":3}}]"#;
+        let clients: Vec<Value> = serde_json::from_str(clients).unwrap();
+        assert_eq!(hyprland_window(&clients[0]).unwrap().desktop, 3);
+
+        let info = r#"{"id":"{4f1e}","pid":90,"class":"org.kde.dolphin","title":"Home","desktop":1,"x":10.5,"y":0,"width":800,"height":600,"active":true}"#;
+        let window = kwin_window(&serde_json::from_str(info).unwrap()).unwrap();
+        assert_eq!(
+            (window.info.id.as_str(), window.info.desktop),
+            ("{4f1e}", 1)
+        );
+        assert_eq!(window.rect, (11, 0, 800, 600));
+        assert!(window.active);
+
+        assert_eq!(
+            kwin_result(r#"{"ok":[1,2]}"#).unwrap(),
+            serde_json::json!([1, 2])
+        );
+        assert_eq!(
+            kwin_result(r#"{"error":"No workspace 9"}"#).unwrap_err(),
+            "No workspace 9"
+        );
+        assert!(kwin_result("js: oops").is_err());
+    }
+
+    #[test]
+    fn test_window_ids_are_checked() {
+        assert_eq!(
+            hyprland_address("0x55d1c0a7b2c0").unwrap(),
+            "address:0x55d1c0a7b2c0"
+        );
+        for id in ["", "0x", "55d1", "0x1,exec rm", "0x12 "] {
+            assert!(hyprland_address(id).is_err(), "{:?}", id);
+        }
+        for id in ["", "12] exec rm -rf ~; [con_id=12", "-1"] {
+            assert!(
+                Sway.command(id, "focus", "focus window").is_err(),
+                "{:?}",
+                id
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_workspaces() {
+        let desktops = "0  * DG: 1920x1080  VP: 0,0  WA: 0,0 1920x1050  Main screen\n\
+                        1  - DG: 1920x1080  VP: N/A  WA: 0,0 1920x1050  2\n";
+        let workspaces: Vec<_> = desktops.lines().filter_map(parse_wmctrl_desktop).collect();
+        assert_eq!(
+            workspaces[0],
+            Workspace {
+                id: 0,
+                name: "Main screen".to_string(),
+                active: true
+            }
+        );
+        assert!(!workspaces[1].active);
+
+        let sway: Value =
+            serde_json::from_str(r#"{"num":3,"name":"3:web","focused":true}"#).unwrap();
+        assert_eq!(sway_workspace(&sway).unwrap().name, "3:web");
+    }
+}
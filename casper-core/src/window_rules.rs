@@ -0,0 +1,393 @@
+use crate::screen::display_size;
+use crate::trigger::TriggerLimits;
+use crate::window::{
+    WindowInfo, focus_window_by_id, maximize_window, move_resize_window, move_window_to_workspace,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which windows a rule applies to
+///
+/// Both patterns are case-insensitive substrings; a rule with neither
+/// pattern matches nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowMatch {
+    pub class: Option<String>,
+    pub title: Option<String>,
+}
+
+impl WindowMatch {
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        if self.class.is_none() && self.title.is_none() {
+            return false;
+        }
+        let contains = |haystack: &str, needle: &Option<String>| {
+            needle
+                .as_ref()
+                .is_none_or(|n| haystack.to_lowercase().contains(&n.to_lowercase()))
+        };
+        contains(&window.class, &self.class) && contains(&window.title, &self.title)
+    }
+}
+
+/// Where a matching window is placed on the screen
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    LeftHalf,
+    RightHalf,
+    TopHalf,
+    BottomHalf,
+    Maximized,
+    Geometry {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+}
+
+impl Placement {
+    /// Pixel geometry (x, y, width, height) on a screen of the given size
+    pub fn geometry(&self, screen_width: i32, screen_height: i32) -> Option<(i32, i32, i32, i32)> {
+        let (half_width, half_height) = (screen_width / 2, screen_height / 2);
+        match self {
+            Placement::LeftHalf => Some((0, 0, half_width, screen_height)),
+            Placement::RightHalf => Some((half_width, 0, half_width, screen_height)),
+            Placement::TopHalf => Some((0, 0, screen_width, half_height)),
+            Placement::BottomHalf => Some((0, half_height, screen_width, half_height)),
+            Placement::Maximized => None,
+            Placement::Geometry {
+                x,
+                y,
+                width,
+                height,
+            } => Some((*x, *y, *width, *height)),
+        }
+    }
+}
+
+/// A persistent rule applied to windows as they appear, e.g.
+///
+/// ```json
+/// { "name": "spotify", "match": { "class": "spotify" },
+///   "workspace": 3, "placement": "right_half", "no_focus": true }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowRule {
+    pub name: String,
+    #[serde(rename = "match")]
+    pub matcher: WindowMatch,
+    /// Workspace (desktop) to move the window to
+    #[serde(default)]
+    pub workspace: Option<i32>,
+    #[serde(default)]
+    pub placement: Option<Placement>,
+    /// Give focus back to the previously focused window
+    #[serde(default)]
+    pub no_focus: bool,
+    /// Debounce, throttle and cooldown for bursts of matching windows
+    #[serde(flatten)]
+    pub limits: TriggerLimits,
+}
+
+impl WindowRule {
+    /// Apply the rule to a window; `previous_focus` is the window focused before it appeared
+    pub fn apply(&self, window: &WindowInfo, previous_focus: Option<&str>) -> Result<(), String> {
+        if let Some(workspace) = self.workspace {
+            move_window_to_workspace(&window.id, workspace)?;
+        }
+        match &self.placement {
+            Some(Placement::Maximized) => maximize_window(&window.id)?,
+            Some(placement) => {
+                let (screen_width, screen_height) = display_size()?;
+                if let Some((x, y, width, height)) = placement.geometry(screen_width, screen_height)
+                {
+                    move_resize_window(&window.id, x, y, width, height)?;
+                }
+            }
+            None => {}
+        }
+        if self.no_focus
+            && let Some(previous) = previous_focus
+            && previous != window.id
+        {
+            focus_window_by_id(previous)?;
+        }
+        Ok(())
+    }
+}
+
+/// The saved window rules
+#[derive(Debug)]
+pub struct WindowRules {
+    path: PathBuf,
+    rules: Vec<WindowRule>,
+}
+
+impl WindowRules {
+    /// No rules, saved to `path` once one is added
+    pub fn new(path: &Path) -> Self {
+        WindowRules {
+            path: path.to_path_buf(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Load the rules at `path`, starting with none if there is no file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut rules = Self::new(path);
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            rules.rules = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid window rules {}: {}", path.display(), e))?;
+        }
+        Ok(rules)
+    }
+
+    pub fn rules(&self) -> &[WindowRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Add a rule, replacing any rule with the same name
+    pub fn add(&mut self, rule: WindowRule) -> Result<(), String> {
+        if rule.name.is_empty() {
+            return Err("Window rule needs a name".to_string());
+        }
+        if rule.matcher.class.is_none() && rule.matcher.title.is_none() {
+            return Err("Window rule needs a class or title to match".to_string());
+        }
+        self.rules.retain(|r| r.name != rule.name);
+        self.rules.push(rule);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        let before = self.rules.len();
+        self.rules.retain(|r| r.name != name);
+        if self.rules.len() == before {
+            return Err(format!("No window rule '{}'", name));
+        }
+        self.save()
+    }
+
+    /// Rules matching a window, in the order they were added
+    pub fn matching(&self, window: &WindowInfo) -> Vec<&WindowRule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matcher.matches(window))
+            .collect()
+    }
+
+    /// Whether any rule needs to know which window had focus
+    pub fn needs_focus_tracking(&self) -> bool {
+        self.rules.iter().any(|rule| rule.no_focus)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.rules)
+            .map_err(|e| format!("Failed to serialize window rules: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write window rules: {}", e))
+    }
+}
+
+/// A change between successive window lists
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum WindowEvent {
+    Created {
+        window: WindowInfo,
+    },
+    Closed {
+        window: WindowInfo,
+    },
+    /// `previous` is the id of the window that had focus before
+    Focused {
+        window: WindowInfo,
+        previous: Option<String>,
+    },
+    TitleChanged {
+        window: WindowInfo,
+        old_title: String,
+    },
+}
+
+impl WindowEvent {
+    /// Event names, as subscribers see them
+    pub const NAMES: &[&str] = &[
+        "window_created",
+        "window_closed",
+        "window_focused",
+        "window_title_changed",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindowEvent::Created { .. } => "window_created",
+            WindowEvent::Closed { .. } => "window_closed",
+            WindowEvent::Focused { .. } => "window_focused",
+            WindowEvent::TitleChanged { .. } => "window_title_changed",
+        }
+    }
+}
+
+/// Tells what changed between successive window lists
+#[derive(Debug, Default)]
+pub struct WindowWatcher {
+    known: Option<HashMap<String, WindowInfo>>,
+    focused: Option<String>,
+}
+
+impl WindowWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Windows not seen in the previous list; the first list only sets the baseline
+    pub fn new_windows(&mut self, windows: &[WindowInfo]) -> Vec<WindowInfo> {
+        self.changes(windows, None)
+            .into_iter()
+            .filter_map(|event| match event {
+                WindowEvent::Created { window } => Some(window),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Windows opened, closed and retitled since the previous list, and a
+    /// focus change when `active` (the focused window id) is given; the
+    /// first list only sets the baseline
+    pub fn changes(&mut self, windows: &[WindowInfo], active: Option<&str>) -> Vec<WindowEvent> {
+        let current: HashMap<String, WindowInfo> =
+            windows.iter().map(|w| (w.id.clone(), w.clone())).collect();
+        let mut events = Vec::new();
+        if let Some(known) = &self.known {
+            for window in windows {
+                match known.get(&window.id) {
+                    None => events.push(WindowEvent::Created {
+                        window: window.clone(),
+                    }),
+                    Some(old) if old.title != window.title => {
+                        events.push(WindowEvent::TitleChanged {
+                            window: window.clone(),
+                            old_title: old.title.clone(),
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+            for (id, window) in known {
+                if !current.contains_key(id) {
+                    events.push(WindowEvent::Closed {
+                        window: window.clone(),
+                    });
+                }
+            }
+            if let Some(active) = active
+                && self.focused.as_deref() != Some(active)
+                && let Some(window) = current.get(active)
+            {
+                events.push(WindowEvent::Focused {
+                    window: window.clone(),
+                    previous: self.focused.clone(),
+                });
+            }
+        }
+        if let Some(active) = active {
+            self.focused = Some(active.to_string());
+        }
+        self.known = Some(current);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(id: &str, class: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            id: id.into(),
+            pid: 0,
+            desktop: 0,
+            class: class.into(),
+            title: title.into(),
+            machine: "localhost".into(),
+        }
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let rule: WindowRule = serde_json::from_str(
+            r#"{ "name": "music", "match": { "class": "spotify" }, "workspace": 3,
+                 "placement": "right_half", "no_focus": true }"#,
+        )
+        .unwrap();
+        assert!(
+            rule.matcher
+                .matches(&window("0x1", "spotify.Spotify", "Daily Mix"))
+        );
+        assert!(
+            !rule
+                .matcher
+                .matches(&window("0x2", "firefox.Firefox", "Spotify - Web"))
+        );
+        assert!(!WindowMatch::default().matches(&window("0x3", "a", "b")));
+        assert_eq!(
+            rule.placement.unwrap().geometry(1920, 1080),
+            Some((960, 0, 960, 1080))
+        );
+    }
+
+    #[test]
+    fn test_watcher_reports_new_windows() {
+        let mut watcher = WindowWatcher::new();
+        assert!(watcher.new_windows(&[window("0x1", "a", "")]).is_empty());
+        let new = watcher.new_windows(&[window("0x1", "a", ""), window("0x2", "b", "")]);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].id, "0x2");
+        assert!(watcher.new_windows(&[window("0x2", "b", "")]).is_empty());
+    }
+
+    #[test]
+    fn test_watcher_reports_window_events() {
+        let mut watcher = WindowWatcher::new();
+        let editor = window("0x1", "gedit", "notes.txt");
+        assert!(
+            watcher
+                .changes(std::slice::from_ref(&editor), Some("0x1"))
+                .is_empty()
+        );
+
+        let dialog = window("0x2", "gedit", "Save As");
+        let events = watcher.changes(&[editor, dialog], Some("0x2"));
+        let names: Vec<_> = events.iter().map(|e| e.name()).collect();
+        assert_eq!(names, ["window_created", "window_focused"]);
+        let focused = serde_json::to_value(&events[1]).unwrap();
+        assert_eq!(focused["window"]["title"], "Save As");
+        assert_eq!(focused["previous"], "0x1");
+
+        let saved = window("0x1", "gedit", "report.txt");
+        let events = watcher.changes(&[saved], Some("0x1"));
+        let names: Vec<_> = events.iter().map(|e| e.name()).collect();
+        assert_eq!(
+            names,
+            ["window_title_changed", "window_closed", "window_focused"]
+        );
+        assert_eq!(
+            serde_json::to_value(&events[0]).unwrap()["old_title"],
+            "notes.txt"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! The built-in example packs, installed into a scratch library and checked
+//! the way the daemon would use them
+
+use casper_core::actions::ActionLibrary;
+use casper_core::dry_run::dry_run;
+use casper_core::executor::{PlaybackOptions, SequenceLookup};
+use casper_core::packs::{builtin_packs, find_pack};
+use casper_core::pipeline::{Pipelines, Variables};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("casper_packs_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn builtin_packs_are_valid() {
+    let packs = builtin_packs();
+    let names: Vec<&str> = packs.iter().map(|p| p.name.as_str()).collect();
+    for expected in [
+        "morning_workspace",
+        "meeting_mode",
+        "screen_text_to_clipboard",
+        "click_by_description",
+    ] {
+        assert!(names.contains(&expected), "missing pack {}", expected);
+        assert_eq!(find_pack(expected).unwrap().name, expected);
+    }
+    for pack in &packs {
+        pack.validate().unwrap();
+        assert!(!pack.description.is_empty());
+    }
+    assert!(find_pack("no_such_pack").is_err());
+}
+
+#[test]
+fn installed_packs_survive_a_reload() {
+    let dir = scratch_dir("install");
+    let library_path = dir.join("actions");
+    let pipelines_path = dir.join("pipelines.json");
+    let mut library = ActionLibrary::new(library_path.to_string_lossy().to_string());
+    let mut pipelines = Pipelines::new(&pipelines_path);
+    for pack in builtin_packs() {
+        assert!(!pack.is_installed(&library, &pipelines));
+        pack.install(&mut library, &mut pipelines).unwrap();
+        assert!(pack.is_installed(&library, &pipelines));
+    }
+
+    let mut reloaded = ActionLibrary::new(library_path.to_string_lossy().to_string());
+    reloaded.load_all().unwrap();
+    let pipelines = Pipelines::load(&pipelines_path).unwrap();
+    for pack in builtin_packs() {
+        assert!(pack.is_installed(&reloaded, &pipelines), "{}", pack.name);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pack_sequences_dry_run() {
+    let lookup: SequenceLookup = Arc::new(|name| Err(format!("No sequence '{}'", name)));
+    for pack in builtin_packs() {
+        for sequence in &pack.sequences {
+            let plan = dry_run(
+                sequence,
+                None,
+                None,
+                PlaybackOptions::default(),
+                &lookup,
+                None,
+            )
+            .unwrap_or_else(|e| panic!("{}: {}", sequence.name, e));
+            assert_eq!(plan.steps.len(), sequence.actions.len());
+        }
+    }
+}
+
+#[test]
+fn pack_pipelines_check_their_inputs() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let pack = find_pack("click_by_description").unwrap();
+    let pipeline = &pack.pipelines[0];
+    assert_eq!(
+        runtime.block_on(pipeline.run(Variables::new(), false)),
+        Err("Missing input 'target'".to_string())
+    );
+    let mut inputs = Variables::new();
+    inputs.insert("target".to_string(), "the Save button".to_string());
+    // Nothing is captured while capture is off, so the run stops at the screenshot
+    let error = runtime.block_on(pipeline.run(inputs, false)).unwrap_err();
+    assert!(error.starts_with("Step 1:"), "{}", error);
+}
@@ -1,60 +1,618 @@
-use casper_core::actions::{Action, ActionLibrary, ActionPlayer, ActionRecorder};
+mod protocol;
+
+use casper_core::actions::{
+    Action, ActionLibrary, ActionPlayer, ActionSequence, ActionWithTimestamp, Anchor, Concurrency,
+    DEFAULT_WAIT_TIMEOUT_MS, ErrorPolicy, PlaybackState, Point, RecordingSessions,
+    ScreenChangeWait, StepRef,
+};
 use casper_core::ai::process_command;
-use casper_core::commands::run_command;
+use casper_core::ai_vision::AIVision;
+use casper_core::assertions::reference_path;
+use casper_core::capture::{
+    CaptureOptions, FrameSource, ImageFormat, ScreenCapture, capture_monitor_temp,
+    capture_screen_small, capture_screen_temp, monitor_source, pixel_color, window_thumbnail,
+};
+use casper_core::clipboard::{get_clipboard, paste_text, set_clipboard};
+use casper_core::commands::{
+    OutputStream, run_command, run_command_streaming, spawn_blocking_bounded, with_deadline,
+    with_deadline_blocking,
+};
+use casper_core::config::DaemonConfig;
 use casper_core::connections::connect_to_service;
+use casper_core::debugger::StepSession;
+use casper_core::displays::{Display, DisplayRef, display_at, find_display, list_displays};
+use casper_core::dry_run::dry_run;
+use casper_core::environment::EnvironmentSnapshot;
+use casper_core::executor::{
+    PlaybackOptions, PlaybackProgress, SequenceLookup, SharedPlayer, spawn_playback,
+};
+use casper_core::features::{FEATURES, feature_status};
+use casper_core::gestures;
+use casper_core::hotkeys::{Hotkey, HotkeyListener, PressHandler};
+use casper_core::image_diff::{compare_images, wait_for_screen_change};
+use casper_core::input_block::{BlockEnd, DEFAULT_PANIC_KEY, InputBlock, PanicChord};
+use casper_core::input_recorder::InputRecorder;
+use casper_core::journal::{self, RecordingJournal, RecoveredRecording};
+use casper_core::keyboard_layout::{detect_layout, type_text_on_layout};
+use casper_core::launcher::search_applications;
+use casper_core::layouts::{Layout, Layouts};
+use casper_core::locate::{
+    DEFAULT_CONFIDENCE, DEFAULT_MAX_SCROLLS, click_in_window, locate_image_on_screen,
+    resolve_point, scroll_to_image,
+};
 use casper_core::mcp::process_mcp;
-use casper_core::notifications::show_notification;
+use casper_core::metrics::Metrics;
+use casper_core::notifications::{NotificationOptions, show_notification_with};
+use casper_core::packs::{builtin_packs, find_pack};
+use casper_core::paths;
+use casper_core::pipeline::{Pipeline, Pipelines, Variables};
+use casper_core::portal;
+use casper_core::processes::{get_process_info, kill_processes, wait_for_process_exit};
+use casper_core::runs::{RunHistory, RunOutcome, RunSummary, RunTracker};
 use casper_core::screen::{
-    click_mouse, get_mouse_position, key_down, key_up, mouse_down, mouse_up, move_mouse, press_key,
-    scroll, type_text,
+    click_at, click_times, drag, get_mouse_position, input_backend, key_down, key_names, key_up,
+    mouse_down, mouse_up, move_mouse, move_mouse_relative, press_key, press_key_combo,
+    release_held, scroll, scroll_pages, scroll_smoothly, set_input_backend,
 };
-use casper_core::tts::speak;
+use casper_core::screenshots::{self, cleanup_screenshots};
+use casper_core::sensors::{self, Sensor, SensorRegistry, watch_screen};
+use casper_core::shortcuts::{Shortcut, ShortcutTable, send_app_action};
+use casper_core::store::{self, KvStore, SharedStore};
+use casper_core::trigger::TriggerGate;
+use casper_core::tts::{speak, speak_blocking, split_sentences};
+use casper_core::usage::UsageStats;
+use casper_core::vault;
 use casper_core::voice::recognize_voice;
 use casper_core::window::{
-    close_window, find_window_by_pattern, focus_window, is_application_visible, is_process_running,
-    launch_application, list_windows, maximize_window, minimize_window, move_resize_window,
-    open_or_focus_application,
+    BulkWindowOp, SnapPosition, WindowField, WindowInfo, WindowQuery, WindowState,
+    active_window_class, active_window_id, bulk_window_op, close_window, find_window_by_pattern,
+    find_windows, focus_window, focus_window_by_id, get_active_window, is_application_visible,
+    is_process_running, launch_application_with, list_windows, list_workspaces, lower_window,
+    maximize_window, minimize_window, move_resize_window, move_window_to_workspace,
+    open_or_focus_application, raise_window, set_window_state, snap_window, switch_workspace,
+    uncovered_windows, wait_for_window, window_backend,
 };
-use serde_json::json;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use casper_core::window_rules::{WindowEvent, WindowRule, WindowRules, WindowWatcher};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, VecDeque};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixListener;
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc};
+
+/// Events buffered per subscriber before slow readers start missing some
+const EVENT_BUFFER: usize = 256;
+
+/// How often the window list is checked for new windows while window rules exist
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often it is checked while a subscriber waits for window events
+const WINDOW_EVENT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Time between the frames of a capture stream unless a request gives one
+const DEFAULT_CAPTURE_STREAM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest time between the frames of a capture stream
+const MIN_CAPTURE_STREAM_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often temporary sequences are checked for having expired
+const SEQUENCE_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest time between two `stream_screen` frames
+const MIN_FRAME_INTERVAL_MS: u64 = 100;
+
+/// Shortest time between two `watch_mouse` positions
+const MIN_MOUSE_INTERVAL_MS: u64 = 10;
+
+/// Request types whose failures can carry a screenshot of the screen
+const ERROR_CAPTURE_REQUESTS: &[&str] = &["run_command", "play_sequence"];
+
+/// Width of the screenshots recorded with each step
+const RECORDING_SCREENSHOT_WIDTH: u32 = 640;
+
+/// Width of the window thumbnails in `list_windows` unless a request gives one
+const DEFAULT_THUMBNAIL_WIDTH: u32 = 160;
+
+/// How long a failure waits for the AI to describe the screen
+const ERROR_DESCRIPTION_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Longest the real keyboard and mouse stay blocked without a timeout given
+const INPUT_BLOCK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Longest a timed `pause_automations` may last, a week
+const MAX_PAUSE_MINUTES: f64 = 7.0 * 24.0 * 60.0;
 
 struct DaemonState {
-    recorder: ActionRecorder,
-    player: ActionPlayer,
+    recordings: RecordingSessions,
+    /// Recordings of the real keyboard and mouse, by session ID
+    input_recorders: BTreeMap<String, InputRecorder>,
+    /// Keeps the real keyboard and mouse away from the desktop, from block_input
+    input_block: Option<InputBlock>,
+    /// Where recordings taking a screenshot at every step keep them until stopped
+    recording_screenshots: BTreeMap<String, PathBuf>,
+    /// Recordings a previous daemon left unfinished, waiting to be recovered or discarded
+    interrupted: Vec<RecoveredRecording>,
+    /// Shared with the background task playing the loaded sequence
+    player: SharedPlayer,
+    /// Playbacks waiting for the current one to end
+    playback_queue: VecDeque<QueuedPlayback>,
+    /// A sequence being played one step at a time
+    step_session: Option<StepSession>,
     library: ActionLibrary,
+    profile: Option<String>,
+    events: broadcast::Sender<Value>,
+    /// Subscribers that asked for window events by name
+    window_event_subscribers: usize,
+    /// Capture streams publishing `screen_frame` events, by stream ID
+    capture_streams: BTreeMap<String, CaptureStream>,
+    next_capture_stream: u64,
+    modes: Modes,
+    sensors: SensorRegistry,
+    config: DaemonConfig,
+    /// Present only when the user opted in to usage statistics
+    usage: Option<UsageStats>,
+    metrics: Metrics,
+    /// Key/value store clients use to coordinate
+    store: KvStore,
+    window_rules: WindowRules,
+    /// Saved window arrangements
+    layouts: Layouts,
+    pipelines: Pipelines,
+    /// Grabs the hotkeys in `config.hotkeys`, if there are any
+    hotkey_listener: Option<HotkeyListener>,
+    /// Where the listener sends the hotkeys that were pressed
+    hotkey_presses: mpsc::UnboundedSender<String>,
+    /// When a timed pause_automations ends by itself
+    resume_automations_at: Option<Instant>,
+}
+
+/// What pressing a bound hotkey does
+enum HotkeyBinding {
+    Sequence(String),
+    ToggleAutomations,
+    AbortAll,
+}
+
+/// A playback request waiting for its turn
+struct QueuedPlayback {
+    sequence: ActionSequence,
+    start: Option<StepRef>,
+    end: Option<StepRef>,
+    options: PlaybackOptions,
+    concurrency: Concurrency,
+    /// Overrides `error_capture.screenshot` of the config for this playback
+    capture_on_error: Option<bool>,
+    /// Block the real keyboard and mouse while it plays, until this panic key
+    block_input: Option<PanicChord>,
 }
 
+/// A screen capture running in the background for `screen_frame` subscribers
+struct CaptureStream {
+    interval_ms: u64,
+    /// Frames published so far
+    frames: u64,
+    task: tokio::task::AbortHandle,
+}
+
+/// User-controlled switches limiting what the daemon may do
+#[derive(Debug, Default, Clone, Copy)]
+struct Modes {
+    /// Refuse to capture the screen
+    privacy: bool,
+    /// Suppress notifications and speech
+    do_not_disturb: bool,
+    /// Hold window rules, hotkeys and queued playbacks; requests still work
+    automations_paused: bool,
+}
+
+/// Tag marking sequences shown in quick-access menus
+const FAVORITE_TAG: &str = "favorite";
+
 impl DaemonState {
-    fn new() -> Self {
-        let home_dir = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-        let library_path = format!("{}/.casper/actions", home_dir);
+    fn new(options: &DaemonOptions, hotkey_presses: mpsc::UnboundedSender<String>) -> Self {
+        let config = DaemonConfig::load(options.profile.as_deref()).unwrap_or_else(|e| {
+            eprintln!("⚠️  {}; using default settings", e);
+            DaemonConfig::default()
+        });
+
+        let encryption = &config.encryption;
+        if encryption.enabled {
+            let salt = paths::salt_file(options.profile.as_deref());
+            let library = paths::actions_dir(options.profile.as_deref());
+            // Saving secrets unencrypted, or with the wrong key, is worse than not starting
+            if let Err(e) = vault::unlock(encryption.key_source, &salt, &library) {
+                eprintln!(
+                    "❌ Library encryption is on, but the key is not available: {}",
+                    e
+                );
+                std::process::exit(1);
+            }
+        }
+
+        screenshots::set_store(options.profile.as_deref(), config.screenshots.clone());
+
+        if let Some(backend) = &config.input_backend
+            && let Err(e) = set_input_backend(backend)
+        {
+            eprintln!("⚠️  {}; detecting the input backend", e);
+        }
 
-        let mut library = ActionLibrary::new(library_path);
+        let library_path = paths::actions_dir(options.profile.as_deref());
+        let mut library = ActionLibrary::new(library_path.to_string_lossy().to_string());
         let _ = library.load_all(); // Load existing sequences
+        if vault::is_unlocked() {
+            // Encrypt sequences saved before encryption was turned on
+            if let Err(e) = library.save_all() {
+                eprintln!("⚠️  Could not encrypt the library: {}", e);
+            }
+        }
+
+        let journal_dir = paths::tmp_dir(options.profile.as_deref());
+        let store_path = paths::store_file(options.profile.as_deref());
+        let store = KvStore::load(&store_path).unwrap_or_else(|e| {
+            eprintln!("⚠️  {}; starting with an empty store", e);
+            KvStore::new(&store_path)
+        });
+
+        let rules_path = paths::window_rules_file(options.profile.as_deref());
+        let window_rules = WindowRules::load(&rules_path).unwrap_or_else(|e| {
+            eprintln!("⚠️  {}; starting without window rules", e);
+            WindowRules::new(&rules_path)
+        });
+
+        let layouts_path = paths::layouts_file(options.profile.as_deref());
+        let layouts = Layouts::load(&layouts_path).unwrap_or_else(|e| {
+            eprintln!("⚠️  {}; starting without layouts", e);
+            Layouts::new(&layouts_path)
+        });
+        let pipelines_path = paths::pipelines_file(options.profile.as_deref());
+        let pipelines = Pipelines::load(&pipelines_path).unwrap_or_else(|e| {
+            eprintln!("⚠️  {}; starting without pipelines", e);
+            Pipelines::new(&pipelines_path)
+        });
 
         DaemonState {
-            recorder: ActionRecorder::new(),
-            player: ActionPlayer::new(),
+            interrupted: journal::find_interrupted(&journal_dir),
+            recordings: RecordingSessions::new().with_journal_dir(journal_dir),
+            input_recorders: BTreeMap::new(),
+            input_block: None,
+            recording_screenshots: BTreeMap::new(),
+            player: Arc::new(Mutex::new(ActionPlayer::new())),
+            playback_queue: VecDeque::new(),
             library,
+            profile: options.profile.clone(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            window_event_subscribers: 0,
+            capture_streams: BTreeMap::new(),
+            next_capture_stream: 0,
+            modes: Modes::default(),
+            sensors: SensorRegistry::new(),
+            usage: config
+                .usage
+                .enabled
+                .then(|| UsageStats::load(&paths::usage_file(options.profile.as_deref()))),
+            config,
+            metrics: Metrics::new(),
+            store,
+            window_rules,
+            layouts,
+            pipelines,
+            hotkey_listener: None,
+            hotkey_presses,
+            resume_automations_at: None,
+            step_session: None,
+        }
+    }
+
+    /// Grab the hotkeys in the config again, releasing the previous ones
+    fn restart_hotkeys(&mut self) -> Result<(), String> {
+        self.hotkey_listener = None;
+        let hotkeys = self
+            .config
+            .hotkeys
+            .keys()
+            .chain(&self.config.pause_hotkey)
+            .map(|hotkey| Hotkey::parse(hotkey))
+            .collect::<Result<Vec<_>, _>>()?;
+        let abort = self.config.abort_hotkey().map(Hotkey::parse).transpose()?;
+        let presses = self.hotkey_presses.clone();
+        let on_press: PressHandler = Arc::new(move |hotkey: &str| {
+            let _ = presses.send(hotkey.to_string());
+        });
+        if let Some(abort) = abort {
+            let mut all = hotkeys.clone();
+            all.push(abort.clone());
+            match HotkeyListener::start(all, Arc::clone(&on_press)) {
+                Ok(listener) => {
+                    self.hotkey_listener = Some(listener);
+                    return Ok(());
+                }
+                // The abort hotkey being taken must not cost the bound ones
+                Err(e) => eprintln!("⚠️  Abort hotkey {} unavailable: {}", abort, e),
+            }
+        }
+        if hotkeys.is_empty() {
+            return Ok(());
+        }
+        self.hotkey_listener = Some(HotkeyListener::start(hotkeys, on_press)?);
+        Ok(())
+    }
+
+    /// What `hotkey` is bound to, also when configured as e.g. "alt+ctrl+1" for "ctrl+alt+1"
+    fn hotkey_binding(&self, hotkey: &Hotkey) -> Option<HotkeyBinding> {
+        let same = |configured: &String| Hotkey::parse(configured).is_ok_and(|h| h == *hotkey);
+        if self.is_abort_hotkey(hotkey) {
+            return Some(HotkeyBinding::AbortAll);
+        }
+        if self.config.pause_hotkey.as_ref().is_some_and(same) {
+            return Some(HotkeyBinding::ToggleAutomations);
+        }
+        self.config
+            .hotkeys
+            .iter()
+            .find(|(configured, _)| same(configured))
+            .map(|(_, sequence)| HotkeyBinding::Sequence(sequence.clone()))
+    }
+
+    fn is_abort_hotkey(&self, hotkey: &Hotkey) -> bool {
+        self.config
+            .abort_hotkey()
+            .is_some_and(|abort| Hotkey::parse(abort).is_ok_and(|h| h == *hotkey))
+    }
+
+    /// Forget whatever `hotkey` is bound to; returns whether it was bound
+    fn unbind_hotkey(&mut self, hotkey: &Hotkey) -> bool {
+        let same = |configured: &String| Hotkey::parse(configured).is_ok_and(|h| h == *hotkey);
+        let count = self.config.hotkeys.len() + self.config.pause_hotkey.iter().count();
+        self.config
+            .hotkeys
+            .retain(|configured, _| !same(configured));
+        if self.config.pause_hotkey.as_ref().is_some_and(same) {
+            self.config.pause_hotkey = None;
+        }
+        count != self.config.hotkeys.len() + self.config.pause_hotkey.iter().count()
+    }
+
+    /// Whether a failure is documented with a screenshot; a request's
+    /// `capture_on_error` wins over the config
+    fn wants_error_capture(&self, requested: Option<bool>) -> bool {
+        requested.unwrap_or(self.config.error_capture.screenshot) && !self.modes.privacy
+    }
+
+    /// Publish an event to every subscribed client
+    fn emit(&self, event: &str, data: Value) {
+        emit_event(&self.events, event, data);
+    }
+
+    /// Count a handled request in the metrics and the opt-in usage statistics
+    fn record_request(&mut self, request_type: &str, response: &Value, elapsed: Duration) {
+        // Only names from the protocol table are stored, never client-provided text
+        let feature = if protocol::REQUESTS.iter().any(|r| r.name == request_type) {
+            request_type
+        } else {
+            "unknown"
+        };
+        let success = response["status"] != "error";
+        self.metrics.record_request(feature, success, elapsed);
+        if let Some(usage) = self.usage.as_mut() {
+            usage.record(feature, success);
+        }
+    }
+
+    /// Turn a sensor on or off and tell subscribers (the tray shows these)
+    fn set_sensor(&mut self, sensor: Sensor, detail: &str, active: bool) {
+        let changed = if active {
+            self.sensors.start(sensor, detail)
+        } else {
+            self.sensors.stop(sensor, detail)
+        };
+        if changed {
+            self.emit(
+                "sensors_changed",
+                json!({ "active_sensors": self.sensors.active() }),
+            );
+        }
+    }
+
+    /// Forget a capture stream, turning its sensor off
+    fn remove_capture_stream(&mut self, stream_id: &str) -> Option<CaptureStream> {
+        let stream = self.capture_streams.remove(stream_id)?;
+        self.set_sensor(Sensor::ScreenWatching, stream_id, false);
+        Some(stream)
+    }
+}
+
+/// Keeps a sensor on until it is dropped
+struct SensorGuard {
+    state: Arc<Mutex<DaemonState>>,
+    sensor: Sensor,
+    detail: String,
+}
+
+impl SensorGuard {
+    fn start(state: &Arc<Mutex<DaemonState>>, sensor: Sensor, detail: String) -> Self {
+        state.lock().unwrap().set_sensor(sensor, &detail, true);
+        SensorGuard {
+            state: Arc::clone(state),
+            sensor,
+            detail,
+        }
+    }
+}
+
+impl Drop for SensorGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.set_sensor(self.sensor, &self.detail, false);
+        }
+    }
+}
+
+/// Publish an event on a channel, for code that runs without the state lock
+fn emit_event(events: &broadcast::Sender<Value>, event: &str, data: Value) {
+    let mut message = json!({ "event": event });
+    if let (Some(message), Value::Object(data)) = (message.as_object_mut(), data) {
+        message.extend(data);
+    }
+    // Sending only fails when nobody is subscribed
+    let _ = events.send(message);
+}
+
+/// Command-line options selecting which daemon instance to run
+struct DaemonOptions {
+    socket_path: PathBuf,
+    profile: Option<String>,
+    /// Capture and input only through xdg-desktop-portal
+    portals: bool,
+}
+
+impl DaemonOptions {
+    fn from_args() -> Result<Self, String> {
+        let mut socket_path = None;
+        let mut profile = None;
+        let mut portals = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--socket" => {
+                    let value = args.next().ok_or("--socket requires a path")?;
+                    socket_path = Some(PathBuf::from(value));
+                }
+                "--profile" => {
+                    let value = args.next().ok_or("--profile requires a name")?;
+                    paths::validate_profile_name(&value)?;
+                    profile = Some(value);
+                }
+                "--portals" => portals = true,
+                "--help" | "-h" => {
+                    println!(
+                        "Usage: casper-daemon [--socket <path>] [--profile <name>] [--portals]"
+                    );
+                    println!();
+                    println!(
+                        "  --socket <path>   Unix socket to listen on (default: $XDG_RUNTIME_DIR/casper.sock)"
+                    );
+                    println!("  --profile <name>  Run an isolated instance with its own library");
+                    println!(
+                        "  --portals         Capture and send input only through desktop portals (default inside Flatpak)"
+                    );
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
         }
+
+        let socket_path = socket_path.unwrap_or_else(|| paths::socket_path(profile.as_deref()));
+        Ok(DaemonOptions {
+            socket_path,
+            profile,
+            portals,
+        })
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let socket_path = Path::new("/tmp/casper.sock");
+    let options = DaemonOptions::from_args()?;
+
+    // Data from before the XDG layout is moved before anything reads it
+    match paths::migrate_legacy_dirs() {
+        Ok(migration) => {
+            for line in migration.moved {
+                println!("📦 Moved {}", line);
+            }
+            for path in migration.skipped {
+                eprintln!(
+                    "⚠️  Left {} in place; the new location already has one",
+                    path.display()
+                );
+            }
+        }
+        Err(e) => eprintln!("⚠️  Could not move ~/.casper to the XDG directories: {}", e),
+    }
+
+    if options.portals {
+        portal::set_portal_only(true);
+    }
+
+    let socket_path = options.socket_path.as_path();
     if socket_path.exists() {
         std::fs::remove_file(socket_path)?;
     }
     let listener = UnixListener::bind(socket_path)?;
+    // Only the owning user may talk to this instance
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
 
-    let state = Arc::new(Mutex::new(DaemonState::new()));
+    let (hotkey_presses, pressed_hotkeys) = mpsc::unbounded_channel();
+    let state = Arc::new(Mutex::new(DaemonState::new(&options, hotkey_presses)));
+    // Playback reading the screen shows up with the other sensors
+    let observed = Arc::downgrade(&state);
+    sensors::set_observer(Box::new(move |sensor, detail, active| {
+        if let Some(state) = observed.upgrade() {
+            state.lock().unwrap().set_sensor(sensor, detail, active);
+        }
+    }));
+    store::set_shared(Box::new(PlaybackStore(Arc::downgrade(&state))));
 
     println!("🤖 Casper Daemon v0.2.0 listening on {:?}", socket_path);
-    println!("📝 Action library: ~/.casper/actions");
+    if let Some(ref profile) = options.profile {
+        println!("👤 Profile: {}", profile);
+    }
+    if portal::portal_only() {
+        println!("🔒 Capture and input go through desktop portals only");
+    }
+    let left_out: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, compiled)| !compiled)
+        .map(|(name, _)| *name)
+        .collect();
+    if !left_out.is_empty() {
+        println!("🧩 Built without: {}", left_out.join(", "));
+    }
+    println!(
+        "📝 Action library: {}",
+        paths::actions_dir(options.profile.as_deref()).display()
+    );
+    {
+        let state = state.lock().unwrap();
+        if !state.interrupted.is_empty() {
+            println!(
+                "♻️  {} interrupted recording(s) can be recovered:",
+                state.interrupted.len()
+            );
+            for recording in &state.interrupted {
+                println!(
+                    "   {} \"{}\" ({} steps) - send recover_recording or discard_recording",
+                    recording.id,
+                    recording.sequence.name,
+                    recording.sequence.actions.len()
+                );
+            }
+        }
+    }
+    let metrics_listen = state.lock().unwrap().config.metrics.listen.clone();
+    if let Some(addr) = metrics_listen {
+        let listener = TcpListener::bind(&addr).await?;
+        println!("📈 Metrics: http://{}/metrics", addr);
+        tokio::spawn(serve_metrics(listener, Arc::clone(&state)));
+    }
+    tokio::spawn(watch_windows(Arc::clone(&state)));
+    tokio::spawn(expire_sequences(Arc::clone(&state)));
+    {
+        let mut state = state.lock().unwrap();
+        match state.restart_hotkeys() {
+            Ok(()) if !state.config.hotkeys.is_empty() => {
+                println!("⌨️  {} global hotkey(s)", state.config.hotkeys.len())
+            }
+            Ok(()) => {}
+            Err(e) => eprintln!("⚠️  Global hotkeys unavailable: {}", e),
+        }
+    }
+    tokio::spawn(play_hotkeys(Arc::clone(&state), pressed_hotkeys));
     println!("✨ Ready to assist!");
 
     loop {
@@ -62,9 +620,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let state_clone = Arc::clone(&state);
 
         tokio::spawn(async move {
-            let mut buf = vec![0; 4096]; // Increased buffer size for larger payloads
-            let n = socket.read(&mut buf).await.unwrap_or(0);
-            let request = String::from_utf8_lossy(&buf[..n]);
+            let max_bytes = state_clone.lock().unwrap().config.requests.max_bytes();
+            let request = match protocol::read_request(&mut socket, max_bytes).await {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => {
+                    let response = json!({ "status": "error", "message": e });
+                    let _ = socket.write_all(response.to_string().as_bytes()).await;
+                    return;
+                }
+            };
 
             let req: serde_json::Value = match serde_json::from_str(&request) {
                 Ok(v) => v,
@@ -78,396 +642,4366 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             };
 
-            let response = handle_request(&req, &state_clone).await;
-            let response_str = response.to_string();
-            let _ = socket.write_all(response_str.as_bytes()).await;
+            if req["type"] == "subscribe" {
+                let _ = handle_subscribe(&req, &state_clone, &mut socket).await;
+                return;
+            }
+            if req["type"] == "store_watch" {
+                let _ = handle_store_watch(&req, &state_clone, &mut socket).await;
+                return;
+            }
+            if req["type"] == "stream_screen" {
+                let _ = handle_stream_screen(&req, &state_clone, &mut socket).await;
+                return;
+            }
+            if req["type"] == "watch_mouse" {
+                let _ = handle_watch_mouse(&req, &mut socket).await;
+                return;
+            }
+
+            if req["stream"].as_bool().unwrap_or(false) {
+                let _ = handle_stream_request(&req, &state_clone, &mut socket).await;
+                return;
+            }
+
+            let started = Instant::now();
+            let mut response = handle_with_timeout(&req, &state_clone).await;
+            attach_error_context(&req, &state_clone, &mut response).await;
+            paginate_response(&req, &state_clone, &mut response);
+            let max_bytes = {
+                let mut state = state_clone.lock().unwrap();
+                state.record_request(
+                    req["type"].as_str().unwrap_or(""),
+                    &response,
+                    started.elapsed(),
+                );
+                req["max_bytes"]
+                    .as_u64()
+                    .map(|n| n as usize)
+                    .or(state.config.responses.max_bytes)
+            };
+            let _ = write_response(&mut socket, &response, max_bytes).await;
         });
     }
 }
 
-async fn handle_request(
-    req: &serde_json::Value,
-    state: &Arc<Mutex<DaemonState>>,
-) -> serde_json::Value {
-    match req["type"].as_str() {
-        // Basic Commands
-        Some("run_command") => {
-            let cmd = req["command"].as_str().unwrap_or("");
-            match run_command(cmd) {
-                Ok(output) => json!({ "status": "success", "output": output }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
+/// Apply window rules to windows as they appear and publish window events
+///
+/// Idle while there are no rules and nobody subscribed to window events, so
+/// nothing is polled unless the user asked for it.
+async fn watch_windows(state: Arc<Mutex<DaemonState>>) {
+    let mut watcher = WindowWatcher::new();
+    let mut gates: BTreeMap<String, TriggerGate<WindowInfo>> = BTreeMap::new();
+    let mut focused: Option<String> = None;
+    let mut interval = WINDOW_POLL_INTERVAL;
+    loop {
+        tokio::time::sleep(interval).await;
+        let (rules, track_focus, paused, subscribed) = {
+            let state = state.lock().unwrap();
+            let rules = state.window_rules.rules().to_vec();
+            let paused = state.modes.automations_paused;
+            let subscribed = state.window_event_subscribers > 0;
+            (
+                rules,
+                state.window_rules.needs_focus_tracking() || subscribed,
+                paused,
+                subscribed,
+            )
+        };
+        interval = if subscribed {
+            WINDOW_EVENT_POLL_INTERVAL
+        } else {
+            WINDOW_POLL_INTERVAL
+        };
+        if rules.is_empty() && !subscribed {
+            watcher = WindowWatcher::new();
+            gates.clear();
+            continue;
         }
 
-        // Screen Control - Mouse
-        Some("move_mouse") => {
-            let x = req["x"].as_i64().unwrap_or(0) as i32;
-            let y = req["y"].as_i64().unwrap_or(0) as i32;
-            match move_mouse(x, y) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
-        }
-        Some("click_mouse") => {
-            let button = req["button"].as_str().unwrap_or("left");
-            match click_mouse(button) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+        let polled = spawn_blocking_bounded(move || {
+            let windows = list_windows()?;
+            let active = if track_focus {
+                active_window_id().ok()
+            } else {
+                None
+            };
+            Ok::<_, String>((windows, active))
+        })
+        .await;
+        let Ok(Ok((windows, active))) = polled else {
+            continue;
+        };
+
+        let changes = watcher.changes(&windows, active.as_deref());
+        if subscribed {
+            let state = state.lock().unwrap();
+            for change in &changes {
+                state.emit(change.name(), json!(change));
             }
         }
-        Some("mouse_down") => {
-            let button = req["button"].as_str().unwrap_or("left");
-            match mouse_down(button) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+        let new_windows: Vec<WindowInfo> = changes
+            .into_iter()
+            .filter_map(|change| match change {
+                WindowEvent::Created { window } => Some(window),
+                _ => None,
+            })
+            .collect();
+        // Windows that open during a pause are never handled, not even after it
+        let rules = if paused { Vec::new() } else { rules };
+        gates.retain(|name, _| rules.iter().any(|rule| &rule.name == name));
+        let now = Instant::now();
+        let mut fire = Vec::new();
+        for rule in &rules {
+            let gate = gates
+                .entry(rule.name.clone())
+                .or_insert_with(|| TriggerGate::new(rule.limits));
+            if gate.limits() != rule.limits {
+                *gate = TriggerGate::new(rule.limits);
             }
-        }
-        Some("mouse_up") => {
-            let button = req["button"].as_str().unwrap_or("left");
-            match mouse_up(button) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+            for window in new_windows.iter().filter(|w| rule.matcher.matches(w)) {
+                if let Some(window) = gate.offer(now, window.clone()) {
+                    fire.push((rule.clone(), window));
+                }
             }
-        }
-        Some("scroll") => {
-            let amount = req["amount"].as_i64().unwrap_or(1) as i32;
-            let direction = req["direction"].as_str().unwrap_or("up");
-            match scroll(amount, direction) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+            if let Some(window) = gate.due(now) {
+                fire.push((rule.clone(), window));
             }
         }
-        Some("get_mouse_position") => match get_mouse_position() {
-            Ok((x, y)) => json!({ "status": "success", "x": x, "y": y }),
-            Err(e) => json!({ "status": "error", "message": e }),
-        },
 
-        // Screen Control - Keyboard
-        Some("type_text") => {
-            let text = req["text"].as_str().unwrap_or("");
-            match type_text(text) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
-        }
-        Some("press_key") => {
-            let key = req["key"].as_str().unwrap_or("");
-            match press_key(key) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
-        }
-        Some("key_down") => {
-            let key = req["key"].as_str().unwrap_or("");
-            match key_down(key) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+        for (rule, window) in fire {
+            let previous = focused.clone();
+            let result = spawn_blocking_bounded(move || {
+                rule.apply(&window, previous.as_deref())
+                    .map(|_| (rule.name, window))
+            })
+            .await;
+            let state = state.lock().unwrap();
+            match result {
+                Ok(Ok((name, window))) => {
+                    if let Some(gate) = gates.get_mut(&name) {
+                        gate.finished(Instant::now());
+                    }
+                    state.emit(
+                        "window_rule_applied",
+                        json!({ "rule": name, "window": window.id, "class": window.class }),
+                    )
+                }
+                Ok(Err(e)) => eprintln!("Window rule failed: {}", e),
+                Err(e) => eprintln!("Window rule failed: {}", e),
             }
         }
-        Some("key_up") => {
-            let key = req["key"].as_str().unwrap_or("");
-            match key_up(key) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
+        // Remember focus from before the next window appears, not after it grabbed focus
+        if let Some(active) = active
+            && !new_windows.iter().any(|w| w.id == active)
+        {
+            focused = Some(active);
         }
+    }
+}
 
-        // Window Management
-        Some("is_process_running") => {
-            let process = req["process"].as_str().unwrap_or("");
-            match is_process_running(process) {
-                Ok(running) => json!({ "status": "success", "running": running }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
-        }
-        Some("is_application_visible") => {
-            let app = req["app"].as_str().unwrap_or("");
-            match is_application_visible(app) {
-                Ok(visible) => json!({ "status": "success", "visible": visible }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
+/// Drop temporary sequences once their time to live is up
+async fn expire_sequences(state: Arc<Mutex<DaemonState>>) {
+    loop {
+        tokio::time::sleep(SEQUENCE_EXPIRY_INTERVAL).await;
+        let mut state = state.lock().unwrap();
+        for name in state.library.remove_expired(Instant::now()) {
+            state.emit(
+                "sequence_deleted",
+                json!({ "sequence": name, "expired": true }),
+            );
         }
-        Some("launch_application") => {
-            let app = req["app"].as_str().unwrap_or("");
-            match launch_application(app) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+    }
+}
+
+/// Play the sequence bound to each hotkey that is pressed
+async fn play_hotkeys(
+    state: Arc<Mutex<DaemonState>>,
+    mut presses: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(pressed) = presses.recv().await {
+        let Ok(hotkey) = Hotkey::parse(&pressed) else {
+            continue;
+        };
+        let (binding, paused) = {
+            let state = state.lock().unwrap();
+            (
+                state.hotkey_binding(&hotkey),
+                state.modes.automations_paused,
+            )
+        };
+        let name = match binding {
+            Some(HotkeyBinding::AbortAll) => {
+                abort_all(&state);
+                continue;
             }
-        }
-        Some("focus_window") => {
-            let window = req["window"].as_str().unwrap_or("");
-            match focus_window(window) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+            Some(HotkeyBinding::ToggleAutomations) if paused => {
+                resume_automations(&state);
+                continue;
             }
-        }
-        Some("list_windows") => match list_windows() {
-            Ok(windows) => {
-                let windows_json: Vec<_> = windows
-                    .iter()
-                    .map(|w| {
-                        json!({
-                            "id": w.id,
-                            "pid": w.pid,
-                            "desktop": w.desktop,
-                            "class": w.class,
-                            "title": w.title,
-                            "machine": w.machine,
-                        })
-                    })
-                    .collect();
-                json!({ "status": "success", "windows": windows_json })
+            Some(HotkeyBinding::ToggleAutomations) => {
+                pause_automations(&state, None);
+                continue;
             }
-            Err(e) => json!({ "status": "error", "message": e }),
-        },
-        Some("find_window") => {
-            let pattern = req["pattern"].as_str().unwrap_or("");
-            match find_window_by_pattern(pattern) {
-                Ok(Some(window)) => json!({
-                    "status": "success",
-                    "window": {
-                        "id": window.id,
-                        "pid": window.pid,
-                        "desktop": window.desktop,
-                        "class": window.class,
-                        "title": window.title,
-                        "machine": window.machine,
-                    }
-                }),
-                Ok(None) => json!({ "status": "success", "window": null }),
-                Err(e) => json!({ "status": "error", "message": e }),
+            Some(HotkeyBinding::Sequence(name)) if !paused => name,
+            _ => continue,
+        };
+        let mut locked = state.lock().unwrap();
+        locked.emit(
+            "hotkey_triggered",
+            json!({ "hotkey": pressed, "sequence": name }),
+        );
+        let result = match locked.library.get_sequence(&name) {
+            Some(sequence) if sequence.draft => Err(format!(
+                "Sequence '{}' is a draft; review it and send approve_sequence first",
+                name
+            )),
+            Some(sequence) => {
+                let playback = QueuedPlayback {
+                    concurrency: sequence.concurrency.unwrap_or_default(),
+                    sequence: sequence.clone(),
+                    start: None,
+                    end: None,
+                    options: PlaybackOptions::default(),
+                    capture_on_error: None,
+                    block_input: None,
+                };
+                play_or_queue(&state, &mut locked, playback)
             }
+            None => Err(format!("Sequence not found: {}", name)),
+        };
+        if let Err(e) = result {
+            locked.emit("playback_failed", json!({ "sequence": name, "error": e }));
         }
-        Some("maximize_window") => {
+    }
+}
+
+/// Answer Prometheus scrapes on `GET /metrics`
+async fn serve_metrics(listener: TcpListener, state: Arc<Mutex<DaemonState>>) {
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            continue;
+        };
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let mut buf = vec![0; 1024];
+            let n = socket.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let (status, content_type, body) = if request.starts_with("GET /metrics ") {
+                (
+                    "200 OK",
+                    "text/plain; version=0.0.4",
+                    state.lock().unwrap().metrics.render_prometheus(),
+                )
+            } else {
+                ("404 Not Found", "text/plain", "Not found\n".to_string())
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                content_type,
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Handle a request in streaming mode
+///
+/// The response is written as newline-delimited JSON: any number of
+/// `{"stream": true, "chunk": ...}` objects followed by a final object with
+/// `"done": true`. Request types without incremental output answer with a
+/// single final object.
+async fn handle_stream_request(
+    req: &serde_json::Value,
+    state: &Arc<Mutex<DaemonState>>,
+    socket: &mut UnixStream,
+) -> std::io::Result<()> {
+    let started = Instant::now();
+    let (tx, mut rx) = mpsc::unbounded_channel::<serde_json::Value>();
+    let request_type = req["type"].as_str().unwrap_or("").to_string();
+    let timeout = request_timeout(&request_type, state);
+    let deadline = timeout.map(|t| Instant::now() + t);
+
+    match req["type"].as_str() {
+        Some("run_command") => {
+            let cmd = req["command"].as_str().unwrap_or("").to_string();
+            spawn_blocking_bounded(move || {
+                let result = run_bounded(deadline, || {
+                    run_command_streaming(&cmd, |stream, line| {
+                        let source = match stream {
+                            OutputStream::Stdout => "stdout",
+                            OutputStream::Stderr => "stderr",
+                        };
+                        let _ = tx.send(json!({ "stream": true, "chunk": line, "source": source }));
+                    })
+                });
+                let _ = tx.send(match result {
+                    Ok(0) => json!({ "done": true, "status": "success", "exit_code": 0 }),
+                    Ok(code) => json!({
+                        "done": true,
+                        "status": "error",
+                        "message": format!("Command exited with code {}", code),
+                        "exit_code": code
+                    }),
+                    Err(_) if deadline_passed(deadline) => {
+                        let mut response = timeout_error(&request_type, timeout);
+                        response["done"] = json!(true);
+                        response
+                    }
+                    Err(e) => json!({ "done": true, "status": "error", "message": e }),
+                });
+            });
+        }
+        Some("speak") if !state.lock().unwrap().modes.do_not_disturb => {
+            let text = req["text"].as_str().unwrap_or("").to_string();
+            spawn_blocking_bounded(move || {
+                let sentences = split_sentences(&text);
+                let total = sentences.len();
+                for (i, sentence) in sentences.iter().enumerate() {
+                    let _ = tx.send(json!({
+                        "stream": true,
+                        "chunk": sentence,
+                        "progress": { "current": i + 1, "total": total }
+                    }));
+                    if let Err(e) = run_bounded(deadline, || speak_blocking(sentence)) {
+                        let mut response = if deadline_passed(deadline) {
+                            timeout_error(&request_type, timeout)
+                        } else {
+                            json!({ "status": "error", "message": e })
+                        };
+                        response["done"] = json!(true);
+                        let _ = tx.send(response);
+                        return;
+                    }
+                }
+                let _ = tx.send(json!({ "done": true, "status": "success" }));
+            });
+        }
+        Some("ask_ai") if !req["use_screen"].as_bool().unwrap_or(false) => {
+            let prompt = req["prompt"].as_str().unwrap_or("").to_string();
+            tokio::spawn(async move {
+                if prompt.is_empty() {
+                    let _ = tx.send(json!({
+                        "done": true,
+                        "status": "error",
+                        "message": "Missing prompt"
+                    }));
+                    return;
+                }
+                let vision = match AIVision::from_env() {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = tx.send(json!({ "done": true, "status": "error", "message": e }));
+                        return;
+                    }
+                };
+                let completion = vision.complete_stream(&prompt, |text| {
+                    let _ = tx.send(json!({ "stream": true, "chunk": text }));
+                });
+                let result =
+                    tokio::time::timeout(timeout.unwrap_or(Duration::MAX), completion).await;
+                let _ = tx.send(match result {
+                    Ok(Ok(answer)) => {
+                        json!({ "done": true, "status": "success", "result": answer })
+                    }
+                    Ok(Err(e)) => json!({ "done": true, "status": "error", "message": e }),
+                    Err(_) => {
+                        let mut response = timeout_error(&request_type, timeout);
+                        response["done"] = json!(true);
+                        response
+                    }
+                });
+            });
+        }
+        _ => {
+            // No incremental output for this request type: answer with one final chunk
+            let mut response = handle_with_timeout(req, state).await;
+            paginate_response(req, state, &mut response);
+            response["done"] = json!(true);
+            let _ = tx.send(response);
+        }
+    }
+
+    while let Some(mut chunk) = rx.recv().await {
+        if chunk["done"] == true {
+            attach_error_context(req, state, &mut chunk).await;
+            let request_type = req["type"].as_str().unwrap_or("");
+            state
+                .lock()
+                .unwrap()
+                .record_request(request_type, &chunk, started.elapsed());
+        }
+        let mut line = chunk.to_string();
+        line.push('\n');
+        socket.write_all(line.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Timeout configured for a request type
+fn request_timeout(request_type: &str, state: &Arc<Mutex<DaemonState>>) -> Option<Duration> {
+    state
+        .lock()
+        .unwrap()
+        .config
+        .timeouts
+        .for_request(request_type)
+}
+
+/// Run blocking work with external tools bounded by the request deadline
+fn run_bounded<R>(deadline: Option<Instant>, f: impl FnOnce() -> R) -> R {
+    match deadline {
+        Some(deadline) => with_deadline_blocking(deadline, f),
+        None => f(),
+    }
+}
+
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| Instant::now() >= d)
+}
+
+fn timeout_error(request_type: &str, timeout: Option<Duration>) -> Value {
+    json!({
+        "status": "error",
+        "code": "E_TIMEOUT",
+        "message": format!(
+            "{} did not finish within {}s and was aborted",
+            request_type,
+            timeout.unwrap_or_default().as_secs()
+        )
+    })
+}
+
+/// Handle a request under the timeout configured for its type
+///
+/// External tools still running at the deadline are killed and pending
+/// network calls are dropped, so a hung helper cannot stall the daemon.
+async fn handle_with_timeout(req: &Value, state: &Arc<Mutex<DaemonState>>) -> Value {
+    let request_type = req["type"].as_str().unwrap_or("");
+    let Some(timeout) = request_timeout(request_type, state) else {
+        return handle_request(req, state).await;
+    };
+
+    let deadline = Instant::now() + timeout;
+    let handler = with_deadline(deadline, handle_request(req, state));
+    match tokio::time::timeout(timeout, handler).await {
+        // A handler finishing past the deadline failed because its tool was killed
+        Ok(_) if deadline_passed(Some(deadline)) => timeout_error(request_type, Some(timeout)),
+        Ok(response) => response,
+        Err(_) => timeout_error(request_type, Some(timeout)),
+    }
+}
+
+/// Cut the list of a paginated request type down to the requested page
+///
+/// Without `offset` or `limit` in the request the page is the first
+/// `responses.max_items` entries, or the whole list if that isn't set.
+fn paginate_response(req: &Value, state: &Arc<Mutex<DaemonState>>, response: &mut Value) {
+    let Some(field) = protocol::paginated_field(req["type"].as_str().unwrap_or("")) else {
+        return;
+    };
+    // Handlers that page before doing work per item have done it already
+    if response.get("next_offset").is_some() {
+        return;
+    }
+    let limit = match req["limit"].as_u64() {
+        Some(limit) => Some(limit as usize),
+        None => state.lock().unwrap().config.responses.max_items,
+    };
+    let offset = req["offset"].as_u64().unwrap_or(0) as usize;
+    if limit.is_some() || offset > 0 {
+        protocol::paginate(response, field, offset, limit);
+    }
+}
+
+/// Write a single response, in `part` lines if it is longer than `max_bytes`
+async fn write_response(
+    socket: &mut UnixStream,
+    response: &Value,
+    max_bytes: Option<usize>,
+) -> std::io::Result<()> {
+    let text = response.to_string();
+    match max_bytes {
+        Some(max_bytes) if text.len() > max_bytes => {
+            for line in protocol::split_response(&text, max_bytes) {
+                socket.write_all(line.as_bytes()).await?;
+            }
+            Ok(())
+        }
+        _ => socket.write_all(text.as_bytes()).await,
+    }
+}
+
+/// Add a screenshot of the screen to a failed `run_command` or `play_sequence`
+/// response when error capture is on
+async fn attach_error_context(req: &Value, state: &Arc<Mutex<DaemonState>>, response: &mut Value) {
+    let request_type = req["type"].as_str().unwrap_or("");
+    if response["status"] != "error"
+        || !ERROR_CAPTURE_REQUESTS.contains(&request_type)
+        || req["dry_run"] == true
+        || !state
+            .lock()
+            .unwrap()
+            .wants_error_capture(req["capture_on_error"].as_bool())
+    {
+        return;
+    }
+    let label = req["name"].as_str().unwrap_or(request_type);
+    if let (Some(response), Value::Object(context)) =
+        (response.as_object_mut(), error_context(state, label).await)
+    {
+        response.extend(context);
+    }
+}
+
+/// Capture the screen as a failure left it into the errors directory
+///
+/// Returns the fields describing it: `screenshot` with the path, plus
+/// `screen_description` when `error_capture.describe` is on and the AI
+/// answers in time, or `screenshot_error` if the capture failed.
+async fn error_context(state: &Arc<Mutex<DaemonState>>, label: &str) -> Value {
+    let (profile, describe) = {
+        let state = state.lock().unwrap();
+        (state.profile.clone(), state.config.error_capture.describe)
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let label: String = label
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let dir = paths::errors_dir(profile.as_deref());
+    let path = dir.join(format!("{}-{}.png", timestamp, label));
+    let path = path.to_string_lossy().to_string();
+
+    let target = path.clone();
+    let captured = spawn_blocking_bounded(move || {
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+        ScreenCapture::new()?.capture_screen(&target)
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Capture panicked: {}", e)));
+    if let Err(e) = captured {
+        return json!({ "screenshot_error": e });
+    }
+
+    let mut context = json!({ "screenshot": path });
+    if describe && let Ok(vision) = AIVision::from_env() {
+        let prompt = "Describe in one short sentence what is on this screen, \
+                      mentioning any error message or dialog that is visible.";
+        let description = vision.analyze_screenshot(&path, prompt);
+        if let Ok(Ok(description)) =
+            tokio::time::timeout(ERROR_DESCRIPTION_TIMEOUT, description).await
+        {
+            context["screen_description"] = json!(description.trim());
+        }
+    }
+    context
+}
+
+/// Stream daemon events to a subscriber until it disconnects
+///
+/// The first line acknowledges the subscription; every following line is an
+/// event object such as `{"event": "recording_stopped", "sequence": "..."}`.
+/// An empty or missing `events` list subscribes to everything.
+async fn handle_subscribe(
+    req: &Value,
+    state: &Arc<Mutex<DaemonState>>,
+    socket: &mut UnixStream,
+) -> std::io::Result<()> {
+    let filter: Vec<String> = req["events"]
+        .as_array()
+        .map(|events| {
+            events
+                .iter()
+                .filter_map(|e| e.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let unknown: Vec<_> = filter
+        .iter()
+        .filter(|e| !protocol::EVENTS.contains(&e.as_str()))
+        .collect();
+    if !unknown.is_empty() {
+        let response = json!({
+            "status": "error",
+            "message": format!("Unknown events: {:?}", unknown),
+            "events": protocol::EVENTS
+        });
+        let mut line = response.to_string();
+        line.push('\n');
+        return socket.write_all(line.as_bytes()).await;
+    }
+
+    // The window list is only polled for events someone named
+    let watches_windows = filter
+        .iter()
+        .any(|e| WindowEvent::NAMES.contains(&e.as_str()));
+    let rx = {
+        let mut state = state.lock().unwrap();
+        if watches_windows {
+            state.window_event_subscribers += 1;
+        }
+        state.events.subscribe()
+    };
+    let ack = json!({ "status": "success", "subscribed": filter });
+    let result = forward_events(rx, ack, socket, |event| {
+        let name = event["event"].as_str().unwrap_or("");
+        // Frames are too large for subscribers that did not ask for them
+        (filter.is_empty() && name != "screen_frame") || filter.iter().any(|f| f == name)
+    })
+    .await;
+    if watches_windows {
+        state.lock().unwrap().window_event_subscribers -= 1;
+    }
+    result
+}
+
+/// The key/value store as `StoreGet` and `StoreSet` steps see it; their
+/// changes are announced like those of `store_set`
+struct PlaybackStore(Weak<Mutex<DaemonState>>);
+
+impl SharedStore for PlaybackStore {
+    fn get(&self, key: &str) -> Option<Value> {
+        let state = self.0.upgrade()?;
+        let state = state.lock().unwrap();
+        state.store.get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: Value) -> Result<(), String> {
+        let state = self.0.upgrade().ok_or("The daemon is shutting down")?;
+        let mut state = state.lock().unwrap();
+        state.store.set(key, value.clone())?;
+        state.emit("store_changed", json!({ "key": key, "value": value }));
+        Ok(())
+    }
+}
+
+/// Stream store changes for the given keys (all keys if none are given)
+///
+/// The acknowledgement carries the current values, so a watcher never misses
+/// a change made between reading a key and starting to watch it.
+async fn handle_store_watch(
+    req: &Value,
+    state: &Arc<Mutex<DaemonState>>,
+    socket: &mut UnixStream,
+) -> std::io::Result<()> {
+    let keys: Vec<String> = req["keys"]
+        .as_array()
+        .map(|keys| {
+            keys.iter()
+                .filter_map(|k| k.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let (rx, ack) = {
+        let state = state.lock().unwrap();
+        let values: serde_json::Map<String, Value> = state
+            .store
+            .all()
+            .iter()
+            .filter(|(key, _)| keys.is_empty() || keys.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let ack = json!({ "status": "success", "keys": keys, "values": values });
+        (state.events.subscribe(), ack)
+    };
+    forward_events(rx, ack, socket, |event| {
+        event["event"] == "store_changed"
+            && (keys.is_empty() || keys.iter().any(|k| event["key"] == k.as_str()))
+    })
+    .await
+}
+
+/// Stream frames of the screen, a region or a window as JSON lines
+///
+/// After the acknowledgement every line is a frame,
+/// `{"frame": 1, "mime": "image/jpeg", "data": <base64>, "timestamp_ms": ...}`,
+/// until the client disconnects, `max_frames` were sent or a capture fails
+/// (ending with `{"done": true, ...}`). Frames are held
+/// back while privacy mode is on, announced with `{"paused": true}` and
+/// `{"paused": false}`. Only the socket's owner can connect, so the stream is
+/// never visible to other users.
+async fn handle_stream_screen(
+    req: &Value,
+    state: &Arc<Mutex<DaemonState>>,
+    socket: &mut UnixStream,
+) -> std::io::Result<()> {
+    let interval = Duration::from_millis(
+        req["interval_ms"]
+            .as_u64()
+            .unwrap_or(1000)
+            .max(MIN_FRAME_INTERVAL_MS),
+    );
+    let max_frames = req["max_frames"].as_u64();
+    let source = match frame_source(req) {
+        Ok(source) => source,
+        Err(e) => {
+            let mut line = json!({ "status": "error", "message": e }).to_string();
+            line.push('\n');
+            return socket.write_all(line.as_bytes()).await;
+        }
+    };
+    let capture = match ScreenCapture::new() {
+        Ok(capture) => Arc::new(capture),
+        Err(e) => {
+            let mut line = json!({ "status": "error", "message": e }).to_string();
+            line.push('\n');
+            return socket.write_all(line.as_bytes()).await;
+        }
+    };
+
+    let ack = json!({
+        "status": "success",
+        "interval_ms": interval.as_millis() as u64,
+        "max_frames": max_frames
+    });
+    let mut line = ack.to_string();
+    line.push('\n');
+    socket.write_all(line.as_bytes()).await?;
+
+    let detail = {
+        let mut state = state.lock().unwrap();
+        state.next_capture_stream += 1;
+        format!("stream-{}", state.next_capture_stream)
+    };
+    // Off again whichever way the stream ends
+    let _watching = SensorGuard::start(state, Sensor::ScreenWatching, detail);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut frames = 0;
+    let mut paused = false;
+    while max_frames.is_none_or(|max| frames < max) {
+        ticker.tick().await;
+        let privacy = state.lock().unwrap().modes.privacy;
+        if privacy != paused {
+            paused = privacy;
+            let mut line = json!({ "paused": paused }).to_string();
+            line.push('\n');
+            socket.write_all(line.as_bytes()).await?;
+        }
+        if paused {
+            continue;
+        }
+
+        let (capture, source) = (Arc::clone(&capture), source.clone());
+        let frame = match spawn_blocking_bounded(move || capture.capture_frame(&source))
+            .await
+            .unwrap_or_else(|e| Err(format!("Capture panicked: {}", e)))
+        {
+            Ok(frame) => frame,
+            Err(e) => {
+                let end =
+                    json!({ "done": true, "status": "error", "message": e, "frames": frames });
+                let mut line = end.to_string();
+                line.push('\n');
+                return socket.write_all(line.as_bytes()).await;
+            }
+        };
+        frames += 1;
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let message = json!({
+            "frame": frames,
+            "mime": frame.mime,
+            "data": frame.to_base64(),
+            "timestamp_ms": timestamp
+        });
+        let mut line = message.to_string();
+        line.push('\n');
+        socket.write_all(line.as_bytes()).await?;
+    }
+    let mut line = json!({ "done": true, "status": "success", "frames": frames }).to_string();
+    line.push('\n');
+    socket.write_all(line.as_bytes()).await
+}
+
+/// Capture frames every `interval` and publish them as `screen_frame`
+/// events until the stream is stopped, `max_frames` were sent or a capture
+/// fails; frames are skipped while privacy mode is on
+async fn run_capture_stream(
+    state: Arc<Mutex<DaemonState>>,
+    stream_id: String,
+    capture: Arc<ScreenCapture>,
+    source: FrameSource,
+    interval: Duration,
+    max_frames: Option<u64>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        ticker.tick().await;
+        if state.lock().unwrap().modes.privacy {
+            continue;
+        }
+        let (capture, source) = (Arc::clone(&capture), source.clone());
+        let captured = spawn_blocking_bounded(move || capture.capture_frame(&source))
+            .await
+            .unwrap_or_else(|e| Err(format!("Capture panicked: {}", e)));
+
+        let mut state = state.lock().unwrap();
+        let Some(stream) = state.capture_streams.get_mut(&stream_id) else {
+            return;
+        };
+        let frame = match captured {
+            Ok(frame) => frame,
+            Err(e) => {
+                let frames = stream.frames;
+                state.remove_capture_stream(&stream_id);
+                state.emit(
+                    "screen_stream_ended",
+                    json!({ "stream_id": stream_id, "frames": frames, "status": "error", "message": e }),
+                );
+                return;
+            }
+        };
+        stream.frames += 1;
+        let frames = stream.frames;
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        state.emit(
+            "screen_frame",
+            json!({
+                "stream_id": stream_id,
+                "frame": frames,
+                "mime": frame.mime,
+                "data": frame.to_base64(),
+                "timestamp_ms": timestamp
+            }),
+        );
+        if max_frames.is_some_and(|max| frames >= max) {
+            state.remove_capture_stream(&stream_id);
+            state.emit(
+                "screen_stream_ended",
+                json!({ "stream_id": stream_id, "frames": frames, "status": "success" }),
+            );
+            return;
+        }
+    }
+}
+
+/// Stream the cursor position as JSON lines, for overlays showing where the
+/// mouse is while the user picks coordinates
+///
+/// The position is read every `interval_ms` (50 by default) and sent when it
+/// changed: `{"x": 812, "y": 440, "monitor": "DP-1", "monitor_x": 812,
+/// "monitor_y": 440, "timestamp_ms": ...}`, the monitor coordinates being
+/// the ones `"monitor"` takes in mouse requests. The stream goes on until
+/// the client disconnects or the position cannot be read (ending with
+/// `{"done": true, ...}`).
+async fn handle_watch_mouse(req: &Value, socket: &mut UnixStream) -> std::io::Result<()> {
+    let interval = Duration::from_millis(
+        req["interval_ms"]
+            .as_u64()
+            .unwrap_or(50)
+            .max(MIN_MOUSE_INTERVAL_MS),
+    );
+    // Monitors rarely change while a position is picked, so they are read once
+    let displays = spawn_blocking_bounded(list_displays)
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .unwrap_or_default();
+
+    let ack = json!({ "status": "success", "interval_ms": interval.as_millis() as u64 });
+    let mut line = ack.to_string();
+    line.push('\n');
+    socket.write_all(line.as_bytes()).await?;
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut last = None;
+    loop {
+        ticker.tick().await;
+        let (x, y) = match get_mouse_position() {
+            Ok(position) => position,
+            Err(e) => {
+                let end = json!({ "done": true, "status": "error", "message": e });
+                let mut line = end.to_string();
+                line.push('\n');
+                return socket.write_all(line.as_bytes()).await;
+            }
+        };
+        if last == Some((x, y)) {
+            continue;
+        }
+        last = Some((x, y));
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut message = json!({ "x": x, "y": y, "timestamp_ms": timestamp });
+        if let Some(display) = display_at(&displays, x, y) {
+            message["monitor"] = json!(display.name);
+            message["monitor_x"] = json!(x - display.x);
+            message["monitor_y"] = json!(y - display.y);
+        }
+        let mut line = message.to_string();
+        line.push('\n');
+        socket.write_all(line.as_bytes()).await?;
+    }
+}
+
+/// The window search of a request: `pattern`, with `match`, `regex` and
+/// `case_sensitive` as `find_window` takes them
+fn window_query(req: &Value) -> Result<WindowQuery, String> {
+    Ok(WindowQuery {
+        pattern: req["pattern"].as_str().unwrap_or("").to_string(),
+        field: WindowField::parse(req["match"].as_str().unwrap_or("any"))?,
+        regex: req["regex"].as_bool().unwrap_or(false),
+        case_sensitive: req["case_sensitive"].as_bool().unwrap_or(false),
+    })
+}
+
+/// The window a request names by its `window_id` or by a title or class
+/// pattern in `window`
+fn requested_window_id(req: &Value) -> Result<String, String> {
+    match (req["window_id"].as_str(), req["window"].as_str()) {
+        (Some(id), _) => Ok(id.to_string()),
+        (None, Some(pattern)) => find_window_by_pattern(pattern)?
+            .map(|window| window.id)
+            .ok_or_else(|| format!("No window matches '{}'", pattern)),
+        (None, None) => Err("Give a window_id or a window pattern".to_string()),
+    }
+}
+
+/// The `workspace` number of a request
+fn requested_workspace(req: &Value) -> Result<i32, String> {
+    let workspace = req["workspace"]
+        .as_i64()
+        .ok_or_else(|| "workspace must be a number".to_string())?;
+    i32::try_from(workspace).map_err(|_| format!("No such workspace: {}", workspace))
+}
+
+/// What a `capture_screen`, `capture_region` or `capture_window` request
+/// captures
+fn capture_source(req: &Value) -> Result<FrameSource, String> {
+    match req["type"].as_str() {
+        Some("capture_region") => {
+            let field = |name: &str| {
+                req[name]
+                    .as_i64()
+                    .map(|value| value as i32)
+                    .ok_or_else(|| format!("capture_region needs an integer '{}'", name))
+            };
+            Ok(FrameSource::Region {
+                x: field("x")?,
+                y: field("y")?,
+                width: field("width")?,
+                height: field("height")?,
+            })
+        }
+        Some("capture_window") => requested_window_id(req).map(FrameSource::Window),
+        Some("capture_monitor") => requested_monitor(req).map(|display| monitor_source(&display)),
+        _ => Ok(FrameSource::Screen),
+    }
+}
+
+/// The monitor a request names in `monitor`, by name or index in `list_displays`
+fn requested_monitor(req: &Value) -> Result<Display, String> {
+    let monitor: DisplayRef = serde_json::from_value(req["monitor"].clone())
+        .map_err(|_| "monitor must be a monitor name or index".to_string())?;
+    find_display(&monitor)
+}
+
+/// What `stream_screen` and `start_capture_stream` show: a `window` (title or class pattern), a
+/// `monitor`, a `region` (`{"x", "y", "width", "height"}`) or the whole screen
+fn frame_source(req: &Value) -> Result<FrameSource, String> {
+    if let Some(pattern) = req["window"].as_str() {
+        let window = find_window_by_pattern(pattern)?
+            .ok_or_else(|| format!("No window matches '{}'", pattern))?;
+        return Ok(FrameSource::Window(window.id));
+    }
+    if !req["monitor"].is_null() {
+        return requested_monitor(req).map(|display| monitor_source(&display));
+    }
+    let region = &req["region"];
+    if region.is_null() {
+        return Ok(FrameSource::Screen);
+    }
+    let field = |name: &str| {
+        region[name]
+            .as_i64()
+            .map(|value| value as i32)
+            .ok_or_else(|| format!("region needs an integer '{}'", name))
+    };
+    Ok(FrameSource::Region {
+        x: field("x")?,
+        y: field("y")?,
+        width: field("width")?,
+        height: field("height")?,
+    })
+}
+
+/// Write `ack`, then every event accepted by `wanted`, as JSON lines
+///
+/// Slow readers are told how many events they missed with a `lagged` event.
+async fn forward_events(
+    mut rx: broadcast::Receiver<Value>,
+    ack: Value,
+    socket: &mut UnixStream,
+    wanted: impl Fn(&Value) -> bool,
+) -> std::io::Result<()> {
+    let mut line = ack.to_string();
+    line.push('\n');
+    socket.write_all(line.as_bytes()).await?;
+
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) if wanted(&event) => event,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                json!({ "event": "lagged", "missed": missed })
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        let mut line = event.to_string();
+        line.push('\n');
+        socket.write_all(line.as_bytes()).await?;
+    }
+}
+
+async fn handle_request(
+    req: &serde_json::Value,
+    state: &Arc<Mutex<DaemonState>>,
+) -> serde_json::Value {
+    match req["type"].as_str() {
+        // Basic Commands
+        Some("run_command") => {
+            let cmd = req["command"].as_str().unwrap_or("");
+            match run_command(cmd) {
+                Ok(output) => json!({ "status": "success", "output": output }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Screen Control - Mouse
+        Some("move_mouse") => {
+            let x = req["x"].as_i64().unwrap_or(0) as i32;
+            let y = req["y"].as_i64().unwrap_or(0) as i32;
+            let moved = if req["relative"].as_bool().unwrap_or(false) {
+                move_mouse_relative(x, y)
+            } else {
+                request_point(req, x, y).and_then(|(x, y)| move_mouse(x, y))
+            };
+            match moved {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("click_mouse") => {
+            let button = req["button"].as_str().unwrap_or("left").to_string();
+            let count = request_count(req).unwrap_or(1);
+            // Repeated clicks pause in between, so keep them off the async workers
+            match spawn_blocking_bounded(move || click_times(&button, count))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("click_at") => {
+            let (Some(x), Some(y)) = (req["x"].as_i64(), req["y"].as_i64()) else {
+                return json!({ "status": "error", "message": "click_at needs x and y" });
+            };
+            let button = req["button"].as_str().unwrap_or("left");
+            let count = request_count(req).unwrap_or(1);
+            let settle_ms = req["settle_ms"].as_u64().unwrap_or(0);
+            let clicked = request_point(req, x as i32, y as i32)
+                .and_then(|(x, y)| click_at(x, y, button, count, settle_ms));
+            match clicked {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("click_in_window") => {
+            let (Some(rel_x), Some(rel_y)) = (req["rel_x"].as_i64(), req["rel_y"].as_i64()) else {
+                return json!({ "status": "error", "message": "click_in_window needs rel_x and rel_y" });
+            };
+            let window_id = match requested_window_id(req) {
+                Ok(id) => id,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let button = req["button"].as_str().unwrap_or("left").to_string();
+            let count = request_count(req).unwrap_or(1);
+            match spawn_blocking_bounded(move || {
+                click_in_window(&window_id, rel_x as i32, rel_y as i32, &button, count)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok((x, y)) => json!({ "status": "success", "x": x, "y": y }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("double_click") => {
+            let button = req["button"].as_str().unwrap_or("left").to_string();
+            match spawn_blocking_bounded(move || click_times(&button, 2))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("drag") => {
+            let point = |name: &str| req[name].as_i64().map(|value| value as i32);
+            let (Some(from_x), Some(from_y), Some(to_x), Some(to_y)) = (
+                point("from_x"),
+                point("from_y"),
+                point("to_x"),
+                point("to_y"),
+            ) else {
+                return json!({ "status": "error", "message": "drag needs from_x, from_y, to_x and to_y" });
+            };
+            let button = req["button"].as_str().unwrap_or("left").to_string();
+            let duration_ms = req["duration_ms"].as_u64().unwrap_or(300);
+            // The drag sleeps between moves, so keep it off the async workers
+            let dragged = spawn_blocking_bounded(move || {
+                drag(from_x, from_y, to_x, to_y, &button, duration_ms)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match dragged {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("mouse_down") => {
+            let button = req["button"].as_str().unwrap_or("left");
+            match mouse_down(button) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("mouse_up") => {
+            let button = req["button"].as_str().unwrap_or("left");
+            match mouse_up(button) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("scroll") => {
+            let amount = req["amount"].as_i64().unwrap_or(1) as i32;
+            let direction = req["direction"].as_str().unwrap_or("up").to_string();
+            let step = req["step"].as_u64().map(|step| step as u32);
+            let delay_ms = req["delay_ms"].as_u64();
+            // Smooth scrolling waits between its steps
+            let scrolled = spawn_blocking_bounded(move || match step {
+                Some(step) => scroll_smoothly(amount, &direction, step, delay_ms),
+                None => scroll(amount, &direction),
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match scrolled {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("scroll_pages") => {
+            let pages = req["pages"].as_u64().unwrap_or(1) as u32;
+            let direction = req["direction"].as_str().unwrap_or("down");
+            match scroll_pages(pages, direction) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("scroll_to_image") => {
+            if state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let image = reference_path(req["image"].as_str().unwrap_or(""));
+            let direction = req["direction"].as_str().unwrap_or("down").to_string();
+            let tolerance = req["tolerance"].as_u64().unwrap_or(0).min(255) as u8;
+            let max_scrolls = req["max_scrolls"]
+                .as_u64()
+                .map(|max| max as u32)
+                .unwrap_or(DEFAULT_MAX_SCROLLS);
+            match spawn_blocking_bounded(move || {
+                scroll_to_image(&image, tolerance, &direction, max_scrolls)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok((x, y)) => json!({ "status": "success", "x": x, "y": y }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("find_image_on_screen") => {
+            if state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let image = reference_path(req["image"].as_str().unwrap_or(""));
+            let confidence = req["confidence"]
+                .as_f64()
+                .unwrap_or(DEFAULT_CONFIDENCE)
+                .clamp(0.0, 1.0);
+            match spawn_blocking_bounded(move || locate_image_on_screen(&image, confidence))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(Some(found)) => {
+                    let (center_x, center_y) = found.center();
+                    json!({
+                        "status": "success",
+                        "found": true,
+                        "x": found.x,
+                        "y": found.y,
+                        "width": found.width,
+                        "height": found.height,
+                        "center_x": center_x,
+                        "center_y": center_y,
+                        "confidence": found.confidence
+                    })
+                }
+                Ok(None) => json!({ "status": "success", "found": false }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("compare_images") => {
+            let a = reference_path(req["a"].as_str().unwrap_or(""));
+            let b = reference_path(req["b"].as_str().unwrap_or(""));
+            let tolerance = req["tolerance"].as_u64().unwrap_or(0).min(255) as u8;
+            let read = |path: &std::path::Path| {
+                std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+            };
+            match spawn_blocking_bounded(move || {
+                read(&a).and_then(|a| compare_images(&a, &read(&b)?, tolerance))
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(comparison) => json!({
+                    "status": "success",
+                    "similarity": comparison.similarity,
+                    "changed": comparison.changed
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("wait_for_screen_change") => {
+            if state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let wait: ScreenChangeWait = match serde_json::from_value(req.clone()) {
+                Ok(wait) => wait,
+                Err(e) => return json!({ "status": "error", "message": e.to_string() }),
+            };
+            match spawn_blocking_bounded(move || {
+                watch_screen("wait_for_screen_change", || wait_for_screen_change(&wait))
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(comparison) => json!({
+                    "status": "success",
+                    "similarity": comparison.similarity,
+                    "changed": comparison.changed
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("cleanup_screenshots") => {
+            let max_count = req["max_count"].as_u64().map(|n| n as usize);
+            let max_age_hours = req["max_age_hours"].as_u64();
+            match spawn_blocking_bounded(move || cleanup_screenshots(max_count, max_age_hours))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(report) => json!({
+                    "status": "success",
+                    "removed": report.removed,
+                    "kept": report.kept,
+                    "freed_bytes": report.freed_bytes
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("get_mouse_position") => match get_mouse_position() {
+            Ok((x, y)) => json!({ "status": "success", "x": x, "y": y }),
+            Err(e) => json!({ "status": "error", "message": e }),
+        },
+
+        Some("list_displays") => {
+            match spawn_blocking_bounded(list_displays)
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(displays) => json!({ "status": "success", "displays": displays }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("get_pixel_color") => {
+            if state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let x = req["x"].as_i64().unwrap_or(0) as i32;
+            let y = req["y"].as_i64().unwrap_or(0) as i32;
+            match spawn_blocking_bounded(move || pixel_color(x, y))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok((r, g, b)) => json!({
+                    "status": "success",
+                    "color": format!("#{:02x}{:02x}{:02x}", r, g, b),
+                    "r": r,
+                    "g": g,
+                    "b": b
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Screen Control - Touch
+        Some("swipe") | Some("pinch") | Some("tap") => {
+            let value = |name: &str| req[name].as_i64().map(|value| value as i32);
+            let fingers = req["fingers"].as_u64().unwrap_or(1) as u32;
+            let strokes = match req["type"].as_str() {
+                Some("swipe") => match (
+                    value("from_x"),
+                    value("from_y"),
+                    value("to_x"),
+                    value("to_y"),
+                ) {
+                    (Some(from_x), Some(from_y), Some(to_x), Some(to_y)) => {
+                        gestures::swipe_strokes((from_x, from_y), (to_x, to_y), fingers)
+                    }
+                    _ => {
+                        return json!({
+                            "status": "error",
+                            "message": "swipe needs from_x, from_y, to_x and to_y"
+                        });
+                    }
+                },
+                Some("pinch") => {
+                    let (Some(x), Some(y)) = (value("x"), value("y")) else {
+                        return json!({ "status": "error", "message": "pinch needs x and y" });
+                    };
+                    let from = value("from_distance").unwrap_or(300);
+                    let to = value("to_distance").unwrap_or(100);
+                    gestures::pinch_strokes((x, y), from, to)
+                }
+                _ => {
+                    let (Some(x), Some(y)) = (value("x"), value("y")) else {
+                        return json!({ "status": "error", "message": "tap needs x and y" });
+                    };
+                    gestures::swipe_strokes((x, y), (x, y), fingers)
+                }
+            };
+            let duration_ms = match req["type"].as_str() {
+                Some("tap") => 50,
+                _ => req["duration_ms"].as_u64().unwrap_or(300),
+            };
+            // The fingers move over the duration, so keep it off the async workers
+            match spawn_blocking_bounded(move || gestures::perform(&strokes, duration_ms))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Screen Control - Keyboard
+        Some("type_text") => {
+            let text = req["text"].as_str().unwrap_or("").to_string();
+            let delay_ms = req["delay_ms"].as_u64();
+            let via_clipboard = req["via_clipboard"].as_bool().unwrap_or(false);
+            let layout = req["layout"].as_str().map(str::to_string);
+            let sensitive = req["sensitive"].as_bool().unwrap_or(false);
+            // Slow typing and pasting wait in between, so keep them off the async workers
+            let typed = spawn_blocking_bounded(move || match via_clipboard && !sensitive {
+                true => paste_text(&text),
+                false => type_text_on_layout(&text, layout.as_deref(), delay_ms, sensitive),
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match typed {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("keyboard_layout") => {
+            match spawn_blocking_bounded(detect_layout)
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(layout) => json!({ "status": "success", "layout": layout }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("get_clipboard") => match get_clipboard() {
+            Ok(text) => json!({ "status": "success", "text": text }),
+            Err(e) => json!({ "status": "error", "message": e }),
+        },
+        Some("set_clipboard") => {
+            let text = req["text"].as_str().unwrap_or("");
+            match set_clipboard(text) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("press_key") => {
+            let key = req["key"].as_str().unwrap_or("");
+            match press_key(key) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("list_keys") => json!({
+            "status": "success",
+            "keys": key_names(),
+            "characters": "Any single character, e.g. \"a\", \"7\" or \"ç\""
+        }),
+        Some("press_combo") => {
+            let combo = req["combo"].as_str().unwrap_or("");
+            match press_key_combo(combo) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("key_down") => {
+            let key = req["key"].as_str().unwrap_or("");
+            match key_down(key) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("key_up") => {
+            let key = req["key"].as_str().unwrap_or("");
+            match key_up(key) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("send_app_action") => {
+            let name = req["name"].as_str().unwrap_or("");
+            match send_app_action(name) {
+                Ok(shortcut) => json!({ "status": "success", "shortcut": shortcut }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("app_shortcuts") => {
+            let class = match req["class"].as_str() {
+                Some(class) => Ok(class.to_string()),
+                None => active_window_class(),
+            };
+            let table = ShortcutTable::load(&paths::shortcuts_file());
+            match (class, table) {
+                (Ok(class), Ok(table)) => {
+                    let shortcuts: Vec<Shortcut> = table.for_class(&class).into_values().collect();
+                    json!({ "status": "success", "class": class, "shortcuts": shortcuts })
+                }
+                (Err(e), _) | (_, Err(e)) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Window Management
+        Some("is_process_running") => {
+            let process = req["process"].as_str().unwrap_or("");
+            match is_process_running(process) {
+                Ok(running) => json!({ "status": "success", "running": running }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("get_process_info") => match get_process_info(req["name"].as_str().unwrap_or("")) {
+            Ok(processes) => json!({ "status": "success", "processes": processes }),
+            Err(e) => json!({ "status": "error", "message": e }),
+        },
+        Some("kill_process") => {
+            let name = req["name"].as_str().unwrap_or("");
+            match kill_processes(name, req["signal"].as_str()) {
+                Ok(pids) => json!({ "status": "success", "pids": pids }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("wait_for_process_exit") => {
+            let name = req["name"].as_str().unwrap_or("").to_string();
+            let timeout_ms = req["timeout_ms"]
+                .as_u64()
+                .unwrap_or(DEFAULT_WAIT_TIMEOUT_MS);
+            match spawn_blocking_bounded(move || wait_for_process_exit(&name, timeout_ms))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("is_application_visible") => {
+            let app = req["app"].as_str().unwrap_or("");
+            match is_application_visible(app) {
+                Ok(visible) => json!({ "status": "success", "visible": visible }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("launch_application") => {
+            let app = req["app"].as_str().unwrap_or("");
+            let args: Vec<String> = req["args"]
+                .as_array()
+                .map(|args| {
+                    args.iter()
+                        .filter_map(|arg| arg.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            match launch_application_with(app, &args) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("search_applications") => {
+            let query = req["query"].as_str().unwrap_or("");
+            let limit = req["limit"].as_u64().unwrap_or(20) as usize;
+            let applications: Vec<_> = search_applications(query)
+                .into_iter()
+                .take(limit)
+                .map(|entry| {
+                    json!({
+                        "id": entry.id,
+                        "name": entry.name,
+                        "generic_name": entry.generic_name,
+                        "icon": entry.icon,
+                        "command": entry.command_line(),
+                    })
+                })
+                .collect();
+            json!({ "status": "success", "applications": applications })
+        }
+        Some("list_applications") => {
+            let applications: Vec<_> = search_applications("")
+                .into_iter()
+                .map(|entry| {
+                    json!({
+                        "id": entry.id,
+                        "name": entry.name,
+                        "generic_name": entry.generic_name,
+                        "icon": entry.icon,
+                        "command": entry.command_line(),
+                        "path": entry.path,
+                    })
+                })
+                .collect();
+            json!({ "status": "success", "applications": applications })
+        }
+        Some("focus_window") => {
+            let result = match req["window_id"].as_str() {
+                Some(window_id) => focus_window_by_id(window_id),
+                None => focus_window(req["window"].as_str().unwrap_or("")),
+            };
+            match result {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("snap_window") => {
+            let position = match SnapPosition::parse(req["position"].as_str().unwrap_or("")) {
+                Ok(position) => position,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let monitor: Option<DisplayRef> = match serde_json::from_value(req["monitor"].clone()) {
+                Ok(monitor) => monitor,
+                Err(_) => {
+                    return json!({
+                        "status": "error",
+                        "message": "monitor must be a monitor name or index"
+                    });
+                }
+            };
+            let req = req.clone();
+            let result = spawn_blocking_bounded(move || {
+                let window_id = requested_window_id(&req)?;
+                let area = snap_window(&window_id, position, monitor.as_ref())?;
+                Ok((window_id, area))
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match result {
+                Ok((window_id, (x, y, width, height))) => json!({
+                    "status": "success",
+                    "window_id": window_id,
+                    "x": x,
+                    "y": y,
+                    "width": width,
+                    "height": height,
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("raise_window") | Some("lower_window") => {
+            let raise = req["type"] == "raise_window";
+            let req = req.clone();
+            let result = spawn_blocking_bounded(move || {
+                let window_id = requested_window_id(&req)?;
+                if raise {
+                    raise_window(&window_id)?;
+                } else {
+                    lower_window(&window_id)?;
+                }
+                Ok(window_id)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match result {
+                Ok(window_id) => json!({ "status": "success", "window_id": window_id }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("list_windows") => {
+            let thumbnails = req["thumbnails"].as_bool().unwrap_or(false);
+            if thumbnails && state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let windows = match list_windows() {
+                Ok(windows) => windows,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let windows_json: Vec<_> = windows
+                .iter()
+                .map(|w| {
+                    json!({
+                        "id": w.id,
+                        "pid": w.pid,
+                        "desktop": w.desktop,
+                        "class": w.class,
+                        "title": w.title,
+                        "machine": w.machine,
+                    })
+                })
+                .collect();
+            let mut response = json!({ "status": "success", "windows": windows_json });
+            if thumbnails {
+                // Page first, so only the windows sent back are captured
+                paginate_response(req, state, &mut response);
+                let ids: Vec<String> = response["windows"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|w| w["id"].as_str().map(String::from))
+                    .collect();
+                let max_width = req["thumbnail_width"]
+                    .as_u64()
+                    .map_or(DEFAULT_THUMBNAIL_WIDTH, |width| width as u32);
+                state
+                    .lock()
+                    .unwrap()
+                    .set_sensor(Sensor::ScreenWatching, "list_windows", true);
+                let thumbnails = spawn_blocking_bounded(move || {
+                    // Other windows would show in the thumbnail of a covered one
+                    let uncovered = uncovered_windows(&windows).unwrap_or_default();
+                    ids.iter()
+                        .map(|id| {
+                            uncovered
+                                .contains(id)
+                                .then(|| window_thumbnail(id, max_width).ok())
+                                .flatten()
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .await
+                .unwrap_or_default();
+                state
+                    .lock()
+                    .unwrap()
+                    .set_sensor(Sensor::ScreenWatching, "list_windows", false);
+                let windows = response["windows"].as_array_mut().into_iter().flatten();
+                for (window, thumbnail) in windows.zip(thumbnails) {
+                    // Windows that could not be captured get a null thumbnail
+                    window["thumbnail"] = json!(thumbnail.map(|frame| frame.to_base64()));
+                }
+            }
+            response
+        }
+        Some("active_window") => {
+            match spawn_blocking_bounded(get_active_window)
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(window) => json!({ "status": "success", "window": window }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("find_window") => {
+            let query = match window_query(req) {
+                Ok(query) => query,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            match find_windows(&query) {
+                Ok(windows) if req["all"].as_bool().unwrap_or(false) => {
+                    json!({ "status": "success", "windows": windows })
+                }
+                Ok(windows) => json!({ "status": "success", "window": windows.first() }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("close_windows") | Some("minimize_windows") | Some("move_windows") => {
+            let op = match req["type"].as_str() {
+                Some("close_windows") => BulkWindowOp::Close,
+                Some("minimize_windows") => BulkWindowOp::Minimize,
+                _ => match req["workspace"].as_i64() {
+                    Some(workspace) => BulkWindowOp::MoveToWorkspace(workspace as i32),
+                    None => {
+                        return json!({ "status": "error", "message": "workspace must be a number" });
+                    }
+                },
+            };
+            let query = match window_query(req) {
+                Ok(query) => query,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            match spawn_blocking_bounded(move || bulk_window_op(&query, op))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(report) => json!({
+                    "status": "success",
+                    "count": report.windows.len(),
+                    "windows": report.windows,
+                    "errors": report.errors,
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("maximize_window") => {
             let window_id = req["window_id"].as_str().unwrap_or("");
             match maximize_window(window_id) {
                 Ok(_) => json!({ "status": "success" }),
                 Err(e) => json!({ "status": "error", "message": e }),
             }
         }
-        Some("minimize_window") => {
-            let window_id = req["window_id"].as_str().unwrap_or("");
-            match minimize_window(window_id) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+        Some("minimize_window") => {
+            let window_id = req["window_id"].as_str().unwrap_or("");
+            match minimize_window(window_id) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("close_window") => {
+            let window_id = req["window_id"].as_str().unwrap_or("");
+            match close_window(window_id) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("move_resize_window") => {
+            let window_id = req["window_id"].as_str().unwrap_or("");
+            let x = req["x"].as_i64().unwrap_or(0) as i32;
+            let y = req["y"].as_i64().unwrap_or(0) as i32;
+            let width = req["width"].as_i64().unwrap_or(800) as i32;
+            let height = req["height"].as_i64().unwrap_or(600) as i32;
+            match move_resize_window(window_id, x, y, width, height) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("set_window_state") => {
+            let state = match WindowState::parse(req["state"].as_str().unwrap_or("")) {
+                Ok(state) => state,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let on = req["on"].as_bool().unwrap_or(true);
+            let req = req.clone();
+            let result = spawn_blocking_bounded(move || {
+                let window_id = requested_window_id(&req)?;
+                set_window_state(&window_id, state, on)?;
+                Ok(window_id)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match result {
+                Ok(window_id) => json!({
+                    "status": "success",
+                    "window_id": window_id,
+                    "state": state.as_str(),
+                    "on": on
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("list_workspaces") => match spawn_blocking_bounded(list_workspaces)
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+        {
+            Ok(workspaces) => json!({ "status": "success", "workspaces": workspaces }),
+            Err(e) => json!({ "status": "error", "message": e }),
+        },
+        Some("switch_workspace") => {
+            let workspace = match requested_workspace(req) {
+                Ok(workspace) => workspace,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            match spawn_blocking_bounded(move || switch_workspace(workspace))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("move_window_to_workspace") => {
+            let workspace = match requested_workspace(req) {
+                Ok(workspace) => workspace,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let req = req.clone();
+            let result = spawn_blocking_bounded(move || {
+                let window_id = requested_window_id(&req)?;
+                move_window_to_workspace(&window_id, workspace)?;
+                Ok(window_id)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            match result {
+                Ok(window_id) => json!({ "status": "success", "window_id": window_id }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("wait_for_window") => {
+            let pattern = req["pattern"].as_str().unwrap_or("").to_string();
+            let timeout_ms = req["timeout_ms"]
+                .as_u64()
+                .unwrap_or(DEFAULT_WAIT_TIMEOUT_MS);
+            match spawn_blocking_bounded(move || wait_for_window(&pattern, timeout_ms))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(window) => json!({ "status": "success", "window": window }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("open_or_focus_application") => {
+            let app = req["app"].as_str().unwrap_or("").to_string();
+            let launch_cmd = req["launch_command"].as_str().map(String::from);
+            match spawn_blocking_bounded(move || {
+                open_or_focus_application(&app, launch_cmd.as_deref())
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(None) => json!({ "status": "success" }),
+                Ok(Some(warning)) => json!({ "status": "success", "warning": warning }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Action Recording
+        Some("start_recording") => {
+            let name = req["name"].as_str().unwrap_or("Unnamed");
+            let description = req["description"].as_str().unwrap_or("");
+            let source = req["source"].as_str().unwrap_or("socket");
+            if source != "socket" && source != "input" {
+                return json!({
+                    "status": "error",
+                    "message": format!("Unknown recording source '{}'; use socket or input", source)
+                });
+            }
+            let shared = Arc::clone(state);
+            let mut state = state.lock().unwrap();
+            match state
+                .recordings
+                .start(name.to_string(), description.to_string())
+            {
+                Ok(session_id) => {
+                    if req["screenshots"].as_bool().unwrap_or(false) {
+                        let dir = paths::tmp_dir(state.profile.as_deref())
+                            .join(format!("{}.screenshots", session_id));
+                        // A previous daemon may have left one with the same session ID
+                        let _ = std::fs::remove_dir_all(&dir);
+                        if let Err(e) = std::fs::create_dir_all(&dir) {
+                            let _ = state.recordings.stop(Some(&session_id));
+                            return json!({
+                                "status": "error",
+                                "message": format!("Failed to create screenshot directory: {}", e)
+                            });
+                        }
+                        state.recording_screenshots.insert(session_id.clone(), dir);
+                    }
+                    if source == "input" {
+                        match record_input(shared, session_id.clone()) {
+                            Ok(recorder) => {
+                                state.input_recorders.insert(session_id.clone(), recorder);
+                            }
+                            Err(e) => {
+                                let _ = state.recordings.stop(Some(&session_id));
+                                if let Some(dir) = state.recording_screenshots.remove(&session_id) {
+                                    let _ = std::fs::remove_dir_all(dir);
+                                }
+                                return json!({ "status": "error", "message": e });
+                            }
+                        }
+                    }
+                    state.set_sensor(
+                        Sensor::Recording,
+                        &recording_detail(name, &session_id),
+                        true,
+                    );
+                    state.emit(
+                        "recording_started",
+                        json!({ "name": name, "session_id": session_id, "source": source }),
+                    );
+                    json!({
+                        "status": "success",
+                        "message": "Recording started",
+                        "session_id": session_id
+                    })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("stop_recording") => {
+            let session_id = req["session_id"].as_str();
+            // The input recorder's thread records into the state, so it is
+            // stopped (and its last actions flushed) without holding the lock
+            let recorder = {
+                let mut state = state.lock().unwrap();
+                match state.recordings.resolve(session_id) {
+                    Ok(id) => state.input_recorders.remove(&id),
+                    Err(_) => None,
+                }
+            };
+            if let Some(recorder) = recorder {
+                recorder.stop();
+            }
+            let environment = req["environment"]
+                .as_bool()
+                .unwrap_or(false)
+                .then(EnvironmentSnapshot::capture);
+            let mut state = state.lock().unwrap();
+            // Checked first so a name clash does not lose the recording
+            if is_temporary_request(req)
+                && let Some(name) = recording_name(&state, session_id)
+                && state.library.is_saved(&name)
+            {
+                return json!({
+                    "status": "error",
+                    "message": format!(
+                        "A saved sequence is named '{}'; temporary sequences need their own name",
+                        name
+                    )
+                });
+            }
+            match state.recordings.stop_with_journal(session_id) {
+                Ok((session_id, mut sequence, journal)) => {
+                    sequence.environment = environment;
+                    let mut screenshots = None;
+                    if let Some(dir) = state.recording_screenshots.remove(&session_id) {
+                        // Temporary sequences have no directory to keep them in
+                        let taken = sequence.actions.iter().any(|s| s.screenshot.is_some());
+                        let stored = if taken && !is_temporary_request(req) {
+                            state.library.store_screenshots(&sequence.name, &dir)
+                        } else {
+                            Err("No screenshots to keep".to_string())
+                        };
+                        match stored {
+                            Ok(()) => {
+                                screenshots = Some(state.library.screenshots_dir(&sequence.name))
+                            }
+                            Err(_) => {
+                                let _ = std::fs::remove_dir_all(&dir);
+                                for step in &mut sequence.actions {
+                                    step.screenshot = None;
+                                }
+                            }
+                        }
+                    }
+                    let saved = add_to_library(&mut state.library, sequence.clone(), req);
+                    let detail = recording_detail(&sequence.name, &session_id);
+                    state.set_sensor(Sensor::Recording, &detail, false);
+                    if let Err(e) = saved {
+                        let mut response = json!({
+                            "status": "error",
+                            "message": format!("Recording stopped but not saved: {}", e),
+                            "session_id": session_id
+                        });
+                        // The journal stays, so the steps can still be recovered
+                        if let Some(recording) = journal.and_then(RecordingJournal::keep) {
+                            response["recording_id"] = json!(recording.id);
+                            state.interrupted.push(recording);
+                        }
+                        return response;
+                    }
+                    if let Some(journal) = journal
+                        && let Err(e) = journal.remove()
+                    {
+                        eprintln!("{}", e);
+                    }
+                    state.emit(
+                        "recording_stopped",
+                        json!({
+                            "sequence": sequence.name,
+                            "session_id": session_id,
+                            "steps": sequence.actions.len()
+                        }),
+                    );
+                    state.emit("sequence_saved", json!({ "sequence": sequence.name }));
+                    let mut response = json!({
+                        "status": "success",
+                        "message": "Recording stopped",
+                        "sequence": sequence.name,
+                        "session_id": session_id
+                    });
+                    if let Some(dir) = screenshots {
+                        response["screenshots_dir"] = json!(dir);
+                    }
+                    response
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("record_action") => {
+            let action_type = req["action"].as_str().unwrap_or("");
+            let action = match action_type {
+                "move_mouse" if req["relative"].as_bool().unwrap_or(false) => {
+                    let dx = req["x"].as_i64().unwrap_or(0) as i32;
+                    let dy = req["y"].as_i64().unwrap_or(0) as i32;
+                    Action::MoveMouseRel { dx, dy }
+                }
+                "move_mouse" => {
+                    let x = req["x"].as_i64().unwrap_or(0) as i32;
+                    let y = req["y"].as_i64().unwrap_or(0) as i32;
+                    Action::MoveMouse {
+                        x,
+                        y,
+                        anchor: request_anchor(req),
+                    }
+                }
+                "click_mouse" => {
+                    let button = req["button"].as_str().unwrap_or("left").to_string();
+                    Action::ClickMouse {
+                        button,
+                        x: req["x"].as_i64().map(|x| x as i32),
+                        y: req["y"].as_i64().map(|y| y as i32),
+                        anchor: request_anchor(req),
+                        settle_ms: req["settle_ms"].as_u64(),
+                        count: request_count(req),
+                    }
+                }
+                "type_text" => {
+                    let text = req["text"].as_str().unwrap_or("").to_string();
+                    Action::TypeText {
+                        text,
+                        sensitive: req["sensitive"].as_bool().unwrap_or(false),
+                        delay_ms: req["delay_ms"].as_u64(),
+                        via_clipboard: req["via_clipboard"].as_bool().unwrap_or(false),
+                        layout: req["layout"].as_str().map(str::to_string),
+                    }
+                }
+                "press_key" => {
+                    let key = req["key"].as_str().unwrap_or("").to_string();
+                    Action::PressKey { key }
+                }
+                "press_combo" => {
+                    let combo = req["combo"].as_str().unwrap_or("").to_string();
+                    Action::PressCombo { combo }
+                }
+                "wait" => {
+                    let ms = req["milliseconds"].as_u64().unwrap_or(1000);
+                    Action::Wait { milliseconds: ms }
+                }
+                "app_action" => {
+                    let name = req["name"].as_str().unwrap_or("").to_string();
+                    Action::AppAction { name }
+                }
+                _ => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Unknown action type: {}", action_type)
+                    });
+                }
+            };
+
+            let step_id = req["id"].as_str().map(|s| s.to_string());
+            let label = req["label"].as_str().map(|s| s.to_string());
+            let comment = req["comment"].as_str().map(|s| s.to_string());
+            let session_id = req["session_id"].as_str();
+            let resolved = state.lock().unwrap().recordings.resolve(session_id);
+            let screenshot = match resolved {
+                Ok(id) => {
+                    let shared = Arc::clone(state);
+                    spawn_blocking_bounded(move || step_screenshot(&shared, &id))
+                        .await
+                        .ok()
+                        .flatten()
+                }
+                Err(_) => None,
+            };
+            let mut state = state.lock().unwrap();
+            let result = state
+                .recordings
+                .record_step(session_id, action, step_id, label, comment);
+            match result {
+                Ok(id) => {
+                    if let Some(file) = screenshot {
+                        let _ = state.recordings.attach_screenshot(&id, file);
+                    }
+                    state.emit(
+                        "action_recorded",
+                        json!({ "action": action_type, "session_id": session_id }),
+                    );
+                    json!({ "status": "success", "message": "Action recorded" })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("capture_screen")
+        | Some("capture_region")
+        | Some("capture_window")
+        | Some("capture_monitor") => {
+            if state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let source = match capture_source(req) {
+                Ok(source) => source,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let path = req["path"].as_str().map(str::to_string);
+            let mut options: CaptureOptions = match serde_json::from_value(req.clone()) {
+                Ok(options) => options,
+                Err(e) => return json!({ "status": "error", "message": e.to_string() }),
+            };
+            // Without a format, a path ending in .jpg or .webp picks one
+            if req["format"].is_null()
+                && let Some(format) = path.as_deref().and_then(ImageFormat::from_path)
+            {
+                options.format = format;
+            }
+            let captured = spawn_blocking_bounded(move || {
+                let capture = ScreenCapture::new()?.with_options(options);
+                match path {
+                    Some(path) => capture
+                        .capture(&source, &path)
+                        .map(|()| json!({ "status": "success", "path": path })),
+                    None => capture.capture_image(&source).map(|frame| {
+                        json!({ "status": "success", "mime": frame.mime, "data": frame.to_base64() })
+                    }),
+                }
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+            captured.unwrap_or_else(|e| json!({ "status": "error", "message": e }))
+        }
+        Some("start_capture_stream") => {
+            let source = match frame_source(req) {
+                Ok(source) => source,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let capture = match ScreenCapture::new() {
+                Ok(capture) => Arc::new(capture),
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let interval = req["interval_ms"]
+                .as_u64()
+                .map_or(DEFAULT_CAPTURE_STREAM_INTERVAL, Duration::from_millis)
+                .max(MIN_CAPTURE_STREAM_INTERVAL);
+            let mut locked = state.lock().unwrap();
+            locked.next_capture_stream += 1;
+            let stream_id = format!("stream-{}", locked.next_capture_stream);
+            // The task waits for the lock held here, so the stream is listed before its first frame
+            let task = tokio::spawn(run_capture_stream(
+                Arc::clone(state),
+                stream_id.clone(),
+                capture,
+                source,
+                interval,
+                req["max_frames"].as_u64(),
+            ));
+            locked.capture_streams.insert(
+                stream_id.clone(),
+                CaptureStream {
+                    interval_ms: interval.as_millis() as u64,
+                    frames: 0,
+                    task: task.abort_handle(),
+                },
+            );
+            locked.set_sensor(Sensor::ScreenWatching, &stream_id, true);
+            json!({ "status": "success", "stream_id": stream_id })
+        }
+        Some("stop_capture_stream") => {
+            let stream_id = req["stream_id"].as_str().unwrap_or("");
+            match state.lock().unwrap().remove_capture_stream(stream_id) {
+                Some(stream) => {
+                    stream.task.abort();
+                    json!({ "status": "success", "frames": stream.frames })
+                }
+                None => {
+                    json!({ "status": "error", "message": format!("No capture stream '{}'", stream_id) })
+                }
+            }
+        }
+        Some("list_capture_streams") => {
+            let state = state.lock().unwrap();
+            let streams: Vec<_> = state
+                .capture_streams
+                .iter()
+                .map(|(id, stream)| {
+                    json!({ "stream_id": id, "interval_ms": stream.interval_ms, "frames": stream.frames })
+                })
+                .collect();
+            json!({ "status": "success", "streams": streams })
+        }
+        Some("capture_point") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let (x, y) = match get_mouse_position() {
+                Ok(position) => position,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let label = req["label"].as_str().map(|s| s.to_string());
+            let mut state = state.lock().unwrap();
+            let session_id = req["session_id"].as_str();
+            match state
+                .recordings
+                .capture_point(session_id, name, Point { x, y }, label)
+            {
+                Ok(session_id) => {
+                    state.emit(
+                        "point_captured",
+                        json!({ "name": name, "x": x, "y": y, "session_id": session_id }),
+                    );
+                    json!({ "status": "success", "name": name, "x": x, "y": y })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("set_sequence_point") => {
+            let sequence_name = req["sequence"].as_str().unwrap_or("");
+            let name = req["name"].as_str().unwrap_or("");
+            // Without coordinates the point is re-captured at the mouse position
+            let point = match (req["x"].as_i64(), req["y"].as_i64()) {
+                (Some(x), Some(y)) => Point {
+                    x: x as i32,
+                    y: y as i32,
+                },
+                _ => match get_mouse_position() {
+                    Ok((x, y)) => Point { x, y },
+                    Err(e) => return json!({ "status": "error", "message": e }),
+                },
+            };
+            let mut state = state.lock().unwrap();
+            let Some(sequence) = state.library.get_sequence_mut(sequence_name) else {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", sequence_name)
+                });
+            };
+            if !sequence.points.contains_key(name) {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence '{}' has no point '{}'", sequence_name, name)
+                });
+            }
+            sequence.points.insert(name.to_string(), point);
+            match state.library.save_all() {
+                Ok(_) => json!({ "status": "success", "name": name, "x": point.x, "y": point.y }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("set_error_policy") => {
+            let sequence_name = req["sequence"].as_str().unwrap_or("");
+            // null clears the policy, falling back to the sequence's (or abort)
+            let policy: Option<ErrorPolicy> = match serde_json::from_value(req["on_error"].clone())
+            {
+                Ok(policy) => policy,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid on_error: {}", e)
+                    });
+                }
+            };
+            let mut state = state.lock().unwrap();
+            let Some(sequence) = state.library.get_sequence_mut(sequence_name) else {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", sequence_name)
+                });
+            };
+            match StepRef::from_json(&req["step"]) {
+                Some(step) => match sequence.resolve_step(&step) {
+                    Ok(index) => sequence.actions[index].on_error = policy,
+                    Err(e) => return json!({ "status": "error", "message": e }),
+                },
+                None => sequence.on_error = policy,
+            }
+            match state.library.save_all() {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("set_concurrency") => {
+            let sequence_name = req["sequence"].as_str().unwrap_or("");
+            let concurrency: Concurrency = match serde_json::from_value(req["concurrency"].clone())
+            {
+                Ok(concurrency) => concurrency,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid concurrency: {}", e)
+                    });
+                }
+            };
+            let mut state = state.lock().unwrap();
+            let Some(sequence) = state.library.get_sequence_mut(sequence_name) else {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", sequence_name)
+                });
+            };
+            sequence.concurrency = Some(concurrency);
+            match state.library.save_all() {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("is_recording") => {
+            let state = state.lock().unwrap();
+            let recording = match req["session_id"].as_str() {
+                Some(id) => state.recordings.resolve(Some(id)).is_ok(),
+                None => state.recordings.is_recording(),
+            };
+            json!({ "status": "success", "recording": recording })
+        }
+        Some("list_recordings") => {
+            let state = state.lock().unwrap();
+            json!({ "status": "success", "recordings": recording_list(&state) })
+        }
+        Some("list_interrupted_recordings") => {
+            let state = state.lock().unwrap();
+            json!({
+                "status": "success",
+                "recordings": interrupted_list(&state.interrupted)
+            })
+        }
+        Some("recover_recording") => {
+            let id = req["id"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            let Some(index) = state.interrupted.iter().position(|r| r.id == id) else {
+                return json!({
+                    "status": "error",
+                    "message": format!("No interrupted recording '{}'", id)
+                });
+            };
+            let name = req["name"]
+                .as_str()
+                .unwrap_or(&state.interrupted[index].sequence.name)
+                .to_string();
+            if state.library.get_sequence(&name).is_some() {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence '{}' already exists; pass another name", name)
+                });
+            }
+
+            let recording = state.interrupted.remove(index);
+            let mut sequence = recording.sequence;
+            sequence.name = name;
+            state.library.add_sequence(sequence.clone());
+            if let Err(e) = state.library.save_all() {
+                return json!({ "status": "error", "message": e });
+            }
+            let _ = std::fs::remove_file(&recording.path);
+            state.emit("sequence_saved", json!({ "sequence": sequence.name }));
+            json!({
+                "status": "success",
+                "sequence": sequence.name,
+                "steps": sequence.actions.len()
+            })
+        }
+        Some("discard_recording") => {
+            let id = req["id"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            match state.interrupted.iter().position(|r| r.id == id) {
+                Some(index) => {
+                    let recording = state.interrupted.remove(index);
+                    let _ = std::fs::remove_file(&recording.path);
+                    json!({ "status": "success" })
+                }
+                None => json!({
+                    "status": "error",
+                    "message": format!("No interrupted recording '{}'", id)
+                }),
+            }
+        }
+
+        // Action Playback
+        Some("load_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let sequence_clone = {
+                let state = state.lock().unwrap();
+                state.library.get_sequence(name).cloned()
+            };
+
+            if let Some(sequence) = sequence_clone {
+                if sequence.draft && !req["allow_draft"].as_bool().unwrap_or(false) {
+                    return json!({
+                        "status": "error",
+                        "message": format!(
+                            "Sequence '{}' is a draft; review it and send approve_sequence first",
+                            sequence.name
+                        )
+                    });
+                }
+                let labels: Vec<_> = sequence
+                    .actions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, step)| {
+                        step.id.is_some() || step.label.is_some() || step.comment.is_some()
+                    })
+                    .map(|(index, step)| {
+                        json!({
+                            "index": index,
+                            "id": step.id,
+                            "label": step.label,
+                            "comment": step.comment,
+                        })
+                    })
+                    .collect();
+                let warnings = sequence
+                    .environment
+                    .as_ref()
+                    .map(|recorded| recorded.differences(&EnvironmentSnapshot::capture()))
+                    .unwrap_or_default();
+                let state = state.lock().unwrap();
+                let mut player = state.player.lock().unwrap();
+                if player.is_playing() {
+                    return json!({
+                        "status": "error",
+                        "message": "A sequence is playing; wait for it to finish first"
+                    });
+                }
+                player.load_sequence(sequence.clone());
+                json!({
+                    "status": "success",
+                    "message": format!("Loaded sequence: {}", sequence.name),
+                    "labels": labels,
+                    "warnings": warnings
+                })
+            } else {
+                json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", name)
+                })
+            }
+        }
+        Some("play_sequence") => {
+            // "start"/"end" accept a step index, label or checkpoint name;
+            // "start_at" and "from_label" are kept as aliases
+            let start = StepRef::from_json(&req["start"])
+                .or_else(|| StepRef::from_json(&req["start_at"]))
+                .or_else(|| StepRef::from_json(&req["from_label"]));
+            let end = StepRef::from_json(&req["end"]);
+            let options = match PlaybackOptions::new(
+                req["speed"].as_f64().unwrap_or(1.0),
+                req["no_delays"].as_bool().unwrap_or(false),
+            ) {
+                Ok(options) => options,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let concurrency: Option<Concurrency> =
+                match serde_json::from_value(req["concurrency"].clone()) {
+                    Ok(concurrency) => concurrency,
+                    Err(e) => {
+                        return json!({
+                            "status": "error",
+                            "message": format!("Invalid concurrency: {}", e)
+                        });
+                    }
+                };
+            let block_input = match panic_chord(req, "block_input") {
+                Ok(chord) => chord,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let dry = req["dry_run"].as_bool().unwrap_or(false);
+            let mut locked = state.lock().unwrap();
+            // With a name the sequence is loaded here, otherwise the loaded one plays
+            let sequence = match req["name"].as_str() {
+                Some(name) => match locked.library.get_sequence(name) {
+                    Some(sequence) if sequence.draft && !dry => {
+                        return json!({
+                            "status": "error",
+                            "message": format!(
+                                "Sequence '{}' is a draft; review it and send approve_sequence first",
+                                name
+                            )
+                        });
+                    }
+                    Some(sequence) => sequence.clone(),
+                    None => {
+                        return json!({
+                            "status": "error",
+                            "message": format!("Sequence not found: {}", name)
+                        });
+                    }
+                },
+                None => match locked.player.lock().unwrap().sequence() {
+                    Some(sequence) => sequence.clone(),
+                    None => return json!({ "status": "error", "message": "No sequence loaded" }),
+                },
+            };
+            if dry {
+                // Drafts can be checked too; nothing is played or queued
+                let allowlist = locked.config.commands.allowlist.clone();
+                drop(locked);
+                let lookup = sequence_lookup(state);
+                return match dry_run(
+                    &sequence,
+                    start.as_ref(),
+                    end.as_ref(),
+                    options,
+                    &lookup,
+                    allowlist.as_deref(),
+                ) {
+                    Ok(plan) => json!({
+                        "status": "success",
+                        "dry_run": true,
+                        "sequence": sequence.name,
+                        "valid": plan.valid,
+                        "duration_ms": plan.duration_ms,
+                        "steps": plan.steps
+                    }),
+                    Err(e) => json!({ "status": "error", "message": e }),
+                };
+            }
+            let playback = QueuedPlayback {
+                concurrency: concurrency.or(sequence.concurrency).unwrap_or_default(),
+                sequence,
+                start,
+                end,
+                options,
+                capture_on_error: req["capture_on_error"].as_bool(),
+                block_input,
+            };
+
+            match play_or_queue(state, &mut locked, playback) {
+                Ok(PlaybackStart::Started) => {
+                    json!({ "status": "success", "message": "Playback started" })
+                }
+                Ok(PlaybackStart::Queued(position)) => json!({
+                    "status": "success",
+                    "message": "Playback queued",
+                    "queued": position
+                }),
+                Ok(PlaybackStart::Skipped) => json!({
+                    "status": "success",
+                    "message": "Skipped: a sequence is already playing",
+                    "skipped": true
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("pause_playback") | Some("resume_playback") => {
+            let pause = req["type"] == "pause_playback";
+            let state = state.lock().unwrap();
+            let result = {
+                let mut player = state.player.lock().unwrap();
+                let result = if pause {
+                    player.pause_playback()
+                } else {
+                    player.resume_playback()
+                };
+                result.map(|_| {
+                    (
+                        player.sequence().map(|s| s.name.clone()),
+                        player.get_progress(),
+                    )
+                })
+            };
+            match result {
+                Ok((name, (current, total))) => {
+                    let event = if pause {
+                        "playback_paused"
+                    } else {
+                        "playback_resumed"
+                    };
+                    state.emit(event, json!({ "sequence": name, "step": current }));
+                    json!({ "status": "success", "step": current, "total": total })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("stop_playback") => {
+            let mut state = state.lock().unwrap();
+            // Queued playbacks would otherwise start as soon as this one ends
+            let cleared = state.playback_queue.len();
+            state.playback_queue.clear();
+            let mut player = state.player.lock().unwrap();
+            if !player.is_playing() {
+                return json!({ "status": "error", "message": "Nothing is playing" });
+            }
+            // The playback task reports playback_stopped once its current step is done
+            player.stop_playback();
+            json!({ "status": "success", "message": "Playback stopped", "cleared": cleared })
+        }
+        Some("abort_all") => {
+            let mut response = abort_all(state);
+            response["status"] = json!("success");
+            response
+        }
+        Some("block_input") => {
+            let combo = req["panic_key"].as_str().unwrap_or(DEFAULT_PANIC_KEY);
+            let panic = match PanicChord::parse(combo) {
+                Ok(panic) => panic,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let timeout = req["timeout_ms"]
+                .as_u64()
+                .map(Duration::from_millis)
+                .unwrap_or(INPUT_BLOCK_TIMEOUT);
+            let mut state = state.lock().unwrap();
+            if state
+                .input_block
+                .as_ref()
+                .is_some_and(InputBlock::is_active)
+            {
+                return json!({ "status": "error", "message": "Input is already blocked" });
+            }
+            match start_input_block(&state, panic, timeout) {
+                Ok(block) => {
+                    let devices = block.devices().to_vec();
+                    state.input_block = Some(block);
+                    json!({
+                        "status": "success",
+                        "message": format!("Input blocked; press {} to release it", combo),
+                        "devices": devices,
+                        "timeout_ms": timeout.as_millis() as u64
+                    })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("unblock_input") => {
+            let block = state.lock().unwrap().input_block.take();
+            match block {
+                Some(block) if block.is_active() => {
+                    block.stop();
+                    json!({ "status": "success", "message": "Input released" })
+                }
+                _ => json!({ "status": "error", "message": "Input is not blocked" }),
+            }
+        }
+        Some("start_step_playback") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let options = match PlaybackOptions::new(
+                req["speed"].as_f64().unwrap_or(1.0),
+                req["no_delays"].as_bool().unwrap_or(false),
+            ) {
+                Ok(options) => options,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let mut state = state.lock().unwrap();
+            if state.player.lock().unwrap().is_playing() {
+                return json!({
+                    "status": "error",
+                    "message": "A sequence is playing; wait for it to finish first"
+                });
+            }
+            if state.step_session.as_ref().is_some_and(|s| s.is_running()) {
+                return json!({ "status": "error", "message": "A step is running" });
+            }
+            let sequence = match state.library.get_sequence(name) {
+                Some(sequence)
+                    if sequence.draft && !req["allow_draft"].as_bool().unwrap_or(false) =>
+                {
+                    return json!({
+                        "status": "error",
+                        "message": format!(
+                            "Sequence '{}' is a draft; review it and send approve_sequence first",
+                            name
+                        )
+                    });
+                }
+                Some(sequence) => sequence.clone(),
+                None => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Sequence not found: {}", name)
+                    });
+                }
+            };
+            match StepSession::new(sequence, options) {
+                Ok(session) => {
+                    let response = step_session_status(&session);
+                    state.step_session = Some(session);
+                    state.emit("step_playback_started", json!({ "sequence": name }));
+                    response
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("step_playback") => {
+            let action: Option<Action> = match req.get("action") {
+                Some(action) if !action.is_null() => match serde_json::from_value(action.clone()) {
+                    Ok(action) => Some(action),
+                    Err(e) => {
+                        return json!({
+                            "status": "error",
+                            "message": format!("Invalid action: {}", e)
+                        });
+                    }
+                },
+                _ => None,
+            };
+            let skip = req["skip"].as_bool().unwrap_or(false);
+            let pending = {
+                let mut locked = state.lock().unwrap();
+                let Some(session) = locked.step_session.as_mut() else {
+                    return json!({ "status": "error", "message": "No step playback; send start_step_playback first" });
+                };
+                if skip {
+                    return match session.skip() {
+                        Ok(result) => {
+                            let result = json!(result);
+                            let mut response = step_session_status(session);
+                            response["result"] = result;
+                            response
+                        }
+                        Err(e) => json!({ "status": "error", "message": e }),
+                    };
+                }
+                match session.begin(action) {
+                    Ok(pending) => pending,
+                    Err(e) => return json!({ "status": "error", "message": e }),
+                }
+            };
+            let session_id = pending.session();
+            let result = pending.perform(sequence_lookup(state)).await;
+            let mut locked = state.lock().unwrap();
+            let Some(session) = locked.step_session.as_mut() else {
+                return json!({ "status": "error", "message": "Step playback was stopped" });
+            };
+            let result = match session.finish(session_id, result) {
+                Ok(result) => json!(result),
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let mut response = step_session_status(session);
+            response["result"] = result.clone();
+            locked.emit("step_played", result);
+            response
+        }
+        Some("step_playback_status") => {
+            let state = state.lock().unwrap();
+            match &state.step_session {
+                Some(session) => {
+                    let mut response = step_session_status(session);
+                    response["results"] = json!(session.results());
+                    response
+                }
+                None => json!({ "status": "error", "message": "No step playback" }),
+            }
+        }
+        Some("set_next_step") => {
+            let Some(step) = StepRef::from_json(&req["step"]) else {
+                return json!({ "status": "error", "message": "step must be an index or label" });
+            };
+            let mut state = state.lock().unwrap();
+            let Some(session) = state.step_session.as_mut() else {
+                return json!({ "status": "error", "message": "No step playback" });
+            };
+            match session.jump(&step) {
+                Ok(_) => step_session_status(session),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("stop_step_playback") => {
+            let mut state = state.lock().unwrap();
+            match state.step_session.take() {
+                Some(session) => {
+                    // A step still running is interrupted at its next wait
+                    session.stop();
+                    let name = session.sequence().name.clone();
+                    state.emit("step_playback_stopped", json!({ "sequence": name }));
+                    json!({
+                        "status": "success",
+                        "message": "Step playback stopped",
+                        "results": session.results()
+                    })
+                }
+                None => json!({ "status": "error", "message": "No step playback" }),
+            }
+        }
+        Some("generate_sequence") => {
+            let description = req["description"].as_str().unwrap_or("").trim();
+            if description.is_empty() {
+                return json!({ "status": "error", "message": "Missing description" });
+            }
+            let name = req["name"]
+                .as_str()
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| draft_name(description));
+            // A draft may replace a draft, but not a sequence someone approved
+            let overwrite = req["overwrite"].as_bool().unwrap_or(false);
+            let approved = |state: &DaemonState| {
+                let existing = state.library.get_sequence(&name);
+                !overwrite && existing.is_some_and(|sequence| !sequence.draft)
+            };
+            let refusal = || {
+                json!({
+                    "status": "error",
+                    "message": format!(
+                        "Sequence '{}' already exists; pick another name or send \"overwrite\": true",
+                        name
+                    )
+                })
+            };
+            if approved(&state.lock().unwrap()) {
+                return refusal();
+            }
+
+            let vision = match AIVision::from_env() {
+                Ok(v) => v,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+
+            // Give the model the current screen as context when we can capture it
+            let privacy = state.lock().unwrap().modes.privacy;
+            let screenshot = if req["use_screen"].as_bool().unwrap_or(true) && !privacy {
+                capture_screen_temp().ok()
+            } else {
+                None
+            };
+            let result = vision
+                .generate_sequence(&name, description, screenshot.as_deref())
+                .await;
+            if let Some(path) = screenshot {
+                let _ = std::fs::remove_file(path);
+            }
+
+            match result {
+                Ok(sequence) => {
+                    let mut state = state.lock().unwrap();
+                    if approved(&state) {
+                        return refusal();
+                    }
+                    if let Err(e) = add_to_library(&mut state.library, sequence.clone(), req) {
+                        return json!({ "status": "error", "message": e });
+                    }
+                    state.emit(
+                        "sequence_saved",
+                        json!({ "sequence": sequence.name, "draft": true }),
+                    );
+                    json!({
+                        "status": "success",
+                        "message": format!("Draft sequence saved: {}", sequence.name),
+                        "sequence": sequence
+                    })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("explain_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let sequence = {
+                let state = state.lock().unwrap();
+                state.library.get_sequence(name).cloned()
+            };
+            let Some(sequence) = sequence else {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", name)
+                });
+            };
+
+            let risky_steps: Vec<_> = sequence
+                .risky_steps()
+                .into_iter()
+                .map(|(index, reason)| {
+                    json!({
+                        "index": index,
+                        "label": sequence.actions[index].label,
+                        "reason": reason,
+                    })
+                })
+                .collect();
+
+            let summary = match AIVision::from_env() {
+                Ok(vision) => vision.explain_sequence(&sequence).await,
+                Err(e) => Err(e),
+            };
+            match summary {
+                Ok(summary) => json!({
+                    "status": "success",
+                    "summary": summary,
+                    "risky_steps": risky_steps
+                }),
+                // The locally detected risks are still worth showing without a model
+                Err(e) => json!({
+                    "status": "error",
+                    "message": e,
+                    "risky_steps": risky_steps
+                }),
+            }
+        }
+        Some("approve_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            match state.library.get_sequence_mut(name) {
+                Some(sequence) => {
+                    sequence.draft = false;
+                    match state.library.save_all() {
+                        Ok(_) => json!({
+                            "status": "success",
+                            "message": format!("Approved sequence: {}", name)
+                        }),
+                        Err(e) => json!({ "status": "error", "message": e }),
+                    }
+                }
+                None => json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", name)
+                }),
+            }
+        }
+        Some("list_sequences") => {
+            let state = state.lock().unwrap();
+            let summaries = state.library.summaries(req["tag"].as_str());
+            // Plain names unless asked for more, as protocol 1 clients expect
+            let sequences = if req["details"].as_bool().unwrap_or(false) {
+                json!(summaries)
+            } else {
+                json!(summaries.iter().map(|s| &s.name).collect::<Vec<_>>())
+            };
+            json!({
+                "status": "success",
+                "sequences": sequences,
+                "temporary": state.library.temporary_sequences()
+            })
+        }
+        Some("save_sequence") => {
+            let sequence = match serde_json::from_value::<ActionSequence>(req["sequence"].clone()) {
+                Ok(sequence) if sequence.name.trim().is_empty() => {
+                    return json!({ "status": "error", "message": "The sequence needs a name" });
+                }
+                Ok(sequence) => sequence,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid sequence: {}", e)
+                    });
+                }
+            };
+            if let Err(e) = sequence.check_not_redacted() {
+                return json!({ "status": "error", "message": e });
+            }
+            let mut state = state.lock().unwrap();
+            match add_to_library(&mut state.library, sequence.clone(), req) {
+                Ok(temporary) => {
+                    state.emit("sequence_saved", json!({ "sequence": sequence.name }));
+                    json!({ "status": "success", "sequence": sequence.name, "temporary": temporary })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("delete_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            match state.library.delete_sequence(name) {
+                Ok(_) => {
+                    state.emit("sequence_deleted", json!({ "sequence": name }));
+                    json!({
+                        "status": "success",
+                        "message": format!("Deleted sequence: {}", name)
+                    })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        Some("get_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let state = state.lock().unwrap();
+            match state.library.get_sequence(name) {
+                Some(sequence) => {
+                    let sequence = match req["reveal"].as_bool().unwrap_or(false) {
+                        true => sequence.clone(),
+                        false => sequence.redacted(),
+                    };
+                    let mut response = json!({ "status": "success", "sequence": sequence });
+                    let screenshots = state.library.screenshots_dir(name);
+                    if screenshots.exists() {
+                        response["screenshots_dir"] = json!(screenshots);
+                    }
+                    response
+                }
+                None => json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", name)
+                }),
+            }
+        }
+        Some("update_action")
+        | Some("insert_action")
+        | Some("remove_action")
+        | Some("move_action") => {
+            let name = req["sequence"].as_str().unwrap_or("");
+            let step = StepRef::from_json(&req["step"]);
+            let action = match req["type"].as_str() {
+                Some("update_action") | Some("insert_action") => {
+                    match serde_json::from_value::<Action>(req["action"].clone()) {
+                        Ok(action) => match action.check_not_redacted() {
+                            Ok(()) => Some(action),
+                            Err(e) => return json!({ "status": "error", "message": e }),
+                        },
+                        Err(e) => {
+                            return json!({
+                                "status": "error",
+                                "message": format!("Invalid action: {}", e)
+                            });
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let mut state = state.lock().unwrap();
+            let library = &mut state.library;
+            let result = match (req["type"].as_str(), step, action) {
+                (Some("update_action"), Some(step), Some(action)) => library
+                    .update_action(name, &step, action)
+                    .map(|index| json!({ "index": index })),
+                (Some("insert_action"), _, Some(action)) => {
+                    let mut step =
+                        ActionWithTimestamp::new(action, req["delay_ms"].as_u64().unwrap_or(0));
+                    step.id = req["id"].as_str().map(str::to_string);
+                    step.label = req["label"].as_str().map(str::to_string);
+                    step.comment = req["comment"].as_str().map(str::to_string);
+                    let index = req["index"].as_u64().map(|index| index as usize);
+                    library
+                        .insert_action(name, index, step)
+                        .map(|index| json!({ "index": index }))
+                }
+                (Some("remove_action"), Some(step), _) => {
+                    library.remove_action(name, &step).map(|mut removed| {
+                        removed.action = removed.action.redacted();
+                        json!({ "removed": removed })
+                    })
+                }
+                (Some("move_action"), Some(step), _) => match req["to"].as_u64() {
+                    Some(to) => library
+                        .move_action(name, &step, to as usize)
+                        .map(|from| json!({ "from": from, "to": to })),
+                    None => Err("Missing 'to' position".to_string()),
+                },
+                _ => Err("Missing 'step' (an index or label)".to_string()),
+            };
+            match result {
+                Ok(mut response) => {
+                    state.emit("sequence_saved", json!({ "sequence": name }));
+                    response["status"] = json!("success");
+                    response
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("rename_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let new_name = req["new_name"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            if let Err(e) = state.library.rename_sequence(name, new_name) {
+                return json!({ "status": "error", "message": e });
+            }
+            // Hotkeys follow the sequence
+            let mut rebound = false;
+            for sequence in state.config.hotkeys.values_mut() {
+                if sequence == name {
+                    *sequence = new_name.to_string();
+                    rebound = true;
+                }
+            }
+            if rebound {
+                let profile = state.profile.clone();
+                if let Err(e) = state.config.save(profile.as_deref()) {
+                    eprintln!("⚠️  {}", e);
+                }
+            }
+            state.emit("sequence_deleted", json!({ "sequence": name }));
+            state.emit(
+                "sequence_saved",
+                json!({ "sequence": new_name, "renamed_from": name }),
+            );
+            json!({
+                "status": "success",
+                "message": format!("Renamed sequence {} to {}", name, new_name)
+            })
+        }
+
+        Some("tag_sequence") | Some("untag_sequence") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let tags: Vec<String> = match serde_json::from_value(req["tags"].clone()) {
+                Ok(tags) => tags,
+                Err(_) => {
+                    return json!({ "status": "error", "message": "tags must be a list of strings" });
+                }
+            };
+            if tags.iter().any(|tag| tag.trim().is_empty()) {
+                return json!({ "status": "error", "message": "Tags cannot be empty" });
+            }
+            let adding = req["type"] == "tag_sequence";
+            let mut state = state.lock().unwrap();
+            let result = state.library.edit_sequence(name, |sequence| {
+                for tag in &tags {
+                    if adding {
+                        sequence.add_tag(tag.trim().to_string());
+                    } else {
+                        sequence.remove_tag(tag.trim());
+                    }
+                }
+                Ok(sequence.tags.clone())
+            });
+            match result {
+                Ok(tags) => {
+                    state.emit("sequence_saved", json!({ "sequence": name }));
+                    json!({ "status": "success", "sequence": name, "tags": tags })
+                }
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("get_run_history") => {
+            let name = req["name"].as_str();
+            let outcome: Option<RunOutcome> = match serde_json::from_value(req["outcome"].clone()) {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid outcome: {}", e)
+                    });
+                }
+            };
+            let profile = state.lock().unwrap().profile.clone();
+            let runs = RunHistory::new(paths::runs_dir(profile.as_deref())).runs(name);
+            let summary = RunSummary::of(&runs);
+            let runs: Vec<_> = runs
+                .into_iter()
+                .filter(|run| outcome.is_none_or(|outcome| run.outcome == outcome))
+                .collect();
+            json!({ "status": "success", "sequence": name, "summary": summary, "runs": runs })
+        }
+        Some("list_versions") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let state = state.lock().unwrap();
+            json!({
+                "status": "success",
+                "sequence": name,
+                "versions": state.library.list_versions(name)
+            })
+        }
+        Some("restore_version") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let version = req["version"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            let steps = match state.library.restore_version(name, version) {
+                Ok(sequence) => sequence.actions.len(),
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            state.emit(
+                "sequence_saved",
+                json!({ "sequence": name, "restored_version": version }),
+            );
+            json!({
+                "status": "success",
+                "message": format!("Restored sequence {} to version {}", name, version),
+                "steps": steps
+            })
+        }
+
+        Some("list_packs") => {
+            let state = state.lock().unwrap();
+            let packs: Vec<Value> = builtin_packs()
+                .iter()
+                .map(|pack| {
+                    json!({
+                        "name": pack.name,
+                        "description": pack.description,
+                        "sequences": pack.sequences.iter().map(|s| &s.name).collect::<Vec<_>>(),
+                        "pipelines": pack.pipelines.iter().map(|p| &p.name).collect::<Vec<_>>(),
+                        "installed": pack.is_installed(&state.library, &state.pipelines)
+                    })
+                })
+                .collect();
+            json!({ "status": "success", "packs": packs })
+        }
+        Some("install_pack") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let pack = match find_pack(name) {
+                Ok(pack) => pack,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let mut locked = state.lock().unwrap();
+            let state = &mut *locked;
+            if let Err(e) = pack.install(&mut state.library, &mut state.pipelines) {
+                return json!({ "status": "error", "message": e });
+            }
+            for sequence in &pack.sequences {
+                state.emit("sequence_saved", json!({ "sequence": sequence.name }));
+            }
+            json!({
+                "status": "success",
+                "pack": pack.name,
+                "sequences": pack.sequences.iter().map(|s| &s.name).collect::<Vec<_>>(),
+                "pipelines": pack.pipelines.iter().map(|p| &p.name).collect::<Vec<_>>()
+            })
+        }
+
+        // Notifications
+        Some("show_notification") => {
+            let summary = req["summary"].as_str().unwrap_or("");
+            let body = req["body"].as_str().unwrap_or("");
+            if state.lock().unwrap().modes.do_not_disturb {
+                return json!({ "status": "success", "suppressed": true });
+            }
+            let options = match serde_json::from_value::<NotificationOptions>(req.clone()) {
+                Ok(options) => options,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid notification options: {}", e)
+                    });
+                }
+            };
+            let (summary, body) = (summary.to_string(), body.to_string());
+            // Waiting for a click can take a while
+            let shown =
+                spawn_blocking_bounded(move || show_notification_with(&summary, &body, &options))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()));
+            match shown {
+                Ok(Some(action)) => json!({ "status": "success", "action": action }),
+                Ok(None) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // External Services
+        Some("connect_to_service") => {
+            let service = req["service"].as_str().unwrap_or("");
+            let action = req["action"].as_str().unwrap_or("");
+            match connect_to_service(service, action).await {
+                Ok(result) => json!({ "status": "success", "result": result }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // MCP
+        Some("process_mcp") => {
+            let data = req["data"].as_str().unwrap_or("");
+            match process_mcp(data) {
+                Ok(result) => json!({ "status": "success", "result": result }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // AI
+        Some("process_command") => {
+            let command = req["command"].as_str().unwrap_or("");
+            match process_command(command) {
+                Ok(result) => json!({ "status": "success", "result": result }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        Some("ask_ai") => {
+            let prompt = req["prompt"].as_str().unwrap_or("");
+            if prompt.is_empty() {
+                return json!({ "status": "error", "message": "Missing prompt" });
+            }
+            let vision = match AIVision::from_env() {
+                Ok(v) => v,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let use_screen = req["use_screen"].as_bool().unwrap_or(false);
+            if use_screen && state.lock().unwrap().modes.privacy {
+                return json!({
+                    "status": "error",
+                    "message": "Screen capture is disabled while privacy mode is on"
+                });
+            }
+            let result = if use_screen {
+                // One monitor keeps the image small and the model on the point
+                let captured = match req["monitor"].is_null() {
+                    true => capture_screen_temp(),
+                    false => serde_json::from_value::<DisplayRef>(req["monitor"].clone())
+                        .map_err(|_| "monitor must be a monitor name or index".to_string())
+                        .and_then(|monitor| capture_monitor_temp(&monitor)),
+                };
+                match captured {
+                    Ok(path) => {
+                        let answer = vision.analyze_screenshot(&path, prompt).await;
+                        let _ = std::fs::remove_file(path);
+                        answer
+                    }
+                    Err(e) => Err(e),
+                }
+            } else {
+                vision.complete(prompt).await
+            };
+            match result {
+                Ok(answer) => json!({ "status": "success", "result": answer }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Voice
+        Some("recognize_voice") => {
+            state
+                .lock()
+                .unwrap()
+                .set_sensor(Sensor::Listening, "recognize_voice", true);
+            let result = recognize_voice();
+            state
+                .lock()
+                .unwrap()
+                .set_sensor(Sensor::Listening, "recognize_voice", false);
+            match result {
+                Ok(result) => json!({ "status": "success", "result": result }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // TTS
+        Some("speak") => {
+            let text = req["text"].as_str().unwrap_or("");
+            if state.lock().unwrap().modes.do_not_disturb {
+                return json!({ "status": "success", "suppressed": true });
+            }
+            match speak(text) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Ping/Status
+        Some("ping") => {
+            let state = state.lock().unwrap();
+            json!({
+                "status": "success",
+                "message": "pong",
+                "version": "0.2.0",
+                "protocol_version": protocol::PROTOCOL_VERSION,
+                "profile": state.profile
+            })
+        }
+        Some("status") => {
+            let state = state.lock().unwrap();
+            let (current, total, playback_state) = {
+                let player = state.player.lock().unwrap();
+                let (current, total) = player.get_progress();
+                (current, total, player.state())
+            };
+            let favorites: Vec<_> = state
+                .library
+                .search_by_tag(FAVORITE_TAG)
+                .iter()
+                .map(|s| s.name.clone())
+                .collect();
+            json!({
+                "status": "success",
+                "profile": state.profile,
+                "recording": state.recordings.is_recording(),
+                "recordings": recording_list(&state),
+                "interrupted_recordings": interrupted_list(&state.interrupted),
+                "playing": playback_state != PlaybackState::Idle,
+                "playback": { "current": current, "total": total, "state": playback_state },
+                "queued_playbacks": state.playback_queue.len(),
+                "modes": {
+                    "privacy": state.modes.privacy,
+                    "do_not_disturb": state.modes.do_not_disturb,
+                    "automations_paused": state.modes.automations_paused
+                },
+                "favorites": favorites,
+                "active_sensors": state.sensors.active(),
+                "portal_only": portal::portal_only(),
+                "input_backend": input_backend().name(),
+                "window_backend": window_backend().name()
+            })
+        }
+        Some("active_sensors") => {
+            let state = state.lock().unwrap();
+            json!({ "status": "success", "active_sensors": state.sensors.active() })
+        }
+        // Window rules
+        Some("list_window_rules") => {
+            let state = state.lock().unwrap();
+            json!({ "status": "success", "rules": state.window_rules.rules() })
+        }
+        Some("add_window_rule") => {
+            let rule: WindowRule = match serde_json::from_value(req["rule"].clone()) {
+                Ok(rule) => rule,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid window rule: {}", e)
+                    });
+                }
+            };
+            let mut state = state.lock().unwrap();
+            match state.window_rules.add(rule) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("remove_window_rule") => {
+            let name = req["name"].as_str().unwrap_or("");
+            match state.lock().unwrap().window_rules.remove(name) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("apply_window_rules") => {
+            // Rules normally only touch new windows; this applies them to open ones too
+            let windows = match spawn_blocking_bounded(list_windows)
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(windows) => windows,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let matches: Vec<_> = {
+                let state = state.lock().unwrap();
+                windows
+                    .iter()
+                    .flat_map(|window| {
+                        state
+                            .window_rules
+                            .matching(window)
+                            .into_iter()
+                            .map(move |rule| (rule.clone(), window.clone()))
+                    })
+                    .collect()
+            };
+            let applying = spawn_blocking_bounded(move || {
+                let mut applied = Vec::new();
+                let mut errors = Vec::new();
+                for (rule, window) in matches {
+                    // Focus is not stolen by windows that are already open
+                    match rule.apply(&window, None) {
+                        Ok(_) => applied.push(json!({ "rule": rule.name, "window": window.id })),
+                        Err(e) => errors
+                            .push(json!({ "rule": rule.name, "window": window.id, "error": e })),
+                    }
+                }
+                (applied, errors)
+            });
+            match applying.await {
+                Ok((applied, errors)) => {
+                    json!({ "status": "success", "applied": applied, "errors": errors })
+                }
+                Err(e) => json!({ "status": "error", "message": e.to_string() }),
+            }
+        }
+
+        // Window Layouts
+        Some("save_layout") => {
+            let name = req["name"].as_str().unwrap_or("").to_string();
+            let snapshot = spawn_blocking_bounded(move || Layout::snapshot(&name))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            let layout = match snapshot {
+                Ok(layout) => layout,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let windows = layout.windows.len();
+            match state.lock().unwrap().layouts.add(layout) {
+                Ok(_) => json!({ "status": "success", "windows": windows }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("apply_layout") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let Some(layout) = state.lock().unwrap().layouts.get(name).cloned() else {
+                return json!({ "status": "error", "message": format!("No layout '{}'", name) });
+            };
+            match spawn_blocking_bounded(move || layout.apply())
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            {
+                Ok(report) => json!({
+                    "status": "success",
+                    "placed": report.placed,
+                    "missing": report.missing,
+                    "errors": report.errors,
+                }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("list_layouts") => {
+            let state = state.lock().unwrap();
+            let layouts: Vec<_> = state
+                .layouts
+                .layouts()
+                .iter()
+                .map(|layout| {
+                    json!({
+                        "name": layout.name,
+                        "saved_at": layout.saved_at,
+                        "windows": layout.windows,
+                    })
+                })
+                .collect();
+            json!({ "status": "success", "layouts": layouts })
+        }
+        Some("delete_layout") => {
+            let name = req["name"].as_str().unwrap_or("");
+            match state.lock().unwrap().layouts.remove(name) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Pipelines
+        Some("list_pipelines") => {
+            let state = state.lock().unwrap();
+            json!({ "status": "success", "pipelines": state.pipelines.pipelines() })
+        }
+        Some("save_pipeline") => {
+            let pipeline: Pipeline = match serde_json::from_value(req["pipeline"].clone()) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("Invalid pipeline: {}", e)
+                    });
+                }
+            };
+            let mut state = state.lock().unwrap();
+            match state.pipelines.add(pipeline) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("delete_pipeline") => {
+            let name = req["name"].as_str().unwrap_or("");
+            match state.lock().unwrap().pipelines.remove(name) {
+                Ok(_) => json!({ "status": "success" }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+        Some("run_pipeline") => {
+            let name = req["name"].as_str().unwrap_or("");
+            let inputs: Variables = match serde_json::from_value(req["vars"].clone()) {
+                Ok(inputs) => inputs,
+                Err(_) if req["vars"].is_null() => Variables::new(),
+                Err(e) => {
+                    return json!({
+                        "status": "error",
+                        "message": format!("vars must map names to strings: {}", e)
+                    });
+                }
+            };
+            let (pipeline, privacy) = {
+                let state = state.lock().unwrap();
+                (state.pipelines.get(name).cloned(), state.modes.privacy)
+            };
+            let Some(pipeline) = pipeline else {
+                return json!({
+                    "status": "error",
+                    "message": format!("No pipeline '{}'", name)
+                });
+            };
+            match pipeline.run(inputs, !privacy).await {
+                Ok(vars) => json!({ "status": "success", "vars": vars }),
+                Err(e) => json!({ "status": "error", "message": e }),
+            }
+        }
+
+        // Hotkeys
+        Some("bind_hotkey") => {
+            let sequence = req["sequence"].as_str();
+            let toggle = req["toggle_automations"].as_bool().unwrap_or(false);
+            if sequence.is_some() == toggle {
+                return json!({
+                    "status": "error",
+                    "message": "Give either a sequence or toggle_automations"
+                });
+            }
+            let hotkey = match Hotkey::parse(req["hotkey"].as_str().unwrap_or("")) {
+                Ok(hotkey) => hotkey,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let mut state = state.lock().unwrap();
+            if state.is_abort_hotkey(&hotkey) {
+                return json!({
+                    "status": "error",
+                    "message": format!("Hotkey {} aborts all automations", hotkey)
+                });
+            }
+            if let Some(sequence) = sequence
+                && state.library.get_sequence(sequence).is_none()
+            {
+                return json!({
+                    "status": "error",
+                    "message": format!("Sequence not found: {}", sequence)
+                });
+            }
+            let previous = state.config.clone();
+            state.unbind_hotkey(&hotkey);
+            match sequence {
+                Some(sequence) => {
+                    state
+                        .config
+                        .hotkeys
+                        .insert(hotkey.to_string(), sequence.to_string());
+                }
+                // Only one hotkey toggles the pause
+                None => state.config.pause_hotkey = Some(hotkey.to_string()),
+            }
+            update_hotkeys(
+                &mut state,
+                previous,
+                json!({ "hotkey": hotkey.to_string() }),
+            )
+        }
+        Some("unbind_hotkey") => {
+            let hotkey = match Hotkey::parse(req["hotkey"].as_str().unwrap_or("")) {
+                Ok(hotkey) => hotkey,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            let mut state = state.lock().unwrap();
+            let previous = state.config.clone();
+            if !state.unbind_hotkey(&hotkey) {
+                return json!({
+                    "status": "error",
+                    "message": format!("Hotkey {} is not bound", hotkey)
+                });
+            }
+            update_hotkeys(
+                &mut state,
+                previous,
+                json!({ "hotkey": hotkey.to_string() }),
+            )
+        }
+        Some("list_hotkeys") => {
+            let state = state.lock().unwrap();
+            let mut hotkeys: Vec<Value> = state
+                .config
+                .hotkeys
+                .iter()
+                .map(|(hotkey, sequence)| json!({ "hotkey": hotkey, "sequence": sequence }))
+                .collect();
+            if let Some(hotkey) = &state.config.pause_hotkey {
+                hotkeys.push(json!({ "hotkey": hotkey, "toggle_automations": true }));
             }
+            if let Some(hotkey) = state.config.abort_hotkey() {
+                hotkeys.push(json!({ "hotkey": hotkey, "abort_all": true }));
+            }
+            json!({
+                "status": "success",
+                "hotkeys": hotkeys,
+                "listening": state.hotkey_listener.is_some()
+            })
         }
-        Some("close_window") => {
-            let window_id = req["window_id"].as_str().unwrap_or("");
-            match close_window(window_id) {
-                Ok(_) => json!({ "status": "success" }),
+
+        // Key/value store
+        Some("store_set") => {
+            let key = req["key"].as_str().unwrap_or("");
+            let value = req["value"].clone();
+            let mut state = state.lock().unwrap();
+            match state.store.set(key, value.clone()) {
+                Ok(previous) => {
+                    state.emit("store_changed", json!({ "key": key, "value": value }));
+                    json!({ "status": "success", "previous": previous })
+                }
                 Err(e) => json!({ "status": "error", "message": e }),
             }
         }
-        Some("move_resize_window") => {
-            let window_id = req["window_id"].as_str().unwrap_or("");
-            let x = req["x"].as_i64().unwrap_or(0) as i32;
-            let y = req["y"].as_i64().unwrap_or(0) as i32;
-            let width = req["width"].as_i64().unwrap_or(800) as i32;
-            let height = req["height"].as_i64().unwrap_or(600) as i32;
-            match move_resize_window(window_id, x, y, width, height) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+        Some("store_get") => {
+            let state = state.lock().unwrap();
+            match req["key"].as_str() {
+                Some(key) => json!({
+                    "status": "success",
+                    "key": key,
+                    "value": state.store.get(key),
+                    "exists": state.store.get(key).is_some()
+                }),
+                None => json!({ "status": "success", "values": state.store.all() }),
             }
         }
-        Some("open_or_focus_application") => {
-            let app = req["app"].as_str().unwrap_or("");
-            let launch_cmd = req["launch_command"].as_str();
-            match open_or_focus_application(app, launch_cmd) {
-                Ok(_) => json!({ "status": "success" }),
+        Some("store_delete") => {
+            let key = req["key"].as_str().unwrap_or("");
+            let mut state = state.lock().unwrap();
+            match state.store.delete(key) {
+                Ok(previous) => {
+                    if previous.is_some() {
+                        state.emit("store_changed", json!({ "key": key, "value": null }));
+                    }
+                    json!({ "status": "success", "previous": previous })
+                }
                 Err(e) => json!({ "status": "error", "message": e }),
             }
         }
 
-        // Action Recording
-        Some("start_recording") => {
-            let name = req["name"].as_str().unwrap_or("Unnamed");
-            let description = req["description"].as_str().unwrap_or("");
+        Some("metrics") => {
+            let state = state.lock().unwrap();
+            match req["format"].as_str().unwrap_or("json") {
+                "json" => json!({ "status": "success", "metrics": state.metrics.snapshot() }),
+                "prometheus" => json!({
+                    "status": "success",
+                    "text": state.metrics.render_prometheus()
+                }),
+                other => json!({
+                    "status": "error",
+                    "message": format!("Unknown metrics format: {} (use json or prometheus)", other)
+                }),
+            }
+        }
+        Some("usage_report") => {
             let mut state = state.lock().unwrap();
-            match state
-                .recorder
-                .start_recording(name.to_string(), description.to_string())
+            let Some(usage) = state.usage.as_mut() else {
+                return json!({
+                    "status": "error",
+                    "message": "Usage statistics are off; set \"usage\": {\"enabled\": true} in config.json to opt in"
+                });
+            };
+            let _ = usage.save();
+            let report = match usage.report(req["epsilon"].as_f64()) {
+                Ok(report) => report,
+                Err(e) => return json!({ "status": "error", "message": e }),
+            };
+            if let Some(path) = req["path"].as_str()
+                && let Err(e) = report.export(std::path::Path::new(path))
             {
-                Ok(_) => json!({ "status": "success", "message": "Recording started" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+                return json!({ "status": "error", "message": e });
             }
+            json!({ "status": "success", "report": report })
         }
-        Some("stop_recording") => {
+        Some("reset_usage") => {
             let mut state = state.lock().unwrap();
-            match state.recorder.stop_recording() {
-                Ok(sequence) => {
-                    state.library.add_sequence(sequence.clone());
-                    let _ = state.library.save_all();
-                    json!({
-                        "status": "success",
-                        "message": "Recording stopped",
-                        "sequence": sequence.name
-                    })
+            match state.usage.as_mut().map(|usage| usage.reset()) {
+                Some(Ok(_)) => {
+                    json!({ "status": "success", "message": "Usage statistics cleared" })
                 }
-                Err(e) => json!({ "status": "error", "message": e }),
+                Some(Err(e)) => json!({ "status": "error", "message": e }),
+                None => json!({ "status": "error", "message": "Usage statistics are off" }),
             }
         }
-        Some("record_action") => {
-            let action_type = req["action"].as_str().unwrap_or("");
+        Some("set_mode") => {
+            let mode = req["mode"].as_str().unwrap_or("");
+            let Some(enabled) = req["enabled"].as_bool() else {
+                return json!({ "status": "error", "message": "Missing 'enabled' flag" });
+            };
             let mut state = state.lock().unwrap();
-
-            let action = match action_type {
-                "move_mouse" => {
-                    let x = req["x"].as_i64().unwrap_or(0) as i32;
-                    let y = req["y"].as_i64().unwrap_or(0) as i32;
-                    Action::MoveMouse { x, y }
-                }
-                "click_mouse" => {
-                    let button = req["button"].as_str().unwrap_or("left").to_string();
-                    Action::ClickMouse { button }
-                }
-                "type_text" => {
-                    let text = req["text"].as_str().unwrap_or("").to_string();
-                    Action::TypeText { text }
-                }
-                "press_key" => {
-                    let key = req["key"].as_str().unwrap_or("").to_string();
-                    Action::PressKey { key }
-                }
-                "wait" => {
-                    let ms = req["milliseconds"].as_u64().unwrap_or(1000);
-                    Action::Wait { milliseconds: ms }
+            match mode {
+                "privacy" => {
+                    state.modes.privacy = enabled;
+                    sensors::set_screen_allowed(!enabled);
                 }
+                "do_not_disturb" => state.modes.do_not_disturb = enabled,
                 _ => {
                     return json!({
                         "status": "error",
-                        "message": format!("Unknown action type: {}", action_type)
+                        "message": format!("Unknown mode: {} (expected privacy or do_not_disturb)", mode)
                     });
                 }
-            };
-
-            match state.recorder.record_action(action) {
-                Ok(_) => json!({ "status": "success", "message": "Action recorded" }),
-                Err(e) => json!({ "status": "error", "message": e }),
             }
+            state.emit("mode_changed", json!({ "mode": mode, "enabled": enabled }));
+            json!({ "status": "success", "mode": mode, "enabled": enabled })
         }
-        Some("is_recording") => {
-            let state = state.lock().unwrap();
-            json!({
-                "status": "success",
-                "recording": state.recorder.is_recording()
-            })
-        }
-
-        // Action Playback
-        Some("load_sequence") => {
-            let name = req["name"].as_str().unwrap_or("");
-            let sequence_clone = {
-                let state = state.lock().unwrap();
-                state.library.get_sequence(name).cloned()
-            };
-
-            if let Some(sequence) = sequence_clone {
-                let mut state = state.lock().unwrap();
-                state.player.load_sequence(sequence.clone());
-                json!({
-                    "status": "success",
-                    "message": format!("Loaded sequence: {}", sequence.name)
-                })
-            } else {
-                json!({
+        Some("pause_automations") => {
+            let minutes = req["minutes"].as_f64();
+            if minutes.is_some_and(|m| !(m > 0.0 && m.is_finite())) {
+                return json!({ "status": "error", "message": "minutes must be positive" });
+            }
+            if minutes.is_some_and(|m| m > MAX_PAUSE_MINUTES) {
+                return json!({
                     "status": "error",
-                    "message": format!("Sequence not found: {}", name)
-                })
+                    "message": format!("Automations can be paused for at most {} minutes", MAX_PAUSE_MINUTES)
+                });
             }
+            let resume_at = match minutes {
+                Some(m) => match Duration::try_from_secs_f64(m * 60.0)
+                    .ok()
+                    .and_then(|pause| Instant::now().checked_add(pause))
+                {
+                    Some(resume_at) => Some(resume_at),
+                    None => {
+                        return json!({ "status": "error", "message": "minutes is out of range" });
+                    }
+                },
+                None => None,
+            };
+            pause_automations(state, resume_at);
+            json!({ "status": "success", "automations_paused": true, "minutes": minutes })
         }
-        Some("play_sequence") => {
-            let mut state = state.lock().unwrap();
-            match state.player.start_playback() {
-                Ok(_) => {
-                    // Playback happens synchronously here for simplicity
-                    drop(state); // Release lock
-                    json!({ "status": "success", "message": "Playback started" })
-                }
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
+        Some("resume_automations") => {
+            let was_paused = resume_automations(state);
+            json!({ "status": "success", "automations_paused": false, "was_paused": was_paused })
         }
-        Some("list_sequences") => {
-            let state = state.lock().unwrap();
-            let sequences = state.library.list_sequences();
-            json!({ "status": "success", "sequences": sequences })
+        Some("capabilities") => {
+            let client_version = req["client_version"].as_u64().map(|v| v as u32);
+            let mut response = protocol::capabilities(client_version);
+            response["features"] = json!(feature_status());
+            response
         }
-        Some("delete_sequence") => {
-            let name = req["name"].as_str().unwrap_or("");
-            let mut state = state.lock().unwrap();
-            match state.library.delete_sequence(name) {
-                Ok(_) => json!({
-                    "status": "success",
-                    "message": format!("Deleted sequence: {}", name)
-                }),
-                Err(e) => json!({ "status": "error", "message": e }),
+
+        // Unknown
+        _ => json!({
+            "status": "error",
+            "message": format!(
+                "Unknown request type: {:?} (send {{\"type\":\"capabilities\"}} for the supported list)",
+                req["type"]
+            ),
+            "protocol_version": protocol::PROTOCOL_VERSION
+        }),
+    }
+}
+
+/// Sensor detail identifying a recording session to the user
+fn recording_detail(name: &str, session_id: &str) -> String {
+    format!("{}, {}", name, session_id)
+}
+
+/// Tell subscribers how a background playback is going
+///
+/// `context` holds fields added to `playback_failed`, such as a screenshot.
+fn report_playback(
+    events: &broadcast::Sender<Value>,
+    sequence: Option<&str>,
+    progress: PlaybackProgress,
+    context: Value,
+) {
+    match progress {
+        PlaybackProgress::Step {
+            index,
+            total,
+            label,
+            duration_ms,
+        } => emit_event(
+            events,
+            "playback_progress",
+            json!({
+                "sequence": sequence,
+                "step": index,
+                "total": total,
+                "label": label,
+                "duration_ms": duration_ms
+            }),
+        ),
+        PlaybackProgress::Finished { steps, failures } => emit_event(
+            events,
+            "playback_finished",
+            json!({ "sequence": sequence, "steps": steps, "failures": failures }),
+        ),
+        PlaybackProgress::Stopped { steps, failures } => emit_event(
+            events,
+            "playback_stopped",
+            json!({ "sequence": sequence, "steps": steps, "failures": failures }),
+        ),
+        PlaybackProgress::Failed {
+            index,
+            error,
+            failures,
+        } => {
+            let mut data = json!({ "sequence": sequence, "step": index, "error": error, "failures": failures });
+            if let (Some(data), Value::Object(context)) = (data.as_object_mut(), context) {
+                data.extend(context);
             }
+            emit_event(events, "playback_failed", data)
         }
+    }
+}
 
-        // Notifications
-        Some("show_notification") => {
-            let summary = req["summary"].as_str().unwrap_or("");
-            let body = req["body"].as_str().unwrap_or("");
-            match show_notification(summary, body) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+/// Finds the sequences called by `CallSequence` steps; the state must not be
+/// locked while it runs
+fn sequence_lookup(shared: &Arc<Mutex<DaemonState>>) -> SequenceLookup {
+    let library = Arc::clone(shared);
+    Arc::new(
+        move |called| match library.lock().unwrap().library.get_sequence(called) {
+            Some(sequence) if sequence.draft => Err(format!(
+                "Sequence '{}' is a draft; review it and send approve_sequence first",
+                called
+            )),
+            Some(sequence) => Ok(sequence.clone()),
+            None => Err(format!("Sequence not found: {}", called)),
+        },
+    )
+}
+
+/// Where a step playback is: the step performed next and whether it is done
+fn step_session_status(session: &StepSession) -> Value {
+    let next = session.next_step().map(|(index, step)| {
+        let mut step = step.clone();
+        step.action = step.action.redacted();
+        json!({ "index": index, "step": step })
+    });
+    json!({
+        "status": "success",
+        "sequence": session.sequence().name,
+        "total": session.sequence().actions.len(),
+        "next": next,
+        "running": session.is_running(),
+        "finished": session.is_finished()
+    })
+}
+
+/// What became of a playback request
+enum PlaybackStart {
+    Started,
+    /// Waiting at this position in the queue
+    Queued(usize),
+    Skipped,
+}
+
+/// Start a playback, or apply its concurrency policy if another one is playing
+fn play_or_queue(
+    shared: &Arc<Mutex<DaemonState>>,
+    state: &mut DaemonState,
+    playback: QueuedPlayback,
+) -> Result<PlaybackStart, String> {
+    let playing = state.player.lock().unwrap().is_playing();
+    if playing {
+        match playback.concurrency {
+            Concurrency::Reject => return Err("A sequence is already playing".to_string()),
+            Concurrency::SkipIfRunning => return Ok(PlaybackStart::Skipped),
+            Concurrency::Queue => {
+                let name = playback.sequence.name.clone();
+                state.playback_queue.push_back(playback);
+                let position = state.playback_queue.len();
+                state.emit(
+                    "playback_queued",
+                    json!({ "sequence": name, "position": position }),
+                );
+                return Ok(PlaybackStart::Queued(position));
             }
+            // The previous playback reports playback_stopped once its current step is done
+            Concurrency::CancelPrevious => state.player.lock().unwrap().stop_playback(),
         }
+    }
+    start_playback(shared, state, playback).map(|_| PlaybackStart::Started)
+}
 
-        // External Services
-        Some("connect_to_service") => {
-            let service = req["service"].as_str().unwrap_or("");
-            let action = req["action"].as_str().unwrap_or("");
-            match connect_to_service(service, action).await {
-                Ok(result) => json!({ "status": "success", "result": result }),
-                Err(e) => json!({ "status": "error", "message": e }),
+/// Load a sequence into the player and play it in the background
+///
+/// When it ends, the next queued playback starts, unless another playback
+/// was started in the meantime.
+fn start_playback(
+    shared: &Arc<Mutex<DaemonState>>,
+    state: &mut DaemonState,
+    playback: QueuedPlayback,
+) -> Result<(), String> {
+    let QueuedPlayback {
+        sequence,
+        start,
+        end,
+        options,
+        capture_on_error,
+        block_input,
+        ..
+    } = playback;
+    let name = sequence.name.clone();
+    // Kept to name the checkpoint a failed playback can resume from
+    let checkpoints = sequence.clone();
+    let capture_errors = state.wants_error_capture(capture_on_error);
+    // Released when the playback ends, whichever way it does
+    let block = match block_input {
+        Some(panic) => Some(start_input_block(state, panic, INPUT_BLOCK_TIMEOUT)?),
+        None => None,
+    };
+    let block = Mutex::new(block);
+    {
+        let mut player = state.player.lock().unwrap();
+        player.load_sequence(sequence);
+        player.start_playback_between(start.as_ref(), end.as_ref())?;
+    }
+    state.metrics.record_playback();
+    state.emit(
+        "playback_started",
+        json!({
+            "sequence": name,
+            "speed": options.speed,
+            "no_delays": options.no_delays
+        }),
+    );
+
+    let events = state.events.clone();
+    let lookup = sequence_lookup(shared);
+    let shared = Arc::clone(shared);
+    let tracker = Mutex::new(RunTracker::start(&name));
+    let history = RunHistory::new(paths::runs_dir(state.profile.as_deref()));
+    spawn_playback(
+        Arc::clone(&state.player),
+        options,
+        lookup,
+        move |progress| {
+            if !matches!(progress, PlaybackProgress::Step { .. }) {
+                block.lock().unwrap().take();
+            }
+            if let Some(run) = tracker.lock().unwrap().observe(&progress)
+                && let Err(e) = history.record(&run)
+            {
+                eprintln!("⚠️  Failed to keep the run of '{}': {}", run.sequence, e);
+            }
+            let mut context = match &progress {
+                PlaybackProgress::Failed { index, .. } => {
+                    json!({ "checkpoint": checkpoints.checkpoint_before(*index) })
+                }
+                _ => Value::Null,
+            };
+            match progress {
+                PlaybackProgress::Failed { .. } if capture_errors => {
+                    // The screen is captured before the next queued playback starts
+                    let (events, name, shared) =
+                        (events.clone(), name.clone(), Arc::clone(&shared));
+                    tokio::spawn(async move {
+                        if let Value::Object(captured) = error_context(&shared, &name).await {
+                            context.as_object_mut().unwrap().extend(captured);
+                        }
+                        report_playback(&events, Some(&name), progress, context);
+                        start_queued_playback(&shared);
+                    });
+                }
+                progress => {
+                    let ended = !matches!(progress, PlaybackProgress::Step { .. });
+                    report_playback(&events, Some(&name), progress, context);
+                    if ended {
+                        start_queued_playback(&shared);
+                    }
+                }
             }
+        },
+    );
+    Ok(())
+}
+
+/// Stop everything casper is doing to the desktop: the playback and the
+/// ones queued after it, a step being debugged, blocked input, and keys or
+/// buttons left held down
+fn abort_all(state: &Arc<Mutex<DaemonState>>) -> Value {
+    let mut state = state.lock().unwrap();
+    let cleared = state.playback_queue.len();
+    state.playback_queue.clear();
+    let stopped = {
+        let mut player = state.player.lock().unwrap();
+        let playing = player.is_playing();
+        player.stop_playback();
+        playing
+    };
+    if let Some(session) = &state.step_session {
+        session.stop();
+    }
+    let unblocked = state.input_block.take().is_some_and(|block| {
+        let active = block.is_active();
+        block.stop();
+        active
+    });
+    let (keys, buttons) = release_held();
+    let summary = json!({
+        "stopped": stopped,
+        "cleared": cleared,
+        "unblocked": unblocked,
+        "released_keys": keys,
+        "released_buttons": buttons
+    });
+    state.emit("automations_aborted", summary.clone());
+    summary
+}
+
+/// Grab the real keyboard and mouse until `timeout` or the panic key
+///
+/// The panic key also stops the playback, so a run going wrong can be
+/// taken back from the keyboard it was keeping busy.
+fn start_input_block(
+    state: &DaemonState,
+    panic: PanicChord,
+    timeout: Duration,
+) -> Result<InputBlock, String> {
+    let events = state.events.clone();
+    let player = Arc::clone(&state.player);
+    InputBlock::start(panic, timeout, move |end| {
+        if end == BlockEnd::PanicKey {
+            player.lock().unwrap().stop_playback();
         }
+        emit_event(
+            &events,
+            "input_unblocked",
+            json!({ "reason": end.as_str() }),
+        );
+    })
+}
 
-        // MCP
-        Some("process_mcp") => {
-            let data = req["data"].as_str().unwrap_or("");
-            match process_mcp(data) {
-                Ok(result) => json!({ "status": "success", "result": result }),
-                Err(e) => json!({ "status": "error", "message": e }),
-            }
+/// The panic key asked for by `field`: true for the default one or a key
+/// combination; absent or false for none
+fn panic_chord(req: &Value, field: &str) -> Result<Option<PanicChord>, String> {
+    match &req[field] {
+        Value::Null | Value::Bool(false) => Ok(None),
+        Value::Bool(true) => PanicChord::parse(DEFAULT_PANIC_KEY).map(Some),
+        Value::String(combo) => PanicChord::parse(combo).map(Some),
+        _ => Err(format!(
+            "{} must be true or a panic key such as \"{}\"",
+            field, DEFAULT_PANIC_KEY
+        )),
+    }
+}
+
+/// Start the next queued playback if the player is free and automations run
+fn start_queued_playback(shared: &Arc<Mutex<DaemonState>>) {
+    let mut state = shared.lock().unwrap();
+    if state.modes.automations_paused {
+        return;
+    }
+    while !state.player.lock().unwrap().is_playing() {
+        let Some(playback) = state.playback_queue.pop_front() else {
+            return;
+        };
+        let name = playback.sequence.name.clone();
+        match start_playback(shared, &mut state, playback) {
+            Ok(()) => return,
+            Err(e) => state.emit("playback_failed", json!({ "sequence": name, "error": e })),
         }
+    }
+}
 
-        // AI
-        Some("process_command") => {
-            let command = req["command"].as_str().unwrap_or("");
-            match process_command(command) {
-                Ok(result) => json!({ "status": "success", "result": result }),
-                Err(e) => json!({ "status": "error", "message": e }),
+/// Grab the changed hotkeys and save them, or go back to `previous` if that fails
+fn update_hotkeys(state: &mut DaemonState, previous: DaemonConfig, mut response: Value) -> Value {
+    let profile = state.profile.clone();
+    let result = state
+        .restart_hotkeys()
+        .and_then(|_| state.config.save(profile.as_deref()));
+    if let Err(e) = result {
+        state.config = previous;
+        let _ = state.restart_hotkeys();
+        return json!({ "status": "error", "message": e });
+    }
+    response["status"] = json!("success");
+    response
+}
+
+/// Hold window rules, hotkey-started playbacks and the playback queue
+///
+/// With `resume_at` the pause ends by itself, unless it was replaced by another one.
+fn pause_automations(shared: &Arc<Mutex<DaemonState>>, resume_at: Option<Instant>) {
+    {
+        let mut state = shared.lock().unwrap();
+        state.modes.automations_paused = true;
+        state.resume_automations_at = resume_at;
+        state.emit(
+            "mode_changed",
+            json!({ "mode": "automations_paused", "enabled": true }),
+        );
+    }
+    if let Some(resume_at) = resume_at {
+        let shared = Arc::clone(shared);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(resume_at.into()).await;
+            let current = shared.lock().unwrap().resume_automations_at;
+            if current == Some(resume_at) {
+                resume_automations(&shared);
             }
+        });
+    }
+}
+
+/// End a pause and start the playbacks queued during it; returns whether automations were paused
+fn resume_automations(shared: &Arc<Mutex<DaemonState>>) -> bool {
+    {
+        let mut state = shared.lock().unwrap();
+        if !state.modes.automations_paused {
+            return false;
         }
+        state.modes.automations_paused = false;
+        state.resume_automations_at = None;
+        state.emit(
+            "mode_changed",
+            json!({ "mode": "automations_paused", "enabled": false }),
+        );
+    }
+    start_queued_playback(shared);
+    true
+}
 
-        // Voice
-        Some("recognize_voice") => match recognize_voice() {
-            Ok(result) => json!({ "status": "success", "result": result }),
-            Err(e) => json!({ "status": "error", "message": e }),
-        },
+/// Whether a request asks for a temporary sequence (`ephemeral` or a `ttl_seconds`)
+fn is_temporary_request(req: &Value) -> bool {
+    req["ephemeral"].as_bool().unwrap_or(false) || req["ttl_seconds"].is_u64()
+}
 
-        // TTS
-        Some("speak") => {
-            let text = req["text"].as_str().unwrap_or("");
-            match speak(text) {
-                Ok(_) => json!({ "status": "success" }),
-                Err(e) => json!({ "status": "error", "message": e }),
+/// Add a sequence to the library, only in memory if the request asks for a
+/// temporary one; returns whether it is temporary
+fn add_to_library(
+    library: &mut ActionLibrary,
+    sequence: ActionSequence,
+    req: &Value,
+) -> Result<bool, String> {
+    if is_temporary_request(req) {
+        let ttl = req["ttl_seconds"].as_u64().map(Duration::from_secs);
+        library.add_temporary(sequence, ttl)?;
+        Ok(true)
+    } else {
+        library.add_sequence(sequence);
+        library.save_all().map(|_| false)
+    }
+}
+
+/// Name of the sequence a recording session is recording
+fn recording_name(state: &DaemonState, session_id: Option<&str>) -> Option<String> {
+    let id = state.recordings.resolve(session_id).ok()?;
+    state
+        .recordings
+        .iter()
+        .find(|(session, _)| **session == id)
+        .map(|(_, sequence)| sequence.name.clone())
+}
+
+/// Summaries of interrupted recordings for list_interrupted_recordings
+fn interrupted_list(interrupted: &[RecoveredRecording]) -> Vec<Value> {
+    interrupted
+        .iter()
+        .map(|recording| {
+            json!({
+                "id": recording.id,
+                "name": recording.sequence.name,
+                "steps": recording.sequence.actions.len(),
+                "created_at": recording.sequence.created_at
+            })
+        })
+        .collect()
+}
+
+fn recording_list(state: &DaemonState) -> Vec<Value> {
+    state
+        .recordings
+        .iter()
+        .map(|(id, sequence)| {
+            let source = if state.input_recorders.contains_key(id) {
+                "input"
+            } else {
+                "socket"
+            };
+            json!({
+                "session_id": id,
+                "name": sequence.name,
+                "steps": sequence.actions.len(),
+                "source": source
+            })
+        })
+        .collect()
+}
+
+/// Record the real keyboard and mouse into a recording session
+fn record_input(
+    state: Arc<Mutex<DaemonState>>,
+    session_id: String,
+) -> Result<InputRecorder, String> {
+    InputRecorder::start(move |action, at| {
+        let action_type = serde_json::to_value(&action)
+            .ok()
+            .and_then(|value| value["type"].as_str().map(str::to_string));
+        let screenshot = step_screenshot(&state, &session_id);
+        let mut state = state.lock().unwrap();
+        if state
+            .recordings
+            .record_step_at(Some(&session_id), action, None, None, None, at)
+            .is_ok()
+        {
+            if let Some(file) = screenshot {
+                let _ = state.recordings.attach_screenshot(&session_id, file);
             }
+            state.emit(
+                "action_recorded",
+                json!({ "action": action_type, "session_id": session_id, "source": "input" }),
+            );
         }
+    })
+}
 
-        // Ping/Status
-        Some("ping") => json!({
-            "status": "success",
-            "message": "pong",
-            "version": "0.2.0"
-        }),
+/// The `count` of a click request; one too large for a `u32` is left for
+/// the click to reject rather than wrapped around
+fn request_count(req: &Value) -> Option<u32> {
+    req["count"]
+        .as_u64()
+        .map(|count| u32::try_from(count).unwrap_or(u32::MAX))
+}
 
-        // Unknown
-        _ => json!({
-            "status": "error",
-            "message": format!("Unknown request type: {:?}", req["type"])
-        }),
+/// The anchor of a recorded mouse step: its `anchor`, or the monitor its
+/// `monitor` names
+fn request_anchor(req: &Value) -> Option<Anchor> {
+    serde_json::from_value(req["anchor"].clone())
+        .ok()
+        .flatten()
+        .or_else(|| {
+            let monitor = serde_json::from_value(req["monitor"].clone()).ok()?;
+            Some(Anchor::Monitor { monitor })
+        })
+}
+
+/// Screen coordinates of a request's `x`/`y`, which `"monitor"` (a name or
+/// an index in `list_displays`) makes relative to that monitor
+fn request_point(req: &Value, x: i32, y: i32) -> Result<(i32, i32), String> {
+    if req["monitor"].is_null() {
+        return Ok((x, y));
+    }
+    let monitor: DisplayRef = serde_json::from_value(req["monitor"].clone())
+        .map_err(|_| "monitor must be a monitor name or index".to_string())?;
+    resolve_point(x, y, Some(&Anchor::Monitor { monitor }))
+}
+
+/// Take the screenshot of a step about to be recorded, if its session takes
+/// them; returns the file name within the session's screenshot directory
+fn step_screenshot(state: &Arc<Mutex<DaemonState>>, session_id: &str) -> Option<String> {
+    let dir = {
+        let state = state.lock().unwrap();
+        if state.modes.privacy {
+            return None;
+        }
+        state.recording_screenshots.get(session_id)?.clone()
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let file = format!("{}.png", timestamp);
+    let path = dir.join(&file);
+    match capture_screen_small(&path.to_string_lossy(), RECORDING_SCREENSHOT_WIDTH) {
+        Ok(()) => Some(file),
+        Err(e) => {
+            eprintln!("Step screenshot of {} failed: {}", session_id, e);
+            None
+        }
     }
 }
+
+/// Derive a library name for a generated sequence from its description
+fn draft_name(description: &str) -> String {
+    let words: Vec<String> = description
+        .split_whitespace()
+        .take(5)
+        .map(|w| {
+            w.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|w| !w.is_empty())
+        .collect();
+    format!("draft_{}", words.join("_"))
+}
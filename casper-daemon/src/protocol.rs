@@ -0,0 +1,1422 @@
+use serde_json::{Value, json};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Version of the socket protocol spoken by this daemon
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version this daemon still answers compatibly
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// A single request parameter
+pub struct Param {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub required: bool,
+}
+
+const fn required(name: &'static str, kind: &'static str) -> Param {
+    Param {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, kind: &'static str) -> Param {
+    Param {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+/// Parameters of the request types listed in `PAGINATED_REQUESTS`
+const PAGE_PARAMS: &[Param] = &[optional("offset", "integer"), optional("limit", "integer")];
+
+/// Description of a request type understood by the daemon
+pub struct RequestSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: &'static [Param],
+}
+
+/// Every request type handled by the daemon, with its parameter schema
+pub const REQUESTS: &[RequestSpec] = &[
+    // Basic Commands
+    RequestSpec {
+        name: "run_command",
+        description: "Run a shell command and return its output",
+        params: &[
+            required("command", "string"),
+            optional("capture_on_error", "boolean"),
+        ],
+    },
+    // Screen Control - Mouse
+    RequestSpec {
+        name: "move_mouse",
+        description: "Move the cursor to absolute coordinates, or by an offset if relative",
+        params: &[
+            required("x", "integer"),
+            required("y", "integer"),
+            optional("relative", "boolean"),
+            optional("monitor", "monitor"),
+        ],
+    },
+    RequestSpec {
+        name: "click_mouse",
+        description: "Click a mouse button, count times for a double or triple click",
+        params: &[optional("button", "string"), optional("count", "integer")],
+    },
+    RequestSpec {
+        name: "drag",
+        description: "Press a button, move smoothly to another point and release it",
+        params: &[
+            required("from_x", "integer"),
+            required("from_y", "integer"),
+            required("to_x", "integer"),
+            required("to_y", "integer"),
+            optional("button", "string"),
+            optional("duration_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "double_click",
+        description: "Double-click a mouse button",
+        params: &[optional("button", "string")],
+    },
+    RequestSpec {
+        name: "click_at",
+        description: "Move the pointer and click there in one step",
+        params: &[
+            required("x", "integer"),
+            required("y", "integer"),
+            optional("button", "string"),
+            optional("count", "integer"),
+            optional("settle_ms", "integer"),
+            optional("monitor", "monitor"),
+        ],
+    },
+    RequestSpec {
+        name: "click_in_window",
+        description: "Focus a window (window_id, or a title or class pattern in window) and click rel_x/rel_y from its top-left corner; negative offsets count from the right and bottom edges",
+        params: &[
+            optional("window_id", "string"),
+            optional("window", "string"),
+            required("rel_x", "integer"),
+            required("rel_y", "integer"),
+            optional("button", "string"),
+            optional("count", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "mouse_down",
+        description: "Press and hold a mouse button",
+        params: &[optional("button", "string")],
+    },
+    RequestSpec {
+        name: "mouse_up",
+        description: "Release a mouse button",
+        params: &[optional("button", "string")],
+    },
+    RequestSpec {
+        name: "scroll",
+        description: "Scroll vertically or horizontally; with step, a few notches at a time with delay_ms (30 by default) between them",
+        params: &[
+            optional("amount", "integer"),
+            optional("direction", "string"),
+            optional("step", "integer"),
+            optional("delay_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "scroll_pages",
+        description: "Scroll the focused window by pages with Page Up or Page Down",
+        params: &[
+            optional("pages", "integer"),
+            optional("direction", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "scroll_to_image",
+        description: "Scroll until a reference image is on the screen and return where it is",
+        params: &[
+            required("image", "string"),
+            optional("direction", "string"),
+            optional("tolerance", "integer"),
+            optional("max_scrolls", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "find_image_on_screen",
+        description: "Find where a reference image is shown on the screen by correlation",
+        params: &[
+            required("image", "string"),
+            optional("confidence", "number"),
+        ],
+    },
+    RequestSpec {
+        name: "compare_images",
+        description: "Compare two PNG images and list the areas that differ",
+        params: &[
+            required("a", "string"),
+            required("b", "string"),
+            optional("tolerance", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "cleanup_screenshots",
+        description: "Remove stored screenshots past the count or age limits",
+        params: &[
+            optional("max_count", "integer"),
+            optional("max_age_hours", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "wait_for_screen_change",
+        description: "Wait until the screen or a region changes, and optionally settles",
+        params: &[
+            optional("region", "object"),
+            optional("threshold", "number"),
+            optional("tolerance", "integer"),
+            optional("stable_ms", "integer"),
+            optional("timeout_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "get_mouse_position",
+        description: "Get the current cursor position",
+        params: &[],
+    },
+    // Screen Control - Touch
+    RequestSpec {
+        name: "swipe",
+        description: "Swipe on a virtual touchscreen with one or more fingers",
+        params: &[
+            required("from_x", "integer"),
+            required("from_y", "integer"),
+            required("to_x", "integer"),
+            required("to_y", "integer"),
+            optional("fingers", "integer"),
+            optional("duration_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "pinch",
+        description: "Move two fingers together (zoom out) or apart (zoom in) around a point",
+        params: &[
+            required("x", "integer"),
+            required("y", "integer"),
+            optional("from_distance", "integer"),
+            optional("to_distance", "integer"),
+            optional("duration_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "tap",
+        description: "Tap the touchscreen with one or more fingers",
+        params: &[
+            required("x", "integer"),
+            required("y", "integer"),
+            optional("fingers", "integer"),
+        ],
+    },
+    // Screen Control - Keyboard
+    RequestSpec {
+        name: "list_displays",
+        description: "List the monitors with their resolution, offset and scale factor",
+        params: &[],
+    },
+    RequestSpec {
+        name: "get_pixel_color",
+        description: "Get the color of the screen pixel at x, y",
+        params: &[required("x", "integer"), required("y", "integer")],
+    },
+    RequestSpec {
+        name: "type_text",
+        description: "Type a string of text, slowly with delay_ms or pasted with via_clipboard; with a layout (\"auto\" to detect it) the characters it would garble are pasted; sensitive text is never pasted",
+        params: &[
+            required("text", "string"),
+            optional("delay_ms", "integer"),
+            optional("via_clipboard", "boolean"),
+            optional("layout", "string"),
+            optional("sensitive", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "keyboard_layout",
+        description: "Return the detected keyboard layout, e.g. \"de\" or \"us(intl)\"",
+        params: &[],
+    },
+    RequestSpec {
+        name: "get_clipboard",
+        description: "Return the text on the clipboard",
+        params: &[],
+    },
+    RequestSpec {
+        name: "set_clipboard",
+        description: "Put text on the clipboard",
+        params: &[required("text", "string")],
+    },
+    RequestSpec {
+        name: "press_key",
+        description: "Press and release a named key",
+        params: &[required("key", "string")],
+    },
+    RequestSpec {
+        name: "list_keys",
+        description: "List the key names press_key understands",
+        params: &[],
+    },
+    RequestSpec {
+        name: "press_combo",
+        description: "Press a key combination such as \"ctrl+shift+t\"",
+        params: &[required("combo", "string")],
+    },
+    RequestSpec {
+        name: "key_down",
+        description: "Press and hold a named key",
+        params: &[required("key", "string")],
+    },
+    RequestSpec {
+        name: "key_up",
+        description: "Release a named key",
+        params: &[required("key", "string")],
+    },
+    RequestSpec {
+        name: "send_app_action",
+        description: "Press the focused application's shortcut for a named action (e.g. \"new tab\")",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "app_shortcuts",
+        description: "List the named shortcuts of the focused window, or of a window class",
+        params: &[optional("class", "string")],
+    },
+    // Window Management
+    RequestSpec {
+        name: "is_process_running",
+        description: "Check whether a process with this exact name is running",
+        params: &[required("process", "string")],
+    },
+    RequestSpec {
+        name: "get_process_info",
+        description: "PID, command line, average CPU use and resident memory of the processes with this name, program or PID",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "kill_process",
+        description: "Send a signal (TERM unless given: KILL, INT, HUP, ...) to every process with this name, program or PID",
+        params: &[required("name", "string"), optional("signal", "string")],
+    },
+    RequestSpec {
+        name: "wait_for_process_exit",
+        description: "Wait until no process with this name, program or PID is left (timeout_ms defaults to 30000)",
+        params: &[
+            required("name", "string"),
+            optional("timeout_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "is_application_visible",
+        description: "Check whether a window matching the application is open",
+        params: &[required("app", "string")],
+    },
+    RequestSpec {
+        name: "launch_application",
+        description: "Launch an application by desktop entry name, generic name, desktop file ID (\"firefox.desktop\") or program, opening the files or URLs in args",
+        params: &[required("app", "string"), optional("args", "array")],
+    },
+    RequestSpec {
+        name: "list_applications",
+        description: "List every installed application (desktop entries), by name",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "search_applications",
+        description: "Search installed applications (desktop entries), best match first",
+        params: &[optional("query", "string"), optional("limit", "integer")],
+    },
+    RequestSpec {
+        name: "focus_window",
+        description: "Focus a window by window_id, or the first whose title or class contains window",
+        params: &[
+            optional("window", "string"),
+            optional("window_id", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "snap_window",
+        description: "Move and resize a window, by window_id or a title/class pattern, to part of its monitor (or the given monitor): left-half, right-half, top-half, bottom-half, top-left, top-right, bottom-left, bottom-right, left-third, center-third, right-third, left-two-thirds or right-two-thirds",
+        params: &[
+            required("position", "string"),
+            optional("window_id", "string"),
+            optional("window", "string"),
+            optional("monitor", "monitor"),
+        ],
+    },
+    RequestSpec {
+        name: "raise_window",
+        description: "Put a window, by window_id or a title/class pattern, above all others without focusing it",
+        params: &[
+            optional("window_id", "string"),
+            optional("window", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "lower_window",
+        description: "Put a window, by window_id or a title/class pattern, below all others",
+        params: &[
+            optional("window_id", "string"),
+            optional("window", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "list_windows",
+        description: "List all open windows, each with a base64 PNG `thumbnail` if thumbnails is true (thumbnail_width defaults to 160 pixels; null for windows that are covered, on another workspace or could not be captured; refused in privacy mode)",
+        params: &[
+            optional("thumbnails", "boolean"),
+            optional("thumbnail_width", "integer"),
+            optional("offset", "integer"),
+            optional("limit", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "active_window",
+        description: "The focused window: id, pid, desktop, class, title, machine and its x, y, width and height",
+        params: &[],
+    },
+    RequestSpec {
+        name: "find_window",
+        description: "Find the best window matching a pattern (match: any, class, title or pid; a regex if regex is true, case-insensitive unless case_sensitive), or all of them ranked with all: true",
+        params: &[
+            required("pattern", "string"),
+            optional("match", "string"),
+            optional("regex", "boolean"),
+            optional("case_sensitive", "boolean"),
+            optional("all", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "close_windows",
+        description: "Close every window matching a pattern (searched as find_window does) and report their ids; no match is not an error",
+        params: &[
+            required("pattern", "string"),
+            optional("match", "string"),
+            optional("regex", "boolean"),
+            optional("case_sensitive", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "minimize_windows",
+        description: "Minimize every window matching a pattern (searched as find_window does) and report their ids",
+        params: &[
+            required("pattern", "string"),
+            optional("match", "string"),
+            optional("regex", "boolean"),
+            optional("case_sensitive", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "move_windows",
+        description: "Move every window matching a pattern (searched as find_window does) to a workspace and report their ids",
+        params: &[
+            required("pattern", "string"),
+            optional("match", "string"),
+            optional("regex", "boolean"),
+            optional("case_sensitive", "boolean"),
+            required("workspace", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "maximize_window",
+        description: "Maximize a window",
+        params: &[required("window_id", "string")],
+    },
+    RequestSpec {
+        name: "minimize_window",
+        description: "Minimize a window",
+        params: &[required("window_id", "string")],
+    },
+    RequestSpec {
+        name: "close_window",
+        description: "Close a window",
+        params: &[required("window_id", "string")],
+    },
+    RequestSpec {
+        name: "move_resize_window",
+        description: "Move and resize a window",
+        params: &[
+            required("window_id", "string"),
+            optional("x", "integer"),
+            optional("y", "integer"),
+            optional("width", "integer"),
+            optional("height", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "set_window_state",
+        description: "Turn a window state on or off (on defaults to true), by window_id or a title/class pattern: fullscreen, always_on_top, always_below, shaded, sticky, skip_taskbar, skip_pager or demands_attention",
+        params: &[
+            optional("window_id", "string"),
+            optional("window", "string"),
+            required("state", "string"),
+            optional("on", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "list_workspaces",
+        description: "List the workspaces (desktops) and which one is shown",
+        params: &[],
+    },
+    RequestSpec {
+        name: "switch_workspace",
+        description: "Show a workspace; X11 desktops count from 0, Hyprland and Sway workspaces from 1",
+        params: &[required("workspace", "integer")],
+    },
+    RequestSpec {
+        name: "move_window_to_workspace",
+        description: "Move a window, by window_id or a title/class pattern, to a workspace without following it",
+        params: &[
+            required("workspace", "integer"),
+            optional("window_id", "string"),
+            optional("window", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "wait_for_window",
+        description: "Wait until a window matching a title/class pattern is open and return it (timeout_ms defaults to 30000)",
+        params: &[
+            required("pattern", "string"),
+            optional("timeout_ms", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "open_or_focus_application",
+        description: "Focus an application, launching it if needed; answers with a `warning` if a launched application shows no window within 10 s",
+        params: &[
+            required("app", "string"),
+            optional("launch_command", "string"),
+        ],
+    },
+    // Action Recording
+    RequestSpec {
+        name: "start_recording",
+        description: "Start recording a new sequence; returns a session_id (source \"input\" records the real keyboard and mouse)",
+        params: &[
+            optional("name", "string"),
+            optional("description", "string"),
+            optional("source", "string"),
+            optional("screenshots", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "stop_recording",
+        description: "Stop recording and save the sequence",
+        params: &[
+            optional("session_id", "string"),
+            optional("environment", "boolean"),
+            optional("ephemeral", "boolean"),
+            optional("ttl_seconds", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "record_action",
+        description: "Append an action to the sequence being recorded",
+        params: &[
+            required("action", "string"),
+            optional("x", "integer"),
+            optional("y", "integer"),
+            optional("button", "string"),
+            optional("anchor", "object"),
+            optional("monitor", "monitor"),
+            optional("relative", "boolean"),
+            optional("settle_ms", "integer"),
+            optional("count", "integer"),
+            optional("text", "string"),
+            optional("sensitive", "boolean"),
+            optional("delay_ms", "integer"),
+            optional("via_clipboard", "boolean"),
+            optional("key", "string"),
+            optional("combo", "string"),
+            optional("milliseconds", "integer"),
+            optional("name", "string"),
+            optional("id", "string"),
+            optional("label", "string"),
+            optional("comment", "string"),
+            optional("session_id", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "capture_screen",
+        description: "Screenshot of the whole screen, written to path if given and otherwise returned as base64 (PNG unless format is jpeg or webp)",
+        params: &[
+            optional("path", "string"),
+            optional("format", "string"),
+            optional("quality", "integer"),
+            optional("scale", "number"),
+            optional("cursor", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "capture_region",
+        description: "Screenshot of a region, written to path if given and otherwise returned as base64 (PNG unless format is jpeg or webp)",
+        params: &[
+            required("x", "integer"),
+            required("y", "integer"),
+            required("width", "integer"),
+            required("height", "integer"),
+            optional("path", "string"),
+            optional("format", "string"),
+            optional("quality", "integer"),
+            optional("scale", "number"),
+            optional("cursor", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "capture_window",
+        description: "Screenshot of a window (window_id, or a title or class pattern in window), written to path if given and otherwise returned as base64 (PNG unless format is jpeg or webp)",
+        params: &[
+            optional("window_id", "string"),
+            optional("window", "string"),
+            optional("path", "string"),
+            optional("format", "string"),
+            optional("quality", "integer"),
+            optional("scale", "number"),
+            optional("cursor", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "capture_monitor",
+        description: "Screenshot of one monitor (a name such as DP-1, or an index in list_displays), written to path if given and otherwise returned as base64 (PNG unless format is jpeg or webp)",
+        params: &[
+            required("monitor", "monitor"),
+            optional("path", "string"),
+            optional("format", "string"),
+            optional("quality", "integer"),
+            optional("scale", "number"),
+            optional("cursor", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "capture_point",
+        description: "Name the current mouse position in the recording and record a move to it",
+        params: &[
+            required("name", "string"),
+            optional("label", "string"),
+            optional("session_id", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "set_sequence_point",
+        description: "Move a named point of a saved sequence (to x/y, or to the current mouse position)",
+        params: &[
+            required("sequence", "string"),
+            required("name", "string"),
+            optional("x", "integer"),
+            optional("y", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "set_error_policy",
+        description: "Set what playback does when a step fails (abort, continue or retry) for a sequence or one step",
+        params: &[
+            required("sequence", "string"),
+            required("on_error", "any"),
+            optional("step", "step"),
+        ],
+    },
+    RequestSpec {
+        name: "set_concurrency",
+        description: "Set what starting a sequence does while another plays (reject, queue, skip_if_running, cancel_previous)",
+        params: &[
+            required("sequence", "string"),
+            required("concurrency", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "is_recording",
+        description: "Check whether a recording (or the given session) is in progress",
+        params: &[optional("session_id", "string")],
+    },
+    RequestSpec {
+        name: "list_recordings",
+        description: "List active recording sessions",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "list_interrupted_recordings",
+        description: "List recordings left unfinished by a crashed or stopped daemon",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "recover_recording",
+        description: "Save an interrupted recording to the library",
+        params: &[required("id", "string"), optional("name", "string")],
+    },
+    RequestSpec {
+        name: "discard_recording",
+        description: "Delete an interrupted recording",
+        params: &[required("id", "string")],
+    },
+    // Action Playback
+    RequestSpec {
+        name: "load_sequence",
+        description: "Load a sequence from the library for playback",
+        params: &[
+            required("name", "string"),
+            optional("allow_draft", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "play_sequence",
+        description: "Play the loaded (or named) sequence in the background, optionally limited to a range of steps; with dry_run, only check the steps and return the plan",
+        params: &[
+            optional("name", "string"),
+            optional("concurrency", "string"),
+            optional("start", "step"),
+            optional("start_at", "step"),
+            optional("end", "step"),
+            optional("from_label", "string"),
+            optional("speed", "number"),
+            optional("no_delays", "boolean"),
+            optional("dry_run", "boolean"),
+            optional("capture_on_error", "boolean"),
+            optional("block_input", "panic_key"),
+        ],
+    },
+    RequestSpec {
+        name: "pause_playback",
+        description: "Hold the running playback before its next step",
+        params: &[],
+    },
+    RequestSpec {
+        name: "resume_playback",
+        description: "Continue a paused playback",
+        params: &[],
+    },
+    RequestSpec {
+        name: "stop_playback",
+        description: "Stop the running playback after its current step",
+        params: &[],
+    },
+    RequestSpec {
+        name: "abort_all",
+        description: "Stop the playback, clear the queue, release blocked input and any held keys or buttons at once",
+        params: &[],
+    },
+    RequestSpec {
+        name: "block_input",
+        description: "Keep the real keyboard and mouse away from the desktop until unblock_input, the panic key (default ctrl+alt+escape, which also stops the playback) or the timeout (timeout_ms defaults to ten minutes and may be at most an hour)",
+        params: &[
+            optional("panic_key", "string"),
+            optional("timeout_ms", "number"),
+        ],
+    },
+    RequestSpec {
+        name: "unblock_input",
+        description: "Give the real keyboard and mouse back to the desktop",
+        params: &[],
+    },
+    RequestSpec {
+        name: "start_step_playback",
+        description: "Start playing a sequence one step at a time, for debugging",
+        params: &[
+            required("name", "string"),
+            optional("speed", "number"),
+            optional("no_delays", "boolean"),
+            optional("allow_draft", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "step_playback",
+        description: "Perform the next step of the step playback (or skip it), optionally with another action in its place",
+        params: &[optional("action", "object"), optional("skip", "boolean")],
+    },
+    RequestSpec {
+        name: "step_playback_status",
+        description: "Return the next step of the step playback and the result of every step so far",
+        params: &[],
+    },
+    RequestSpec {
+        name: "set_next_step",
+        description: "Make a step (index or label) the next one of the step playback",
+        params: &[required("step", "step")],
+    },
+    RequestSpec {
+        name: "stop_step_playback",
+        description: "End the step playback, interrupting a step that is running",
+        params: &[],
+    },
+    RequestSpec {
+        name: "generate_sequence",
+        description: "Ask the AI provider to draft a sequence from a description",
+        params: &[
+            required("description", "string"),
+            optional("name", "string"),
+            optional("use_screen", "boolean"),
+            optional("overwrite", "boolean"),
+            optional("ephemeral", "boolean"),
+            optional("ttl_seconds", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "explain_sequence",
+        description: "Summarize what a sequence does and flag risky steps before running it",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "approve_sequence",
+        description: "Mark a reviewed draft sequence as ready for playback",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "list_sequences",
+        description: "List the sequences in the library by name, or in detail, only those with a tag if given",
+        params: &[
+            optional("tag", "string"),
+            optional("details", "boolean"),
+            optional("offset", "integer"),
+            optional("limit", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "delete_sequence",
+        description: "Delete a sequence from the library",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "save_sequence",
+        description: "Add or replace a sequence given as JSON, optionally only in memory (ephemeral, ttl_seconds)",
+        params: &[
+            required("sequence", "object"),
+            optional("ephemeral", "boolean"),
+            optional("ttl_seconds", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "get_sequence",
+        description: "Return a sequence from the library with all its steps (sensitive text hidden unless reveal is set)",
+        params: &[required("name", "string"), optional("reveal", "boolean")],
+    },
+    RequestSpec {
+        name: "update_action",
+        description: "Replace the action of a step, keeping its delay and label",
+        params: &[
+            required("sequence", "string"),
+            required("step", "step"),
+            required("action", "object"),
+        ],
+    },
+    RequestSpec {
+        name: "insert_action",
+        description: "Insert a step before the given index, or append it",
+        params: &[
+            required("sequence", "string"),
+            required("action", "object"),
+            optional("index", "integer"),
+            optional("delay_ms", "integer"),
+            optional("id", "string"),
+            optional("label", "string"),
+            optional("comment", "string"),
+        ],
+    },
+    RequestSpec {
+        name: "remove_action",
+        description: "Remove a step from a sequence",
+        params: &[required("sequence", "string"), required("step", "step")],
+    },
+    RequestSpec {
+        name: "move_action",
+        description: "Move a step to another position in its sequence",
+        params: &[
+            required("sequence", "string"),
+            required("step", "step"),
+            required("to", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "rename_sequence",
+        description: "Rename a sequence in the library",
+        params: &[required("name", "string"), required("new_name", "string")],
+    },
+    RequestSpec {
+        name: "tag_sequence",
+        description: "Add tags to a sequence",
+        params: &[required("name", "string"), required("tags", "array")],
+    },
+    RequestSpec {
+        name: "untag_sequence",
+        description: "Remove tags from a sequence",
+        params: &[required("name", "string"), required("tags", "array")],
+    },
+    RequestSpec {
+        name: "get_run_history",
+        description: "List past playbacks of a sequence (or of all sequences), newest first, with a summary",
+        params: &[
+            optional("name", "string"),
+            optional("outcome", "string"),
+            optional("offset", "integer"),
+            optional("limit", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "list_versions",
+        description: "List the earlier versions kept of a sequence, newest first",
+        params: &[
+            required("name", "string"),
+            optional("offset", "integer"),
+            optional("limit", "integer"),
+        ],
+    },
+    RequestSpec {
+        name: "restore_version",
+        description: "Bring back an earlier version of a sequence",
+        params: &[required("name", "string"), required("version", "string")],
+    },
+    RequestSpec {
+        name: "list_packs",
+        description: "List the example packs shipped with casper",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "install_pack",
+        description: "Install a built-in pack by name, or a pack file by path",
+        params: &[required("name", "string")],
+    },
+    // Notifications
+    RequestSpec {
+        name: "show_notification",
+        description: "Show a desktop notification",
+        params: &[
+            required("summary", "string"),
+            optional("body", "string"),
+            optional("actions", "array"),
+            optional("category", "string"),
+            optional("urgency", "string"),
+            optional("timeout_ms", "integer"),
+            optional("sound", "string"),
+            optional("subtitle", "string"),
+            optional("app_id", "string"),
+            optional("wait", "boolean"),
+        ],
+    },
+    // External Services
+    RequestSpec {
+        name: "connect_to_service",
+        description: "Call an external service",
+        params: &[required("service", "string"), optional("action", "string")],
+    },
+    // MCP
+    RequestSpec {
+        name: "process_mcp",
+        description: "Process MCP protocol data",
+        params: &[required("data", "string")],
+    },
+    // AI
+    RequestSpec {
+        name: "process_command",
+        description: "Interpret a natural language command",
+        params: &[required("command", "string")],
+    },
+    RequestSpec {
+        name: "ask_ai",
+        description: "Ask the configured AI provider a question, optionally about the screen",
+        params: &[
+            required("prompt", "string"),
+            optional("use_screen", "boolean"),
+            optional("monitor", "monitor"),
+        ],
+    },
+    // Voice
+    RequestSpec {
+        name: "recognize_voice",
+        description: "Recognize speech from the microphone",
+        params: &[],
+    },
+    // TTS
+    RequestSpec {
+        name: "speak",
+        description: "Speak text aloud",
+        params: &[required("text", "string")],
+    },
+    // Ping/Status
+    RequestSpec {
+        name: "ping",
+        description: "Health check",
+        params: &[],
+    },
+    RequestSpec {
+        name: "status",
+        description: "Report recording/playback state, active modes and favorite sequences",
+        params: &[],
+    },
+    RequestSpec {
+        name: "active_sensors",
+        description: "List the sensors currently observing the user (recording, listening, screen capture)",
+        params: &[],
+    },
+    // Window rules
+    RequestSpec {
+        name: "list_window_rules",
+        description: "List the rules applied to windows as they appear",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "add_window_rule",
+        description: "Add or replace a window rule (match by class/title; workspace, placement, no_focus)",
+        params: &[required("rule", "object")],
+    },
+    RequestSpec {
+        name: "remove_window_rule",
+        description: "Delete a window rule by name",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "apply_window_rules",
+        description: "Apply the window rules to windows that are already open",
+        params: &[],
+    },
+    // Window Layouts
+    RequestSpec {
+        name: "save_layout",
+        description: "Save the class, title, workspace and geometry of every open window as a named layout",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "apply_layout",
+        description: "Move open windows back to where a saved layout has them; windows of applications that are not running are reported as missing",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "list_layouts",
+        description: "List the saved window layouts",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "delete_layout",
+        description: "Delete a saved window layout",
+        params: &[required("name", "string")],
+    },
+    // Pipelines
+    RequestSpec {
+        name: "list_pipelines",
+        description: "List the saved pipelines",
+        params: PAGE_PARAMS,
+    },
+    RequestSpec {
+        name: "save_pipeline",
+        description: "Add or replace a pipeline (capture, ocr, ai, http, extract and action steps bound to variables)",
+        params: &[required("pipeline", "object")],
+    },
+    RequestSpec {
+        name: "delete_pipeline",
+        description: "Delete a pipeline by name",
+        params: &[required("name", "string")],
+    },
+    RequestSpec {
+        name: "run_pipeline",
+        description: "Run a pipeline and return its variables",
+        params: &[required("name", "string"), optional("vars", "object")],
+    },
+    // Hotkeys
+    RequestSpec {
+        name: "bind_hotkey",
+        description: "Play a sequence (or toggle the automation pause) when a global hotkey such as ctrl+alt+1 is pressed",
+        params: &[
+            required("hotkey", "string"),
+            optional("sequence", "string"),
+            optional("toggle_automations", "boolean"),
+        ],
+    },
+    RequestSpec {
+        name: "unbind_hotkey",
+        description: "Remove a global hotkey",
+        params: &[required("hotkey", "string")],
+    },
+    RequestSpec {
+        name: "list_hotkeys",
+        description: "List the global hotkeys and the sequences they play",
+        params: PAGE_PARAMS,
+    },
+    // Key/value store
+    RequestSpec {
+        name: "store_set",
+        description: "Set a key in the persistent key/value store",
+        params: &[required("key", "string"), required("value", "any")],
+    },
+    RequestSpec {
+        name: "store_get",
+        description: "Read a key from the key/value store, or every key if none is given",
+        params: &[optional("key", "string")],
+    },
+    RequestSpec {
+        name: "store_delete",
+        description: "Remove a key from the key/value store",
+        params: &[required("key", "string")],
+    },
+    RequestSpec {
+        name: "store_watch",
+        description: "Keep the connection open and receive store_changed events for the given keys",
+        params: &[optional("keys", "array")],
+    },
+    RequestSpec {
+        name: "metrics",
+        description: "Request counts, error counts, latencies and playback runs since the daemon started",
+        params: &[optional("format", "string")],
+    },
+    RequestSpec {
+        name: "usage_report",
+        description: "Report local feature usage counts (opt-in), optionally with differential-privacy noise or exported to a file",
+        params: &[optional("epsilon", "number"), optional("path", "string")],
+    },
+    RequestSpec {
+        name: "reset_usage",
+        description: "Clear the local usage statistics",
+        params: &[],
+    },
+    RequestSpec {
+        name: "set_mode",
+        description: "Turn privacy mode (no screen capture) or do-not-disturb (no notifications or speech) on or off",
+        params: &[required("mode", "string"), required("enabled", "boolean")],
+    },
+    RequestSpec {
+        name: "pause_automations",
+        description: "Hold window rules, hotkeys and queued playbacks (optionally for some minutes) while direct requests keep working",
+        params: &[optional("minutes", "number")],
+    },
+    RequestSpec {
+        name: "resume_automations",
+        description: "End pause_automations",
+        params: &[],
+    },
+    RequestSpec {
+        name: "capabilities",
+        description: "Describe the protocol version and supported request types",
+        params: &[optional("client_version", "integer")],
+    },
+    RequestSpec {
+        name: "stream_screen",
+        description: "Keep the connection open and receive frames of the screen, a monitor, a region or a window as base64 JSON lines",
+        params: &[
+            optional("interval_ms", "integer"),
+            optional("max_frames", "integer"),
+            optional("window", "string"),
+            optional("monitor", "monitor"),
+            optional("region", "object"),
+        ],
+    },
+    RequestSpec {
+        name: "start_capture_stream",
+        description: "Capture the screen, a monitor, a region or a window every interval_ms (1000 by default, 50 at least) and publish the frames as screen_frame events; answers with the stream_id",
+        params: &[
+            optional("interval_ms", "integer"),
+            optional("max_frames", "integer"),
+            optional("window", "string"),
+            optional("monitor", "monitor"),
+            optional("region", "object"),
+        ],
+    },
+    RequestSpec {
+        name: "stop_capture_stream",
+        description: "Stop a capture stream and report how many frames it published",
+        params: &[required("stream_id", "string")],
+    },
+    RequestSpec {
+        name: "list_capture_streams",
+        description: "The running capture streams with their interval and frame count",
+        params: &[],
+    },
+    RequestSpec {
+        name: "watch_mouse",
+        description: "Keep the connection open and receive the cursor position as JSON lines whenever it moves",
+        params: &[optional("interval_ms", "integer")],
+    },
+    RequestSpec {
+        name: "subscribe",
+        description: "Keep the connection open and receive daemon events as JSON lines",
+        params: &[optional("events", "array")],
+    },
+];
+
+/// Event names a client can subscribe to
+pub const EVENTS: &[&str] = &[
+    "recording_started",
+    "recording_stopped",
+    "action_recorded",
+    "sequence_saved",
+    "sequence_deleted",
+    "playback_started",
+    "playback_queued",
+    "hotkey_triggered",
+    "playback_progress",
+    "playback_finished",
+    "playback_failed",
+    "playback_paused",
+    "playback_resumed",
+    "playback_stopped",
+    "input_unblocked",
+    "automations_aborted",
+    "mode_changed",
+    "sensors_changed",
+    "store_changed",
+    "window_rule_applied",
+    "window_created",
+    "window_closed",
+    "window_focused",
+    "window_title_changed",
+    "point_captured",
+    "screen_frame",
+    "screen_stream_ended",
+];
+
+/// Request types that produce incremental chunks when sent with `"stream": true`
+pub const STREAMING_REQUESTS: &[&str] = &["run_command", "speak", "ask_ai"];
+
+/// Request types answering with a list that can be paged with `offset` and
+/// `limit`, and the field holding the list
+pub const PAGINATED_REQUESTS: &[(&str, &str)] = &[
+    ("list_windows", "windows"),
+    ("list_sequences", "sequences"),
+    ("list_recordings", "recordings"),
+    ("list_interrupted_recordings", "recordings"),
+    ("list_window_rules", "rules"),
+    ("list_layouts", "layouts"),
+    ("list_applications", "applications"),
+    ("list_pipelines", "pipelines"),
+    ("list_hotkeys", "hotkeys"),
+    ("list_versions", "versions"),
+    ("list_packs", "packs"),
+    ("get_run_history", "runs"),
+];
+
+/// Smallest `max_bytes` a client can ask for; smaller values are raised to it
+pub const MIN_MAX_BYTES: usize = 256;
+
+/// Field holding the list of a paginated request type
+pub fn paginated_field(request_type: &str) -> Option<&'static str> {
+    PAGINATED_REQUESTS
+        .iter()
+        .find(|(name, _)| *name == request_type)
+        .map(|(_, field)| *field)
+}
+
+/// Cut the list in `field` of a response down to one page
+///
+/// Adds `total`, `offset` and `next_offset`, which is null on the last page.
+pub fn paginate(response: &mut Value, field: &str, offset: usize, limit: Option<usize>) {
+    let Some(items) = response.get_mut(field).and_then(Value::as_array_mut) else {
+        return;
+    };
+    let total = items.len();
+    let start = offset.min(total);
+    let end = limit.map_or(total, |limit| start.saturating_add(limit).min(total));
+    items.truncate(end);
+    items.drain(..start);
+    response["total"] = json!(total);
+    response["offset"] = json!(offset);
+    response["next_offset"] = if end < total { json!(end) } else { Value::Null };
+}
+
+/// Split a serialized response into lines of at most `max_bytes` bytes,
+/// newline included
+///
+/// Each line is `{"part": 1, "parts": 3, "data": "..."}`; joining the `data`
+/// strings in order gives the response back.
+pub fn split_response(text: &str, max_bytes: usize) -> Vec<String> {
+    let max_bytes = max_bytes.max(MIN_MAX_BYTES);
+    // Part numbers never have more digits than the length of the text
+    let digits = text.len().max(1).to_string().len();
+    let budget = max_bytes - r#"{"part":,"parts":,"data":""}"#.len() - 1 - 2 * digits;
+
+    let mut pieces = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (index, c) in text.char_indices() {
+        let escaped = match c {
+            '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+            c if c < ' ' => 6,
+            c => c.len_utf8(),
+        };
+        if size + escaped > budget {
+            pieces.push(&text[start..index]);
+            (start, size) = (index, 0);
+        }
+        size += escaped;
+    }
+    pieces.push(&text[start..]);
+
+    let parts = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            let mut line = json!({ "part": index + 1, "parts": parts, "data": data }).to_string();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+/// Whether a client speaking `client_version` can talk to this daemon
+pub fn is_compatible(client_version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&client_version)
+}
+
+/// Build the "capabilities" response payload
+pub fn capabilities(client_version: Option<u32>) -> serde_json::Value {
+    let requests: Vec<_> = REQUESTS
+        .iter()
+        .map(|spec| {
+            let params: Vec<_> = spec
+                .params
+                .iter()
+                .map(|p| json!({ "name": p.name, "type": p.kind, "required": p.required }))
+                .collect();
+            json!({
+                "type": spec.name,
+                "description": spec.description,
+                "params": params,
+                "streaming": STREAMING_REQUESTS.contains(&spec.name),
+                "paginated": paginated_field(spec.name).is_some(),
+            })
+        })
+        .collect();
+
+    json!({
+        "status": "success",
+        "protocol_version": PROTOCOL_VERSION,
+        "min_protocol_version": MIN_PROTOCOL_VERSION,
+        "compatible": client_version.map(is_compatible).unwrap_or(true),
+        "requests": requests,
+        "events": EVENTS,
+    })
+}
+
+/// Read one request: everything up to a newline or the end of the stream
+///
+/// Clients that send neither are still served once the bytes read so far
+/// form a complete JSON value. Requests over `max_bytes` are refused.
+pub async fn read_request<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> Result<Vec<u8>, String> {
+    let mut request = Vec::new();
+    let mut buf = vec![0; 8192];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read request: {}", e))?;
+        if n == 0 {
+            return Ok(request);
+        }
+        let line_end = buf[..n].iter().position(|&b| b == b'\n');
+        request.extend_from_slice(&buf[..line_end.unwrap_or(n)]);
+        if request.len() > max_bytes {
+            return Err(format!(
+                "Request too large: the limit is {} bytes (requests.max_bytes)",
+                max_bytes
+            ));
+        }
+        if line_end.is_some() || serde_json::from_slice::<Value>(&request).is_ok() {
+            return Ok(request);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_large_requests_are_read_whole() {
+        let request = json!({ "type": "type_text", "text": "x".repeat(10_000) });
+        let line = format!("{}\n", request);
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let writer = tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            client.write_all(line.as_bytes()).await.unwrap();
+            client
+        });
+        let read = read_request(&mut server, 1 << 20).await.unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&read).unwrap(), request);
+        let _client = writer.await.unwrap();
+
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+        tokio::io::AsyncWriteExt::write_all(&mut client, request.to_string().as_bytes())
+            .await
+            .unwrap();
+        let error = read_request(&mut server, 4096).await.unwrap_err();
+        assert!(error.starts_with("Request too large"));
+    }
+
+    #[test]
+    fn test_request_names_are_unique() {
+        for (i, spec) in REQUESTS.iter().enumerate() {
+            assert!(
+                REQUESTS[i + 1..]
+                    .iter()
+                    .all(|other| other.name != spec.name),
+                "duplicate request type: {}",
+                spec.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_paginated_requests_take_page_params() {
+        for (name, _) in PAGINATED_REQUESTS {
+            let spec = REQUESTS.iter().find(|spec| spec.name == *name).unwrap();
+            assert!(spec.params.iter().any(|p| p.name == "limit"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_paginate() {
+        let mut response = json!({ "status": "success", "windows": [1, 2, 3, 4, 5] });
+        paginate(&mut response, "windows", 1, Some(2));
+        assert_eq!(response["windows"], json!([2, 3]));
+        assert_eq!(response["total"], 5);
+        assert_eq!(response["next_offset"], 3);
+
+        let mut response = json!({ "windows": [1, 2, 3] });
+        paginate(&mut response, "windows", 2, Some(5));
+        assert_eq!(response["windows"], json!([3]));
+        assert_eq!(response["next_offset"], Value::Null);
+
+        let mut response = json!({ "windows": [1, 2, 3] });
+        paginate(&mut response, "windows", 7, None);
+        assert_eq!(response["windows"], json!([]));
+        assert_eq!(response["total"], 3);
+    }
+
+    #[test]
+    fn test_split_response() {
+        let response = json!({
+            "status": "success",
+            "output": "line \"one\"\n\u{1}ünïcödé ".repeat(100)
+        })
+        .to_string();
+        let lines = split_response(&response, 300);
+        assert!(lines.len() > 1);
+        let mut joined = String::new();
+        for (index, line) in lines.iter().enumerate() {
+            assert!(line.len() <= 300, "{} bytes", line.len());
+            let part: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(part["part"], index + 1);
+            assert_eq!(part["parts"], lines.len());
+            joined.push_str(part["data"].as_str().unwrap());
+        }
+        assert_eq!(joined, response);
+    }
+
+    #[test]
+    fn test_version_compatibility() {
+        assert!(is_compatible(PROTOCOL_VERSION));
+        assert!(!is_compatible(PROTOCOL_VERSION + 1));
+        assert_eq!(
+            capabilities(Some(PROTOCOL_VERSION + 1))["compatible"],
+            false
+        );
+        assert!(REQUESTS.iter().any(|spec| spec.name == "ping"));
+    }
+}
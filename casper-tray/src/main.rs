@@ -1,3 +1,404 @@
-fn main() {
-    println!("Hello, world!");
+use casper_client::Client;
+use ksni::TrayMethods;
+use ksni::menu::{CheckmarkItem, MenuItem, StandardItem, SubMenu};
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How often the daemon status is polled when no events arrive
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Something chosen from the tray menu, carried out by the main loop
+enum Command {
+    Refresh,
+    SetMode(&'static str, bool),
+    SetPaused(bool),
+    StopRecording,
+    StopPlayback,
+    PlaySequence(String),
+}
+
+/// Daemon state as last seen by the tray
+#[derive(Default)]
+struct Snapshot {
+    connected: bool,
+    recording: bool,
+    playing: bool,
+    privacy: bool,
+    do_not_disturb: bool,
+    /// `None` when the daemon has no automation pause switch
+    paused: Option<bool>,
+    favorites: Vec<String>,
+    /// Active sensors as display labels, e.g. "Recording (demo)"
+    sensors: Vec<String>,
+}
+
+struct CasperTray {
+    commands: mpsc::UnboundedSender<Command>,
+    state: Snapshot,
+}
+
+impl CasperTray {
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+}
+
+impl ksni::Tray for CasperTray {
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").into()
+    }
+
+    fn title(&self) -> String {
+        "Casper".into()
+    }
+
+    fn icon_name(&self) -> String {
+        if !self.state.connected {
+            "network-offline"
+        } else if !self.state.sensors.is_empty() {
+            "media-record"
+        } else if self.state.playing {
+            "media-playback-start"
+        } else {
+            "input-mouse"
+        }
+        .into()
+    }
+
+    fn status(&self) -> ksni::Status {
+        // Anything observing the user must stay visible, even in collapsed tray areas
+        if !self.state.sensors.is_empty() {
+            ksni::Status::NeedsAttention
+        } else {
+            ksni::Status::Active
+        }
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        ksni::ToolTip {
+            title: "Casper".into(),
+            description: status_line(&self.state),
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.send(Command::Refresh);
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let state = &self.state;
+        let mut items: Vec<MenuItem<Self>> = vec![
+            StandardItem {
+                label: status_line(state),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+        ];
+
+        if !state.connected {
+            items.push(MenuItem::Separator);
+            items.push(refresh_item());
+            items.push(quit_item());
+            return items;
+        }
+
+        // A single sensor is already named by the status line
+        for sensor in state.sensors.iter().filter(|_| state.sensors.len() > 1) {
+            items.push(
+                StandardItem {
+                    label: format!("● {}", sensor),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        if state.recording {
+            items.push(
+                StandardItem {
+                    label: "Stop all recordings".into(),
+                    icon_name: "media-playback-stop".into(),
+                    activate: Box::new(|tray: &mut Self| tray.send(Command::StopRecording)),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        if state.playing {
+            items.push(
+                StandardItem {
+                    label: "Stop playback".into(),
+                    icon_name: "media-playback-stop".into(),
+                    activate: Box::new(|tray: &mut Self| tray.send(Command::StopPlayback)),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        items.push(MenuItem::Separator);
+        items.push(
+            CheckmarkItem {
+                label: "Privacy mode".into(),
+                checked: state.privacy,
+                activate: Box::new(|tray: &mut Self| {
+                    let enabled = !tray.state.privacy;
+                    tray.send(Command::SetMode("privacy", enabled));
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        items.push(
+            CheckmarkItem {
+                label: "Do not disturb".into(),
+                checked: state.do_not_disturb,
+                activate: Box::new(|tray: &mut Self| {
+                    let enabled = !tray.state.do_not_disturb;
+                    tray.send(Command::SetMode("do_not_disturb", enabled));
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        if let Some(paused) = state.paused {
+            items.push(
+                CheckmarkItem {
+                    label: "Pause automations".into(),
+                    checked: paused,
+                    activate: Box::new(move |tray: &mut Self| {
+                        tray.send(Command::SetPaused(!paused))
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        items.push(MenuItem::Separator);
+        let favorites: Vec<MenuItem<Self>> = if state.favorites.is_empty() {
+            vec![
+                StandardItem {
+                    label: "Tag a sequence \"favorite\" to list it here".into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            ]
+        } else {
+            state
+                .favorites
+                .iter()
+                .map(|name| {
+                    let name = name.clone();
+                    StandardItem {
+                        label: name.clone(),
+                        icon_name: "media-playback-start".into(),
+                        enabled: !state.recording && !state.playing,
+                        activate: Box::new(move |tray: &mut Self| {
+                            tray.send(Command::PlaySequence(name.clone()))
+                        }),
+                        ..Default::default()
+                    }
+                    .into()
+                })
+                .collect()
+        };
+        items.push(
+            SubMenu {
+                label: "Favorite sequences".into(),
+                submenu: favorites,
+                ..Default::default()
+            }
+            .into(),
+        );
+
+        items.push(MenuItem::Separator);
+        items.push(refresh_item());
+        items.push(quit_item());
+        items
+    }
+}
+
+fn refresh_item() -> MenuItem<CasperTray> {
+    StandardItem {
+        label: "Refresh".into(),
+        icon_name: "view-refresh".into(),
+        activate: Box::new(|tray: &mut CasperTray| tray.send(Command::Refresh)),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn quit_item() -> MenuItem<CasperTray> {
+    StandardItem {
+        label: "Quit".into(),
+        icon_name: "application-exit".into(),
+        activate: Box::new(|_| std::process::exit(0)),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn status_line(state: &Snapshot) -> String {
+    if !state.connected {
+        "Daemon not running".into()
+    } else if state.sensors.len() == 1 {
+        format!("● {}", state.sensors[0])
+    } else if !state.sensors.is_empty() {
+        format!("● {} sensors active", state.sensors.len())
+    } else if state.playing {
+        "▶ Playing a sequence".into()
+    } else if state.paused == Some(true) {
+        "Automations paused".into()
+    } else {
+        "Daemon running".into()
+    }
+}
+
+/// Carry out a menu command against the daemon
+async fn run_command(client: &Client, command: Command) -> Result<(), String> {
+    match command {
+        Command::Refresh => Ok(()),
+        Command::SetMode(mode, enabled) => client.set_mode(mode, enabled).await,
+        Command::SetPaused(paused) => {
+            let request = if paused {
+                "pause_automations"
+            } else {
+                "resume_automations"
+            };
+            client.request(json!({ "type": request })).await.map(|_| ())
+        }
+        Command::StopRecording => {
+            // The tray owns no session, so stop every active recording
+            let status = client.status().await?;
+            let sessions = status["recordings"].as_array().cloned().unwrap_or_default();
+            for session in sessions {
+                client
+                    .stop_recording(session["session_id"].as_str())
+                    .await?;
+            }
+            Ok(())
+        }
+        Command::StopPlayback => client.stop_playback().await,
+        Command::PlaySequence(name) => client.play_sequence(&name).await,
+    }
+}
+
+/// Query the daemon for everything the tray displays
+async fn fetch_snapshot(client: &Client, supports_pause: &mut Option<bool>) -> Snapshot {
+    let status = match client.status().await {
+        Ok(status) => status,
+        Err(_) => {
+            // Re-check capabilities once the daemon is back, it may have been upgraded
+            *supports_pause = None;
+            return Snapshot::default();
+        }
+    };
+
+    if supports_pause.is_none() {
+        *supports_pause = client.capabilities().await.ok().map(|caps| {
+            caps["requests"]
+                .as_array()
+                .is_some_and(|requests| requests.iter().any(|r| r["type"] == "pause_automations"))
+        });
+    }
+
+    let modes = &status["modes"];
+    Snapshot {
+        connected: true,
+        recording: status["recording"].as_bool().unwrap_or(false),
+        playing: status["playing"].as_bool().unwrap_or(false),
+        privacy: modes["privacy"].as_bool().unwrap_or(false),
+        do_not_disturb: modes["do_not_disturb"].as_bool().unwrap_or(false),
+        paused: if *supports_pause == Some(true) {
+            Some(modes["automations_paused"].as_bool().unwrap_or(false))
+        } else {
+            None
+        },
+        favorites: status["favorites"]
+            .as_array()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        sensors: status["active_sensors"]
+            .as_array()
+            .map(|sensors| sensors.iter().map(sensor_label).collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Describe an entry of the daemon's active_sensors list
+fn sensor_label(sensor: &Value) -> String {
+    let name = match sensor["sensor"].as_str().unwrap_or("") {
+        "recording" => "Recording input",
+        "listening" => "Listening to the microphone",
+        "timelapse" => "Taking timelapse screenshots",
+        "screen_watching" => "Watching the screen",
+        other => other,
+    };
+    match sensor["detail"].as_str() {
+        Some(detail) if !detail.is_empty() => format!("{} ({})", name, detail),
+        _ => name.to_string(),
+    }
+}
+
+/// Refresh the tray whenever the daemon reports an event
+async fn watch_events(client: Client, commands: mpsc::UnboundedSender<Command>) {
+    loop {
+        if let Ok(mut events) = client.subscribe_events(&[]).await {
+            while let Some(Ok(_)) = events.next().await {
+                if commands.send(Command::Refresh).is_err() {
+                    return;
+                }
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let client = Client::from_env()
+        .with_connect_retries(0)
+        .with_timeout(Duration::from_secs(5));
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let tray = CasperTray {
+        commands: tx.clone(),
+        state: Snapshot::default(),
+    };
+    let handle = tray.spawn().await?;
+
+    tokio::spawn(watch_events(client.clone(), tx.clone()));
+    let ticker = tx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if ticker.send(Command::Refresh).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut supports_pause = None;
+    while let Some(command) = rx.recv().await {
+        if let Err(e) = run_command(&client, command).await {
+            eprintln!("casper-tray: {}", e);
+        }
+        let snapshot = fetch_snapshot(&client, &mut supports_pause).await;
+        handle.update(|tray| tray.state = snapshot).await;
+    }
+
+    Ok(())
 }
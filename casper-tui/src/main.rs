@@ -1,19 +1,20 @@
+use casper_client::Client;
+use crossterm::{
+    event::{self, Event, KeyCode},
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+};
 use ratatui::{
+    Terminal,
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
     widgets::{Block, Borders, Paragraph},
-    Terminal,
-};
-use crossterm::{
-    event::{self, Event, KeyCode},
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    execute,
 };
-use tokio::net::UnixStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use serde_json::json;
+use serde_json::{Value, json};
 use std::io;
 
+mod setup;
+
 struct App {
     input: String,
     output: String,
@@ -28,20 +29,32 @@ impl App {
     }
 }
 
-async fn send_request(request: &str) -> Result<String, String> {
-    let mut stream = UnixStream::connect("/tmp/casper.sock")
-        .await
-        .map_err(|e| e.to_string())?;
-    stream
-        .write_all(request.as_bytes())
-        .await
-        .map_err(|e| e.to_string())?;
-    let mut buf = vec![0; 1024];
-    let n = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
-    Ok(String::from_utf8_lossy(&buf[..n]).to_string())
+/// One line per step of a sequence, with its label, ID and comment
+fn describe_steps(sequence: &Value) -> String {
+    let steps = sequence["actions"].as_array().cloned().unwrap_or_default();
+    let mut lines = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        let mut line = format!("{:>3}  ", index);
+        if let Some(label) = step["label"].as_str() {
+            line.push_str(&format!("[{}] ", label));
+        }
+        line.push_str(step["action"]["type"].as_str().unwrap_or("?"));
+        if let Some(id) = step["id"].as_str() {
+            line.push_str(&format!(" (id: {})", id));
+        }
+        if let Some(comment) = step["comment"].as_str() {
+            line.push_str(&format!("  # {}", comment));
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
 }
 
 fn main() -> io::Result<()> {
+    if std::env::args().any(|arg| arg == "--setup") || setup::is_first_run() {
+        setup::run()?;
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -51,6 +64,7 @@ fn main() -> io::Result<()> {
 
     // Run TUI
     let mut app = App::new();
+    let client = Client::from_env();
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async {
         loop {
@@ -74,15 +88,23 @@ fn main() -> io::Result<()> {
                     KeyCode::Char(c) => app.input.push(c),
                     KeyCode::Backspace => {
                         app.input.pop(); // Discard return value to return ()
-                    },
+                    }
                     KeyCode::Enter => {
-                        let request = json!({
-                            "type": "run_command",
-                            "command": app.input.clone()
-                        });
-                        app.output = match send_request(&request.to_string()).await {
-                            Ok(resp) => resp,
-                            Err(e) => format!("Error: {}", e),
+                        app.output = match app.input.strip_prefix("steps ") {
+                            Some(name) => match client.get_sequence(name.trim()).await {
+                                Ok(sequence) => describe_steps(&sequence),
+                                Err(e) => format!("Error: {}", e),
+                            },
+                            None => {
+                                let request = json!({
+                                    "type": "run_command",
+                                    "command": app.input.clone()
+                                });
+                                match client.send(request).await {
+                                    Ok(resp) => resp.to_string(),
+                                    Err(e) => format!("Error: {}", e),
+                                }
+                            }
                         };
                         app.input.clear();
                     }
@@ -98,4 +120,4 @@ fn main() -> io::Result<()> {
     disable_raw_mode()?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
     Ok(())
-}
\ No newline at end of file
+}
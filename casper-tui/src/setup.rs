@@ -0,0 +1,168 @@
+use casper_core::ai_vision::{AIConfig, AIProviderKind, AIVision};
+use casper_core::paths;
+use casper_core::screen::click_mouse;
+use casper_core::setup::{
+    Environment, SessionType, check_tools, create_directories, verify_input_injection,
+    write_env_file,
+};
+use std::io::{self, BufRead, Write};
+
+const GEMINI_URL: &str =
+    "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:generateContent";
+const GEMINI_MODEL: &str = "gemini-2.0-flash";
+
+/// Whether this looks like the first time Casper runs for this user
+pub fn is_first_run() -> bool {
+    !paths::config_dir(None).exists() && !paths::legacy_dir(None).exists()
+}
+
+/// Interactive first-run setup, run in the plain terminal before the TUI starts
+pub fn run() -> io::Result<()> {
+    println!("🤖 Casper setup");
+    println!("{}", "=".repeat(60));
+
+    // 1. Environment
+    let env = Environment::detect();
+    let session = match env.session {
+        SessionType::Wayland => "Wayland",
+        SessionType::X11 => "X11",
+        SessionType::Unknown => "unknown (no display detected)",
+    };
+    println!("\n🖥️  Session: {}", session);
+    println!(
+        "   Desktop: {}",
+        env.desktop.as_deref().unwrap_or("unknown")
+    );
+
+    // 2. External tools
+    println!("\n🔧 Checking tools:");
+    let tools = check_tools(&env);
+    for tool in &tools {
+        let mark = match (tool.found, tool.required) {
+            (true, _) => "✅",
+            (false, true) => "❌",
+            (false, false) => "⚠️ ",
+        };
+        println!("   {} {:<10} {}", mark, tool.name, tool.purpose);
+        if !tool.found {
+            println!("      install: {}", tool.install_hint());
+        }
+    }
+
+    // 3. Directories
+    match create_directories(None) {
+        Ok(dirs) => {
+            println!("\n📁 Data directories:");
+            for dir in dirs {
+                println!("   {}", dir.display());
+            }
+        }
+        Err(e) => println!("\n❌ {}", e),
+    }
+
+    // 4. AI provider
+    println!("\n🧠 AI provider");
+    let config_path = paths::config_file(None);
+    if AIConfig::from_env().is_ok()
+        && !confirm("   An AI provider is already configured. Change it?")?
+    {
+        println!("   Keeping the current settings");
+    } else if confirm("   Configure an AI provider now?")? {
+        let request_url = prompt_default("   Request URL", GEMINI_URL)?;
+        let model = prompt_default("   Model", GEMINI_MODEL)?;
+        let token = prompt("   API token")?;
+        if token.is_empty() {
+            println!("   No token given, skipping");
+        } else {
+            match write_env_file(
+                &config_path,
+                &[
+                    ("AI_REQUEST_URL", &request_url),
+                    ("AI_MODEL", &model),
+                    ("AI_TOKEN", &token),
+                ],
+            ) {
+                Ok(_) => {
+                    println!(
+                        "   Saved to {} (owner-readable only)",
+                        config_path.display()
+                    );
+                    check_ai(AIConfig {
+                        provider: AIProviderKind::from_url(&request_url),
+                        request_url,
+                        token,
+                        model,
+                        max_tokens: None,
+                        temperature: None,
+                        timeout_seconds: None,
+                    });
+                }
+                Err(e) => println!("   ❌ {}", e),
+            }
+        }
+    }
+
+    // 5. Input injection
+    println!("\n🖱️  Input injection");
+    match verify_input_injection() {
+        Ok(_) => {
+            println!("   ✅ Pointer movement works");
+            if confirm("   Send a test left click at the current pointer position?")? {
+                match click_mouse("left") {
+                    Ok(_) => println!("   ✅ Click sent"),
+                    Err(e) => println!("   ❌ Click failed: {}", e),
+                }
+            }
+        }
+        Err(e) => {
+            println!("   ❌ {}", e);
+            if env.session == SessionType::Wayland {
+                println!("   On Wayland, make sure your user can access /dev/uinput");
+                println!("   (e.g. add it to the 'input' group) or run an X11 session.");
+            }
+        }
+    }
+
+    println!("\n✨ Setup finished. Start the daemon with: cargo run -p casper-daemon");
+    println!("   Run `casper-tui --setup` any time to repeat these checks.\n");
+    Ok(())
+}
+
+/// Send a tiny prompt to the provider to confirm the settings work
+fn check_ai(config: AIConfig) {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            println!("   ⚠️  Could not test the provider: {}", e);
+            return;
+        }
+    };
+    let vision = AIVision::new(config);
+    let result = runtime.block_on(vision.complete("Reply with the single word OK."));
+    match result {
+        Ok(_) => println!("   ✅ Provider answered"),
+        Err(e) => println!("   ❌ Provider check failed: {}", e),
+    }
+}
+
+fn prompt(label: &str) -> io::Result<String> {
+    print!("{}: ", label);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+fn prompt_default(label: &str, default: &str) -> io::Result<String> {
+    let answer = prompt(&format!("{} [{}]", label, default))?;
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    })
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    let answer = prompt(&format!("{} [y/N]", question))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
@@ -1,16 +1,18 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
-
-async fn send_request(request: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let mut stream = UnixStream::connect("/tmp/casper.sock").await?;
-    stream.write_all(request.as_bytes()).await?;
-    let mut buf = vec![0; 4096];
-    let n = stream.read(&mut buf).await?;
-    Ok(String::from_utf8_lossy(&buf[..n]).to_string())
+use casper_client::Client;
+
+async fn send_request(
+    client: &Client,
+    request: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let request: serde_json::Value = serde_json::from_str(request)?;
+    let response = client.send(request).await?;
+    Ok(response.to_string())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let client = Client::from_env();
+
     println!("🧪 Casper v0.2.0 Comprehensive Test Suite");
     println!("{}", "=".repeat(60));
 
@@ -31,7 +33,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     ];
 
     for request in &basic_tests {
-        let response = send_request(request).await?;
+        let response = send_request(&client, request).await?;
         println!("Request: {}\nResponse: {}\n", request, response);
     }
 
@@ -69,7 +71,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     for (name, request) in &new_tests {
         println!("\n🔹 Testing: {}", name);
-        let response = send_request(request).await?;
+        let response = send_request(&client, request).await?;
         println!("   Response: {}", response);
     }
 
@@ -78,31 +80,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", "-".repeat(60));
 
     println!("\n▶️  Starting recording...");
-    let response = send_request(r#"{"type":"start_recording","name":"test_sequence_v2","description":"Testing v0.2.0 recording"}"#).await?;
+    let response = send_request(&client, r#"{"type":"start_recording","name":"test_sequence_v2","description":"Testing v0.2.0 recording"}"#).await?;
     println!("   Response: {}", response);
 
     println!("\n▶️  Checking recording status...");
-    let response = send_request(r#"{"type":"is_recording"}"#).await?;
+    let response = send_request(&client, r#"{"type":"is_recording"}"#).await?;
     println!("   Response: {}", response);
 
     println!("\n▶️  Recording some actions...");
     tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-    send_request(r#"{"type":"move_mouse","x":500,"y":500}"#).await?;
+    send_request(&client, r#"{"type":"move_mouse","x":500,"y":500}"#).await?;
     tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
-    send_request(r#"{"type":"click_mouse","button":"left"}"#).await?;
+    send_request(&client, r#"{"type":"click_mouse","button":"left"}"#).await?;
     println!("   Recorded 2 actions");
 
     println!("\n▶️  Stopping recording...");
-    let response = send_request(r#"{"type":"stop_recording"}"#).await?;
+    let response = send_request(&client, r#"{"type":"stop_recording"}"#).await?;
     println!("   Response: {}", response);
 
     println!("\n▶️  Listing saved sequences...");
-    let response = send_request(r#"{"type":"list_sequences"}"#).await?;
+    let response = send_request(&client, r#"{"type":"list_sequences"}"#).await?;
     println!("   Response: {}", response);
 
     println!("\n{}", "=".repeat(60));
     println!("✅ All tests completed!");
-    println!("\n💡 Check ~/.casper/actions/ for saved sequences");
+    println!("\n💡 Check ~/.local/share/casper/actions/ for saved sequences");
     println!("📖 See examples/ directory for real-world workflows");
 
     Ok(())